@@ -16,9 +16,16 @@
  *
  */
 
-use std::{io::ErrorKind, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::ErrorKind,
+    sync::{Arc, RwLock},
+};
 
-use self::{column::Column, snapshot::ManifestItem};
+use self::{
+    column::{Column, TypedStatistics},
+    snapshot::ManifestItem,
+};
 use crate::handlers::http::base_path_without_preceding_slash;
 use crate::metrics::{EVENTS_INGESTED_SIZE_TODAY, EVENTS_INGESTED_TODAY, STORAGE_SIZE_TODAY};
 use crate::option::CONFIG;
@@ -26,18 +33,26 @@ use crate::stats::{event_labels, storage_size_labels, update_deleted_stats};
 use crate::{
     catalog::manifest::Manifest,
     event::DEFAULT_TIMESTAMP_KEY,
-    query::PartialTimeFilter,
-    storage::{object_storage::manifest_path, ObjectStorage, ObjectStorageError},
+    query::{partition_format::PartitionFormat, PartialTimeFilter},
+    storage::{key_naming, object_storage::manifest_path, ObjectStorage, ObjectStorageError},
 };
 use crate::{handlers, Mode};
+use arrow_schema::TimeUnit;
 use bytes::Bytes;
-use chrono::{DateTime, Local, NaiveTime, Utc};
-use relative_path::RelativePathBuf;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Utc};
+use futures::TryStreamExt;
+use once_cell::sync::Lazy;
+use relative_path::{RelativePath, RelativePathBuf};
 use std::io::Error as IOError;
+pub mod batch;
 pub mod column;
 pub mod manifest;
+pub mod pruning;
 pub mod snapshot;
-use crate::storage::ObjectStoreFormat;
+use crate::storage::{
+    tiering::{self, Tier},
+    ObjectStoreFormat, STREAM_ROOT_DIRECTORY,
+};
 pub use manifest::create_from_parquet_file;
 pub trait Snapshot {
     fn manifests(&self, time_predicates: &[PartialTimeFilter]) -> Vec<ManifestItem>;
@@ -88,12 +103,13 @@ fn get_file_bounds(
         .stats
         .as_ref()
         .unwrap()
+        .timestamp_bounds(&TimeUnit::Millisecond)
     {
-        column::TypedStatistics::Int(stats) => (
-            DateTime::from_timestamp_millis(stats.min).unwrap(),
-            DateTime::from_timestamp_millis(stats.max).unwrap(),
+        Some((min, max)) => (
+            DateTime::from_timestamp_millis(min).unwrap(),
+            DateTime::from_timestamp_millis(max).unwrap(),
         ),
-        _ => unreachable!(),
+        None => unreachable!(),
     }
 }
 
@@ -102,6 +118,42 @@ pub async fn update_snapshot(
     stream_name: &str,
     change: manifest::File,
 ) -> Result<(), ObjectStorageError> {
+    update_partition(storage, stream_name, vec![change]).await
+}
+
+/// Adds the manifest entries of many uploaded files, with a single snapshot
+/// and manifest write per date partition
+pub async fn update_snapshot_batch(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    changes: Vec<manifest::File>,
+) -> Result<(), ObjectStorageError> {
+    let time_partition = storage
+        .get_object_store_format(stream_name)
+        .await?
+        .time_partition
+        .unwrap_or_else(|| DEFAULT_TIMESTAMP_KEY.to_string());
+    let mut partitions: BTreeMap<NaiveDate, Vec<manifest::File>> = BTreeMap::new();
+    for change in changes {
+        let (lower_bound, _) = get_file_bounds(&change, time_partition.clone());
+        partitions
+            .entry(lower_bound.date_naive())
+            .or_default()
+            .push(change);
+    }
+    for changes in partitions.into_values() {
+        update_partition(storage.clone(), stream_name, changes).await?;
+    }
+    Ok(())
+}
+
+/// Adds manifest entries of files of the same date partition
+async fn update_partition(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    changes: Vec<manifest::File>,
+) -> Result<(), ObjectStorageError> {
+    invalidate_column_summary(stream_name);
     // get current snapshot
     let event_labels = event_labels(stream_name, "json");
     let storage_size_labels = storage_size_labels(stream_name);
@@ -124,16 +176,20 @@ pub async fn update_snapshot(
     let time_partition = &meta_clone.time_partition;
     let lower_bound = match time_partition {
         Some(time_partition) => {
-            let (lower_bound, _) = get_file_bounds(&change, time_partition.to_string());
+            let (lower_bound, _) = get_file_bounds(&changes[0], time_partition.to_string());
             lower_bound
         }
         None => {
-            let (lower_bound, _) = get_file_bounds(&change, DEFAULT_TIMESTAMP_KEY.to_string());
+            let (lower_bound, _) = get_file_bounds(&changes[0], DEFAULT_TIMESTAMP_KEY.to_string());
             lower_bound
         }
     };
+    // late files of a partition moved to the cold tier start a new hot item,
+    // which is merged into the cold one when moved
     let pos = manifests.iter().position(|item| {
-        item.time_lower_bound <= lower_bound && lower_bound < item.time_upper_bound
+        item.tier.is_hot()
+            && item.time_lower_bound <= lower_bound
+            && lower_bound < item.time_upper_bound
     });
 
     // if the mode in I.S. manifest needs to be created but it is not getting created because
@@ -160,14 +216,16 @@ pub async fn update_snapshot(
         storage.put_snapshot(stream_name, meta.snapshot).await?;
         if ch {
             if let Some(mut manifest) = storage.get_manifest(&path).await? {
-                manifest.apply_change(change);
+                for change in changes {
+                    manifest.apply_change(change);
+                }
                 storage.put_manifest(&path, manifest).await?;
             } else {
                 //instead of returning an error, create a new manifest (otherwise local to storage sync fails)
                 //but don't update the snapshot
                 create_manifest(
                     lower_bound,
-                    change,
+                    changes,
                     storage.clone(),
                     stream_name,
                     false,
@@ -181,7 +239,7 @@ pub async fn update_snapshot(
         } else {
             create_manifest(
                 lower_bound,
-                change,
+                changes,
                 storage.clone(),
                 stream_name,
                 true,
@@ -195,7 +253,7 @@ pub async fn update_snapshot(
     } else {
         create_manifest(
             lower_bound,
-            change,
+            changes,
             storage.clone(),
             stream_name,
             true,
@@ -213,7 +271,7 @@ pub async fn update_snapshot(
 #[allow(clippy::too_many_arguments)]
 async fn create_manifest(
     lower_bound: DateTime<Utc>,
-    change: manifest::File,
+    changes: Vec<manifest::File>,
     storage: Arc<dyn ObjectStorage + Send>,
     stream_name: &str,
     update_snapshot: bool,
@@ -235,7 +293,7 @@ async fn create_manifest(
         .and_utc();
 
     let manifest = Manifest {
-        files: vec![change],
+        files: changes,
         ..Manifest::default()
     };
 
@@ -254,6 +312,8 @@ async fn create_manifest(
             events_ingested,
             ingestion_size,
             storage_size,
+            tier: Tier::Hot,
+            format: PartitionFormat::Parquet,
         };
         manifests.push(new_snapshot_entry);
         meta.snapshot.manifest_list = manifests;
@@ -263,6 +323,106 @@ async fn create_manifest(
     Ok(())
 }
 
+/// Rebuilds all manifests and the snapshot of a stream from the parquet files present in storage.
+/// Existing manifests are overwritten, so running this repeatedly produces the same catalog.
+/// Returns the number of parquet files added to the rebuilt catalog.
+pub async fn rebuild_catalog(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+) -> Result<usize, ObjectStorageError> {
+    let mut meta = storage.get_object_store_format(stream_name).await?;
+    let time_partition = meta
+        .time_partition
+        .clone()
+        .unwrap_or_else(|| DEFAULT_TIMESTAMP_KEY.to_string());
+
+    let mut manifests: BTreeMap<NaiveDate, Manifest> = BTreeMap::new();
+    let mut files_processed = 0;
+    let mut parquet_files = storage.list_objects_meta(stream_name);
+    while let Some(object) = parquet_files.try_next().await? {
+        let Some((date, file)) =
+            catalog_entry(&*storage, &object.path, object.size, &time_partition).await?
+        else {
+            continue;
+        };
+        manifests.entry(date).or_default().files.push(file);
+        files_processed += 1;
+    }
+
+    let mut manifest_list = Vec::with_capacity(manifests.len());
+    for (date, manifest) in manifests {
+        let lower_bound = date.and_time(NaiveTime::MIN).and_utc();
+        let upper_bound = date
+            .and_hms_nano_opt(23, 59, 59, 999_999_999)
+            .expect("valid time")
+            .and_utc();
+        let path = partition_path(stream_name, lower_bound, upper_bound);
+        let manifest_file_path = manifest_path(path.as_str());
+
+        let item = snapshot::ManifestItem {
+            manifest_path: storage.absolute_url(&manifest_file_path).to_string(),
+            time_lower_bound: lower_bound,
+            time_upper_bound: upper_bound,
+            events_ingested: manifest.files.iter().map(|file| file.num_rows).sum(),
+            ingestion_size: manifest.files.iter().map(|file| file.ingestion_size).sum(),
+            storage_size: manifest.files.iter().map(|file| file.file_size).sum(),
+            tier: Tier::Hot,
+            format: PartitionFormat::Parquet,
+        };
+        storage.put_manifest(&path, manifest).await?;
+        manifest_list.push(item);
+    }
+
+    meta.snapshot.manifest_list = manifest_list;
+    storage.put_snapshot(stream_name, meta.snapshot).await?;
+
+    Ok(files_processed)
+}
+
+/// Manifest entry of a parquet object and the day it belongs to, read from the footer
+/// so the data pages aren't downloaded. None for unreadable files and files whose time
+/// partition column has no timestamp statistics, they are skipped by the rebuild.
+async fn catalog_entry(
+    storage: &(impl ObjectStorage + ?Sized),
+    path: &RelativePath,
+    file_size: u64,
+    time_partition: &str,
+) -> Result<Option<(NaiveDate, manifest::File)>, ObjectStorageError> {
+    let metadata = match storage.get_parquet_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(err @ (ObjectStorageError::Custom(_) | ObjectStorageError::UnhandledError(_))) => {
+            log::warn!("Skipping unreadable parquet file {path} during catalog rebuild: {err}");
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let file = manifest::create_from_parquet_metadata(
+        storage.absolute_url(path).to_string(),
+        file_size,
+        &metadata,
+    );
+
+    // statistics of other types have no unit, their values can't be taken for times
+    let lower_bound = file
+        .columns()
+        .iter()
+        .find(|col| col.name == time_partition)
+        .and_then(|col| match &col.stats {
+            Some(TypedStatistics::Timestamp(stats)) => {
+                Some(stats.bounds_in(&TimeUnit::Millisecond).0)
+            }
+            _ => None,
+        })
+        .and_then(DateTime::from_timestamp_millis);
+    let Some(lower_bound) = lower_bound else {
+        log::warn!(
+            "Skipping parquet file {path} without timestamp statistics for {time_partition} during catalog rebuild"
+        );
+        return Ok(None);
+    };
+    Ok(Some((lower_bound.date_naive(), file)))
+}
+
 pub async fn remove_manifest_from_snapshot(
     storage: Arc<dyn ObjectStorage + Send>,
     stream_name: &str,
@@ -308,7 +468,8 @@ pub async fn get_first_event(
                 manifest.time_lower_bound,
                 manifest.time_upper_bound,
             );
-            let Some(manifest) = storage.get_manifest(&path).await? else {
+            let manifest_storage = tiering::store_of(manifest.tier, storage.clone())?;
+            let Some(manifest) = manifest_storage.get_manifest(&path).await? else {
                 return Err(ObjectStorageError::UnhandledError(
                     "Manifest found in snapshot but not in object-storage"
                         .to_string()
@@ -369,6 +530,142 @@ pub async fn get_first_event(
     Ok(Some(first_event_at))
 }
 
+// stream name to the manifest list a summary was computed from and the summary
+type ColumnSummaries = HashMap<String, (Vec<ManifestItem>, Arc<Vec<Column>>)>;
+
+/// Whole stream column summaries, reused while the manifest list of the stream is unchanged
+static COLUMN_SUMMARIES: Lazy<RwLock<ColumnSummaries>> = Lazy::new(RwLock::default);
+
+/// Drops the cached column summary of a stream, called when its manifests change
+pub fn invalidate_column_summary(stream_name: &str) {
+    COLUMN_SUMMARIES
+        .write()
+        .expect("column summary lock is not poisoned")
+        .remove(stream_name);
+}
+
+/// Statistics of every column of a stream merged across all files in its manifests.
+/// Sizes and null counts are summed and min/max are unioned.
+pub async fn column_summary(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+) -> Result<Arc<Vec<Column>>, ObjectStorageError> {
+    let manifest_list = manifest_list(&*storage, stream_name).await?;
+
+    // uploads on any node change the manifest list, which invalidates the entry
+    if let Some((cached_list, summary)) = COLUMN_SUMMARIES
+        .read()
+        .expect("column summary lock is not poisoned")
+        .get(stream_name)
+    {
+        if *cached_list == manifest_list {
+            return Ok(Arc::clone(summary));
+        }
+    }
+
+    let mut files = Vec::new();
+    for item in &manifest_list {
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        let manifest_storage = tiering::store_of(item.tier, storage.clone())?;
+        let Some(manifest) = manifest_storage.get_manifest(&path).await? else {
+            continue;
+        };
+        files.extend(manifest.files);
+    }
+    let summary = Arc::new(summarize_columns(
+        files.iter().flat_map(|file| file.columns()),
+    ));
+
+    COLUMN_SUMMARIES
+        .write()
+        .expect("column summary lock is not poisoned")
+        .insert(
+            stream_name.to_owned(),
+            (manifest_list, Arc::clone(&summary)),
+        );
+    Ok(summary)
+}
+
+/// Manifest entries of every file of the stream uploaded on `date` with the tier
+/// of their partition, ordered by key
+pub async fn partition_files(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    date: NaiveDate,
+) -> Result<Vec<(manifest::File, Tier)>, ObjectStorageError> {
+    let mut files = Vec::new();
+    for item in manifest_list(&*storage, stream_name).await? {
+        if item.time_lower_bound.date_naive() != date {
+            continue;
+        }
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        let manifest_storage = tiering::store_of(item.tier, storage.clone())?;
+        if let Some(manifest) = manifest_storage.get_manifest(&path).await? {
+            files.extend(manifest.files.into_iter().map(|file| (file, item.tier)));
+        }
+    }
+    files.sort_by(|(a, _), (b, _)| a.file_path.cmp(&b.file_path));
+    // items of several ingestors can resolve to the same manifest
+    files.dedup_by(|(a, _), (b, _)| a.file_path == b.file_path);
+    Ok(files)
+}
+
+/// Manifest items of a stream, across the snapshots of all ingestors in query mode
+async fn manifest_list(
+    storage: &dyn ObjectStorage,
+    stream_name: &str,
+) -> Result<Vec<ManifestItem>, ObjectStorageError> {
+    let manifest_list = match CONFIG.parseable.mode {
+        // each ingestor keeps its own snapshot of the stream
+        Mode::Query => {
+            let path = RelativePathBuf::from_iter([stream_name, STREAM_ROOT_DIRECTORY]);
+            storage
+                .get_objects(
+                    Some(&path),
+                    Box::new(|file_name| file_name.ends_with("stream.json")),
+                )
+                .await?
+                .iter()
+                .filter_map(|bytes| serde_json::from_slice::<ObjectStoreFormat>(bytes).ok())
+                .flat_map(|format| format.snapshot.manifest_list)
+                .collect()
+        }
+        Mode::All | Mode::Ingest => {
+            storage
+                .get_object_store_format(stream_name)
+                .await?
+                .snapshot
+                .manifest_list
+        }
+    };
+    Ok(manifest_list)
+}
+
+/// Folds per file entries into one entry per column
+fn summarize_columns<'a>(columns: impl Iterator<Item = &'a Column>) -> Vec<Column> {
+    let mut summary: BTreeMap<&str, (Column, Vec<TypedStatistics>)> = BTreeMap::new();
+    for column in columns {
+        let (entry, stats) = summary.entry(&column.name).or_insert_with(|| {
+            (
+                Column::from_parquet(column.name.clone(), None, 0, 0),
+                Vec::new(),
+            )
+        });
+        entry.uncompressed_size += column.uncompressed_size;
+        entry.compressed_size += column.compressed_size;
+        entry.null_count += column.null_count;
+        stats.extend(column.stats.clone());
+    }
+
+    summary
+        .into_values()
+        .map(|(mut column, stats)| {
+            column.stats = TypedStatistics::merge_all(stats);
+            column
+        })
+        .collect()
+}
+
 /// Partition the path to which this manifest belongs.
 /// Useful when uploading the manifest file.
 pub fn partition_path(
@@ -376,11 +673,120 @@ pub fn partition_path(
     lower_bound: DateTime<Utc>,
     upper_bound: DateTime<Utc>,
 ) -> RelativePathBuf {
-    let lower = lower_bound.date_naive().format("%Y-%m-%d").to_string();
-    let upper = upper_bound.date_naive().format("%Y-%m-%d").to_string();
-    if lower == upper {
-        RelativePathBuf::from_iter([stream, &format!("date={}", lower)])
-    } else {
-        RelativePathBuf::from_iter([stream, &format!("date={}:{}", lower, upper)])
+    let partition =
+        key_naming::strategy().date_partition(lower_bound.date_naive(), upper_bound.date_naive());
+    RelativePathBuf::from_iter([stream, &partition])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Int64Array, RecordBatch, TimestampSecondArray};
+    use bytes::Bytes;
+    use chrono::NaiveDate;
+    use parquet::arrow::ArrowWriter;
+    use relative_path::RelativePath;
+
+    use super::{
+        catalog_entry,
+        column::{Column, Int64Type, TypedStatistics},
+        summarize_columns,
+    };
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    fn column(name: &str, bounds: Option<(i64, i64)>) -> Column {
+        Column {
+            name: name.to_string(),
+            stats: bounds.map(|(min, max)| TypedStatistics::Int(Int64Type { min, max })),
+            uncompressed_size: 100,
+            compressed_size: 10,
+            null_count: 1,
+        }
+    }
+
+    #[test]
+    fn summary_merges_files_per_column() {
+        let files = [
+            column("a", Some((10, 20))),
+            column("b", None),
+            column("a", Some((5, 15))),
+            column("a", None),
+        ];
+        let summary = summarize_columns(files.iter());
+
+        assert_eq!(summary.len(), 2);
+        let a = &summary[0];
+        assert_eq!(a.name, "a");
+        assert_eq!(a.uncompressed_size, 300);
+        assert_eq!(a.compressed_size, 30);
+        assert_eq!(a.null_count, 3);
+        assert!(matches!(
+            a.stats,
+            Some(TypedStatistics::Int(Int64Type { min: 5, max: 20 }))
+        ));
+        assert!(summary[1].stats.is_none());
+    }
+
+    fn parquet(column: ArrayRef) -> Bytes {
+        let batch = RecordBatch::try_from_iter([("p_timestamp", column)]).unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buffer, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        Bytes::from(buffer)
+    }
+
+    #[actix_web::test]
+    async fn files_are_placed_by_their_timestamp_statistics() {
+        let root = std::env::temp_dir().join(format!("parseable-catalog-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        // 2024-01-01T23:00:00Z and 2024-01-02T01:00:00Z, in seconds
+        let seconds: ArrayRef = Arc::new(TimestampSecondArray::from(vec![
+            1_704_150_000,
+            1_704_157_200,
+        ]));
+        let integers: ArrayRef = Arc::new(Int64Array::from(vec![1_704_150_000_000]));
+        let files = [
+            ("app/seconds.parquet", parquet(seconds)),
+            ("app/integers.parquet", parquet(integers)),
+            ("app/truncated.parquet", Bytes::from_static(b"PAR1")),
+        ];
+        for (path, bytes) in &files {
+            storage
+                .put_object(RelativePath::new(path), bytes.clone())
+                .await
+                .unwrap();
+        }
+
+        let mut entries = vec![];
+        for (path, bytes) in &files {
+            let entry = catalog_entry(
+                &storage,
+                RelativePath::new(path),
+                bytes.len() as u64,
+                "p_timestamp",
+            )
+            .await
+            .unwrap();
+            entries.push(entry);
+        }
+        let missing = catalog_entry(
+            &storage,
+            RelativePath::new("app/missing.parquet"),
+            0,
+            "p_timestamp",
+        )
+        .await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let (date, file) = entries[0].take().unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        assert_eq!(file.file_size, files[0].1.len() as u64);
+        assert_eq!(file.num_rows, 2);
+        // integers have no unit and truncated files no footer, both are skipped
+        assert!(entries[1].is_none());
+        assert!(entries[2].is_none());
+        assert!(missing.is_err());
     }
 }
@@ -0,0 +1,253 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Every uploaded file rewrites the snapshot and the manifest of its partition. With a
+//! write interval configured the entries of the files of a stream are collected and
+//! written at once when the interval since the first one ends.
+//!
+//! While entries are collected a marker file sits in the staging directory of the
+//! stream. A stream still marked on startup lost entries of uploaded files, its catalog
+//! is rebuilt from storage like the repair endpoint does.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+use super::{manifest, rebuild_catalog, update_snapshot, update_snapshot_batch};
+use crate::metadata::STREAM_INFO;
+use crate::option::{Mode, CONFIG};
+use crate::storage::{object_storage::SYNC_LOCK, ObjectStorage, ObjectStorageError};
+
+const PENDING_MARKER: &str = ".manifest_pending";
+
+// how often collected entries are checked for the end of their interval
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub static MANIFEST_BATCH: Lazy<ManifestBatch> = Lazy::new(|| {
+    // a rebuild on an ingestor would claim the files of every ingestor
+    let interval = match CONFIG.parseable.mode {
+        Mode::Ingest => Duration::ZERO,
+        _ => Duration::from_secs(CONFIG.parseable.manifest_write_interval),
+    };
+    ManifestBatch::new(interval)
+});
+
+/// Manifest entries collected per stream
+#[derive(Debug)]
+pub struct ManifestBatch {
+    interval: Duration,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    since: Instant,
+    changes: Vec<manifest::File>,
+}
+
+impl ManifestBatch {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Collects `change`, returns whether it is the first entry collected for `stream`
+    fn add(&self, stream: &str, change: manifest::File, now: Instant) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get_mut(stream) {
+            Some(batch) => {
+                batch.changes.push(change);
+                false
+            }
+            None => {
+                pending.insert(
+                    stream.to_owned(),
+                    Pending {
+                        since: now,
+                        changes: vec![change],
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Takes the entries of the streams whose interval ended at `now`
+    fn take_due(&self, now: Instant) -> Vec<(String, Vec<manifest::File>)> {
+        let mut pending = self.pending.lock().unwrap();
+        let due: Vec<String> = pending
+            .iter()
+            .filter(|(_, batch)| now.duration_since(batch.since) >= self.interval)
+            .map(|(stream, _)| stream.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|stream| {
+                let batch = pending.remove(&stream)?;
+                Some((stream, batch.changes))
+            })
+            .collect()
+    }
+
+    fn take(&self, stream: &str) -> Option<Vec<manifest::File>> {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(stream)
+            .map(|batch| batch.changes)
+    }
+}
+
+fn marker_path(stream: &str) -> PathBuf {
+    CONFIG
+        .parseable
+        .local_stream_data_path(stream)
+        .join(PENDING_MARKER)
+}
+
+/// Adds the manifest entry of an uploaded file to the catalog of `stream`, collected
+/// when a write interval is configured and written at once otherwise
+pub async fn update(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream: &str,
+    change: manifest::File,
+) -> Result<(), ObjectStorageError> {
+    if MANIFEST_BATCH.interval.is_zero() {
+        return update_snapshot(storage, stream, change).await;
+    }
+    if MANIFEST_BATCH.add(stream, change, Instant::now()) {
+        std::fs::write(marker_path(stream), b"")?;
+    }
+    Ok(())
+}
+
+/// Writes the collected entries of `stream` now, called with the sync lock held
+pub async fn flush(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream: &str,
+) -> Result<(), ObjectStorageError> {
+    match MANIFEST_BATCH.take(stream) {
+        Some(changes) => write(storage, stream, changes).await,
+        None => Ok(()),
+    }
+}
+
+// entries of a failed write are dropped, the marker left behind rebuilds the catalog on startup
+async fn write(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream: &str,
+    changes: Vec<manifest::File>,
+) -> Result<(), ObjectStorageError> {
+    update_snapshot_batch(storage, stream, changes).await?;
+    if let Err(err) = std::fs::remove_file(marker_path(stream)) {
+        log::warn!("Failed to remove pending manifest marker of {stream}: {err}");
+    }
+    Ok(())
+}
+
+/// Writes collected entries once their interval ends
+pub fn init() {
+    if MANIFEST_BATCH.interval.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _guard = SYNC_LOCK.lock().await;
+            for (stream, changes) in MANIFEST_BATCH.take_due(Instant::now()) {
+                let storage = CONFIG.storage().get_object_store();
+                if let Err(err) = write(storage, &stream, changes).await {
+                    log::error!("Failed to write manifest updates of {stream}: {err}");
+                }
+            }
+        }
+    });
+}
+
+/// Rebuilds the catalog of the streams whose collected entries weren't written
+/// before the server stopped
+pub async fn reconcile(storage: Arc<dyn ObjectStorage + Send>) {
+    for stream in STREAM_INFO.list_streams() {
+        let marker = marker_path(&stream);
+        if !marker.exists() {
+            continue;
+        }
+        log::warn!(
+            "Manifest updates of {stream} were not written before restart, rebuilding its catalog"
+        );
+        match rebuild_catalog(storage.clone(), &stream).await {
+            Ok(files) => {
+                log::info!("Rebuilt catalog of {stream} with {files} files");
+                if let Err(err) = std::fs::remove_file(&marker) {
+                    log::warn!("Failed to remove pending manifest marker of {stream}: {err}");
+                }
+            }
+            Err(err) => log::error!("Failed to rebuild catalog of {stream}: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ManifestBatch;
+    use crate::catalog::manifest;
+
+    fn file(name: &str) -> manifest::File {
+        manifest::File {
+            file_path: name.to_owned(),
+            ..manifest::File::default()
+        }
+    }
+
+    #[test]
+    fn quick_updates_are_coalesced_into_one_write() {
+        let batch = ManifestBatch::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        // ten flushes a second apart, one write per stream once the interval ends
+        for second in 0..10 {
+            let now = start + Duration::from_secs(second);
+            let first = batch.add("app", file(&format!("{second}.parquet")), now);
+            assert_eq!(first, second == 0);
+            assert!(batch.take_due(now).is_empty());
+        }
+        batch.add("other", file("a.parquet"), start + Duration::from_secs(5));
+
+        let writes = batch.take_due(start + Duration::from_secs(10));
+        assert_eq!(writes.len(), 1);
+        let (stream, changes) = &writes[0];
+        assert_eq!(stream, "app");
+        assert_eq!(changes.len(), 10);
+        assert_eq!(changes[9].file_path, "9.parquet");
+
+        // a later update starts a new batch
+        assert!(batch.add("app", file("10.parquet"), start + Duration::from_secs(11)));
+        assert_eq!(batch.take("other").map(|changes| changes.len()), Some(1));
+        assert!(batch.take_due(start + Duration::from_secs(15)).is_empty());
+        assert_eq!(batch.take_due(start + Duration::from_secs(21)).len(), 1);
+    }
+}
@@ -18,8 +18,9 @@
 
 use std::cmp::{max, min};
 
-use arrow_schema::DataType;
+use arrow_schema::{DataType, TimeUnit};
 use datafusion::scalar::ScalarValue;
+use parquet::file::page_index::index::{Index, PageIndex};
 use parquet::file::statistics::Statistics;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -40,24 +41,225 @@ pub struct Int64Type {
     pub max: i64,
 }
 
+/// Days since the unix epoch. Date64 columns are written to parquet in days as well.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DateType {
+    pub min: i32,
+    pub max: i32,
+}
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TimestampType {
+    pub min: i64,
+    pub max: i64,
+    pub unit: TimeUnit,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+}
+
+impl TimestampType {
+    /// Bounds converted to `unit`. A coarser min is rounded down and a coarser max up,
+    /// a finer bound past the range of i64 saturates, so they still bound the column.
+    pub fn bounds_in(&self, unit: &TimeUnit) -> (i64, i64) {
+        let (from, to) = (units_per_second(&self.unit), units_per_second(unit));
+        if to >= from {
+            let factor = to / from;
+            (
+                self.min.saturating_mul(factor),
+                self.max.saturating_mul(factor),
+            )
+        } else {
+            let factor = from / to;
+            let max = self.max.div_euclid(factor) + i64::from(self.max.rem_euclid(factor) != 0);
+            (self.min.div_euclid(factor), max)
+        }
+    }
+}
+
+fn units_per_second(unit: &TimeUnit) -> i64 {
+    match unit {
+        TimeUnit::Second => 1,
+        TimeUnit::Millisecond => 1_000,
+        TimeUnit::Microsecond => 1_000_000,
+        TimeUnit::Nanosecond => 1_000_000_000,
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Utf8Type {
     pub min: String,
     pub max: String,
+    /// Bounds were cut to `MAX_BINARY_STATS_LENGTH` bytes. A cut min is still a lower bound,
+    /// a cut max is only a prefix of the real upper bound and can't be used for pruning.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl Utf8Type {
+    /// Cuts bounds longer than `MAX_BINARY_STATS_LENGTH` bytes at a char boundary
+    pub fn truncated(min: &str, max: &str) -> Self {
+        Self {
+            min: truncate_str(min).to_owned(),
+            max: truncate_str(max).to_owned(),
+            truncated: max.len() > MAX_BINARY_STATS_LENGTH,
+        }
+    }
+}
+
+fn truncate_str(value: &str) -> &str {
+    let mut end = value.len().min(MAX_BINARY_STATS_LENGTH);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BinaryType {
+    pub min: Vec<u8>,
+    pub max: Vec<u8>,
+}
+
+/// Max length in bytes of binary and string min/max kept in a manifest
+pub const MAX_BINARY_STATS_LENGTH: usize = 64;
+
+impl BinaryType {
+    /// Truncates bounds to `MAX_BINARY_STATS_LENGTH` bytes while keeping them valid,
+    /// min is cut to a prefix and max is cut to a prefix with its last byte incremented.
+    pub fn truncated(min: &[u8], max: &[u8]) -> Self {
+        let min = min[..min.len().min(MAX_BINARY_STATS_LENGTH)].to_vec();
+        let max = truncate_upper_bound(max);
+        Self { min, max }
+    }
+}
+
+fn truncate_upper_bound(max: &[u8]) -> Vec<u8> {
+    if max.len() <= MAX_BINARY_STATS_LENGTH {
+        return max.to_vec();
+    }
+
+    let mut truncated = max[..MAX_BINARY_STATS_LENGTH].to_vec();
+    while let Some(last) = truncated.pop() {
+        if last < u8::MAX {
+            truncated.push(last + 1);
+            return truncated;
+        }
+    }
+    // every byte in the prefix is 0xFF, no shorter upper bound exists
+    max.to_vec()
 }
 
 // Typed statistics are typed variant of statistics
-// Currently all parquet types are casted down to these 4 types
-// Binary types are assumed to be of valid Utf8
+// Currently all parquet types are casted down to these 7 types
+// Byte arrays are stored as String if they are valid Utf8 and as Binary otherwise
+// Dates and timestamps carry their unit, manifests written before they did store them as Int
+// List columns keep the statistics of their elements
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum TypedStatistics {
     Bool(BoolType),
     Int(Int64Type),
     Float(Float64Type),
     String(Utf8Type),
+    Binary(BinaryType),
+    Date(DateType),
+    Timestamp(TimestampType),
+    List(Box<TypedStatistics>),
+}
+
+/// Type of typed statistics, statistics of the same type can be updated with each other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatisticsType {
+    Bool,
+    Int,
+    Float,
+    String,
+    Binary,
+    Date,
+    Timestamp,
+    List(Box<StatisticsType>),
+}
+
+impl StatisticsType {
+    /// Whether statistics of both types can be updated with each other. Legacy `Int`
+    /// statistics of a temporal column go with its typed statistics.
+    fn merges_with(&self, other: &Self) -> bool {
+        match (self, other) {
+            (StatisticsType::Int, StatisticsType::Date | StatisticsType::Timestamp)
+            | (StatisticsType::Date | StatisticsType::Timestamp, StatisticsType::Int) => true,
+            (StatisticsType::List(this), StatisticsType::List(other)) => this.merges_with(other),
+            (this, other) => this == other,
+        }
+    }
 }
 
 impl TypedStatistics {
+    pub fn statistics_type(&self) -> StatisticsType {
+        match self {
+            TypedStatistics::Bool(_) => StatisticsType::Bool,
+            TypedStatistics::Int(_) => StatisticsType::Int,
+            TypedStatistics::Float(_) => StatisticsType::Float,
+            TypedStatistics::String(_) => StatisticsType::String,
+            TypedStatistics::Binary(_) => StatisticsType::Binary,
+            TypedStatistics::Date(_) => StatisticsType::Date,
+            TypedStatistics::Timestamp(_) => StatisticsType::Timestamp,
+            TypedStatistics::List(inner) => StatisticsType::List(inner.statistics_type().into()),
+        }
+    }
+
+    /// Whether both statistics are of the same type and can be updated with each other
+    pub fn same_type(&self, other: &Self) -> bool {
+        self.statistics_type().merges_with(&other.statistics_type())
+    }
+
+    /// Statistics read as those of a column of `data_type`. Manifests written before
+    /// temporal statistics were typed have `Int` bounds of dates and timestamps, these
+    /// are the days or the units of the column.
+    pub fn for_type(self, data_type: &DataType) -> Self {
+        match (self, data_type) {
+            (TypedStatistics::Int(stats), DataType::Date32 | DataType::Date64) => {
+                TypedStatistics::Date(DateType {
+                    min: stats.min as i32,
+                    max: stats.max as i32,
+                })
+            }
+            (TypedStatistics::Int(stats), DataType::Timestamp(unit, tz)) => {
+                TypedStatistics::Timestamp(TimestampType {
+                    min: stats.min,
+                    max: stats.max,
+                    unit: unit.clone(),
+                    tz: tz.as_deref().map(str::to_owned),
+                })
+            }
+            (TypedStatistics::List(inner), DataType::List(field) | DataType::LargeList(field)) => {
+                TypedStatistics::List(Box::new(inner.for_type(field.data_type())))
+            }
+            (stats, DataType::Dictionary(_, value_type)) => stats.for_type(value_type),
+            (stats, _) => stats,
+        }
+    }
+
+    /// Bounds of the statistics of a timestamp column in `unit`. Legacy `Int` statistics
+    /// are taken to be in `unit` already.
+    pub fn timestamp_bounds(&self, unit: &TimeUnit) -> Option<(i64, i64)> {
+        match self {
+            TypedStatistics::Int(stats) => Some((stats.min, stats.max)),
+            TypedStatistics::Timestamp(stats) => Some(stats.bounds_in(unit)),
+            _ => None,
+        }
+    }
+
+    /// Folds the statistics of a column across files into one.
+    /// `None` when there are no statistics or they disagree on the type.
+    pub fn merge_all(stats: impl IntoIterator<Item = Self>) -> Option<Self> {
+        let mut stats = stats.into_iter();
+        let first = stats.next()?;
+        stats.try_fold(first, |merged, other| {
+            merged.same_type(&other).then(|| merged.update(other))
+        })
+    }
+
     pub fn update(self, other: Self) -> Self {
         match (self, other) {
             (TypedStatistics::Bool(this), TypedStatistics::Bool(other)) => {
@@ -82,14 +284,86 @@ impl TypedStatistics {
                 TypedStatistics::String(Utf8Type {
                     min: min(this.min, other.min),
                     max: max(this.max, other.max),
+                    truncated: this.truncated || other.truncated,
                 })
             }
+            (TypedStatistics::Binary(this), TypedStatistics::Binary(other)) => {
+                TypedStatistics::Binary(BinaryType {
+                    min: min(this.min, other.min),
+                    max: max(this.max, other.max),
+                })
+            }
+            (TypedStatistics::Date(this), TypedStatistics::Date(other)) => {
+                TypedStatistics::Date(DateType {
+                    min: min(this.min, other.min),
+                    max: max(this.max, other.max),
+                })
+            }
+            (TypedStatistics::Timestamp(this), TypedStatistics::Timestamp(other)) => {
+                // bounds of the other are converted to the unit of this one
+                let (other_min, other_max) = other.bounds_in(&this.unit);
+                TypedStatistics::Timestamp(TimestampType {
+                    min: min(this.min, other_min),
+                    max: max(this.max, other_max),
+                    ..this
+                })
+            }
+            (TypedStatistics::Int(legacy), typed) | (typed, TypedStatistics::Int(legacy))
+                if matches!(
+                    typed,
+                    TypedStatistics::Date(_) | TypedStatistics::Timestamp(_)
+                ) =>
+            {
+                let data_type = match &typed {
+                    TypedStatistics::Timestamp(stats) => {
+                        DataType::Timestamp(stats.unit.clone(), stats.tz.as_deref().map(Into::into))
+                    }
+                    _ => DataType::Date32,
+                };
+                typed.update(TypedStatistics::Int(legacy).for_type(&data_type))
+            }
+            (TypedStatistics::List(this), TypedStatistics::List(other)) => {
+                TypedStatistics::List(Box::new(this.update(*other)))
+            }
             _ => panic!("Cannot update wrong types"),
         }
     }
 
+    /// Min and max as JSON values. Binary bounds are hex encoded and list statistics are
+    /// the bounds of their elements.
+    pub fn min_max_json(&self) -> (serde_json::Value, serde_json::Value) {
+        use serde_json::json;
+        match self {
+            TypedStatistics::Bool(stats) => (json!(stats.min), json!(stats.max)),
+            TypedStatistics::Int(stats) => (json!(stats.min), json!(stats.max)),
+            TypedStatistics::Date(stats) => (json!(stats.min), json!(stats.max)),
+            TypedStatistics::Timestamp(stats) => (json!(stats.min), json!(stats.max)),
+            // NaN and infinite bounds have no JSON number and are rendered as null
+            TypedStatistics::Float(stats) => (json!(stats.min), json!(stats.max)),
+            TypedStatistics::String(stats) => (json!(stats.min), json!(stats.max)),
+            TypedStatistics::Binary(stats) => (
+                json!(hex::encode(&stats.min)),
+                json!(hex::encode(&stats.max)),
+            ),
+            TypedStatistics::List(inner) => inner.min_max_json(),
+        }
+    }
+
     pub fn min_max_as_scalar(self, datatype: &DataType) -> Option<(ScalarValue, ScalarValue)> {
-        let (min, max) = match (self, datatype) {
+        // a truncated max is not an upper bound of the column
+        if matches!(&self, TypedStatistics::String(stats) if stats.truncated) {
+            return None;
+        }
+        // dictionary encoded columns are written with the statistics of their values
+        if let DataType::Dictionary(key_type, value_type) = datatype {
+            let (min, max) = self.min_max_as_scalar(value_type)?;
+            return Some((
+                ScalarValue::Dictionary(key_type.clone(), Box::new(min)),
+                ScalarValue::Dictionary(key_type.clone(), Box::new(max)),
+            ));
+        }
+
+        let (min, max) = match (self.for_type(datatype), datatype) {
             (TypedStatistics::Bool(stats), DataType::Boolean) => (
                 ScalarValue::Boolean(Some(stats.min)),
                 ScalarValue::Boolean(Some(stats.max)),
@@ -110,10 +384,52 @@ impl TypedStatistics {
                 ScalarValue::Float64(Some(stats.min)),
                 ScalarValue::Float64(Some(stats.max)),
             ),
+            (TypedStatistics::Date(stats), DataType::Date32) => (
+                ScalarValue::Date32(Some(stats.min)),
+                ScalarValue::Date32(Some(stats.max)),
+            ),
+            (TypedStatistics::Date(stats), DataType::Date64) => (
+                ScalarValue::Date64(Some(stats.min as i64 * MILLIS_PER_DAY)),
+                ScalarValue::Date64(Some(stats.max as i64 * MILLIS_PER_DAY)),
+            ),
+            (TypedStatistics::Timestamp(stats), DataType::Timestamp(unit, tz)) => {
+                let (min, max) = stats.bounds_in(unit);
+                let (min, max) = (Some(min), Some(max));
+                match unit {
+                    TimeUnit::Second => (
+                        ScalarValue::TimestampSecond(min, tz.clone()),
+                        ScalarValue::TimestampSecond(max, tz.clone()),
+                    ),
+                    TimeUnit::Millisecond => (
+                        ScalarValue::TimestampMillisecond(min, tz.clone()),
+                        ScalarValue::TimestampMillisecond(max, tz.clone()),
+                    ),
+                    TimeUnit::Microsecond => (
+                        ScalarValue::TimestampMicrosecond(min, tz.clone()),
+                        ScalarValue::TimestampMicrosecond(max, tz.clone()),
+                    ),
+                    TimeUnit::Nanosecond => (
+                        ScalarValue::TimestampNanosecond(min, tz.clone()),
+                        ScalarValue::TimestampNanosecond(max, tz.clone()),
+                    ),
+                }
+            }
             (TypedStatistics::String(stats), DataType::Utf8) => (
                 ScalarValue::Utf8(Some(stats.min)),
                 ScalarValue::Utf8(Some(stats.max)),
             ),
+            (TypedStatistics::String(stats), DataType::LargeUtf8) => (
+                ScalarValue::LargeUtf8(Some(stats.min)),
+                ScalarValue::LargeUtf8(Some(stats.max)),
+            ),
+            (TypedStatistics::Binary(stats), DataType::Binary) => (
+                ScalarValue::Binary(Some(stats.min)),
+                ScalarValue::Binary(Some(stats.max)),
+            ),
+            (TypedStatistics::Binary(stats), DataType::LargeBinary) => (
+                ScalarValue::LargeBinary(Some(stats.min)),
+                ScalarValue::LargeBinary(Some(stats.max)),
+            ),
             _ => {
                 return None;
             }
@@ -131,6 +447,62 @@ pub struct Column {
     pub stats: Option<TypedStatistics>,
     pub uncompressed_size: u64,
     pub compressed_size: u64,
+    #[serde(default)]
+    pub null_count: u64,
+}
+
+impl Column {
+    /// Builds a column entry from parquet column chunk statistics.
+    /// Sizes and null count are always recorded, min/max stats are left out
+    /// when they are missing or can't be converted.
+    pub fn from_parquet(
+        name: String,
+        stats: Option<&Statistics>,
+        uncompressed_size: u64,
+        compressed_size: u64,
+    ) -> Self {
+        Self {
+            name,
+            stats: stats.and_then(|stats| stats.try_into().ok()),
+            uncompressed_size,
+            compressed_size,
+            null_count: stats.map_or(0, |stats| stats.null_count()),
+        }
+    }
+
+    /// Replaces min/max stats with the bounds of the column index of the chunk, when
+    /// it has one. Files written with page statistics carry a column index.
+    pub fn with_column_index(mut self, index: Option<&Index>) -> Self {
+        if let Some(stats) = index.and_then(|index| TypedStatistics::try_from(index).ok()) {
+            self.stats = Some(stats);
+        }
+        self
+    }
+
+    /// Ratio of uncompressed to compressed size, `None` when no data was written
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.compressed_size > 0)
+            .then(|| self.uncompressed_size as f64 / self.compressed_size as f64)
+    }
+
+    /// Merges statistics of the same column from another row group or file into this one.
+    /// Sizes and null counts are summed. Min/max stats are kept if either side has them
+    /// and dropped if both sides disagree on the type.
+    pub fn merge(&mut self, other: &Column) {
+        self.uncompressed_size += other.uncompressed_size;
+        self.compressed_size += other.compressed_size;
+        self.null_count += other.null_count;
+        self.stats = match (self.stats.take(), other.stats.clone()) {
+            (Some(this), Some(other)) => {
+                if this.same_type(&other) {
+                    Some(this.update(other))
+                } else {
+                    None
+                }
+            }
+            (this, other) => this.or(other),
+        };
+    }
 }
 
 impl TryFrom<&Statistics> for TypedStatistics {
@@ -167,16 +539,486 @@ impl TryFrom<&Statistics> for TypedStatistics {
                 min: *stats.min(),
                 max: *stats.max(),
             }),
-            Statistics::ByteArray(stats) => TypedStatistics::String(Utf8Type {
-                min: stats.min().as_utf8()?.to_owned(),
-                max: stats.max().as_utf8()?.to_owned(),
-            }),
-            Statistics::FixedLenByteArray(stats) => TypedStatistics::String(Utf8Type {
-                min: stats.min().as_utf8()?.to_owned(),
-                max: stats.max().as_utf8()?.to_owned(),
-            }),
+            Statistics::ByteArray(stats) => {
+                byte_array_stats(stats.min().data(), stats.max().data())
+            }
+            Statistics::FixedLenByteArray(stats) => {
+                byte_array_stats(stats.min().data(), stats.max().data())
+            }
         };
 
         Ok(res)
     }
 }
+
+/// Bounds of a column chunk from its column index, the least page min and the greatest
+/// page max. Fails when no page has bounds, e.g. pages of nulls only.
+impl TryFrom<&Index> for TypedStatistics {
+    type Error = parquet::errors::ParquetError;
+    fn try_from(value: &Index) -> Result<Self, Self::Error> {
+        let res = match value {
+            Index::NONE => None,
+            Index::BOOLEAN(index) => page_bounds(&index.indexes, |value| *value)
+                .map(|(min, max)| TypedStatistics::Bool(BoolType { min, max })),
+            Index::INT32(index) => page_bounds(&index.indexes, |value| *value as i64)
+                .map(|(min, max)| TypedStatistics::Int(Int64Type { min, max })),
+            Index::INT64(index) => page_bounds(&index.indexes, |value| *value)
+                .map(|(min, max)| TypedStatistics::Int(Int64Type { min, max })),
+            Index::INT96(index) => page_bounds(&index.indexes, |value| value.to_i64())
+                .map(|(min, max)| TypedStatistics::Int(Int64Type { min, max })),
+            Index::FLOAT(index) => page_bounds(&index.indexes, |value| *value as f64)
+                .map(|(min, max)| TypedStatistics::Float(Float64Type { min, max })),
+            Index::DOUBLE(index) => page_bounds(&index.indexes, |value| *value)
+                .map(|(min, max)| TypedStatistics::Float(Float64Type { min, max })),
+            Index::BYTE_ARRAY(index) => page_bounds(&index.indexes, |value| value.data())
+                .map(|(min, max)| byte_array_stats(min, max)),
+            Index::FIXED_LEN_BYTE_ARRAY(index) => page_bounds(&index.indexes, |value| value.data())
+                .map(|(min, max)| byte_array_stats(min, max)),
+        };
+
+        res.ok_or_else(|| {
+            parquet::errors::ParquetError::General("column index has no min max".to_string())
+        })
+    }
+}
+
+fn page_bounds<'a, T, K: PartialOrd>(
+    pages: &'a [PageIndex<T>],
+    key: impl Fn(&'a T) -> K,
+) -> Option<(K, K)> {
+    let min = pages
+        .iter()
+        .filter_map(|page| page.min.as_ref().map(&key))
+        .reduce(|min, value| if value < min { value } else { min })?;
+    let max = pages
+        .iter()
+        .filter_map(|page| page.max.as_ref().map(&key))
+        .reduce(|max, value| if value > max { value } else { max })?;
+    Some((min, max))
+}
+
+fn byte_array_stats(min: &[u8], max: &[u8]) -> TypedStatistics {
+    match (std::str::from_utf8(min), std::str::from_utf8(max)) {
+        (Ok(min), Ok(max)) => TypedStatistics::String(Utf8Type::truncated(min, max)),
+        _ => TypedStatistics::Binary(BinaryType::truncated(min, max)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{types::Int32Type, ArrayRef, DictionaryArray, RecordBatch};
+    use arrow_schema::{DataType, TimeUnit};
+    use bytes::Bytes;
+    use datafusion::scalar::ScalarValue;
+    use parquet::{
+        arrow::ArrowWriter,
+        file::{
+            page_index::index::{Index, NativeIndex, PageIndex},
+            reader::{FileReader, SerializedFileReader},
+            statistics::Statistics,
+        },
+        format::BoundaryOrder,
+    };
+    use rstest::rstest;
+
+    use super::{
+        BinaryType, Column, DateType, Int64Type, TimestampType, TypedStatistics, Utf8Type,
+        MAX_BINARY_STATS_LENGTH,
+    };
+
+    fn int_stats() -> TypedStatistics {
+        TypedStatistics::Int(Int64Type { min: 10, max: 20 })
+    }
+
+    fn column(stats: Option<TypedStatistics>) -> Column {
+        Column {
+            name: "a".to_string(),
+            stats,
+            uncompressed_size: 100,
+            compressed_size: 10,
+            null_count: 1,
+        }
+    }
+
+    fn int_bounds(column: &Column) -> Option<(i64, i64)> {
+        match column.stats {
+            Some(TypedStatistics::Int(Int64Type { min, max })) => Some((min, max)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn from_parquet_with_min_max() {
+        let stats = Statistics::int64(Some(1), Some(5), None, 2, false);
+        let column = Column::from_parquet("a".to_string(), Some(&stats), 100, 10);
+
+        assert_eq!(int_bounds(&column), Some((1, 5)));
+        assert_eq!(column.null_count, 2);
+        assert_eq!(column.uncompressed_size, 100);
+        assert_eq!(column.compressed_size, 10);
+    }
+
+    #[test]
+    fn from_parquet_without_min_max() {
+        let stats = Statistics::int64(None, None, None, 3, false);
+        let column = Column::from_parquet("a".to_string(), Some(&stats), 100, 10);
+
+        assert!(column.stats.is_none());
+        assert_eq!(column.null_count, 3);
+        assert_eq!(column.uncompressed_size, 100);
+        assert_eq!(column.compressed_size, 10);
+    }
+
+    #[test]
+    fn column_index_bounds_replace_legacy_statistics() {
+        let legacy = Statistics::int64(Some(1), Some(50), None, 0, false);
+        let page = |min: Option<i64>, max: Option<i64>| PageIndex {
+            min,
+            max,
+            null_count: Some(0),
+        };
+        let index = Index::INT64(NativeIndex {
+            indexes: vec![
+                page(Some(3), Some(9)),
+                page(None, None),
+                page(Some(2), Some(7)),
+            ],
+            boundary_order: BoundaryOrder::UNORDERED,
+        });
+
+        let column = Column::from_parquet("a".to_string(), Some(&legacy), 100, 10);
+        assert_eq!(int_bounds(&column), Some((1, 50)));
+        let column = column.with_column_index(Some(&index));
+        assert_eq!(int_bounds(&column), Some((2, 9)));
+
+        // legacy statistics are kept without a usable column index
+        let nulls = Index::INT64(NativeIndex {
+            indexes: vec![page(None, None)],
+            boundary_order: BoundaryOrder::UNORDERED,
+        });
+        for index in [None, Some(&Index::NONE), Some(&nulls)] {
+            let column = Column::from_parquet("a".to_string(), Some(&legacy), 100, 10)
+                .with_column_index(index);
+            assert_eq!(int_bounds(&column), Some((1, 50)));
+        }
+    }
+
+    #[test]
+    fn from_parquet_without_statistics() {
+        let column = Column::from_parquet("a".to_string(), None, 100, 10);
+
+        assert!(column.stats.is_none());
+        assert_eq!(column.null_count, 0);
+        assert_eq!(column.uncompressed_size, 100);
+    }
+
+    #[test]
+    fn merge_none_with_some() {
+        let mut this = column(None);
+        this.merge(&column(Some(int_stats())));
+
+        assert_eq!(int_bounds(&this), Some((10, 20)));
+        assert_eq!(this.uncompressed_size, 200);
+        assert_eq!(this.compressed_size, 20);
+        assert_eq!(this.null_count, 2);
+    }
+
+    #[test]
+    fn merge_some_with_none() {
+        let mut this = column(Some(int_stats()));
+        this.merge(&column(None));
+
+        assert_eq!(int_bounds(&this), Some((10, 20)));
+        assert_eq!(this.null_count, 2);
+    }
+
+    #[test]
+    fn merge_none_with_none() {
+        let mut this = column(None);
+        this.merge(&column(None));
+
+        assert!(this.stats.is_none());
+        assert_eq!(this.compressed_size, 20);
+    }
+
+    #[test]
+    fn merge_some_with_some() {
+        let mut this = column(Some(int_stats()));
+        this.merge(&column(Some(TypedStatistics::Int(Int64Type {
+            min: 5,
+            max: 15,
+        }))));
+
+        assert_eq!(int_bounds(&this), Some((5, 20)));
+    }
+
+    #[test]
+    fn long_strings_are_truncated_at_char_boundary() {
+        let max = "é".repeat(MAX_BINARY_STATS_LENGTH);
+        let stats = Utf8Type::truncated("a", &max);
+
+        assert!(stats.truncated);
+        assert_eq!(stats.max, "é".repeat(MAX_BINARY_STATS_LENGTH / 2));
+        assert!(!Utf8Type::truncated("a", "b").truncated);
+        assert!(TypedStatistics::String(stats)
+            .min_max_as_scalar(&DataType::Utf8)
+            .is_none());
+    }
+
+    #[test]
+    fn merge_all_unions_min_max() {
+        let merged = TypedStatistics::merge_all([
+            int_stats(),
+            TypedStatistics::Int(Int64Type { min: 5, max: 15 }),
+            TypedStatistics::Int(Int64Type { min: 12, max: 30 }),
+        ]);
+        assert_eq!(int_bounds(&column(merged)), Some((5, 30)));
+
+        assert!(TypedStatistics::merge_all([]).is_none());
+        assert!(TypedStatistics::merge_all([
+            int_stats(),
+            TypedStatistics::Binary(BinaryType::truncated(b"a", b"b")),
+        ])
+        .is_none());
+    }
+
+    #[rstest]
+    #[case(TimeUnit::Second, ScalarValue::TimestampSecond(Some(10), None))]
+    #[case(
+        TimeUnit::Millisecond,
+        ScalarValue::TimestampMillisecond(Some(10), None)
+    )]
+    #[case(
+        TimeUnit::Microsecond,
+        ScalarValue::TimestampMicrosecond(Some(10), None)
+    )]
+    #[case(TimeUnit::Nanosecond, ScalarValue::TimestampNanosecond(Some(10), None))]
+    fn timestamp_unit_is_honored(#[case] unit: TimeUnit, #[case] expected_min: ScalarValue) {
+        let (min, max) = int_stats()
+            .min_max_as_scalar(&DataType::Timestamp(unit, None))
+            .unwrap();
+
+        assert_eq!(min, expected_min);
+        assert_eq!(max.data_type(), expected_min.data_type());
+    }
+
+    #[test]
+    fn timestamp_timezone_is_preserved() {
+        let tz: Option<Arc<str>> = Some("+05:30".into());
+        let (min, max) = int_stats()
+            .min_max_as_scalar(&DataType::Timestamp(TimeUnit::Millisecond, tz.clone()))
+            .unwrap();
+
+        assert_eq!(min, ScalarValue::TimestampMillisecond(Some(10), tz.clone()));
+        assert_eq!(max, ScalarValue::TimestampMillisecond(Some(20), tz));
+    }
+
+    fn timestamp_stats(min: i64, max: i64, unit: TimeUnit) -> TypedStatistics {
+        TypedStatistics::Timestamp(TimestampType {
+            min,
+            max,
+            unit,
+            tz: Some("+05:30".to_string()),
+        })
+    }
+
+    #[test]
+    fn temporal_stats_serde_round_trip() {
+        let stats = timestamp_stats(1_000, 2_000, TimeUnit::Millisecond);
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "Timestamp": {"min": 1_000, "max": 2_000, "unit": "Millisecond", "tz": "+05:30"}
+            })
+        );
+        let TypedStatistics::Timestamp(stats) = serde_json::from_value(json).unwrap() else {
+            panic!("expected timestamp statistics")
+        };
+        assert_eq!((stats.min, stats.max), (1_000, 2_000));
+        assert_eq!(stats.unit, TimeUnit::Millisecond);
+        assert_eq!(stats.tz.as_deref(), Some("+05:30"));
+
+        let json = serde_json::to_string(&TypedStatistics::Date(DateType { min: 1, max: 3 }));
+        let TypedStatistics::Date(stats) = serde_json::from_str(&json.unwrap()).unwrap() else {
+            panic!("expected date statistics")
+        };
+        assert_eq!((stats.min, stats.max), (1, 3));
+    }
+
+    #[test]
+    fn timestamp_merge_converts_units() {
+        let merged = TypedStatistics::merge_all([
+            timestamp_stats(1_500, 2_000, TimeUnit::Millisecond),
+            timestamp_stats(1, 3, TimeUnit::Second),
+            timestamp_stats(900_000, 1_000_001, TimeUnit::Microsecond),
+        ])
+        .unwrap();
+
+        // the microsecond max is rounded up to a bound in milliseconds
+        assert_eq!(
+            merged.timestamp_bounds(&TimeUnit::Millisecond),
+            Some((900, 3_000))
+        );
+        let (min, max) = merged
+            .min_max_as_scalar(&DataType::Timestamp(TimeUnit::Second, None))
+            .unwrap();
+        assert_eq!(min, ScalarValue::TimestampSecond(Some(0), None));
+        assert_eq!(max, ScalarValue::TimestampSecond(Some(3), None));
+    }
+
+    #[test]
+    fn legacy_int_stats_merge_with_temporal_stats() {
+        let merged = TypedStatistics::merge_all([
+            timestamp_stats(15, 30, TimeUnit::Millisecond),
+            int_stats(),
+        ])
+        .unwrap();
+        let TypedStatistics::Timestamp(stats) = merged else {
+            panic!("expected timestamp statistics")
+        };
+        assert_eq!((stats.min, stats.max), (10, 30));
+
+        let merged = TypedStatistics::Int(Int64Type { min: 5, max: 7 })
+            .update(TypedStatistics::Date(DateType { min: 6, max: 9 }));
+        let TypedStatistics::Date(stats) = merged else {
+            panic!("expected date statistics")
+        };
+        assert_eq!((stats.min, stats.max), (5, 9));
+
+        assert!(TypedStatistics::merge_all([
+            int_stats(),
+            TypedStatistics::Date(DateType { min: 1, max: 2 }),
+            timestamp_stats(1, 2, TimeUnit::Second),
+        ])
+        .is_none());
+    }
+
+    #[test]
+    fn date_stats_as_scalar() {
+        let stats = || TypedStatistics::Date(DateType { min: 1, max: 2 });
+        let (min, max) = stats().min_max_as_scalar(&DataType::Date32).unwrap();
+        assert_eq!(min, ScalarValue::Date32(Some(1)));
+        assert_eq!(max, ScalarValue::Date32(Some(2)));
+
+        let (min, max) = stats().min_max_as_scalar(&DataType::Date64).unwrap();
+        assert_eq!(min, ScalarValue::Date64(Some(86_400_000)));
+        assert_eq!(max, ScalarValue::Date64(Some(172_800_000)));
+
+        // legacy Int statistics of a date column are its days
+        let (min, _) = int_stats().min_max_as_scalar(&DataType::Date32).unwrap();
+        assert_eq!(min, ScalarValue::Date32(Some(10)));
+        assert!(stats().min_max_as_scalar(&DataType::Int64).is_none());
+    }
+
+    #[test]
+    fn dictionary_column_statistics() {
+        let values = DictionaryArray::<Int32Type>::from_iter(["warn", "error", "warn", "info"]);
+        let batch = RecordBatch::try_from_iter([("level", Arc::new(values) as ArrayRef)]).unwrap();
+        let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        let file = SerializedFileReader::new(Bytes::from(writer.into_inner().unwrap())).unwrap();
+        let chunk = file.metadata().row_group(0).column(0);
+
+        let column = Column::from_parquet(
+            "level".to_string(),
+            chunk.statistics(),
+            chunk.uncompressed_size() as u64,
+            chunk.compressed_size() as u64,
+        );
+        let data_type = batch.schema().field(0).data_type().clone();
+        let (min, max) = column.stats.unwrap().min_max_as_scalar(&data_type).unwrap();
+
+        let dictionary = |value: &str| {
+            ScalarValue::Dictionary(
+                Box::new(DataType::Int32),
+                Box::new(ScalarValue::Utf8(Some(value.to_string()))),
+            )
+        };
+        assert_eq!(min, dictionary("error"));
+        assert_eq!(max, dictionary("warn"));
+        assert_eq!(min.data_type(), data_type);
+    }
+
+    #[test]
+    fn binary_update_compares_bytewise() {
+        let this = TypedStatistics::Binary(BinaryType {
+            min: vec![0x10, 0xFF],
+            max: vec![0x80],
+        });
+        let other = TypedStatistics::Binary(BinaryType {
+            min: vec![0x10, 0x00, 0x01],
+            max: vec![0x80, 0x00],
+        });
+
+        let TypedStatistics::Binary(merged) = this.update(other) else {
+            panic!("expected binary statistics")
+        };
+        assert_eq!(merged.min, vec![0x10, 0x00, 0x01]);
+        assert_eq!(merged.max, vec![0x80, 0x00]);
+    }
+
+    #[test]
+    fn binary_stats_are_truncated() {
+        let min = vec![0x01; MAX_BINARY_STATS_LENGTH + 10];
+        let mut max = vec![0x02; MAX_BINARY_STATS_LENGTH + 10];
+        max[MAX_BINARY_STATS_LENGTH - 1] = 0xFF;
+
+        let stats = BinaryType::truncated(&min, &max);
+
+        assert_eq!(stats.min, vec![0x01; MAX_BINARY_STATS_LENGTH]);
+        let mut expected_max = vec![0x02; MAX_BINARY_STATS_LENGTH - 1];
+        *expected_max.last_mut().unwrap() = 0x03;
+        assert_eq!(stats.max, expected_max);
+        assert!(stats.max > max);
+    }
+
+    #[test]
+    fn binary_max_of_all_ones_is_not_truncated() {
+        let max = vec![0xFF; MAX_BINARY_STATS_LENGTH + 1];
+
+        assert_eq!(BinaryType::truncated(&[], &max).max, max);
+    }
+
+    #[test]
+    fn binary_stats_as_scalar() {
+        let (min, max) = TypedStatistics::Binary(BinaryType {
+            min: vec![0x00],
+            max: vec![0xFF],
+        })
+        .min_max_as_scalar(&DataType::Binary)
+        .unwrap();
+
+        assert_eq!(min, ScalarValue::Binary(Some(vec![0x00])));
+        assert_eq!(max, ScalarValue::Binary(Some(vec![0xFF])));
+    }
+
+    #[test]
+    fn min_max_json() {
+        use serde_json::json;
+
+        assert_eq!(int_stats().min_max_json(), (json!(10), json!(20)));
+
+        let binary = TypedStatistics::Binary(BinaryType {
+            min: vec![0x00, 0x1F],
+            max: vec![0xFF],
+        });
+        assert_eq!(binary.min_max_json(), (json!("001f"), json!("ff")));
+
+        let list = TypedStatistics::List(Box::new(TypedStatistics::String(Utf8Type::truncated(
+            "a", "z",
+        ))));
+        assert_eq!(list.min_max_json(), (json!("a"), json!("z")));
+    }
+
+    #[test]
+    fn compression_ratio() {
+        let mut column = Column::from_parquet("a".to_string(), None, 400, 100);
+        assert_eq!(column.compression_ratio(), Some(4.0));
+
+        column.compressed_size = 0;
+        assert_eq!(column.compression_ratio(), None);
+    }
+}
@@ -18,10 +18,19 @@
 
 use std::collections::HashMap;
 
+use arrow_schema::Schema;
 use itertools::Itertools;
-use parquet::{file::reader::FileReader, format::SortingColumn};
+use parquet::{
+    arrow::parquet_to_arrow_schema,
+    file::{
+        metadata::{ParquetColumnIndex, ParquetMetaData, RowGroupMetaData},
+        reader::FileReader,
+        serialized_reader::{ReadOptions, ReadOptionsBuilder, SerializedFileReader},
+    },
+    format::SortingColumn,
+};
 
-use super::column::Column;
+use super::column::{Column, TypedStatistics};
 
 #[derive(
     Debug,
@@ -92,24 +101,49 @@ pub fn create_from_parquet_file(
     object_store_path: String,
     fs_file_path: &std::path::Path,
 ) -> anyhow::Result<File> {
+    let file = std::fs::File::open(fs_file_path)?;
+    let file_size = file.metadata()?.len();
+
+    let file = SerializedFileReader::new_with_options(file, page_index_options())?;
+    Ok(create_from_parquet_metadata(
+        object_store_path,
+        file_size,
+        file.metadata(),
+    ))
+}
+
+/// Options of readers building manifest entries, the column index is read with the
+/// footer so bounds are taken from it when the file has one
+pub fn page_index_options() -> ReadOptions {
+    ReadOptionsBuilder::new().with_page_index().build()
+}
+
+/// Creates a manifest entry from already parsed parquet metadata.
+/// Column bounds come from the column index when it was read, see `page_index_options`,
+/// and from the column chunk statistics otherwise.
+pub fn create_from_parquet_metadata(
+    object_store_path: String,
+    file_size: u64,
+    metadata: &ParquetMetaData,
+) -> File {
     let mut manifest_file = File {
         file_path: object_store_path,
+        file_size,
         ..File::default()
     };
 
-    let file = std::fs::File::open(fs_file_path)?;
-    manifest_file.file_size = file.metadata()?.len();
-
-    let file = parquet::file::serialized_reader::SerializedFileReader::new(file)?;
-    let file_meta = file.metadata().file_metadata();
-    let row_groups = file.metadata().row_groups();
+    let file_meta = metadata.file_metadata();
+    let row_groups = metadata.row_groups();
 
     manifest_file.num_rows = file_meta.num_rows() as u64;
     manifest_file.ingestion_size = row_groups
         .iter()
         .fold(0, |acc, x| acc + x.total_byte_size() as u64);
 
-    let columns = column_statistics(row_groups);
+    // statistics of dates and timestamps are typed by the arrow schema of the file
+    let schema =
+        parquet_to_arrow_schema(file_meta.schema_descr(), file_meta.key_value_metadata()).ok();
+    let columns = column_statistics(row_groups, metadata.column_index(), schema.as_ref());
     manifest_file.columns = columns.into_values().collect();
     let mut sort_orders = sort_order(row_groups);
     if let Some(last_sort_order) = sort_orders.pop() {
@@ -121,12 +155,10 @@ pub fn create_from_parquet_file(
         }
     }
 
-    Ok(manifest_file)
+    manifest_file
 }
 
-fn sort_order(
-    row_groups: &[parquet::file::metadata::RowGroupMetaData],
-) -> Vec<Vec<(String, SortOrder)>> {
+fn sort_order(row_groups: &[RowGroupMetaData]) -> Vec<Vec<(String, SortOrder)>> {
     let mut sort_orders = Vec::new();
     for row_group in row_groups {
         let sort_order = row_group.sorting_columns().unwrap();
@@ -160,30 +192,226 @@ fn sort_order(
 }
 
 fn column_statistics(
-    row_groups: &[parquet::file::metadata::RowGroupMetaData],
+    row_groups: &[RowGroupMetaData],
+    column_index: Option<&ParquetColumnIndex>,
+    schema: Option<&Schema>,
 ) -> HashMap<String, Column> {
     let mut columns: HashMap<String, Column> = HashMap::new();
-    for row_group in row_groups {
-        for col in row_group.columns() {
-            let col_name = col.column_descr().path().string();
-            if let Some(entry) = columns.get_mut(&col_name) {
-                entry.compressed_size += col.compressed_size() as u64;
-                entry.uncompressed_size += col.uncompressed_size() as u64;
-                if let Some(other) = col.statistics().and_then(|stats| stats.try_into().ok()) {
-                    entry.stats = entry.stats.clone().map(|this| this.update(other));
+    for (row_group_idx, row_group) in row_groups.iter().enumerate() {
+        for (col_idx, col) in row_group.columns().iter().enumerate() {
+            let descr = col.column_descr();
+            let index = column_index
+                .and_then(|index| index.get(row_group_idx))
+                .and_then(|index| index.get(col_idx));
+            let mut column = Column::from_parquet(
+                descr.path().string(),
+                col.statistics(),
+                col.uncompressed_size() as u64,
+                col.compressed_size() as u64,
+            )
+            .with_column_index(index);
+            match list_column_name(descr.path().parts()) {
+                // element statistics of a list are tracked against the list column
+                Some(name) if descr.max_rep_level() == 1 => {
+                    column.name = name;
+                    column.stats = column
+                        .stats
+                        .map(|stats| TypedStatistics::List(Box::new(stats)));
                 }
+                // values nested deeper in repeated fields don't map to a column
+                _ if descr.max_rep_level() > 0 => column.stats = None,
+                _ => {}
+            }
+            if let Some(field) = schema.and_then(|schema| schema.field_with_name(&column.name).ok())
+            {
+                column.stats = column.stats.map(|stats| stats.for_type(field.data_type()));
+            }
+            let col_name = column.name.clone();
+            if let Some(entry) = columns.get_mut(&col_name) {
+                entry.merge(&column);
             } else {
-                columns.insert(
-                    col_name.clone(),
-                    Column {
-                        name: col_name,
-                        stats: col.statistics().and_then(|stats| stats.try_into().ok()),
-                        uncompressed_size: col.uncompressed_size() as u64,
-                        compressed_size: col.compressed_size() as u64,
-                    },
-                );
+                columns.insert(col_name, column);
             }
         }
     }
     columns
 }
+
+// leaf of a list column has path `<column>.list.<element>`
+fn list_column_name(parts: &[String]) -> Option<String> {
+    match parts {
+        [column @ .., list, _] if list == "list" && !column.is_empty() => Some(column.join(".")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        builder::ListBuilder, builder::StringBuilder, ArrayRef, Int64Array, RecordBatch,
+        StringArray, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use parquet::{
+        arrow::ArrowWriter,
+        file::{
+            page_index::index::Index,
+            properties::{EnabledStatistics, WriterProperties},
+            reader::FileReader,
+            serialized_reader::SerializedFileReader,
+        },
+    };
+
+    use crate::catalog::column::TypedStatistics;
+
+    use super::{create_from_parquet_metadata, page_index_options};
+
+    #[test]
+    fn bounds_are_read_from_column_index_or_legacy_statistics() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("latency", DataType::Int64, true),
+            Field::new("host", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![Some(40), None, Some(-3), Some(17)])),
+                Arc::new(StringArray::from(vec!["web-2", "db-1", "web-1", "api-9"])),
+            ],
+        )
+        .unwrap();
+
+        let mut bounds = Vec::new();
+        // page statistics write a column index, chunk statistics only the legacy ones
+        for (statistics, has_index) in [
+            (EnabledStatistics::Page, true),
+            (EnabledStatistics::Chunk, false),
+        ] {
+            let props = WriterProperties::builder()
+                .set_statistics_enabled(statistics)
+                .set_data_page_row_count_limit(2)
+                .set_write_batch_size(2)
+                .build();
+            let mut buffer = Vec::new();
+            let mut writer =
+                ArrowWriter::try_new(&mut buffer, schema.clone(), Some(props)).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+
+            let size = buffer.len() as u64;
+            let reader = SerializedFileReader::new_with_options(
+                bytes::Bytes::from(buffer),
+                page_index_options(),
+            )
+            .unwrap();
+            let index_written = reader.metadata().column_index().is_some_and(|index| {
+                index
+                    .iter()
+                    .flatten()
+                    .any(|index| !matches!(index, Index::NONE))
+            });
+            assert_eq!(index_written, has_index);
+
+            let mut file =
+                create_from_parquet_metadata("file.parquet".to_string(), size, reader.metadata());
+            file.columns.sort_by(|a, b| a.name.cmp(&b.name));
+            let stats = file
+                .columns
+                .iter()
+                .map(|column| {
+                    (
+                        column.name.clone(),
+                        column.stats.as_ref().map(|stats| stats.min_max_json()),
+                    )
+                })
+                .collect::<Vec<_>>();
+            bounds.push(stats);
+        }
+
+        assert_eq!(
+            bounds[0],
+            [
+                (
+                    "host".to_string(),
+                    Some((serde_json::json!("api-9"), serde_json::json!("web-2")))
+                ),
+                (
+                    "latency".to_string(),
+                    Some((serde_json::json!(-3), serde_json::json!(40)))
+                ),
+            ]
+        );
+        assert_eq!(bounds[0], bounds[1]);
+    }
+
+    #[test]
+    fn list_column_records_element_stats() {
+        let mut tags = ListBuilder::new(StringBuilder::new());
+        tags.values().append_value("b");
+        tags.values().append_value("c");
+        tags.append(true);
+        tags.values().append_value("a");
+        tags.append(true);
+        tags.append(false);
+        let tags = tags.finish();
+
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "tags",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            true,
+        )]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(tags)]).unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buffer, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let size = buffer.len() as u64;
+        let reader =
+            parquet::file::serialized_reader::SerializedFileReader::new(bytes::Bytes::from(buffer))
+                .unwrap();
+        let file =
+            create_from_parquet_metadata("file.parquet".to_string(), size, reader.metadata());
+
+        assert_eq!(file.columns.len(), 1);
+        let column = &file.columns[0];
+        assert_eq!(column.name, "tags");
+        let Some(TypedStatistics::List(stats)) = &column.stats else {
+            panic!("expected list statistics, found {:?}", column.stats);
+        };
+        let TypedStatistics::String(stats) = stats.as_ref() else {
+            panic!("expected string element statistics, found {stats:?}");
+        };
+        assert_eq!(stats.min, "a");
+        assert_eq!(stats.max, "c");
+    }
+
+    #[test]
+    fn timestamp_column_records_typed_stats() {
+        let timestamps =
+            TimestampMillisecondArray::from(vec![3_000, 1_000, 2_000]).with_timezone("+05:30");
+        let batch = RecordBatch::try_from_iter([("p_timestamp", Arc::new(timestamps) as ArrayRef)])
+            .unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buffer, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let size = buffer.len() as u64;
+        let reader = SerializedFileReader::new(bytes::Bytes::from(buffer)).unwrap();
+        let file =
+            create_from_parquet_metadata("file.parquet".to_string(), size, reader.metadata());
+
+        let Some(TypedStatistics::Timestamp(stats)) = &file.columns[0].stats else {
+            panic!(
+                "expected timestamp statistics, found {:?}",
+                file.columns[0].stats
+            );
+        };
+        assert_eq!((stats.min, stats.max), (1_000, 3_000));
+        assert_eq!(stats.unit, TimeUnit::Millisecond);
+        assert_eq!(stats.tz.as_deref(), Some("+05:30"));
+    }
+}
@@ -0,0 +1,466 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Decides from the column statistics of a manifest file whether a predicate can match
+//! any of its rows. Every case that isn't understood keeps the file, a file is only
+//! skipped when the statistics prove that no row satisfies the predicate.
+
+use std::cmp::Ordering;
+
+use arrow_schema::DataType;
+use datafusion::{
+    common::tree_node::{TreeNode, TreeNodeRecursion},
+    logical_expr::{expr::InList, Between, BinaryExpr, Like, Operator},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::column::{Column, TypedStatistics, Utf8Type};
+
+/// Whether no row of a file with these columns can satisfy `predicate`
+pub fn can_skip_file(predicate: &Expr, columns: &[Column]) -> bool {
+    match predicate {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        }) => can_skip_file(left, columns) || can_skip_file(right, columns),
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        }) => can_skip_file(left, columns) && can_skip_file(right, columns),
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(col), Expr::Literal(value)) => {
+                    compare_skips(find_stats(columns, &col.name), *op, value)
+                }
+                // `lit op col` is `col swapped(op) lit`
+                (Expr::Literal(value), Expr::Column(col)) => op
+                    .swap()
+                    .is_some_and(|op| compare_skips(find_stats(columns, &col.name), op, value)),
+                _ => false,
+            }
+        }
+        Expr::Between(Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        }) => {
+            let (Expr::Column(col), Expr::Literal(low), Expr::Literal(high)) =
+                (expr.as_ref(), low.as_ref(), high.as_ref())
+            else {
+                return false;
+            };
+            let stats = find_stats(columns, &col.name);
+            compare_skips(stats, Operator::GtEq, low) || compare_skips(stats, Operator::LtEq, high)
+        }
+        Expr::InList(InList {
+            expr,
+            list,
+            negated: false,
+        }) => {
+            let Expr::Column(col) = expr.as_ref() else {
+                return false;
+            };
+            // a file holds none of the values only if each of them is outside its bounds
+            let stats = find_stats(columns, &col.name);
+            !list.is_empty()
+                && list.iter().all(|item| match item {
+                    Expr::Literal(value) => compare_skips(stats, Operator::Eq, value),
+                    _ => false,
+                })
+        }
+        Expr::Like(like) => {
+            let Expr::Column(col) = like.expr.as_ref() else {
+                return false;
+            };
+            let (Some(prefix), Some(TypedStatistics::String(stats))) =
+                (like_prefix(like), find_stats(columns, &col.name))
+            else {
+                return false;
+            };
+            !may_contain_prefix(prefix, stats)
+        }
+        // array_has(list, value) holds only if value lies within the element bounds
+        Expr::ScalarFunction(func) if is_array_has(func.name()) => {
+            let (Some(Expr::Column(col)), Some(Expr::Literal(value))) =
+                (func.args.first(), func.args.get(1))
+            else {
+                return false;
+            };
+            let Some(TypedStatistics::List(stats)) = find_stats(columns, &col.name) else {
+                return false;
+            };
+            compare_skips(Some(stats.as_ref()), Operator::Eq, value)
+        }
+        _ => false,
+    }
+}
+
+/// Whether every IN list in `predicate` has at most `max_len` values. Each file is compared
+/// with every value of a list, so filters with longer lists are better left to the query.
+pub fn in_lists_within(predicate: &Expr, max_len: usize) -> bool {
+    let mut within = true;
+    let _ = predicate.apply(&mut |expr| {
+        if matches!(expr, Expr::InList(InList { list, .. }) if list.len() > max_len) {
+            within = false;
+            return Ok(TreeNodeRecursion::Stop);
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    within
+}
+
+fn is_array_has(name: &str) -> bool {
+    matches!(name, "array_has" | "array_contains" | "list_has")
+}
+
+fn find_stats<'a>(columns: &'a [Column], name: &str) -> Option<&'a TypedStatistics> {
+    columns
+        .iter()
+        .find(|col| col.name == name)
+        .and_then(|col| col.stats.as_ref())
+}
+
+/// Whether `col op value` is false for every value between the column bounds
+fn compare_skips(stats: Option<&TypedStatistics>, op: Operator, value: &ScalarValue) -> bool {
+    let Some(stats) = stats else {
+        return false;
+    };
+    let Some((value, datatype)) = normalize(value) else {
+        return false;
+    };
+    let Some((min, max)) = bounds(stats, &datatype) else {
+        return false;
+    };
+
+    // values of mismatched types are incomparable and never skip
+    let cmp_min = value.partial_cmp(&min);
+    let cmp_max = max.as_ref().and_then(|max| value.partial_cmp(max));
+    match op {
+        Operator::Eq | Operator::IsNotDistinctFrom => {
+            cmp_min == Some(Ordering::Less) || cmp_max == Some(Ordering::Greater)
+        }
+        // only a file holding nothing but `value` is skipped
+        Operator::NotEq | Operator::IsDistinctFrom => {
+            cmp_min == Some(Ordering::Equal) && cmp_max == Some(Ordering::Equal)
+        }
+        Operator::Lt => matches!(cmp_min, Some(Ordering::Less | Ordering::Equal)),
+        Operator::LtEq => cmp_min == Some(Ordering::Less),
+        Operator::Gt => matches!(cmp_max, Some(Ordering::Greater | Ordering::Equal)),
+        Operator::GtEq => cmp_max == Some(Ordering::Greater),
+        _ => false,
+    }
+}
+
+/// Literal in the representation of the statistics of its type, with the type to read
+/// the statistics as. Integers and floats are widened as both are stored as 64 bit.
+fn normalize(value: &ScalarValue) -> Option<(ScalarValue, DataType)> {
+    let int =
+        |value: Option<i64>| value.map(|value| (ScalarValue::Int64(Some(value)), DataType::Int64));
+    match value {
+        ScalarValue::Boolean(Some(_)) => Some((value.clone(), DataType::Boolean)),
+        ScalarValue::Int8(value) => int(value.map(i64::from)),
+        ScalarValue::Int16(value) => int(value.map(i64::from)),
+        ScalarValue::Int32(value) => int(value.map(i64::from)),
+        ScalarValue::Int64(value) => int(*value),
+        ScalarValue::UInt8(value) => int(value.map(i64::from)),
+        ScalarValue::UInt16(value) => int(value.map(i64::from)),
+        ScalarValue::UInt32(value) => int(value.map(i64::from)),
+        ScalarValue::UInt64(value) => int(value.and_then(|value| i64::try_from(value).ok())),
+        // NaN isn't recorded in parquet statistics, a file may hold it whatever its bounds
+        ScalarValue::Float32(Some(value)) if !value.is_nan() => {
+            Some((ScalarValue::Float64(Some(*value as f64)), DataType::Float64))
+        }
+        ScalarValue::Float64(Some(value)) if !value.is_nan() => {
+            Some((ScalarValue::Float64(Some(*value)), DataType::Float64))
+        }
+        ScalarValue::Utf8(Some(value)) | ScalarValue::LargeUtf8(Some(value)) => {
+            Some((ScalarValue::Utf8(Some(value.clone())), DataType::Utf8))
+        }
+        ScalarValue::Binary(Some(value)) | ScalarValue::LargeBinary(Some(value)) => {
+            Some((ScalarValue::Binary(Some(value.clone())), DataType::Binary))
+        }
+        // timestamps are stored as integers in the unit of the column
+        ScalarValue::TimestampSecond(Some(_), _)
+        | ScalarValue::TimestampMillisecond(Some(_), _)
+        | ScalarValue::TimestampMicrosecond(Some(_), _)
+        | ScalarValue::TimestampNanosecond(Some(_), _) => Some((value.clone(), value.data_type())),
+        ScalarValue::Dictionary(_, value) => normalize(value),
+        // comparisons with null are never true, but are left for the query to decide
+        _ => None,
+    }
+}
+
+/// Bounds of the statistics read as `datatype`. The max of truncated strings isn't an
+/// upper bound of the column, only their min is returned.
+fn bounds(
+    stats: &TypedStatistics,
+    datatype: &DataType,
+) -> Option<(ScalarValue, Option<ScalarValue>)> {
+    match stats {
+        TypedStatistics::String(stats) if stats.truncated => matches!(datatype, DataType::Utf8)
+            .then(|| (ScalarValue::Utf8(Some(stats.min.clone())), None)),
+        stats => {
+            let (min, max) = stats.clone().min_max_as_scalar(datatype)?;
+            Some((min, Some(max)))
+        }
+    }
+}
+
+/// Literal prefix of a case sensitive `LIKE 'prefix%'` pattern
+fn like_prefix(like: &Like) -> Option<&str> {
+    if like.negated || like.case_insensitive || like.escape_char.is_some() {
+        return None;
+    }
+    let Expr::Literal(ScalarValue::Utf8(Some(pattern))) = like.pattern.as_ref() else {
+        return None;
+    };
+    // a backslash escapes wildcards by default
+    let end = pattern.find(['%', '_', '\\'])?;
+    (end > 0).then(|| &pattern[..end])
+}
+
+/// Whether a file with these bounds may hold a value starting with `prefix`.
+/// Values starting with a prefix sort right after it, before any other greater value.
+fn may_contain_prefix(prefix: &str, stats: &Utf8Type) -> bool {
+    if stats.min.as_str() > prefix && !stats.min.starts_with(prefix) {
+        return false;
+    }
+    // the values of a truncated max may start with the prefix even if the max sorts before it
+    stats.truncated || stats.max.as_str() >= prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::{
+        prelude::{col, lit, Expr},
+        scalar::ScalarValue,
+    };
+    use rstest::rstest;
+
+    use super::{can_skip_file, in_lists_within};
+    use crate::catalog::column::{
+        BinaryType, BoolType, Column, Float64Type, Int64Type, TypedStatistics, Utf8Type,
+    };
+
+    fn columns(stats: Option<TypedStatistics>) -> Vec<Column> {
+        vec![Column {
+            name: "a".to_string(),
+            stats,
+            uncompressed_size: 0,
+            compressed_size: 0,
+            null_count: 0,
+        }]
+    }
+
+    fn int(min: i64, max: i64) -> Vec<Column> {
+        columns(Some(TypedStatistics::Int(Int64Type { min, max })))
+    }
+
+    fn string(min: &str, max: &str, truncated: bool) -> Vec<Column> {
+        columns(Some(TypedStatistics::String(Utf8Type {
+            min: min.to_string(),
+            max: max.to_string(),
+            truncated,
+        })))
+    }
+
+    #[rstest]
+    #[case::eq_below(col("a").eq(lit(5i64)), true)]
+    #[case::eq_min(col("a").eq(lit(10i64)), false)]
+    #[case::eq_inside(col("a").eq(lit(15i64)), false)]
+    #[case::eq_max(col("a").eq(lit(20i64)), false)]
+    #[case::eq_above(col("a").eq(lit(25i64)), true)]
+    #[case::not_eq(col("a").not_eq(lit(15i64)), false)]
+    #[case::lt_min(col("a").lt(lit(10i64)), true)]
+    #[case::lt_above_min(col("a").lt(lit(11i64)), false)]
+    #[case::lt_eq_min(col("a").lt_eq(lit(10i64)), false)]
+    #[case::lt_eq_below(col("a").lt_eq(lit(9i64)), true)]
+    #[case::gt_max(col("a").gt(lit(20i64)), true)]
+    #[case::gt_below_max(col("a").gt(lit(19i64)), false)]
+    #[case::gt_eq_max(col("a").gt_eq(lit(20i64)), false)]
+    #[case::gt_eq_above(col("a").gt_eq(lit(21i64)), true)]
+    #[case::swapped(lit(5i64).gt(col("a")), true)]
+    #[case::swapped_inside(lit(15i64).gt(col("a")), false)]
+    #[case::narrow_int(col("a").eq(lit(25i32)), true)]
+    #[case::unsigned(col("a").eq(lit(25u64)), true)]
+    #[case::unsigned_overflow(col("a").lt(lit(u64::MAX)), false)]
+    #[case::between_outside(col("a").between(lit(21i64), lit(30i64)), true)]
+    #[case::between_overlapping(col("a").between(lit(15i64), lit(30i64)), false)]
+    #[case::not_between(col("a").not_between(lit(21i64), lit(30i64)), false)]
+    #[case::in_list_outside(col("a").in_list(vec![lit(1i64), lit(30i64)], false), true)]
+    #[case::in_list_one_inside(col("a").in_list(vec![lit(1i64), lit(15i64)], false), false)]
+    #[case::not_in_list(col("a").in_list(vec![lit(1i64), lit(30i64)], true), false)]
+    #[case::and(col("a").gt(lit(0i64)).and(col("a").gt(lit(20i64))), true)]
+    #[case::or(col("a").lt(lit(10i64)).or(col("a").gt(lit(20i64))), true)]
+    #[case::or_one_matching(col("a").lt(lit(10i64)).or(col("a").gt(lit(15i64))), false)]
+    #[case::other_column(col("b").eq(lit(5i64)), false)]
+    #[case::float_literal(col("a").eq(lit(5.0f64)), false)]
+    #[case::not(Expr::Not(Box::new(col("a").eq(lit(15i64)))), false)]
+    fn int_stats(#[case] predicate: Expr, #[case] skipped: bool) {
+        assert_eq!(can_skip_file(&predicate, &int(10, 20)), skipped);
+    }
+
+    #[test]
+    fn single_value_file_is_skipped_by_not_eq() {
+        assert!(can_skip_file(&col("a").not_eq(lit(7i64)), &int(7, 7)));
+        assert!(!can_skip_file(&col("a").not_eq(lit(8i64)), &int(7, 7)));
+    }
+
+    #[test]
+    fn bool_stats() {
+        let all_true = columns(Some(TypedStatistics::Bool(BoolType {
+            min: true,
+            max: true,
+        })));
+        assert!(can_skip_file(&col("a").eq(lit(false)), &all_true));
+        assert!(!can_skip_file(&col("a").eq(lit(true)), &all_true));
+    }
+
+    #[test]
+    fn float_stats() {
+        let floats = columns(Some(TypedStatistics::Float(Float64Type {
+            min: 1.5,
+            max: 2.5,
+        })));
+        assert!(can_skip_file(&col("a").gt(lit(2.5f64)), &floats));
+        assert!(can_skip_file(&col("a").lt(lit(1.0f32)), &floats));
+        assert!(!can_skip_file(&col("a").eq(lit(2.0f64)), &floats));
+        assert!(!can_skip_file(&col("a").eq(lit(f64::NAN)), &floats));
+        assert!(!can_skip_file(&col("a").gt(lit(f32::NAN)), &floats));
+    }
+
+    #[test]
+    fn string_stats() {
+        let strings = string("b", "d", false);
+        assert!(can_skip_file(&col("a").eq(lit("a")), &strings));
+        assert!(can_skip_file(&col("a").gt(lit("d")), &strings));
+        assert!(!can_skip_file(&col("a").eq(lit("c")), &strings));
+        assert!(!can_skip_file(
+            &col("a").eq(lit(ScalarValue::LargeUtf8(Some("c".to_string())))),
+            &strings
+        ));
+        assert!(can_skip_file(&col("a").like(lit("e%")), &strings));
+        assert!(!can_skip_file(&col("a").like(lit("c%")), &strings));
+    }
+
+    #[test]
+    fn string_in_list() {
+        let strings = string("db", "web", false);
+        let services =
+            |values: &[&str]| col("a").in_list(values.iter().map(|v| lit(*v)).collect(), false);
+
+        assert!(can_skip_file(&services(&["api", "zookeeper"]), &strings));
+        assert!(!can_skip_file(&services(&["api", "gateway"]), &strings));
+        assert!(!can_skip_file(&services(&["api", "db"]), &strings));
+        assert!(!can_skip_file(&services(&["web"]), &strings));
+        assert!(!can_skip_file(
+            &col("a").in_list(vec![lit("api"), lit("zookeeper")], true),
+            &strings
+        ));
+        assert!(!can_skip_file(&col("a").in_list(vec![], false), &strings));
+
+        // a truncated max only bounds the values below
+        let truncated = string("server-a", "server-", true);
+        assert!(can_skip_file(&services(&["api", "db"]), &truncated));
+        assert!(!can_skip_file(&services(&["api", "zookeeper"]), &truncated));
+    }
+
+    #[test]
+    fn long_in_lists_are_detected() {
+        let list = col("a").in_list((0..4i64).map(lit).collect(), false);
+        assert!(in_lists_within(&list, 4));
+        assert!(!in_lists_within(&list, 3));
+        assert!(!in_lists_within(&col("b").eq(lit(1i64)).or(list), 3));
+        assert!(in_lists_within(&col("b").eq(lit(1i64)), 0));
+    }
+
+    #[test]
+    fn truncated_string_stats_only_bound_below() {
+        // the real max "server-b-0001" was truncated to "server-"
+        let strings = string("server-a", "server-", true);
+        assert!(can_skip_file(&col("a").eq(lit("api")), &strings));
+        assert!(can_skip_file(&col("a").lt(lit("api")), &strings));
+        assert!(!can_skip_file(&col("a").eq(lit("server-b-0001")), &strings));
+        assert!(!can_skip_file(&col("a").gt(lit("server-b")), &strings));
+        assert!(!can_skip_file(&col("a").gt(lit("zzz")), &strings));
+    }
+
+    #[test]
+    fn binary_stats() {
+        let binary = columns(Some(TypedStatistics::Binary(BinaryType {
+            min: vec![0x10],
+            max: vec![0x20],
+        })));
+        let bytes = |value: &[u8]| lit(ScalarValue::Binary(Some(value.to_vec())));
+        assert!(can_skip_file(&col("a").eq(bytes(&[0x30])), &binary));
+        assert!(!can_skip_file(&col("a").eq(bytes(&[0x15])), &binary));
+        assert!(!can_skip_file(&col("a").eq(lit("\u{30}")), &binary));
+    }
+
+    #[test]
+    fn timestamp_stats() {
+        let millis = int(1_000, 2_000);
+        let ts = |value: i64| lit(ScalarValue::TimestampMillisecond(Some(value), None));
+        assert!(can_skip_file(&col("a").gt(ts(2_000)), &millis));
+        assert!(can_skip_file(&col("a").lt(ts(1_000)), &millis));
+        assert!(!can_skip_file(&col("a").gt_eq(ts(2_000)), &millis));
+        assert!(!can_skip_file(
+            &col("a").between(ts(500), ts(1_500)),
+            &millis
+        ));
+
+        let tz = Some("UTC".into());
+        let with_tz = lit(ScalarValue::TimestampMillisecond(Some(2_500), tz));
+        assert!(can_skip_file(&col("a").gt_eq(with_tz), &millis));
+    }
+
+    #[test]
+    fn list_stats_prune_array_has() {
+        let tags = columns(Some(TypedStatistics::List(Box::new(
+            TypedStatistics::String(Utf8Type {
+                min: "b".to_string(),
+                max: "d".to_string(),
+                truncated: false,
+            }),
+        ))));
+        let array_has = datafusion::functions_array::expr_fn::array_has;
+        assert!(can_skip_file(&array_has(col("a"), lit("a")), &tags));
+        assert!(!can_skip_file(&array_has(col("a"), lit("c")), &tags));
+        assert!(!can_skip_file(&col("a").eq(lit("a")), &tags));
+    }
+
+    #[rstest]
+    #[case::null_literal(col("a").eq(lit(ScalarValue::Int64(None))))]
+    #[case::null_in_list(col("a").in_list(vec![lit(ScalarValue::Int64(None))], false))]
+    #[case::is_null(col("a").is_null())]
+    #[case::is_not_null(col("a").is_not_null())]
+    #[case::null_typed(col("a").eq(lit(ScalarValue::Null)))]
+    fn nulls_never_skip(#[case] predicate: Expr) {
+        assert!(!can_skip_file(&predicate, &int(10, 20)));
+    }
+
+    #[test]
+    fn missing_stats_never_skip() {
+        assert!(!can_skip_file(&col("a").eq(lit(5i64)), &columns(None)));
+        assert!(!can_skip_file(&col("a").eq(lit(5i64)), &[]));
+    }
+}
@@ -20,7 +20,10 @@ use std::ops::Bound;
 
 use chrono::{DateTime, Utc};
 
-use crate::query::PartialTimeFilter;
+use crate::{
+    query::{partition_format::PartitionFormat, PartialTimeFilter},
+    storage::tiering::Tier,
+};
 
 pub const CURRENT_SNAPSHOT_VERSION: &str = "v2";
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -79,4 +82,10 @@ pub struct ManifestItem {
     pub events_ingested: u64,
     pub ingestion_size: u64,
     pub storage_size: u64,
+    /// Storage the manifest and files of the partition are in
+    #[serde(default, skip_serializing_if = "Tier::is_hot")]
+    pub tier: Tier,
+    /// Format of the data files of the partition
+    #[serde(default, skip_serializing_if = "PartitionFormat::is_parquet")]
+    pub format: PartitionFormat,
 }
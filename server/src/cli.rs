@@ -17,13 +17,16 @@
  */
 
 use clap::{value_parser, Arg, ArgGroup, Command, FromArgMatches};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use url::Url;
 
 use crate::{
     oidc::{self, OpenidConfig},
-    option::{validation, Compression, Mode},
+    option::{validation, BloomFilterColumn, Compression, FutureEventPolicy, Mode},
+    storage::{key_naming::KeyNaming, overwrite::OverwritePolicy, trash::DeleteMode},
 };
 
 #[derive(Debug, Default)]
@@ -81,9 +84,21 @@ pub struct Cli {
     /// Query memory limit in bytes
     pub query_memory_pool_size: Option<usize>,
 
+    /// Directory used by queries to spill to disk when the memory limit is reached
+    pub query_spill_path: Option<PathBuf>,
+
+    /// Csv file of ip ranges and their country, looked up by `ip_to_country` in queries
+    pub geoip_ranges_path: Option<PathBuf>,
+
     /// Parquet compression algorithm
     pub parquet_compression: Compression,
 
+    /// Columns written with parquet dictionary encoding, all columns are when empty
+    pub parquet_dictionary_columns: Vec<String>,
+
+    /// Columns written with a parquet bloom filter
+    pub parquet_bloom_filter_columns: Vec<BloomFilterColumn>,
+
     /// Mode of operation
     pub mode: Mode,
 
@@ -93,11 +108,121 @@ pub struct Cli {
     /// port use by airplane(flight query service)
     pub flight_port: u16,
 
+    /// Port of the PostgreSQL wire protocol server, not started when unset
+    pub postgres_port: Option<u16>,
+
+    /// Time range before now read by queries over the PostgreSQL wire protocol
+    pub postgres_query_range: String,
+
     /// to query cached data
     pub query_cache_path: Option<PathBuf>,
 
     /// Size for local cache
     pub query_cache_size: u64,
+
+    /// Minimum free space in bytes on the staging disk, below which ingestion is rejected
+    pub min_free_disk: Option<u64>,
+
+    /// Default most records per second ingested into a stream
+    pub ingest_records_per_sec: Option<u64>,
+
+    /// Default most bytes per second ingested into a stream
+    pub ingest_bytes_per_sec: Option<u64>,
+
+    /// Number of concurrent object store list calls made while planning a query
+    pub query_listing_concurrency: usize,
+
+    /// Name of the marker object written to a partition after each flush
+    pub flush_marker: Option<String>,
+
+    /// Deadline for query execution
+    pub query_timeout: Option<Duration>,
+
+    /// Longest time range in days a query may span, admins may query any range
+    pub max_query_range_days: Option<u64>,
+
+    /// Largest IN list of a filter checked against the statistics of each file
+    pub query_in_list_pruning_limit: usize,
+
+    /// Evaluate query filters while decoding parquet pages (late materialization)
+    pub query_pushdown_filters: bool,
+
+    /// Use the parquet page index to skip pages that do not match query filters
+    pub query_page_index: bool,
+
+    /// Expose the date and hour of Hive style object keys as `date` and `hour` columns
+    pub query_partition_columns: bool,
+
+    /// Move parquet files that fail to decode during a query out of the stream
+    pub quarantine_corrupt_files: bool,
+
+    /// Add parquet files written to stream prefixes by other pipelines to the catalog
+    pub external_files: bool,
+
+    /// Interval in seconds at which streams are listed for external files
+    pub external_files_interval: u64,
+
+    /// Largest number of external files added per listing round
+    pub external_files_max: usize,
+
+    /// Interval in seconds at which staged arrow files are converted to parquet and uploaded,
+    /// whole minutes so that only files of minutes that have ended are flushed
+    pub staging_flush_interval: u64,
+
+    /// Staged bytes across all streams above which data is flushed before the interval elapses,
+    /// the files still being written to included.
+    /// Parquet files larger than the multipart threshold (100 MiB) are uploaded in parts,
+    /// keeping this lower results in single request uploads.
+    pub staging_max_size: Option<u64>,
+
+    /// Seconds manifest updates of a stream are collected before they are written at once,
+    /// zero writes them with every uploaded file
+    pub manifest_write_interval: u64,
+
+    /// Layout of object keys for uploaded data files
+    pub key_naming: KeyNaming,
+
+    /// Handling of data files uploaded to a key which already holds an object
+    pub object_overwrite_policy: OverwritePolicy,
+
+    /// Largest object in bytes read into memory by a single get
+    pub max_object_read_size: Option<usize>,
+
+    /// Number of staged parquet files waiting for upload at which ingestion is rejected
+    pub max_pending_upload_files: Option<u64>,
+
+    /// Bytes of staged parquet files waiting for upload at which ingestion is rejected
+    pub max_pending_upload_size: Option<u64>,
+
+    /// Streams stored in a location other than the default one, stream name -> bucket or directory
+    pub stream_storage: HashMap<String, String>,
+
+    /// Bucket or directory holding metadata and manifests apart from parquet files
+    pub metadata_storage: Option<String>,
+
+    /// Run the storage self-diagnostic and exit instead of starting the server
+    pub check_storage: bool,
+
+    /// Time after now up to which event timestamps of time partitioned streams are accepted
+    pub max_clock_skew: Option<Duration>,
+
+    /// Handling of events dated further in the future than the allowed clock skew
+    pub future_event_policy: FutureEventPolicy,
+
+    /// Handling of objects deleted with a stream or by retention
+    pub delete_mode: DeleteMode,
+
+    /// Days objects are kept in the trash in soft delete mode
+    pub trash_retention_days: u32,
+
+    /// Bucket or directory partitions are moved to once they are older than the hot tier days
+    pub cold_storage: Option<String>,
+
+    /// Days of the newest partitions kept in the default storage when a cold storage is set
+    pub hot_tier_days: u32,
+
+    /// Time allowed on shutdown to flush staged data and finish uploads
+    pub shutdown_timeout: Duration,
 }
 
 impl Cli {
@@ -123,13 +248,53 @@ impl Cli {
     pub const LIVETAIL_CAPACITY: &'static str = "livetail-capacity";
     // todo : what should this flag be
     pub const QUERY_MEM_POOL_SIZE: &'static str = "query-mempool-size";
+    pub const QUERY_MEM_POOL_SIZE_MB: &'static str = "query-mempool-size-mb";
+    pub const QUERY_SPILL_PATH: &'static str = "query-spill-path";
+    pub const GEOIP_RANGES_PATH: &'static str = "geoip-ranges-file";
     pub const ROW_GROUP_SIZE: &'static str = "row-group-size";
     pub const PARQUET_COMPRESSION_ALGO: &'static str = "compression-algo";
+    pub const PARQUET_DICTIONARY_COLUMNS: &'static str = "parquet-dictionary-columns";
+    pub const PARQUET_BLOOM_FILTER_COLUMNS: &'static str = "parquet-bloom-filter-columns";
     pub const MODE: &'static str = "mode";
     pub const INGESTOR_ENDPOINT: &'static str = "ingestor-endpoint";
     pub const DEFAULT_USERNAME: &'static str = "admin";
     pub const DEFAULT_PASSWORD: &'static str = "admin";
     pub const FLIGHT_PORT: &'static str = "flight-port";
+    pub const POSTGRES_PORT: &'static str = "postgres-port";
+    pub const POSTGRES_QUERY_RANGE: &'static str = "postgres-query-range";
+    pub const MIN_FREE_DISK: &'static str = "min-free-disk";
+    pub const INGEST_RECORDS_PER_SEC: &'static str = "ingest-records-per-sec";
+    pub const INGEST_BYTES_PER_SEC: &'static str = "ingest-bytes-per-sec";
+    pub const QUERY_LISTING_CONCURRENCY: &'static str = "query-listing-concurrency";
+    pub const FLUSH_MARKER: &'static str = "flush-marker";
+    pub const QUERY_TIMEOUT: &'static str = "query-timeout";
+    pub const MAX_QUERY_RANGE_DAYS: &'static str = "max-query-range-days";
+    pub const QUERY_IN_LIST_PRUNING_LIMIT: &'static str = "query-in-list-pruning-limit";
+    pub const QUERY_PUSHDOWN_FILTERS: &'static str = "query-pushdown-filters";
+    pub const QUERY_PAGE_INDEX: &'static str = "query-page-index";
+    pub const QUERY_PARTITION_COLUMNS: &'static str = "query-partition-columns";
+    pub const QUARANTINE_CORRUPT_FILES: &'static str = "quarantine-corrupt-files";
+    pub const EXTERNAL_FILES: &'static str = "external-files";
+    pub const EXTERNAL_FILES_INTERVAL: &'static str = "external-files-interval";
+    pub const EXTERNAL_FILES_MAX: &'static str = "external-files-max";
+    pub const STAGING_FLUSH_INTERVAL: &'static str = "staging-flush-interval";
+    pub const STAGING_MAX_SIZE: &'static str = "staging-max-size";
+    pub const MANIFEST_WRITE_INTERVAL: &'static str = "manifest-write-interval";
+    pub const KEY_NAMING: &'static str = "key-naming";
+    pub const OBJECT_OVERWRITE_POLICY: &'static str = "object-overwrite-policy";
+    pub const MAX_OBJECT_READ_SIZE: &'static str = "max-object-read-size";
+    pub const MAX_PENDING_UPLOAD_FILES: &'static str = "max-pending-upload-files";
+    pub const MAX_PENDING_UPLOAD_SIZE: &'static str = "max-pending-upload-size";
+    pub const STREAM_STORAGE: &'static str = "stream-storage";
+    pub const METADATA_STORAGE: &'static str = "metadata-storage";
+    pub const CHECK_STORAGE: &'static str = "check-storage";
+    pub const MAX_CLOCK_SKEW: &'static str = "max-clock-skew";
+    pub const FUTURE_EVENT_POLICY: &'static str = "future-event-policy";
+    pub const DELETE_MODE: &'static str = "delete-mode";
+    pub const TRASH_RETENTION_DAYS: &'static str = "trash-retention-days";
+    pub const COLD_STORAGE: &'static str = "cold-storage";
+    pub const HOT_TIER_DAYS: &'static str = "hot-tier-days";
+    pub const SHUTDOWN_TIMEOUT: &'static str = "shutdown-timeout";
 
     pub fn local_stream_data_path(&self, stream_name: &str) -> PathBuf {
         self.local_staging_path.join(stream_name)
@@ -316,6 +481,25 @@ impl Cli {
                     .value_parser(value_parser!(u16))
                     .help("Port for Arrow Flight Querying Engine"),
             )
+            .arg(
+                Arg::new(Self::POSTGRES_PORT)
+                    .long(Self::POSTGRES_PORT)
+                    .env("P_POSTGRES_PORT")
+                    .value_name("PORT")
+                    .required(false)
+                    .value_parser(value_parser!(u16))
+                    .help("Port for read only SQL queries over the PostgreSQL wire protocol, disabled by default. Requires TLS unless the server listens on a loopback address"),
+            )
+            .arg(
+                Arg::new(Self::POSTGRES_QUERY_RANGE)
+                    .long(Self::POSTGRES_QUERY_RANGE)
+                    .env("P_POSTGRES_QUERY_RANGE")
+                    .value_name("DURATION")
+                    .default_value("1d")
+                    .required(false)
+                    .value_parser(validation::human_time_duration)
+                    .help("Time range before now read by queries over the PostgreSQL wire protocol, like 1h or 7d"),
+            )
             .arg(
                 Arg::new(Self::LIVETAIL_CAPACITY)
                     .long(Self::LIVETAIL_CAPACITY)
@@ -335,6 +519,313 @@ impl Cli {
                     .value_parser(value_parser!(u8))
                     .help("Set a fixed memory limit for query"),
             )
+            .arg(
+                Arg::new(Self::QUERY_MEM_POOL_SIZE_MB)
+                    .long(Self::QUERY_MEM_POOL_SIZE_MB)
+                    .env("P_QUERY_MEMORY_MB")
+                    .value_name("MiB")
+                    .required(false)
+                    .conflicts_with(Self::QUERY_MEM_POOL_SIZE)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Set a fixed memory limit for query in MiB"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_SPILL_PATH)
+                    .long(Self::QUERY_SPILL_PATH)
+                    .env("P_QUERY_SPILL_DIR")
+                    .value_name("DIR")
+                    .required(false)
+                    .value_parser(validation::canonicalize_path)
+                    .help("Directory for queries to spill to when the memory limit is reached, defaults to OS temp directory"),
+            )
+            .arg(
+                Arg::new(Self::GEOIP_RANGES_PATH)
+                    .long(Self::GEOIP_RANGES_PATH)
+                    .env("P_GEOIP_RANGES_FILE")
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(validation::file_path)
+                    .help("Csv file of start ip, end ip and country lines used by the ip_to_country query function"),
+            )
+            .arg(
+                Arg::new(Self::STAGING_FLUSH_INTERVAL)
+                    .long(Self::STAGING_FLUSH_INTERVAL)
+                    .env("P_STAGING_FLUSH_INTERVAL")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .default_value("60")
+                    .value_parser(validation::staging_flush_interval)
+                    .help("Interval in seconds at which staged data is converted to parquet and uploaded, a multiple of 60"),
+            )
+            .arg(
+                Arg::new(Self::MANIFEST_WRITE_INTERVAL)
+                    .long(Self::MANIFEST_WRITE_INTERVAL)
+                    .env("P_MANIFEST_WRITE_INTERVAL")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .default_value("0")
+                    .value_parser(value_parser!(u64))
+                    .help("Interval in seconds over which manifest updates of a stream are coalesced into one write, 0 writes them with every uploaded file. Ignored on ingestors"),
+            )
+            .arg(
+                Arg::new(Self::STAGING_MAX_SIZE)
+                    .long(Self::STAGING_MAX_SIZE)
+                    .env("P_STAGING_MAX_SIZE_MB")
+                    .value_name("MiB")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Flush and upload staged data once it grows beyond this size, without waiting for the flush interval"),
+            )
+            .arg(
+                Arg::new(Self::KEY_NAMING)
+                    .long(Self::KEY_NAMING)
+                    .env("P_KEY_NAMING")
+                    .value_name("STRING")
+                    .required(false)
+                    .default_value("hive")
+                    .value_parser([
+                        "hive",
+                        "flat"])
+                    .help("Layout of object keys for data files, changing it for existing streams hides previously uploaded data"),
+            )
+            .arg(
+                Arg::new(Self::OBJECT_OVERWRITE_POLICY)
+                    .long(Self::OBJECT_OVERWRITE_POLICY)
+                    .env("P_OBJECT_OVERWRITE_POLICY")
+                    .value_name("STRING")
+                    .required(false)
+                    .default_value("overwrite")
+                    .value_parser([
+                        "overwrite",
+                        "skip-if-exists",
+                        "error-if-exists",
+                        "rename-if-exists"])
+                    .help("Upload of a data file to a key which already holds an object overwrites it, keeps the existing object, fails or uploads the file under a new name"),
+            )
+            .arg(
+                Arg::new(Self::MAX_OBJECT_READ_SIZE)
+                    .long(Self::MAX_OBJECT_READ_SIZE)
+                    .env("P_MAX_OBJECT_READ_SIZE_MB")
+                    .value_name("MiB")
+                    .required(false)
+                    .value_parser(value_parser!(u64))
+                    .help("Fail reads of single objects larger than this limit instead of buffering them"),
+            )
+            .arg(
+                Arg::new(Self::MAX_PENDING_UPLOAD_FILES)
+                    .long(Self::MAX_PENDING_UPLOAD_FILES)
+                    .env("P_MAX_PENDING_UPLOAD_FILES")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Reject ingestion while this many staged files are waiting to be uploaded"),
+            )
+            .arg(
+                Arg::new(Self::MAX_PENDING_UPLOAD_SIZE)
+                    .long(Self::MAX_PENDING_UPLOAD_SIZE)
+                    .env("P_MAX_PENDING_UPLOAD_SIZE_MB")
+                    .value_name("MiB")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Reject ingestion while staged files of this size are waiting to be uploaded"),
+            )
+            .arg(
+                Arg::new(Self::STREAM_STORAGE)
+                    .long(Self::STREAM_STORAGE)
+                    .env("P_STREAM_STORAGE")
+                    .value_name("STREAM=LOCATION,...")
+                    .required(false)
+                    .value_delimiter(',')
+                    .value_parser(validation::stream_location)
+                    .help("Store a stream in another bucket, or directory for local-store, than the default one"),
+            )
+            .arg(
+                Arg::new(Self::METADATA_STORAGE)
+                    .long(Self::METADATA_STORAGE)
+                    .env("P_METADATA_STORAGE")
+                    .value_name("LOCATION")
+                    .required(false)
+                    .help("Store metadata and manifests in another bucket, or directory for local-store, than parquet files"),
+            )
+            .arg(
+                Arg::new(Self::MAX_CLOCK_SKEW)
+                    .long(Self::MAX_CLOCK_SKEW)
+                    .env("P_MAX_CLOCK_SKEW")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .value_parser(value_parser!(u64))
+                    .help("Events of time partitioned streams dated more than this after now are handled by the future event policy"),
+            )
+            .arg(
+                Arg::new(Self::FUTURE_EVENT_POLICY)
+                    .long(Self::FUTURE_EVENT_POLICY)
+                    .env("P_FUTURE_EVENT_POLICY")
+                    .value_name("STRING")
+                    .required(false)
+                    .default_value("reject")
+                    .value_parser([
+                        "reject",
+                        "clamp"])
+                    .help("Reject requests with events dated beyond the max clock skew, or set their timestamp to now"),
+            )
+            .arg(
+                Arg::new(Self::DELETE_MODE)
+                    .long(Self::DELETE_MODE)
+                    .env("P_DELETE_MODE")
+                    .value_name("STRING")
+                    .required(false)
+                    .default_value("hard")
+                    .value_parser([
+                        "hard",
+                        "soft"])
+                    .help("Remove deleted streams and partitions right away, or move them to the trash until the trash retention has passed"),
+            )
+            .arg(
+                Arg::new(Self::TRASH_RETENTION_DAYS)
+                    .long(Self::TRASH_RETENTION_DAYS)
+                    .env("P_TRASH_RETENTION_DAYS")
+                    .value_name("DAYS")
+                    .required(false)
+                    .default_value("7")
+                    .value_parser(value_parser!(u32))
+                    .help("Days deleted objects are kept in the trash in soft delete mode"),
+            )
+            .arg(
+                Arg::new(Self::COLD_STORAGE)
+                    .long(Self::COLD_STORAGE)
+                    .env("P_COLD_STORAGE")
+                    .value_name("LOCATION")
+                    .required(false)
+                    .help("Bucket, or directory for local-store, partitions older than the hot tier days are moved to"),
+            )
+            .arg(
+                Arg::new(Self::HOT_TIER_DAYS)
+                    .long(Self::HOT_TIER_DAYS)
+                    .env("P_HOT_TIER_DAYS")
+                    .value_name("DAYS")
+                    .required(false)
+                    .default_value("7")
+                    .value_parser(value_parser!(u32))
+                    .help("Days of the newest partitions kept in the default storage when a cold storage is set"),
+            )
+            .arg(
+                Arg::new(Self::SHUTDOWN_TIMEOUT)
+                    .long(Self::SHUTDOWN_TIMEOUT)
+                    .env("P_SHUTDOWN_TIMEOUT")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .default_value("60")
+                    .value_parser(value_parser!(u64))
+                    .help("Seconds allowed on shutdown to flush staged data and finish uploads before pending uploads are aborted"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_PUSHDOWN_FILTERS)
+                    .long(Self::QUERY_PUSHDOWN_FILTERS)
+                    .env("P_QUERY_PUSHDOWN_FILTERS")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("false")
+                    .value_parser(value_parser!(bool))
+                    .help("Apply query filters while decoding parquet files"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_PAGE_INDEX)
+                    .long(Self::QUERY_PAGE_INDEX)
+                    .env("P_QUERY_PAGE_INDEX")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("true")
+                    .value_parser(value_parser!(bool))
+                    .help("Use parquet page index to skip pages not matching query filters"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_PARTITION_COLUMNS)
+                    .long(Self::QUERY_PARTITION_COLUMNS)
+                    .env("P_QUERY_PARTITION_COLUMNS")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("false")
+                    .value_parser(value_parser!(bool))
+                    .help("Expose date and hour partitions of object keys as queryable columns"),
+            )
+            .arg(
+                Arg::new(Self::QUARANTINE_CORRUPT_FILES)
+                    .long(Self::QUARANTINE_CORRUPT_FILES)
+                    .env("P_QUARANTINE_CORRUPT_FILES")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("false")
+                    .value_parser(value_parser!(bool))
+                    .help("Move parquet files that fail to decode to the quarantine prefix so queries skip them"),
+            )
+            .arg(
+                Arg::new(Self::EXTERNAL_FILES)
+                    .long(Self::EXTERNAL_FILES)
+                    .env("P_EXTERNAL_FILES")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("false")
+                    .value_parser(value_parser!(bool))
+                    .help("Add parquet files written to stream prefixes by other pipelines to the catalog, from the SQS queue of the bucket if configured or by listing"),
+            )
+            .arg(
+                Arg::new(Self::EXTERNAL_FILES_INTERVAL)
+                    .long(Self::EXTERNAL_FILES_INTERVAL)
+                    .env("P_EXTERNAL_FILES_INTERVAL")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .default_value("300")
+                    .value_parser(value_parser!(u64).range(10..))
+                    .help("Interval in seconds at which streams are listed for external files"),
+            )
+            .arg(
+                Arg::new(Self::EXTERNAL_FILES_MAX)
+                    .long(Self::EXTERNAL_FILES_MAX)
+                    .env("P_EXTERNAL_FILES_MAX")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .default_value("1000")
+                    .value_parser(value_parser!(usize).range(1..))
+                    .help("Largest number of external files added per listing round"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_TIMEOUT)
+                    .long(Self::QUERY_TIMEOUT)
+                    .env("P_QUERY_TIMEOUT")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .value_parser(value_parser!(u64))
+                    .help("Cancel queries running longer than this, can be overridden per query with the x-p-query-timeout header"),
+            )
+            .arg(
+                Arg::new(Self::MAX_QUERY_RANGE_DAYS)
+                    .long(Self::MAX_QUERY_RANGE_DAYS)
+                    .env("P_MAX_QUERY_RANGE_DAYS")
+                    .value_name("DAYS")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Reject queries spanning more days than this unless run by an admin"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_IN_LIST_PRUNING_LIMIT)
+                    .long(Self::QUERY_IN_LIST_PRUNING_LIMIT)
+                    .env("P_QUERY_IN_LIST_PRUNING_LIMIT")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .default_value("1024")
+                    .value_parser(value_parser!(usize))
+                    .help("Largest IN list of a filter used to skip files by their column statistics, 0 disables IN list pruning"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_LISTING_CONCURRENCY)
+                    .long(Self::QUERY_LISTING_CONCURRENCY)
+                    .env("P_QUERY_LISTING_CONCURRENCY")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .default_value("32")
+                    .value_parser(value_parser!(usize))
+                    .help("Number of concurrent object store list calls made while planning a query"),
+            )
             .arg(
                 Arg::new(Self::ROW_GROUP_SIZE)
                     .long(Self::ROW_GROUP_SIZE)
@@ -344,6 +835,42 @@ impl Cli {
                     .default_value("16384")
                     .value_parser(value_parser!(usize))
                     .help("Number of rows in a row group"),
+            )
+            .arg(
+                Arg::new(Self::FLUSH_MARKER)
+                    .long(Self::FLUSH_MARKER)
+                    .env("P_FLUSH_MARKER_NAME")
+                    .value_name("STRING")
+                    .required(false)
+                    .value_parser(validation::flush_marker)
+                    .help("Write a marker object with this name (e.g. _SUCCESS) under each partition after its files are uploaded"),
+            )
+            .arg(
+                Arg::new(Self::MIN_FREE_DISK)
+                    .long(Self::MIN_FREE_DISK)
+                    .env("P_MIN_FREE_DISK_MB")
+                    .value_name("MiB")
+                    .required(false)
+                    .value_parser(value_parser!(u64))
+                    .help("Reject ingestion when free space on the staging disk drops below this limit"),
+            )
+            .arg(
+                Arg::new(Self::INGEST_RECORDS_PER_SEC)
+                    .long(Self::INGEST_RECORDS_PER_SEC)
+                    .env("P_INGEST_RECORDS_PER_SEC")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Most records per second ingested into a stream without a rate limit of its own"),
+            )
+            .arg(
+                Arg::new(Self::INGEST_BYTES_PER_SEC)
+                    .long(Self::INGEST_BYTES_PER_SEC)
+                    .env("P_INGEST_BYTES_PER_SEC")
+                    .value_name("BYTES")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Most bytes per second ingested into a stream without a rate limit of its own"),
             ).arg(
                 Arg::new(Self::MODE)
                     .long(Self::MODE)
@@ -381,11 +908,33 @@ impl Cli {
                         "lz4",
                         "zstd"])
                     .help("Parquet compression algorithm"),
+            )
+            .arg(
+                Arg::new(Self::PARQUET_DICTIONARY_COLUMNS)
+                    .long(Self::PARQUET_DICTIONARY_COLUMNS)
+                    .env("P_PARQUET_DICTIONARY_COLUMNS")
+                    .value_name("COLUMN,...")
+                    .required(false)
+                    .value_delimiter(',')
+                    .help("Low cardinality columns to dictionary encode, disables dictionary encoding for other columns"),
+            )
+            .arg(
+                Arg::new(Self::PARQUET_BLOOM_FILTER_COLUMNS)
+                    .long(Self::PARQUET_BLOOM_FILTER_COLUMNS)
+                    .env("P_PARQUET_BLOOM_FILTER_COLUMNS")
+                    .value_name("COLUMN[:FPP[:NDV]],...")
+                    .required(false)
+                    .value_delimiter(',')
+                    .value_parser(validation::bloom_filter_column)
+                    .help("Columns to write bloom filters for, with optional false positive probability and expected distinct values per row group"),
             ).group(
                 ArgGroup::new("oidc")
                     .args([Self::OPENID_CLIENT_ID, Self::OPENID_CLIENT_SECRET, Self::OPENID_ISSUER])
                     .requires_all([Self::OPENID_CLIENT_ID, Self::OPENID_CLIENT_SECRET, Self::OPENID_ISSUER])
                     .multiple(true)
+        ).subcommand(
+            Command::new(Self::CHECK_STORAGE)
+                .about("Run every storage operation against a scratch prefix, report which succeed and how long each took, and exit"),
         )
     }
 }
@@ -451,6 +1000,11 @@ impl FromArgMatches for Cli {
             .get_one::<u16>(Self::FLIGHT_PORT)
             .cloned()
             .expect("default for flight port");
+        self.postgres_port = m.get_one::<u16>(Self::POSTGRES_PORT).cloned();
+        self.postgres_query_range = m
+            .get_one::<String>(Self::POSTGRES_QUERY_RANGE)
+            .cloned()
+            .expect("default for postgres query range");
         self.livetail_channel_capacity = m
             .get_one::<usize>(Self::LIVETAIL_CAPACITY)
             .cloned()
@@ -459,11 +1013,150 @@ impl FromArgMatches for Cli {
         self.query_memory_pool_size = m
             .get_one::<u8>(Self::QUERY_MEM_POOL_SIZE)
             .cloned()
-            .map(|gib| gib as usize * 1024usize.pow(3));
+            .map(|gib| gib as usize * 1024usize.pow(3))
+            .or_else(|| {
+                m.get_one::<u64>(Self::QUERY_MEM_POOL_SIZE_MB)
+                    .map(|mib| *mib as usize * 1024usize.pow(2))
+            });
+        self.query_spill_path = m.get_one::<PathBuf>(Self::QUERY_SPILL_PATH).cloned();
+        self.geoip_ranges_path = m.get_one::<PathBuf>(Self::GEOIP_RANGES_PATH).cloned();
+        self.flush_marker = m.get_one::<String>(Self::FLUSH_MARKER).cloned();
+        self.query_timeout = m
+            .get_one::<u64>(Self::QUERY_TIMEOUT)
+            .cloned()
+            .map(Duration::from_secs);
+        self.max_query_range_days = m.get_one::<u64>(Self::MAX_QUERY_RANGE_DAYS).cloned();
+        self.query_in_list_pruning_limit = m
+            .get_one::<usize>(Self::QUERY_IN_LIST_PRUNING_LIMIT)
+            .cloned()
+            .expect("default for query in list pruning limit");
+        self.staging_flush_interval = m
+            .get_one::<u64>(Self::STAGING_FLUSH_INTERVAL)
+            .cloned()
+            .expect("default for staging flush interval");
+        self.manifest_write_interval = m
+            .get_one::<u64>(Self::MANIFEST_WRITE_INTERVAL)
+            .cloned()
+            .expect("default for manifest write interval");
+        self.staging_max_size = m
+            .get_one::<u64>(Self::STAGING_MAX_SIZE)
+            .cloned()
+            .map(|mib| mib * 1024u64.pow(2));
+        self.key_naming = match m
+            .get_one::<String>(Self::KEY_NAMING)
+            .expect("default for key naming")
+            .as_str()
+        {
+            "hive" => KeyNaming::Hive,
+            "flat" => KeyNaming::Flat,
+            _ => unreachable!(),
+        };
+        self.object_overwrite_policy = match m
+            .get_one::<String>(Self::OBJECT_OVERWRITE_POLICY)
+            .expect("default for object overwrite policy")
+            .as_str()
+        {
+            "overwrite" => OverwritePolicy::Overwrite,
+            "skip-if-exists" => OverwritePolicy::SkipIfExists,
+            "error-if-exists" => OverwritePolicy::ErrorIfExists,
+            "rename-if-exists" => OverwritePolicy::RenameIfExists,
+            _ => unreachable!(),
+        };
+        self.max_object_read_size = m
+            .get_one::<u64>(Self::MAX_OBJECT_READ_SIZE)
+            .cloned()
+            .map(|mib| mib as usize * 1024usize.pow(2));
+        self.max_pending_upload_files = m.get_one::<u64>(Self::MAX_PENDING_UPLOAD_FILES).cloned();
+        self.max_pending_upload_size = m
+            .get_one::<u64>(Self::MAX_PENDING_UPLOAD_SIZE)
+            .cloned()
+            .map(|mib| mib * 1024u64.pow(2));
+        self.stream_storage = m
+            .get_many::<(String, String)>(Self::STREAM_STORAGE)
+            .map(|locations| locations.cloned().collect())
+            .unwrap_or_default();
+        self.metadata_storage = m.get_one::<String>(Self::METADATA_STORAGE).cloned();
+        self.check_storage = m.subcommand_name() == Some(Self::CHECK_STORAGE);
+        self.max_clock_skew = m
+            .get_one::<u64>(Self::MAX_CLOCK_SKEW)
+            .cloned()
+            .map(Duration::from_secs);
+        self.future_event_policy = match m
+            .get_one::<String>(Self::FUTURE_EVENT_POLICY)
+            .expect("default for future event policy")
+            .as_str()
+        {
+            "reject" => FutureEventPolicy::Reject,
+            "clamp" => FutureEventPolicy::Clamp,
+            _ => unreachable!(),
+        };
+        self.delete_mode = match m
+            .get_one::<String>(Self::DELETE_MODE)
+            .expect("default for delete mode")
+            .as_str()
+        {
+            "hard" => DeleteMode::Hard,
+            "soft" => DeleteMode::Soft,
+            _ => unreachable!(),
+        };
+        self.trash_retention_days = m
+            .get_one::<u32>(Self::TRASH_RETENTION_DAYS)
+            .cloned()
+            .expect("default for trash retention days");
+        self.cold_storage = m.get_one::<String>(Self::COLD_STORAGE).cloned();
+        self.hot_tier_days = m
+            .get_one::<u32>(Self::HOT_TIER_DAYS)
+            .cloned()
+            .expect("default for hot tier days");
+        self.shutdown_timeout = m
+            .get_one::<u64>(Self::SHUTDOWN_TIMEOUT)
+            .cloned()
+            .map(Duration::from_secs)
+            .expect("default for shutdown timeout");
+        self.query_pushdown_filters = m
+            .get_one::<bool>(Self::QUERY_PUSHDOWN_FILTERS)
+            .cloned()
+            .expect("default for query pushdown filters");
+        self.query_page_index = m
+            .get_one::<bool>(Self::QUERY_PAGE_INDEX)
+            .cloned()
+            .expect("default for query page index");
+        self.query_partition_columns = m
+            .get_one::<bool>(Self::QUERY_PARTITION_COLUMNS)
+            .cloned()
+            .expect("default for query partition columns");
+        self.quarantine_corrupt_files = m
+            .get_one::<bool>(Self::QUARANTINE_CORRUPT_FILES)
+            .cloned()
+            .expect("default for quarantine corrupt files");
+        self.external_files = m
+            .get_one::<bool>(Self::EXTERNAL_FILES)
+            .cloned()
+            .expect("default for external files");
+        self.external_files_interval = m
+            .get_one::<u64>(Self::EXTERNAL_FILES_INTERVAL)
+            .cloned()
+            .expect("default for external files interval");
+        self.external_files_max = m
+            .get_one::<usize>(Self::EXTERNAL_FILES_MAX)
+            .cloned()
+            .expect("default for external files max");
+        self.query_listing_concurrency = m
+            .get_one::<usize>(Self::QUERY_LISTING_CONCURRENCY)
+            .cloned()
+            .expect("default for query listing concurrency")
+            .max(1);
         self.row_group_size = m
             .get_one::<usize>(Self::ROW_GROUP_SIZE)
             .cloned()
             .expect("default for row_group size");
+        // converts MiB to bytes before assigning
+        self.min_free_disk = m
+            .get_one::<u64>(Self::MIN_FREE_DISK)
+            .cloned()
+            .map(|mib| mib * 1024u64.pow(2));
+        self.ingest_records_per_sec = m.get_one::<u64>(Self::INGEST_RECORDS_PER_SEC).cloned();
+        self.ingest_bytes_per_sec = m.get_one::<u64>(Self::INGEST_BYTES_PER_SEC).cloned();
         self.parquet_compression = match m
             .get_one::<String>(Self::PARQUET_COMPRESSION_ALGO)
             .expect("default for compression algo")
@@ -478,6 +1171,14 @@ impl FromArgMatches for Cli {
             "zstd" => Compression::ZSTD,
             _ => unreachable!(),
         };
+        self.parquet_dictionary_columns = m
+            .get_many::<String>(Self::PARQUET_DICTIONARY_COLUMNS)
+            .map(|columns| columns.map(|column| column.trim().to_owned()).collect())
+            .unwrap_or_default();
+        self.parquet_bloom_filter_columns = m
+            .get_many::<BloomFilterColumn>(Self::PARQUET_BLOOM_FILTER_COLUMNS)
+            .map(|columns| columns.cloned().collect())
+            .unwrap_or_default();
 
         let openid_client_id = m.get_one::<String>(Self::OPENID_CLIENT_ID).cloned();
         let openid_client_secret = m.get_one::<String>(Self::OPENID_CLIENT_SECRET).cloned();
@@ -517,3 +1218,102 @@ impl FromArgMatches for Cli {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::FromArgMatches;
+
+    use super::Cli;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let required = ["parseable", "--username", "admin", "--password", "admin"];
+        let m = Cli::create_cli_command_with_clap("parseable")
+            .try_get_matches_from(required.iter().chain(args))?;
+        Cli::from_arg_matches(&m)
+    }
+
+    #[test]
+    fn listing_concurrency_is_at_least_one() {
+        assert_eq!(parse(&[]).unwrap().query_listing_concurrency, 32);
+        let concurrency = |value| {
+            parse(&["--query-listing-concurrency", value]).map(|cli| cli.query_listing_concurrency)
+        };
+        assert_eq!(concurrency("8").unwrap(), 8);
+        // no list call would ever run
+        assert_eq!(concurrency("0").unwrap(), 1);
+        assert!(concurrency("-1").is_err());
+        assert!(concurrency("many").is_err());
+    }
+
+    #[test]
+    fn flush_marker_is_a_plain_non_data_object_name() {
+        let marker = |value| parse(&["--flush-marker", value]).map(|cli| cli.flush_marker);
+        assert_eq!(parse(&[]).unwrap().flush_marker, None);
+        assert_eq!(marker("_SUCCESS").unwrap().as_deref(), Some("_SUCCESS"));
+
+        // markers must stay directly under the partition and out of query listings
+        for value in ["", "done/_SUCCESS", "_SUCCESS.parquet"] {
+            assert!(marker(value).is_err(), "{value:?} accepted as a marker");
+        }
+    }
+
+    #[test]
+    fn query_memory_limit_is_set_in_gib_or_mib() {
+        let pool_size = |args: &[&str]| parse(args).map(|cli| cli.query_memory_pool_size);
+        assert_eq!(pool_size(&[]).unwrap(), None);
+        assert_eq!(
+            pool_size(&["--query-mempool-size", "2"]).unwrap(),
+            Some(2 * 1024 * 1024 * 1024)
+        );
+        assert_eq!(
+            pool_size(&["--query-mempool-size-mb", "512"]).unwrap(),
+            Some(512 * 1024 * 1024)
+        );
+
+        // two limits are ambiguous, a zero limit fails every query
+        assert!(pool_size(&[
+            "--query-mempool-size",
+            "2",
+            "--query-mempool-size-mb",
+            "512"
+        ])
+        .is_err());
+        assert!(pool_size(&["--query-mempool-size-mb", "0"]).is_err());
+        assert!(pool_size(&["--query-mempool-size-mb", "1.5"]).is_err());
+    }
+
+    #[test]
+    fn spill_directory_is_made_absolute() {
+        assert_eq!(parse(&[]).unwrap().query_spill_path, None);
+
+        let spill = parse(&["--query-spill-path", "spill"]).unwrap();
+        assert_eq!(
+            spill.query_spill_path,
+            Some(std::env::current_dir().unwrap().join("spill"))
+        );
+        let spill = parse(&["--query-spill-path", "/tmp/parseable-spill"]).unwrap();
+        assert_eq!(
+            spill.query_spill_path,
+            Some(PathBuf::from("/tmp/parseable-spill"))
+        );
+    }
+
+    #[test]
+    fn staging_flush_interval_is_whole_minutes() {
+        let interval = |value| {
+            parse(&["--staging-flush-interval", value]).map(|cli| cli.staging_flush_interval)
+        };
+        assert_eq!(parse(&[]).unwrap().staging_flush_interval, 60);
+        assert_eq!(interval("300").unwrap(), 300);
+
+        // files are staged per minute, a flush mid minute would leave part of it behind
+        for value in ["0", "30", "90", "-60", "1m"] {
+            assert!(
+                interval(value).is_err(),
+                "{value:?} accepted as an interval"
+            );
+        }
+    }
+}
@@ -18,6 +18,7 @@
 */
 
 pub mod format;
+pub mod rate_limit;
 mod writer;
 
 use arrow_array::RecordBatch;
@@ -27,7 +28,7 @@ use std::sync::Arc;
 
 use self::error::EventError;
 pub use self::writer::STREAM_WRITERS;
-use crate::{handlers::http::ingest::PostError, metadata};
+use crate::{handlers::http::ingest::PostError, metadata, storage::key_naming};
 use chrono::NaiveDateTime;
 use std::collections::HashMap;
 
@@ -122,6 +123,11 @@ impl Event {
         parsed_timestamp: NaiveDateTime,
         custom_partition_values: &HashMap<String, String>,
     ) -> Result<(), EventError> {
+        // staged files, and the object keys derived from them, are named in the partition zone
+        let parsed_timestamp = key_naming::partition_time(
+            parsed_timestamp,
+            metadata::STREAM_INFO.get_partition_time_zone(stream_name)?,
+        );
         STREAM_WRITERS.append_to_local(
             stream_name,
             schema_key,
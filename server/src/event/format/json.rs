@@ -29,12 +29,16 @@ use serde_json::Value;
 use std::{collections::HashMap, sync::Arc};
 
 use super::{EventFormat, Metadata, Tags};
-use crate::utils::{arrow::get_field, json::flatten_json_body};
+use crate::{
+    storage::schema_mode::SchemaMode,
+    utils::{arrow::get_field, json::flatten_json_body},
+};
 
 pub struct Event {
     pub data: Value,
     pub tags: Tags,
     pub metadata: Metadata,
+    pub schema_mode: SchemaMode,
 }
 
 impl EventFormat for Event {
@@ -59,6 +63,7 @@ impl EventFormat for Event {
             value @ Value::Object(_) => vec![value],
             _ => unreachable!("flatten would have failed beforehand"),
         };
+        self.schema_mode.check(&stream_schema, &value_arr)?;
 
         // collect all the keys from all the json objects in the request body
         let fields =
@@ -129,7 +134,7 @@ impl EventFormat for Event {
 
 // Returns arrow schema with the fields that are present in the request body
 // This schema is an input to convert the request body to arrow record batch
-fn derive_arrow_schema(
+pub(crate) fn derive_arrow_schema(
     schema: &HashMap<String, Arc<Field>>,
     fields: Vec<&str>,
 ) -> Result<Vec<Arc<Field>>, ()> {
@@ -162,18 +167,23 @@ fn collect_keys<'a>(values: impl Iterator<Item = &'a Value>) -> Result<Vec<&'a s
 }
 
 fn fields_mismatch(schema: &[Arc<Field>], body: &Value) -> bool {
+    mismatched_field(schema, body).is_some()
+}
+
+// Returns the first field of the body not in the schema or with a value not of its type
+pub(crate) fn mismatched_field<'a>(schema: &[Arc<Field>], body: &'a Value) -> Option<&'a str> {
     for (name, val) in body.as_object().expect("body is of object variant") {
         if val.is_null() {
             continue;
         }
         let Some(field) = get_field(schema, name) else {
-            return true;
+            return Some(name);
         };
         if !valid_type(field.data_type(), val) {
-            return true;
+            return Some(name);
         }
     }
-    false
+    None
 }
 
 fn valid_type(data_type: &DataType, value: &Value) -> bool {
@@ -181,7 +191,8 @@ fn valid_type(data_type: &DataType, value: &Value) -> bool {
         DataType::Boolean => value.is_boolean(),
         DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => value.is_i64(),
         DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => value.is_u64(),
-        DataType::Float16 | DataType::Float32 | DataType::Float64 => value.is_f64(),
+        // integers are read as floats when decoded
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => value.is_number(),
         DataType::Utf8 => value.is_string(),
         DataType::List(field) => {
             let data_type = field.data_type();
@@ -0,0 +1,281 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Limits the records and bytes per second ingested into each stream, so one client
+//! can't overwhelm a shared instance. Every limited quantity of a stream has a token
+//! bucket holding up to a second of its rate. A request taking more tokens than are
+//! left is rejected with the time until they are refilled, a request larger than a
+//! whole bucket is let through when the bucket is full and the next ones wait it off.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    metrics::{INGEST_RATE, INGEST_THROTTLED},
+    option::CONFIG,
+};
+
+// the reported rate is what was accepted over windows of this length
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+pub static RATE_LIMITER: Lazy<RateLimiter> = Lazy::new(|| {
+    RateLimiter::new(RateLimit {
+        records_per_sec: CONFIG.parseable.ingest_records_per_sec,
+        bytes_per_sec: CONFIG.parseable.ingest_bytes_per_sec,
+    })
+});
+
+/// Most records and bytes per second ingested into a stream, unlimited when unset
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub records_per_sec: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl RateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.records_per_sec.is_none() && self.bytes_per_sec.is_none()
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled_at = now;
+    }
+
+    /// Time until `amount` tokens can be taken, zero when they can be taken now
+    fn wait(&self, amount: u64) -> Duration {
+        // a full bucket lets any amount through
+        let needed = amount.min(self.rate) as f64;
+        if self.tokens >= needed {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((needed - self.tokens) / self.rate as f64)
+    }
+
+    fn take(&mut self, amount: u64) {
+        self.tokens -= amount as f64;
+    }
+}
+
+#[derive(Debug)]
+struct StreamState {
+    limit: RateLimit,
+    records: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    window_start: Instant,
+    window_records: u64,
+    window_bytes: u64,
+}
+
+impl StreamState {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            records: limit
+                .records_per_sec
+                .map(|rate| TokenBucket::new(rate, now)),
+            bytes: limit.bytes_per_sec.map(|rate| TokenBucket::new(rate, now)),
+            window_start: now,
+            window_records: 0,
+            window_bytes: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    default: RateLimit,
+    streams: Mutex<HashMap<String, StreamState>>,
+}
+
+impl RateLimiter {
+    pub fn new(default: RateLimit) -> Self {
+        Self {
+            default,
+            streams: Mutex::default(),
+        }
+    }
+
+    /// Limit of a stream, the stream's own or else the default one
+    pub fn limit_for(&self, own: Option<RateLimit>) -> RateLimit {
+        own.unwrap_or(self.default)
+    }
+
+    /// Accepts `records` and `bytes` into `stream` limited by `limit`, or returns how
+    /// long to wait before trying again. Nothing is taken from a rejected request.
+    pub fn acquire(
+        &self,
+        stream: &str,
+        limit: RateLimit,
+        records: u64,
+        bytes: u64,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut streams = self
+            .streams
+            .lock()
+            .expect("rate limiter lock is not poisoned");
+        let state = streams
+            .entry(stream.to_owned())
+            .or_insert_with(|| StreamState::new(limit, now));
+        // a changed limit starts over with full buckets
+        if state.limit != limit {
+            *state = StreamState::new(limit, now);
+        }
+
+        let mut buckets: Vec<(&mut TokenBucket, u64)> = [
+            (state.records.as_mut(), records),
+            (state.bytes.as_mut(), bytes),
+        ]
+        .into_iter()
+        .filter_map(|(bucket, amount)| Some((bucket?, amount)))
+        .collect();
+        let mut wait = Duration::ZERO;
+        for (bucket, amount) in buckets.iter_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait(*amount));
+        }
+        if !wait.is_zero() {
+            INGEST_THROTTLED.with_label_values(&[stream]).inc();
+            return Err(wait);
+        }
+        for (bucket, amount) in buckets {
+            bucket.take(amount);
+        }
+
+        state.window_records += records;
+        state.window_bytes += bytes;
+        let elapsed = now.saturating_duration_since(state.window_start);
+        if elapsed >= RATE_WINDOW {
+            let secs = elapsed.as_secs_f64();
+            INGEST_RATE
+                .with_label_values(&[stream, "records"])
+                .set((state.window_records as f64 / secs) as i64);
+            INGEST_RATE
+                .with_label_values(&[stream, "bytes"])
+                .set((state.window_bytes as f64 / secs) as i64);
+            state.window_start = now;
+            state.window_records = 0;
+            state.window_bytes = 0;
+        }
+        Ok(())
+    }
+
+    pub fn remove_stream(&self, stream: &str) {
+        self.streams
+            .lock()
+            .expect("rate limiter lock is not poisoned")
+            .remove(stream);
+        for unit in ["records", "bytes"] {
+            let _ = INGEST_RATE.remove_label_values(&[stream, unit]);
+        }
+        let _ = INGEST_THROTTLED.remove_label_values(&[stream]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{RateLimit, RateLimiter};
+
+    #[test]
+    fn requests_past_the_rate_are_throttled_until_tokens_refill() {
+        let limiter = RateLimiter::new(RateLimit {
+            records_per_sec: Some(100),
+            bytes_per_sec: None,
+        });
+        let limit = limiter.limit_for(None);
+        let start = Instant::now();
+
+        // the bucket holds one second of records
+        for _ in 0..10 {
+            assert!(limiter.acquire("app", limit, 10, 1000, start).is_ok());
+        }
+        let wait = limiter.acquire("app", limit, 10, 1000, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(100));
+        // other streams have buckets of their own
+        assert!(limiter.acquire("other", limit, 100, 1000, start).is_ok());
+
+        // refilled at the rate
+        let later = start + Duration::from_millis(100);
+        assert!(limiter.acquire("app", limit, 10, 1000, later).is_ok());
+        assert!(limiter.acquire("app", limit, 10, 1000, later).is_err());
+
+        // a request larger than the bucket passes once it is full, the next ones wait
+        let idle = later + Duration::from_secs(5);
+        assert!(limiter.acquire("app", limit, 250, 1000, idle).is_ok());
+        let wait = limiter.acquire("app", limit, 1, 1000, idle).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(1510));
+        assert!(limiter
+            .acquire("app", limit, 1, 1000, idle + Duration::from_millis(1600))
+            .is_ok());
+    }
+
+    #[test]
+    fn stream_limit_overrides_the_default() {
+        let limiter = RateLimiter::new(RateLimit::default());
+        let start = Instant::now();
+
+        let unlimited = limiter.limit_for(None);
+        assert!(unlimited.is_unlimited());
+        for _ in 0..1000 {
+            assert!(limiter
+                .acquire("app", unlimited, 1000, 1 << 20, start)
+                .is_ok());
+        }
+
+        let own = limiter.limit_for(Some(RateLimit {
+            records_per_sec: None,
+            bytes_per_sec: Some(1024),
+        }));
+        assert!(limiter.acquire("app", own, 1, 1024, start).is_ok());
+        // a rejected request takes nothing, so a smaller one still fits later
+        assert!(limiter.acquire("app", own, 1, 512, start).is_err());
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.acquire("app", own, 1, 512, later).is_ok());
+    }
+}
@@ -217,6 +217,18 @@ impl WriterTable {
         }
     }
 
+    /// Closes the writer of a stream and runs `f` before any event of the stream can
+    /// be written again, so staged files can be read while nothing appends to them.
+    pub fn close_stream_while<T>(&self, stream_name: &str, f: impl FnOnce() -> T) -> T {
+        let mut table = self.write().unwrap();
+        if let Some(writer) = table.remove(stream_name) {
+            writer.into_inner().unwrap().disk.close_all();
+        }
+        let res = f();
+        drop(table);
+        res
+    }
+
     pub fn recordbatches_cloned(
         &self,
         stream_name: &str,
@@ -0,0 +1,417 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Export of a stream's data to another location as parquet or NDJSON.
+//! Records are read through the query path and uploaded in parts as they arrive,
+//! so only a single part is held in memory at a time.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use arrow_array::{Array, ArrayRef, RecordBatch, StructArray};
+use arrow_schema::{ArrowError, Field, Schema, SchemaRef};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use datafusion::error::DataFusionError;
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
+use parquet::{arrow::ArrowWriter, errors::ParquetError, file::properties::WriterProperties};
+use relative_path::RelativePathBuf;
+use ulid::Ulid;
+
+use crate::{
+    metadata::LOCK_EXPECT,
+    option::CONFIG,
+    query::{error::ExecuteError, Query},
+    storage::{ObjectStorage, ObjectStorageError},
+};
+
+/// Size after which an output part is uploaded and a new one is started
+const PART_SIZE: usize = 64 * 1024 * 1024;
+const FLATTEN_SEPARATOR: &str = "_";
+
+static EXPORTS: Lazy<RwLock<HashMap<Ulid, ExportProgress>>> = Lazy::new(RwLock::default);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Parquet,
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// Export request through http endpoint
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest {
+    pub start_time: String,
+    pub end_time: String,
+    /// Bucket, or directory for drive storage, to write the export to
+    pub location: String,
+    /// Prefix of the written parts, defaults to `<stream>/<export id>`
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Write nested columns as top level `parent_child` columns
+    #[serde(default)]
+    pub flatten: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportProgress {
+    pub stream: String,
+    pub state: ExportState,
+    pub rows: u64,
+    pub bytes: u64,
+    pub files: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+/// Progress of an export started on this server
+pub fn progress(id: &str) -> Option<ExportProgress> {
+    let id = id.parse::<Ulid>().ok()?;
+    EXPORTS.read().expect(LOCK_EXPECT).get(&id).cloned()
+}
+
+/// Starts exporting the results of `query` to `target` in the background
+pub fn start(
+    stream: String,
+    query: Query,
+    target: Arc<dyn ObjectStorage + Send>,
+    request: ExportRequest,
+) -> Ulid {
+    let id = Ulid::new();
+    let prefix = request
+        .prefix
+        .clone()
+        .unwrap_or_else(|| format!("{stream}/{id}"));
+
+    EXPORTS.write().expect(LOCK_EXPECT).insert(
+        id,
+        ExportProgress {
+            stream: stream.clone(),
+            state: ExportState::Running,
+            rows: 0,
+            bytes: 0,
+            files: Vec::new(),
+            started_at: Utc::now(),
+            finished_at: None,
+            error: None,
+        },
+    );
+
+    tokio::spawn(async move {
+        let res = export(id, stream, query, target, prefix, &request).await;
+        update(id, |progress| {
+            progress.finished_at = Some(Utc::now());
+            match res {
+                Ok(()) => progress.state = ExportState::Completed,
+                Err(err) => {
+                    log::error!("export {id} failed: {err}");
+                    progress.state = ExportState::Failed;
+                    progress.error = Some(err.to_string());
+                }
+            }
+        });
+    });
+
+    id
+}
+
+fn update(id: Ulid, f: impl FnOnce(&mut ExportProgress)) {
+    if let Some(progress) = EXPORTS.write().expect(LOCK_EXPECT).get_mut(&id) {
+        f(progress)
+    }
+}
+
+async fn export(
+    id: Ulid,
+    stream: String,
+    query: Query,
+    target: Arc<dyn ObjectStorage + Send>,
+    prefix: String,
+    request: &ExportRequest,
+) -> Result<(), ExportError> {
+    let mut batches = query.execute_stream(stream).await?;
+    let mut writer: Option<PartWriter> = None;
+    let mut parts = 0;
+
+    while let Some(batch) = batches.next().await {
+        let batch = batch?;
+        let batch = if request.flatten {
+            flatten_batch(&batch)?
+        } else {
+            batch
+        };
+        let rows = batch.num_rows() as u64;
+
+        if writer.is_none() {
+            writer = Some(PartWriter::new(
+                request.format,
+                batch.schema(),
+                Some(writer_props()),
+            )?);
+        }
+        let part = writer.as_mut().expect("part is created above");
+        part.write(&batch)?;
+        update(id, |progress| progress.rows += rows);
+
+        if part.size() >= PART_SIZE {
+            let part = writer.take().expect("part is being written");
+            upload(id, &*target, &prefix, parts, request.format, part).await?;
+            parts += 1;
+        }
+    }
+
+    if let Some(part) = writer {
+        upload(id, &*target, &prefix, parts, request.format, part).await?;
+    }
+
+    Ok(())
+}
+
+async fn upload(
+    id: Ulid,
+    target: &dyn ObjectStorage,
+    prefix: &str,
+    index: usize,
+    format: ExportFormat,
+    part: PartWriter,
+) -> Result<(), ExportError> {
+    let path = RelativePathBuf::from(format!(
+        "{}/part-{index:05}.{}",
+        prefix.trim_end_matches('/'),
+        format.extension()
+    ));
+    let data = part.finish()?;
+    let size = data.len() as u64;
+    target.put_object(&path, Bytes::from(data)).await?;
+
+    update(id, |progress| {
+        progress.bytes += size;
+        progress.files.push(path.to_string());
+    });
+    Ok(())
+}
+
+fn writer_props() -> WriterProperties {
+    WriterProperties::builder()
+        .set_max_row_group_size(CONFIG.parseable.row_group_size)
+        .set_compression(CONFIG.parseable.parquet_compression.into())
+        .build()
+}
+
+/// Output part held in memory until it is uploaded
+enum PartWriter {
+    Parquet(ArrowWriter<Vec<u8>>),
+    Ndjson(Vec<u8>),
+}
+
+impl PartWriter {
+    fn new(
+        format: ExportFormat,
+        schema: SchemaRef,
+        props: Option<WriterProperties>,
+    ) -> Result<Self, ExportError> {
+        let writer = match format {
+            ExportFormat::Parquet => {
+                PartWriter::Parquet(ArrowWriter::try_new(Vec::new(), schema, props)?)
+            }
+            ExportFormat::Ndjson => PartWriter::Ndjson(Vec::new()),
+        };
+        Ok(writer)
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), ExportError> {
+        match self {
+            PartWriter::Parquet(writer) => writer.write(batch)?,
+            PartWriter::Ndjson(buf) => {
+                let mut writer = arrow_json::LineDelimitedWriter::new(buf);
+                writer.write(batch)?;
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PartWriter::Parquet(writer) => writer.bytes_written() + writer.in_progress_size(),
+            PartWriter::Ndjson(buf) => buf.len(),
+        }
+    }
+
+    fn finish(self) -> Result<Vec<u8>, ExportError> {
+        match self {
+            PartWriter::Parquet(writer) => Ok(writer.into_inner()?),
+            PartWriter::Ndjson(buf) => Ok(buf),
+        }
+    }
+}
+
+/// Replaces struct columns by their leaf columns, named after their path
+fn flatten_batch(batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+    let mut fields = Vec::new();
+    let mut columns = Vec::new();
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        flatten_column(field.name(), field, column, &mut fields, &mut columns);
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+fn flatten_column(
+    name: &str,
+    field: &Field,
+    column: &ArrayRef,
+    fields: &mut Vec<Field>,
+    columns: &mut Vec<ArrayRef>,
+) {
+    match column.as_any().downcast_ref::<StructArray>() {
+        Some(array) => {
+            for (child, child_column) in array.fields().iter().zip(array.columns()) {
+                let name = format!("{name}{FLATTEN_SEPARATOR}{}", child.name());
+                flatten_column(&name, child, child_column, fields, columns);
+            }
+        }
+        None => {
+            fields.push(field.clone().with_name(name).with_nullable(true));
+            columns.push(column.clone());
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Execution Error: {0}")]
+    Execute(#[from] ExecuteError),
+    #[error("Datafusion Error: {0}")]
+    Datafusion(#[from] DataFusionError),
+    #[error("Arrow Error: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("Parquet Error: {0}")]
+    Parquet(#[from] ParquetError),
+    #[error("ObjectStorage Error: {0}")]
+    ObjectStorage(#[from] ObjectStorageError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, ArrayRef, Int64Array, RecordBatch, StringArray, StructArray};
+    use arrow_schema::{DataType, Field, Fields};
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::{flatten_batch, ExportFormat, PartWriter};
+
+    fn nested_batch() -> RecordBatch {
+        let inner = StructArray::from(vec![
+            (
+                Arc::new(Field::new("name", DataType::Utf8, true)),
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new("pid", DataType::Int64, true)),
+                Arc::new(Int64Array::from(vec![1, 2])) as ArrayRef,
+            ),
+        ]);
+        let outer = StructArray::from(vec![(
+            Arc::new(Field::new(
+                "process",
+                DataType::Struct(Fields::from(inner.fields().to_vec())),
+                true,
+            )),
+            Arc::new(inner) as ArrayRef,
+        )]);
+        RecordBatch::try_from_iter(vec![
+            (
+                "level",
+                Arc::new(StringArray::from(vec!["info", "warn"])) as ArrayRef,
+            ),
+            ("host", Arc::new(outer) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn flatten_nested_columns() {
+        let batch = flatten_batch(&nested_batch()).unwrap();
+        let names = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["level", "host_process_name", "host_process_pid"]);
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.column(2).len(), 2);
+    }
+
+    #[test]
+    fn parts_are_readable() {
+        let batch = nested_batch();
+
+        let mut part = PartWriter::new(ExportFormat::Parquet, batch.schema(), None).unwrap();
+        part.write(&batch).unwrap();
+        part.write(&batch).unwrap();
+        let data = Bytes::from(part.finish().unwrap());
+        let rows = ParquetRecordBatchReaderBuilder::try_new(data)
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum::<usize>();
+        assert_eq!(rows, 4);
+
+        let mut part = PartWriter::new(ExportFormat::Ndjson, batch.schema(), None).unwrap();
+        part.write(&batch).unwrap();
+        assert!(part.size() > 0);
+        let data = String::from_utf8(part.finish().unwrap()).unwrap();
+        assert_eq!(data.lines().count(), 2);
+        assert!(data
+            .lines()
+            .next()
+            .unwrap()
+            .contains(r#""process":{"name":"a","pid":1}"#));
+    }
+}
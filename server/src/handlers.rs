@@ -19,6 +19,7 @@
 pub mod airplane;
 pub mod http;
 pub mod livetail;
+pub mod postgres;
 
 const PREFIX_TAGS: &str = "x-p-tag-";
 const PREFIX_META: &str = "x-p-meta-";
@@ -26,11 +27,14 @@ const STREAM_NAME_HEADER_KEY: &str = "x-p-stream";
 const CACHE_RESULTS_HEADER_KEY: &str = "x-p-cache-results";
 const CACHE_VIEW_HEADER_KEY: &str = "x-p-show-cached";
 const USER_ID_HEADER_KEY: &str = "x-p-user-id";
+const QUERY_TIMEOUT_HEADER_KEY: &str = "x-p-query-timeout";
+const COLD_QUERY_HEADER_KEY: &str = "x-p-cold-query";
 const LOG_SOURCE_KEY: &str = "x-p-log-source";
 const TIME_PARTITION_KEY: &str = "x-p-time-partition";
 const TIME_PARTITION_LIMIT_KEY: &str = "x-p-time-partition-limit";
 const CUSTOM_PARTITION_KEY: &str = "x-p-custom-partition";
 const STATIC_SCHEMA_FLAG: &str = "x-p-static-schema-flag";
+const PARTITION_TIME_ZONE_KEY: &str = "x-p-partition-time-zone";
 const AUTHORIZATION_KEY: &str = "authorization";
 const SEPARATOR: char = '^';
 
@@ -41,7 +41,8 @@ use crate::option::CONFIG;
 use crate::handlers::livetail::cross_origin_config;
 
 use crate::handlers::http::query::{
-    authorize_and_set_filter_tags, into_query, put_results_in_cache, update_schema_when_distributed,
+    authorize_and_set_filter_tags, check_query_range, into_query, put_results_in_cache,
+    update_schema_when_distributed,
 };
 use crate::query::{TableScanVisitor, QUERY_SESSION};
 use crate::querycache::QueryCacheManager;
@@ -202,6 +203,9 @@ impl FlightService for AirServiceImpl {
         let mut query = into_query(&ticket, &session_state)
             .await
             .map_err(|_| Status::internal("Failed to parse query"))?;
+        let permissions = Users.get_permissions(&key);
+        check_query_range(&query, &permissions)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
 
         let event =
             if send_to_ingester(query.start.timestamp_millis(), query.end.timestamp_millis()) {
@@ -231,7 +235,6 @@ impl FlightService for AirServiceImpl {
             } else {
                 None
             };
-        let permissions = Users.get_permissions(&key);
 
         authorize_and_set_filter_tags(&mut query, permissions, &stream_name).map_err(|_| {
             Status::permission_denied("User Does not have permission to access this")
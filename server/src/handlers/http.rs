@@ -26,6 +26,7 @@ use crate::option::CONFIG;
 use self::{cluster::get_ingestor_info, query::Query};
 
 pub(crate) mod about;
+pub(crate) mod alias;
 mod cache;
 pub mod cluster;
 pub(crate) mod health_check;
@@ -0,0 +1,207 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+
+use actix_web::{http::header::ContentType, web, HttpResponse, Responder};
+use http::StatusCode;
+
+use crate::{
+    metadata::{self, LOCK_EXPECT, STREAM_ALIASES, STREAM_INFO},
+    option::CONFIG,
+    storage::{self, ObjectStorageError, StorageMetadata},
+    validator::{self, error::StreamNameValidationError},
+};
+
+// Handler for PUT /api/v1/alias/{name}
+// Creates a new alias or points an existing one to another stream
+pub async fn put(
+    name: web::Path<String>,
+    stream: web::Json<String>,
+) -> Result<impl Responder, AliasError> {
+    let name = name.into_inner();
+    let stream = stream.into_inner();
+    check_alias(&name, &stream)?;
+    let mut metadata = get_metadata().await?;
+    metadata.stream_aliases.insert(name.clone(), stream.clone());
+    put_metadata(&metadata).await?;
+    STREAM_ALIASES
+        .write()
+        .expect(LOCK_EXPECT)
+        .insert(name, stream);
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Handler for GET /api/v1/alias
+// Fetch all aliases along with the stream they point to
+pub async fn list() -> Result<impl Responder, AliasError> {
+    let aliases = STREAM_ALIASES.read().expect(LOCK_EXPECT).clone();
+    Ok(web::Json(aliases))
+}
+
+// Handler for DELETE /api/v1/alias/{name}
+// Delete existing alias
+pub async fn delete(name: web::Path<String>) -> Result<impl Responder, AliasError> {
+    let name = name.into_inner();
+    if !metadata::is_stream_alias(&name) {
+        return Err(AliasError::AliasNotFound(name));
+    }
+    let mut metadata = get_metadata().await?;
+    metadata.stream_aliases.remove(&name);
+    put_metadata(&metadata).await?;
+    STREAM_ALIASES.write().expect(LOCK_EXPECT).remove(&name);
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Removes all aliases pointing to a deleted stream
+pub async fn remove_aliases_of(stream: &str) -> Result<(), ObjectStorageError> {
+    let mut metadata = get_metadata().await?;
+    if drop_aliases_of(&mut metadata.stream_aliases, stream) {
+        put_metadata(&metadata).await?;
+    }
+    drop_aliases_of(&mut STREAM_ALIASES.write().expect(LOCK_EXPECT), stream);
+    Ok(())
+}
+
+// an alias is named like a stream, as it's queried like one, and points to a stream
+fn check_alias(name: &str, stream: &str) -> Result<(), AliasError> {
+    validator::stream_name(name)?;
+    if STREAM_INFO.stream_exists(name) {
+        return Err(AliasError::StreamNameCollision(name.to_owned()));
+    }
+    if !STREAM_INFO.stream_exists(stream) {
+        return Err(AliasError::StreamNotFound(stream.to_owned()));
+    }
+    Ok(())
+}
+
+/// Removes the aliases pointing to `stream`, returns whether any was removed
+fn drop_aliases_of(aliases: &mut HashMap<String, String>, stream: &str) -> bool {
+    let count = aliases.len();
+    aliases.retain(|_, target| target != stream);
+    aliases.len() != count
+}
+
+async fn get_metadata() -> Result<crate::storage::StorageMetadata, ObjectStorageError> {
+    let metadata = CONFIG
+        .storage()
+        .get_object_store()
+        .get_metadata()
+        .await?
+        .expect("metadata is initialized");
+    Ok(metadata)
+}
+
+async fn put_metadata(metadata: &StorageMetadata) -> Result<(), ObjectStorageError> {
+    storage::put_remote_metadata(metadata).await?;
+    storage::put_staging_metadata(metadata)?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AliasError {
+    #[error("Failed to connect to storage: {0}")]
+    ObjectStorageError(#[from] ObjectStorageError),
+    #[error("Invalid alias: {0}")]
+    InvalidName(#[from] StreamNameValidationError),
+    #[error("Alias {0} collides with an existing log stream")]
+    StreamNameCollision(String),
+    #[error("Log stream {0} does not exist")]
+    StreamNotFound(String),
+    #[error("Alias {0} does not exist")]
+    AliasNotFound(String),
+}
+
+impl actix_web::ResponseError for AliasError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::ObjectStorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidName(_) => StatusCode::BAD_REQUEST,
+            Self::StreamNameCollision(_) => StatusCode::CONFLICT,
+            Self::StreamNotFound(_) | Self::AliasNotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{check_alias, drop_aliases_of, AliasError};
+    use crate::metadata::{resolve_stream_alias, LOCK_EXPECT, STREAM_ALIASES, STREAM_INFO};
+
+    fn add_stream(name: &str) {
+        STREAM_INFO.add_stream(
+            name.to_owned(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            None,
+            HashMap::new(),
+        );
+    }
+
+    #[test]
+    fn alias_must_be_a_valid_unused_stream_name() {
+        add_stream("aliasedapp");
+        add_stream("aliasedweb");
+
+        assert!(check_alias("applogs", "aliasedapp").is_ok());
+        assert!(matches!(
+            check_alias("aliasedweb", "aliasedapp"),
+            Err(AliasError::StreamNameCollision(name)) if name == "aliasedweb"
+        ));
+        assert!(matches!(
+            check_alias("applogs", "missing"),
+            Err(AliasError::StreamNotFound(name)) if name == "missing"
+        ));
+        for name in ["", "app logs", "app/logs", "AppLogs", "1logs"] {
+            assert!(matches!(
+                check_alias(name, "aliasedapp"),
+                Err(AliasError::InvalidName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn aliases_resolve_until_their_stream_is_deleted() {
+        STREAM_ALIASES.write().expect(LOCK_EXPECT).extend(
+            [("deletedlogs", "deleted"), ("keptlogs", "kept")]
+                .map(|(alias, stream)| (alias.to_owned(), stream.to_owned())),
+        );
+        assert_eq!(resolve_stream_alias("deletedlogs"), "deleted");
+        assert_eq!(resolve_stream_alias("deleted"), "deleted");
+
+        let removed = drop_aliases_of(&mut STREAM_ALIASES.write().expect(LOCK_EXPECT), "deleted");
+        let removed_again =
+            drop_aliases_of(&mut STREAM_ALIASES.write().expect(LOCK_EXPECT), "deleted");
+
+        assert!(removed);
+        assert!(!removed_again);
+        assert_eq!(resolve_stream_alias("deletedlogs"), "deletedlogs");
+        assert_eq!(resolve_stream_alias("keptlogs"), "kept");
+    }
+}
@@ -25,6 +25,7 @@ use crate::event::{
     self,
     error::EventError,
     format::{self, EventFormat},
+    rate_limit::RATE_LIMITER,
 };
 use crate::handlers::{
     LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, LOG_SOURCE_OTEL, PREFIX_META, PREFIX_TAGS, SEPARATOR,
@@ -32,19 +33,26 @@ use crate::handlers::{
 };
 use crate::localcache::CacheError;
 use crate::metadata::{self, STREAM_INFO};
-use crate::option::{Mode, CONFIG};
-use crate::storage::{LogStream, ObjectStorageError};
+use crate::metrics::FUTURE_EVENTS;
+use crate::option::{FutureEventPolicy, Mode, CONFIG};
+use crate::shutdown;
+use crate::storage::schema_mode::{SchemaMode, SchemaViolation};
+use crate::storage::{staging, upload_backlog::UPLOAD_BACKLOG, LogStream, ObjectStorageError};
 use crate::utils::header_parsing::{collect_labelled_headers, ParseHeaderError};
 use crate::utils::json::convert_array_to_object;
-use actix_web::{http::header::ContentType, HttpRequest, HttpResponse};
+use actix_web::{
+    http::header::{self, ContentType},
+    HttpRequest, HttpResponse,
+};
 use arrow_array::RecordBatch;
 use arrow_schema::{Field, Schema};
 use bytes::Bytes;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
 use http::StatusCode;
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // Handler for POST /api/v1/ingest
 // ingests events by extracting stream name from header
@@ -87,6 +95,7 @@ pub async fn ingest_internal_stream(stream_name: String, body: Bytes) -> Result<
             data: body_val,
             tags: String::default(),
             metadata: String::default(),
+            schema_mode: SchemaMode::default(),
         };
         event.into_recordbatch(schema, None, None)?
     };
@@ -110,6 +119,8 @@ async fn flatten_and_push_logs(
     body: Bytes,
     stream_name: String,
 ) -> Result<(), PostError> {
+    check_ingestion_allowed()?;
+
     //flatten logs
     if let Some((_, log_source)) = req.headers().iter().find(|&(key, _)| key == LOG_SOURCE_KEY) {
         let mut json: Vec<BTreeMap<String, Value>> = Vec::new();
@@ -132,6 +143,111 @@ async fn flatten_and_push_logs(
     Ok(())
 }
 
+/// Rejects events while the server can't take them in
+fn check_ingestion_allowed() -> Result<(), PostError> {
+    if shutdown::is_shutting_down() {
+        return Err(PostError::ShuttingDown);
+    }
+    if !staging::has_free_disk_space() {
+        return Err(PostError::InsufficientDiskSpace);
+    }
+    if UPLOAD_BACKLOG.is_full() {
+        return Err(PostError::UploadBacklog);
+    }
+    Ok(())
+}
+
+/// Rejects events past the rate limit of the stream
+fn check_rate_limit(stream_name: &str, body: &Value, size: usize) -> Result<(), PostError> {
+    let records = match body {
+        Value::Array(events) => events.len(),
+        _ => 1,
+    };
+    let limit = RATE_LIMITER.limit_for(STREAM_INFO.get_rate_limit(stream_name).ok().flatten());
+    RATE_LIMITER
+        .acquire(
+            stream_name,
+            limit,
+            records as u64,
+            size as u64,
+            Instant::now(),
+        )
+        .map_err(|retry_after| PostError::RateLimited {
+            stream: stream_name.to_owned(),
+            retry_after,
+        })
+}
+
+// Handler for POST /api/v1/otlp/v1/logs
+// ingests OTLP/HTTP logs, encoded as protobuf or JSON, into the stream named in the header
+// creates if stream does not exist
+pub async fn ingest_otel_logs(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let stream_name = otel_stream(&req).await?;
+    let records = otel::flatten_otlp_logs(&body, is_protobuf(&req)?)?;
+    push_otel_records(stream_name, req, records).await
+}
+
+// Handler for POST /api/v1/otlp/v1/traces
+// ingests OTLP/HTTP traces encoded as protobuf, one event per span
+// creates if stream does not exist
+pub async fn ingest_otel_traces(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let stream_name = otel_stream(&req).await?;
+    if !is_protobuf(&req)? {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "OTLP traces are only accepted as protobuf"
+        )));
+    }
+    let records = otel::flatten_otlp_traces(&body)?;
+    push_otel_records(stream_name, req, records).await
+}
+
+/// Stream named in the header of an OTLP request, created if it doesn't exist
+async fn otel_stream(req: &HttpRequest) -> Result<String, PostError> {
+    let Some(stream_name) = req.headers().get(STREAM_NAME_HEADER_KEY) else {
+        return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+    };
+    let stream_name = stream_name.to_str().unwrap().to_owned();
+    if stream_name.eq(INTERNAL_STREAM_NAME) {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "Stream {} is an internal stream and cannot be ingested into",
+            stream_name
+        )));
+    }
+    check_ingestion_allowed()?;
+    create_stream_if_not_exists(&stream_name).await?;
+    Ok(stream_name)
+}
+
+/// Whether an OTLP request body is protobuf rather than JSON, from its content type
+fn is_protobuf(req: &HttpRequest) -> Result<bool, PostError> {
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("application/x-protobuf") {
+        Ok(true)
+    } else if content_type.starts_with("application/json") {
+        Ok(false)
+    } else {
+        Err(PostError::Invalid(anyhow::anyhow!(
+            "OTLP content type must be application/x-protobuf or application/json"
+        )))
+    }
+}
+
+async fn push_otel_records(
+    stream_name: String,
+    req: HttpRequest,
+    records: Vec<BTreeMap<String, Value>>,
+) -> Result<HttpResponse, PostError> {
+    if !records.is_empty() {
+        let body = Bytes::from(serde_json::to_vec(&records)?);
+        push_logs(stream_name, req, body).await?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
 // Handler for POST /api/v1/logstream/{logstream}
 // only ingests events into the specified logstream
 // fails if the logstream does not exist
@@ -179,6 +295,7 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
     let custom_partition = object_store_format.custom_partition;
     let body_val: Value = serde_json::from_slice(&body)?;
     let size: usize = body.len();
+    check_rate_limit(&stream_name, &body_val, size)?;
     let mut parsed_timestamp = Utc::now().naive_utc();
     if time_partition.is_none() {
         if custom_partition.is_none() {
@@ -219,12 +336,13 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
             }
         }
     } else if custom_partition.is_none() {
-        let data = convert_array_to_object(
+        let mut data = convert_array_to_object(
             body_val.clone(),
             time_partition.clone(),
             time_partition_limit,
             None,
         )?;
+        check_future_events(&stream_name, &mut data, time_partition.as_deref().unwrap())?;
         for value in data {
             parsed_timestamp = get_parsed_timestamp(&value, &time_partition);
             let size = value.to_string().into_bytes().len() as u64;
@@ -241,12 +359,13 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
             .await?;
         }
     } else {
-        let data = convert_array_to_object(
+        let mut data = convert_array_to_object(
             body_val.clone(),
             time_partition.clone(),
             time_partition_limit,
             custom_partition.clone(),
         )?;
+        check_future_events(&stream_name, &mut data, time_partition.as_deref().unwrap())?;
         let custom_partition = custom_partition.unwrap();
         let custom_partition_list = custom_partition.split(',').collect::<Vec<&str>>();
 
@@ -273,6 +392,71 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
     Ok(())
 }
 
+/// Applies the future event policy to events dated more than the max clock skew after now,
+/// they would be stored in partitions of the future
+fn check_future_events(
+    stream_name: &str,
+    data: &mut [Value],
+    time_partition: &str,
+) -> Result<(), PostError> {
+    let Some(max_skew) = CONFIG.parseable.max_clock_skew else {
+        return Ok(());
+    };
+    let policy = CONFIG.parseable.future_event_policy;
+    let count = limit_future_events(data, time_partition, Utc::now(), max_skew, policy);
+    if count == 0 {
+        return Ok(());
+    }
+
+    let action = match policy {
+        FutureEventPolicy::Reject => "rejected",
+        FutureEventPolicy::Clamp => "clamped",
+    };
+    FUTURE_EVENTS
+        .with_label_values(&[stream_name, action])
+        .inc_by(count as u64);
+    if policy == FutureEventPolicy::Reject {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "{count} events have a {time_partition} more than {} seconds in the future",
+            max_skew.as_secs()
+        )));
+    }
+    Ok(())
+}
+
+/// Counts the events dated more than `max_skew` after `now`. Their
+/// timestamp is set to `now` when the policy clamps them.
+fn limit_future_events(
+    data: &mut [Value],
+    time_partition: &str,
+    now: DateTime<Utc>,
+    max_skew: Duration,
+    policy: FutureEventPolicy,
+) -> usize {
+    let Some(limit) = TimeDelta::from_std(max_skew)
+        .ok()
+        .and_then(|skew| now.checked_add_signed(skew))
+    else {
+        return 0;
+    };
+
+    let mut count = 0;
+    for event in data {
+        let timestamp = event
+            .get(time_partition)
+            .and_then(Value::as_str)
+            .and_then(|timestamp| timestamp.parse::<DateTime<Utc>>().ok());
+        if !timestamp.is_some_and(|timestamp| timestamp > limit) {
+            continue;
+        }
+        count += 1;
+        if policy == FutureEventPolicy::Clamp {
+            event[time_partition] = Value::String(now.to_rfc3339_opts(SecondsFormat::Millis, true));
+        }
+    }
+    count
+}
+
 fn get_parsed_timestamp(body: &Value, time_partition: &Option<String>) -> NaiveDateTime {
     let body_timestamp = body.get(&time_partition.clone().unwrap().to_string());
     let parsed_timestamp = body_timestamp
@@ -348,18 +532,24 @@ fn get_stream_schema(
     time_partition: Option<String>,
 ) -> Result<(arrow_array::RecordBatch, bool), PostError> {
     let hash_map = STREAM_INFO.read().unwrap();
-    let schema = hash_map
+    let metadata = hash_map
         .get(&stream_name)
-        .ok_or(PostError::StreamNotFound(stream_name))?
-        .schema
-        .clone();
-    into_event_batch(req, body, schema, static_schema_flag, time_partition)
+        .ok_or(PostError::StreamNotFound(stream_name))?;
+    into_event_batch(
+        req,
+        body,
+        metadata.schema.clone(),
+        metadata.schema_mode,
+        static_schema_flag,
+        time_partition,
+    )
 }
 
 fn into_event_batch(
     req: HttpRequest,
     body: Value,
     schema: HashMap<String, Arc<Field>>,
+    schema_mode: SchemaMode,
     static_schema_flag: Option<String>,
     time_partition: Option<String>,
 ) -> Result<(arrow_array::RecordBatch, bool), PostError> {
@@ -369,8 +559,14 @@ fn into_event_batch(
         data: body,
         tags,
         metadata,
+        schema_mode,
     };
-    let (rb, is_first) = event.into_recordbatch(schema, static_schema_flag, time_partition)?;
+    let (rb, is_first) = event
+        .into_recordbatch(schema, static_schema_flag, time_partition)
+        .map_err(|err| match err.downcast::<SchemaViolation>() {
+            Ok(violation) => PostError::SchemaViolation(violation),
+            Err(err) => PostError::Invalid(err),
+        })?;
     Ok((rb, is_first))
 }
 
@@ -387,6 +583,7 @@ pub async fn create_stream_if_not_exists(stream_name: &str) -> Result<(), PostEr
                 "",
                 "",
                 "",
+                None,
                 Arc::new(Schema::empty()),
             )
             .await?;
@@ -446,6 +643,19 @@ pub enum PostError {
     DashboardError(#[from] DashboardError),
     #[error("Error: {0}")]
     CacheError(#[from] CacheError),
+    #[error("Free space on staging disk is below the configured limit, try again later")]
+    InsufficientDiskSpace,
+    #[error("Uploads to storage are falling behind ingestion, try again later")]
+    UploadBacklog,
+    #[error("Event does not match the schema of the stream: {0}")]
+    SchemaViolation(#[from] SchemaViolation),
+    #[error("Server is shutting down, try again later")]
+    ShuttingDown,
+    #[error("Stream {stream} is ingesting faster than its rate limit, try again in {} ms", .retry_after.as_millis())]
+    RateLimited {
+        stream: String,
+        retry_after: Duration,
+    },
 }
 
 impl actix_web::ResponseError for PostError {
@@ -466,34 +676,47 @@ impl actix_web::ResponseError for PostError {
             PostError::DashboardError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::FiltersError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::CacheError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PostError::InsufficientDiskSpace => StatusCode::SERVICE_UNAVAILABLE,
+            PostError::UploadBacklog => StatusCode::SERVICE_UNAVAILABLE,
+            PostError::SchemaViolation(_) => StatusCode::BAD_REQUEST,
+            PostError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            PostError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
-        actix_web::HttpResponse::build(self.status_code())
-            .insert_header(ContentType::plaintext())
-            .body(self.to_string())
+        let mut response = actix_web::HttpResponse::build(self.status_code());
+        response.insert_header(ContentType::plaintext());
+        if let PostError::RateLimited { retry_after, .. } = self {
+            // whole seconds, rounded up so the client doesn't come back too early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.insert_header((header::RETRY_AFTER, secs.max(1).to_string()));
+        }
+        response.body(self.to_string())
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::{collections::HashMap, sync::Arc};
+    use std::{collections::HashMap, sync::Arc, time::Duration};
 
     use actix_web::test::TestRequest;
     use arrow_array::{
         types::Int64Type, ArrayRef, Float64Array, Int64Array, ListArray, StringArray,
     };
     use arrow_schema::{DataType, Field};
-    use serde_json::json;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use serde_json::{json, Value};
 
     use crate::{
         event,
         handlers::{PREFIX_META, PREFIX_TAGS},
+        option::FutureEventPolicy,
+        storage::schema_mode::SchemaMode,
     };
 
-    use super::into_event_batch;
+    use super::{into_event_batch, limit_future_events, PostError};
 
     trait TestExt {
         fn as_int64_arr(&self) -> &Int64Array;
@@ -515,6 +738,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn throttled_requests_are_told_when_to_retry() {
+        let err = PostError::RateLimited {
+            stream: "app".to_string(),
+            retry_after: Duration::from_millis(1200),
+        };
+        let response = actix_web::ResponseError::error_response(&err);
+        assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response
+                .headers()
+                .get(actix_web::http::header::RETRY_AFTER)
+                .unwrap(),
+            "2"
+        );
+    }
+
     fn fields_to_map(iter: impl Iterator<Item = Field>) -> HashMap<String, Arc<Field>> {
         iter.map(|x| (x.name().clone(), Arc::new(x))).collect()
     }
@@ -532,7 +772,15 @@ mod tests {
             .append_header((PREFIX_META.to_string() + "C", "meta1"))
             .to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch(
+            req,
+            json,
+            HashMap::default(),
+            SchemaMode::Lenient,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 6);
@@ -572,7 +820,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch(
+            req,
+            json,
+            HashMap::default(),
+            SchemaMode::Lenient,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 6);
@@ -604,7 +860,7 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, schema, None, None).unwrap();
+        let (rb, _) = into_event_batch(req, json, schema, SchemaMode::Lenient, None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 5);
@@ -636,7 +892,7 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        assert!(into_event_batch(req, json, schema, None, None).is_err());
+        assert!(into_event_batch(req, json, schema, SchemaMode::Lenient, None, None).is_err());
     }
 
     #[test]
@@ -654,7 +910,7 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, schema, None, None).unwrap();
+        let (rb, _) = into_event_batch(req, json, schema, SchemaMode::Lenient, None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 3);
@@ -666,7 +922,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        assert!(into_event_batch(req, json, HashMap::default(), None, None).is_err())
+        assert!(into_event_batch(
+            req,
+            json,
+            HashMap::default(),
+            SchemaMode::Lenient,
+            None,
+            None
+        )
+        .is_err())
     }
 
     #[test]
@@ -689,7 +953,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch(
+            req,
+            json,
+            HashMap::default(),
+            SchemaMode::Lenient,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -737,7 +1009,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch(
+            req,
+            json,
+            HashMap::default(),
+            SchemaMode::Lenient,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -785,7 +1065,7 @@ mod tests {
         );
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, schema, None, None).unwrap();
+        let (rb, _) = into_event_batch(req, json, schema, SchemaMode::Lenient, None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -814,7 +1094,7 @@ mod tests {
             {
                 "a": 1,
                 "b": "hello",
-                "c": 1
+                "c": "1"
             },
             {
                 "a": 1,
@@ -834,7 +1114,65 @@ mod tests {
             .into_iter(),
         );
 
-        assert!(into_event_batch(req, json, schema, None, None).is_err());
+        assert!(into_event_batch(req, json, schema, SchemaMode::Lenient, None, None).is_err());
+    }
+
+    #[test]
+    fn arr_with_integer_in_float_field() {
+        let json = json!([
+            {
+                "c": 1.24
+            },
+            {
+                "c": 1
+            },
+        ]);
+
+        let req = TestRequest::default().to_http_request();
+
+        let schema = fields_to_map([Field::new("c", DataType::Float64, true)].into_iter());
+
+        for schema_mode in [SchemaMode::Lenient, SchemaMode::Strict] {
+            let (rb, _) = into_event_batch(
+                req.clone(),
+                json.clone(),
+                schema.clone(),
+                schema_mode,
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                rb.column_by_name("c").unwrap().as_float64_arr(),
+                &Float64Array::from(vec![Some(1.24), Some(1.0)])
+            );
+        }
+    }
+
+    #[test]
+    fn strict_stream_rejects_event_with_new_field() {
+        let json = json!({
+            "a": 1,
+            "d": "new"
+        });
+
+        let req = TestRequest::default().to_http_request();
+
+        let schema = fields_to_map([Field::new("a", DataType::Int64, true)].into_iter());
+
+        assert!(into_event_batch(
+            req.clone(),
+            json.clone(),
+            schema.clone(),
+            SchemaMode::Lenient,
+            None,
+            None
+        )
+        .is_ok());
+        assert!(matches!(
+            into_event_batch(req, json, schema, SchemaMode::Strict, None, None),
+            Err(PostError::SchemaViolation(_))
+        ));
     }
 
     #[test]
@@ -862,7 +1200,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch(
+            req,
+            json,
+            HashMap::default(),
+            SchemaMode::Lenient,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 4);
         assert_eq!(rb.num_columns(), 7);
@@ -901,4 +1247,53 @@ mod tests {
             &ListArray::from_iter_primitive::<Int64Type, _, _>(c_b)
         );
     }
+
+    fn events_around(now: DateTime<Utc>) -> Vec<Value> {
+        [-3600, 30, 7200]
+            .into_iter()
+            .map(|secs| json!({"time": (now + TimeDelta::seconds(secs)).to_rfc3339(), "a": 1}))
+            .collect()
+    }
+
+    #[test]
+    fn future_events_are_rejected() {
+        let now = Utc::now();
+        let mut events = events_around(now);
+        let original = events.clone();
+
+        let count = limit_future_events(
+            &mut events,
+            "time",
+            now,
+            Duration::from_secs(60),
+            FutureEventPolicy::Reject,
+        );
+
+        assert_eq!(count, 1);
+        assert_eq!(events, original);
+    }
+
+    #[test]
+    fn future_events_are_clamped_to_now() {
+        let now = Utc::now();
+        let mut events = events_around(now);
+        let original = events.clone();
+
+        let count = limit_future_events(
+            &mut events,
+            "time",
+            now,
+            Duration::from_secs(60),
+            FutureEventPolicy::Clamp,
+        );
+
+        assert_eq!(count, 1);
+        assert_eq!(events[..2], original[..2]);
+        let clamped = events[2]["time"].as_str().unwrap();
+        assert_eq!(
+            clamped.parse::<DateTime<Utc>>().unwrap().timestamp_millis(),
+            now.timestamp_millis()
+        );
+        assert_eq!(events[2]["a"], 1);
+    }
 }
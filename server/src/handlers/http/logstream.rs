@@ -20,14 +20,31 @@ use self::error::{CreateStreamError, StreamError};
 use super::base_path_without_preceding_slash;
 use super::cluster::utils::{merge_quried_stats, IngestionStats, QueriedStats, StorageStats};
 use super::cluster::{fetch_stats_from_ingestors, INTERNAL_STREAM_NAME};
+use super::query::{into_query, Query};
 use crate::alerts::Alerts;
+use crate::event::rate_limit::{RateLimit, RATE_LIMITER};
+use crate::export::{self, ExportRequest};
 use crate::handlers::{
-    CUSTOM_PARTITION_KEY, STATIC_SCHEMA_FLAG, TIME_PARTITION_KEY, TIME_PARTITION_LIMIT_KEY,
+    CUSTOM_PARTITION_KEY, PARTITION_TIME_ZONE_KEY, STATIC_SCHEMA_FLAG, TIME_PARTITION_KEY,
+    TIME_PARTITION_LIMIT_KEY,
 };
 use crate::metadata::STREAM_INFO;
 use crate::option::{Mode, CONFIG};
+use crate::query::QUERY_SESSION;
 use crate::static_schema::{convert_static_schema_to_arrow_schema, StaticSchema};
-use crate::storage::{retention::Retention, LogStream, StorageDir, StreamInfo};
+use crate::storage::{
+    column_access::ColumnAccess,
+    file_sizes,
+    partition_granularity::PartitionGranularity,
+    retention::Retention,
+    rollup::Rollup,
+    schema_mode::SchemaMode,
+    sort_order::{self, SortColumn},
+    statistics_level::StatisticsLevel,
+    tiering, trash,
+    upload_backlog::UPLOAD_BACKLOG,
+    LogStream, StorageDir, StreamInfo,
+};
 use crate::{
     catalog::{self, remove_manifest_from_snapshot},
     event, stats,
@@ -37,16 +54,24 @@ use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, Responder};
 use arrow_schema::{Field, Schema};
 use bytes::Bytes;
-use chrono::Utc;
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
+use futures::StreamExt;
 use itertools::Itertools;
+use relative_path::RelativePath;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
 use std::num::NonZeroU32;
 use std::sync::Arc;
 
+// files of a partition listed per request by default and at most
+const DEFAULT_FILES_PAGE_SIZE: usize = 100;
+const MAX_FILES_PAGE_SIZE: usize = 1000;
+
 pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
     if !metadata::STREAM_INFO.stream_exists(&stream_name) {
         return Err(StreamError::StreamNotFound(stream_name));
     }
@@ -54,7 +79,14 @@ pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
         Mode::Query | Mode::All => {
             let objectstore = CONFIG.storage().get_object_store();
 
-            objectstore.delete_stream(&stream_name).await?;
+            trash::delete_stream(&*objectstore, &stream_name).await?;
+            if let Some(cold_store) = tiering::cold_store() {
+                if let Err(err) = trash::delete_stream(&*cold_store, &stream_name).await {
+                    log::warn!(
+                        "failed to delete cold partitions of stream {stream_name} due to {err:?}"
+                    );
+                }
+            }
             let stream_dir = StorageDir::new(&stream_name);
             if fs::remove_dir_all(&stream_dir.data_path).is_err() {
                 log::warn!(
@@ -80,12 +112,17 @@ pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
                 // delete the stream
                 super::cluster::send_stream_delete_request(&url, ingestor.clone()).await?;
             }
+
+            super::alias::remove_aliases_of(&stream_name).await?;
         }
         _ => {}
     }
 
     metadata::STREAM_INFO.delete_stream(&stream_name);
     event::STREAM_WRITERS.delete_stream(&stream_name);
+    catalog::invalidate_column_summary(&stream_name);
+    UPLOAD_BACKLOG.remove_stream(&stream_name);
+    RATE_LIMITER.remove_stream(&stream_name);
     stats::delete_stats(&stream_name, "json").unwrap_or_else(|e| {
         log::warn!("failed to delete stats for stream {}: {:?}", stream_name, e)
     });
@@ -136,6 +173,7 @@ pub async fn retention_cleanup(
 }
 
 pub async fn list(_: HttpRequest) -> impl Responder {
+    // aliases are only names to query by, they are listed by the alias endpoint
     let res: Vec<LogStream> = STREAM_INFO
         .list_streams()
         .into_iter()
@@ -147,6 +185,7 @@ pub async fn list(_: HttpRequest) -> impl Responder {
 
 pub async fn schema(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
     let schema = STREAM_INFO.schema(&stream_name)?;
     Ok((web::Json(schema), StatusCode::OK))
 }
@@ -241,6 +280,23 @@ pub async fn put_stream(req: HttpRequest, body: Bytes) -> Result<impl Responder,
             });
         }
     }
+    let mut partition_time_zone: Option<Tz> = None;
+    if let Some((_, zone)) = req
+        .headers()
+        .iter()
+        .find(|&(key, _)| key == PARTITION_TIME_ZONE_KEY)
+    {
+        let zone = zone.to_str().unwrap_or_default();
+        match zone.parse() {
+            Ok(zone) => partition_time_zone = Some(zone),
+            Err(_) => {
+                return Err(StreamError::Custom {
+                    msg: format!("{zone} is not a valid IANA time zone name"),
+                    status: StatusCode::BAD_REQUEST,
+                })
+            }
+        }
+    }
 
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
     let mut schema = Arc::new(Schema::empty());
@@ -253,6 +309,12 @@ pub async fn put_stream(req: HttpRequest, body: Bytes) -> Result<impl Responder,
             status: StatusCode::BAD_REQUEST,
         });
     }
+    if metadata::is_stream_alias(&stream_name) {
+        return Err(StreamError::Custom {
+            msg: format!("{stream_name} is already used as an alias for another log stream"),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
 
     if !body.is_empty() && static_schema_flag == "true" {
         let static_schema: StaticSchema = serde_json::from_slice(&body)?;
@@ -285,6 +347,7 @@ pub async fn put_stream(req: HttpRequest, body: Bytes) -> Result<impl Responder,
         time_partition_in_days,
         custom_partition,
         static_schema_flag,
+        partition_time_zone,
         schema,
     )
     .await?;
@@ -351,6 +414,7 @@ pub async fn put_alert(
 
 pub async fn get_retention(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
     let objectstore = CONFIG.storage().get_object_store();
 
     if !objectstore.stream_exists(&stream_name).await? {
@@ -390,6 +454,485 @@ pub async fn put_retention(
     ))
 }
 
+pub async fn get_sort_order(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let sort_order = STREAM_INFO.get_sort_order(&stream_name)?;
+
+    Ok((web::Json(sort_order), StatusCode::OK))
+}
+
+/// Sets the order rows are sorted by in parquet files of the stream. Files already
+/// written keep their order, an empty list restores sorting by the time partition.
+pub async fn put_sort_order(
+    req: HttpRequest,
+    body: web::Json<Vec<SortColumn>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let sort_order = body.into_inner();
+
+    let schema = STREAM_INFO.schema(&stream_name)?;
+    sort_order::validate(&sort_order, &schema).map_err(StreamError::InvalidSortOrder)?;
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.sort_order.clone_from(&sort_order);
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_sort_order(&stream_name, sort_order)?;
+    Ok((
+        format!("set sort order for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_column_access(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let column_access = STREAM_INFO.get_column_access(&stream_name)?;
+
+    Ok((web::Json(column_access), StatusCode::OK))
+}
+
+/// Sets the columns of the stream that queries can read, null permits all columns
+pub async fn put_column_access(
+    req: HttpRequest,
+    body: web::Json<Option<ColumnAccess>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let column_access = body.into_inner();
+
+    if let Some(column_access) = &column_access {
+        let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
+        column_access
+            .validate(time_partition.as_deref())
+            .map_err(StreamError::InvalidColumnAccess)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.column_access.clone_from(&column_access);
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_column_access(&stream_name, column_access)?;
+    Ok((
+        format!("set column access for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_statistics_level(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let statistics_level = STREAM_INFO.get_statistics_level(&stream_name)?;
+
+    Ok((web::Json(statistics_level), StatusCode::OK))
+}
+
+/// Sets the column statistics written to parquet files of the stream, files already
+/// written keep their statistics. Less statistics save write CPU but prune less on query.
+pub async fn put_statistics_level(
+    req: HttpRequest,
+    body: web::Json<StatisticsLevel>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let statistics_level = body.into_inner();
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.statistics_level = statistics_level;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_statistics_level(&stream_name, statistics_level)?;
+    Ok((
+        format!("set statistics level for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_schema_mode(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let schema_mode = STREAM_INFO.get_schema_mode(&stream_name)?;
+
+    Ok((web::Json(schema_mode), StatusCode::OK))
+}
+
+/// Sets whether events may add fields to the schema of the stream or are rejected
+/// unless they match it
+pub async fn put_schema_mode(
+    req: HttpRequest,
+    body: web::Json<SchemaMode>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let schema_mode = body.into_inner();
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.schema_mode = schema_mode;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_schema_mode(&stream_name, schema_mode)?;
+    Ok((
+        format!("set schema mode for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_partition_granularity(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let partition_granularity = STREAM_INFO.get_partition_granularity(&stream_name)?;
+
+    Ok((web::Json(partition_granularity), StatusCode::OK))
+}
+
+/// Sets the finest time partition in the keys of data files uploaded from now on,
+/// files uploaded before keep their layout
+pub async fn put_partition_granularity(
+    req: HttpRequest,
+    body: web::Json<PartitionGranularity>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let partition_granularity = body.into_inner();
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.partition_granularity = partition_granularity;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_partition_granularity(&stream_name, partition_granularity)?;
+    Ok((
+        format!("set partition granularity for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_rollup(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let rollup = STREAM_INFO.get_rollup(&stream_name)?;
+
+    Ok((web::Json(rollup), StatusCode::OK))
+}
+
+/// Sets the rollup materialized for the stream, or removes it when the body is null.
+/// A new or changed rollup starts covering from the last settled minute, the rollup
+/// stream keeps the rows materialized before.
+pub async fn put_rollup(
+    req: HttpRequest,
+    body: web::Json<Option<Rollup>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let mut rollup = body.into_inner();
+
+    if let Some(rollup) = &mut rollup {
+        let schema = STREAM_INFO.schema(&stream_name)?;
+        let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
+        rollup
+            .validate(&schema, time_partition.as_deref())
+            .map_err(StreamError::InvalidRollup)?;
+        rollup.coverage = None;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.rollup.clone_from(&rollup);
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_rollup(&stream_name, rollup)?;
+    Ok((
+        format!("set rollup for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_rate_limit(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let rate_limit = STREAM_INFO.get_rate_limit(&stream_name)?;
+
+    Ok((web::Json(rate_limit), StatusCode::OK))
+}
+
+/// Sets the records and bytes per second ingested into the stream, or falls back to
+/// the server default when the body is null
+pub async fn put_rate_limit(
+    req: HttpRequest,
+    body: web::Json<Option<RateLimit>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let rate_limit = body.into_inner();
+    if rate_limit
+        .is_some_and(|limit| limit.records_per_sec == Some(0) || limit.bytes_per_sec == Some(0))
+    {
+        return Err(StreamError::Custom {
+            msg: "rate limits must be greater than zero".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.rate_limit = rate_limit;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_rate_limit(&stream_name, rate_limit)?;
+    Ok((
+        format!("set rate limit for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn repair_catalog(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let objectstore = CONFIG.storage().get_object_store();
+
+    if !objectstore.stream_exists(&stream_name).await? {
+        return Err(StreamError::StreamNotFound(stream_name.to_string()));
+    }
+
+    let files_processed = catalog::rebuild_catalog(objectstore, &stream_name).await?;
+
+    Ok((
+        web::Json(serde_json::json!({ "files_processed": files_processed })),
+        StatusCode::OK,
+    ))
+}
+
+/// Moves the latest trashed copy of a deleted stream back and loads it
+pub async fn restore(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    if metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::Custom {
+            msg: format!("log stream {stream_name} already exists"),
+            status: StatusCode::CONFLICT,
+        });
+    }
+
+    let objectstore = CONFIG.storage().get_object_store();
+    let Some(trashed) = trash::find_trashed_stream(&*objectstore, &stream_name).await? else {
+        return Err(StreamError::StreamNotFound(stream_name));
+    };
+    trash::restore(&*objectstore, &trashed).await?;
+    if let Some(cold_store) = tiering::cold_store() {
+        let restored = match trash::find_trashed_stream(&*cold_store, &stream_name).await {
+            Ok(Some(trashed)) => trash::restore(&*cold_store, &trashed).await.map(|_| ()),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = restored {
+            log::warn!("failed to restore cold partitions of stream {stream_name} due to {err:?}");
+        }
+    }
+
+    metadata::STREAM_INFO
+        .upsert_stream_info(
+            &*objectstore,
+            LogStream {
+                name: stream_name.clone(),
+            },
+        )
+        .await
+        .map_err(|err| StreamError::Anyhow(err.into()))?;
+
+    Ok((format!("log stream {stream_name} restored"), StatusCode::OK))
+}
+
+/// Histogram of the sizes of the parquet files of the stream, listed from the store
+pub async fn get_file_sizes(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let histogram = file_sizes::histogram(storage, &stream_name).await?;
+
+    Ok((web::Json(histogram), StatusCode::OK))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct FlushQuery {
+    stream: Option<String>,
+}
+
+/// Flushes and uploads staged data now, of one stream or of all of them
+pub async fn flush(query: web::Query<FlushQuery>) -> Result<impl Responder, StreamError> {
+    if CONFIG.parseable.mode == Mode::Query {
+        return Err(StreamError::Custom {
+            msg: "query server does not stage data".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let streams = match query.into_inner().stream {
+        Some(stream) if !STREAM_INFO.stream_exists(&stream) => {
+            return Err(StreamError::StreamNotFound(stream));
+        }
+        Some(stream) => vec![stream],
+        None => STREAM_INFO.list_streams(),
+    };
+
+    let files_uploaded = CONFIG
+        .storage()
+        .get_object_store()
+        .sync_streams(&streams, true)
+        .await?;
+
+    Ok((
+        web::Json(serde_json::json!({ "files_uploaded": files_uploaded })),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn export(
+    req: HttpRequest,
+    body: web::Json<ExportRequest>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let request = body.into_inner();
+    let query = Query {
+        query: format!("SELECT * FROM \"{stream_name}\""),
+        start_time: request.start_time.clone(),
+        end_time: request.end_time.clone(),
+        send_null: false,
+        fields: false,
+        filter_tags: None,
+        as_of: None,
+    };
+    let query = into_query(&query, &QUERY_SESSION.state())
+        .await
+        .map_err(|err| StreamError::Custom {
+            msg: err.to_string(),
+            status: StatusCode::BAD_REQUEST,
+        })?;
+
+    let target = CONFIG.storage().get_object_store_at(&request.location);
+    let id = export::start(stream_name, query, target, request);
+
+    Ok((
+        web::Json(serde_json::json!({ "id": id.to_string() })),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+pub async fn get_export(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let id = req.match_info().get("id").unwrap();
+
+    match export::progress(id).filter(|progress| progress.stream == stream_name) {
+        Some(progress) => Ok((web::Json(progress), StatusCode::OK)),
+        None => Err(StreamError::Custom {
+            msg: format!("Export {id} not found for stream {stream_name}"),
+            status: StatusCode::NOT_FOUND,
+        }),
+    }
+}
+
+/// Files of one date partition of the stream with their size, row count and per column
+/// min/max, from the manifests. Paged with the `offset` and `limit` query parameters.
+pub async fn get_partition_files(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+    let date = req.match_info().get("date").unwrap();
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| StreamError::Custom {
+        msg: format!("Invalid date {date}, expected YYYY-MM-DD"),
+        status: StatusCode::BAD_REQUEST,
+    })?;
+    let params =
+        web::Query::<HashMap<String, usize>>::from_query(req.query_string()).map_err(|err| {
+            StreamError::Custom {
+                msg: format!("Invalid pagination parameters: {err}"),
+                status: StatusCode::BAD_REQUEST,
+            }
+        })?;
+    let offset = params.get("offset").copied().unwrap_or(0);
+    let limit = params
+        .get("limit")
+        .copied()
+        .unwrap_or(DEFAULT_FILES_PAGE_SIZE)
+        .clamp(1, MAX_FILES_PAGE_SIZE);
+
+    let storage = CONFIG.storage().get_object_store();
+    let files = catalog::partition_files(storage.clone(), &stream_name, date).await?;
+    // key value metadata is read from the footer of each file of the page
+    let metadata = futures::stream::iter(files.iter().skip(offset).take(limit))
+        .map(|(file, tier)| {
+            let storage = storage.clone();
+            async move {
+                let store = tiering::store_of(*tier, storage).ok()?;
+                store
+                    .get_parquet_key_value_metadata(RelativePath::new(&file.file_path))
+                    .await
+                    .map_err(|err| {
+                        log::warn!("Failed to read metadata of {}: {err}", file.file_path)
+                    })
+                    .ok()
+            }
+        })
+        .buffered(CONFIG.parseable.query_listing_concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    let page = files
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .zip(metadata)
+        .map(|((file, _), metadata)| {
+            let columns = file
+                .columns
+                .iter()
+                .map(|column| {
+                    let (min, max) = column
+                        .stats
+                        .as_ref()
+                        .map_or((Value::Null, Value::Null), |stats| stats.min_max_json());
+                    serde_json::json!({
+                        "name": column.name,
+                        "min": min,
+                        "max": max,
+                        "null_count": column.null_count,
+                    })
+                })
+                .collect_vec();
+            serde_json::json!({
+                "key": file.file_path,
+                "size": file.file_size,
+                "rows": file.num_rows,
+                "columns": columns,
+                "metadata": metadata,
+            })
+        })
+        .collect_vec();
+
+    Ok((
+        web::Json(serde_json::json!({
+            "date": date.to_string(),
+            "total": files.len(),
+            "offset": offset,
+            "limit": limit,
+            "files": page,
+        })),
+        StatusCode::OK,
+    ))
+}
+
 pub async fn get_cache_enabled(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
@@ -485,6 +1028,7 @@ pub async fn put_enable_cache(
 
 pub async fn get_stats(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
 
     if !metadata::STREAM_INFO.stream_exists(&stream_name) {
         return Err(StreamError::StreamNotFound(stream_name));
@@ -554,7 +1098,30 @@ pub async fn get_stats(req: HttpRequest) -> Result<impl Responder, StreamError>
         stats
     };
 
-    let stats = serde_json::to_value(stats)?;
+    drop(hash_map);
+    let mut stats = serde_json::to_value(stats)?;
+
+    // per column statistics are read from all manifests of the stream, so only on request
+    let with_columns = web::Query::<HashMap<String, bool>>::from_query(req.query_string())
+        .is_ok_and(|params| params.get("columns").copied().unwrap_or(false));
+    if with_columns {
+        let storage = CONFIG.storage().get_object_store();
+        let columns = catalog::column_summary(storage, &stream_name)
+            .await?
+            .iter()
+            .map(|column| {
+                serde_json::json!({
+                    "name": column.name,
+                    "uncompressed_size": column.uncompressed_size,
+                    "compressed_size": column.compressed_size,
+                    "compression_ratio": column.compression_ratio(),
+                    "null_count": column.null_count,
+                    "stats": column.stats,
+                })
+            })
+            .collect_vec();
+        stats["columns"] = Value::Array(columns);
+    }
 
     Ok((web::Json(stats), StatusCode::OK))
 }
@@ -588,6 +1155,7 @@ pub async fn create_stream(
     time_partition_limit: &str,
     custom_partition: &str,
     static_schema_flag: &str,
+    partition_time_zone: Option<Tz>,
     schema: Arc<Schema>,
 ) -> Result<(), CreateStreamError> {
     // fail to proceed if invalid stream name
@@ -604,6 +1172,7 @@ pub async fn create_stream(
             time_partition_limit,
             custom_partition,
             static_schema_flag,
+            partition_time_zone,
             schema.clone(),
         )
         .await
@@ -635,6 +1204,7 @@ pub async fn create_stream(
         time_partition_limit.to_string(),
         custom_partition.to_string(),
         static_schema_flag.to_string(),
+        partition_time_zone,
         static_schema,
     );
 
@@ -643,6 +1213,7 @@ pub async fn create_stream(
 
 pub async fn get_stream_info(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let stream_name = metadata::resolve_stream_alias(&stream_name);
     if !metadata::STREAM_INFO.stream_exists(&stream_name) {
         return Err(StreamError::StreamNotFound(stream_name));
     }
@@ -673,6 +1244,16 @@ pub async fn get_stream_info(req: HttpRequest) -> Result<impl Responder, StreamE
         custom_partition: stream_meta.custom_partition.clone(),
         cache_enabled: stream_meta.cache_enabled,
         static_schema_flag: stream_meta.static_schema_flag.clone(),
+        partition_time_zone: stream_meta
+            .partition_time_zone
+            .map(|zone| zone.name().to_string()),
+        sort_order: stream_meta.sort_order.clone(),
+        column_access: stream_meta.column_access.clone(),
+        statistics_level: stream_meta.statistics_level,
+        schema_mode: stream_meta.schema_mode,
+        partition_granularity: stream_meta.partition_granularity,
+        rollup: stream_meta.rollup.clone(),
+        rate_limit: stream_meta.rate_limit,
     };
 
     // get the other info from
@@ -746,6 +1327,12 @@ pub mod error {
         InvalidAlertMessage(String, String),
         #[error("failed to set retention configuration due to err: {0}")]
         InvalidRetentionConfig(serde_json::Error),
+        #[error("invalid sort order: {0}")]
+        InvalidSortOrder(String),
+        #[error("invalid column access: {0}")]
+        InvalidColumnAccess(String),
+        #[error("invalid rollup: {0}")]
+        InvalidRollup(String),
         #[error("{msg}")]
         Custom { msg: String, status: StatusCode },
         #[error("Error: {0}")]
@@ -776,6 +1363,9 @@ pub mod error {
                 StreamError::InvalidAlert(_) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidAlertMessage(_, _) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidRetentionConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidSortOrder(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidColumnAccess(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidRollup(_) => StatusCode::BAD_REQUEST,
                 StreamError::SerdeError(_) => StatusCode::BAD_REQUEST,
                 StreamError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
                 StreamError::Network(err) => {
@@ -807,8 +1397,8 @@ pub mod error {
 #[cfg(test)]
 mod tests {
     use crate::handlers::http::logstream::error::StreamError;
-    use crate::handlers::http::logstream::get_stats;
-    use actix_web::test::TestRequest;
+    use crate::handlers::http::logstream::{flush, get_stats, FlushQuery};
+    use actix_web::{body::to_bytes, test::TestRequest, web, Responder};
     use anyhow::bail;
 
     #[actix_web::test]
@@ -829,4 +1419,32 @@ mod tests {
             _ => bail!("expected StreamNotFound error"),
         }
     }
+
+    #[actix_web::test]
+    async fn flush_stream_not_found_error_for_unknown_logstream() -> anyhow::Result<()> {
+        let query = web::Query::<FlushQuery>::from_query("stream=unknown")?;
+
+        match flush(query).await {
+            Err(StreamError::StreamNotFound(stream)) if stream == "unknown" => Ok(()),
+            _ => bail!("expected StreamNotFound error"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn flush_without_staged_data_uploads_nothing() -> anyhow::Result<()> {
+        let query = web::Query::<FlushQuery>::from_query("")?;
+        let req = TestRequest::default().to_http_request();
+
+        let Ok(responder) = flush(query).await else {
+            bail!("expected flush to succeed");
+        };
+        let response = responder.respond_to(&req);
+        assert!(response.status().is_success());
+        let Ok(body) = to_bytes(response.into_body()).await else {
+            bail!("expected a response body");
+        };
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["files_uploaded"], 0);
+        Ok(())
+    }
 }
@@ -22,7 +22,7 @@ use std::future::{ready, Ready};
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
     error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized},
-    http::header::{self, HeaderName},
+    http::header::{self, HeaderName, HeaderValue},
     Error, Route,
 };
 use futures_util::future::LocalBoxFuture;
@@ -43,6 +43,27 @@ use crate::{
 
 use serde::{Deserialize, Serialize};
 
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    // correlation id of the request being served, set by the RequestId middleware
+    static REQUEST_ID: Option<String>;
+}
+
+/// Correlation id of the request whose handler is currently running, if the client sent one
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok().flatten()
+}
+
+/// Request ids are written to logs as is, so only short ids of URL safe characters are used
+fn is_valid_request_id(id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Message {
     #[serde(rename = "commonAttributes")]
@@ -358,3 +379,106 @@ where
         }
     }
 }
+
+/// Makes the `X-Request-Id` header of incoming requests available to code running in
+/// their handlers through [`current_request_id`] and echoes it back in the response.
+pub struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddleware { service }))
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid_request_id(id))
+            .map(str::to_owned);
+        let fut = REQUEST_ID.scope(request_id.clone(), self.service.call(req));
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+
+    use super::{current_request_id, RequestId, REQUEST_ID_HEADER};
+
+    #[actix_web::test]
+    async fn only_valid_request_ids_reach_handlers_and_are_echoed() {
+        let app = test::init_service(App::new().wrap(RequestId).route(
+            "/",
+            web::get().to(|| async { current_request_id().unwrap_or_default() }),
+        ))
+        .await;
+        let call = |id: Option<&str>| {
+            let mut req = test::TestRequest::get().uri("/");
+            if let Some(id) = id {
+                req = req.insert_header((REQUEST_ID_HEADER, id));
+            }
+            test::call_service(&app, req.to_request())
+        };
+
+        let res = call(Some("3f2a-01:b.c_d")).await;
+        assert_eq!(
+            res.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "3f2a-01:b.c_d"
+        );
+        assert_eq!(test::read_body(res).await, "3f2a-01:b.c_d");
+
+        // ids that could forge log fields or flood the logs are dropped
+        let too_long = "a".repeat(129);
+        for id in [
+            None,
+            Some(""),
+            Some("3f2a key=forged"),
+            Some(too_long.as_str()),
+        ] {
+            let res = call(id).await;
+            assert!(res.headers().get(REQUEST_ID_HEADER).is_none(), "{id:?}");
+            assert_eq!(test::read_body(res).await, "", "{id:?}");
+        }
+
+        // outside of a request there is none
+        assert_eq!(current_request_id(), None);
+    }
+}
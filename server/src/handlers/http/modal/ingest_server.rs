@@ -20,7 +20,7 @@ use crate::analytics;
 use crate::banner;
 use crate::handlers::airplane;
 use crate::handlers::http::logstream;
-use crate::handlers::http::middleware::RouteExt;
+use crate::handlers::http::middleware::{RequestId, RouteExt};
 use crate::localcache::LocalCacheManager;
 use crate::metadata;
 use crate::metrics;
@@ -28,6 +28,7 @@ use crate::migration;
 use crate::migration::metadata_migration::migrate_ingester_metadata;
 use crate::rbac;
 use crate::rbac::role::Action;
+use crate::shutdown;
 use crate::storage;
 use crate::storage::object_storage::ingestor_metadata_path;
 use crate::storage::object_storage::parseable_json_path;
@@ -85,22 +86,27 @@ impl ParseableServer for IngestServer {
             App::new()
                 .wrap(prometheus.clone())
                 .configure(|config| IngestServer::configure_routes(config, None))
+                .wrap(RequestId)
                 .wrap(actix_web::middleware::Logger::default())
                 .wrap(actix_web::middleware::Compress::default())
                 .wrap(cross_origin_config())
         };
 
         // concurrent workers equal to number of logical cores
-        let http_server = HttpServer::new(create_app_fn).workers(num_cpus::get());
+        let http_server = HttpServer::new(create_app_fn)
+            .workers(num_cpus::get())
+            // signals are handled by shutdown::stop_on_signal
+            .disable_signals();
 
-        if let Some(config) = ssl {
+        let server = if let Some(config) = ssl {
             http_server
                 .bind_rustls_0_22(&CONFIG.parseable.address, config)?
                 .run()
-                .await?;
         } else {
-            http_server.bind(&CONFIG.parseable.address)?.run().await?;
-        }
+            http_server.bind(&CONFIG.parseable.address)?.run()
+        };
+        tokio::spawn(shutdown::stop_on_signal(server.handle()));
+        server.await?;
 
         Ok(())
     }
@@ -108,6 +114,7 @@ impl ParseableServer for IngestServer {
     /// implement the init method will just invoke the initialize method
     async fn init(&self) -> anyhow::Result<()> {
         self.validate()?;
+        CONFIG.check_bucket().await?;
 
         // check for querier state. Is it there, or was it there in the past
         self.check_querier_state().await?;
@@ -143,8 +150,10 @@ impl IngestServer {
             // Base path "{url}/api/v1"
             web::scope(&base_path())
                 .service(Server::get_ingest_factory())
+                .service(Server::get_otel_webscope())
                 .service(Self::logstream_api())
                 .service(Server::get_about_factory())
+                .service(Server::get_flush_factory())
                 .service(Self::analytics_factory())
                 .service(Server::get_liveness_factory())
                 .service(Server::get_readiness_factory()),
@@ -351,11 +360,15 @@ impl IngestServer {
         loop {
             tokio::select! {
                 e = &mut app => {
-                    // actix server finished .. stop other threads and stop the server
+                    // actix server finished .. flush staged data, stop other threads and stop the server
+                    let drained = shutdown::drain().await;
                     remote_sync_inbox.send(()).unwrap_or(());
                     localsync_inbox.send(()).unwrap_or(());
                     localsync_handler.join().unwrap_or(());
-                    remote_sync_handler.join().unwrap_or(());
+                    // a sync stuck past the shutdown timeout is left behind
+                    if drained {
+                        remote_sync_handler.join().unwrap_or(());
+                    }
                     return e
                 },
                 _ = &mut localsync_outbox => {
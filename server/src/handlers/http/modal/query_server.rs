@@ -16,10 +16,10 @@
  *
  */
 
-use crate::handlers::airplane;
 use crate::handlers::http::cluster::{self, init_cluster_metrics_schedular};
-use crate::handlers::http::middleware::RouteExt;
+use crate::handlers::http::middleware::{RequestId, RouteExt};
 use crate::handlers::http::{base_path, cross_origin_config, API_BASE_PATH, API_VERSION};
+use crate::handlers::{airplane, postgres};
 
 use crate::rbac::role::Action;
 use crate::sync;
@@ -68,6 +68,7 @@ impl ParseableServer for QueryServer {
             App::new()
                 .wrap(prometheus.clone())
                 .configure(|config| QueryServer::configure_routes(config, oidc_client.clone()))
+                .wrap(RequestId)
                 .wrap(actix_web::middleware::Logger::default())
                 .wrap(actix_web::middleware::Compress::default())
                 .wrap(cross_origin_config())
@@ -97,6 +98,7 @@ impl ParseableServer for QueryServer {
         banner::print(&CONFIG, &metadata).await;
         // initialize the rbac map
         rbac::map::init(&metadata);
+        metadata::load_stream_aliases(&metadata);
         // keep metadata info in mem
         metadata.set_global();
         self.initialize().await
@@ -132,6 +134,7 @@ impl QueryServer {
                     .service(Server::get_llm_webscope())
                     .service(Server::get_oauth_webscope(oidc_client))
                     .service(Server::get_user_role_webscope())
+                    .service(Server::get_alias_webscope())
                     .service(Self::get_cluster_web_scope()),
             )
             .service(Server::get_generated());
@@ -202,6 +205,7 @@ impl QueryServer {
             sync::object_store_sync();
 
         tokio::spawn(airplane::server());
+        tokio::spawn(postgres::server());
         let app = self.start(prometheus, CONFIG.parseable.openid.clone());
 
         tokio::pin!(app);
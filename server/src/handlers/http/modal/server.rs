@@ -18,6 +18,7 @@
 
 use crate::analytics;
 use crate::banner;
+use crate::catalog;
 use crate::handlers;
 use crate::handlers::http::about;
 use crate::handlers::http::base_path;
@@ -33,6 +34,7 @@ use crate::metadata;
 use crate::metrics;
 use crate::migration;
 use crate::rbac;
+use crate::shutdown;
 use crate::storage;
 use crate::sync;
 use crate::users::dashboards::DASHBOARDS;
@@ -49,8 +51,8 @@ use async_trait::async_trait;
 
 use crate::{
     handlers::http::{
-        self, cross_origin_config, ingest, llm, logstream,
-        middleware::{DisAllowRootUser, RouteExt},
+        self, alias, cross_origin_config, ingest, llm, logstream,
+        middleware::{DisAllowRootUser, RequestId, RouteExt},
         oidc, role, MAX_EVENT_PAYLOAD_SIZE,
     },
     option::CONFIG,
@@ -87,6 +89,7 @@ impl ParseableServer for Server {
             App::new()
                 .wrap(prometheus.clone())
                 .configure(|cfg| Server::configure_routes(cfg, oidc_client.clone()))
+                .wrap(RequestId)
                 .wrap(actix_web::middleware::Logger::default())
                 .wrap(actix_web::middleware::Compress::default())
                 .wrap(cross_origin_config())
@@ -98,15 +101,19 @@ impl ParseableServer for Server {
         )?;
 
         // concurrent workers equal to number of cores on the cpu
-        let http_server = HttpServer::new(create_app_fn).workers(num_cpus::get());
-        if let Some(config) = ssl {
+        let http_server = HttpServer::new(create_app_fn)
+            .workers(num_cpus::get())
+            // signals are handled by shutdown::stop_on_signal
+            .disable_signals();
+        let server = if let Some(config) = ssl {
             http_server
                 .bind_rustls_0_22(&CONFIG.parseable.address, config)?
                 .run()
-                .await?;
         } else {
-            http_server.bind(&CONFIG.parseable.address)?.run().await?;
-        }
+            http_server.bind(&CONFIG.parseable.address)?.run()
+        };
+        tokio::spawn(shutdown::stop_on_signal(server.handle()));
+        server.await?;
 
         Ok(())
     }
@@ -120,6 +127,7 @@ impl ParseableServer for Server {
         let metadata = storage::resolve_parseable_metadata().await?;
         banner::print(&CONFIG, &metadata).await;
         rbac::map::init(&metadata);
+        metadata::load_stream_aliases(&metadata);
         metadata.set_global();
         self.initialize().await?;
         Ok(())
@@ -140,16 +148,19 @@ impl Server {
                     .service(Self::get_query_factory())
                     .service(Self::get_cache_webscope())
                     .service(Self::get_ingest_factory())
+                    .service(Self::get_otel_webscope())
                     .service(Self::get_liveness_factory())
                     .service(Self::get_readiness_factory())
                     .service(Self::get_about_factory())
+                    .service(Self::get_flush_factory())
                     .service(Self::get_logstream_webscope())
                     .service(Self::get_user_webscope())
                     .service(Self::get_dashboards_webscope())
                     .service(Self::get_filters_webscope())
                     .service(Self::get_llm_webscope())
                     .service(Self::get_oauth_webscope(oidc_client))
-                    .service(Self::get_user_role_webscope()),
+                    .service(Self::get_user_role_webscope())
+                    .service(Self::get_alias_webscope()),
             )
             .service(Self::get_generated());
     }
@@ -332,6 +343,159 @@ impl Server {
                                     .to(logstream::get_cache_enabled)
                                     .authorize_for_stream(Action::GetCacheEnabled),
                             ),
+                    )
+                    .service(
+                        web::resource("/sort-order")
+                            // PUT "/logstream/{logstream}/sort-order" ==> Set sort order of parquet files for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_sort_order)
+                                    .authorize_for_stream(Action::PutSortOrder),
+                            )
+                            // GET "/logstream/{logstream}/sort-order" ==> Get sort order of parquet files for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_sort_order)
+                                    .authorize_for_stream(Action::GetSortOrder),
+                            ),
+                    )
+                    .service(
+                        web::resource("/column-access")
+                            // PUT "/logstream/{logstream}/column-access" ==> Set columns that queries can read for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_column_access)
+                                    .authorize_for_stream(Action::PutColumnAccess),
+                            )
+                            // GET "/logstream/{logstream}/column-access" ==> Get columns that queries can read for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_column_access)
+                                    .authorize_for_stream(Action::GetColumnAccess),
+                            ),
+                    )
+                    .service(
+                        web::resource("/statistics-level")
+                            // PUT "/logstream/{logstream}/statistics-level" ==> Set column statistics written to parquet files for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_statistics_level)
+                                    .authorize_for_stream(Action::PutStatisticsLevel),
+                            )
+                            // GET "/logstream/{logstream}/statistics-level" ==> Get column statistics written to parquet files for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_statistics_level)
+                                    .authorize_for_stream(Action::GetStatisticsLevel),
+                            ),
+                    )
+                    .service(
+                        web::resource("/schema-mode")
+                            // PUT "/logstream/{logstream}/schema-mode" ==> Set whether events may add fields to the schema of given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_schema_mode)
+                                    .authorize_for_stream(Action::PutSchemaMode),
+                            )
+                            // GET "/logstream/{logstream}/schema-mode" ==> Get whether events may add fields to the schema of given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_schema_mode)
+                                    .authorize_for_stream(Action::GetSchemaMode),
+                            ),
+                    )
+                    .service(
+                        web::resource("/partition-granularity")
+                            // PUT "/logstream/{logstream}/partition-granularity" ==> Set the finest time partition of data files of given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_partition_granularity)
+                                    .authorize_for_stream(Action::PutPartitionGranularity),
+                            )
+                            // GET "/logstream/{logstream}/partition-granularity" ==> Get the finest time partition of data files of given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_partition_granularity)
+                                    .authorize_for_stream(Action::GetPartitionGranularity),
+                            ),
+                    )
+                    .service(
+                        web::resource("/rollup")
+                            // PUT "/logstream/{logstream}/rollup" ==> Set the aggregations materialized into the rollup stream of given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_rollup)
+                                    .authorize_for_stream(Action::PutRollup),
+                            )
+                            // GET "/logstream/{logstream}/rollup" ==> Get the rollup and the range it covers for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_rollup)
+                                    .authorize_for_stream(Action::GetRollup),
+                            ),
+                    )
+                    .service(
+                        web::resource("/rate-limit")
+                            // PUT "/logstream/{logstream}/rate-limit" ==> Set the records and bytes per second ingested into given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_rate_limit)
+                                    .authorize_for_stream(Action::PutRateLimit),
+                            )
+                            // GET "/logstream/{logstream}/rate-limit" ==> Get the rate limit of given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_rate_limit)
+                                    .authorize_for_stream(Action::GetRateLimit),
+                            ),
+                    )
+                    .service(
+                        // POST "/logstream/{logstream}/restore" ==> Restore a deleted log stream from the trash
+                        web::resource("/restore").route(
+                            web::post()
+                                .to(logstream::restore)
+                                .authorize_for_stream(Action::RestoreStream),
+                        ),
+                    )
+                    .service(
+                        // POST "/logstream/{logstream}/catalog/repair" ==> Rebuild manifests and snapshot from parquet files
+                        web::resource("/catalog/repair").route(
+                            web::post()
+                                .to(logstream::repair_catalog)
+                                .authorize_for_stream(Action::RepairCatalog),
+                        ),
+                    )
+                    .service(
+                        // GET "/logstream/{logstream}/files/{date}" ==> List files of a date partition with their stats
+                        web::resource("/files/{date}").route(
+                            web::get()
+                                .to(logstream::get_partition_files)
+                                .authorize_for_stream(Action::ListPartitionFiles),
+                        ),
+                    )
+                    .service(
+                        // GET "/logstream/{logstream}/file-sizes" ==> Get the histogram of parquet file sizes
+                        web::resource("/file-sizes").route(
+                            web::get()
+                                .to(logstream::get_file_sizes)
+                                .authorize_for_stream(Action::GetFileSizes),
+                        ),
+                    )
+                    .service(
+                        // POST "/logstream/{logstream}/export" ==> Export stream data to another location
+                        web::resource("/export").route(
+                            web::post()
+                                .to(logstream::export)
+                                .authorize_for_stream(Action::ExportStream),
+                        ),
+                    )
+                    .service(
+                        // GET "/logstream/{logstream}/export/{id}" ==> Get progress of an export
+                        web::resource("/export/{id}").route(
+                            web::get()
+                                .to(logstream::get_export)
+                                .authorize_for_stream(Action::ExportStream),
+                        ),
                     ),
             )
     }
@@ -347,6 +511,28 @@ impl Server {
             .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE))
     }
 
+    // get the OTLP/HTTP web scope, exporters append /v1/logs and /v1/traces to the endpoint
+    pub fn get_otel_webscope() -> Scope {
+        web::scope("/otlp/v1")
+            .service(
+                // POST "/otlp/v1/logs" ==> Ingest OTLP logs into the stream in the X-P-Stream header
+                web::resource("/logs").route(
+                    web::post()
+                        .to(ingest::ingest_otel_logs)
+                        .authorize_for_stream(Action::Ingest),
+                ),
+            )
+            .service(
+                // POST "/otlp/v1/traces" ==> Ingest OTLP traces into the stream in the X-P-Stream header
+                web::resource("/traces").route(
+                    web::post()
+                        .to(ingest::ingest_otel_traces)
+                        .authorize_for_stream(Action::Ingest),
+                ),
+            )
+            .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE))
+    }
+
     // get the oauth webscope
     pub fn get_oauth_webscope(oidc_client: Option<OpenIdClient>) -> Scope {
         let oauth = web::scope("/o")
@@ -381,6 +567,23 @@ impl Server {
             )
     }
 
+    // get the alias webscope
+    pub fn get_alias_webscope() -> Scope {
+        web::scope("/alias")
+            // GET Alias List
+            .service(resource("").route(web::get().to(alias::list).authorize(Action::ListStream)))
+            .service(
+                // PUT, DELETE Aliases
+                resource("/{name}")
+                    .route(web::put().to(alias::put).authorize(Action::PutAlias))
+                    .route(
+                        web::delete()
+                            .to(alias::delete)
+                            .authorize(Action::DeleteAlias),
+                    ),
+            )
+    }
+
     // get the user webscope
     pub fn get_user_webscope() -> Scope {
         web::scope("/user")
@@ -470,6 +673,11 @@ impl Server {
         web::resource("/about").route(web::get().to(about::about).authorize(Action::GetAbout))
     }
 
+    // POST "/flush" ==> Flush and upload staged data
+    pub fn get_flush_factory() -> Resource {
+        web::resource("/flush").route(web::post().to(logstream::flush).authorize(Action::Flush))
+    }
+
     // GET "/" ==> Serve the static frontend directory
     pub fn get_generated() -> ResourceFiles {
         ResourceFiles::new("/", generate()).resolve_not_found_to_root()
@@ -491,6 +699,7 @@ impl Server {
         if let Err(err) = metadata::STREAM_INFO.load(&*storage).await {
             log::warn!("could not populate local metadata. {:?}", err);
         }
+        catalog::batch::reconcile(storage.clone()).await;
 
         FILTERS.load().await?;
         DASHBOARDS.load().await?;
@@ -498,6 +707,9 @@ impl Server {
         metrics::fetch_stats_from_storage().await;
         metrics::reset_daily_metric_from_global();
         storage::retention::load_retention_from_global();
+        storage::external_files::init();
+        storage::rollup::init();
+        catalog::batch::init();
 
         let (localsync_handler, mut localsync_outbox, localsync_inbox) = sync::run_local_sync();
         let (mut remote_sync_handler, mut remote_sync_outbox, mut remote_sync_inbox) =
@@ -509,6 +721,7 @@ impl Server {
 
         tokio::spawn(handlers::livetail::server());
         tokio::spawn(handlers::airplane::server());
+        tokio::spawn(handlers::postgres::server());
 
         let app = self.start(prometheus, CONFIG.parseable.openid.clone());
 
@@ -516,11 +729,15 @@ impl Server {
         loop {
             tokio::select! {
                 e = &mut app => {
-                    // actix server finished .. stop other threads and stop the server
+                    // actix server finished .. flush staged data, stop other threads and stop the server
+                    let drained = shutdown::drain().await;
                     remote_sync_inbox.send(()).unwrap_or(());
                     localsync_inbox.send(()).unwrap_or(());
                     localsync_handler.join().unwrap_or(());
-                    remote_sync_handler.join().unwrap_or(());
+                    // a sync stuck past the shutdown timeout is left behind
+                    if drained {
+                        remote_sync_handler.join().unwrap_or(());
+                    }
                     return e
                 },
                 _ = &mut localsync_outbox => {
@@ -17,8 +17,14 @@
  */
 
 use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat};
+use prost::Message;
 use serde_json::Value;
+mod otlp;
 mod proto;
+use crate::handlers::http::otel::otlp::{
+    ExportLogsServiceRequest, ExportTraceServiceRequest, KeyValue,
+};
 use crate::handlers::http::otel::proto::logs::v1::LogRecordFlags;
 use crate::handlers::http::otel::proto::logs::v1::LogsData;
 use crate::handlers::http::otel::proto::logs::v1::SeverityNumber;
@@ -97,10 +103,161 @@ fn collect_json_from_values(
 }
 
 pub fn flatten_otel_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
-    let mut vec_otel_json: Vec<BTreeMap<String, Value>> = Vec::new();
     let body_str = std::str::from_utf8(body).unwrap();
 
     let message: LogsData = serde_json::from_str(body_str).unwrap();
+    flatten_logs_data(message)
+}
+
+/// Flattens an OTLP/HTTP logs export request, encoded as protobuf or as JSON,
+/// into one JSON object per log record
+pub fn flatten_otlp_logs(
+    body: &Bytes,
+    protobuf: bool,
+) -> anyhow::Result<Vec<BTreeMap<String, Value>>> {
+    let message = if protobuf {
+        ExportLogsServiceRequest::decode(body.as_ref())?.into()
+    } else {
+        serde_json::from_slice::<LogsData>(body)?
+    };
+    Ok(flatten_logs_data(message))
+}
+
+/// Flattens an OTLP/HTTP traces export request encoded as protobuf into one JSON object
+/// per span. Resource, scope and span attributes are prefixed like those of logs.
+pub fn flatten_otlp_traces(body: &Bytes) -> anyhow::Result<Vec<BTreeMap<String, Value>>> {
+    let request = ExportTraceServiceRequest::decode(body.as_ref())?;
+    let mut spans_json = Vec::new();
+    for resource_spans in request.resource_spans {
+        let mut resource_json: BTreeMap<String, Value> = BTreeMap::new();
+        if let Some(resource) = resource_spans.resource {
+            insert_attributes(&mut resource_json, "resource", resource.attributes);
+            if resource.dropped_attributes_count > 0 {
+                resource_json.insert(
+                    "resource_dropped_attributes_count".to_string(),
+                    Value::from(resource.dropped_attributes_count),
+                );
+            }
+        }
+        if !resource_spans.schema_url.is_empty() {
+            resource_json.insert(
+                "resource_schema_url".to_string(),
+                Value::String(resource_spans.schema_url),
+            );
+        }
+
+        for scope_spans in resource_spans.scope_spans {
+            let mut scope_json = resource_json.clone();
+            if let Some(scope) = scope_spans.scope {
+                if !scope.name.is_empty() {
+                    scope_json.insert(
+                        "instrumentation_scope_name".to_string(),
+                        Value::String(scope.name),
+                    );
+                }
+                if !scope.version.is_empty() {
+                    scope_json.insert(
+                        "instrumentation_scope_version".to_string(),
+                        Value::String(scope.version),
+                    );
+                }
+                insert_attributes(&mut scope_json, "instrumentation_scope", scope.attributes);
+            }
+            if !scope_spans.schema_url.is_empty() {
+                scope_json.insert(
+                    "scope_span_schema_url".to_string(),
+                    Value::String(scope_spans.schema_url),
+                );
+            }
+
+            for span in scope_spans.spans {
+                let mut span_json = scope_json.clone();
+                for (key, id) in [
+                    ("trace_id", span.trace_id),
+                    ("span_id", span.span_id),
+                    ("parent_span_id", span.parent_span_id),
+                ] {
+                    if !id.is_empty() {
+                        span_json.insert(key.to_string(), Value::String(hex::encode(id)));
+                    }
+                }
+                if !span.trace_state.is_empty() {
+                    span_json.insert("trace_state".to_string(), Value::String(span.trace_state));
+                }
+                span_json.insert("span_name".to_string(), Value::String(span.name));
+                span_json.insert(
+                    "span_kind".to_string(),
+                    Value::String(otlp::span_kind_name(span.kind).to_string()),
+                );
+                if let Some(timestamp) = timestamp(span.start_time_unix_nano) {
+                    span_json.insert("timestamp".to_string(), timestamp);
+                }
+                span_json.insert(
+                    "start_time_unix_nano".to_string(),
+                    Value::String(span.start_time_unix_nano.to_string()),
+                );
+                span_json.insert(
+                    "end_time_unix_nano".to_string(),
+                    Value::String(span.end_time_unix_nano.to_string()),
+                );
+                span_json.insert(
+                    "duration_nano".to_string(),
+                    Value::from(
+                        span.end_time_unix_nano
+                            .saturating_sub(span.start_time_unix_nano),
+                    ),
+                );
+                insert_attributes(&mut span_json, "span", span.attributes);
+                if span.dropped_attributes_count > 0 {
+                    span_json.insert(
+                        "span_dropped_attributes_count".to_string(),
+                        Value::from(span.dropped_attributes_count),
+                    );
+                }
+                if let Some(status) = span.status {
+                    span_json.insert(
+                        "status_code".to_string(),
+                        Value::String(otlp::status_code_name(status.code).to_string()),
+                    );
+                    if !status.message.is_empty() {
+                        span_json
+                            .insert("status_message".to_string(), Value::String(status.message));
+                    }
+                }
+                spans_json.push(span_json);
+            }
+        }
+    }
+    Ok(spans_json)
+}
+
+/// Inserts attributes under `<prefix>_<key>` like the attributes of logs
+fn insert_attributes(json: &mut BTreeMap<String, Value>, prefix: &str, attributes: Vec<KeyValue>) {
+    for attribute in attributes {
+        let value = attribute.value.map(Into::into);
+        json.extend(collect_json_from_values(
+            &value,
+            &format!("{}_{}", prefix, attribute.key),
+        ));
+    }
+}
+
+/// Nanoseconds since the epoch as an RFC 3339 timestamp, none for the unknown time 0
+fn timestamp(unix_nano: u64) -> Option<Value> {
+    if unix_nano == 0 {
+        return None;
+    }
+    let time = DateTime::from_timestamp(
+        (unix_nano / 1_000_000_000) as i64,
+        (unix_nano % 1_000_000_000) as u32,
+    )?;
+    Some(Value::String(
+        time.to_rfc3339_opts(SecondsFormat::Millis, true),
+    ))
+}
+
+fn flatten_logs_data(message: LogsData) -> Vec<BTreeMap<String, Value>> {
+    let mut vec_otel_json: Vec<BTreeMap<String, Value>> = Vec::new();
     for records in message.resource_logs.iter() {
         for record in records.iter() {
             let mut otel_json: BTreeMap<String, Value> = BTreeMap::new();
@@ -166,13 +323,21 @@ pub fn flatten_otel_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
 
                     for log_record in scope_log.log_records.iter() {
                         let mut log_record_json: BTreeMap<String, Value> = BTreeMap::new();
-                        if !log_record.time_unix_nano > 0 {
+                        // time of the event, or the time it was observed when unknown
+                        let time_unix_nano = match log_record.time_unix_nano {
+                            0 => log_record.observed_time_unix_nano,
+                            time_unix_nano => time_unix_nano,
+                        };
+                        if let Some(timestamp) = timestamp(time_unix_nano) {
+                            log_record_json.insert("timestamp".to_string(), timestamp);
+                        }
+                        if log_record.time_unix_nano > 0 {
                             log_record_json.insert(
                                 "time_unix_nano".to_string(),
                                 Value::String(log_record.time_unix_nano.to_string()),
                             );
                         }
-                        if !log_record.observed_time_unix_nano > 0 {
+                        if log_record.observed_time_unix_nano > 0 {
                             log_record_json.insert(
                                 "observed_time_unix_nano".to_string(),
                                 Value::String(log_record.observed_time_unix_nano.to_string()),
@@ -255,10 +420,10 @@ pub fn flatten_otel_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
                                 Value::String(log_record.trace_id.to_string()),
                             );
                         }
-                        for key in log_record_json.keys() {
-                            otel_json.insert(key.to_owned(), log_record_json[key].to_owned());
-                        }
-                        vec_otel_json.push(otel_json.clone());
+                        // fields of a record aren't carried over to the next one
+                        let mut record_json = otel_json.clone();
+                        record_json.extend(log_record_json);
+                        vec_otel_json.push(record_json);
                     }
 
                     if !scope_log.schema_url.is_empty() {
@@ -279,3 +444,189 @@ pub fn flatten_otel_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
     }
     vec_otel_json
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_json::reader::infer_json_schema_from_iterator;
+    use arrow_schema::{DataType, Schema};
+    use bytes::Bytes;
+    use prost::Message;
+    use serde_json::{json, Value};
+
+    use super::otlp::{
+        any_value, AnyValue, ExportLogsServiceRequest, ExportTraceServiceRequest, KeyValue,
+        LogRecord, Resource, ResourceLogs, ResourceSpans, ScopeLogs, ScopeSpans, Span, Status,
+    };
+    use super::{flatten_otlp_logs, flatten_otlp_traces};
+
+    fn string_attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn resource() -> Option<Resource> {
+        Some(Resource {
+            attributes: vec![string_attribute("service.name", "checkout")],
+            dropped_attributes_count: 0,
+        })
+    }
+
+    fn schema(records: &[std::collections::BTreeMap<String, Value>]) -> Arc<Schema> {
+        let values: Vec<Value> = records
+            .iter()
+            .map(|record| serde_json::to_value(record).unwrap())
+            .collect();
+        Arc::new(infer_json_schema_from_iterator(values.iter().map(Ok)).unwrap())
+    }
+
+    fn data_type(schema: &Schema, name: &str) -> DataType {
+        schema.field_with_name(name).unwrap().data_type().clone()
+    }
+
+    #[test]
+    fn protobuf_logs_decode_into_flattened_columns() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: resource(),
+                scope_logs: vec![ScopeLogs {
+                    scope: None,
+                    log_records: vec![
+                        LogRecord {
+                            time_unix_nano: 1_700_000_000_123_000_000,
+                            severity_number: 17,
+                            body: Some(AnyValue {
+                                value: Some(any_value::Value::StringValue(
+                                    "payment failed".to_string(),
+                                )),
+                            }),
+                            attributes: vec![KeyValue {
+                                key: "http.status_code".to_string(),
+                                value: Some(AnyValue {
+                                    value: Some(any_value::Value::IntValue(502)),
+                                }),
+                            }],
+                            trace_id: vec![0xab; 16],
+                            span_id: vec![0xcd; 8],
+                            ..LogRecord::default()
+                        },
+                        LogRecord {
+                            observed_time_unix_nano: 1_700_000_001_000_000_000,
+                            severity_text: "INFO".to_string(),
+                            ..LogRecord::default()
+                        },
+                    ],
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        };
+        let body = Bytes::from(request.encode_to_vec());
+        let records = flatten_otlp_logs(&body, true).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["timestamp"], json!("2023-11-14T22:13:20.123Z"));
+        assert_eq!(records[0]["severity_text"], json!("SEVERITY_NUMBER_ERROR"));
+        assert_eq!(records[0]["trace_id"], json!("ab".repeat(16)));
+        assert_eq!(records[0]["span_id"], json!("cd".repeat(8)));
+        assert_eq!(records[0]["resource_service.name"], json!("checkout"));
+        // the timestamp falls back to the observed time and fields don't leak across records
+        assert_eq!(records[1]["timestamp"], json!("2023-11-14T22:13:21.000Z"));
+        assert!(!records[1].contains_key("log_record_http.status_code"));
+
+        let schema = schema(&records);
+        assert_eq!(data_type(&schema, "body"), DataType::Utf8);
+        assert_eq!(data_type(&schema, "severity_number"), DataType::Int64);
+        assert_eq!(
+            data_type(&schema, "log_record_http.status_code"),
+            DataType::Int64
+        );
+        assert_eq!(data_type(&schema, "resource_service.name"), DataType::Utf8);
+
+        assert!(flatten_otlp_logs(&Bytes::from_static(b"\xff\xff"), true).is_err());
+    }
+
+    #[test]
+    fn json_logs_decode_like_protobuf() {
+        let body = json!({
+            "resourceLogs": [{
+                "resource": {
+                    "attributes": [{"key": "service.name", "value": {"stringValue": "checkout"}}],
+                    "droppedAttributesCount": 0
+                },
+                "scopeLogs": [{
+                    "logRecords": [{
+                        "timeUnixNano": 1_700_000_000_123_000_000u64,
+                        "observedTimeUnixNano": 0,
+                        "severityNumber": 9,
+                        "severityText": "",
+                        "droppedAttributesCount": 0,
+                        "flags": 0,
+                        "traceId": "",
+                        "spanId": ""
+                    }],
+                    "schemaUrl": ""
+                }],
+                "schemaUrl": ""
+            }]
+        });
+        let body = Bytes::from(serde_json::to_vec(&body).unwrap());
+        let records = flatten_otlp_logs(&body, false).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["timestamp"], json!("2023-11-14T22:13:20.123Z"));
+        assert_eq!(records[0]["severity_text"], json!("SEVERITY_NUMBER_INFO"));
+        assert_eq!(records[0]["resource_service.name"], json!("checkout"));
+    }
+
+    #[test]
+    fn protobuf_traces_decode_one_event_per_span() {
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: resource(),
+                scope_spans: vec![ScopeSpans {
+                    scope: None,
+                    spans: vec![Span {
+                        trace_id: vec![0x01; 16],
+                        span_id: vec![0x02; 8],
+                        parent_span_id: vec![0x03; 8],
+                        name: "GET /cart".to_string(),
+                        kind: 2,
+                        start_time_unix_nano: 1_700_000_000_000_000_000,
+                        end_time_unix_nano: 1_700_000_000_250_000_000,
+                        attributes: vec![string_attribute("http.method", "GET")],
+                        status: Some(Status {
+                            message: "upstream timeout".to_string(),
+                            code: 2,
+                        }),
+                        ..Span::default()
+                    }],
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        };
+        let body = Bytes::from(request.encode_to_vec());
+        let records = flatten_otlp_traces(&body).unwrap();
+
+        assert_eq!(records.len(), 1);
+        let span = &records[0];
+        assert_eq!(span["trace_id"], json!("01".repeat(16)));
+        assert_eq!(span["parent_span_id"], json!("03".repeat(8)));
+        assert_eq!(span["span_kind"], json!("SPAN_KIND_SERVER"));
+        assert_eq!(span["timestamp"], json!("2023-11-14T22:13:20.000Z"));
+        assert_eq!(span["duration_nano"], json!(250_000_000));
+        assert_eq!(span["status_code"], json!("STATUS_CODE_ERROR"));
+        assert_eq!(span["span_http.method"], json!("GET"));
+        assert_eq!(span["resource_service.name"], json!("checkout"));
+
+        let schema = schema(&records);
+        assert_eq!(data_type(&schema, "duration_nano"), DataType::Int64);
+        assert_eq!(data_type(&schema, "span_name"), DataType::Utf8);
+    }
+}
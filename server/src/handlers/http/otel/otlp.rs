@@ -0,0 +1,348 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Protobuf messages of OTLP/HTTP export requests for logs and traces, with the field tags
+//! of the opentelemetry-proto definitions. Span events and links are not decoded.
+//! Decoded logs are converted to the JSON model in `proto` to be flattened the same way.
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+use super::proto::{
+    common::v1 as json_common, logs::v1 as json_logs, resource::v1 as json_resource,
+};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResourceLogs {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_logs: Vec<ScopeLogs>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeLogs {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub log_records: Vec<LogRecord>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogRecord {
+    #[prost(fixed64, tag = "1")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "11")]
+    pub observed_time_unix_nano: u64,
+    #[prost(int32, tag = "2")]
+    pub severity_number: i32,
+    #[prost(string, tag = "3")]
+    pub severity_text: String,
+    #[prost(message, optional, tag = "5")]
+    pub body: Option<AnyValue>,
+    #[prost(message, repeated, tag = "6")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "7")]
+    pub dropped_attributes_count: u32,
+    #[prost(fixed32, tag = "8")]
+    pub flags: u32,
+    #[prost(bytes = "vec", tag = "9")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "10")]
+    pub span_id: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportTraceServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResourceSpans {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_spans: Vec<ScopeSpans>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeSpans {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub spans: Vec<Span>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Span {
+    #[prost(bytes = "vec", tag = "1")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub span_id: Vec<u8>,
+    #[prost(string, tag = "3")]
+    pub trace_state: String,
+    #[prost(bytes = "vec", tag = "4")]
+    pub parent_span_id: Vec<u8>,
+    #[prost(string, tag = "5")]
+    pub name: String,
+    #[prost(int32, tag = "6")]
+    pub kind: i32,
+    #[prost(fixed64, tag = "7")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "8")]
+    pub end_time_unix_nano: u64,
+    #[prost(message, repeated, tag = "9")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "10")]
+    pub dropped_attributes_count: u32,
+    #[prost(message, optional, tag = "15")]
+    pub status: Option<Status>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Status {
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(int32, tag = "3")]
+    pub code: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "2")]
+    pub dropped_attributes_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(message, repeated, tag = "3")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "4")]
+    pub dropped_attributes_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnyValue {
+    #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub value: Option<any_value::Value>,
+}
+
+pub mod any_value {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        IntValue(i64),
+        #[prost(double, tag = "4")]
+        DoubleValue(f64),
+        #[prost(message, tag = "5")]
+        ArrayValue(super::ArrayValue),
+        #[prost(message, tag = "6")]
+        KvlistValue(super::KeyValueList),
+        #[prost(bytes = "vec", tag = "7")]
+        BytesValue(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ArrayValue {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<KeyValue>,
+}
+
+/// Name of a `Span.SpanKind` value
+pub fn span_kind_name(kind: i32) -> &'static str {
+    match kind {
+        1 => "SPAN_KIND_INTERNAL",
+        2 => "SPAN_KIND_SERVER",
+        3 => "SPAN_KIND_CLIENT",
+        4 => "SPAN_KIND_PRODUCER",
+        5 => "SPAN_KIND_CONSUMER",
+        _ => "SPAN_KIND_UNSPECIFIED",
+    }
+}
+
+/// Name of a `Status.StatusCode` value
+pub fn status_code_name(code: i32) -> &'static str {
+    match code {
+        1 => "STATUS_CODE_OK",
+        2 => "STATUS_CODE_ERROR",
+        _ => "STATUS_CODE_UNSET",
+    }
+}
+
+// OTLP/JSON encodes trace and span ids as hex and other bytes as base64
+
+impl From<AnyValue> for json_common::Value {
+    fn from(value: AnyValue) -> Self {
+        use any_value::Value;
+
+        let mut json = json_common::Value {
+            str_val: None,
+            bool_val: None,
+            int_val: None,
+            double_val: None,
+            array_val: None,
+            kv_list_val: None,
+            bytes_val: None,
+        };
+        match value.value {
+            Some(Value::StringValue(value)) => json.str_val = Some(value),
+            Some(Value::BoolValue(value)) => json.bool_val = Some(value),
+            Some(Value::IntValue(value)) => json.int_val = Some(value),
+            Some(Value::DoubleValue(value)) => json.double_val = Some(value),
+            Some(Value::ArrayValue(array)) => {
+                json.array_val = Some(json_common::ArrayValue {
+                    values: array
+                        .values
+                        .into_iter()
+                        .map(|value| json_common::AnyValue {
+                            value: value.into(),
+                        })
+                        .collect(),
+                })
+            }
+            Some(Value::KvlistValue(list)) => {
+                json.kv_list_val = Some(json_common::KeyValueList {
+                    values: list.values.into_iter().map(Into::into).collect(),
+                })
+            }
+            Some(Value::BytesValue(value)) => json.bytes_val = Some(BASE64_STANDARD.encode(value)),
+            None => {}
+        }
+        json
+    }
+}
+
+impl From<KeyValue> for json_common::KeyValue {
+    fn from(key_value: KeyValue) -> Self {
+        json_common::KeyValue {
+            key: key_value.key,
+            value: key_value.value.map(Into::into),
+        }
+    }
+}
+
+fn attributes(attributes: Vec<KeyValue>) -> Option<Vec<json_common::KeyValue>> {
+    Some(attributes.into_iter().map(Into::into).collect())
+}
+
+impl From<Resource> for json_resource::Resource {
+    fn from(resource: Resource) -> Self {
+        json_resource::Resource {
+            attributes: attributes(resource.attributes),
+            dropped_attributes_count: resource.dropped_attributes_count,
+        }
+    }
+}
+
+impl From<InstrumentationScope> for json_common::InstrumentationScope {
+    fn from(scope: InstrumentationScope) -> Self {
+        json_common::InstrumentationScope {
+            name: scope.name,
+            version: scope.version,
+            attributes: attributes(scope.attributes),
+            dropped_attributes_count: scope.dropped_attributes_count,
+        }
+    }
+}
+
+impl From<LogRecord> for json_logs::LogRecord {
+    fn from(record: LogRecord) -> Self {
+        json_logs::LogRecord {
+            time_unix_nano: record.time_unix_nano,
+            observed_time_unix_nano: record.observed_time_unix_nano,
+            severity_number: record.severity_number,
+            severity_text: record.severity_text,
+            body: record.body.map(Into::into),
+            attributes: attributes(record.attributes),
+            dropped_attributes_count: record.dropped_attributes_count,
+            flags: record.flags,
+            trace_id: hex::encode(record.trace_id),
+            span_id: hex::encode(record.span_id),
+        }
+    }
+}
+
+impl From<ExportLogsServiceRequest> for json_logs::LogsData {
+    fn from(request: ExportLogsServiceRequest) -> Self {
+        let resource_logs = request
+            .resource_logs
+            .into_iter()
+            .map(|resource_logs| json_logs::ResourceLogs {
+                resource: resource_logs.resource.map(Into::into),
+                scope_logs: Some(
+                    resource_logs
+                        .scope_logs
+                        .into_iter()
+                        .map(|scope_logs| json_logs::ScopeLogs {
+                            scope: scope_logs.scope.map(Into::into),
+                            log_records: scope_logs
+                                .log_records
+                                .into_iter()
+                                .map(Into::into)
+                                .collect(),
+                            schema_url: scope_logs.schema_url,
+                        })
+                        .collect(),
+                ),
+                schema_url: resource_logs.schema_url,
+            })
+            .collect();
+        json_logs::LogsData {
+            resource_logs: Some(resource_logs),
+        }
+    }
+}
@@ -16,41 +16,50 @@
  *
  */
 
-use actix_web::http::header::ContentType;
+use actix_web::http::header::{self, ContentType};
 use actix_web::web::{self, Json};
-use actix_web::{FromRequest, HttpRequest, Responder};
+use actix_web::{Either, FromRequest, HttpRequest, HttpResponse, Responder};
 use anyhow::anyhow;
+use arrow_schema::ArrowError;
 use chrono::{DateTime, Utc};
 use datafusion::common::tree_node::TreeNode;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::SessionState;
-use futures_util::Future;
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use futures_util::{Future, StreamExt};
 use http::StatusCode;
 use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::event::error::EventError;
 use crate::handlers::http::fetch_schema;
 use arrow_array::RecordBatch;
 
 use crate::event::commit_schema;
-use crate::handlers::{CACHE_RESULTS_HEADER_KEY, CACHE_VIEW_HEADER_KEY, USER_ID_HEADER_KEY};
+use crate::handlers::{
+    CACHE_RESULTS_HEADER_KEY, CACHE_VIEW_HEADER_KEY, COLD_QUERY_HEADER_KEY,
+    QUERY_TIMEOUT_HEADER_KEY, USER_ID_HEADER_KEY,
+};
 use crate::localcache::CacheError;
 use crate::metrics::QUERY_EXECUTE_TIME;
 use crate::option::{Mode, CONFIG};
 use crate::query::error::ExecuteError;
 use crate::query::Query as LogicalQuery;
-use crate::query::{TableScanVisitor, QUERY_SESSION};
+use crate::query::{as_of, explain_denied_column, TableScanVisitor, QUERY_SESSION};
 use crate::querycache::{CacheMetadata, QueryCacheManager};
 use crate::rbac::role::{Action, Permission};
 use crate::rbac::Users;
-use crate::response::QueryResponse;
+use crate::response::{into_arrow_ipc_stream, QueryResponse};
 use crate::storage::object_storage::commit_schema_to_storage;
-use crate::storage::ObjectStorageError;
+use crate::storage::{etag_cache, ObjectStorageError};
 use crate::utils::actix::extract_session_key_from_req;
 
+/// Content type for query results streamed as Arrow IPC record batches
+pub const ARROW_STREAM_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+
 /// Query Request through http endpoint.
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -64,15 +73,52 @@ pub struct Query {
     pub fields: bool,
     #[serde(skip)]
     pub filter_tags: Option<Vec<String>>,
+    /// Only files uploaded by this time are read, for results that don't change
+    /// as newer data is flushed
+    #[serde(default)]
+    pub as_of: Option<String>,
 }
 
-pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Responder, QueryError> {
+pub async fn query(
+    req: HttpRequest,
+    query_request: Query,
+) -> Result<Either<impl Responder, HttpResponse>, QueryError> {
+    // cold queries bypass every cache, to check the data and latency of the object store
+    let cold = req
+        .headers()
+        .get(COLD_QUERY_HEADER_KEY)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"));
+    let as_of = query_request
+        .as_of
+        .as_deref()
+        .map(|as_of| DateTime::parse_from_rfc3339(as_of).map_err(|_| QueryError::AsOfParse))
+        .transpose()?;
+
+    let run = run_query(req, query_request);
+    let run = async move {
+        if cold {
+            etag_cache::cold_read(run).await
+        } else {
+            run.await
+        }
+    };
+    match as_of {
+        Some(as_of) => as_of::scope(as_of.into(), run).await,
+        None => run.await,
+    }
+}
+
+async fn run_query(
+    req: HttpRequest,
+    query_request: Query,
+) -> Result<Either<impl Responder, HttpResponse>, QueryError> {
     let session_state = QUERY_SESSION.state();
 
     // get the logical plan and extract the table name
     let raw_logical_plan = session_state
         .create_logical_plan(&query_request.query)
-        .await?;
+        .await
+        .map_err(explain_denied_column)?;
 
     // create a visitor to extract the table name
     let mut visitor = TableScanVisitor::default();
@@ -85,14 +131,21 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
         .await
         .unwrap_or(None);
 
+    // cached results may include files uploaded after the cutoff of the query
+    let cold = etag_cache::is_cold_read() || as_of::cutoff().is_some();
+    // streamed results are sent as they are computed and never held in full to be
+    // cached, and cached results are only served as JSON
+    let arrow_stream = accepts_arrow_stream(&req);
     let cache_results = req
         .headers()
         .get(CACHE_RESULTS_HEADER_KEY)
-        .and_then(|value| value.to_str().ok());
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| !cold && !arrow_stream);
     let show_cached = req
         .headers()
         .get(CACHE_VIEW_HEADER_KEY)
-        .and_then(|value| value.to_str().ok());
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| !cold && !arrow_stream);
     let user_id = req
         .headers()
         .get(USER_ID_HEADER_KEY)
@@ -112,7 +165,7 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
     )
     .await
     {
-        return results.to_http();
+        return Ok(Either::Left(results.to_http()?));
     };
 
     let tables = visitor.into_inner();
@@ -126,10 +179,24 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
         .first_table_name()
         .ok_or_else(|| QueryError::MalformedQuery("No table name found in query"))?;
 
+    check_query_range(&query, &permissions)?;
     authorize_and_set_filter_tags(&mut query, permissions, &table_name)?;
 
+    let deadline = query_deadline(&req)?;
+
+    if arrow_stream {
+        let time = Instant::now();
+        let expires = deadline.map(|deadline| (tokio::time::Instant::now() + deadline, deadline));
+        let stream = with_deadline(deadline, query.execute_stream(table_name.clone())).await??;
+        let stream = with_execute_time(with_stream_deadline(stream, expires), table_name, time);
+        let response = HttpResponse::Ok()
+            .content_type(ARROW_STREAM_CONTENT_TYPE)
+            .streaming(into_arrow_ipc_stream(stream)?);
+        return Ok(Either::Right(response));
+    }
+
     let time = Instant::now();
-    let (records, fields) = query.execute(table_name.clone()).await?;
+    let (records, fields) = with_deadline(deadline, query.execute(table_name.clone())).await??;
     // deal with cache saving
     if let Err(err) = put_results_in_cache(
         cache_results,
@@ -160,7 +227,114 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
         .with_label_values(&[&table_name])
         .observe(time);
 
-    Ok(response)
+    Ok(Either::Left(response))
+}
+
+/// Deadline for the query, taken from the request header if present else from server config.
+/// The header can only shorten the deadline configured for the server.
+fn query_deadline(req: &HttpRequest) -> Result<Option<Duration>, QueryError> {
+    let requested = req
+        .headers()
+        .get(QUERY_TIMEOUT_HEADER_KEY)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .ok_or(QueryError::MalformedQuery(
+                    "x-p-query-timeout header should be a number of seconds",
+                ))
+        })
+        .transpose()?;
+
+    Ok(capped_deadline(requested, CONFIG.parseable.query_timeout))
+}
+
+fn capped_deadline(requested: Option<Duration>, configured: Option<Duration>) -> Option<Duration> {
+    match (requested, configured) {
+        (Some(requested), Some(configured)) => Some(requested.min(configured)),
+        (requested, configured) => requested.or(configured),
+    }
+}
+
+/// Runs the query future until it completes or the deadline passes.
+/// On timeout the future is dropped, which cancels the running plan along with its
+/// in-flight object store requests, releasing their concurrency permits.
+async fn with_deadline<F: Future>(
+    deadline: Option<Duration>,
+    fut: F,
+) -> Result<F::Output, QueryError> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout(deadline, fut)
+            .await
+            .map_err(|_| QueryError::Timeout(deadline)),
+        None => Ok(fut.await),
+    }
+}
+
+/// Ends the result stream with a timeout error once the deadline passes. Batches are
+/// computed as the stream is polled, so this bounds the execution of a streamed query.
+/// The stream is dropped on timeout, cancelling the running plan.
+fn with_stream_deadline(
+    stream: SendableRecordBatchStream,
+    expires: Option<(tokio::time::Instant, Duration)>,
+) -> SendableRecordBatchStream {
+    let Some((expires, deadline)) = expires else {
+        return stream;
+    };
+    let schema = stream.schema();
+    let expired = Box::pin(tokio::time::sleep_until(expires));
+    let stream = futures_util::stream::unfold(Some((stream, expired)), move |state| async move {
+        let (mut stream, mut expired) = state?;
+        tokio::select! {
+            batch = stream.next() => batch.map(|batch| (batch, Some((stream, expired)))),
+            _ = &mut expired => {
+                let err = DataFusionError::Execution(QueryError::Timeout(deadline).to_string());
+                Some((Err(err), None))
+            }
+        }
+    });
+    Box::pin(RecordBatchStreamAdapter::new(schema, stream))
+}
+
+/// Records the execution time of a streamed query once its last batch is computed.
+/// Queries ending with an error or dropped by the client are not recorded.
+fn with_execute_time(
+    stream: SendableRecordBatchStream,
+    table_name: String,
+    time: Instant,
+) -> SendableRecordBatchStream {
+    let schema = stream.schema();
+    let stream =
+        futures_util::stream::unfold(Some((stream, table_name)), move |state| async move {
+            let (mut stream, table_name) = state?;
+            match stream.next().await {
+                Some(Ok(batch)) => Some((Ok(batch), Some((stream, table_name)))),
+                Some(Err(err)) => Some((Err(err), None)),
+                None => {
+                    QUERY_EXECUTE_TIME
+                        .with_label_values(&[&table_name])
+                        .observe(time.elapsed().as_secs_f64());
+                    None
+                }
+            }
+        });
+    Box::pin(RecordBatchStreamAdapter::new(schema, stream))
+}
+
+/// Results are streamed as Arrow IPC only when the client explicitly asks for it.
+/// JSON remains the default response format.
+fn accepts_arrow_stream(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|mime| {
+            mime.split(';')
+                .next()
+                .is_some_and(|mime| mime.trim() == ARROW_STREAM_CONTENT_TYPE)
+        })
 }
 
 pub async fn update_schema_when_distributed(tables: Vec<String>) -> Result<(), QueryError> {
@@ -335,6 +509,41 @@ pub fn authorize_and_set_filter_tags(
     Ok(())
 }
 
+/// Rejects queries spanning more than the configured maximum range, so a query over all
+/// time of a stream doesn't scan years of data. Admins may query any range.
+pub fn check_query_range(
+    query: &LogicalQuery,
+    permissions: &[Permission],
+) -> Result<(), QueryError> {
+    let max_range = CONFIG
+        .parseable
+        .max_query_range_days
+        .map(|days| chrono::Duration::days(days as i64));
+    check_range(query.start, query.end, max_range, permissions)
+}
+
+fn check_range(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    max_range: Option<chrono::Duration>,
+    permissions: &[Permission],
+) -> Result<(), QueryError> {
+    let Some(max_range) = max_range else {
+        return Ok(());
+    };
+    let range = end - start;
+    let admin = permissions
+        .iter()
+        .any(|permission| matches!(permission, Permission::Stream(Action::All, _)));
+    if range <= max_range || admin {
+        return Ok(());
+    }
+    Err(QueryError::RangeTooLong {
+        range_days: range.num_days(),
+        max_days: max_range.num_days(),
+    })
+}
+
 impl FromRequest for Query {
     type Error = actix_web::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
@@ -446,6 +655,7 @@ fn transform_query_for_ingestor(query: &Query) -> Option<Query> {
         send_null: query.send_null,
         start_time: start_time.to_rfc3339(),
         end_time: end_time.to_rfc3339(),
+        as_of: query.as_of.clone(),
     };
 
     Some(q)
@@ -463,12 +673,16 @@ pub enum QueryError {
     StartTimeParse,
     #[error("Could not parse end time correctly")]
     EndTimeParse,
+    #[error("Could not parse as of time correctly")]
+    AsOfParse,
     #[error("While generating times for 'now' failed to parse duration")]
     NotValidDuration(#[from] humantime::DurationError),
     #[error("Parsed duration out of range")]
     OutOfRange(#[from] chrono::OutOfRangeError),
     #[error("Start time cannot be greater than the end time")]
     StartTimeAfterEndTime,
+    #[error("Query spans {range_days} days, more than the maximum of {max_days} days set by the server, narrow the start and end time")]
+    RangeTooLong { range_days: i64, max_days: i64 },
     #[error("Unauthorized")]
     Unauthorized,
     #[error("Datafusion Error: {0}")]
@@ -495,12 +709,19 @@ Description: {0}"#
     ActixError(#[from] actix_web::Error),
     #[error("Error: {0}")]
     Anyhow(#[from] anyhow::Error),
+    #[error("Arrow Error: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("Query did not finish within {0:?} and was cancelled")]
+    Timeout(Duration),
 }
 
 impl actix_web::ResponseError for QueryError {
     fn status_code(&self) -> http::StatusCode {
         match self {
-            QueryError::Execute(_) | QueryError::JsonParse(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            QueryError::Execute(_) | QueryError::JsonParse(_) | QueryError::Arrow(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            QueryError::Timeout(_) => StatusCode::REQUEST_TIMEOUT,
             _ => StatusCode::BAD_REQUEST,
         }
     }
@@ -511,3 +732,192 @@ impl actix_web::ResponseError for QueryError {
             .body(self.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use actix_web::{http::header, test::TestRequest};
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use chrono::{TimeZone, Utc};
+    use datafusion::prelude::SessionContext;
+    use futures_util::StreamExt;
+    use object_store::{path::Path, ObjectStore};
+    use parquet::arrow::ArrowWriter;
+    use url::Url;
+
+    use super::{
+        accepts_arrow_stream, capped_deadline, check_range, with_deadline, with_execute_time,
+        with_stream_deadline, QueryError,
+    };
+    use crate::metrics::QUERY_EXECUTE_TIME;
+    use crate::rbac::role::{Action, Permission};
+    use crate::storage::faulty_store::{Fault, FaultyStore, Operation};
+
+    #[actix_web::test]
+    async fn deadline_ends_streamed_query_on_slow_store() {
+        let store = Arc::new(FaultyStore::default());
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..10))],
+        )
+        .unwrap();
+        let mut parquet = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut parquet, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        store
+            .put(&Path::from("app/data.parquet"), parquet.into())
+            .await
+            .unwrap();
+
+        let ctx = SessionContext::new();
+        let url = Url::parse("slow://bucket").unwrap();
+        ctx.runtime_env().register_object_store(&url, store.clone());
+        ctx.register_parquet("app", "slow://bucket/app/", Default::default())
+            .await
+            .unwrap();
+        // planning lists the files, reading them never finishes
+        store.inject_always(Operation::Get, Fault::Delay(Duration::from_secs(60)));
+
+        let deadline = Duration::from_millis(100);
+        let expires = Some((tokio::time::Instant::now() + deadline, deadline));
+        let stream = with_deadline(Some(deadline), async {
+            ctx.sql("SELECT * FROM app").await?.execute_stream().await
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let batches: Vec<_> = with_stream_deadline(stream, expires).collect().await;
+
+        assert_eq!(batches.len(), 1);
+        let err = batches[0].as_ref().unwrap_err();
+        assert!(err.to_string().contains("did not finish"), "{err}");
+        assert!(store.calls(Operation::Get) > 0, "query reached the store");
+    }
+
+    #[test]
+    fn header_deadline_is_capped_by_server_deadline() {
+        let secs = Duration::from_secs;
+
+        assert_eq!(
+            capped_deadline(Some(secs(600)), Some(secs(60))),
+            Some(secs(60))
+        );
+        assert_eq!(
+            capped_deadline(Some(secs(10)), Some(secs(60))),
+            Some(secs(10))
+        );
+        assert_eq!(capped_deadline(None, Some(secs(60))), Some(secs(60)));
+        assert_eq!(capped_deadline(Some(secs(10)), None), Some(secs(10)));
+    }
+
+    #[test]
+    fn range_longer_than_max_is_rejected_unless_admin() {
+        let start = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let max = Some(chrono::Duration::days(30));
+        let reader = [
+            Permission::StreamWithTag(Action::Query, "app".to_owned(), None),
+            Permission::SelfUser,
+        ];
+
+        let res = check_range(start, end, max, &reader);
+        assert!(matches!(
+            res,
+            Err(QueryError::RangeTooLong {
+                range_days: 730,
+                max_days: 30
+            })
+        ));
+        assert!(check_range(end - chrono::Duration::days(30), end, max, &reader).is_ok());
+        assert!(check_range(start, end, None, &reader).is_ok());
+
+        let admin = [Permission::Stream(Action::All, "*".to_owned())];
+        assert!(check_range(start, end, max, &admin).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn streamed_query_time_is_recorded_once_complete() {
+        let ctx = SessionContext::new();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch =
+            RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from_iter_values(0..10))])
+                .unwrap();
+        ctx.register_batch("app", batch).unwrap();
+        let count = |stream| {
+            QUERY_EXECUTE_TIME
+                .with_label_values(&[stream])
+                .get_sample_count()
+        };
+
+        let stream = ctx
+            .sql("SELECT * FROM app")
+            .await
+            .unwrap()
+            .execute_stream()
+            .await
+            .unwrap();
+        let mut stream = with_execute_time(stream, "streamed_app".to_owned(), Instant::now());
+        assert!(stream.next().await.unwrap().is_ok());
+        assert_eq!(count("streamed_app"), 0);
+        assert!(stream.next().await.is_none());
+        assert_eq!(count("streamed_app"), 1);
+
+        // a client going away before the end is not recorded
+        let stream = ctx
+            .sql("SELECT * FROM app")
+            .await
+            .unwrap()
+            .execute_stream()
+            .await
+            .unwrap();
+        drop(with_execute_time(
+            stream,
+            "dropped_app".to_owned(),
+            Instant::now(),
+        ));
+        assert_eq!(count("dropped_app"), 0);
+    }
+
+    #[actix_web::test]
+    async fn query_without_deadline_runs_to_completion() {
+        let res = with_deadline(None, async { 42 }).await;
+
+        assert!(matches!(res, Ok(42)));
+    }
+
+    #[test]
+    fn arrow_stream_is_only_sent_when_asked_for() {
+        let accepts = |values: &[&str]| {
+            let req = values
+                .iter()
+                .fold(TestRequest::default(), |req, value| {
+                    req.append_header((header::ACCEPT, *value))
+                })
+                .to_http_request();
+            accepts_arrow_stream(&req)
+        };
+
+        assert!(accepts(&["application/vnd.apache.arrow.stream"]));
+        assert!(accepts(&[
+            "application/json;q=0.5, application/vnd.apache.arrow.stream;q=0.9"
+        ]));
+        assert!(accepts(&[
+            "application/json",
+            "application/vnd.apache.arrow.stream"
+        ]));
+
+        // JSON stays the default, wildcards and other Arrow formats don't opt in
+        assert!(!accepts(&[]));
+        assert!(!accepts(&["*/*"]));
+        assert!(!accepts(&["application/*"]));
+        assert!(!accepts(&["application/vnd.apache.arrow.file"]));
+        assert!(!accepts(&["application/vnd.apache.arrow.streaming"]));
+    }
+}
@@ -0,0 +1,1773 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Read only SQL over the PostgreSQL wire protocol (v3), for BI tools and clients that
+//! only speak Postgres.
+//!
+//! Users log in with their basic auth credentials, sent as a cleartext password. When the
+//! server has a TLS certificate connections must be encrypted before logging in, without
+//! one the server only listens on a loopback address.
+//! Queries are planned, authorized and executed as in the query API, reading the
+//! configured range before now. Statements other than queries are rejected, except for
+//! the `SET`, `SHOW` and transaction statements clients send on connect, which are
+//! acknowledged without effect. Queries of `information_schema` list the streams the
+//! user may query.
+//!
+//! Both the simple and the extended query protocol are served, without parameters.
+//! Rows are sent in text format, or in binary for the types clients usually ask it for.
+//! Queries can't be cancelled, cancel requests are ignored and clients are given a key
+//! that is never checked.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, StringArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{
+    ArrowPrimitiveType, DataType, Date32Type, Field, Float32Type, Float64Type, Int16Type,
+    Int32Type, Int64Type, Schema, SchemaRef, TimeUnit, TimestampMicrosecondType,
+};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::common::tree_node::TreeNode;
+use datafusion::datasource::empty::EmptyTable;
+use datafusion::error::DataFusionError;
+use datafusion::execution::context::SessionContext;
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::prelude::SessionConfig;
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast;
+use datafusion::sql::sqlparser::dialect::PostgreSqlDialect;
+use datafusion::sql::sqlparser::parser::ParserError;
+use datafusion::sql::TableReference;
+use futures::TryStreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+
+use crate::handlers::http::modal::ssl_acceptor::get_ssl_acceptor;
+use crate::handlers::http::query::{
+    authorize_and_set_filter_tags, check_query_range, into_query, update_schema_when_distributed,
+    Query, QueryError,
+};
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+use crate::query::error::ExecuteError;
+use crate::query::{explain_denied_column, TableScanVisitor, QUERY_SESSION};
+use crate::rbac::map::SessionKey;
+use crate::rbac::role::Action;
+use crate::rbac::{self, Users};
+
+const PROTOCOL_VERSION: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+const AUTH_OK: i32 = 0;
+const AUTH_CLEARTEXT_PASSWORD: i32 = 3;
+
+// larger messages are rejected, so a client can't make the server allocate any amount
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+// limit of the startup and password messages, read before the client is authenticated
+const MAX_STARTUP_MESSAGE_LEN: usize = 10_000;
+// time a client has from connecting to being logged in
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+// buffered rows are sent once they reach this size
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+// postgres counts dates and timestamps from 2000-01-01
+const POSTGRES_EPOCH_DAYS: i32 = 10_957;
+const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+const TIMESTAMPTZ_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f%:z";
+
+pub const BOOL: i32 = 16;
+pub const BYTEA: i32 = 17;
+pub const INT8: i32 = 20;
+pub const INT2: i32 = 21;
+pub const INT4: i32 = 23;
+pub const TEXT: i32 = 25;
+pub const FLOAT4: i32 = 700;
+pub const FLOAT8: i32 = 701;
+pub const DATE: i32 = 1082;
+pub const TIMESTAMP: i32 = 1114;
+pub const TIMESTAMPTZ: i32 = 1184;
+pub const NUMERIC: i32 = 1700;
+
+// reported on connect and to SHOW
+const PARAMETERS: [(&str, &str); 10] = [
+    ("server_version", "14.0"),
+    ("server_encoding", "UTF8"),
+    ("client_encoding", "UTF8"),
+    ("DateStyle", "ISO, MDY"),
+    ("TimeZone", "UTC"),
+    ("IntervalStyle", "postgres"),
+    ("integer_datetimes", "on"),
+    ("standard_conforming_strings", "on"),
+    ("transaction_isolation", "read committed"),
+    ("search_path", "public"),
+];
+
+/// Postgres type of the values of an arrow type, types without a postgres
+/// counterpart like lists and structs are sent as text
+pub fn type_oid(data_type: &DataType) -> i32 {
+    match data_type {
+        DataType::Boolean => BOOL,
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => INT2,
+        DataType::Int32 | DataType::UInt16 => INT4,
+        DataType::Int64 | DataType::UInt32 => INT8,
+        DataType::UInt64 | DataType::Decimal128(..) | DataType::Decimal256(..) => NUMERIC,
+        DataType::Float16 | DataType::Float32 => FLOAT4,
+        DataType::Float64 => FLOAT8,
+        DataType::Date32 | DataType::Date64 => DATE,
+        DataType::Timestamp(_, None) => TIMESTAMP,
+        DataType::Timestamp(_, Some(_)) => TIMESTAMPTZ,
+        DataType::Binary | DataType::LargeBinary => BYTEA,
+        _ => TEXT,
+    }
+}
+
+fn type_size(oid: i32) -> i16 {
+    match oid {
+        BOOL => 1,
+        INT2 => 2,
+        INT4 | FLOAT4 | DATE => 4,
+        INT8 | FLOAT8 | TIMESTAMP | TIMESTAMPTZ => 8,
+        _ => -1,
+    }
+}
+
+// arrow type the values of a postgres type are encoded from
+fn encoded_type(oid: i32) -> Option<DataType> {
+    let data_type = match oid {
+        BOOL => DataType::Boolean,
+        INT2 => DataType::Int16,
+        INT4 => DataType::Int32,
+        INT8 => DataType::Int64,
+        FLOAT4 => DataType::Float32,
+        FLOAT8 => DataType::Float64,
+        DATE => DataType::Date32,
+        TIMESTAMP => DataType::Timestamp(TimeUnit::Microsecond, None),
+        TIMESTAMPTZ => DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+        BYTEA => DataType::Binary,
+        _ => return None,
+    };
+    Some(data_type)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text = 0,
+    Binary = 1,
+}
+
+impl Format {
+    fn from_code(code: i16) -> Result<Self, PostgresError> {
+        match code {
+            0 => Ok(Format::Text),
+            1 => Ok(Format::Binary),
+            _ => Err(PostgresError::Protocol(format!(
+                "invalid format code {code}"
+            ))),
+        }
+    }
+}
+
+/// Result format of each of `columns`, from the format codes of a Bind message
+fn column_formats(codes: &[i16], columns: usize) -> Result<Vec<Format>, PostgresError> {
+    match codes {
+        [] => Ok(vec![Format::Text; columns]),
+        [code] => Ok(vec![Format::from_code(*code)?; columns]),
+        codes if codes.len() == columns => {
+            codes.iter().map(|&code| Format::from_code(code)).collect()
+        }
+        codes => Err(PostgresError::Protocol(format!(
+            "{} result formats for {columns} columns",
+            codes.len()
+        ))),
+    }
+}
+
+/// Values of `array` in `format`, None for nulls
+pub fn encode_column(
+    array: &ArrayRef,
+    format: Format,
+) -> Result<Vec<Option<Vec<u8>>>, PostgresError> {
+    let oid = type_oid(array.data_type());
+    let array = match encoded_type(oid) {
+        Some(data_type) => cast(array, &data_type)?,
+        None => array.clone(),
+    };
+    match format {
+        Format::Text => encode_text(&array, oid),
+        Format::Binary => encode_binary(&array, oid),
+    }
+}
+
+fn encode_text(array: &ArrayRef, oid: i32) -> Result<Vec<Option<Vec<u8>>>, PostgresError> {
+    let values = match oid {
+        BOOL => array
+            .as_boolean()
+            .iter()
+            .map(|value| value.map(|value| if value { b"t" } else { b"f" }.to_vec()))
+            .collect(),
+        BYTEA => array
+            .as_binary::<i32>()
+            .iter()
+            .map(|value| {
+                value.map(|bytes| {
+                    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                    format!("\\x{hex}").into_bytes()
+                })
+            })
+            .collect(),
+        _ => {
+            let options = FormatOptions::new()
+                .with_date_format(Some("%Y-%m-%d"))
+                .with_timestamp_format(Some(TIMESTAMP_FORMAT))
+                .with_timestamp_tz_format(Some(TIMESTAMPTZ_FORMAT));
+            let formatter = ArrayFormatter::try_new(array.as_ref(), &options)?;
+            (0..array.len())
+                .map(|row| {
+                    array
+                        .is_valid(row)
+                        .then(|| formatter.value(row).to_string().into_bytes())
+                })
+                .collect()
+        }
+    };
+    Ok(values)
+}
+
+fn encode_binary(array: &ArrayRef, oid: i32) -> Result<Vec<Option<Vec<u8>>>, PostgresError> {
+    let values = match oid {
+        BOOL => array
+            .as_boolean()
+            .iter()
+            .map(|value| value.map(|value| vec![value as u8]))
+            .collect(),
+        INT2 => primitive::<Int16Type>(array, |value| value.to_be_bytes().to_vec()),
+        INT4 => primitive::<Int32Type>(array, |value| value.to_be_bytes().to_vec()),
+        INT8 => primitive::<Int64Type>(array, |value| value.to_be_bytes().to_vec()),
+        FLOAT4 => primitive::<Float32Type>(array, |value| value.to_be_bytes().to_vec()),
+        FLOAT8 => primitive::<Float64Type>(array, |value| value.to_be_bytes().to_vec()),
+        DATE => primitive::<Date32Type>(array, |days| {
+            (days - POSTGRES_EPOCH_DAYS).to_be_bytes().to_vec()
+        }),
+        TIMESTAMP | TIMESTAMPTZ => primitive::<TimestampMicrosecondType>(array, |micros| {
+            (micros - POSTGRES_EPOCH_MICROS).to_be_bytes().to_vec()
+        }),
+        BYTEA => array
+            .as_binary::<i32>()
+            .iter()
+            .map(|value| value.map(<[u8]>::to_vec))
+            .collect(),
+        // the binary format of text is the text itself
+        TEXT => return encode_text(array, oid),
+        _ => {
+            return Err(PostgresError::Unsupported(format!(
+                "binary format is not supported for values of type {}",
+                array.data_type()
+            )))
+        }
+    };
+    Ok(values)
+}
+
+fn primitive<T: ArrowPrimitiveType>(
+    array: &ArrayRef,
+    encode: impl Fn(T::Native) -> Vec<u8>,
+) -> Vec<Option<Vec<u8>>> {
+    array
+        .as_primitive::<T>()
+        .iter()
+        .map(|value| value.map(&encode))
+        .collect()
+}
+
+/// A statement of a query string
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statement {
+    /// Query whose rows are sent
+    Query(String),
+    /// Statement acknowledged without effect, with its command tag
+    Command(&'static str),
+    /// Single value computed without running a query, like the result of SHOW
+    Value { column: String, value: String },
+}
+
+fn parse(sql: &str) -> Result<Vec<Statement>, PostgresError> {
+    DFParser::parse_sql_with_dialect(sql, &PostgreSqlDialect {})?
+        .into_iter()
+        .map(|statement| {
+            let statement = match statement {
+                DFStatement::Statement(statement) => statement,
+                _ => return Err(unsupported()),
+            };
+            let sql = statement.to_string();
+            match *statement {
+                ast::Statement::Query(_) if sql.eq_ignore_ascii_case("SELECT version()") => {
+                    Ok(Statement::Value {
+                        column: "version".to_owned(),
+                        value: format!(
+                            "PostgreSQL 14.0 on Parseable {}",
+                            env!("CARGO_PKG_VERSION")
+                        ),
+                    })
+                }
+                ast::Statement::Query(_) => Ok(Statement::Query(sql)),
+                ast::Statement::SetVariable { .. }
+                | ast::Statement::SetNames { .. }
+                | ast::Statement::SetTimeZone { .. } => Ok(Statement::Command("SET")),
+                ast::Statement::StartTransaction { .. } => Ok(Statement::Command("BEGIN")),
+                ast::Statement::Commit { .. } => Ok(Statement::Command("COMMIT")),
+                ast::Statement::Rollback { .. } => Ok(Statement::Command("ROLLBACK")),
+                ast::Statement::ShowVariable { variable } => show(&variable),
+                _ => Err(unsupported()),
+            }
+        })
+        .collect()
+}
+
+fn unsupported() -> PostgresError {
+    PostgresError::Unsupported("only queries are supported".to_owned())
+}
+
+fn show(variable: &[ast::Ident]) -> Result<Statement, PostgresError> {
+    let name = variable
+        .iter()
+        .map(|ident| ident.value.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_");
+    let name = match name.as_str() {
+        "transaction_isolation_level" => "transaction_isolation",
+        name => name,
+    };
+    PARAMETERS
+        .iter()
+        .find(|(parameter, _)| parameter.eq_ignore_ascii_case(name))
+        .map(|(parameter, value)| Statement::Value {
+            column: parameter.to_lowercase(),
+            value: value.to_string(),
+        })
+        .ok_or_else(|| {
+            PostgresError::Unsupported(format!("unrecognized configuration parameter {name}"))
+        })
+}
+
+fn value_batch(column: &str, value: &str) -> RecordBatch {
+    let schema = Schema::new(vec![Field::new(column, DataType::Utf8, false)]);
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(StringArray::from(vec![value]))],
+    )
+    .expect("single column matches schema")
+}
+
+/// Plans and runs the queries of a connection
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Whether the credentials of a connection are valid
+    fn authenticate(&self, key: &SessionKey) -> bool;
+
+    /// Schema of the rows of `sql` run by `key`
+    async fn schema(&self, key: &SessionKey, sql: &str) -> Result<SchemaRef, PostgresError>;
+
+    /// Rows of `sql` run by `key`
+    async fn execute(
+        &self,
+        key: &SessionKey,
+        sql: &str,
+    ) -> Result<SendableRecordBatchStream, PostgresError>;
+}
+
+/// Runs queries as the query API does, as the logged in user
+pub struct ParseableBackend;
+
+#[async_trait]
+impl Backend for ParseableBackend {
+    fn authenticate(&self, key: &SessionKey) -> bool {
+        matches!(
+            Users.authorize(key.clone(), Action::Query, None, None),
+            rbac::Response::Authorized
+        )
+    }
+
+    async fn schema(&self, key: &SessionKey, sql: &str) -> Result<SchemaRef, PostgresError> {
+        let plan = match introspection_context(key, sql) {
+            Some(ctx) => ctx.state().create_logical_plan(sql).await?,
+            None => QUERY_SESSION
+                .state()
+                .create_logical_plan(sql)
+                .await
+                .map_err(explain_denied_column)?,
+        };
+        Ok(Arc::new(Schema::from(plan.schema().as_ref())))
+    }
+
+    async fn execute(
+        &self,
+        key: &SessionKey,
+        sql: &str,
+    ) -> Result<SendableRecordBatchStream, PostgresError> {
+        if let Some(ctx) = introspection_context(key, sql) {
+            return Ok(ctx.sql(sql).await?.execute_stream().await?);
+        }
+
+        let session_state = QUERY_SESSION.state();
+        let plan = session_state
+            .create_logical_plan(sql)
+            .await
+            .map_err(explain_denied_column)?;
+        let mut visitor = TableScanVisitor::default();
+        let _ = plan.visit(&mut visitor);
+        let tables = visitor.into_inner();
+        if tables.is_empty() {
+            // no stream is read, like the queries clients check their connection with
+            let df = QUERY_SESSION.execute_logical_plan(plan).await?;
+            return Ok(df.execute_stream().await?);
+        }
+
+        update_schema_when_distributed(tables).await?;
+        let request = Query {
+            query: sql.to_owned(),
+            start_time: CONFIG.parseable.postgres_query_range.clone(),
+            end_time: "now".to_owned(),
+            send_null: false,
+            fields: false,
+            filter_tags: None,
+            as_of: None,
+        };
+        let mut query = into_query(&request, &session_state).await?;
+        let permissions = Users.get_permissions(key);
+        let table_name = query
+            .first_table_name()
+            .ok_or(QueryError::MalformedQuery("No table name found in query"))?;
+        check_query_range(&query, &permissions)?;
+        authorize_and_set_filter_tags(&mut query, permissions, &table_name)?;
+        Ok(query.execute_stream(table_name).await?)
+    }
+}
+
+/// Session answering queries of the information_schema, with an empty table for each
+/// stream `key` may query. None for queries not reading the information_schema.
+fn introspection_context(key: &SessionKey, sql: &str) -> Option<SessionContext> {
+    if !reads_information_schema(sql) {
+        return None;
+    }
+    let config = SessionConfig::new()
+        .with_information_schema(true)
+        .with_default_catalog_and_schema("parseable", "public");
+    let ctx = SessionContext::new_with_config(config);
+    for stream in STREAM_INFO.list_streams() {
+        let authorized = matches!(
+            Users.authorize(key.clone(), Action::Query, Some(&stream), None),
+            rbac::Response::Authorized
+        );
+        let Ok(schema) = STREAM_INFO.schema(&stream) else {
+            continue;
+        };
+        if authorized {
+            let table = Arc::new(EmptyTable::new(schema));
+            if let Err(err) = ctx.register_table(TableReference::bare(stream.clone()), table) {
+                log::warn!("Failed to list stream {stream} in information_schema: {err}");
+            }
+        }
+    }
+    Some(ctx)
+}
+
+/// Whether any table `sql` reads is in the information_schema
+fn reads_information_schema(sql: &str) -> bool {
+    let Ok(statements) = DFParser::parse_sql_with_dialect(sql, &PostgreSqlDialect {}) else {
+        return false;
+    };
+    statements.iter().any(|statement| {
+        let DFStatement::Statement(statement) = statement else {
+            return false;
+        };
+        ast::visit_relations(&**statement, |relation| {
+            let schema = relation.0.iter().rev().nth(1);
+            match schema {
+                Some(schema) if schema.value.eq_ignore_ascii_case("information_schema") => {
+                    ControlFlow::Break(())
+                }
+                _ => ControlFlow::Continue(()),
+            }
+        })
+        .is_break()
+    })
+}
+
+/// Serves PostgreSQL clients on the configured port until the server stops,
+/// returns immediately when no port is configured
+pub async fn server() {
+    let Some(port) = CONFIG.parseable.postgres_port else {
+        return;
+    };
+    let mut addr: SocketAddr = CONFIG
+        .parseable
+        .address
+        .parse()
+        .expect("valid socket address");
+    addr.set_port(port);
+
+    let tls = match get_ssl_acceptor(
+        &CONFIG.parseable.tls_cert_path,
+        &CONFIG.parseable.tls_key_path,
+    ) {
+        Ok(config) => config.map(|config| TlsAcceptor::from(Arc::new(config))),
+        Err(err) => {
+            log::error!("Failed to load the TLS certificate of the PostgreSQL server: {err}");
+            return;
+        }
+    };
+    // passwords are sent in cleartext, only encrypted connections may leave the host
+    if tls.is_none() && !addr.ip().is_loopback() {
+        log::error!(
+            "PostgreSQL server not started on {addr}, a TLS certificate is required to listen on a non loopback address"
+        );
+        return;
+    }
+
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Failed to start PostgreSQL server on {addr}: {err}");
+            return;
+        }
+    };
+    log::info!("PostgreSQL server listening on {addr}");
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                log::warn!("Failed to accept PostgreSQL connection: {err}");
+                continue;
+            }
+        };
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve(socket, &ParseableBackend, tls.as_ref()).await {
+                log::warn!("PostgreSQL connection from {peer} closed: {err}");
+            }
+        });
+    }
+}
+
+/// Serves one connection, from the startup message until the client terminates.
+/// With `tls` the connection must be encrypted before logging in.
+pub async fn serve<S, B>(
+    stream: S,
+    backend: &B,
+    tls: Option<&TlsAcceptor>,
+) -> Result<(), PostgresError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: Backend + ?Sized,
+{
+    serve_until(stream, backend, tls, Instant::now() + HANDSHAKE_TIMEOUT).await
+}
+
+/// Serves one connection, closing it if the client isn't logged in by `deadline`
+async fn serve_until<S, B>(
+    mut stream: S,
+    backend: &B,
+    tls: Option<&TlsAcceptor>,
+    deadline: Instant,
+) -> Result<(), PostgresError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: Backend + ?Sized,
+{
+    match (
+        before(deadline, startup(&mut stream, tls.is_some())).await?,
+        tls,
+    ) {
+        (None, _) => Ok(()),
+        (Some(Startup::Parameters(_)), Some(_)) => {
+            let err = PostgresError::Encryption;
+            let mut out = BytesMut::new();
+            error_response(&mut out, "FATAL", &err);
+            stream.write_all(&out).await?;
+            Err(err)
+        }
+        (Some(Startup::Parameters(parameters)), None) => {
+            login(stream, backend, parameters, deadline).await
+        }
+        (Some(Startup::Tls), Some(tls)) => {
+            let mut stream = before(deadline, tls.accept(stream)).await?;
+            match before(deadline, startup(&mut stream, false)).await? {
+                Some(Startup::Parameters(parameters)) => {
+                    login(stream, backend, parameters, deadline).await
+                }
+                _ => Ok(()),
+            }
+        }
+        (Some(Startup::Tls), None) => unreachable!("TLS is only accepted when offered"),
+    }
+}
+
+/// Step of the handshake, failing once `deadline` has passed
+async fn before<T, E>(
+    deadline: Instant,
+    step: impl std::future::Future<Output = Result<T, E>>,
+) -> Result<T, PostgresError>
+where
+    PostgresError: From<E>,
+{
+    match tokio::time::timeout_at(deadline, step).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(PostgresError::Timeout),
+    }
+}
+
+/// Authenticates the user of a started connection and serves its queries
+async fn login<S, B>(
+    mut stream: S,
+    backend: &B,
+    parameters: HashMap<String, String>,
+    deadline: Instant,
+) -> Result<(), PostgresError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: Backend + ?Sized,
+{
+    let username = parameters.get("user").cloned().unwrap_or_default();
+
+    let mut out = BytesMut::new();
+    message(&mut out, b'R', |out| out.put_i32(AUTH_CLEARTEXT_PASSWORD));
+    stream.write_all(&out.split()).await?;
+    let password =
+        match before(deadline, read_message(&mut stream, MAX_STARTUP_MESSAGE_LEN)).await? {
+            Some((b'p', mut body)) => get_cstr(&mut body)?,
+            Some((tag, _)) => {
+                return Err(PostgresError::Protocol(format!(
+                    "expected a password message, got {}",
+                    tag as char
+                )))
+            }
+            None => return Ok(()),
+        };
+    let key = SessionKey::BasicAuth {
+        username: username.clone(),
+        password,
+    };
+    if !backend.authenticate(&key) {
+        let err = PostgresError::Authentication(username);
+        error_response(&mut out, "FATAL", &err);
+        stream.write_all(&out).await?;
+        return Err(err);
+    }
+
+    message(&mut out, b'R', |out| out.put_i32(AUTH_OK));
+    for (name, value) in PARAMETERS {
+        message(&mut out, b'S', |out| {
+            put_cstr(out, name);
+            put_cstr(out, value);
+        });
+    }
+    // queries can't be cancelled, clients expect a key but it is never checked
+    message(&mut out, b'K', |out| {
+        out.put_i32(std::process::id() as i32);
+        out.put_i32(0);
+    });
+
+    let mut connection = Connection {
+        stream,
+        backend,
+        key,
+        out,
+        statements: HashMap::new(),
+        portals: HashMap::new(),
+        failed: false,
+    };
+    connection.ready();
+    connection.flush().await?;
+    connection.run().await
+}
+
+enum Startup {
+    /// Parameters of the startup message
+    Parameters(HashMap<String, String>),
+    /// The client asked for TLS and it was accepted
+    Tls,
+}
+
+/// Start of a connection, None when the client went away before sending the startup message
+async fn startup<S>(stream: &mut S, offer_tls: bool) -> Result<Option<Startup>, PostgresError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let len = match stream.read_i32().await {
+            Ok(len) => len,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut body = read_body(stream, len, MAX_STARTUP_MESSAGE_LEN).await?;
+        let code = get_i32(&mut body)?;
+        match code {
+            SSL_REQUEST if offer_tls => {
+                stream.write_all(b"S").await?;
+                stream.flush().await?;
+                return Ok(Some(Startup::Tls));
+            }
+            // other encryption is not offered, clients continue without it
+            SSL_REQUEST | GSSENC_REQUEST => stream.write_all(b"N").await?,
+            // cancelling is not supported, the request is dropped with its connection
+            CANCEL_REQUEST => return Ok(None),
+            PROTOCOL_VERSION => {
+                let mut parameters = HashMap::new();
+                loop {
+                    let name = get_cstr(&mut body)?;
+                    if name.is_empty() {
+                        return Ok(Some(Startup::Parameters(parameters)));
+                    }
+                    parameters.insert(name, get_cstr(&mut body)?);
+                }
+            }
+            code => {
+                return Err(PostgresError::Protocol(format!(
+                    "unsupported protocol version {}.{}",
+                    code >> 16,
+                    code & 0xffff
+                )))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Portal {
+    statement: Option<Statement>,
+    formats: Vec<i16>,
+}
+
+struct Connection<'a, S, B: ?Sized> {
+    stream: S,
+    backend: &'a B,
+    key: SessionKey,
+    out: BytesMut,
+    // prepared statements by name, None for an empty query
+    statements: HashMap<String, Option<Statement>>,
+    portals: HashMap<String, Portal>,
+    // after an error of the extended protocol messages are skipped until a Sync
+    failed: bool,
+}
+
+impl<S, B> Connection<'_, S, B>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: Backend + ?Sized,
+{
+    async fn run(&mut self) -> Result<(), PostgresError> {
+        while let Some((tag, body)) = read_message(&mut self.stream, MAX_MESSAGE_LEN).await? {
+            if self.failed && tag != b'S' {
+                continue;
+            }
+            let result = match tag {
+                b'Q' => self.simple_query(body).await,
+                b'P' => self.parse(body),
+                b'B' => self.bind(body),
+                b'D' => self.describe(body).await,
+                b'E' => self.execute(body).await,
+                b'C' => self.close(body),
+                b'S' => {
+                    self.failed = false;
+                    self.ready();
+                    Ok(())
+                }
+                b'H' => Ok(()),
+                b'X' => return Ok(()),
+                tag => Err(PostgresError::Protocol(format!(
+                    "unexpected message {}",
+                    tag as char
+                ))),
+            };
+            match result {
+                Ok(()) => {}
+                Err(PostgresError::Io(err)) => return Err(err.into()),
+                Err(err) => {
+                    error_response(&mut self.out, "ERROR", &err);
+                    if tag == b'Q' {
+                        self.ready();
+                    } else {
+                        self.failed = true;
+                    }
+                }
+            }
+            if matches!(tag, b'Q' | b'S' | b'H') {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn simple_query(&mut self, mut body: Bytes) -> Result<(), PostgresError> {
+        let sql = get_cstr(&mut body)?;
+        let statements = parse(&sql)?;
+        if statements.is_empty() {
+            message(&mut self.out, b'I', |_| {});
+        }
+        for statement in statements {
+            if let Statement::Command(tag) = statement {
+                self.command_complete(tag);
+                continue;
+            }
+            let rows = self.rows(&statement).await?;
+            let schema = rows.schema();
+            let formats = vec![Format::Text; schema.fields().len()];
+            row_description(&mut self.out, &schema, &formats);
+            let count = self.send_rows(rows, &formats).await?;
+            self.command_complete(&format!("SELECT {count}"));
+        }
+        self.ready();
+        Ok(())
+    }
+
+    fn parse(&mut self, mut body: Bytes) -> Result<(), PostgresError> {
+        let name = get_cstr(&mut body)?;
+        let sql = get_cstr(&mut body)?;
+        if get_i16(&mut body)? > 0 {
+            return Err(PostgresError::Unsupported(
+                "query parameters are not supported".to_owned(),
+            ));
+        }
+        let mut statements = parse(&sql)?;
+        if statements.len() > 1 {
+            return Err(PostgresError::Protocol(
+                "cannot insert multiple commands into a prepared statement".to_owned(),
+            ));
+        }
+        self.statements.insert(name, statements.pop());
+        message(&mut self.out, b'1', |_| {});
+        Ok(())
+    }
+
+    fn bind(&mut self, mut body: Bytes) -> Result<(), PostgresError> {
+        let portal = get_cstr(&mut body)?;
+        let name = get_cstr(&mut body)?;
+        for _ in 0..get_i16(&mut body)? {
+            get_i16(&mut body)?;
+        }
+        if get_i16(&mut body)? > 0 {
+            return Err(PostgresError::Unsupported(
+                "query parameters are not supported".to_owned(),
+            ));
+        }
+        let formats = (0..get_i16(&mut body)?)
+            .map(|_| get_i16(&mut body))
+            .collect::<Result<_, _>>()?;
+        let statement = self.statement(&name)?;
+        self.portals.insert(portal, Portal { statement, formats });
+        message(&mut self.out, b'2', |_| {});
+        Ok(())
+    }
+
+    async fn describe(&mut self, mut body: Bytes) -> Result<(), PostgresError> {
+        let kind = get_u8(&mut body)?;
+        let name = get_cstr(&mut body)?;
+        let (statement, formats) = match kind {
+            b'S' => {
+                // parameters are not supported, so a statement never has any
+                message(&mut self.out, b't', |out| out.put_i16(0));
+                (self.statement(&name)?, Vec::new())
+            }
+            _ => {
+                let portal = self.portal(&name)?;
+                (portal.statement, portal.formats)
+            }
+        };
+        let schema = match &statement {
+            Some(Statement::Query(sql)) => Some(self.backend.schema(&self.key, sql).await?),
+            Some(Statement::Value { column, value }) => Some(value_batch(column, value).schema()),
+            Some(Statement::Command(_)) | None => None,
+        };
+        match schema {
+            Some(schema) => {
+                let formats = column_formats(&formats, schema.fields().len())?;
+                row_description(&mut self.out, &schema, &formats);
+            }
+            None => message(&mut self.out, b'n', |_| {}),
+        }
+        Ok(())
+    }
+
+    async fn execute(&mut self, mut body: Bytes) -> Result<(), PostgresError> {
+        let name = get_cstr(&mut body)?;
+        // a row limit is not supported, every row is sent
+        let _max_rows = get_i32(&mut body)?;
+        let portal = self.portal(&name)?;
+        match portal.statement {
+            None => message(&mut self.out, b'I', |_| {}),
+            Some(Statement::Command(tag)) => self.command_complete(tag),
+            Some(statement) => {
+                let rows = self.rows(&statement).await?;
+                let formats = column_formats(&portal.formats, rows.schema().fields().len())?;
+                let count = self.send_rows(rows, &formats).await?;
+                self.command_complete(&format!("SELECT {count}"));
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, mut body: Bytes) -> Result<(), PostgresError> {
+        let kind = get_u8(&mut body)?;
+        let name = get_cstr(&mut body)?;
+        match kind {
+            b'S' => {
+                self.statements.remove(&name);
+            }
+            _ => {
+                self.portals.remove(&name);
+            }
+        }
+        message(&mut self.out, b'3', |_| {});
+        Ok(())
+    }
+
+    fn statement(&self, name: &str) -> Result<Option<Statement>, PostgresError> {
+        self.statements.get(name).cloned().ok_or_else(|| {
+            PostgresError::Protocol(format!("prepared statement \"{name}\" does not exist"))
+        })
+    }
+
+    fn portal(&self, name: &str) -> Result<Portal, PostgresError> {
+        self.portals
+            .get(name)
+            .cloned()
+            .ok_or_else(|| PostgresError::Protocol(format!("portal \"{name}\" does not exist")))
+    }
+
+    async fn rows(
+        &self,
+        statement: &Statement,
+    ) -> Result<SendableRecordBatchStream, PostgresError> {
+        match statement {
+            Statement::Query(sql) => self.backend.execute(&self.key, sql).await,
+            Statement::Value { column, value } => {
+                let batch = value_batch(column, value);
+                Ok(Box::pin(RecordBatchStreamAdapter::new(
+                    batch.schema(),
+                    futures::stream::iter([Ok(batch)]),
+                )))
+            }
+            Statement::Command(_) => unreachable!("commands have no rows"),
+        }
+    }
+
+    /// Sends the rows of `rows` as they are computed, returns the number of rows sent
+    async fn send_rows(
+        &mut self,
+        mut rows: SendableRecordBatchStream,
+        formats: &[Format],
+    ) -> Result<usize, PostgresError> {
+        let mut count = 0;
+        while let Some(batch) = rows.try_next().await? {
+            data_rows(&mut self.out, &batch, formats)?;
+            count += batch.num_rows();
+            if self.out.len() >= FLUSH_THRESHOLD {
+                self.flush().await?;
+            }
+        }
+        Ok(count)
+    }
+
+    fn command_complete(&mut self, tag: &str) {
+        message(&mut self.out, b'C', |out| put_cstr(out, tag));
+    }
+
+    fn ready(&mut self) {
+        // every statement runs on its own, a transaction is never open
+        message(&mut self.out, b'Z', |out| out.put_u8(b'I'));
+    }
+
+    async fn flush(&mut self) -> Result<(), PostgresError> {
+        self.stream.write_all(&self.out.split()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+fn row_description(out: &mut BytesMut, schema: &Schema, formats: &[Format]) {
+    message(out, b'T', |out| {
+        out.put_i16(schema.fields().len() as i16);
+        for (field, format) in schema.fields().iter().zip(formats) {
+            let oid = type_oid(field.data_type());
+            put_cstr(out, field.name());
+            // not a column of a table
+            out.put_i32(0);
+            out.put_i16(0);
+            out.put_i32(oid);
+            out.put_i16(type_size(oid));
+            out.put_i32(-1);
+            out.put_i16(*format as i16);
+        }
+    });
+}
+
+fn data_rows(
+    out: &mut BytesMut,
+    batch: &RecordBatch,
+    formats: &[Format],
+) -> Result<(), PostgresError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(formats)
+        .map(|(array, format)| encode_column(array, *format))
+        .collect::<Result<Vec<_>, _>>()?;
+    for row in 0..batch.num_rows() {
+        message(out, b'D', |out| {
+            out.put_i16(columns.len() as i16);
+            for column in &columns {
+                match &column[row] {
+                    Some(value) => {
+                        out.put_i32(value.len() as i32);
+                        out.put_slice(value);
+                    }
+                    None => out.put_i32(-1),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+fn error_response(out: &mut BytesMut, severity: &str, err: &PostgresError) {
+    let text = err.to_string();
+    message(out, b'E', |out| {
+        for (field, value) in [
+            (b'S', severity),
+            (b'V', severity),
+            (b'C', err.code()),
+            (b'M', text.as_str()),
+        ] {
+            out.put_u8(field);
+            put_cstr(out, value);
+        }
+        out.put_u8(0);
+    });
+}
+
+/// Appends a message of type `tag` with the body written by `body`
+fn message(out: &mut BytesMut, tag: u8, body: impl FnOnce(&mut BytesMut)) {
+    out.put_u8(tag);
+    let start = out.len();
+    out.put_i32(0);
+    body(out);
+    let len = (out.len() - start) as i32;
+    out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn put_cstr(out: &mut BytesMut, value: &str) {
+    out.put_slice(value.as_bytes());
+    out.put_u8(0);
+}
+
+/// Next message of at most `max_len` bytes, None when the client closed the connection
+async fn read_message<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_len: usize,
+) -> Result<Option<(u8, Bytes)>, PostgresError> {
+    let tag = match stream.read_u8().await {
+        Ok(tag) => tag,
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let len = stream.read_i32().await?;
+    Ok(Some((tag, read_body(stream, len, max_len).await?)))
+}
+
+async fn read_body<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    len: i32,
+    max_len: usize,
+) -> Result<Bytes, PostgresError> {
+    let body_len = usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_sub(4))
+        .filter(|len| *len <= max_len)
+        .ok_or_else(|| PostgresError::Protocol(format!("invalid message length {len}")))?;
+    let mut body = vec![0; body_len];
+    stream.read_exact(&mut body).await?;
+    Ok(body.into())
+}
+
+fn get_cstr(body: &mut Bytes) -> Result<String, PostgresError> {
+    let end = body
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| PostgresError::Protocol("unterminated string".to_owned()))?;
+    let value = String::from_utf8(body.split_to(end).to_vec())
+        .map_err(|_| PostgresError::Protocol("string is not utf-8".to_owned()))?;
+    body.advance(1);
+    Ok(value)
+}
+
+fn get_u8(body: &mut Bytes) -> Result<u8, PostgresError> {
+    if body.remaining() < 1 {
+        return Err(PostgresError::Protocol("message too short".to_owned()));
+    }
+    Ok(body.get_u8())
+}
+
+fn get_i16(body: &mut Bytes) -> Result<i16, PostgresError> {
+    if body.remaining() < 2 {
+        return Err(PostgresError::Protocol("message too short".to_owned()));
+    }
+    Ok(body.get_i16())
+}
+
+fn get_i32(body: &mut Bytes) -> Result<i32, PostgresError> {
+    if body.remaining() < 4 {
+        return Err(PostgresError::Protocol("message too short".to_owned()));
+    }
+    Ok(body.get_i32())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PostgresError {
+    #[error("password authentication failed for user \"{0}\"")]
+    Authentication(String),
+    #[error("the server requires an encrypted connection, connect with sslmode=require")]
+    Encryption,
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("protocol violation: {0}")]
+    Protocol(String),
+    #[error("the client did not log in in time")]
+    Timeout,
+    #[error("{0}")]
+    Parse(#[from] ParserError),
+    #[error("{0}")]
+    Query(#[from] QueryError),
+    #[error("{0}")]
+    Execute(#[from] ExecuteError),
+    #[error("{0}")]
+    Datafusion(#[from] DataFusionError),
+    #[error("{0}")]
+    Arrow(#[from] ArrowError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl PostgresError {
+    /// SQLSTATE of the error
+    fn code(&self) -> &'static str {
+        match self {
+            PostgresError::Authentication(_) => "28P01",
+            PostgresError::Encryption => "28000",
+            PostgresError::Unsupported(_) => "0A000",
+            PostgresError::Protocol(_) => "08P01",
+            PostgresError::Parse(_) => "42601",
+            PostgresError::Query(QueryError::Unauthorized) => "42501",
+            PostgresError::Query(QueryError::RangeTooLong { .. }) => "54000",
+            _ => "XX000",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use bytes::{Buf, BufMut, Bytes, BytesMut};
+    use datafusion::arrow::array::{
+        ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMillisecondArray,
+    };
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::execution::context::SessionContext;
+    use datafusion::execution::SendableRecordBatchStream;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use rustls::server::ResolvesServerCertUsingSni;
+    use rustls::ServerConfig;
+    use tokio::net::TcpListener;
+    use tokio_postgres::error::SqlState;
+    use tokio_postgres::NoTls;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{
+        encode_column, get_cstr, message, put_cstr, read_message, reads_information_schema, serve,
+        serve_until, startup, type_oid, Backend, Format, ParseableBackend, PostgresError, Startup,
+        BOOL, FLOAT8, INT8, MAX_MESSAGE_LEN, MAX_STARTUP_MESSAGE_LEN, TEXT, TIMESTAMP, TIMESTAMPTZ,
+    };
+    use crate::rbac::map::{self, Roles, SessionKey, Sessions, Users, ROLES, SESSIONS, USERS};
+    use crate::rbac::role::model::DefaultPrivilege;
+    use crate::rbac::user::User;
+
+    struct TestBackend(SessionContext);
+
+    #[async_trait]
+    impl Backend for TestBackend {
+        fn authenticate(&self, key: &SessionKey) -> bool {
+            matches!(key, SessionKey::BasicAuth { password, .. } if password == "secret")
+        }
+
+        async fn schema(&self, _: &SessionKey, sql: &str) -> Result<SchemaRef, PostgresError> {
+            let plan = self.0.state().create_logical_plan(sql).await?;
+            Ok(Arc::new(Schema::from(plan.schema().as_ref())))
+        }
+
+        async fn execute(
+            &self,
+            _: &SessionKey,
+            sql: &str,
+        ) -> Result<SendableRecordBatchStream, PostgresError> {
+            Ok(self.0.sql(sql).await?.execute_stream().await?)
+        }
+    }
+
+    fn backend() -> TestBackend {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("status", DataType::Int64, true),
+            Field::new("latency", DataType::Float64, true),
+            Field::new("ok", DataType::Boolean, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![Some("a"), Some("b"), None])),
+                Arc::new(Int64Array::from(vec![200, 500, 404])),
+                Arc::new(Float64Array::from(vec![1.5, 20.25, 3.0])),
+                Arc::new(BooleanArray::from(vec![true, false, false])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_batch("app", batch).unwrap();
+        TestBackend(ctx)
+    }
+
+    /// Minimal client speaking the protocol as libpq does
+    struct Client(DuplexStream);
+
+    impl Client {
+        async fn connect(password: &str) -> (Client, Vec<(u8, Bytes)>) {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(async move {
+                let _ = serve(server, &backend(), None).await;
+            });
+            let mut client = Client(client);
+
+            // encryption is declined before startup
+            client
+                .0
+                .write_all(&[0, 0, 0, 8, 4, 210, 22, 47])
+                .await
+                .unwrap();
+            assert_eq!(client.0.read_u8().await.unwrap(), b'N');
+
+            let mut body = BytesMut::new();
+            body.put_i32(196608);
+            put_cstr(&mut body, "user");
+            put_cstr(&mut body, "admin");
+            body.put_u8(0);
+            client.0.write_i32(body.len() as i32 + 4).await.unwrap();
+            client.0.write_all(&body).await.unwrap();
+
+            let (tag, mut request) = client.recv().await;
+            assert_eq!((tag, request.get_i32()), (b'R', 3));
+            client.send(b'p', |out| put_cstr(out, password)).await;
+            let messages = client.until(b'Z').await;
+            (client, messages)
+        }
+
+        async fn send(&mut self, tag: u8, body: impl FnOnce(&mut BytesMut)) {
+            let mut out = BytesMut::new();
+            message(&mut out, tag, body);
+            self.0.write_all(&out).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> (u8, Bytes) {
+            read_message(&mut self.0, MAX_MESSAGE_LEN)
+                .await
+                .unwrap()
+                .unwrap()
+        }
+
+        /// Messages up to and including the first of type `tag`, or until the server
+        /// closes the connection
+        async fn until(&mut self, tag: u8) -> Vec<(u8, Bytes)> {
+            let mut messages = Vec::new();
+            while let Some(message) = read_message(&mut self.0, MAX_MESSAGE_LEN).await.unwrap() {
+                let done = message.0 == tag;
+                messages.push(message);
+                if done {
+                    break;
+                }
+            }
+            messages
+        }
+
+        async fn query(&mut self, sql: &str) -> Vec<(u8, Bytes)> {
+            self.send(b'Q', |out| put_cstr(out, sql)).await;
+            self.until(b'Z').await
+        }
+    }
+
+    fn tags(messages: &[(u8, Bytes)]) -> String {
+        messages.iter().map(|(tag, _)| *tag as char).collect()
+    }
+
+    // name and type of each column of a RowDescription
+    fn columns(mut body: Bytes) -> Vec<(String, i32, i16)> {
+        (0..body.get_i16())
+            .map(|_| {
+                let name = get_cstr(&mut body).unwrap();
+                body.advance(6);
+                let oid = body.get_i32();
+                body.advance(6);
+                (name, oid, body.get_i16())
+            })
+            .collect()
+    }
+
+    fn values(mut body: Bytes) -> Vec<Option<Bytes>> {
+        (0..body.get_i16())
+            .map(|_| match body.get_i32() {
+                -1 => None,
+                len => Some(body.split_to(len as usize)),
+            })
+            .collect()
+    }
+
+    fn text_row(body: Bytes) -> Vec<Option<String>> {
+        values(body)
+            .into_iter()
+            .map(|value| value.map(|value| String::from_utf8(value.to_vec()).unwrap()))
+            .collect()
+    }
+
+    fn error_code(mut body: Bytes) -> String {
+        loop {
+            let field = body.get_u8();
+            let value = get_cstr(&mut body).unwrap();
+            if field == b'C' {
+                return value;
+            }
+        }
+    }
+
+    #[test]
+    fn arrow_values_are_encoded_as_postgres_values() {
+        assert_eq!(type_oid(&DataType::Int64), INT8);
+        assert_eq!(type_oid(&DataType::Utf8), TEXT);
+        assert_eq!(
+            type_oid(&DataType::Timestamp(TimeUnit::Millisecond, None)),
+            TIMESTAMP
+        );
+        assert_eq!(
+            type_oid(&DataType::Timestamp(TimeUnit::Second, Some("UTC".into()))),
+            TIMESTAMPTZ
+        );
+
+        let timestamps: ArrayRef = Arc::new(TimestampMillisecondArray::from(vec![
+            Some(1_700_000_000_123),
+            None,
+        ]));
+        assert_eq!(
+            encode_column(&timestamps, Format::Text).unwrap(),
+            [Some(b"2023-11-14 22:13:20.123".to_vec()), None]
+        );
+        // microseconds since 2000-01-01
+        assert_eq!(
+            encode_column(&timestamps, Format::Binary).unwrap(),
+            [Some(753_315_200_123_000i64.to_be_bytes().to_vec()), None]
+        );
+
+        let booleans: ArrayRef = Arc::new(BooleanArray::from(vec![true, false]));
+        assert_eq!(
+            encode_column(&booleans, Format::Text).unwrap(),
+            [Some(b"t".to_vec()), Some(b"f".to_vec())]
+        );
+        assert_eq!(
+            encode_column(&booleans, Format::Binary).unwrap(),
+            [Some(vec![1]), Some(vec![0])]
+        );
+    }
+
+    #[actix_web::test]
+    async fn queries_are_answered_over_the_simple_protocol() {
+        let (_, messages) = Client::connect("wrong").await;
+        assert_eq!(tags(&messages), "E");
+        assert_eq!(error_code(messages[0].1.clone()), "28P01");
+
+        let (mut client, messages) = Client::connect("secret").await;
+        assert_eq!(&tags(&messages)[..2], "RS");
+        assert!(tags(&messages).ends_with("KZ"));
+
+        let messages = client
+            .query("select host, status, latency, ok from app order by status")
+            .await;
+        assert_eq!(tags(&messages), "TDDDCZ");
+        assert_eq!(
+            columns(messages[0].1.clone()),
+            [
+                ("host".to_owned(), TEXT, 0),
+                ("status".to_owned(), INT8, 0),
+                ("latency".to_owned(), FLOAT8, 0),
+                ("ok".to_owned(), BOOL, 0),
+            ]
+        );
+        assert_eq!(
+            text_row(messages[1].1.clone()),
+            [Some("a"), Some("200"), Some("1.5"), Some("t")].map(|v| v.map(str::to_owned))
+        );
+        assert_eq!(
+            text_row(messages[2].1.clone()),
+            [None, Some("404"), Some("3.0"), Some("f")].map(|v| v.map(str::to_owned))
+        );
+        assert_eq!(&messages[4].1[..], b"SELECT 3\0");
+
+        // what clients send on connect
+        let messages = client
+            .query("SET datestyle = 'ISO'; SELECT version()")
+            .await;
+        assert_eq!(tags(&messages), "CTDCZ");
+        assert_eq!(&messages[0].1[..], b"SET\0");
+        assert!(text_row(messages[2].1.clone())[0]
+            .as_ref()
+            .unwrap()
+            .starts_with("PostgreSQL 14.0 on Parseable"));
+        let messages = client.query("SHOW TRANSACTION ISOLATION LEVEL").await;
+        assert_eq!(tags(&messages), "TDCZ");
+        assert_eq!(
+            text_row(messages[1].1.clone()),
+            [Some("read committed".to_owned())]
+        );
+
+        // writes are rejected and the connection stays usable
+        let messages = client.query("delete from app").await;
+        assert_eq!(tags(&messages), "EZ");
+        assert_eq!(error_code(messages[0].1.clone()), "0A000");
+        let messages = client.query("select count(*) as total from app").await;
+        assert_eq!(tags(&messages), "TDCZ");
+        assert_eq!(text_row(messages[1].1.clone()), [Some("3".to_owned())]);
+    }
+
+    #[actix_web::test]
+    async fn queries_are_answered_over_the_extended_protocol() {
+        let (mut client, _) = Client::connect("secret").await;
+
+        client
+            .send(b'P', |out| {
+                put_cstr(out, "");
+                put_cstr(
+                    out,
+                    "select status, host from app where ok = false order by status",
+                );
+                out.put_i16(0);
+            })
+            .await;
+        // binary results, as most drivers ask for
+        client
+            .send(b'B', |out| {
+                put_cstr(out, "");
+                put_cstr(out, "");
+                out.put_i16(0);
+                out.put_i16(0);
+                out.put_i16(1);
+                out.put_i16(1);
+            })
+            .await;
+        client
+            .send(b'D', |out| {
+                out.put_u8(b'P');
+                put_cstr(out, "");
+            })
+            .await;
+        client
+            .send(b'E', |out| {
+                put_cstr(out, "");
+                out.put_i32(0);
+            })
+            .await;
+        client.send(b'S', |_| {}).await;
+
+        let messages = client.until(b'Z').await;
+        assert_eq!(tags(&messages), "12TDDCZ");
+        assert_eq!(
+            columns(messages[2].1.clone()),
+            [("status".to_owned(), INT8, 1), ("host".to_owned(), TEXT, 1)]
+        );
+        assert_eq!(
+            values(messages[3].1.clone()),
+            [Some(Bytes::from(404i64.to_be_bytes().to_vec())), None]
+        );
+        assert_eq!(
+            values(messages[4].1.clone()),
+            [
+                Some(Bytes::from(500i64.to_be_bytes().to_vec())),
+                Some(Bytes::from_static(b"b"))
+            ]
+        );
+        assert_eq!(&messages[5].1[..], b"SELECT 2\0");
+
+        // after an error messages are skipped until the sync
+        client
+            .send(b'P', |out| {
+                put_cstr(out, "");
+                put_cstr(out, "insert into app values ('c', 200, 1.0, true)");
+                out.put_i16(0);
+            })
+            .await;
+        client
+            .send(b'E', |out| {
+                put_cstr(out, "");
+                out.put_i32(0);
+            })
+            .await;
+        client.send(b'S', |_| {}).await;
+        let messages = client.until(b'Z').await;
+        assert_eq!(tags(&messages), "EZ");
+        assert_eq!(error_code(messages[0].1.clone()), "0A000");
+    }
+
+    #[test]
+    fn only_tables_of_the_information_schema_are_introspected() {
+        assert!(reads_information_schema(
+            "select table_name from information_schema.tables"
+        ));
+        assert!(reads_information_schema(
+            "SELECT * FROM parseable.INFORMATION_SCHEMA.columns WHERE table_name = 'app'"
+        ));
+        assert!(!reads_information_schema(
+            "select * from app where host = 'information_schema.tables'"
+        ));
+        assert!(!reads_information_schema(
+            "select information_schema from app"
+        ));
+        assert!(!reads_information_schema("select * from app"));
+    }
+
+    #[actix_web::test]
+    async fn messages_before_login_are_limited() {
+        let startup_len = |len: usize| {
+            let mut message = BytesMut::new();
+            message.put_i32(len as i32 + 4);
+            message.put_i32(196608);
+            message
+        };
+
+        // the length is checked before anything is allocated or read
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(&startup_len(MAX_STARTUP_MESSAGE_LEN + 1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            startup(&mut server, false).await,
+            Err(PostgresError::Protocol(_))
+        ));
+
+        // a password as large as a query is refused
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let served = tokio::spawn(async move { serve(server, &backend(), None).await });
+        let mut client = Client(client);
+        let mut body = BytesMut::new();
+        body.put_i32(196608);
+        put_cstr(&mut body, "user");
+        put_cstr(&mut body, "admin");
+        body.put_u8(0);
+        client.0.write_i32(body.len() as i32 + 4).await.unwrap();
+        client.0.write_all(&body).await.unwrap();
+        assert_eq!(client.recv().await.0, b'R');
+        client.0.write_u8(b'p').await.unwrap();
+        client
+            .0
+            .write_i32(MAX_STARTUP_MESSAGE_LEN as i32 + 5)
+            .await
+            .unwrap();
+        assert!(matches!(
+            served.await.unwrap(),
+            Err(PostgresError::Protocol(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn connections_are_closed_when_login_takes_too_long() {
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_millis(50);
+
+        // silent after connecting
+        let (_client, server) = tokio::io::duplex(1024);
+        assert!(matches!(
+            serve_until(server, &backend(), None, deadline).await,
+            Err(PostgresError::Timeout)
+        ));
+
+        // started but never sends the password
+        let (client, server) = tokio::io::duplex(1024);
+        let served =
+            tokio::spawn(async move { serve_until(server, &backend(), None, deadline).await });
+        let mut client = Client(client);
+        let mut body = BytesMut::new();
+        body.put_i32(196608);
+        put_cstr(&mut body, "user");
+        put_cstr(&mut body, "admin");
+        body.put_u8(0);
+        client.0.write_i32(body.len() as i32 + 4).await.unwrap();
+        client.0.write_all(&body).await.unwrap();
+        assert_eq!(client.recv().await.0, b'R');
+        assert!(matches!(served.await.unwrap(), Err(PostgresError::Timeout)));
+    }
+
+    #[actix_web::test]
+    async fn encryption_is_required_when_tls_is_configured() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(&[0, 0, 0, 8, 4, 210, 22, 47])
+            .await
+            .unwrap();
+        assert!(matches!(
+            startup(&mut server, true).await,
+            Ok(Some(Startup::Tls))
+        ));
+        assert_eq!(client.read_u8().await.unwrap(), b'S');
+
+        // a certificate is never needed, logging in without TLS is refused before
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(ResolvesServerCertUsingSni::new()));
+        let tls = TlsAcceptor::from(Arc::new(config));
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let _ = serve(server, &backend(), Some(&tls)).await;
+        });
+        let mut client = Client(client);
+        let mut body = BytesMut::new();
+        body.put_i32(196608);
+        put_cstr(&mut body, "user");
+        put_cstr(&mut body, "admin");
+        body.put_u8(0);
+        client.0.write_i32(body.len() as i32 + 4).await.unwrap();
+        client.0.write_all(&body).await.unwrap();
+        let messages = client.until(b'Z').await;
+        assert_eq!(tags(&messages), "E");
+        assert_eq!(error_code(messages[0].1.clone()), "28000");
+    }
+
+    /// Logs in as the server does, through the users and roles of the rbac maps, and
+    /// answers queries from the tables of `TestBackend`
+    struct RbacBackend(TestBackend);
+
+    #[async_trait]
+    impl Backend for RbacBackend {
+        fn authenticate(&self, key: &SessionKey) -> bool {
+            ParseableBackend.authenticate(key)
+        }
+
+        async fn schema(&self, key: &SessionKey, sql: &str) -> Result<SchemaRef, PostgresError> {
+            self.0.schema(key, sql).await
+        }
+
+        async fn execute(
+            &self,
+            key: &SessionKey,
+            sql: &str,
+        ) -> Result<SendableRecordBatchStream, PostgresError> {
+            self.0.execute(key, sql).await
+        }
+    }
+
+    /// Credentials of a user allowed to query the app stream
+    fn reader() -> (String, String) {
+        ROLES.get_or_init(|| std::sync::RwLock::new(Roles::default()));
+        USERS.get_or_init(|| std::sync::RwLock::new(Users::default()));
+        SESSIONS.get_or_init(|| std::sync::RwLock::new(Sessions::default()));
+        map::mut_roles().insert(
+            "app_reader".to_owned(),
+            vec![DefaultPrivilege::Reader {
+                stream: "app".to_owned(),
+                tag: None,
+            }],
+        );
+        let (mut user, password) = User::new_basic("postgres_reader".to_owned());
+        user.roles.insert("app_reader".to_owned());
+        map::mut_users().insert(user);
+        ("postgres_reader".to_owned(), password)
+    }
+
+    #[actix_web::test]
+    async fn postgres_clients_log_in_as_parseable_users() {
+        let (username, password) = reader();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let _ = serve(socket, &RbacBackend(backend()), None).await;
+                });
+            }
+        });
+        let config = |password: &str| {
+            format!(
+                "host=127.0.0.1 port={port} user={username} password={password} dbname=parseable"
+            )
+        };
+
+        let err = tokio_postgres::connect(&config("wrong"), NoTls)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), Some(&SqlState::INVALID_PASSWORD));
+
+        let (client, connection) = tokio_postgres::connect(&config(&password), NoTls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        // prepared and sent in binary, as the driver does
+        let rows = client
+            .query(
+                "select status, host from app where ok = false order by status",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get::<_, i64>("status"), 404);
+        assert_eq!(rows[0].get::<_, Option<&str>>("host"), None);
+        assert_eq!(rows[1].get::<_, i64>("status"), 500);
+        assert_eq!(rows[1].get::<_, Option<&str>>("host"), Some("b"));
+
+        let err = client.query("delete from app", &[]).await.err().unwrap();
+        assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+        let total: i64 = client
+            .query_one("select count(*) from app", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(total, 3);
+    }
+}
@@ -23,6 +23,7 @@ mod banner;
 mod catalog;
 mod cli;
 mod event;
+mod export;
 mod handlers;
 mod livetail;
 mod localcache;
@@ -35,6 +36,7 @@ mod query;
 mod querycache;
 mod rbac;
 mod response;
+mod shutdown;
 mod static_schema;
 mod stats;
 mod storage;
@@ -51,11 +53,19 @@ use option::{Mode, CONFIG};
 use crate::handlers::http::modal::{
     ingest_server::IngestServer, query_server::QueryServer, server::Server,
 };
-pub const STORAGE_UPLOAD_INTERVAL: u32 = 60;
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
+    CONFIG.storage().validate_config()?;
+
+    if CONFIG.parseable.check_storage {
+        let store = CONFIG.storage().get_raw_store()?;
+        let report = storage::diagnostic::check_storage(store.as_ref()).await;
+        println!("{}\n{report}", CONFIG.storage().get_endpoint());
+        anyhow::ensure!(report.passed(), "storage check failed");
+        return Ok(());
+    }
 
     // these are empty ptrs so mem footprint should be minimal
     let server: Arc<dyn ParseableServer> = match CONFIG.parseable.mode {
@@ -19,6 +19,7 @@
 use arrow_array::RecordBatch;
 use arrow_schema::{Field, Fields, Schema};
 use chrono::Local;
+use chrono_tz::Tz;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
@@ -26,11 +27,18 @@ use std::sync::{Arc, RwLock};
 
 use self::error::stream_info::{CheckAlertError, LoadError, MetadataError};
 use crate::alerts::Alerts;
+use crate::event::rate_limit::RateLimit;
 use crate::metrics::{
     EVENTS_INGESTED, EVENTS_INGESTED_SIZE, EVENTS_INGESTED_SIZE_TODAY, EVENTS_INGESTED_TODAY,
     LIFETIME_EVENTS_INGESTED, LIFETIME_EVENTS_INGESTED_SIZE,
 };
-use crate::storage::{LogStream, ObjectStorage, StorageDir};
+use crate::option::CONFIG;
+use crate::storage::{
+    column_access::ColumnAccess, object_storage::schema_path,
+    partition_granularity::PartitionGranularity, rollup::Rollup, schema_cache::SchemaCache,
+    schema_mode::SchemaMode, sort_order::SortColumn, statistics_level::StatisticsLevel, LogStream,
+    ObjectStorage, StorageDir, StorageMetadata,
+};
 use crate::utils::arrow::MergedRecordReader;
 use derive_more::{Deref, DerefMut};
 
@@ -38,6 +46,9 @@ use derive_more::{Deref, DerefMut};
 // A read-write lock to allow multiple reads while and isolated write
 pub static STREAM_INFO: Lazy<StreamInfo> = Lazy::new(StreamInfo::default);
 
+// Query facing alias names mapped to the stream they point to
+pub static STREAM_ALIASES: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(RwLock::default);
+
 #[derive(Debug, Deref, DerefMut, Default)]
 pub struct StreamInfo(RwLock<HashMap<String, LogStreamMetadata>>);
 
@@ -52,6 +63,14 @@ pub struct LogStreamMetadata {
     pub time_partition_limit: Option<String>,
     pub custom_partition: Option<String>,
     pub static_schema_flag: Option<String>,
+    pub partition_time_zone: Option<Tz>,
+    pub sort_order: Vec<SortColumn>,
+    pub column_access: Option<ColumnAccess>,
+    pub statistics_level: StatisticsLevel,
+    pub schema_mode: SchemaMode,
+    pub partition_granularity: PartitionGranularity,
+    pub rollup: Option<Rollup>,
+    pub rate_limit: Option<RateLimit>,
 }
 
 // It is very unlikely that panic will occur when dealing with metadata.
@@ -111,6 +130,162 @@ impl StreamInfo {
             .map(|metadata| metadata.custom_partition.clone())
     }
 
+    pub fn get_partition_time_zone(&self, stream_name: &str) -> Result<Option<Tz>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.partition_time_zone)
+    }
+
+    pub fn get_sort_order(&self, stream_name: &str) -> Result<Vec<SortColumn>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.sort_order.clone())
+    }
+
+    pub fn set_sort_order(
+        &self,
+        stream_name: &str,
+        sort_order: Vec<SortColumn>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.sort_order = sort_order;
+            })
+    }
+
+    pub fn get_column_access(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<ColumnAccess>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.column_access.clone())
+    }
+
+    pub fn set_column_access(
+        &self,
+        stream_name: &str,
+        column_access: Option<ColumnAccess>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.column_access = column_access;
+            })
+    }
+
+    pub fn get_statistics_level(
+        &self,
+        stream_name: &str,
+    ) -> Result<StatisticsLevel, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.statistics_level)
+    }
+
+    pub fn set_statistics_level(
+        &self,
+        stream_name: &str,
+        statistics_level: StatisticsLevel,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.statistics_level = statistics_level;
+            })
+    }
+
+    pub fn get_rollup(&self, stream_name: &str) -> Result<Option<Rollup>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.rollup.clone())
+    }
+
+    pub fn set_rollup(
+        &self,
+        stream_name: &str,
+        rollup: Option<Rollup>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.rollup = rollup;
+            })
+    }
+
+    pub fn get_rate_limit(&self, stream_name: &str) -> Result<Option<RateLimit>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.rate_limit)
+    }
+
+    pub fn set_rate_limit(
+        &self,
+        stream_name: &str,
+        rate_limit: Option<RateLimit>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.rate_limit = rate_limit;
+            })
+    }
+
+    pub fn get_schema_mode(&self, stream_name: &str) -> Result<SchemaMode, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.schema_mode)
+    }
+
+    pub fn set_schema_mode(
+        &self,
+        stream_name: &str,
+        schema_mode: SchemaMode,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.schema_mode = schema_mode;
+            })
+    }
+
+    pub fn get_partition_granularity(
+        &self,
+        stream_name: &str,
+    ) -> Result<PartitionGranularity, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.partition_granularity)
+    }
+
+    pub fn set_partition_granularity(
+        &self,
+        stream_name: &str,
+        partition_granularity: PartitionGranularity,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.partition_granularity = partition_granularity;
+            })
+    }
+
     pub fn get_static_schema_flag(
         &self,
         stream_name: &str,
@@ -180,6 +355,7 @@ impl StreamInfo {
         time_partition_limit: String,
         custom_partition: String,
         static_schema_flag: String,
+        partition_time_zone: Option<Tz>,
         static_schema: HashMap<String, Arc<Field>>,
     ) {
         let mut map = self.write().expect(LOCK_EXPECT);
@@ -209,6 +385,7 @@ impl StreamInfo {
             } else {
                 Some(static_schema_flag)
             },
+            partition_time_zone,
             schema: if static_schema.is_empty() {
                 HashMap::new()
             } else {
@@ -230,8 +407,29 @@ impl StreamInfo {
         // .schema file could be empty in that case it will be treated as an uninitialized stream.
         // return error in case of an error from object storage itself.
 
+        // schemas are served from the local cache and checked against the store in the background
+        let schema_cache = SchemaCache::global();
+        let mut cached = vec![];
         for stream in storage.list_streams().await? {
-            self.upsert_stream_info(storage, stream).await?;
+            let schema = match schema_cache.get(&stream.name) {
+                Some(schema) => {
+                    cached.push(stream.name.clone());
+                    schema
+                }
+                None => match schema_cache
+                    .load(storage, &stream.name, &schema_path(&stream.name))
+                    .await
+                {
+                    Ok(schema) => schema,
+                    // e.g. the schema of an ingestor is copied from the base schema on its first start
+                    Err(_) => storage.get_schema_on_server_start(&stream.name).await?,
+                },
+            };
+            self.insert_stream_info(storage, stream, schema).await?;
+        }
+
+        if !cached.is_empty() {
+            tokio::spawn(refresh_cached_schemas(cached));
         }
         Ok(())
     }
@@ -241,8 +439,17 @@ impl StreamInfo {
         storage: &(impl ObjectStorage + ?Sized),
         stream: LogStream,
     ) -> Result<(), LoadError> {
-        let alerts = storage.get_alerts(&stream.name).await?;
         let schema = storage.get_schema_on_server_start(&stream.name).await?;
+        self.insert_stream_info(storage, stream, schema).await
+    }
+
+    async fn insert_stream_info(
+        &self,
+        storage: &(impl ObjectStorage + ?Sized),
+        stream: LogStream,
+        schema: Schema,
+    ) -> Result<(), LoadError> {
+        let alerts = storage.get_alerts(&stream.name).await?;
         let meta = storage.get_stream_metadata(&stream.name).await?;
 
         let schema = update_schema_from_staging(&stream.name, schema);
@@ -263,6 +470,22 @@ impl StreamInfo {
             time_partition_limit: meta.time_partition_limit,
             custom_partition: meta.custom_partition,
             static_schema_flag: meta.static_schema_flag,
+            partition_time_zone: meta
+                .partition_time_zone
+                .and_then(|zone| match zone.parse() {
+                    Ok(zone) => Some(zone),
+                    Err(err) => {
+                        log::warn!("invalid partition time zone for {}: {err}", stream.name);
+                        None
+                    }
+                }),
+            sort_order: meta.sort_order,
+            column_access: meta.column_access,
+            statistics_level: meta.statistics_level,
+            schema_mode: meta.schema_mode,
+            partition_granularity: meta.partition_granularity,
+            rollup: meta.rollup,
+            rate_limit: meta.rate_limit,
         };
 
         let mut map = self.write().expect(LOCK_EXPECT);
@@ -309,6 +532,59 @@ impl StreamInfo {
     }
 }
 
+pub fn load_stream_aliases(metadata: &StorageMetadata) {
+    STREAM_ALIASES
+        .write()
+        .expect(LOCK_EXPECT)
+        .clone_from(&metadata.stream_aliases);
+}
+
+/// Resolves a query facing name to the stream it points to.
+/// Names that are not aliases are returned unchanged.
+pub fn resolve_stream_alias(name: &str) -> String {
+    STREAM_ALIASES
+        .read()
+        .expect(LOCK_EXPECT)
+        .get(name)
+        .cloned()
+        .unwrap_or_else(|| name.to_owned())
+}
+
+pub fn is_stream_alias(name: &str) -> bool {
+    STREAM_ALIASES.read().expect(LOCK_EXPECT).contains_key(name)
+}
+
+/// Checks schemas served from the cache on startup against the store, fields
+/// of a schema changed in the store since it was cached are added to the stream
+async fn refresh_cached_schemas(streams: Vec<String>) {
+    let storage = CONFIG.storage().get_object_store();
+    let schema_cache = SchemaCache::global();
+    for stream_name in streams {
+        let schema = match schema_cache
+            .refresh(&*storage, &stream_name, &schema_path(&stream_name))
+            .await
+        {
+            Ok(Some(schema)) => schema,
+            Ok(None) => continue,
+            Err(err) => {
+                log::warn!("failed to check cached schema of stream {stream_name}: {err}");
+                continue;
+            }
+        };
+
+        log::info!("cached schema of stream {stream_name} was outdated, updating it");
+        let mut map = STREAM_INFO.write().expect(LOCK_EXPECT);
+        if let Some(metadata) = map.get_mut(&stream_name) {
+            for field in schema.fields() {
+                metadata
+                    .schema
+                    .entry(field.name().clone())
+                    .or_insert_with(|| field.clone());
+            }
+        }
+    }
+}
+
 fn update_schema_from_staging(stream_name: &str, current_schema: Schema) -> Schema {
     let staging_files = StorageDir::new(stream_name).arrow_files();
     let schema = MergedRecordReader::try_new(&staging_files)
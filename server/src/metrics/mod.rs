@@ -26,7 +26,9 @@ use clokwerk::AsyncScheduler;
 use clokwerk::Job;
 use clokwerk::TimeUnits;
 use once_cell::sync::Lazy;
-use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+};
 use std::thread;
 use std::time::Duration;
 
@@ -158,6 +160,100 @@ pub static STAGING_FILES: Lazy<IntGaugeVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
+pub static STAGING_FREE_DISK: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new(
+            "staging_free_disk",
+            "Free space in bytes on the staging disk",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static PENDING_UPLOAD_FILES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "pending_upload_files",
+            "Staged parquet files waiting to be uploaded",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static PENDING_UPLOAD_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "pending_upload_size",
+            "Size in bytes of staged parquet files waiting to be uploaded",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static INGEST_RATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "ingest_rate",
+            "Records or bytes per second recently ingested into a stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "unit"],
+    )
+    .expect("metric can be created")
+});
+
+pub static INGEST_THROTTLED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "ingest_throttled",
+            "Ingest requests rejected for exceeding the rate limit of a stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static UPLOAD_BACKPRESSURE: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new(
+            "upload_backpressure",
+            "1 while ingestion is rejected because of the upload backlog",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static FUTURE_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "future_events",
+            "Events dated beyond the max clock skew, by the action taken",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "action"],
+    )
+    .expect("metric can be created")
+});
+
+pub static QUARANTINED_FILES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "quarantined_files",
+            "Parquet files moved to quarantine after failing to decode",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
 pub static QUERY_EXECUTE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
     HistogramVec::new(
         HistogramOpts::new("query_execute_time", "Query execute time").namespace(METRICS_NAMESPACE),
@@ -222,6 +318,30 @@ fn custom_metrics(registry: &Registry) {
     registry
         .register(Box::new(STAGING_FILES.clone()))
         .expect("metric can be registered");
+    registry
+        .register(Box::new(STAGING_FREE_DISK.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(PENDING_UPLOAD_FILES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(PENDING_UPLOAD_SIZE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(INGEST_RATE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(INGEST_THROTTLED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(UPLOAD_BACKPRESSURE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(FUTURE_EVENTS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(QUARANTINED_FILES.clone()))
+        .expect("metric can be registered");
     registry
         .register(Box::new(QUERY_EXECUTE_TIME.clone()))
         .expect("metric can be registered");
@@ -51,7 +51,7 @@ pub mod localfs {
 pub mod s3 {
     use crate::{metrics::METRICS_NAMESPACE, storage::S3Config};
     use once_cell::sync::Lazy;
-    use prometheus::{HistogramOpts, HistogramVec};
+    use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts};
 
     use super::StorageMetrics;
 
@@ -64,6 +64,15 @@ pub mod s3 {
         .expect("metric can be created")
     });
 
+    pub static STREAM_REQUEST_RESPONSE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+        HistogramVec::new(
+            HistogramOpts::new("s3_stream_response_time", "S3 Request Latency by stream")
+                .namespace(METRICS_NAMESPACE),
+            &["stream", "method", "status"],
+        )
+        .expect("metric can be created")
+    });
+
     pub static QUERY_LAYER_STORAGE_REQUEST_RESPONSE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
         HistogramVec::new(
             HistogramOpts::new("query_s3_response_time", "S3 Request Latency")
@@ -73,6 +82,66 @@ pub mod s3 {
         .expect("metric can be created")
     });
 
+    pub static REQUEST_PERMITS_IN_USE: Lazy<IntGaugeVec> = Lazy::new(|| {
+        IntGaugeVec::new(
+            Opts::new(
+                "s3_request_permits_in_use",
+                "S3 requests in flight per concurrency budget",
+            )
+            .namespace(METRICS_NAMESPACE),
+            &["budget"],
+        )
+        .expect("metric can be created")
+    });
+
+    pub static REQUEST_PERMITS_LIMIT: Lazy<IntGaugeVec> = Lazy::new(|| {
+        IntGaugeVec::new(
+            Opts::new(
+                "s3_request_permits_limit",
+                "Maximum concurrent S3 requests per concurrency budget",
+            )
+            .namespace(METRICS_NAMESPACE),
+            &["budget"],
+        )
+        .expect("metric can be created")
+    });
+
+    pub static REQUESTS_WAITING_FOR_PERMIT: Lazy<IntGaugeVec> = Lazy::new(|| {
+        IntGaugeVec::new(
+            Opts::new(
+                "s3_requests_waiting_for_permit",
+                "S3 requests queued for a permit per concurrency budget",
+            )
+            .namespace(METRICS_NAMESPACE),
+            &["budget"],
+        )
+        .expect("metric can be created")
+    });
+
+    pub static REQUEST_PERMIT_WAITS: Lazy<IntCounterVec> = Lazy::new(|| {
+        IntCounterVec::new(
+            Opts::new(
+                "s3_request_permit_waits",
+                "S3 requests that found no free permit per concurrency budget",
+            )
+            .namespace(METRICS_NAMESPACE),
+            &["budget"],
+        )
+        .expect("metric can be created")
+    });
+
+    pub static REQUEST_PERMIT_WAIT_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+        HistogramVec::new(
+            HistogramOpts::new(
+                "s3_request_permit_wait_time",
+                "Time S3 requests waited for a permit per concurrency budget",
+            )
+            .namespace(METRICS_NAMESPACE),
+            &["budget"],
+        )
+        .expect("metric can be created")
+    });
+
     impl StorageMetrics for S3Config {
         fn register_metrics(&self, handler: &actix_web_prometheus::PrometheusMetrics) {
             handler
@@ -83,6 +152,38 @@ pub mod s3 {
                 .registry
                 .register(Box::new(QUERY_LAYER_STORAGE_REQUEST_RESPONSE_TIME.clone()))
                 .expect("metric can be registered");
+            handler
+                .registry
+                .register(Box::new(REQUEST_PERMITS_IN_USE.clone()))
+                .expect("metric can be registered");
+            handler
+                .registry
+                .register(Box::new(REQUEST_PERMITS_LIMIT.clone()))
+                .expect("metric can be registered");
+            handler
+                .registry
+                .register(Box::new(REQUESTS_WAITING_FOR_PERMIT.clone()))
+                .expect("metric can be registered");
+            handler
+                .registry
+                .register(Box::new(REQUEST_PERMIT_WAITS.clone()))
+                .expect("metric can be registered");
+            handler
+                .registry
+                .register(Box::new(REQUEST_PERMIT_WAIT_TIME.clone()))
+                .expect("metric can be registered");
+            REQUEST_PERMITS_LIMIT
+                .with_label_values(&["read"])
+                .set(self.max_read_requests as i64);
+            REQUEST_PERMITS_LIMIT
+                .with_label_values(&["write"])
+                .set(self.max_write_requests as i64);
+            if self.stream_metrics {
+                handler
+                    .registry
+                    .register(Box::new(STREAM_REQUEST_RESPONSE_TIME.clone()))
+                    .expect("metric can be registered");
+            }
         }
     }
 }
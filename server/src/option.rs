@@ -42,7 +42,7 @@ pub struct Config {
 
 impl Config {
     fn new() -> Self {
-        let cli = create_parseable_cli_command()
+        let command = create_parseable_cli_command()
             .name("Parseable")
             .about(
                 r#"A Cloud Native, log analytics platform
@@ -51,8 +51,26 @@ Log Lake for the cloud-native world
             )
             .arg_required_else_help(true)
             .subcommand_required(true)
-            .color(clap::ColorChoice::Always)
-            .get_matches();
+            .color(clap::ColorChoice::Always);
+
+        // the arguments of a test process are the test harness's, tests get a local store
+        #[cfg(test)]
+        let cli = {
+            let dir = env::temp_dir().join("parseable-test");
+            command.get_matches_from([
+                "parseable",
+                "local-store",
+                "--username",
+                "admin",
+                "--password",
+                "admin",
+                "--local-staging-path",
+                &*dir.join("staging").to_string_lossy(),
+                &*dir.join("data").to_string_lossy(),
+            ])
+        };
+        #[cfg(not(test))]
+        let cli = command.get_matches();
 
         match cli.subcommand() {
             Some(("local-store", m)) => {
@@ -99,6 +117,21 @@ Log Lake for the cloud-native world
                     Err(err) => err.exit(),
                 };
 
+                if let Err(err) = storage.check_network_options() {
+                    create_parseable_cli_command()
+                        .error(ErrorKind::ValueValidation, err)
+                        .exit()
+                }
+
+                if storage.region.is_none() && storage.is_aws_endpoint() {
+                    create_parseable_cli_command()
+                        .error(
+                            ErrorKind::MissingRequiredArgument,
+                            "Region is required when using AWS S3 endpoint.",
+                        )
+                        .exit()
+                }
+
                 Config {
                     parseable: cli,
                     storage: Arc::new(storage),
@@ -109,9 +142,19 @@ Log Lake for the cloud-native world
         }
     }
 
+    // fail early with a clear error if the configured bucket does not exist,
+    // other check failures such as a missing parseable.json are expected on a new bucket
+    pub async fn check_bucket(&self) -> Result<(), ObjectStorageError> {
+        match self.storage.get_object_store().check().await {
+            Err(err @ ObjectStorageError::BucketNotFound(_)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
     // validate the storage, if the proper path for staging directory is provided
     // if the proper data directory is provided, or s3 bucket is provided etc
     pub async fn validate_storage(&self) -> Result<(), ObjectStorageError> {
+        self.check_bucket().await?;
         let obj_store = self.storage.get_object_store();
         let rel_path = parseable_json_path();
 
@@ -242,6 +285,27 @@ impl fmt::Display for Mode {
     }
 }
 
+/// Column of parquet files written with a bloom filter.
+/// Unset values use the parquet defaults, 0.05 fpp for 1 million distinct values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilterColumn {
+    pub column: String,
+    /// False positive probability
+    pub fpp: Option<f64>,
+    /// Expected number of distinct values in a row group, sizes the filter
+    pub ndv: Option<u64>,
+}
+
+/// Handling of events dated too far in the future, e.g. sent by clients with a wrong clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FutureEventPolicy {
+    /// Fails the request
+    #[default]
+    Reject,
+    /// Ingests the event with its timestamp set to now
+    Clamp,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum Compression {
@@ -272,14 +336,15 @@ impl From<Compression> for parquet::basic::Compression {
 pub mod validation {
     use std::{
         env, io,
-        net::ToSocketAddrs,
+        net::{IpAddr, ToSocketAddrs},
         path::{Path, PathBuf},
         str::FromStr,
     };
 
     use path_clean::PathClean;
 
-    use crate::option::MIN_CACHE_SIZE_BYTES;
+    use crate::option::{BloomFilterColumn, MIN_CACHE_SIZE_BYTES};
+    use crate::storage::OBJECT_STORE_DATA_GRANULARITY;
     use human_size::{multiples, SpecificSize};
 
     pub fn file_path(s: &str) -> Result<PathBuf, String> {
@@ -295,6 +360,26 @@ pub mod validation {
 
         Ok(path)
     }
+
+    pub fn ca_certificate(s: &str) -> Result<String, String> {
+        let path = file_path(s)?;
+        let pem =
+            std::fs::read_to_string(path).map_err(|err| format!("could not read file, {err}"))?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut pem.as_bytes()) {
+            let cert = cert.map_err(|err| format!("could not parse PEM file, {err}"))?;
+            roots
+                .add(cert)
+                .map_err(|err| format!("could not parse CA certificate, {err}"))?;
+        }
+        if roots.is_empty() {
+            return Err("file does not contain a PEM encoded certificate".to_string());
+        }
+
+        Ok(pem)
+    }
+
     pub fn absolute_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
         let path = path.as_ref();
 
@@ -320,6 +405,91 @@ pub mod validation {
             .ok_or_else(|| "Socket Address for server is invalid".to_string())
     }
 
+    /// Staged files cover one granularity of minutes each, flushes line up with them
+    pub fn staging_flush_interval(s: &str) -> Result<u64, String> {
+        let interval: u64 = s
+            .parse()
+            .map_err(|_| format!("{s} is not a number of seconds"))?;
+        let granularity = u64::from(OBJECT_STORE_DATA_GRANULARITY) * 60;
+        if interval == 0 || interval % granularity != 0 {
+            return Err(format!(
+                "staging flush interval must be a multiple of {granularity} seconds"
+            ));
+        }
+        Ok(interval)
+    }
+
+    pub fn flush_marker(s: &str) -> Result<String, String> {
+        if s.is_empty() || s.contains('/') {
+            return Err("flush marker must be a name without /".to_string());
+        }
+        if s.ends_with(".parquet") {
+            return Err("flush marker would be read as a parquet data file".to_string());
+        }
+        Ok(s.to_string())
+    }
+
+    /// Keeps a duration like `1h` or `7d` as written, it is parsed again relative to now
+    pub fn human_time_duration(s: &str) -> Result<String, String> {
+        humantime::parse_duration(s)
+            .map(|_| s.to_string())
+            .map_err(|err| format!("{s} is not a duration: {err}"))
+    }
+
+    pub fn host_override(s: &str) -> Result<(String, IpAddr), String> {
+        let (host, address) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected host=address, got {s}"))?;
+        if host.is_empty() {
+            return Err("host is empty".to_string());
+        }
+        let address = address
+            .parse()
+            .map_err(|_| format!("{address} is not an IP address"))?;
+        Ok((host.to_string(), address))
+    }
+
+    pub fn stream_location(s: &str) -> Result<(String, String), String> {
+        match s.split_once('=') {
+            Some((stream, location)) if !stream.is_empty() && !location.is_empty() => {
+                Ok((stream.to_string(), location.to_string()))
+            }
+            _ => Err(format!("expected stream=location, got {s}")),
+        }
+    }
+
+    /// Parses `column[:fpp[:ndv]]`
+    pub fn bloom_filter_column(s: &str) -> Result<BloomFilterColumn, String> {
+        let mut parts = s.trim().split(':');
+        let column = parts.next().unwrap_or_default().to_owned();
+        if column.is_empty() {
+            return Err("column name is empty".to_string());
+        }
+        let fpp = parts
+            .next()
+            .map(|fpp| match fpp.parse::<f64>() {
+                Ok(fpp) if fpp > 0.0 && fpp < 1.0 => Ok(fpp),
+                _ => Err(format!(
+                    "fpp of {column} should be between 0 and 1, got {fpp}"
+                )),
+            })
+            .transpose()?;
+        let ndv = parts
+            .next()
+            .map(|ndv| match ndv.parse::<u64>() {
+                Ok(ndv) if ndv > 0 => Ok(ndv),
+                _ => Err(format!(
+                    "ndv of {column} should be a positive number, got {ndv}"
+                )),
+            })
+            .transpose()?;
+        if parts.next().is_some() {
+            return Err(format!("expected column[:fpp[:ndv]], got {s}"));
+        }
+
+        Ok(BloomFilterColumn { column, fpp, ndv })
+    }
+
     pub fn url(s: &str) -> Result<url::Url, String> {
         url::Url::parse(s).map_err(|_| "Invalid URL provided".to_string())
     }
@@ -16,21 +16,33 @@
  *
  */
 
+pub mod as_of;
+mod count_star;
 mod filter_optimizer;
 mod listing_table_builder;
+mod parquet_reader;
+mod partition_columns;
+pub mod partition_format;
+mod quarantine;
+pub mod rollup;
 pub mod stream_schema_provider;
+pub mod udf;
 
 use chrono::{DateTime, Utc};
 use chrono::{NaiveDateTime, TimeZone};
 use datafusion::arrow::record_batch::RecordBatch;
 
 use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion, TreeNodeVisitor};
+use datafusion::common::SchemaError;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::SessionState;
 use datafusion::execution::disk_manager::DiskManagerConfig;
 use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::execution::SendableRecordBatchStream;
 use datafusion::logical_expr::{Explain, Filter, LogicalPlan, PlanType, ToStringifiedPlan};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
 use datafusion::prelude::*;
+use futures_util::TryStreamExt;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use serde_json::{json, Value};
@@ -43,6 +55,7 @@ use self::error::ExecuteError;
 use self::stream_schema_provider::GlobalSchemaProvider;
 pub use self::stream_schema_provider::PartialTimeFilter;
 use crate::event;
+use crate::metadata;
 use crate::option::CONFIG;
 use crate::storage::{ObjectStorageProvider, StorageDir};
 
@@ -63,9 +76,13 @@ impl Query {
     pub fn create_session_context(
         storage: Arc<dyn ObjectStorageProvider + Send>,
     ) -> SessionContext {
+        let disk_manager = match &CONFIG.parseable.query_spill_path {
+            Some(path) => DiskManagerConfig::NewSpecified(vec![path.clone()]),
+            None => DiskManagerConfig::NewOs,
+        };
         let runtime_config = storage
             .get_datafusion_runtime()
-            .with_disk_manager(DiskManagerConfig::NewOs);
+            .with_disk_manager(disk_manager);
 
         let (pool_size, fraction) = match CONFIG.parseable.query_memory_pool_size {
             Some(size) => (size, 1.),
@@ -99,7 +116,11 @@ impl Query {
             )
             .unwrap();
 
-        SessionContext::new_with_state(state)
+        let ctx = SessionContext::new_with_state(state);
+        for udf in udf::udfs() {
+            ctx.register_udf(udf);
+        }
+        ctx
     }
 
     pub async fn execute(
@@ -109,10 +130,20 @@ impl Query {
         let store = CONFIG.storage().get_object_store();
         let object_store_format = store.get_object_store_format(&stream_name).await?;
         let time_partition = object_store_format.time_partition;
+        let plan = self.final_logical_plan(&time_partition);
+
+        if let Some(batch) = count_star::from_catalog(&plan).await? {
+            let fields = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.name().clone())
+                .collect_vec();
+            return Ok((vec![batch], fields));
+        }
+        let plan = rollup::route(plan).await?;
 
-        let df = QUERY_SESSION
-            .execute_logical_plan(self.final_logical_plan(&time_partition))
-            .await?;
+        let df = QUERY_SESSION.execute_logical_plan(plan).await?;
 
         let fields = df
             .schema()
@@ -126,10 +157,42 @@ impl Query {
             return Ok((vec![], fields));
         }
 
-        let results = df.collect().await?;
+        let results = df.collect().await.map_err(|err| {
+            quarantine::on_query_error(&err);
+            err
+        })?;
         Ok((results, fields))
     }
 
+    /// Same as `execute` but returns the record batches as a stream
+    /// instead of collecting them in memory
+    pub async fn execute_stream(
+        &self,
+        stream_name: String,
+    ) -> Result<SendableRecordBatchStream, ExecuteError> {
+        let store = CONFIG.storage().get_object_store();
+        let object_store_format = store.get_object_store_format(&stream_name).await?;
+        let time_partition = object_store_format.time_partition;
+        let plan = self.final_logical_plan(&time_partition);
+
+        if let Some(batch) = count_star::from_catalog(&plan).await? {
+            let schema = batch.schema();
+            let stream = futures_util::stream::iter([Ok(batch)]);
+            return Ok(Box::pin(RecordBatchStreamAdapter::new(schema, stream)));
+        }
+        let plan = rollup::route(plan).await?;
+
+        let df = QUERY_SESSION.execute_logical_plan(plan).await?;
+
+        let stream = df.execute_stream().await?;
+        let schema = stream.schema();
+        let stream = stream.map_err(|err| {
+            quarantine::on_query_error(&err);
+            err
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(schema, stream)))
+    }
+
     /// return logical plan with all time filters applied through
     fn final_logical_plan(&self, time_partition: &Option<String>) -> LogicalPlan {
         let filters = self.filter_tag.clone().and_then(tag_filter);
@@ -197,7 +260,9 @@ impl TreeNodeVisitor for TableScanVisitor {
     fn f_down(&mut self, node: &Self::Node) -> Result<TreeNodeRecursion, DataFusionError> {
         match node {
             LogicalPlan::TableScan(table) => {
-                self.tables.push(table.table_name.table().to_string());
+                // aliases are collected as the stream they point to
+                self.tables
+                    .push(metadata::resolve_stream_alias(table.table_name.table()));
                 Ok(TreeNodeRecursion::Jump)
             }
             _ => Ok(TreeNodeRecursion::Continue),
@@ -205,6 +270,90 @@ impl TreeNodeVisitor for TableScanVisitor {
     }
 }
 
+/// Registers a stream as `table_name` in a session created outside of Parseable, to be
+/// queried together with tables of other sources:
+///
+/// ```ignore
+/// register_stream_table(&ctx, "app_logs", "app")?;
+/// ctx.register_csv("users", "users.csv", CsvReadOptions::new()).await?;
+/// let df = ctx
+///     .sql("SELECT u.name, count(*) FROM app_logs l JOIN users u ON l.user_id = u.id \
+///           WHERE l.p_timestamp > now() - interval '1 hour' GROUP BY u.name")
+///     .await?;
+/// ```
+///
+/// The object stores holding the stream are registered in the runtime of the session.
+/// As for any query of a stream, scans need a bound on the time column.
+///
+/// The table is a snapshot of the stream schema at registration, columns added later
+/// are queried after registering the stream again. Uploaded files and staged events
+/// are listed on every scan, so new data is seen without registering again. The table
+/// stays registered after the stream is deleted, scans of it fail.
+// not called by the server itself, it is the entry point for code embedding it
+#[allow(dead_code)]
+pub fn register_stream_table(
+    ctx: &SessionContext,
+    table_name: &str,
+    stream_name: &str,
+) -> Result<(), DataFusionError> {
+    let storage = CONFIG.storage().get_object_store();
+    let table = stream_schema_provider::stream_table(storage.as_ref(), stream_name)
+        .ok_or_else(|| DataFusionError::Plan(format!("stream {stream_name} does not exist")))?;
+
+    let url = storage.stream_store_url(&metadata::resolve_stream_alias(stream_name));
+    let object_store = QUERY_SESSION
+        .runtime_env()
+        .object_store_registry
+        .get_store(&url)?;
+    ctx.runtime_env().register_object_store(&url, object_store);
+
+    ctx.register_table(table_name, table)?;
+    Ok(())
+}
+
+/// Planning reports columns left out by the column access of their stream as missing,
+/// the error is replaced by one naming the column as not permitted
+pub fn explain_denied_column(err: DataFusionError) -> DataFusionError {
+    match denied_column(&err) {
+        Some((column, stream)) => DataFusionError::Plan(format!(
+            "column {column} of stream {stream} is not permitted by its column access"
+        )),
+        None => err,
+    }
+}
+
+fn denied_column(err: &DataFusionError) -> Option<(String, String)> {
+    let DataFusionError::SchemaError(
+        SchemaError::FieldNotFound {
+            field,
+            valid_fields,
+        },
+        ..,
+    ) = err
+    else {
+        return None;
+    };
+    // unqualified columns are looked up in all tables of the query
+    let tables = field.relation.iter().chain(
+        valid_fields
+            .iter()
+            .filter_map(|column| column.relation.as_ref()),
+    );
+    for table in tables.unique() {
+        let stream = metadata::resolve_stream_alias(table.table());
+        let Ok(Some(column_access)) = metadata::STREAM_INFO.get_column_access(&stream) else {
+            continue;
+        };
+        let exists = metadata::STREAM_INFO
+            .schema(&stream)
+            .is_ok_and(|schema| schema.field_with_name(&field.name).is_ok());
+        if exists && !column_access.permits(&field.name) {
+            return Some((field.name.clone(), stream));
+        }
+    }
+    None
+}
+
 fn tag_filter(filters: Vec<String>) -> Option<Expr> {
     filters
         .iter()
@@ -403,12 +552,80 @@ pub mod error {
 
 #[cfg(test)]
 mod tests {
+    use arrow_array::{Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
     use serde_json::json;
 
+    use crate::metadata::STREAM_INFO;
     use crate::query::flatten_objects_for_count;
 
-    use super::time_from_path;
+    use super::{register_stream_table, time_from_path};
+    use std::collections::HashMap;
     use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn registered_stream_joins_tables_of_other_sources() {
+        let fields = [
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("user_id", DataType::Int64, true),
+        ];
+        STREAM_INFO.add_stream(
+            "embeddedapp".to_owned(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            None,
+            HashMap::from_iter(
+                fields
+                    .into_iter()
+                    .map(|field| (field.name().clone(), Arc::new(field))),
+            ),
+        );
+
+        let users_schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let users = RecordBatch::try_new(
+            users_schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec!["ana", "bo"])),
+            ],
+        )
+        .unwrap();
+
+        let ctx = SessionContext::new();
+        register_stream_table(&ctx, "app_logs", "embeddedapp").unwrap();
+        ctx.register_table(
+            "users",
+            Arc::new(MemTable::try_new(users_schema, vec![vec![users]]).unwrap()),
+        )
+        .unwrap();
+
+        let df = ctx
+            .sql("SELECT u.name, l.p_timestamp FROM app_logs l JOIN users u ON l.user_id = u.id")
+            .await
+            .unwrap();
+        let columns: Vec<_> = df
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(columns, ["name", "p_timestamp"]);
+
+        assert!(register_stream_table(&ctx, "missing_logs", "missingapp").is_err());
+    }
 
     #[test]
     fn test_time_from_parquet_path() {
@@ -0,0 +1,134 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Queries as of a point in time read only the data files uploaded by then, so files
+//! flushed while a report runs don't change its results. Files are selected by the last
+//! modified time the object store lists for them. This is not a true snapshot: a file
+//! rewritten after the cutoff is left out, and staged events not yet uploaded are never
+//! read.
+
+use std::collections::HashSet;
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use itertools::Itertools;
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+
+use crate::catalog::manifest::File;
+
+tokio::task_local! {
+    // set while serving a query as of a point in time
+    static AS_OF: DateTime<Utc>;
+}
+
+/// Runs `fut` reading only files uploaded by `as_of`
+pub async fn scope<F: Future>(as_of: DateTime<Utc>, fut: F) -> F::Output {
+    AS_OF.scope(as_of, fut).await
+}
+
+/// Cutoff of the query served by the current task, if any
+pub fn cutoff() -> Option<DateTime<Utc>> {
+    AS_OF.try_with(|as_of| *as_of).ok()
+}
+
+/// Whether a listed object was uploaded by the cutoff of the current query
+pub fn includes(object: &ObjectMeta) -> bool {
+    cutoff().map_or(true, |as_of| object.last_modified <= as_of)
+}
+
+/// Keeps the manifest files last modified by `as_of`. Each directory holding
+/// a file is listed once, `concurrency` directories at a time.
+pub async fn retain_uploaded(
+    store: &dyn ObjectStore,
+    files: Vec<File>,
+    as_of: DateTime<Utc>,
+    concurrency: usize,
+) -> Result<Vec<File>, object_store::Error> {
+    let dirs = files
+        .iter()
+        .map(|file| parent(&file.file_path))
+        .unique()
+        .collect_vec();
+    let uploaded: HashSet<Path> = stream::iter(dirs)
+        .map(|dir| async move { store.list(Some(&dir)).try_collect::<Vec<_>>().await })
+        .buffer_unordered(concurrency)
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .flatten()
+        .filter(|object| object.last_modified <= as_of)
+        .map(|object| object.location)
+        .collect();
+
+    Ok(files
+        .into_iter()
+        .filter(|file| uploaded.contains(&Path::from(file.file_path.as_str())))
+        .collect())
+}
+
+fn parent(file_path: &str) -> Path {
+    let path = Path::from(file_path);
+    let parts = path.parts().collect_vec();
+    Path::from_iter(parts[..parts.len().saturating_sub(1)].iter().cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use object_store::{local::LocalFileSystem, path::Path, ObjectStore};
+
+    use super::retain_uploaded;
+    use crate::catalog::manifest::File;
+
+    #[actix_web::test]
+    async fn files_uploaded_after_the_cutoff_are_excluded() {
+        let root = std::env::temp_dir().join(format!("parseable-as-of-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&root).unwrap();
+        let store = LocalFileSystem::new_with_prefix(&root).unwrap();
+        let before = "app/date=2024-01-01/hour=00/minute=00/a.parquet";
+        let after = "app/date=2024-01-01/hour=00/minute=00/b.parquet";
+        let file = |file_path: &str| File {
+            file_path: file_path.to_string(),
+            ..File::default()
+        };
+
+        store
+            .put(&Path::from(before), Bytes::from_static(b"a"))
+            .await
+            .unwrap();
+        let as_of = store.head(&Path::from(before)).await.unwrap().last_modified;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        store
+            .put(&Path::from(after), Bytes::from_static(b"b"))
+            .await
+            .unwrap();
+
+        let retained = retain_uploaded(&store, vec![file(before), file(after)], as_of, 2).await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let retained: Vec<_> = retained
+            .unwrap()
+            .into_iter()
+            .map(|file| file.file_path)
+            .collect();
+        assert_eq!(retained, [before]);
+    }
+}
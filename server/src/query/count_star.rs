@@ -0,0 +1,179 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! A bare `SELECT COUNT(*)` of a stream is answered from the row counts of its manifests
+//! when filters only bound the time column or test partition columns, so no parquet file
+//! is opened. Queries with other filters, over staged events or over data listed by the
+//! older table format run the normal plan.
+
+use std::sync::Arc;
+
+use arrow_array::{Int64Array, RecordBatch};
+use arrow_schema::{DataType, Schema};
+use datafusion::{
+    error::DataFusionError,
+    logical_expr::{
+        aggregate_function,
+        expr::{AggregateFunction, AggregateFunctionDefinition},
+        utils::split_conjunction,
+        Expr, LogicalPlan,
+    },
+};
+
+use super::{stream_schema_provider, QUERY_SESSION};
+use crate::option::CONFIG;
+
+/// Count of the rows of a table matching the filters of the plan
+#[derive(Debug)]
+struct CountStar {
+    table: String,
+    filters: Vec<Expr>,
+}
+
+/// Result of `plan` counted from the catalog, none if it isn't a count the catalog answers
+pub async fn from_catalog(plan: &LogicalPlan) -> Result<Option<RecordBatch>, DataFusionError> {
+    let Some(count) = count_star(plan) else {
+        return Ok(None);
+    };
+    let schema = Arc::new(Schema::from(plan.schema().as_ref()));
+    if schema.fields().len() != 1 || schema.field(0).data_type() != &DataType::Int64 {
+        return Ok(None);
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let rows = stream_schema_provider::count_from_catalog(
+        &QUERY_SESSION.state(),
+        storage.as_ref(),
+        &count.table,
+        &count.filters,
+    )
+    .await?;
+    let Some(rows) = rows else {
+        return Ok(None);
+    };
+    log::debug!("counted {rows} rows of {} from the catalog", count.table);
+    let batch = RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![rows as i64]))])?;
+    Ok(Some(batch))
+}
+
+/// Matches a count of all rows of a single table, optionally renamed, without grouping
+fn count_star(plan: &LogicalPlan) -> Option<CountStar> {
+    let mut plan = plan;
+    if let LogicalPlan::Projection(projection) = plan {
+        match projection.expr.as_slice() {
+            [expr] if matches!(expr.clone().unalias(), Expr::Column(_)) => {}
+            _ => return None,
+        }
+        plan = projection.input.as_ref();
+    }
+    let LogicalPlan::Aggregate(aggregate) = plan else {
+        return None;
+    };
+    match aggregate.aggr_expr.as_slice() {
+        [expr] if aggregate.group_expr.is_empty() && is_count_star(expr) => {}
+        _ => return None,
+    }
+
+    let mut filters = Vec::new();
+    let mut input = aggregate.input.as_ref();
+    loop {
+        match input {
+            LogicalPlan::Filter(filter) => {
+                filters.extend(split_conjunction(&filter.predicate).into_iter().cloned());
+                input = filter.input.as_ref();
+            }
+            LogicalPlan::SubqueryAlias(alias) => input = alias.input.as_ref(),
+            LogicalPlan::TableScan(scan) if scan.fetch.is_none() => {
+                filters.extend(scan.filters.iter().flat_map(split_conjunction).cloned());
+                return Some(CountStar {
+                    table: scan.table_name.table().to_owned(),
+                    filters,
+                });
+            }
+            _ => return None,
+        }
+    }
+}
+
+pub(super) fn is_count_star(expr: &Expr) -> bool {
+    let Expr::AggregateFunction(AggregateFunction {
+        func_def: AggregateFunctionDefinition::BuiltIn(aggregate_function::AggregateFunction::Count),
+        args,
+        distinct: false,
+        filter: None,
+        ..
+    }) = expr.clone().unalias()
+    else {
+        return false;
+    };
+    // COUNT(*) is planned as a count of a non null literal
+    match args.as_slice() {
+        [Expr::Wildcard { .. }] => true,
+        [Expr::Literal(value)] => !value.is_null(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+
+    use super::count_star;
+
+    async fn plan(sql: &str) -> datafusion::logical_expr::LogicalPlan {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("status", DataType::Int64, true),
+        ]));
+        let ctx = SessionContext::new();
+        ctx.register_table(
+            "app",
+            Arc::new(MemTable::try_new(schema, vec![vec![]]).unwrap()),
+        )
+        .unwrap();
+        ctx.state().create_logical_plan(sql).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn only_bare_counts_are_matched() {
+        let count = count_star(
+            &plan("select count(*) as total from app where p_timestamp >= '2024-01-01T00:00:00'")
+                .await,
+        )
+        .unwrap();
+        assert_eq!(count.table, "app");
+        assert_eq!(count.filters.len(), 1);
+
+        for sql in [
+            "select count(status) from app",
+            "select count(distinct status) from app",
+            "select status, count(*) from app group by status",
+            "select count(*) from app having count(*) > 1",
+            "select count(*), max(status) from app",
+        ] {
+            assert!(count_star(&plan(sql).await).is_none(), "{sql}");
+        }
+    }
+}
@@ -16,34 +16,35 @@
  *
  */
 
-use std::{collections::HashMap, ops::Bound, pin::Pin, sync::Arc};
+use std::{collections::HashSet, ops::Bound, pin::Pin, sync::Arc, time::Instant};
 
-use arrow_schema::Schema;
+use arrow_schema::{Field, Schema};
 use datafusion::{
-    datasource::{
-        file_format::parquet::ParquetFormat,
-        listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+    datasource::listing::{
+        ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl, PartitionedFile,
     },
     error::DataFusionError,
     logical_expr::{col, Expr},
 };
-use futures_util::{future, stream::FuturesUnordered, Future, TryStreamExt};
+use futures_util::{future, stream, Future, StreamExt, TryStreamExt};
 use itertools::Itertools;
 use object_store::{ObjectMeta, ObjectStore};
 
 use crate::{
     event::DEFAULT_TIMESTAMP_KEY,
-    storage::{ObjectStorage, OBJECT_STORE_DATA_GRANULARITY},
+    metadata::STREAM_INFO,
+    option::CONFIG,
+    storage::{key_naming, sort_order::SortColumn, ObjectStorage, OBJECT_STORE_DATA_GRANULARITY},
     utils::TimePeriod,
 };
 
-use super::PartialTimeFilter;
+use super::{as_of, partition_columns, stream_schema_provider::parquet_format, PartialTimeFilter};
 
 // Listing Table Builder for querying old data
 #[derive(Debug, Default)]
 pub struct ListingTableBuilder {
     stream: String,
-    listing: Vec<String>,
+    listing: Vec<ObjectMeta>,
 }
 
 impl ListingTableBuilder {
@@ -87,110 +88,104 @@ impl ListingTableBuilder {
             ));
         };
 
+        // partitions are named after wall clock time in the partition zone of the stream
+        let zone = STREAM_INFO
+            .get_partition_time_zone(&self.stream)
+            .map_err(|err| DataFusionError::Execution(err.to_string()))?;
+        let (start_time, end_time) = key_naming::partition_range(start_time, end_time, zone);
+
+        // partitions missing from storage are skipped instead of listed one by one
+        let existing = storage
+            .prefixes_for_range(&self.stream, start_time.and_utc(), end_time.and_utc())
+            .await
+            .map_err(|err| DataFusionError::External(Box::new(err)))?;
+
+        // prefixes are listed recursively, so files written with finer partitions than
+        // the current granularity of the stream are found under the truncated prefixes
+        let granularity = STREAM_INFO
+            .get_partition_granularity(&self.stream)
+            .map_err(|err| DataFusionError::Execution(err.to_string()))?;
         let prefixes = TimePeriod::new(
             start_time.and_utc(),
             end_time.and_utc(),
             OBJECT_STORE_DATA_GRANULARITY,
         )
-        .generate_prefixes();
+        .generate_prefixes()
+        .into_iter()
+        .map(|entry| granularity.truncate(&entry))
+        .unique()
+        .collect_vec();
 
-        let prefixes = prefixes
-            .into_iter()
-            .map(|entry| {
-                let path =
-                    relative_path::RelativePathBuf::from(format!("{}/{}", &self.stream, entry));
-                storage.absolute_url(path.as_relative_path()).to_string()
-            })
-            .collect_vec();
+        let strategy = key_naming::strategy();
+        let to_url = |entry: &str| {
+            let path = relative_path::RelativePathBuf::from(format!(
+                "{}/{}",
+                &self.stream,
+                strategy.prefix(entry)
+            ));
+            storage.absolute_url(path.as_relative_path()).to_string()
+        };
 
-        let mut minute_resolve: HashMap<String, Vec<String>> = HashMap::new();
+        // files of a date written before it was partitioned by hour
+        let mut date_resolve: HashSet<String> = HashSet::new();
         let mut all_resolve = Vec::new();
 
-        for prefix in prefixes {
-            let components = prefix.split_terminator('/');
-            if components.last().is_some_and(|x| x.starts_with("minute")) {
-                let hour_prefix = &prefix[0..prefix.rfind("minute").expect("minute exists")];
-                minute_resolve
-                    .entry(hour_prefix.to_owned())
-                    .and_modify(|list| list.push(prefix))
-                    .or_default();
-            } else {
-                all_resolve.push(prefix)
+        for entry in prefixes {
+            let prefix = strategy.prefix(&entry);
+            if !existing
+                .iter()
+                .any(|exists| prefix.starts_with(exists) || exists.starts_with(&prefix))
+            {
+                continue;
             }
+            if entry.split_terminator('/').count() > 1 {
+                let date_prefix = &entry[..=entry.find('/').expect("prefix has a date")];
+                date_resolve.insert(to_url(date_prefix));
+            }
+            all_resolve.push(to_url(&entry))
         }
 
-        type ResolveFuture = Pin<
-            Box<dyn Future<Output = Result<Vec<ObjectMeta>, object_store::Error>> + Send + 'static>,
-        >;
-        // Pin<Box<dyn Future<Output = Result<BoxStream<'_, Result<ObjectMeta>>>> + Send + 'async_trait>>
-        // BoxStream<'_, Result<ObjectMeta>>
-        let tasks: FuturesUnordered<ResolveFuture> = FuturesUnordered::new();
-
-        for (listing_prefix, prefix) in minute_resolve {
-            let client = Arc::clone(&client);
-            tasks.push(Box::pin(async move {
-                let mut list = client
-                    .list(Some(&object_store::path::Path::from(listing_prefix)))
-                    .try_collect::<Vec<_>>()
-                    .await?;
-
-                list.retain(|object| {
-                    prefix.iter().any(|prefix| {
-                        object
-                            .location
-                            .prefix_matches(&object_store::path::Path::from(prefix.as_ref()))
-                    })
-                });
-
-                Ok(list)
-            }));
-        }
-
-        for prefix in all_resolve {
-            let client = Arc::clone(&client);
-            tasks.push(Box::pin(async move {
-                client
-                    .list(Some(&object_store::path::Path::from(prefix)))
-                    .try_collect::<Vec<_>>()
-                    .await
-                    .map_err(Into::into)
-            }));
-        }
-
-        let res: Vec<Vec<String>> = tasks
-            .and_then(|res| {
-                future::ok(
-                    res.into_iter()
-                        .map(|res| res.location.to_string())
-                        .collect_vec(),
-                )
-            })
-            .try_collect()
-            .await
-            .map_err(|err| DataFusionError::External(Box::new(err)))?;
-
-        let mut res = res.into_iter().flatten().collect_vec();
-        res.sort();
-        res.reverse();
+        let instant = Instant::now();
+        let listing = list_data_files(
+            client,
+            date_resolve,
+            all_resolve,
+            CONFIG.parseable.query_listing_concurrency,
+        )
+        .await?;
+        log::debug!(
+            "listed prefixes of stream {} in {:?}",
+            self.stream,
+            instant.elapsed()
+        );
 
         Ok(Self {
             stream: self.stream,
-            listing: res,
+            listing,
         })
     }
 
+    /// Listing table over the listed files, None if no data file was found in which
+    /// case the query reads nothing from storage
     pub fn build(
         self,
         schema: Arc<Schema>,
         map: impl Fn(Vec<String>) -> Vec<ListingTableUrl>,
         time_partition: Option<String>,
+        sort_order: &[SortColumn],
     ) -> Result<Option<Arc<ListingTable>>, DataFusionError> {
         if self.listing.is_empty() {
             return Ok(None);
         }
         let file_sort_order: Vec<Vec<Expr>>;
-        let file_format = ParquetFormat::default().with_enable_pruning(true);
-        if let Some(time_partition) = time_partition {
+        let file_format = parquet_format(
+            CONFIG.parseable.query_pushdown_filters,
+            CONFIG.parseable.query_page_index,
+            !CONFIG.parseable.parquet_bloom_filter_columns.is_empty(),
+        );
+        if !sort_order.is_empty() {
+            file_sort_order = vec![sort_order.iter().map(SortColumn::to_expr).collect()];
+        } else if let Some(time_partition) = time_partition {
             file_sort_order = vec![vec![col(time_partition).sort(true, false)]];
         } else {
             file_sort_order = vec![vec![col(DEFAULT_TIMESTAMP_KEY).sort(true, false)]];
@@ -202,11 +197,173 @@ impl ListingTableBuilder {
             .with_collect_stat(true)
             .with_target_partitions(1);
 
-        let config = ListingTableConfig::new_with_multi_paths(map(self.listing))
+        let paths = self
+            .listing
+            .into_iter()
+            .map(|object| object.location.to_string())
+            .collect();
+        let config = ListingTableConfig::new_with_multi_paths(map(paths))
             .with_listing_options(listing_options)
             .with_schema(schema);
 
         let listing_table = Arc::new(ListingTable::try_new(config)?);
         Ok(Some(listing_table))
     }
+
+    /// Listed files with the values of the given partition columns parsed from their keys,
+    /// files which can't match the filters are left out
+    pub fn partitioned_files(
+        self,
+        partition_fields: &[Field],
+        filters: &[Expr],
+    ) -> Vec<PartitionedFile> {
+        self.listing
+            .into_iter()
+            .filter_map(|object| {
+                let values =
+                    partition_columns::partition_values(object.location.as_ref(), partition_fields);
+                if filters.iter().any(|filter| {
+                    partition_columns::can_be_pruned(filter, partition_fields, &values)
+                }) {
+                    return None;
+                }
+                let mut file = PartitionedFile::from(object);
+                file.partition_values = partition_columns::to_scalars(values);
+                Some(file)
+            })
+            .collect()
+    }
+}
+
+/// Data files under the prefixes, newest first. Date prefixes are listed one level deep,
+/// the other prefixes recursively. Prefixes which don't exist or hold no data files, such
+/// as partitions with only a flush marker, add nothing to the listing.
+async fn list_data_files(
+    client: Arc<dyn ObjectStore>,
+    date_prefixes: HashSet<String>,
+    prefixes: Vec<String>,
+    concurrency: usize,
+) -> Result<Vec<ObjectMeta>, DataFusionError> {
+    type ResolveFuture = Pin<
+        Box<dyn Future<Output = Result<Vec<ObjectMeta>, object_store::Error>> + Send + 'static>,
+    >;
+    let mut tasks: Vec<(String, ResolveFuture)> = Vec::new();
+
+    for prefix in date_prefixes {
+        let client = Arc::clone(&client);
+        let key = prefix.clone();
+        let task: ResolveFuture = Box::pin(async move {
+            client
+                .list_with_delimiter(Some(&object_store::path::Path::from(prefix)))
+                .await
+                .map(|list| list.objects)
+        });
+        tasks.push((key, task));
+    }
+
+    for prefix in prefixes {
+        let client = Arc::clone(&client);
+        let key = prefix.clone();
+        let task: ResolveFuture = Box::pin(async move {
+            client
+                .list(Some(&object_store::path::Path::from(prefix)))
+                .try_collect::<Vec<_>>()
+                .await
+        });
+        tasks.push((key, task));
+    }
+
+    // list newest prefixes first with bounded concurrency,
+    // so queries terminating early get to recent data sooner
+    tasks.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let res: Vec<Vec<ObjectMeta>> = stream::iter(tasks.into_iter().map(|(_, task)| task))
+        .buffer_unordered(concurrency)
+        .or_else(|err| match err {
+            // a prefix removed since it was resolved has no files
+            object_store::Error::NotFound { .. } => future::ok(Vec::new()),
+            err => future::err(err),
+        })
+        .and_then(|res| {
+            // skip non data objects such as flush markers and empty files
+            // left behind by interrupted uploads
+            future::ok(
+                res.into_iter()
+                    .filter(|res| res.location.extension() == Some("parquet") && res.size > 0)
+                    .filter(as_of::includes)
+                    .collect_vec(),
+            )
+        })
+        .try_collect()
+        .await
+        .map_err(|err| DataFusionError::External(Box::new(err)))?;
+
+    let mut res = res.into_iter().flatten().collect_vec();
+    res.sort_by(|a, b| b.location.cmp(&a.location));
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+
+    use super::{list_data_files, ListingTableBuilder};
+
+    async fn store_with(objects: &[(&str, &'static [u8])]) -> Arc<dyn ObjectStore> {
+        let store = InMemory::new();
+        for (key, content) in objects {
+            store
+                .put(&Path::from(*key), Bytes::from_static(content))
+                .await
+                .unwrap();
+        }
+        Arc::new(store)
+    }
+
+    #[actix_web::test]
+    async fn empty_prefix_lists_nothing() {
+        let store = store_with(&[("app/date=2024-01-02/hour=00/a.parquet", b"PAR1")]).await;
+        let listing = list_data_files(
+            store,
+            HashSet::from(["app/date=2024-01-01/".to_owned()]),
+            vec!["app/date=2024-01-01/hour=10/".to_owned()],
+            2,
+        )
+        .await
+        .unwrap();
+        assert!(listing.is_empty());
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, true)]));
+        let builder = ListingTableBuilder {
+            stream: "app".to_owned(),
+            listing,
+        };
+        let table = builder.build(schema, |_| Vec::new(), None, &[]).unwrap();
+        assert!(table.is_none(), "nothing is read instead of failing");
+    }
+
+    #[actix_web::test]
+    async fn marker_only_prefix_lists_nothing() {
+        let store = store_with(&[
+            ("app/date=2024-01-01/_SUCCESS", b""),
+            ("app/date=2024-01-01/hour=10/_SUCCESS", b""),
+            // a data file whose upload was interrupted
+            ("app/date=2024-01-01/hour=10/b.parquet", b""),
+            ("app/date=2024-01-01/hour=11/c.parquet", b"PAR1"),
+        ])
+        .await;
+        let listing = list_data_files(
+            store,
+            HashSet::from(["app/date=2024-01-01/".to_owned()]),
+            vec!["app/date=2024-01-01/hour=10/".to_owned()],
+            2,
+        )
+        .await
+        .unwrap();
+        assert!(listing.is_empty());
+    }
 }
@@ -0,0 +1,262 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{ops::Range, sync::Arc};
+
+use bytes::Bytes;
+use datafusion::{
+    datasource::physical_plan::{
+        parquet::DefaultParquetFileReaderFactory, FileMeta, ParquetFileReaderFactory,
+    },
+    error::Result as DataFusionResult,
+    physical_plan::metrics::ExecutionPlanMetricsSet,
+};
+use futures_util::{future::BoxFuture, FutureExt, TryFutureExt};
+use object_store::{path::Path, ObjectStore};
+use parquet::{
+    arrow::async_reader::AsyncFileReader,
+    basic::Compression,
+    errors::{ParquetError, Result as ParquetResult},
+    file::metadata::ParquetMetaData,
+};
+
+/// Parquet reader factory which validates the compression codecs of a file
+/// before it is decoded and attaches the object key to every read error,
+/// so that a single bad file can be identified from the query error.
+#[derive(Debug)]
+pub struct CheckedParquetFileReaderFactory {
+    inner: DefaultParquetFileReaderFactory,
+}
+
+impl CheckedParquetFileReaderFactory {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            inner: DefaultParquetFileReaderFactory::new(store),
+        }
+    }
+}
+
+impl ParquetFileReaderFactory for CheckedParquetFileReaderFactory {
+    fn create_reader(
+        &self,
+        partition_index: usize,
+        file_meta: FileMeta,
+        metadata_size_hint: Option<usize>,
+        metrics: &ExecutionPlanMetricsSet,
+    ) -> DataFusionResult<Box<dyn AsyncFileReader + Send>> {
+        let location = file_meta.location().clone();
+        let inner =
+            self.inner
+                .create_reader(partition_index, file_meta, metadata_size_hint, metrics)?;
+        Ok(Box::new(CheckedParquetFileReader { inner, location }))
+    }
+}
+
+struct CheckedParquetFileReader {
+    inner: Box<dyn AsyncFileReader + Send>,
+    location: Path,
+}
+
+impl AsyncFileReader for CheckedParquetFileReader {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, ParquetResult<Bytes>> {
+        let location = &self.location;
+        self.inner
+            .get_bytes(range)
+            .map_err(|err| with_location(location, err))
+            .boxed()
+    }
+
+    fn get_byte_ranges(
+        &mut self,
+        ranges: Vec<Range<usize>>,
+    ) -> BoxFuture<'_, ParquetResult<Vec<Bytes>>> {
+        let location = &self.location;
+        self.inner
+            .get_byte_ranges(ranges)
+            .map_err(|err| with_location(location, err))
+            .boxed()
+    }
+
+    fn get_metadata(&mut self) -> BoxFuture<'_, ParquetResult<Arc<ParquetMetaData>>> {
+        let inner = &mut self.inner;
+        let location = &self.location;
+        async move {
+            let metadata = inner.get_metadata().await.map_err(|err| {
+                if is_fetch_error(&err) {
+                    with_location(location, err)
+                } else {
+                    corrupt(location, err)
+                }
+            })?;
+            check_compression(&metadata).map_err(|err| with_location(location, err))?;
+            Ok(metadata)
+        }
+        .boxed()
+    }
+}
+
+/// A parquet file whose metadata can't be decoded, e.g. truncated by a failed upload
+#[derive(Debug, thiserror::Error)]
+#[error("failed to read parquet file {location}: {source}")]
+pub struct CorruptParquetFile {
+    pub location: Path,
+    source: ParquetError,
+}
+
+fn with_location(location: &Path, err: ParquetError) -> ParquetError {
+    ParquetError::General(format!("failed to read parquet file {location}: {err}"))
+}
+
+fn corrupt(location: &Path, source: ParquetError) -> ParquetError {
+    ParquetError::External(Box::new(CorruptParquetFile {
+        location: location.clone(),
+        source,
+    }))
+}
+
+// errors of the object store, as opposed to errors decoding the bytes it returned
+fn is_fetch_error(err: &ParquetError) -> bool {
+    matches!(err, ParquetError::External(err) if err.is::<object_store::Error>())
+}
+
+// parquet is built with default features which enable every codec it implements,
+// LZO is the only codec in the format specification without an implementation
+fn check_compression(metadata: &ParquetMetaData) -> ParquetResult<()> {
+    for row_group in metadata.row_groups() {
+        for column in row_group.columns() {
+            if let Compression::LZO = column.compression() {
+                return Err(ParquetError::General(format!(
+                    "column {} is compressed with unsupported codec {}",
+                    column.column_path(),
+                    column.compression()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        config::TableParquetOptions,
+        datasource::{
+            listing::PartitionedFile,
+            physical_plan::{FileScanConfig, ParquetExec},
+        },
+        execution::object_store::ObjectStoreUrl,
+        physical_plan::{collect, Statistics},
+        prelude::SessionContext,
+    };
+    use object_store::local::LocalFileSystem;
+    use parquet::{
+        arrow::ArrowWriter,
+        basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel},
+        file::properties::WriterProperties,
+    };
+
+    use super::CheckedParquetFileReaderFactory;
+
+    fn scan(schema: Arc<Schema>, files: &[std::path::PathBuf]) -> Arc<ParquetExec> {
+        let files = files
+            .iter()
+            .map(|path| {
+                let size = std::fs::metadata(path).unwrap().len();
+                PartitionedFile::new(path.to_str().unwrap().to_string(), size)
+            })
+            .collect();
+        let exec = ParquetExec::new(
+            FileScanConfig {
+                object_store_url: ObjectStoreUrl::local_filesystem(),
+                file_schema: schema.clone(),
+                file_groups: vec![files],
+                statistics: Statistics::new_unknown(&schema),
+                projection: None,
+                limit: None,
+                output_ordering: vec![],
+                table_partition_cols: vec![],
+            },
+            None,
+            None,
+            TableParquetOptions::default(),
+        )
+        .with_parquet_file_reader_factory(Arc::new(CheckedParquetFileReaderFactory::new(
+            Arc::new(LocalFileSystem::new()),
+        )));
+        Arc::new(exec)
+    }
+
+    #[actix_web::test]
+    async fn reads_files_with_different_codecs() {
+        let dir = std::env::temp_dir().join(format!("parseable-codecs-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..100))],
+        )
+        .unwrap();
+
+        let codecs = [
+            Compression::UNCOMPRESSED,
+            Compression::SNAPPY,
+            Compression::GZIP(GzipLevel::default()),
+            Compression::LZ4,
+            Compression::LZ4_RAW,
+            Compression::ZSTD(ZstdLevel::default()),
+            Compression::BROTLI(BrotliLevel::default()),
+        ];
+        let mut files = Vec::new();
+        for (index, codec) in codecs.into_iter().enumerate() {
+            let path = dir.join(format!("{index}.parquet"));
+            let props = WriterProperties::builder().set_compression(codec).build();
+            let file = std::fs::File::create(&path).unwrap();
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(props)).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+            files.push(path);
+        }
+
+        let ctx = SessionContext::new();
+        let batches = collect(scan(schema, &files), ctx.task_ctx()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rows: usize = batches.unwrap().iter().map(|batch| batch.num_rows()).sum();
+        assert_eq!(rows, 100 * codecs.len());
+    }
+
+    #[actix_web::test]
+    async fn error_contains_file_key() {
+        let path =
+            std::env::temp_dir().join(format!("parseable-corrupt-{}.parquet", ulid::Ulid::new()));
+        std::fs::write(&path, b"not a parquet file at all").unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+
+        let ctx = SessionContext::new();
+        let result = collect(scan(schema, &[path.clone()]), ctx.task_ctx()).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let err = result.unwrap_err().to_string();
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        assert!(err.contains(file_name), "{err}");
+    }
+}
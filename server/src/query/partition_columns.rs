@@ -0,0 +1,280 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! `date` and `hour` components of Hive style object keys exposed as queryable columns.
+//! Filters on these columns prune files by their key, before any file is opened.
+//! `hour` is exposed for hourly partitioned streams only, it is null for files of the
+//! stream written before it was partitioned by hour.
+
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, StringArray};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use chrono::NaiveDateTime;
+use datafusion::{
+    logical_expr::{BinaryExpr, Operator},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use crate::{
+    option::CONFIG,
+    storage::{
+        key_naming::{KeyNaming, PartitionComponent},
+        partition_granularity::PartitionGranularity,
+    },
+};
+
+pub const DATE_COLUMN: &str = "date";
+pub const HOUR_COLUMN: &str = "hour";
+
+/// Partition columns exposed for a stream with the given schema and granularity.
+/// Empty unless enabled for a Hive key layout, columns clashing with stream fields are left out.
+pub fn partition_fields(schema: &Schema, granularity: PartitionGranularity) -> Vec<Field> {
+    if !CONFIG.parseable.query_partition_columns || CONFIG.parseable.key_naming != KeyNaming::Hive {
+        return Vec::new();
+    }
+    fields_for(schema, granularity)
+}
+
+fn fields_for(schema: &Schema, granularity: PartitionGranularity) -> Vec<Field> {
+    [DATE_COLUMN, HOUR_COLUMN]
+        .into_iter()
+        .filter(|name| *name != HOUR_COLUMN || granularity == PartitionGranularity::Hour)
+        .filter(|name| schema.field_with_name(name).is_err())
+        .map(|name| Field::new(name, DataType::Utf8, true))
+        .collect()
+}
+
+/// Values of the partition columns parsed from an object key or a cached file name
+pub fn partition_values(path: &str, fields: &[Field]) -> Vec<Option<String>> {
+    let components = path
+        .split(['/', '.'])
+        .filter_map(PartitionComponent::from_hive)
+        .collect::<Vec<_>>();
+
+    fields
+        .iter()
+        .map(|field| {
+            components.iter().find_map(|component| match component {
+                PartitionComponent::Date(date) if field.name() == DATE_COLUMN => {
+                    Some(date.format("%Y-%m-%d").to_string())
+                }
+                PartitionComponent::Hour(hour) if field.name() == HOUR_COLUMN => {
+                    Some(format!("{hour:02}"))
+                }
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+/// Values of the partition columns for data at `time`, used for rows still in staging
+pub fn partition_values_at(time: NaiveDateTime, fields: &[Field]) -> Vec<Option<String>> {
+    let path = format!(
+        "date={}/hour={}",
+        time.format("%Y-%m-%d"),
+        time.format("%H")
+    );
+    partition_values(&path, fields)
+}
+
+pub fn to_scalars(values: Vec<Option<String>>) -> Vec<ScalarValue> {
+    values.into_iter().map(ScalarValue::Utf8).collect()
+}
+
+/// Appends constant partition columns to a batch
+pub fn with_partition_columns(
+    batch: &RecordBatch,
+    schema: Arc<Schema>,
+    values: &[Option<String>],
+) -> Result<RecordBatch, ArrowError> {
+    let mut columns = batch.columns().to_vec();
+    for value in values {
+        let column: ArrayRef = Arc::new(StringArray::from(vec![value.clone(); batch.num_rows()]));
+        columns.push(column);
+    }
+    RecordBatch::try_new(schema, columns)
+}
+
+/// Whether a filter references any of the partition columns
+pub fn references_partition(filter: &Expr, fields: &[Field]) -> bool {
+    filter
+        .to_columns()
+        .map(|columns| {
+            columns
+                .iter()
+                .any(|column| fields.iter().any(|field| field.name() == &column.name))
+        })
+        .unwrap_or(false)
+}
+
+/// Whether a file with the given partition values can't match the filter.
+/// Only comparisons and IN lists of a partition column with string literals are evaluated.
+pub fn can_be_pruned(filter: &Expr, fields: &[Field], values: &[Option<String>]) -> bool {
+    let value_of = |expr: &Expr| match expr {
+        Expr::Column(column) => fields
+            .iter()
+            .position(|field| field.name() == &column.name)
+            .map(|index| values[index].as_deref()),
+        _ => None,
+    };
+    let literal = |expr: &Expr| match expr {
+        Expr::Literal(ScalarValue::Utf8(Some(value)))
+        | Expr::Literal(ScalarValue::LargeUtf8(Some(value))) => Some(value.clone()),
+        _ => None,
+    };
+
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) if *op == Operator::And => {
+            can_be_pruned(left, fields, values) || can_be_pruned(right, fields, values)
+        }
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let (value, literal, op) = match (value_of(left.as_ref()), literal(right.as_ref())) {
+                (Some(value), Some(literal)) => (value, literal, *op),
+                _ => match (literal(left.as_ref()), value_of(right.as_ref()), op.swap()) {
+                    (Some(literal), Some(value), Some(op)) => (value, literal, op),
+                    _ => return false,
+                },
+            };
+            // the column is null for files without the partition in their key,
+            // which no comparison matches
+            let Some(value) = value else {
+                return true;
+            };
+            let value = value.to_owned();
+            match op {
+                Operator::Eq => value != literal,
+                Operator::NotEq => value == literal,
+                Operator::Lt => value >= literal,
+                Operator::LtEq => value > literal,
+                Operator::Gt => value <= literal,
+                Operator::GtEq => value < literal,
+                _ => false,
+            }
+        }
+        Expr::InList(in_list) => {
+            let Some(value) = value_of(in_list.expr.as_ref()) else {
+                return false;
+            };
+            let Some(value) = value else {
+                return true;
+            };
+            let Some(list) = in_list.list.iter().map(literal).collect::<Option<Vec<_>>>() else {
+                return false;
+            };
+            list.iter().any(|item| item == value) == in_list.negated
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::prelude::{col, lit};
+
+    use crate::storage::partition_granularity::PartitionGranularity::{Day, Hour};
+
+    use super::{can_be_pruned, fields_for, partition_values};
+
+    #[test]
+    fn values_from_object_key_and_cached_file() {
+        let fields = fields_for(&Schema::empty(), Hour);
+
+        assert_eq!(
+            partition_values(
+                "app/date=2024-01-01/hour=10/minute=05/host.data.parquet",
+                &fields
+            ),
+            [Some("2024-01-01".to_string()), Some("10".to_string())]
+        );
+        assert_eq!(
+            partition_values(
+                "/cache/app/date=2024-01-01.hour=10.minute=05.host.data.parquet",
+                &fields
+            ),
+            [Some("2024-01-01".to_string()), Some("10".to_string())]
+        );
+    }
+
+    #[test]
+    fn stream_fields_take_precedence() {
+        let schema = Schema::new(vec![Field::new("date", DataType::Utf8, true)]);
+        let fields = fields_for(&schema, Hour);
+
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].name(), "hour");
+        assert!(fields_for(&schema, Day).is_empty());
+    }
+
+    #[test]
+    fn partition_filter_skips_other_directories() {
+        let fields = fields_for(&Schema::empty(), Hour);
+        let files = [
+            "app/date=2023-12-31/hour=23/minute=59/host.data.parquet",
+            "app/date=2024-01-01/hour=00/minute=00/host.data.parquet",
+            "app/date=2024-01-02/hour=00/minute=00/host.data.parquet",
+        ];
+        let matching = |filter| {
+            files
+                .iter()
+                .filter(|file| !can_be_pruned(&filter, &fields, &partition_values(file, &fields)))
+                .count()
+        };
+
+        assert_eq!(matching(col("date").eq(lit("2024-01-01"))), 1);
+        assert_eq!(matching(col("date").gt_eq(lit("2024-01-01"))), 2);
+        assert_eq!(matching(lit("2024-01-01").gt(col("date"))), 1);
+        assert_eq!(
+            matching(col("date").in_list(vec![lit("2023-12-31"), lit("2024-01-02")], false)),
+            2
+        );
+        assert_eq!(
+            matching(
+                col("date")
+                    .eq(lit("2024-01-01"))
+                    .and(col("hour").eq(lit("01")))
+            ),
+            0
+        );
+        // filters on other columns never prune
+        assert_eq!(matching(col("level").eq(lit("error"))), 3);
+    }
+
+    #[test]
+    fn hour_filter_skips_files_of_daily_partitions() {
+        let fields = fields_for(&Schema::empty(), Hour);
+        // written before the stream was partitioned by hour
+        let daily = partition_values("app/date=2024-01-01/host.data.parquet", &fields);
+        let hourly = partition_values("app/date=2024-01-01/hour=10/host.data.parquet", &fields);
+        assert_eq!(daily, [Some("2024-01-01".to_string()), None]);
+
+        let filter = col("hour").eq(lit("10"));
+        assert!(can_be_pruned(&filter, &fields, &daily));
+        assert!(!can_be_pruned(&filter, &fields, &hourly));
+        let filter = col("hour").in_list(vec![lit("09"), lit("10")], true);
+        assert!(can_be_pruned(&filter, &fields, &daily));
+        // date filters still apply to them
+        assert!(!can_be_pruned(
+            &col("date").eq(lit("2024-01-01")),
+            &fields,
+            &daily
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Physical format of the data files of a partition. Partitions are written as parquet,
+//! archived partitions may be kept in another format, recorded with the partition in the
+//! snapshot. Queries plan every format with its own file source and union the results,
+//! so a query spans partitions of any format. The codec of parquet files is read from
+//! the files themselves and needs no recording.
+
+use std::sync::Arc;
+
+use datafusion::{
+    config::TableParquetOptions,
+    datasource::{
+        file_format::file_compression_type::FileCompressionType,
+        physical_plan::{FileScanConfig, NdJsonExec, ParquetExec},
+    },
+    physical_expr::PhysicalExpr,
+    physical_plan::ExecutionPlan,
+};
+use object_store::ObjectStore;
+
+use super::parquet_reader::CheckedParquetFileReaderFactory;
+use crate::catalog::snapshot::Snapshot;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PartitionFormat {
+    /// Parquet files as uploaded from staging
+    #[default]
+    Parquet,
+    /// Newline delimited JSON files, each compressed as a whole
+    Json {
+        #[serde(default)]
+        compression: Codec,
+    },
+}
+
+/// Compression of whole files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    Uncompressed,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl From<Codec> for FileCompressionType {
+    fn from(codec: Codec) -> Self {
+        match codec {
+            Codec::Uncompressed => FileCompressionType::UNCOMPRESSED,
+            Codec::Gzip => FileCompressionType::GZIP,
+            Codec::Zstd => FileCompressionType::ZSTD,
+            Codec::Bzip2 => FileCompressionType::BZIP2,
+            Codec::Xz => FileCompressionType::XZ,
+        }
+    }
+}
+
+impl PartitionFormat {
+    pub fn is_parquet(&self) -> bool {
+        *self == PartitionFormat::Parquet
+    }
+
+    /// Plan reading the files of `config` in this format. Only parquet files are
+    /// pruned with `predicate` and declare the sort order of the stream.
+    pub fn create_physical_plan(
+        self,
+        mut config: FileScanConfig,
+        predicate: Option<Arc<dyn PhysicalExpr>>,
+        parquet_options: TableParquetOptions,
+        store: Arc<dyn ObjectStore>,
+    ) -> Arc<dyn ExecutionPlan> {
+        match self {
+            PartitionFormat::Parquet => {
+                // files are read through a reader which reports the offending file
+                // when it cannot be decoded
+                let plan = ParquetExec::new(config, predicate, None, parquet_options)
+                    .with_parquet_file_reader_factory(Arc::new(
+                        CheckedParquetFileReaderFactory::new(store),
+                    ));
+                Arc::new(plan)
+            }
+            PartitionFormat::Json { compression } => {
+                config.output_ordering = Vec::new();
+                Arc::new(NdJsonExec::new(config, compression.into()))
+            }
+        }
+    }
+}
+
+/// Splits the partitions of a snapshot into the parquet ones and the ones of each other format
+pub fn split(snapshot: Snapshot) -> (Snapshot, Vec<(PartitionFormat, Snapshot)>) {
+    let mut parquet = Vec::new();
+    let mut others: Vec<(PartitionFormat, Snapshot)> = Vec::new();
+    for item in snapshot.manifest_list {
+        if item.format.is_parquet() {
+            parquet.push(item);
+            continue;
+        }
+        match others.iter_mut().find(|(format, _)| *format == item.format) {
+            Some((_, snapshot)) => snapshot.manifest_list.push(item),
+            None => others.push((
+                item.format,
+                Snapshot {
+                    manifest_list: vec![item],
+                    version: snapshot.version.clone(),
+                },
+            )),
+        }
+    }
+    (
+        Snapshot {
+            manifest_list: parquet,
+            version: snapshot.version,
+        },
+        others,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Arc};
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use datafusion::{
+        config::TableParquetOptions,
+        datasource::{
+            file_format::file_compression_type::FileCompressionType, listing::PartitionedFile,
+            physical_plan::FileScanConfig,
+        },
+        execution::object_store::ObjectStoreUrl,
+        physical_plan::{collect, union::UnionExec, Statistics},
+        prelude::SessionContext,
+    };
+    use futures_util::{StreamExt, TryStreamExt};
+    use object_store::local::LocalFileSystem;
+    use parquet::{
+        arrow::ArrowWriter,
+        basic::{Compression, ZstdLevel},
+        file::properties::WriterProperties,
+    };
+
+    use super::{Codec, PartitionFormat};
+
+    fn config(schema: Arc<Schema>, path: &std::path::Path) -> FileScanConfig {
+        let size = std::fs::metadata(path).unwrap().len();
+        FileScanConfig {
+            object_store_url: ObjectStoreUrl::local_filesystem(),
+            file_schema: schema.clone(),
+            file_groups: vec![vec![PartitionedFile::new(
+                path.to_str().unwrap().to_string(),
+                size,
+            )]],
+            statistics: Statistics::new_unknown(&schema),
+            projection: None,
+            limit: None,
+            output_ordering: vec![],
+            table_partition_cols: vec![],
+        }
+    }
+
+    #[test]
+    fn formats_are_tagged_by_type() {
+        let json = PartitionFormat::Json {
+            compression: Codec::Gzip,
+        };
+        assert_eq!(
+            serde_json::to_value(json).unwrap(),
+            serde_json::json!({"type": "json", "compression": "gzip"})
+        );
+        let parsed: PartitionFormat = serde_json::from_str(r#"{"type": "json"}"#).unwrap();
+        assert_eq!(
+            parsed,
+            PartitionFormat::Json {
+                compression: Codec::Uncompressed
+            }
+        );
+    }
+
+    #[actix_web::test]
+    async fn query_spans_partitions_of_different_formats() {
+        let dir = std::env::temp_dir().join(format!("parseable-formats-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..100))],
+        )
+        .unwrap();
+
+        // a hot partition as uploaded
+        let parquet = dir.join("hot.parquet");
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let mut writer = ArrowWriter::try_new(
+            std::fs::File::create(&parquet).unwrap(),
+            schema.clone(),
+            Some(props),
+        )
+        .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        // archived partitions as plain and gzip compressed JSON
+        let mut ndjson = Vec::new();
+        for value in 0..50 {
+            writeln!(ndjson, r#"{{"a": {value}}}"#).unwrap();
+        }
+        let plain = dir.join("archived.json");
+        std::fs::write(&plain, &ndjson).unwrap();
+        let compressed: Vec<Bytes> = FileCompressionType::GZIP
+            .convert_to_compress_stream(
+                futures_util::stream::once(async move { Ok(Bytes::from(ndjson)) }).boxed(),
+            )
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let gzip = dir.join("archived.json.gz");
+        std::fs::write(&gzip, compressed.concat()).unwrap();
+
+        let store = Arc::new(LocalFileSystem::new());
+        let plans = [
+            (PartitionFormat::Parquet, &parquet),
+            (
+                PartitionFormat::Json {
+                    compression: Codec::Uncompressed,
+                },
+                &plain,
+            ),
+            (
+                PartitionFormat::Json {
+                    compression: Codec::Gzip,
+                },
+                &gzip,
+            ),
+        ]
+        .into_iter()
+        .map(|(format, path)| {
+            format.create_physical_plan(
+                config(schema.clone(), path),
+                None,
+                TableParquetOptions::default(),
+                store.clone(),
+            )
+        })
+        .collect();
+
+        let ctx = SessionContext::new();
+        let batches = collect(Arc::new(UnionExec::new(plans)), ctx.task_ctx()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rows: usize = batches.unwrap().iter().map(|batch| batch.num_rows()).sum();
+        assert_eq!(rows, 200);
+    }
+}
@@ -0,0 +1,257 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! A parquet file that can't be decoded fails every query over its time range. When
+//! quarantining is enabled such a file is removed from its manifest and moved under the
+//! quarantine prefix, keeping the rest of the stream queryable. Only files listed in the
+//! manifests written by this server are moved, a query server leaves files of ingestors
+//! in place.
+
+use std::error::Error;
+
+use datafusion::error::DataFusionError;
+use object_store::path::Path;
+use relative_path::{RelativePath, RelativePathBuf};
+
+use crate::catalog::{self, manifest::Manifest};
+use crate::metrics::QUARANTINED_FILES;
+use crate::option::{Mode, CONFIG};
+use crate::storage::object_storage::manifest_path;
+use crate::storage::{ObjectStorage, ObjectStorageError, QUARANTINE_ROOT_DIRECTORY};
+
+use super::parquet_reader::CorruptParquetFile;
+
+/// Key of the corrupt parquet file that failed a query, if the error was caused by one
+pub fn corrupt_file(err: &DataFusionError) -> Option<&Path> {
+    let mut source: Option<&(dyn Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if let Some(corrupt) = err.downcast_ref::<CorruptParquetFile>() {
+            return Some(&corrupt.location);
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Logs the corrupt file that failed a query and quarantines it in the background if enabled
+pub fn on_query_error(err: &DataFusionError) {
+    let Some(location) = corrupt_file(err) else {
+        return;
+    };
+    log::error!("query failed to decode parquet file {location}");
+    if !CONFIG.parseable.quarantine_corrupt_files || CONFIG.parseable.mode == Mode::Query {
+        return;
+    }
+
+    let location = location.clone();
+    tokio::spawn(async move {
+        let storage = CONFIG.storage().get_object_store();
+        match quarantine(&*storage, &location).await {
+            Ok(Some(stream)) => {
+                QUARANTINED_FILES.with_label_values(&[&stream]).inc();
+                log::warn!("moved corrupt parquet file {location} to quarantine");
+            }
+            Ok(None) => {
+                log::warn!("corrupt parquet file {location} is not in a manifest of this server")
+            }
+            Err(err) => log::error!("failed to quarantine parquet file {location}: {err}"),
+        }
+    });
+}
+
+/// Quarantines the file at `location` of the store. Returns the stream of the file,
+/// none if no manifest of this server lists it.
+async fn quarantine(
+    storage: &(impl ObjectStorage + ?Sized),
+    location: &Path,
+) -> Result<Option<String>, ObjectStorageError> {
+    // files read from the local cache are outside of the store
+    let Some(key) = object_key(storage, location) else {
+        return Ok(None);
+    };
+    let Some(stream) = key.iter().next().map(str::to_owned) else {
+        return Ok(None);
+    };
+    let meta = match storage.get_object_store_format(&stream).await {
+        Ok(meta) => meta,
+        Err(ObjectStorageError::NoSuchKey(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    for item in meta.snapshot.manifest_list {
+        let partition =
+            catalog::partition_path(&stream, item.time_lower_bound, item.time_upper_bound);
+        if !key.starts_with(&partition) {
+            continue;
+        }
+        let manifest = manifest_path(partition.as_str());
+        if quarantine_file(storage, location, &key, &manifest).await? {
+            catalog::invalidate_column_summary(&stream);
+            return Ok(Some(stream));
+        }
+    }
+    Ok(None)
+}
+
+// key of the object relative to the root of the store
+fn object_key(storage: &(impl ObjectStorage + ?Sized), location: &Path) -> Option<RelativePathBuf> {
+    let root = storage.absolute_url(RelativePath::new(""));
+    let parts: Vec<_> = location.prefix_match(&root)?.collect();
+    Some(RelativePathBuf::from_iter(
+        parts.iter().map(|part| part.as_ref()),
+    ))
+}
+
+/// Removes the file at `location` from the manifest object at `manifest` and moves
+/// the object at `key` under the quarantine prefix. The object is copied before the
+/// manifest is written and deleted after, a failure leaves the file queryable or in
+/// both places but never lost. Returns false if the manifest doesn't list the file.
+async fn quarantine_file(
+    storage: &(impl ObjectStorage + ?Sized),
+    location: &Path,
+    key: &RelativePath,
+    manifest: &RelativePath,
+) -> Result<bool, ObjectStorageError> {
+    let mut contents: Manifest = match storage.get_object(manifest).await {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(ObjectStorageError::NoSuchKey(_)) => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    let files = contents.files.len();
+    contents
+        .files
+        .retain(|file| Path::from(file.file_path.as_str()) != *location);
+    if contents.files.len() == files {
+        return Ok(false);
+    }
+
+    let quarantine_key = RelativePathBuf::from_iter([QUARANTINE_ROOT_DIRECTORY, key.as_str()]);
+    let bytes = storage.get_object(key).await?;
+    storage.put_object(&quarantine_key, bytes).await?;
+    storage
+        .put_object(manifest, serde_json::to_vec(&contents)?.into())
+        .await?;
+    storage.delete_object(key).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use datafusion::{
+        config::TableParquetOptions,
+        datasource::{
+            listing::PartitionedFile,
+            physical_plan::{FileScanConfig, ParquetExec},
+        },
+        execution::object_store::ObjectStoreUrl,
+        physical_plan::{collect, Statistics},
+        prelude::SessionContext,
+    };
+    use object_store::local::LocalFileSystem;
+    use relative_path::{RelativePath, RelativePathBuf};
+
+    use super::{corrupt_file, object_key, quarantine_file};
+    use crate::catalog::manifest::{File, Manifest};
+    use crate::query::parquet_reader::CheckedParquetFileReaderFactory;
+    use crate::storage::{localfs::LocalFS, ObjectStorage, ObjectStorageError};
+
+    #[actix_web::test]
+    async fn corrupt_file_is_moved_out_of_the_manifest() {
+        let root = std::env::temp_dir().join(format!("parseable-quarantine-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        let key = RelativePath::new("app/date=2024-01-01/hour=00/minute=00/data.parquet");
+        let good = RelativePath::new("app/date=2024-01-01/hour=00/minute=01/data.parquet");
+        let manifest = RelativePath::new("app/date=2024-01-01/manifest.json");
+        storage
+            .put_object(key, Bytes::from_static(b"truncated by a failed upload"))
+            .await
+            .unwrap();
+        let file = |key: &RelativePath| File {
+            file_path: storage.absolute_url(key).to_string(),
+            file_size: 28,
+            ..File::default()
+        };
+        let contents = Manifest {
+            files: vec![file(key), file(good)],
+            ..Manifest::default()
+        };
+        storage
+            .put_object(manifest, serde_json::to_vec(&contents).unwrap().into())
+            .await
+            .unwrap();
+
+        // queries fail with the location of the file attached
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let path = root.join(key.as_str());
+        let exec = ParquetExec::new(
+            FileScanConfig {
+                object_store_url: ObjectStoreUrl::local_filesystem(),
+                file_schema: schema.clone(),
+                file_groups: vec![vec![PartitionedFile::new(
+                    path.to_str().unwrap().to_string(),
+                    28,
+                )]],
+                statistics: Statistics::new_unknown(&schema),
+                projection: None,
+                limit: None,
+                output_ordering: vec![],
+                table_partition_cols: vec![],
+            },
+            None,
+            None,
+            TableParquetOptions::default(),
+        )
+        .with_parquet_file_reader_factory(Arc::new(CheckedParquetFileReaderFactory::new(
+            Arc::new(LocalFileSystem::new()),
+        )));
+        let err = collect(Arc::new(exec), SessionContext::new().task_ctx())
+            .await
+            .unwrap_err();
+        let location = corrupt_file(&err).cloned();
+        let found_key = location
+            .as_ref()
+            .and_then(|location| object_key(&storage, location));
+
+        let moved = match &location {
+            Some(location) => Some(quarantine_file(&storage, location, key, manifest).await),
+            None => None,
+        };
+        let original = storage.get_object(key).await;
+        let quarantined = storage
+            .get_object(&RelativePathBuf::from_iter(["quarantine", key.as_str()]))
+            .await;
+        let remaining: Manifest =
+            serde_json::from_slice(&storage.get_object(manifest).await.unwrap()).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(location.is_some(), "{err}");
+        assert_eq!(found_key.as_deref(), Some(key));
+        assert!(moved.unwrap().unwrap());
+        assert!(matches!(original, Err(ObjectStorageError::NoSuchKey(_))));
+        assert_eq!(
+            quarantined.unwrap(),
+            Bytes::from_static(b"truncated by a failed upload")
+        );
+        let remaining: Vec<_> = remaining.files.into_iter().map(|f| f.file_path).collect();
+        assert_eq!(remaining, [storage.absolute_url(good).to_string()]);
+    }
+}
@@ -0,0 +1,723 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! An aggregation of a stream with a rollup reads the rollup stream for the whole minutes
+//! the rollup covers and raw events for the rest of the queried range. Queries grouping by
+//! dimensions and time buckets of a minute or coarser, aggregating measures with count,
+//! min, max, sum or avg and filtering on time and dimensions only are routed. The per
+//! minute partial aggregates of both sources are combined by the grouping of the query.
+
+use std::ops::Bound;
+
+use arrow_schema::DataType;
+use chrono::{DateTime, DurationRound, NaiveDateTime, TimeDelta};
+use datafusion::{
+    common::{
+        tree_node::{Transformed, TreeNode},
+        Column, OwnedTableReference,
+    },
+    error::DataFusionError,
+    execution::context::SessionState,
+    logical_expr::{
+        aggregate_function::AggregateFunction as AggregateKind,
+        expr::{AggregateFunction, AggregateFunctionDefinition, ScalarFunction},
+        utils::{conjunction, split_conjunction},
+        Aggregate, BinaryExpr, ExprSchemable, LogicalPlan, LogicalPlanBuilder, Operator,
+    },
+    prelude::*,
+    scalar::ScalarValue,
+};
+use itertools::Itertools;
+
+use super::{as_of, count_star, QUERY_SESSION};
+use crate::event::DEFAULT_TIMESTAMP_KEY;
+use crate::metadata::{resolve_stream_alias, STREAM_INFO};
+use crate::storage::rollup::{Coverage, Rollup, COUNT_COLUMN, PARTIALS};
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+
+/// Units of `date_trunc` whose buckets are made of whole minutes
+const TIME_BUCKETS: [&str; 7] = ["minute", "hour", "day", "week", "month", "quarter", "year"];
+
+/// Plan answering `plan` from the rollup of its stream where the rollup covers the queried
+/// range, `plan` itself if it isn't an aggregation the rollup answers
+pub async fn route(plan: LogicalPlan) -> Result<LogicalPlan, DataFusionError> {
+    // the rollup holds data materialized after the cutoff
+    if as_of::cutoff().is_some() {
+        return Ok(plan);
+    }
+    let Some(table) = find_aggregate(&plan).and_then(scan) else {
+        return Ok(plan);
+    };
+    let stream = resolve_stream_alias(&table.table);
+    let Ok(Some(rollup)) = STREAM_INFO.get_rollup(&stream) else {
+        return Ok(plan);
+    };
+    let rollup_stream = Rollup::stream_name(&stream);
+    if !STREAM_INFO.stream_exists(&rollup_stream) {
+        return Ok(plan);
+    }
+    match rewrite(&plan, &QUERY_SESSION.state(), &rollup, &rollup_stream).await? {
+        Some(routed) => {
+            log::debug!("routed aggregation of {stream} to {rollup_stream}");
+            Ok(routed)
+        }
+        None => Ok(plan),
+    }
+}
+
+/// SQL of the per minute partial aggregates of `table` in a time range, one row per
+/// minute and dimension values with the columns of the rollup stream
+pub fn partial_sql(
+    table: &str,
+    rollup: &Rollup,
+    lower: Bound<NaiveDateTime>,
+    upper: Bound<NaiveDateTime>,
+) -> String {
+    let minute = format!(
+        "arrow_cast(date_trunc('minute', {}), 'Timestamp(Millisecond, None)')",
+        quote(DEFAULT_TIMESTAMP_KEY)
+    );
+    let mut select = vec![format!("{minute} AS {}", quote(DEFAULT_TIMESTAMP_KEY))];
+    let mut group_by = vec![minute];
+    for dimension in &rollup.dimensions {
+        select.push(quote(dimension));
+        group_by.push(quote(dimension));
+    }
+    select.push(format!("COUNT(*) AS {}", quote(COUNT_COLUMN)));
+    for measure in &rollup.measures {
+        for (function, suffix) in PARTIALS {
+            select.push(format!(
+                "{function}({}) AS {}",
+                quote(measure),
+                quote(&Rollup::partial_column(measure, suffix))
+            ));
+        }
+    }
+    format!(
+        "SELECT {} FROM {} WHERE {} GROUP BY {}",
+        select.join(", "),
+        quote(table),
+        time_predicate(lower, upper),
+        group_by.join(", ")
+    )
+}
+
+/// `plan` with its aggregation combining the rollup in `rollup_table` and raw events,
+/// none if the aggregation doesn't match the rollup or the rollup covers no whole minute
+/// of the queried range
+async fn rewrite(
+    plan: &LogicalPlan,
+    state: &SessionState,
+    rollup: &Rollup,
+    rollup_table: &str,
+) -> Result<Option<LogicalPlan>, DataFusionError> {
+    let Some(matched) = Matched::new(plan, rollup) else {
+        return Ok(None);
+    };
+    let Some(coverage) = rollup.coverage else {
+        return Ok(None);
+    };
+    let Some((start, end)) = rolled_up_range(matched.lower, matched.upper, coverage) else {
+        return Ok(None);
+    };
+
+    let mut sources = vec![format!(
+        "SELECT {} FROM {} WHERE {}",
+        rollup
+            .columns()
+            .iter()
+            .map(|column| quote(column))
+            .join(", "),
+        quote(rollup_table),
+        time_predicate(Bound::Included(start), Bound::Excluded(end))
+    )];
+    if time_of(matched.lower).is_some_and(|lower| lower < start) {
+        sources.push(partial_sql(
+            &matched.table,
+            rollup,
+            matched.lower,
+            Bound::Excluded(start),
+        ));
+    }
+    let after = match matched.upper {
+        Bound::Included(upper) => upper >= end,
+        Bound::Excluded(upper) => upper > end,
+        Bound::Unbounded => false,
+    };
+    if after {
+        sources.push(partial_sql(
+            &matched.table,
+            rollup,
+            Bound::Included(end),
+            matched.upper,
+        ));
+    }
+    let partials = state
+        .create_logical_plan(&sources.join(" UNION ALL "))
+        .await?;
+
+    let qualifier = matched.qualifier.clone();
+    let partial = |column: &str| Expr::Column(Column::new(Some(qualifier.clone()), column));
+    let mut aggregates = Vec::new();
+    let mut projection = matched
+        .aggregate
+        .group_expr
+        .iter()
+        .map(|expr| match expr {
+            Expr::Column(column) => Ok(Expr::Column(column.clone())),
+            expr => Ok(Expr::Column(Column::from_name(expr.display_name()?))),
+        })
+        .collect::<Result<Vec<_>, DataFusionError>>()?;
+    for expr in &matched.aggregate.aggr_expr {
+        let Some((kind, measure)) = rollup_aggregate(expr, rollup) else {
+            return Ok(None);
+        };
+        let measure_partial =
+            |suffix: &str| partial(&Rollup::partial_column(measure.as_deref().unwrap(), suffix));
+        let combined = match (kind, &measure) {
+            (AggregateKind::Count, None) => {
+                zero_if_null(add_aggregate(&mut aggregates, sum(partial(COUNT_COLUMN)))?)?
+            }
+            (AggregateKind::Count, Some(_)) => zero_if_null(add_aggregate(
+                &mut aggregates,
+                sum(measure_partial("count")),
+            )?)?,
+            (AggregateKind::Min, _) => add_aggregate(&mut aggregates, min(measure_partial("min")))?,
+            (AggregateKind::Max, _) => add_aggregate(&mut aggregates, max(measure_partial("max")))?,
+            (AggregateKind::Sum, _) => add_aggregate(&mut aggregates, sum(measure_partial("sum")))?,
+            (AggregateKind::Avg, _) => {
+                let total = add_aggregate(&mut aggregates, sum(measure_partial("sum")))?;
+                let count = add_aggregate(&mut aggregates, sum(measure_partial("count")))?;
+                cast(total, DataType::Float64) / cast(count, DataType::Float64)
+            }
+            _ => return Ok(None),
+        };
+        let data_type = expr.get_type(matched.aggregate.input.schema().as_ref())?;
+        projection.push(cast(combined, data_type).alias(expr.display_name()?));
+    }
+
+    let mut builder = LogicalPlanBuilder::from(partials).alias(qualifier)?;
+    if let Some(filter) = conjunction(matched.filters) {
+        builder = builder.filter(filter)?;
+    }
+    let aggregate = builder
+        .aggregate(matched.aggregate.group_expr.clone(), aggregates)?
+        .project(projection)?
+        .build()?;
+    replace_aggregate(plan, &aggregate).map(Some)
+}
+
+/// Aggregation of a single table by a query, with the time range and filters it's over
+struct Matched<'a> {
+    aggregate: &'a Aggregate,
+    table: String,
+    /// name the columns of the table are referred to by above the scan
+    qualifier: OwnedTableReference,
+    lower: Bound<NaiveDateTime>,
+    upper: Bound<NaiveDateTime>,
+    /// filters on dimensions, unqualified
+    filters: Vec<Expr>,
+}
+
+/// Table scanned by an aggregation and the filters between them
+struct Scan {
+    table: String,
+    qualifier: OwnedTableReference,
+    filters: Vec<Expr>,
+}
+
+impl<'a> Matched<'a> {
+    fn new(plan: &'a LogicalPlan, rollup: &Rollup) -> Option<Self> {
+        let aggregate = find_aggregate(plan)?;
+        if !aggregate
+            .group_expr
+            .iter()
+            .all(|expr| groups_by_rollup(expr, rollup))
+            || !aggregate
+                .aggr_expr
+                .iter()
+                .all(|expr| rollup_aggregate(expr, rollup).is_some())
+        {
+            return None;
+        }
+
+        let scan = scan(aggregate)?;
+        let mut lower = Bound::Unbounded;
+        let mut upper = Bound::Unbounded;
+        let mut filters = Vec::new();
+        for filter in scan.filters {
+            let columns = filter.to_columns().ok()?;
+            if columns.is_empty() {
+                return None;
+            }
+            if columns
+                .iter()
+                .all(|column| column.name == DEFAULT_TIMESTAMP_KEY)
+            {
+                match time_bound(&filter)? {
+                    TimeBound::Lower(bound) => lower = tighter(lower, bound, true),
+                    TimeBound::Upper(bound) => upper = tighter(upper, bound, false),
+                }
+            } else if columns
+                .iter()
+                .all(|column| rollup.dimensions.contains(&column.name))
+            {
+                filters.push(unqualify(filter)?);
+            } else {
+                return None;
+            }
+        }
+        Some(Self {
+            aggregate,
+            table: scan.table,
+            qualifier: scan.qualifier,
+            lower,
+            upper,
+            filters,
+        })
+    }
+}
+
+/// Aggregation under the projections, sorts, limits and filters of its output
+fn find_aggregate(plan: &LogicalPlan) -> Option<&Aggregate> {
+    match plan {
+        LogicalPlan::Aggregate(aggregate) => Some(aggregate),
+        LogicalPlan::Projection(projection) => find_aggregate(&projection.input),
+        LogicalPlan::Sort(sort) => find_aggregate(&sort.input),
+        LogicalPlan::Limit(limit) => find_aggregate(&limit.input),
+        LogicalPlan::Filter(filter) => find_aggregate(&filter.input),
+        _ => None,
+    }
+}
+
+fn scan(aggregate: &Aggregate) -> Option<Scan> {
+    let mut filters = Vec::new();
+    let mut qualifier = None;
+    let mut input = aggregate.input.as_ref();
+    loop {
+        match input {
+            LogicalPlan::Filter(filter) => {
+                filters.extend(split_conjunction(&filter.predicate).into_iter().cloned());
+                input = filter.input.as_ref();
+            }
+            LogicalPlan::SubqueryAlias(alias) if qualifier.is_none() => {
+                qualifier = Some(alias.alias.clone());
+                input = alias.input.as_ref();
+            }
+            LogicalPlan::TableScan(scan) if scan.fetch.is_none() => {
+                filters.extend(scan.filters.iter().flat_map(split_conjunction).cloned());
+                return Some(Scan {
+                    table: scan.table_name.table().to_owned(),
+                    qualifier: qualifier.unwrap_or_else(|| scan.table_name.clone()),
+                    filters,
+                });
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Grouping by a dimension or by a time bucket made of whole minutes
+fn groups_by_rollup(expr: &Expr, rollup: &Rollup) -> bool {
+    match expr {
+        Expr::Column(column) => rollup.dimensions.contains(&column.name),
+        Expr::ScalarFunction(ScalarFunction { func_def, args }) => {
+            func_def.name() == "date_trunc"
+                && matches!(
+                    args.as_slice(),
+                    [Expr::Literal(ScalarValue::Utf8(Some(unit))), Expr::Column(column)]
+                        if TIME_BUCKETS.contains(&unit.to_lowercase().as_str())
+                            && column.name == DEFAULT_TIMESTAMP_KEY
+                )
+        }
+        _ => false,
+    }
+}
+
+/// Aggregate function of a query answered by combining partial aggregates, with the
+/// measure it aggregates. `COUNT(*)` has no measure.
+fn rollup_aggregate(expr: &Expr, rollup: &Rollup) -> Option<(AggregateKind, Option<String>)> {
+    if count_star::is_count_star(expr) {
+        return Some((AggregateKind::Count, None));
+    }
+    let Expr::AggregateFunction(AggregateFunction {
+        func_def: AggregateFunctionDefinition::BuiltIn(kind),
+        args,
+        distinct: false,
+        filter: None,
+        order_by: None,
+        ..
+    }) = expr.clone().unalias()
+    else {
+        return None;
+    };
+    let [Expr::Column(column)] = args.as_slice() else {
+        return None;
+    };
+    match kind {
+        AggregateKind::Count
+        | AggregateKind::Min
+        | AggregateKind::Max
+        | AggregateKind::Sum
+        | AggregateKind::Avg
+            if rollup.measures.contains(&column.name) =>
+        {
+            Some((kind, Some(column.name.clone())))
+        }
+        _ => None,
+    }
+}
+
+/// Adds an aggregate of partial aggregates once, returning the column of its result
+fn add_aggregate(aggregates: &mut Vec<Expr>, aggregate: Expr) -> Result<Expr, DataFusionError> {
+    let column = Expr::Column(Column::from_name(aggregate.display_name()?));
+    if !aggregates.contains(&aggregate) {
+        aggregates.push(aggregate);
+    }
+    Ok(column)
+}
+
+/// Counts of groups without rows are zero, not null
+fn zero_if_null(expr: Expr) -> Result<Expr, DataFusionError> {
+    when(expr.clone().is_null(), lit(0i64)).otherwise(expr)
+}
+
+fn unqualify(expr: Expr) -> Option<Expr> {
+    expr.transform(&|expr| match expr {
+        Expr::Column(column) => Ok(Transformed::yes(Expr::Column(Column::from_name(
+            column.name,
+        )))),
+        expr => Ok(Transformed::no(expr)),
+    })
+    .ok()
+    .map(|expr| expr.data)
+}
+
+/// `plan` with the aggregation replaced, parents are rebuilt on the new schema
+fn replace_aggregate(
+    plan: &LogicalPlan,
+    aggregate: &LogicalPlan,
+) -> Result<LogicalPlan, DataFusionError> {
+    match plan {
+        LogicalPlan::Aggregate(_) => Ok(aggregate.clone()),
+        plan => {
+            let inputs = plan
+                .inputs()
+                .into_iter()
+                .map(|input| replace_aggregate(input, aggregate))
+                .collect::<Result<Vec<_>, _>>()?;
+            plan.with_new_exprs(plan.expressions(), inputs)
+        }
+    }
+}
+
+enum TimeBound {
+    Lower(Bound<NaiveDateTime>),
+    Upper(Bound<NaiveDateTime>),
+}
+
+/// Bound of a comparison of the time column with a timestamp or a time string
+fn time_bound(expr: &Expr) -> Option<TimeBound> {
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return None;
+    };
+    let (Expr::Column(_), Expr::Literal(value)) = (left.as_ref(), right.as_ref()) else {
+        return None;
+    };
+    let time = match value {
+        ScalarValue::TimestampMillisecond(Some(millis), None) => {
+            DateTime::from_timestamp_millis(*millis)?.naive_utc()
+        }
+        ScalarValue::Utf8(Some(time)) => time.parse().ok()?,
+        _ => return None,
+    };
+    let bound = match op {
+        Operator::Gt => TimeBound::Lower(Bound::Excluded(time)),
+        Operator::GtEq => TimeBound::Lower(Bound::Included(time)),
+        Operator::Lt => TimeBound::Upper(Bound::Excluded(time)),
+        Operator::LtEq => TimeBound::Upper(Bound::Included(time)),
+        _ => return None,
+    };
+    Some(bound)
+}
+
+fn time_of(bound: Bound<NaiveDateTime>) -> Option<NaiveDateTime> {
+    match bound {
+        Bound::Included(time) | Bound::Excluded(time) => Some(time),
+        Bound::Unbounded => None,
+    }
+}
+
+/// The narrower of two lower or upper bounds
+fn tighter(a: Bound<NaiveDateTime>, b: Bound<NaiveDateTime>, lower: bool) -> Bound<NaiveDateTime> {
+    match (time_of(a), time_of(b)) {
+        (None, _) => b,
+        (_, None) => a,
+        (Some(x), Some(y)) if x == y => match a {
+            Bound::Excluded(_) => a,
+            _ => b,
+        },
+        (Some(x), Some(y)) if (x > y) == lower => a,
+        _ => b,
+    }
+}
+
+/// Whole minutes of the queried range covered by the rollup
+fn rolled_up_range(
+    lower: Bound<NaiveDateTime>,
+    upper: Bound<NaiveDateTime>,
+    coverage: Coverage,
+) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let minute = TimeDelta::minutes(1);
+    let start = match lower {
+        Bound::Included(time) => {
+            let floor = time.duration_trunc(minute).ok()?;
+            if floor == time {
+                time
+            } else {
+                floor + minute
+            }
+        }
+        Bound::Excluded(time) => time.duration_trunc(minute).ok()? + minute,
+        Bound::Unbounded => return None,
+    };
+    let end = time_of(upper)?.duration_trunc(minute).ok()?;
+    let start = start.max(coverage.from.naive_utc());
+    let end = end.min(coverage.until.naive_utc());
+    (start < end).then_some((start, end))
+}
+
+fn time_predicate(lower: Bound<NaiveDateTime>, upper: Bound<NaiveDateTime>) -> String {
+    let column = quote(DEFAULT_TIMESTAMP_KEY);
+    let mut predicates = Vec::new();
+    match lower {
+        Bound::Included(time) => {
+            predicates.push(format!("{column} >= '{}'", time.format(TIME_FORMAT)))
+        }
+        Bound::Excluded(time) => {
+            predicates.push(format!("{column} > '{}'", time.format(TIME_FORMAT)))
+        }
+        Bound::Unbounded => {}
+    }
+    match upper {
+        Bound::Included(time) => {
+            predicates.push(format!("{column} <= '{}'", time.format(TIME_FORMAT)))
+        }
+        Bound::Excluded(time) => {
+            predicates.push(format!("{column} < '{}'", time.format(TIME_FORMAT)))
+        }
+        Bound::Unbounded => {}
+    }
+    if predicates.is_empty() {
+        "TRUE".to_owned()
+    } else {
+        predicates.join(" AND ")
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+    use datafusion::{
+        arrow::util::pretty::pretty_format_batches, datasource::MemTable,
+        logical_expr::LogicalPlan, prelude::SessionContext,
+    };
+
+    use super::{partial_sql, rewrite, Matched};
+    use crate::storage::rollup::{Coverage, Rollup};
+
+    fn time(minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(10, minute, second)
+            .unwrap()
+    }
+
+    fn rollup() -> Rollup {
+        Rollup {
+            dimensions: vec!["host".to_owned()],
+            measures: vec!["latency".to_owned()],
+            coverage: Some(Coverage {
+                from: Utc.from_utc_datetime(&time(1, 0)),
+                until: Utc.from_utc_datetime(&time(4, 0)),
+            }),
+        }
+    }
+
+    fn context() -> SessionContext {
+        let events = [
+            (time(0, 10), "a", Some(5)),
+            (time(0, 50), "b", Some(7)),
+            (time(1, 5), "a", Some(1)),
+            (time(1, 30), "a", None),
+            (time(1, 45), "b", Some(12)),
+            (time(2, 0), "a", Some(3)),
+            (time(3, 59), "b", Some(8)),
+            (time(4, 20), "a", Some(2)),
+            (time(5, 10), "b", Some(9)),
+            (time(5, 40), "a", Some(4)),
+        ];
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("latency", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from_iter_values(
+                    events
+                        .iter()
+                        .map(|(time, ..)| time.and_utc().timestamp_millis()),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    events.iter().map(|(_, host, _)| *host),
+                )),
+                Arc::new(Int64Array::from_iter(
+                    events.iter().map(|(.., latency)| *latency),
+                )),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_table(
+            "app",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn rows(ctx: &SessionContext, plan: LogicalPlan) -> Vec<String> {
+        let batches = ctx
+            .execute_logical_plan(plan)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let mut rows: Vec<_> = pretty_format_batches(&batches)
+            .unwrap()
+            .to_string()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    /// Plan of `sql` over the queried range, as the query handler builds it
+    async fn plan(ctx: &SessionContext, sql: &str) -> LogicalPlan {
+        let plan = ctx.state().create_logical_plan(sql).await.unwrap();
+        super::super::transform(plan, time(0, 30), time(5, 30), None, &None).data
+    }
+
+    #[actix_web::test]
+    async fn partials_are_aggregated_per_minute_and_dimension() {
+        let ctx = context();
+        let sql = partial_sql(
+            "app",
+            &rollup(),
+            Bound::Included(time(1, 0)),
+            Bound::Excluded(time(3, 0)),
+        );
+        let partials = ctx.sql(&sql).await.unwrap().logical_plan().clone();
+        assert_eq!(
+            rows(&ctx, partials).await,
+            [
+                "+---------------------+------+---------+-------------+-------------+-------------+---------------+",
+                "+---------------------+------+---------+-------------+-------------+-------------+---------------+",
+                "+---------------------+------+---------+-------------+-------------+-------------+---------------+",
+                "| 2024-05-01T10:01:00 | a    | 2       | 1           | 1           | 1           | 1             |",
+                "| 2024-05-01T10:01:00 | b    | 1       | 12          | 12          | 12          | 1             |",
+                "| 2024-05-01T10:02:00 | a    | 1       | 3           | 3           | 3           | 1             |",
+                "| p_timestamp         | host | p_count | latency_min | latency_max | latency_sum | latency_count |",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn only_aggregations_answered_by_the_rollup_are_matched() {
+        let ctx = context();
+        let rollup = rollup();
+        for sql in [
+            "select host, count(*), avg(latency) from app group by host",
+            "select date_trunc('hour', p_timestamp), sum(latency) from app group by date_trunc('hour', p_timestamp)",
+            "select count(*) as total from app where host = 'a' and p_timestamp >= '2024-05-01T10:01:00'",
+        ] {
+            assert!(Matched::new(&plan(&ctx, sql).await, &rollup).is_some(), "{sql}");
+        }
+        for sql in [
+            "select * from app",
+            "select latency, count(*) from app group by latency",
+            "select count(distinct latency) from app",
+            "select count(*) from app where latency > 3",
+            "select date_trunc('second', p_timestamp), count(*) from app group by date_trunc('second', p_timestamp)",
+        ] {
+            assert!(Matched::new(&plan(&ctx, sql).await, &rollup).is_none(), "{sql}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn routed_aggregations_match_raw_results() {
+        let ctx = context();
+        let rollup = rollup();
+        // rollup materialized for its coverage
+        let sql = partial_sql(
+            "app",
+            &rollup,
+            Bound::Included(time(1, 0)),
+            Bound::Excluded(time(4, 0)),
+        );
+        let df = ctx.sql(&sql).await.unwrap();
+        let schema = Arc::new(df.schema().into());
+        let table = MemTable::try_new(schema, vec![df.collect().await.unwrap()]).unwrap();
+        ctx.register_table("app_rollup", Arc::new(table)).unwrap();
+
+        for sql in [
+            "select host, count(*), count(latency), min(latency), max(latency), sum(latency), avg(latency) from app group by host",
+            "select date_trunc('minute', p_timestamp) as minute, count(*) as events from app where host = 'a' group by date_trunc('minute', p_timestamp) order by minute",
+            "select count(*) from app",
+        ] {
+            let raw = plan(&ctx, sql).await;
+            let routed = rewrite(&raw, &ctx.state(), &rollup, "app_rollup")
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                routed.display_indent().to_string().contains("app_rollup"),
+                "{sql}"
+            );
+            assert_eq!(rows(&ctx, routed).await, rows(&ctx, raw).await, "{sql}");
+        }
+    }
+}
@@ -18,15 +18,16 @@
 
 use crate::Mode;
 use crate::{
-    catalog::snapshot::{self, Snapshot},
+    catalog::snapshot::Snapshot,
     storage::{ObjectStoreFormat, STREAM_ROOT_DIRECTORY},
 };
 use arrow_array::RecordBatch;
-use arrow_schema::{Schema, SchemaRef, SortOptions};
+use arrow_schema::{Field, Schema, SchemaRef, SortOptions};
 use bytes::Bytes;
 use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
 use datafusion::common::stats::Precision;
-use datafusion::logical_expr::utils::conjunction;
+use datafusion::config::TableParquetOptions;
+use datafusion::logical_expr::utils::{conjunction, split_conjunction};
 use datafusion::{
     catalog::schema::SchemaProvider,
     common::{
@@ -34,21 +35,19 @@ use datafusion::{
         ToDFSchema,
     },
     datasource::{
-        file_format::{parquet::ParquetFormat, FileFormat},
-        listing::PartitionedFile,
-        physical_plan::FileScanConfig,
-        MemTable, TableProvider,
+        file_format::parquet::ParquetFormat, listing::PartitionedFile,
+        physical_plan::FileScanConfig, MemTable, TableProvider,
     },
     error::{DataFusionError, Result as DataFusionResult},
     execution::{context::SessionState, object_store::ObjectStoreUrl},
-    logical_expr::{BinaryExpr, Operator, TableProviderFilterPushDown, TableType},
+    logical_expr::{expr::InList, BinaryExpr, Operator, TableProviderFilterPushDown, TableType},
     physical_expr::{create_physical_expr, PhysicalSortExpr},
     physical_plan::{self, empty::EmptyExec, union::UnionExec, ExecutionPlan, Statistics},
     prelude::Expr,
     scalar::ScalarValue,
 };
 
-use futures_util::{stream::FuturesOrdered, StreamExt, TryFutureExt, TryStreamExt};
+use futures_util::{stream::FuturesOrdered, StreamExt};
 use itertools::Itertools;
 use object_store::{path::Path, ObjectStore};
 use relative_path::RelativePathBuf;
@@ -56,18 +55,20 @@ use std::{any::Any, collections::HashMap, ops::Bound, sync::Arc};
 use url::Url;
 
 use crate::{
-    catalog::{
-        self, column::TypedStatistics, manifest::Manifest, snapshot::ManifestItem, ManifestFile,
-    },
+    catalog::{self, manifest::Manifest, pruning, snapshot::ManifestItem, ManifestFile},
     event::{self, DEFAULT_TIMESTAMP_KEY},
     localcache::LocalCacheManager,
-    metadata::STREAM_INFO,
+    metadata::{resolve_stream_alias, LOCK_EXPECT, STREAM_ALIASES, STREAM_INFO},
     metrics::QUERY_CACHE_HIT,
     option::CONFIG,
-    storage::ObjectStorage,
+    storage::{etag_cache, key_naming, sort_order::SortColumn, tiering, ObjectStorage},
+    utils::arrow::merge_schemas,
 };
 
+use super::as_of;
 use super::listing_table_builder::ListingTableBuilder;
+use super::partition_columns;
+use super::partition_format::{self, PartitionFormat};
 use crate::catalog::Snapshot as CatalogSnapshot;
 
 // schema provider for stream based on global data
@@ -82,37 +83,458 @@ impl SchemaProvider for GlobalSchemaProvider {
     }
 
     fn table_names(&self) -> Vec<String> {
-        STREAM_INFO.list_streams()
+        let mut names = STREAM_INFO.list_streams();
+        names.extend(STREAM_ALIASES.read().expect(LOCK_EXPECT).keys().cloned());
+        names
     }
 
     async fn table(&self, name: &str) -> DataFusionResult<Option<Arc<dyn TableProvider>>> {
-        if self.table_exist(name) {
-            Ok(Some(Arc::new(StandardTableProvider {
-                schema: STREAM_INFO.schema(name).unwrap(),
-                stream: name.to_owned(),
-                url: self.storage.store_url(),
-            })))
-        } else {
-            Ok(None)
-        }
+        let Some(mut table) = standard_table(self.storage.as_ref(), name) else {
+            return Ok(None);
+        };
+        table.schema =
+            widen_to_schema_history(self.storage.as_ref(), &table.stream, table.schema).await;
+        Ok(Some(Arc::new(table)))
     }
 
     fn table_exist(&self, name: &str) -> bool {
-        STREAM_INFO.stream_exists(name)
+        STREAM_INFO.stream_exists(&resolve_stream_alias(name))
+    }
+}
+
+/// Table provider of the stream `name` resolves to, none if the stream doesn't exist.
+/// The schema is the one at the time of the call, data is listed on every scan.
+/// Columns not permitted by the column access of the stream are left out.
+pub fn stream_table(storage: &dyn ObjectStorage, name: &str) -> Option<Arc<dyn TableProvider>> {
+    standard_table(storage, name).map(|table| Arc::new(table) as Arc<dyn TableProvider>)
+}
+
+fn standard_table(storage: &dyn ObjectStorage, name: &str) -> Option<StandardTableProvider> {
+    let stream = resolve_stream_alias(name);
+    let mut schema = STREAM_INFO.schema(&stream).ok()?;
+    if let Some(column_access) = STREAM_INFO.get_column_access(&stream).ok()? {
+        schema = Arc::new(column_access.project(&schema));
+    }
+    let granularity = STREAM_INFO.get_partition_granularity(&stream).ok()?;
+    Some(StandardTableProvider {
+        partition_fields: partition_columns::partition_fields(&schema, granularity),
+        schema,
+        url: storage.stream_store_url(&stream),
+        stream,
+    })
+}
+
+/// Files uploaded under an older schema may have a column with a type the stream schema
+/// no longer has. The types of the columns of `schema` are widened over the schema history
+/// of the stream so those files are read coerced, other columns are left as they are.
+async fn widen_to_schema_history(
+    storage: &dyn ObjectStorage,
+    stream: &str,
+    schema: SchemaRef,
+) -> SchemaRef {
+    let history = match storage.get_schema_history(stream).await {
+        Ok(history) if !history.is_empty() => history,
+        Ok(_) => return schema,
+        Err(err) => {
+            log::debug!("schema history of stream {stream} not read: {err}");
+            return schema;
+        }
+    };
+    let merged = merge_schemas(std::iter::once(schema.as_ref().clone()).chain(history));
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| match merged.field_with_name(field.name()) {
+            Ok(merged) => Arc::new(merged.clone()),
+            Err(_) => field.clone(),
+        })
+        .collect_vec();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+/// Rows of the stream `name` matching `filters`, summed from the row counts of its
+/// manifests without opening any data file. None if the rows can't all be counted
+/// from the catalog, the query is then planned as usual.
+pub async fn count_from_catalog(
+    state: &SessionState,
+    storage: &dyn ObjectStorage,
+    name: &str,
+    filters: &[Expr],
+) -> Result<Option<u64>, DataFusionError> {
+    let Some(table) = standard_table(storage, name) else {
+        return Ok(None);
+    };
+    let object_store_format = storage
+        .get_object_store_format(&table.stream)
+        .await
+        .map_err(|err| DataFusionError::Plan(err.to_string()))?;
+    let time_partition = object_store_format.time_partition;
+    let time_filters = extract_primary_filter(filters, time_partition.clone());
+    // staged events are only counted by reading them
+    if time_filters.is_empty()
+        || include_now(filters, time_partition.clone())
+        || as_of::cutoff().is_some()
+    {
+        return Ok(None);
+    }
+
+    let snapshot = merged_snapshot(storage, &table.stream, object_store_format.snapshot).await;
+    if is_overlapping_query(&snapshot.manifest_list, &time_filters) {
+        return Ok(None);
+    }
+    let time_column = time_partition.as_deref().unwrap_or(DEFAULT_TIMESTAMP_KEY);
+    let (snapshot, cold_snapshot) = tiering::split(snapshot);
+
+    let object_store = state
+        .runtime_env()
+        .object_store_registry
+        .get_store(&table.url)?;
+    let Some(hot_rows) = catalog_row_count(
+        &snapshot,
+        &time_filters,
+        object_store,
+        filters,
+        time_column,
+        &table.partition_fields,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    if cold_snapshot.manifest_list.is_empty() {
+        return Ok(Some(hot_rows));
     }
+    let Some(cold_store) = tiering::cold_store() else {
+        return Ok(None);
+    };
+    let object_store = state
+        .runtime_env()
+        .object_store_registry
+        .get_store(&cold_store.store_url())?;
+    let cold_rows = catalog_row_count(
+        &cold_snapshot,
+        &time_filters,
+        object_store,
+        filters,
+        time_column,
+        &table.partition_fields,
+    )
+    .await?;
+    Ok(cold_rows.map(|cold_rows| hot_rows + cold_rows))
+}
+
+/// Sums the row counts of the files matching `filters`, none unless every file either
+/// matches them as a whole or not at all. Only filters on the time column and partition
+/// columns can match whole files.
+async fn catalog_row_count(
+    snapshot: &Snapshot,
+    time_filters: &[PartialTimeFilter],
+    object_store: Arc<dyn ObjectStore>,
+    filters: &[Expr],
+    time_column: &str,
+    partition_fields: &[Field],
+) -> Result<Option<u64>, DataFusionError> {
+    let filters = filters
+        .iter()
+        .flat_map(split_conjunction)
+        .cloned()
+        .collect_vec();
+    let answerable = filters.iter().all(|filter| {
+        filter.to_columns().is_ok_and(|columns| {
+            columns.iter().all(|column| {
+                column.name == time_column
+                    || partition_fields
+                        .iter()
+                        .any(|field| field.name() == &column.name)
+            })
+        })
+    });
+    if !answerable {
+        return Ok(None);
+    }
+
+    let files = collect_from_snapshot(
+        snapshot,
+        time_filters,
+        object_store,
+        &filters,
+        None,
+        partition_fields,
+    )
+    .await?;
+    let mut count = 0;
+    for file in files {
+        let values = partition_columns::partition_values(&file.file_path, partition_fields);
+        // every row matches a filter if no row matches its negation
+        let all_match = filters.iter().all(|filter| {
+            let Some(negated) = negated(filter) else {
+                return false;
+            };
+            if partition_columns::references_partition(filter, partition_fields) {
+                // a null partition matches neither the filter nor its negation
+                values.iter().all(Option::is_some)
+                    && partition_columns::can_be_pruned(&negated, partition_fields, &values)
+            } else {
+                let no_nulls = file
+                    .columns
+                    .iter()
+                    .any(|column| column.name == time_column && column.null_count == 0);
+                no_nulls && file.can_be_pruned(&negated)
+            }
+        });
+        if !all_match {
+            return Ok(None);
+        }
+        count += file.num_rows;
+    }
+    Ok(Some(count))
+}
+
+/// Negation of a comparison or an IN list, rows matching neither are null
+fn negated(filter: &Expr) -> Option<Expr> {
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => Some(Expr::BinaryExpr(
+            BinaryExpr::new(left.clone(), op.negate()?, right.clone()),
+        )),
+        Expr::InList(in_list) => Some(Expr::InList(InList {
+            negated: !in_list.negated,
+            ..in_list.clone()
+        })),
+        _ => None,
+    }
+}
+
+/// Snapshot of a stream, merged from the snapshots of all ingestors in query mode
+async fn merged_snapshot(
+    storage: &dyn ObjectStorage,
+    stream: &str,
+    snapshot: Snapshot,
+) -> Snapshot {
+    if CONFIG.parseable.mode != Mode::Query {
+        return snapshot;
+    }
+    let mut merged_snapshot = Snapshot::default();
+    let path = RelativePathBuf::from_iter([stream, STREAM_ROOT_DIRECTORY]);
+    let obs = storage
+        .get_objects(
+            Some(&path),
+            Box::new(|file_name| file_name.ends_with("stream.json")),
+        )
+        .await;
+    if let Ok(obs) = obs {
+        for ob in obs {
+            if let Ok(object_store_format) = serde_json::from_slice::<ObjectStoreFormat>(&ob) {
+                let snapshot = object_store_format.snapshot;
+                for manifest in snapshot.manifest_list {
+                    merged_snapshot.manifest_list.push(manifest);
+                }
+            }
+        }
+    }
+    merged_snapshot
 }
 
 #[derive(Debug)]
 struct StandardTableProvider {
     schema: SchemaRef,
+    // columns derived from object keys, appended after the stream schema
+    partition_fields: Vec<Field>,
     // prefix under which to find snapshot
     stream: String,
     // url to find right instance of object store
     url: Url,
 }
 
+impl StandardTableProvider {
+    fn table_schema(&self) -> SchemaRef {
+        table_schema(&self.schema, &self.partition_fields)
+    }
+
+    /// Plan reading the files of the partitions in the cold tier from the cold storage
+    #[allow(clippy::too_many_arguments)]
+    async fn cold_exec(
+        &self,
+        state: &SessionState,
+        snapshot: Snapshot,
+        time_filters: &[PartialTimeFilter],
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+        time_partition: Option<String>,
+        sort_order: &[SortColumn],
+    ) -> Result<Option<Arc<dyn ExecutionPlan>>, DataFusionError> {
+        if snapshot.manifest_list.is_empty() {
+            return Ok(None);
+        }
+        let Some(url) = tiering::cold_store().map(|store| store.store_url()) else {
+            return Err(DataFusionError::Plan(format!(
+                "partitions of {} are in the cold tier but no cold storage is set",
+                self.stream
+            )));
+        };
+        let (snapshot, archived) = partition_format::split(snapshot);
+        let mut plans = Vec::new();
+        for (format, snapshot) in
+            std::iter::once((PartitionFormat::Parquet, snapshot)).chain(archived)
+        {
+            plans.push(
+                self.files_exec(
+                    state,
+                    &url,
+                    format,
+                    &snapshot,
+                    time_filters,
+                    projection,
+                    filters,
+                    limit,
+                    time_partition.clone(),
+                    sort_order,
+                )
+                .await?,
+            );
+        }
+        Ok(union_plan(plans))
+    }
+
+    /// Plan reading the files of the partitions in other formats than parquet from `url`
+    #[allow(clippy::too_many_arguments)]
+    async fn archived_exec(
+        &self,
+        state: &SessionState,
+        url: &Url,
+        archived: Vec<(PartitionFormat, Snapshot)>,
+        time_filters: &[PartialTimeFilter],
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+        time_partition: Option<String>,
+        sort_order: &[SortColumn],
+    ) -> Result<Option<Arc<dyn ExecutionPlan>>, DataFusionError> {
+        let mut plans = Vec::new();
+        for (format, snapshot) in archived {
+            plans.push(
+                self.files_exec(
+                    state,
+                    url,
+                    format,
+                    &snapshot,
+                    time_filters,
+                    projection,
+                    filters,
+                    limit,
+                    time_partition.clone(),
+                    sort_order,
+                )
+                .await?,
+            );
+        }
+        Ok(union_plan(plans))
+    }
+
+    /// Plan reading the files of the partitions of `snapshot`, all in `format`, from `url`
+    #[allow(clippy::too_many_arguments)]
+    async fn files_exec(
+        &self,
+        state: &SessionState,
+        url: &Url,
+        format: PartitionFormat,
+        snapshot: &Snapshot,
+        time_filters: &[PartialTimeFilter],
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+        time_partition: Option<String>,
+        sort_order: &[SortColumn],
+    ) -> Result<Option<Arc<dyn ExecutionPlan>>, DataFusionError> {
+        if snapshot.manifest_list.is_empty() {
+            return Ok(None);
+        }
+        let object_store = state.runtime_env().object_store_registry.get_store(url)?;
+        let mut manifest_files = collect_from_snapshot(
+            snapshot,
+            time_filters,
+            object_store.clone(),
+            filters,
+            limit,
+            &self.partition_fields,
+        )
+        .await?;
+        if let Some(as_of) = as_of::cutoff() {
+            manifest_files = as_of::retain_uploaded(
+                object_store.as_ref(),
+                manifest_files,
+                as_of,
+                CONFIG.parseable.query_listing_concurrency,
+            )
+            .await
+            .map_err(|err| DataFusionError::External(Box::new(err)))?;
+        }
+        if manifest_files.is_empty() {
+            return Ok(None);
+        }
+
+        let (partitioned_files, statistics) =
+            partitioned_files(manifest_files, &self.schema, &self.partition_fields, 1);
+        let plan = create_physical_plan(
+            format,
+            ObjectStoreUrl::parse(url).unwrap(),
+            partitioned_files,
+            statistics,
+            self.schema.clone(),
+            projection,
+            filters,
+            limit,
+            state,
+            time_partition,
+            sort_order,
+            self.partition_fields.clone(),
+        )
+        .await?;
+        Ok(Some(plan))
+    }
+
+    /// Adds the partition columns of the current hour to batches still in staging
+    fn with_staging_partitions(
+        &self,
+        records: Vec<RecordBatch>,
+    ) -> Result<Vec<RecordBatch>, DataFusionError> {
+        if self.partition_fields.is_empty() {
+            return Ok(records);
+        }
+        let schema = self.table_schema();
+        // staged data is named after the current time in the partition zone of the stream
+        let zone = STREAM_INFO
+            .get_partition_time_zone(&self.stream)
+            .map_err(|err| DataFusionError::Execution(err.to_string()))?;
+        let now = key_naming::partition_time(Utc::now().naive_utc(), zone);
+        let values = partition_columns::partition_values_at(now, &self.partition_fields);
+        records
+            .iter()
+            .map(|batch| {
+                partition_columns::with_partition_columns(batch, schema.clone(), &values)
+                    .map_err(DataFusionError::from)
+            })
+            .collect()
+    }
+}
+
+/// Stream schema followed by the partition columns
+fn table_schema(schema: &SchemaRef, partition_fields: &[Field]) -> SchemaRef {
+    if partition_fields.is_empty() {
+        return schema.clone();
+    }
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .chain(partition_fields.iter().cloned())
+        .collect_vec();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
 #[allow(clippy::too_many_arguments)]
-async fn create_parquet_physical_plan(
+async fn create_physical_plan(
+    format: PartitionFormat,
     object_store_url: ObjectStoreUrl,
     partitions: Vec<Vec<PartitionedFile>>,
     statistics: Statistics,
@@ -122,8 +544,16 @@ async fn create_parquet_physical_plan(
     limit: Option<usize>,
     state: &SessionState,
     time_partition: Option<String>,
+    sort_order: &[SortColumn],
+    table_partition_cols: Vec<Field>,
 ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
-    let filters = if let Some(expr) = conjunction(filters.to_vec()) {
+    // partition columns are not stored in files, filters on them are applied through pruning
+    let filters = filters
+        .iter()
+        .filter(|filter| !partition_columns::references_partition(filter, &table_partition_cols))
+        .cloned()
+        .collect_vec();
+    let filters = if let Some(expr) = conjunction(filters) {
         let table_df_schema = schema.as_ref().clone().to_dfschema()?;
         let filters = create_physical_expr(&expr, &table_df_schema, state.execution_props())?;
         Some(filters)
@@ -131,37 +561,78 @@ async fn create_parquet_physical_plan(
         None
     };
 
-    let sort_expr = PhysicalSortExpr {
-        expr: if let Some(time_partition) = time_partition {
-            physical_plan::expressions::col(&time_partition, &schema)?
-        } else {
-            physical_plan::expressions::col(DEFAULT_TIMESTAMP_KEY, &schema)?
-        },
-        options: SortOptions {
-            descending: true,
-            nulls_first: true,
-        },
+    let output_ordering = if sort_order.is_empty() {
+        vec![PhysicalSortExpr {
+            expr: if let Some(time_partition) = time_partition {
+                physical_plan::expressions::col(&time_partition, &schema)?
+            } else {
+                physical_plan::expressions::col(DEFAULT_TIMESTAMP_KEY, &schema)?
+            },
+            options: SortOptions {
+                descending: true,
+                nulls_first: true,
+            },
+        }]
+    } else {
+        // files are sorted by the declared order of the stream,
+        // any prefix of it holds if a column is missing from the schema
+        sort_order
+            .iter()
+            .map_while(|sort_column| {
+                let expr = physical_plan::expressions::col(&sort_column.column, &schema).ok()?;
+                Some(PhysicalSortExpr {
+                    expr,
+                    options: sort_column.options(),
+                })
+            })
+            .collect()
+    };
+    let options = parquet_options(
+        CONFIG.parseable.query_pushdown_filters,
+        CONFIG.parseable.query_page_index,
+        !CONFIG.parseable.parquet_bloom_filter_columns.is_empty(),
+    );
+    let store = state.runtime_env().object_store(&object_store_url)?;
+
+    let config = FileScanConfig {
+        object_store_url,
+        file_schema: schema.clone(),
+        file_groups: partitions,
+        statistics,
+        projection: projection.cloned(),
+        limit,
+        output_ordering: vec![output_ordering],
+        table_partition_cols,
     };
-    let file_format = ParquetFormat::default().with_enable_pruning(true);
+    Ok(format.create_physical_plan(config, filters, options, store))
+}
 
-    // create the execution plan
-    let plan = file_format
-        .create_physical_plan(
-            state,
-            FileScanConfig {
-                object_store_url,
-                file_schema: schema.clone(),
-                file_groups: partitions,
-                statistics,
-                projection: projection.cloned(),
-                limit,
-                output_ordering: vec![vec![sort_expr]],
-                table_partition_cols: Vec::new(),
-            },
-            filters.as_ref(),
-        )
-        .await?;
-    Ok(plan)
+/// Parquet options used for scanning stream files. Row group pruning is always on,
+/// page level pruning, bloom filters and filter pushdown into the parquet decoder are optional.
+fn parquet_options(
+    pushdown_filters: bool,
+    page_index: bool,
+    bloom_filter: bool,
+) -> TableParquetOptions {
+    let mut options = TableParquetOptions::default();
+    options.global.pruning = true;
+    options.global.enable_page_index = page_index;
+    options.global.bloom_filter_enabled = bloom_filter;
+    options.global.pushdown_filters = pushdown_filters;
+    options.global.reorder_filters = pushdown_filters;
+    options
+}
+
+pub(crate) fn parquet_format(
+    pushdown_filters: bool,
+    page_index: bool,
+    bloom_filter: bool,
+) -> ParquetFormat {
+    ParquetFormat::default().with_options(parquet_options(
+        pushdown_filters,
+        page_index,
+        bloom_filter,
+    ))
 }
 
 async fn collect_from_snapshot(
@@ -170,6 +641,7 @@ async fn collect_from_snapshot(
     object_store: Arc<dyn ObjectStore>,
     filters: &[Expr],
     limit: Option<usize>,
+    partition_fields: &[Field],
 ) -> Result<Vec<catalog::manifest::File>, DataFusionError> {
     let items = snapshot.manifests(time_filters);
     let manifest_files = collect_manifest_files(
@@ -187,9 +659,21 @@ async fn collect_from_snapshot(
         .flat_map(|file| file.files)
         .rev()
         .collect();
-    for filter in filters {
+    let max_in_list = CONFIG.parseable.query_in_list_pruning_limit;
+    for filter in filters
+        .iter()
+        .filter(|filter| pruning::in_lists_within(filter, max_in_list))
+    {
         manifest_files.retain(|file| !file.can_be_pruned(filter))
     }
+    if !partition_fields.is_empty() {
+        manifest_files.retain(|file| {
+            let values = partition_columns::partition_values(&file.file_path, partition_fields);
+            !filters
+                .iter()
+                .any(|filter| partition_columns::can_be_pruned(filter, partition_fields, &values))
+        })
+    }
     if let Some(limit) = limit {
         let limit = limit as u64;
         let mut curr_limit = 0;
@@ -214,6 +698,7 @@ async fn collect_from_snapshot(
 fn partitioned_files(
     manifest_files: Vec<catalog::manifest::File>,
     table_schema: &Schema,
+    partition_fields: &[Field],
     target_partition: usize,
 ) -> (Vec<Vec<PartitionedFile>>, datafusion::common::Statistics) {
     let mut partitioned_files = Vec::from_iter((0..target_partition).map(|_| Vec::new()));
@@ -230,16 +715,28 @@ fn partitioned_files(
             columns,
             ..
         } = file;
-        partitioned_files[index].push(PartitionedFile::new(file_path, file.file_size));
+        let partition_values = partition_columns::to_scalars(partition_columns::partition_values(
+            &file_path,
+            partition_fields,
+        ));
+        let mut partitioned_file = PartitionedFile::new(file_path, file.file_size);
+        partitioned_file.partition_values = partition_values;
+        partitioned_files[index].push(partitioned_file);
         columns.into_iter().for_each(|col| {
+            // legacy manifests keep temporal bounds as Int, read them in the column's type
+            let col_stats = match table_schema.field_with_name(&col.name) {
+                Ok(field) => col.stats.map(|stats| stats.for_type(field.data_type())),
+                Err(_) => col.stats,
+            };
             column_statistics
                 .entry(col.name)
                 .and_modify(|x| {
-                    if let Some((stats, col_stats)) = x.as_ref().cloned().zip(col.stats.clone()) {
-                        *x = Some(stats.update(col_stats));
+                    if let Some((stats, col_stats)) = x.as_ref().cloned().zip(col_stats.clone()) {
+                        // a column can switch between String and Binary stats across files
+                        *x = stats.same_type(&col_stats).then(|| stats.update(col_stats));
                     }
                 })
-                .or_insert_with(|| col.stats.as_ref().cloned());
+                .or_insert_with(|| col_stats);
         });
         count += num_rows;
     }
@@ -277,7 +774,7 @@ impl TableProvider for StandardTableProvider {
     }
 
     fn schema(&self) -> SchemaRef {
-        self.schema.clone()
+        self.table_schema()
     }
 
     fn table_type(&self) -> TableType {
@@ -305,16 +802,19 @@ impl TableProvider for StandardTableProvider {
             .await
             .map_err(|err| DataFusionError::Plan(err.to_string()))?;
         let time_partition = object_store_format.time_partition;
+        let sort_order = object_store_format.sort_order;
         let time_filters = extract_primary_filter(filters, time_partition.clone());
         if time_filters.is_empty() {
             return Err(DataFusionError::Plan("potentially unbounded query on time range. Table scanning requires atleast one time bound".to_string()));
         }
 
-        if include_now(filters, time_partition.clone()) {
+        // staged events are not uploaded yet, queries as of a point in time never read them
+        if include_now(filters, time_partition.clone()) && as_of::cutoff().is_none() {
             if let Some(records) =
                 event::STREAM_WRITERS.recordbatches_cloned(&self.stream, &self.schema)
             {
-                let reversed_mem_table = reversed_mem_table(records, self.schema.clone())?;
+                let records = self.with_staging_partitions(records)?;
+                let reversed_mem_table = reversed_mem_table(records, self.table_schema())?;
                 memory_exec = Some(
                     reversed_mem_table
                         .scan(state, projection, filters, limit)
@@ -322,30 +822,12 @@ impl TableProvider for StandardTableProvider {
                 );
             }
         };
-        let mut merged_snapshot: snapshot::Snapshot = Snapshot::default();
-        if CONFIG.parseable.mode == Mode::Query {
-            let path = RelativePathBuf::from_iter([&self.stream, STREAM_ROOT_DIRECTORY]);
-            let obs = glob_storage
-                .get_objects(
-                    Some(&path),
-                    Box::new(|file_name| file_name.ends_with("stream.json")),
-                )
-                .await;
-            if let Ok(obs) = obs {
-                for ob in obs {
-                    if let Ok(object_store_format) =
-                        serde_json::from_slice::<ObjectStoreFormat>(&ob)
-                    {
-                        let snapshot = object_store_format.snapshot;
-                        for manifest in snapshot.manifest_list {
-                            merged_snapshot.manifest_list.push(manifest);
-                        }
-                    }
-                }
-            }
-        } else {
-            merged_snapshot = object_store_format.snapshot;
-        }
+        let merged_snapshot = merged_snapshot(
+            glob_storage.as_ref(),
+            &self.stream,
+            object_store_format.snapshot,
+        )
+        .await;
 
         // Is query timerange is overlapping with older data.
         if is_overlapping_query(&merged_snapshot.manifest_list, &time_filters) {
@@ -356,30 +838,80 @@ impl TableProvider for StandardTableProvider {
                 object_store,
                 &time_filters,
                 self.schema.clone(),
+                &self.partition_fields,
                 state,
                 projection,
                 filters,
                 limit,
                 time_partition.clone(),
+                &sort_order,
             )
             .await;
         }
 
+        let (merged_snapshot, cold_snapshot) = tiering::split(merged_snapshot);
+        let cold_exec = self
+            .cold_exec(
+                state,
+                cold_snapshot,
+                &time_filters,
+                projection,
+                filters,
+                limit,
+                time_partition.clone(),
+                &sort_order,
+            )
+            .await?;
+        // partitions archived in other formats are never cached locally
+        let (merged_snapshot, archived) = partition_format::split(merged_snapshot);
+        let archived_exec = self
+            .archived_exec(
+                state,
+                &self.url,
+                archived,
+                &time_filters,
+                projection,
+                filters,
+                limit,
+                time_partition.clone(),
+                &sort_order,
+            )
+            .await?;
+
         let mut manifest_files = collect_from_snapshot(
             &merged_snapshot,
             &time_filters,
-            object_store,
+            object_store.clone(),
             filters,
             limit,
+            &self.partition_fields,
         )
         .await?;
 
+        if let Some(as_of) = as_of::cutoff() {
+            manifest_files = as_of::retain_uploaded(
+                object_store.as_ref(),
+                manifest_files,
+                as_of,
+                CONFIG.parseable.query_listing_concurrency,
+            )
+            .await
+            .map_err(|err| DataFusionError::External(Box::new(err)))?;
+        }
+
         if manifest_files.is_empty() {
-            return final_plan(vec![memory_exec], projection, self.schema.clone());
+            return final_plan(
+                vec![memory_exec, cold_exec, archived_exec],
+                projection,
+                self.table_schema(),
+            );
         }
 
         // Based on entries in the manifest files, find them in the cache and create a physical plan.
-        if let Some(cache_manager) = LocalCacheManager::global() {
+        // cold queries read every file from the object store
+        if let Some(cache_manager) =
+            LocalCacheManager::global().filter(|_| !etag_cache::is_cold_read())
+        {
             let (cached, remainder) = cache_manager
                 .partition_on_cached(&self.stream, manifest_files, |file| &file.file_path)
                 .await
@@ -399,8 +931,10 @@ impl TableProvider for StandardTableProvider {
                 })
                 .collect();
 
-            let (partitioned_files, statistics) = partitioned_files(cached, &self.schema, 1);
-            let plan = create_parquet_physical_plan(
+            let (partitioned_files, statistics) =
+                partitioned_files(cached, &self.schema, &self.partition_fields, 1);
+            let plan = create_physical_plan(
+                PartitionFormat::Parquet,
                 ObjectStoreUrl::parse("file:///").unwrap(),
                 partitioned_files,
                 statistics,
@@ -410,6 +944,8 @@ impl TableProvider for StandardTableProvider {
                 limit,
                 state,
                 time_partition.clone(),
+                &sort_order,
+                self.partition_fields.clone(),
             )
             .await?;
 
@@ -419,15 +955,17 @@ impl TableProvider for StandardTableProvider {
         if manifest_files.is_empty() {
             QUERY_CACHE_HIT.with_label_values(&[&self.stream]).inc();
             return final_plan(
-                vec![memory_exec, cache_exec],
+                vec![memory_exec, cache_exec, cold_exec, archived_exec],
                 projection,
-                self.schema.clone(),
+                self.table_schema(),
             );
         }
 
-        let (partitioned_files, statistics) = partitioned_files(manifest_files, &self.schema, 1);
-        let remote_exec = create_parquet_physical_plan(
-            ObjectStoreUrl::parse(&glob_storage.store_url()).unwrap(),
+        let (partitioned_files, statistics) =
+            partitioned_files(manifest_files, &self.schema, &self.partition_fields, 1);
+        let remote_exec = create_physical_plan(
+            PartitionFormat::Parquet,
+            ObjectStoreUrl::parse(glob_storage.stream_store_url(&self.stream)).unwrap(),
             partitioned_files,
             statistics,
             self.schema.clone(),
@@ -436,13 +974,21 @@ impl TableProvider for StandardTableProvider {
             limit,
             state,
             time_partition.clone(),
+            &sort_order,
+            self.partition_fields.clone(),
         )
         .await?;
 
         Ok(final_plan(
-            vec![memory_exec, cache_exec, Some(remote_exec)],
+            vec![
+                memory_exec,
+                cache_exec,
+                Some(remote_exec),
+                cold_exec,
+                archived_exec,
+            ],
             projection,
-            self.schema.clone(),
+            self.table_schema(),
         )?)
     }
 
@@ -469,31 +1015,71 @@ async fn legacy_listing_table(
     object_store: Arc<dyn ObjectStore>,
     time_filters: &[PartialTimeFilter],
     schema: Arc<Schema>,
+    partition_fields: &[Field],
     state: &SessionState,
     projection: Option<&Vec<usize>>,
     filters: &[Expr],
     limit: Option<usize>,
     time_partition: Option<String>,
+    sort_order: &[SortColumn],
 ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
-    let remote_table = ListingTableBuilder::new(stream)
+    let builder = ListingTableBuilder::new(stream)
         .populate_via_listing(glob_storage.clone(), object_store, time_filters)
-        .and_then(|builder| async {
-            let table = builder.build(
-                schema.clone(),
-                |x| glob_storage.query_prefixes(x),
-                time_partition,
-            )?;
-            let res = match table {
-                Some(table) => Some(table.scan(state, projection, filters, limit).await?),
-                _ => None,
-            };
-            Ok(res)
-        })
         .await?;
 
+    // a listing table can't derive partition values from individual files,
+    // so they are scanned directly when partition columns are exposed
+    if !partition_fields.is_empty() {
+        let files = builder.partitioned_files(partition_fields, filters);
+        let table_schema = table_schema(&schema, partition_fields);
+        let remote_exec = if files.is_empty() {
+            None
+        } else {
+            Some(
+                create_physical_plan(
+                    PartitionFormat::Parquet,
+                    ObjectStoreUrl::parse(glob_storage.stream_store_url(&stream)).unwrap(),
+                    vec![files],
+                    Statistics::new_unknown(&schema),
+                    schema,
+                    projection,
+                    filters,
+                    limit,
+                    state,
+                    time_partition,
+                    sort_order,
+                    partition_fields.to_vec(),
+                )
+                .await?,
+            )
+        };
+        return final_plan(vec![mem_exec, remote_exec], projection, table_schema);
+    }
+
+    let table = builder.build(
+        schema.clone(),
+        |x| glob_storage.query_prefixes(x),
+        time_partition,
+        sort_order,
+    )?;
+    let remote_table = match table {
+        Some(table) => Some(table.scan(state, projection, filters, limit).await?),
+        _ => None,
+    };
+
     final_plan(vec![mem_exec, remote_table], projection, schema)
 }
 
+/// Union of the plans, None if there are none
+fn union_plan(plans: Vec<Option<Arc<dyn ExecutionPlan>>>) -> Option<Arc<dyn ExecutionPlan>> {
+    let mut plans = plans.into_iter().flatten().collect_vec();
+    match plans.len() {
+        0 => None,
+        1 => plans.pop(),
+        _ => Some(Arc::new(UnionExec::new(plans))),
+    }
+}
+
 fn final_plan(
     execution_plans: Vec<Option<Arc<dyn ExecutionPlan>>>,
     projection: Option<&Vec<usize>>,
@@ -691,11 +1277,10 @@ async fn collect_manifest_files(
     let tasks = manifest_urls.into_iter().map(|path| {
         let path = Path::parse(path).unwrap();
         let storage = Arc::clone(&storage);
-        async move { storage.get(&path).await }
+        async move { etag_cache::get(storage.as_ref(), &path).await }
     });
 
     let resp = FuturesOrdered::from_iter(tasks)
-        .and_then(|res| res.bytes())
         .collect::<Vec<object_store::Result<Bytes>>>()
         .await;
 
@@ -727,120 +1312,50 @@ fn extract_primary_filter(
 }
 
 trait ManifestExt: ManifestFile {
-    fn find_matching_column(&self, partial_filter: &Expr) -> Option<&catalog::column::Column> {
-        let name = match partial_filter {
-            Expr::BinaryExpr(binary_expr) => {
-                let Expr::Column(col) = binary_expr.left.as_ref() else {
-                    return None;
-                };
-                &col.name
-            }
-            _ => {
-                return None;
-            }
-        };
-
-        self.columns().iter().find(|col| &col.name == name)
-    }
-
     fn can_be_pruned(&self, partial_filter: &Expr) -> bool {
-        fn extract_op_scalar(expr: &Expr) -> Option<(Operator, &ScalarValue)> {
-            let Expr::BinaryExpr(expr) = expr else {
-                return None;
-            };
-            let Expr::Literal(value) = &*expr.right else {
-                return None;
-            };
-            Some((expr.op, value))
-        }
-
-        let Some(col) = self.find_matching_column(partial_filter) else {
-            return false;
-        };
-
-        let Some((op, value)) = extract_op_scalar(partial_filter) else {
-            return false;
-        };
-
-        let Some(value) = cast_or_none(value) else {
-            return false;
-        };
-
-        let Some(stats) = &col.stats else {
-            return false;
-        };
-
-        !satisfy_constraints(value, op, stats).unwrap_or(true)
+        pruning::can_skip_file(partial_filter, self.columns())
     }
 }
 
 impl<T: ManifestFile> ManifestExt for T {}
 
-enum CastRes<'a> {
-    Bool(bool),
-    Int(i64),
-    Float(f64),
-    String(&'a str),
-}
-
-fn cast_or_none(scalar: &ScalarValue) -> Option<CastRes<'_>> {
-    match scalar {
-        ScalarValue::Null => None,
-        ScalarValue::Boolean(val) => val.map(CastRes::Bool),
-        ScalarValue::Float32(val) => val.map(|val| CastRes::Float(val as f64)),
-        ScalarValue::Float64(val) => val.map(CastRes::Float),
-        ScalarValue::Int8(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::Int16(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::Int32(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::Int64(val) => val.map(CastRes::Int),
-        ScalarValue::UInt8(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::UInt16(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::UInt32(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::UInt64(val) => val.map(|val| CastRes::Int(val as i64)),
-        ScalarValue::Utf8(val) => val.as_ref().map(|val| CastRes::String(val)),
-        ScalarValue::TimestampMillisecond(val, _) => val.map(CastRes::Int),
-        _ => None,
-    }
-}
-
-fn satisfy_constraints(value: CastRes, op: Operator, stats: &TypedStatistics) -> Option<bool> {
-    fn matches<T: std::cmp::PartialOrd>(value: T, min: T, max: T, op: Operator) -> Option<bool> {
-        let val = match op {
-            Operator::Eq | Operator::IsNotDistinctFrom => value >= min && value <= max,
-            Operator::NotEq => value < min && value > max,
-            Operator::Lt => value > min,
-            Operator::LtEq => value >= min,
-            Operator::Gt => value < max,
-            Operator::GtEq => value <= max,
-            _ => return None,
-        };
-        Some(val)
-    }
-
-    match (value, stats) {
-        (CastRes::Bool(val), TypedStatistics::Bool(stats)) => {
-            matches(val, stats.min, stats.max, op)
-        }
-        (CastRes::Int(val), TypedStatistics::Int(stats)) => matches(val, stats.min, stats.max, op),
-        (CastRes::Float(val), TypedStatistics::Float(stats)) => {
-            matches(val, stats.min, stats.max, op)
-        }
-        (CastRes::String(val), TypedStatistics::String(stats)) => {
-            matches(val, &stats.min, &stats.max, op)
-        }
-        _ => None,
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::ops::Add;
 
     use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
 
-    use crate::catalog::snapshot::ManifestItem;
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        common::ToDFSchema,
+        datasource::{
+            file_format::FileFormat, listing::PartitionedFile, physical_plan::FileScanConfig,
+        },
+        execution::{context::ExecutionProps, object_store::ObjectStoreUrl},
+        physical_expr::create_physical_expr,
+        physical_plan::{collect, ExecutionPlan, Statistics},
+        prelude::{col, lit, Expr, SessionContext},
+        scalar::ScalarValue,
+    };
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+
+    use crate::catalog::snapshot::{ManifestItem, Snapshot};
+    use crate::query::partition_format::PartitionFormat;
+    use crate::storage::tiering::Tier;
 
-    use super::{is_overlapping_query, PartialTimeFilter};
+    use crate::catalog::{
+        column::{Column, Int64Type, TypedStatistics, Utf8Type},
+        manifest::{File, Manifest},
+    };
+
+    use super::{
+        catalog_row_count, extract_primary_filter, is_overlapping_query, parquet_format,
+        ManifestExt, PartialTimeFilter,
+    };
 
     fn datetime_min(year: i32, month: u32, day: u32) -> DateTime<Utc> {
         NaiveDate::from_ymd_opt(year, month, day)
@@ -866,6 +1381,8 @@ mod tests {
                 events_ingested: 0,
                 ingestion_size: 0,
                 storage_size: 0,
+                tier: Tier::Hot,
+                format: PartitionFormat::Parquet,
             },
             ManifestItem {
                 manifest_path: "2".to_string(),
@@ -874,6 +1391,8 @@ mod tests {
                 events_ingested: 0,
                 ingestion_size: 0,
                 storage_size: 0,
+                tier: Tier::Hot,
+                format: PartitionFormat::Parquet,
             },
             ManifestItem {
                 manifest_path: "3".to_string(),
@@ -882,6 +1401,8 @@ mod tests {
                 events_ingested: 0,
                 ingestion_size: 0,
                 storage_size: 0,
+                tier: Tier::Hot,
+                format: PartitionFormat::Parquet,
             },
         ]
     }
@@ -923,4 +1444,230 @@ mod tests {
 
         assert!(!res)
     }
+
+    async fn bytes_scanned(path: &std::path::Path, page_index: bool) -> (usize, usize) {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let ctx = SessionContext::new();
+        let state = ctx.state();
+        let predicate = create_physical_expr(
+            &col("a").eq(lit(4242i64)),
+            &schema.clone().to_dfschema().unwrap(),
+            &ExecutionProps::new(),
+        )
+        .unwrap();
+        let file_size = std::fs::metadata(path).unwrap().len();
+
+        let plan = parquet_format(false, page_index, false)
+            .create_physical_plan(
+                &state,
+                FileScanConfig {
+                    object_store_url: ObjectStoreUrl::local_filesystem(),
+                    file_schema: schema.clone(),
+                    file_groups: vec![vec![PartitionedFile::new(
+                        path.to_str().unwrap().to_string(),
+                        file_size,
+                    )]],
+                    statistics: Statistics::new_unknown(&schema),
+                    projection: None,
+                    limit: None,
+                    output_ordering: vec![],
+                    table_partition_cols: vec![],
+                },
+                Some(&predicate),
+            )
+            .await
+            .unwrap();
+
+        let batches = collect(plan.clone(), state.task_ctx()).await.unwrap();
+        let rows = batches.iter().map(|batch| batch.num_rows()).sum();
+        let bytes = plan
+            .metrics()
+            .and_then(|metrics| metrics.sum_by_name("bytes_scanned"))
+            .map(|value| value.as_usize())
+            .unwrap();
+        (rows, bytes)
+    }
+
+    #[actix_web::test]
+    async fn page_index_reduces_bytes_scanned() {
+        let path = std::env::temp_dir().join(format!(
+            "parseable-page-index-{}.parquet",
+            ulid::Ulid::new()
+        ));
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..100_000))],
+        )
+        .unwrap();
+        let props = WriterProperties::builder()
+            .set_data_page_row_count_limit(1000)
+            .set_write_batch_size(1000)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), schema, Some(props))
+                .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let (rows_without_index, bytes_without_index) = bytes_scanned(&path, false).await;
+        let (rows_with_index, bytes_with_index) = bytes_scanned(&path, true).await;
+        std::fs::remove_file(&path).unwrap();
+
+        // without filter pushdown whole pages are emitted, the filter is applied afterwards
+        assert_eq!(rows_without_index, 100_000);
+        assert!(rows_with_index <= 1000);
+        assert!(bytes_with_index < bytes_without_index / 10);
+    }
+
+    #[actix_web::test]
+    async fn count_is_summed_from_manifests_without_reading_files() {
+        let start = datetime_min(2024, 1, 1);
+        let minute = |n: i64| (start + Duration::minutes(n)).timestamp_millis();
+        let file = |n: i64, num_rows| File {
+            file_path: format!("app/date=2024-01-01/hour=00/minute=0{n}/data.parquet"),
+            num_rows,
+            columns: vec![Column {
+                name: "p_timestamp".to_string(),
+                stats: Some(TypedStatistics::Int(Int64Type {
+                    min: minute(n),
+                    max: minute(n + 1) - 1,
+                })),
+                uncompressed_size: 0,
+                compressed_size: 0,
+                null_count: 0,
+            }],
+            ..File::default()
+        };
+        let manifest = Manifest {
+            files: vec![file(0, 10), file(1, 20), file(2, 5)],
+            ..Manifest::default()
+        };
+        // only the manifest is stored, reading any data file would fail
+        let store = Arc::new(InMemory::new());
+        let manifest_path = "app/date=2024-01-01/manifest.json";
+        store
+            .put(
+                &Path::from(manifest_path),
+                serde_json::to_vec(&manifest).unwrap().into(),
+            )
+            .await
+            .unwrap();
+        let snapshot = Snapshot {
+            manifest_list: vec![ManifestItem {
+                manifest_path: manifest_path.to_string(),
+                time_lower_bound: start,
+                time_upper_bound: datetime_max(2024, 1, 1),
+                events_ingested: 35,
+                ingestion_size: 0,
+                storage_size: 0,
+                tier: Tier::Hot,
+                format: PartitionFormat::Parquet,
+            }],
+            ..Snapshot::default()
+        };
+        let timestamp = |n| lit(ScalarValue::TimestampMillisecond(Some(minute(n)), None));
+        let count = |filters: Vec<Expr>| {
+            let snapshot = &snapshot;
+            let store = store.clone();
+            async move {
+                let time_filters = extract_primary_filter(&filters, None);
+                catalog_row_count(snapshot, &time_filters, store, &filters, "p_timestamp", &[])
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let whole_minutes = vec![
+            col("p_timestamp").gt_eq(timestamp(0)),
+            col("p_timestamp").lt(timestamp(2)),
+        ];
+        assert_eq!(count(whole_minutes).await, Some(30));
+        // the first file holds rows before and after the bound
+        let part_of_minute = vec![
+            col("p_timestamp").gt_eq(lit(ScalarValue::TimestampMillisecond(
+                Some(minute(0) + 30_000),
+                None,
+            ))),
+            col("p_timestamp").lt(timestamp(2)),
+        ];
+        assert_eq!(count(part_of_minute).await, None);
+        let other_column = vec![
+            col("p_timestamp").gt_eq(timestamp(0)),
+            col("status").eq(lit(500i64)),
+        ];
+        assert_eq!(count(other_column).await, None);
+    }
+
+    #[test]
+    fn array_has_is_pruned_with_list_element_stats() {
+        let file = File {
+            columns: vec![Column {
+                name: "tags".to_string(),
+                stats: Some(TypedStatistics::List(Box::new(TypedStatistics::String(
+                    Utf8Type {
+                        min: "b".to_string(),
+                        max: "d".to_string(),
+                        truncated: false,
+                    },
+                )))),
+                uncompressed_size: 0,
+                compressed_size: 0,
+                null_count: 0,
+            }],
+            ..File::default()
+        };
+        let array_has = datafusion::functions_array::expr_fn::array_has;
+
+        assert!(file.can_be_pruned(&array_has(col("tags"), lit("a"))));
+        assert!(!file.can_be_pruned(&array_has(col("tags"), lit("c"))));
+        // element stats are not used for comparisons on the list itself
+        assert!(!file.can_be_pruned(&col("tags").eq(lit("a"))));
+    }
+
+    fn string_file(min: &str, max: &str, truncated: bool) -> File {
+        File {
+            columns: vec![Column {
+                name: "host".to_string(),
+                stats: Some(TypedStatistics::String(Utf8Type {
+                    min: min.to_string(),
+                    max: max.to_string(),
+                    truncated,
+                })),
+                uncompressed_size: 0,
+                compressed_size: 0,
+                null_count: 0,
+            }],
+            ..File::default()
+        }
+    }
+
+    #[test]
+    fn like_prefix_is_pruned_with_string_stats() {
+        let file = string_file("b", "d", false);
+
+        assert!(file.can_be_pruned(&col("host").like(lit("a%"))));
+        assert!(file.can_be_pruned(&col("host").like(lit("e%"))));
+        assert!(!file.can_be_pruned(&col("host").like(lit("c%"))));
+        // values starting with "b" sort after "b" itself
+        assert!(!file.can_be_pruned(&col("host").like(lit("b_x%"))));
+        assert!(!file.can_be_pruned(&col("host").not_like(lit("a%"))));
+        assert!(!file.can_be_pruned(&col("host").like(lit("%a"))));
+    }
+
+    #[test]
+    fn truncated_max_never_prunes_matching_file() {
+        // the real max "server-b-0001" was truncated to "server-"
+        let file = string_file("server-a", "server-", true);
+        assert!(!file.can_be_pruned(&col("host").like(lit("server-b%"))));
+        assert!(!file.can_be_pruned(&col("host").eq(lit("server-b-0001"))));
+        assert!(!file.can_be_pruned(&col("host").gt(lit("server-b"))));
+        // the min is still a lower bound
+        assert!(file.can_be_pruned(&col("host").like(lit("api%"))));
+        assert!(file.can_be_pruned(&col("host").lt(lit("api"))));
+
+        // the same bounds without the flag would prune the file
+        let file = string_file("server-a", "server-", false);
+        assert!(file.can_be_pruned(&col("host").like(lit("server-b%"))));
+    }
 }
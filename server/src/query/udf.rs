@@ -0,0 +1,376 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Scalar functions available in every query, registered once in the query session.
+//!
+//! To add a function implement datafusion's `ScalarUDFImpl` for it and add it to the
+//! list returned by `udfs`, it is then callable by its name from SQL.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{ArrayRef, StringArray};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::cast::{as_int64_array, as_string_array};
+use datafusion::common::ScalarValue;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::logical_expr::{
+    ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, TypeSignature, Volatility,
+};
+use regex::Regex;
+
+use crate::option::CONFIG;
+
+/// Functions registered in the query session
+pub fn udfs() -> Vec<ScalarUDF> {
+    let country_ranges = CONFIG
+        .parseable
+        .geoip_ranges_path
+        .as_deref()
+        .map(load_country_ranges)
+        .unwrap_or_default();
+
+    vec![
+        ScalarUDF::new_from_impl(RegexpExtract::new()),
+        ScalarUDF::new_from_impl(IpToCountry::new(country_ranges)),
+    ]
+}
+
+/// Arguments as arrays, scalars are repeated to the length of the array arguments.
+/// Also returns whether every argument is a scalar.
+fn to_arrays(args: &[ColumnarValue]) -> Result<(Vec<ArrayRef>, bool)> {
+    let rows = args.iter().find_map(|arg| match arg {
+        ColumnarValue::Array(array) => Some(array.len()),
+        ColumnarValue::Scalar(_) => None,
+    });
+    let arrays = args
+        .iter()
+        .map(|arg| arg.clone().into_array(rows.unwrap_or(1)))
+        .collect::<Result<_>>()?;
+    Ok((arrays, rows.is_none()))
+}
+
+fn to_columnar(array: ArrayRef, scalar: bool) -> Result<ColumnarValue> {
+    if scalar {
+        ScalarValue::try_from_array(&array, 0).map(ColumnarValue::Scalar)
+    } else {
+        Ok(ColumnarValue::Array(array))
+    }
+}
+
+/// `regexp_extract(str, pattern [, group])`, the capture group `group` of the first
+/// match of `pattern` in `str`. Group 0 is the whole match and the first group is
+/// extracted by default. Null when the pattern or the group doesn't match.
+#[derive(Debug)]
+pub struct RegexpExtract {
+    signature: Signature,
+}
+
+impl RegexpExtract {
+    fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8]),
+                    TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8, DataType::Int64]),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl ScalarUDFImpl for RegexpExtract {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "regexp_extract"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let (arrays, scalar) = to_arrays(args)?;
+        let strings = as_string_array(&arrays[0])?;
+        let patterns = as_string_array(&arrays[1])?;
+        let groups = arrays
+            .get(2)
+            .map(|groups| as_int64_array(groups))
+            .transpose()?;
+
+        // patterns are usually literals, each distinct one is compiled once
+        let mut compiled: HashMap<&str, Regex> = HashMap::new();
+        let mut extracted = Vec::with_capacity(strings.len());
+        for row in 0..strings.len() {
+            if strings.is_null(row) || patterns.is_null(row) {
+                extracted.push(None);
+                continue;
+            }
+            let group = match groups {
+                Some(groups) if groups.is_null(row) => {
+                    extracted.push(None);
+                    continue;
+                }
+                Some(groups) => groups.value(row),
+                None => 1,
+            };
+            let pattern = patterns.value(row);
+            if !compiled.contains_key(pattern) {
+                let regex = Regex::new(pattern).map_err(|err| {
+                    DataFusionError::Execution(format!(
+                        "regexp_extract: invalid pattern {pattern}: {err}"
+                    ))
+                })?;
+                compiled.insert(pattern, regex);
+            }
+            extracted.push(
+                usize::try_from(group)
+                    .ok()
+                    .and_then(|group| compiled[pattern].captures(strings.value(row))?.get(group))
+                    .map(|matched| matched.as_str().to_owned()),
+            );
+        }
+
+        to_columnar(Arc::new(StringArray::from(extracted)), scalar)
+    }
+}
+
+/// Inclusive range of addresses, ipv4 addresses are mapped to ipv6
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountryRange {
+    start: u128,
+    end: u128,
+    country: String,
+}
+
+fn ip_to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// Ranges of a csv file of `start,end,country` lines, as in the freely available ip to
+/// country databases. Lines that are not ranges, like a header, are skipped.
+pub fn parse_country_ranges(csv: &str) -> Vec<CountryRange> {
+    let mut ranges: Vec<CountryRange> = csv
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',').map(|field| field.trim().trim_matches('"'));
+            let start: IpAddr = fields.next()?.parse().ok()?;
+            let end: IpAddr = fields.next()?.parse().ok()?;
+            let country = fields.next().filter(|country| !country.is_empty())?;
+            Some(CountryRange {
+                start: ip_to_u128(start),
+                end: ip_to_u128(end),
+                country: country.to_owned(),
+            })
+        })
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+    ranges
+}
+
+fn load_country_ranges(path: &Path) -> Vec<CountryRange> {
+    match std::fs::read_to_string(path) {
+        Ok(csv) => {
+            let ranges = parse_country_ranges(&csv);
+            log::info!(
+                "Loaded {} ip ranges for ip_to_country from {}",
+                ranges.len(),
+                path.display()
+            );
+            ranges
+        }
+        Err(err) => {
+            log::error!("Failed to read ip ranges from {}: {err}", path.display());
+            Vec::new()
+        }
+    }
+}
+
+/// `ip_to_country(ip)`, the country of an ipv4 or ipv6 address from the configured ranges.
+/// Null when the address isn't valid or isn't in any range.
+#[derive(Debug)]
+pub struct IpToCountry {
+    signature: Signature,
+    ranges: Vec<CountryRange>,
+}
+
+impl IpToCountry {
+    pub fn new(ranges: Vec<CountryRange>) -> Self {
+        Self {
+            signature: Signature::exact(vec![DataType::Utf8], Volatility::Immutable),
+            ranges,
+        }
+    }
+
+    fn lookup(&self, ip: &str) -> Option<&str> {
+        let ip = ip_to_u128(ip.trim().parse().ok()?);
+        let index = self.ranges.partition_point(|range| range.start <= ip);
+        let range = &self.ranges[index.checked_sub(1)?];
+        (ip <= range.end).then_some(range.country.as_str())
+    }
+}
+
+impl ScalarUDFImpl for IpToCountry {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "ip_to_country"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let (arrays, scalar) = to_arrays(args)?;
+        let countries: StringArray = as_string_array(&arrays[0])?
+            .iter()
+            .map(|ip| ip.and_then(|ip| self.lookup(ip)))
+            .collect();
+
+        to_columnar(Arc::new(countries), scalar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::StringArray;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::logical_expr::ScalarUDF;
+    use datafusion::prelude::SessionContext;
+
+    use super::{parse_country_ranges, IpToCountry, RegexpExtract};
+
+    fn context() -> SessionContext {
+        let ranges = parse_country_ranges(
+            "start,end,country\n\
+             10.0.0.0,10.255.255.255,ZZ\n\
+             1.0.0.0,1.0.0.255,AU\n\
+             \"2001:db8::\",\"2001:db8::ffff\",\"NL\"\n",
+        );
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::new_from_impl(RegexpExtract::new()));
+        ctx.register_udf(ScalarUDF::new_from_impl(IpToCountry::new(ranges)));
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("client_ip", DataType::Utf8, true),
+            Field::new("message", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("1.0.0.7"),
+                    Some("10.1.2.3"),
+                    Some("2001:db8::1"),
+                    Some("8.8.8.8"),
+                    Some("not an ip"),
+                    None,
+                ])),
+                Arc::new(StringArray::from(vec![
+                    Some("GET /a trace_id=4bf92f3577b34da6 status=200"),
+                    Some("GET /b trace_id=00f067aa0ba902b7 status=500"),
+                    Some("no trace"),
+                    None,
+                    Some("trace_id=a3ce929d0e0e4736"),
+                    Some("trace_id=TOOSHORT"),
+                ])),
+            ],
+        )
+        .unwrap();
+        ctx.register_batch("logs", batch).unwrap();
+        ctx
+    }
+
+    async fn rows(ctx: &SessionContext, sql: &str) -> Vec<String> {
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        pretty_format_batches(&batches)
+            .unwrap()
+            .to_string()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn registered_functions_are_callable_from_sql() {
+        let ctx = context();
+        assert_eq!(
+            rows(
+                &ctx,
+                "select ip_to_country(client_ip) as country, \
+                 regexp_extract(message, 'trace_id=([0-9a-f]{16})') as trace_id \
+                 from logs"
+            )
+            .await,
+            [
+                "+---------+------------------+",
+                "| country | trace_id         |",
+                "+---------+------------------+",
+                "| AU      | 4bf92f3577b34da6 |",
+                "| ZZ      | 00f067aa0ba902b7 |",
+                "| NL      |                  |",
+                "|         |                  |",
+                "|         | a3ce929d0e0e4736 |",
+                "|         |                  |",
+                "+---------+------------------+",
+            ]
+        );
+
+        assert_eq!(
+            rows(
+                &ctx,
+                "select regexp_extract('status=500 GET', '(\\w+)=(\\d+)', 2) as status, \
+                 regexp_extract('status=500 GET', '(\\w+)=(\\d+)', 0) as pair, \
+                 ip_to_country('1.0.0.255') as country"
+            )
+            .await,
+            [
+                "+--------+------------+---------+",
+                "| status | pair       | country |",
+                "+--------+------------+---------+",
+                "| 500    | status=500 | AU      |",
+                "+--------+------------+---------+",
+            ]
+        );
+    }
+}
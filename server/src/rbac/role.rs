@@ -28,10 +28,25 @@ pub enum Action {
     GetSchema,
     GetStats,
     DeleteStream,
+    RestoreStream,
     GetRetention,
     PutRetention,
     GetCacheEnabled,
     PutCacheEnabled,
+    GetSortOrder,
+    PutSortOrder,
+    GetColumnAccess,
+    PutColumnAccess,
+    GetStatisticsLevel,
+    PutStatisticsLevel,
+    GetSchemaMode,
+    PutSchemaMode,
+    GetPartitionGranularity,
+    PutPartitionGranularity,
+    GetRollup,
+    PutRollup,
+    GetRateLimit,
+    PutRateLimit,
     PutAlert,
     GetAlert,
     PutUser,
@@ -60,6 +75,13 @@ pub enum Action {
     DeleteFilter,
     ListCache,
     RemoveCache,
+    RepairCatalog,
+    ListPartitionFiles,
+    GetFileSizes,
+    ExportStream,
+    PutAlias,
+    DeleteAlias,
+    Flush,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -112,6 +134,7 @@ impl RoleBuilder {
                 | Action::ListRole
                 | Action::CreateStream
                 | Action::DeleteStream
+                | Action::RestoreStream
                 | Action::GetStream
                 | Action::ListStream
                 | Action::ListCluster
@@ -127,6 +150,13 @@ impl RoleBuilder {
                 | Action::DeleteFilter
                 | Action::ListCache
                 | Action::RemoveCache
+                | Action::RepairCatalog
+                | Action::ListPartitionFiles
+                | Action::GetFileSizes
+                | Action::ExportStream
+                | Action::PutAlias
+                | Action::DeleteAlias
+                | Action::Flush
                 | Action::GetAnalytics => Permission::Unit(action),
                 Action::Ingest
                 | Action::GetSchema
@@ -135,6 +165,20 @@ impl RoleBuilder {
                 | Action::PutRetention
                 | Action::GetCacheEnabled
                 | Action::PutCacheEnabled
+                | Action::GetSortOrder
+                | Action::PutSortOrder
+                | Action::GetColumnAccess
+                | Action::PutColumnAccess
+                | Action::GetStatisticsLevel
+                | Action::PutStatisticsLevel
+                | Action::GetSchemaMode
+                | Action::PutSchemaMode
+                | Action::GetPartitionGranularity
+                | Action::PutPartitionGranularity
+                | Action::GetRollup
+                | Action::PutRollup
+                | Action::GetRateLimit
+                | Action::PutRateLimit
                 | Action::PutAlert
                 | Action::GetAlert
                 | Action::All => Permission::Stream(action, self.stream.clone().unwrap()),
@@ -206,6 +250,20 @@ pub mod model {
                 Action::PutRetention,
                 Action::PutCacheEnabled,
                 Action::GetCacheEnabled,
+                Action::GetSortOrder,
+                Action::PutSortOrder,
+                Action::GetColumnAccess,
+                Action::PutColumnAccess,
+                Action::GetStatisticsLevel,
+                Action::PutStatisticsLevel,
+                Action::GetSchemaMode,
+                Action::PutSchemaMode,
+                Action::GetPartitionGranularity,
+                Action::PutPartitionGranularity,
+                Action::GetRollup,
+                Action::PutRollup,
+                Action::GetRateLimit,
+                Action::PutRateLimit,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
@@ -226,6 +284,13 @@ pub mod model {
                 Action::GetSchema,
                 Action::GetStats,
                 Action::GetRetention,
+                Action::GetSortOrder,
+                Action::GetColumnAccess,
+                Action::GetStatisticsLevel,
+                Action::GetSchemaMode,
+                Action::GetPartitionGranularity,
+                Action::GetRollup,
+                Action::GetRateLimit,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
@@ -245,6 +310,13 @@ pub mod model {
                 Action::GetSchema,
                 Action::GetStats,
                 Action::GetRetention,
+                Action::GetSortOrder,
+                Action::GetColumnAccess,
+                Action::GetStatisticsLevel,
+                Action::GetSchemaMode,
+                Action::GetPartitionGranularity,
+                Action::GetRollup,
+                Action::GetRateLimit,
                 Action::GetAlert,
                 Action::GetAbout,
                 Action::QueryLLM,
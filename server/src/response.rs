@@ -24,7 +24,11 @@ use crate::{
     },
 };
 use actix_web::{web, Responder};
+use arrow_ipc::writer::StreamWriter;
+use bytes::Bytes;
 use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::execution::SendableRecordBatchStream;
+use futures::{Stream, StreamExt};
 use itertools::Itertools;
 use serde_json::{json, Value};
 use tonic::{Response, Status};
@@ -69,3 +73,105 @@ impl QueryResponse {
         into_flight_data(self.records)
     }
 }
+
+/// Encodes a stream of record batches as an Arrow IPC stream.
+/// Schema is sent along with the first chunk and the end of stream marker with the last.
+pub fn into_arrow_ipc_stream(
+    stream: SendableRecordBatchStream,
+) -> Result<impl Stream<Item = Result<Bytes, QueryError>>, QueryError> {
+    let writer = StreamWriter::try_new(Vec::new(), &stream.schema())?;
+
+    let ipc_stream = futures::stream::unfold(Some((writer, stream)), |state| async move {
+        let (mut writer, mut stream) = state?;
+        match stream.next().await {
+            Some(Ok(batch)) => {
+                let chunk = writer
+                    .write(&batch)
+                    .map(|_| Bytes::from(std::mem::take(writer.get_mut())))
+                    .map_err(QueryError::from);
+                Some((chunk, Some((writer, stream))))
+            }
+            Some(Err(err)) => Some((Err(QueryError::from(err)), None)),
+            None => {
+                let chunk = writer
+                    .finish()
+                    .map(|_| Bytes::from(std::mem::take(writer.get_mut())))
+                    .map_err(QueryError::from);
+                Some((chunk, None))
+            }
+        }
+    });
+
+    Ok(ipc_stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_ipc::reader::StreamReader;
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{error::DataFusionError, physical_plan::stream::RecordBatchStreamAdapter};
+    use futures::{StreamExt, TryStreamExt};
+
+    use super::into_arrow_ipc_stream;
+
+    fn schema() -> Arc<Schema> {
+        Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]))
+    }
+
+    fn batch(values: Vec<i64>) -> Result<RecordBatch, DataFusionError> {
+        Ok(RecordBatch::try_new(
+            schema(),
+            vec![Arc::new(Int64Array::from(values))],
+        )?)
+    }
+
+    #[actix_web::test]
+    async fn empty_result_is_a_valid_stream_without_batches() {
+        let stream = RecordBatchStreamAdapter::new(
+            schema(),
+            futures::stream::empty::<Result<RecordBatch, DataFusionError>>(),
+        );
+
+        let chunks: Vec<_> = into_arrow_ipc_stream(Box::pin(stream))
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        let encoded = chunks.concat();
+        let reader = StreamReader::try_new(encoded.as_slice(), None).unwrap();
+        assert_eq!(reader.schema(), schema());
+        assert_eq!(reader.count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn failing_query_ends_the_stream_without_end_marker() {
+        let batches = [
+            batch(vec![1, 2]),
+            Err(DataFusionError::Execution(
+                "object store unreachable".to_string(),
+            )),
+            batch(vec![3]),
+        ];
+        let stream = RecordBatchStreamAdapter::new(schema(), futures::stream::iter(batches));
+
+        let chunks: Vec<_> = into_arrow_ipc_stream(Box::pin(stream))
+            .unwrap()
+            .collect()
+            .await;
+
+        // the batch before the error, then the error and nothing after it, the
+        // response body is cut off instead of ending with the end of stream marker
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1]
+            .as_ref()
+            .is_err_and(|err| err.to_string().contains("object store unreachable")));
+
+        let encoded = chunks[0].as_ref().unwrap().clone();
+        let mut reader = StreamReader::try_new(encoded.as_ref(), None).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().num_rows(), 2);
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Graceful shutdown. On SIGTERM or SIGINT the HTTP server stops taking requests and
+//! ingestion is rejected, then staged data is flushed and uploads in flight are given
+//! until the shutdown timeout to finish. Uploads still running after that are aborted,
+//! so that no partial multipart upload is left behind.
+
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use actix_web::dev::ServerHandle;
+use once_cell::sync::Lazy;
+use tokio::sync::{watch, Notify};
+
+use crate::{metadata::STREAM_INFO, option::CONFIG};
+
+/// Time aborted uploads are given to clean up before the process exits
+const ABORT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+pub static UPLOADS: Lazy<Uploads> = Lazy::new(Uploads::new);
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Stops `server` on the first SIGTERM or SIGINT, letting requests in flight complete
+pub async fn stop_on_signal(server: ServerHandle) {
+    wait_for_signal().await;
+    log::info!("Received shutdown signal, stopping the server");
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    server.stop(true).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler is installed");
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = actix_web::rt::signal::ctrl_c() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = actix_web::rt::signal::ctrl_c().await;
+}
+
+/// Flushes the staged data of every stream and waits for uploads in flight. Returns
+/// whether everything was uploaded within the shutdown timeout.
+pub async fn drain() -> bool {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    let flush = async {
+        let storage = CONFIG.storage().get_object_store();
+        if let Err(err) = storage
+            .sync_streams(&STREAM_INFO.list_streams(), true)
+            .await
+        {
+            log::error!("failed to flush staged data on shutdown. {:?}", err);
+        }
+    };
+    UPLOADS
+        .drain(flush, CONFIG.parseable.shutdown_timeout)
+        .await
+}
+
+/// Uploads to the object store in flight, across all runtimes of the process
+pub struct Uploads {
+    in_flight: AtomicUsize,
+    idle: Notify,
+    abort: watch::Sender<bool>,
+}
+
+/// Marks an upload as in flight until dropped
+pub struct UploadGuard<'a>(&'a Uploads);
+
+impl Drop for UploadGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Uploads {
+    fn new() -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+            abort: watch::channel(false).0,
+        }
+    }
+
+    pub fn start(&self) -> UploadGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        UploadGuard(self)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once uploads in flight have to be aborted
+    pub async fn aborted(&self) {
+        let mut abort = self.abort.subscribe();
+        let _ = abort.wait_for(|aborted| *aborted).await;
+    }
+
+    async fn wait_idle(&self) {
+        loop {
+            // registered before the check so a concurrent last upload can't be missed
+            let idle = self.idle.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Runs `flush` and waits for all uploads, aborting those still running after `timeout`
+    async fn drain<F>(&self, flush: F, timeout: Duration) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // spawned so that uploads of the flush see the abort rather than being dropped
+        let flush = tokio::spawn(flush);
+        let drained = tokio::time::timeout(timeout, async {
+            let _ = flush.await;
+            self.wait_idle().await;
+        })
+        .await
+        .is_ok();
+
+        if !drained {
+            log::warn!(
+                "{} uploads did not finish within the shutdown timeout, aborting them",
+                self.in_flight()
+            );
+            self.abort.send_replace(true);
+            let _ = tokio::time::timeout(ABORT_GRACE_PERIOD, self.wait_idle()).await;
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use once_cell::sync::Lazy;
+
+    use super::Uploads;
+
+    #[actix_web::test]
+    async fn pending_upload_completes_before_shutdown() {
+        static UPLOADS: Lazy<Uploads> = Lazy::new(Uploads::new);
+        let completed = Arc::new(AtomicBool::new(false));
+
+        // an upload started before shutdown, outside of the flush
+        let upload = UPLOADS.start();
+        let upload_completed = completed.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            upload_completed.store(true, Ordering::SeqCst);
+            drop(upload);
+        });
+
+        assert!(UPLOADS.drain(async {}, Duration::from_secs(5)).await);
+        assert!(completed.load(Ordering::SeqCst));
+        assert_eq!(UPLOADS.in_flight(), 0);
+    }
+
+    #[actix_web::test]
+    async fn uploads_past_timeout_are_aborted() {
+        static UPLOADS: Lazy<Uploads> = Lazy::new(Uploads::new);
+        let aborted = Arc::new(AtomicBool::new(false));
+
+        let upload_aborted = aborted.clone();
+        let flush = async move {
+            let _upload = UPLOADS.start();
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(60)) => {},
+                _ = UPLOADS.aborted() => upload_aborted.store(true, Ordering::SeqCst),
+            }
+        };
+
+        assert!(!UPLOADS.drain(flush, Duration::from_millis(50)).await);
+        assert!(aborted.load(Ordering::SeqCst));
+        assert_eq!(UPLOADS.in_flight(), 0);
+    }
+}
@@ -24,18 +24,55 @@ use chrono::Local;
 
 use std::fmt::Debug;
 
+pub mod column_access;
+mod consistency_wait;
+mod credential_chain;
+mod credentials;
+pub mod diagnostic;
+pub(crate) mod etag_cache;
+pub mod external_files;
+#[cfg(test)]
+pub(crate) mod faulty_store;
+pub mod file_sizes;
+pub mod key_naming;
 mod localfs;
+pub mod lock;
+pub mod metadata_store;
 mod metrics_layer;
+pub mod object_key;
 pub(crate) mod object_storage;
+pub mod overwrite;
+pub mod partition_granularity;
+pub mod provenance;
+mod request_limit;
 pub mod retention;
+pub mod rollup;
+pub mod routing;
 mod s3;
+pub mod schema_cache;
+pub mod schema_mode;
+pub mod sort_order;
+mod sqs;
 pub mod staging;
+pub mod statistics_level;
 mod store_metadata;
+mod throttle_retry;
+pub mod tiering;
+mod timeout_layer;
+pub mod trash;
+pub mod upload_backlog;
 
+use self::column_access::ColumnAccess;
+use self::partition_granularity::PartitionGranularity;
 use self::retention::Retention;
+use self::rollup::Rollup;
+use self::schema_mode::SchemaMode;
+use self::sort_order::SortColumn;
 pub use self::staging::StorageDir;
+use self::statistics_level::StatisticsLevel;
+use crate::event::rate_limit::RateLimit;
 pub use localfs::FSConfig;
-pub use object_storage::{ObjectStorage, ObjectStorageProvider};
+pub use object_storage::{ObjectMetaInfo, ObjectStorage, ObjectStorageProvider};
 pub use s3::S3Config;
 pub use store_metadata::{
     put_remote_metadata, put_staging_metadata, resolve_parseable_metadata, StorageMetadata,
@@ -46,7 +83,13 @@ pub const STREAM_METADATA_FILE_NAME: &str = ".stream.json";
 pub const PARSEABLE_METADATA_FILE_NAME: &str = ".parseable.json";
 pub const STREAM_ROOT_DIRECTORY: &str = ".stream";
 pub const PARSEABLE_ROOT_DIRECTORY: &str = ".parseable";
+// corrupt parquet files moved out of their stream, under their original key
+pub const QUARANTINE_ROOT_DIRECTORY: &str = "quarantine";
+// objects of deleted streams and partitions in soft delete mode
+pub const TRASH_ROOT_DIRECTORY: &str = ".trash";
 pub const SCHEMA_FILE_NAME: &str = ".schema";
+// every schema a stream had, one object per distinct schema under the stream root directory
+pub const SCHEMA_HISTORY_DIRECTORY: &str = "schema_history";
 pub const ALERT_FILE_NAME: &str = ".alert.json";
 pub const MANIFEST_FILE: &str = "manifest.json";
 
@@ -58,7 +101,7 @@ pub const LOCAL_SYNC_INTERVAL: u64 = 60;
 /// used for storage. Defaults to 1 min.
 pub const OBJECT_STORE_DATA_GRANULARITY: u32 = (LOCAL_SYNC_INTERVAL as u32) / 60;
 
-// max concurrent request allowed for datafusion object store
+// default max concurrent reads and writes each allowed for the object store
 const MAX_OBJECT_STORE_REQUESTS: usize = 1000;
 
 // all the supported permissions
@@ -100,6 +143,31 @@ pub struct ObjectStoreFormat {
     pub custom_partition: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub static_schema_flag: Option<String>,
+    /// Time zone of the date and hour partitions of data files, UTC when unset.
+    /// Manifests stay grouped by UTC date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_time_zone: Option<String>,
+    /// Order of the rows in parquet files, by the time partition when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sort_order: Vec<SortColumn>,
+    /// Columns that queries can read, all columns when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column_access: Option<ColumnAccess>,
+    /// Column statistics written to parquet files
+    #[serde(default, skip_serializing_if = "StatisticsLevel::is_default")]
+    pub statistics_level: StatisticsLevel,
+    /// Whether events may add fields to the schema or must match it
+    #[serde(default, skip_serializing_if = "SchemaMode::is_default")]
+    pub schema_mode: SchemaMode,
+    /// Finest time partition in the keys of data files
+    #[serde(default, skip_serializing_if = "PartitionGranularity::is_default")]
+    pub partition_granularity: PartitionGranularity,
+    /// Per minute aggregations materialized into a rollup stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollup: Option<Rollup>,
+    /// Records and bytes per second ingested, the server default applies when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -119,6 +187,22 @@ pub struct StreamInfo {
     pub custom_partition: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub static_schema_flag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_time_zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sort_order: Vec<SortColumn>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column_access: Option<ColumnAccess>,
+    #[serde(default, skip_serializing_if = "StatisticsLevel::is_default")]
+    pub statistics_level: StatisticsLevel,
+    #[serde(default, skip_serializing_if = "SchemaMode::is_default")]
+    pub schema_mode: SchemaMode,
+    #[serde(default, skip_serializing_if = "PartitionGranularity::is_default")]
+    pub partition_granularity: PartitionGranularity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollup: Option<Rollup>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -167,6 +251,14 @@ impl Default for ObjectStoreFormat {
             time_partition_limit: None,
             custom_partition: None,
             static_schema_flag: None,
+            partition_time_zone: None,
+            sort_order: Vec::new(),
+            column_access: None,
+            statistics_level: StatisticsLevel::default(),
+            schema_mode: SchemaMode::default(),
+            partition_granularity: PartitionGranularity::default(),
+            rollup: None,
+            rate_limit: None,
         }
     }
 }
@@ -211,9 +303,31 @@ pub enum ObjectStorageError {
     UnhandledError(Box<dyn std::error::Error + Send + Sync + 'static>),
     #[error("Error: {0}")]
     PathError(relative_path::FromPathError),
+    #[error("Invalid Key: {0}")]
+    InvalidKey(#[from] object_key::InvalidKey),
     #[error("Error: {0}")]
     MetadataError(#[from] MetadataError),
 
+    // conditional put found an existing object
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+
+    // configured bucket does not exist in the object store
+    #[error("Bucket '{0}' does not exist, create it or correct the configured bucket name")]
+    BucketNotFound(String),
+
+    // object exceeds the configured single object read size
+    #[error("{path} is {size} bytes, larger than the read limit of {limit} bytes")]
+    ObjectTooLarge {
+        path: String,
+        size: usize,
+        limit: usize,
+    },
+
+    // object store call didn't complete within the operation timeout
+    #[error("Timeout: {0}")]
+    Timeout(String),
+
     #[allow(dead_code)]
     #[error("Authentication Error: {0}")]
     AuthenticationError(Box<dyn std::error::Error + Send + Sync + 'static>),
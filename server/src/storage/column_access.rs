@@ -0,0 +1,116 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Columns of a stream that queries can read. Columns not permitted are left out of the
+//! table of the stream, `SELECT *` doesn't return them and they are never read from
+//! parquet files. Ingestion and the schema of the stream are not affected.
+
+use arrow_schema::Schema;
+
+use crate::event::DEFAULT_TIMESTAMP_KEY;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnAccess {
+    /// Only these columns can be queried, columns added later are not
+    Allow(Vec<String>),
+    /// All columns but these can be queried
+    Deny(Vec<String>),
+}
+
+impl ColumnAccess {
+    pub fn permits(&self, column: &str) -> bool {
+        // every query is bounded on the timestamp
+        if column == DEFAULT_TIMESTAMP_KEY {
+            return true;
+        }
+        match self {
+            ColumnAccess::Allow(columns) => columns.iter().any(|allowed| allowed == column),
+            ColumnAccess::Deny(columns) => !columns.iter().any(|denied| denied == column),
+        }
+    }
+
+    /// Schema with the fields that can be queried
+    pub fn project(&self, schema: &Schema) -> Schema {
+        let fields: Vec<_> = schema
+            .fields()
+            .iter()
+            .filter(|field| self.permits(field.name()))
+            .cloned()
+            .collect();
+        Schema::new_with_metadata(fields, schema.metadata().clone())
+    }
+
+    /// Queries of a time partitioned stream are bounded on the time partition column
+    pub fn validate(&self, time_partition: Option<&str>) -> Result<(), String> {
+        match time_partition {
+            Some(column) if !self.permits(column) => {
+                Err(format!("time partition column {column} must be permitted"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::ColumnAccess;
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("p_timestamp", DataType::Int64, false),
+            Field::new("message", DataType::Utf8, true),
+            Field::new("ssn", DataType::Utf8, true),
+            Field::new("event_time", DataType::Utf8, true),
+        ])
+    }
+
+    fn names(schema: &Schema) -> Vec<&str> {
+        schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect()
+    }
+
+    #[test]
+    fn only_permitted_columns_are_in_the_table() {
+        let deny: ColumnAccess = serde_json::from_str(r#"{"deny": ["ssn"]}"#).unwrap();
+        assert_eq!(
+            names(&deny.project(&schema())),
+            ["p_timestamp", "message", "event_time"]
+        );
+
+        let allow = ColumnAccess::Allow(vec!["message".to_string()]);
+        assert_eq!(names(&allow.project(&schema())), ["p_timestamp", "message"]);
+        assert!(!allow.permits("ssn"));
+        assert!(allow.permits("p_timestamp"));
+    }
+
+    #[test]
+    fn time_partition_must_be_permitted() {
+        let deny = ColumnAccess::Deny(vec!["event_time".to_string()]);
+        assert!(deny.validate(None).is_ok());
+        assert!(deny.validate(Some("event_time")).is_err());
+
+        let allow = ColumnAccess::Allow(vec!["event_time".to_string()]);
+        assert!(allow.validate(Some("event_time")).is_ok());
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Some S3 compatible stores are eventually consistent: an object just written may not be
+//! found by a head or a listing yet, so compaction and flush markers written right after
+//! would miss it. When enabled a write is only complete once a head finds the object,
+//! polled with an exponential backoff a bounded number of times. AWS S3 is strongly
+//! consistent and never waits.
+
+use std::time::Duration;
+
+use object_store::{path::Path, ObjectStore, Result as ObjectStoreResult};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsistencyWait {
+    /// heads retried while the object isn't found, none disables the wait
+    retries: u32,
+    /// delay before the first retry, doubled for every following one
+    backoff: Duration,
+}
+
+impl ConsistencyWait {
+    pub fn new(retries: u32, backoff: Duration) -> Self {
+        Self { retries, backoff }
+    }
+
+    /// Waits until `location` is found in `store`. The not found error of the last
+    /// head is returned once retries are exhausted.
+    pub async fn until_visible(
+        &self,
+        store: &dyn ObjectStore,
+        location: &Path,
+    ) -> ObjectStoreResult<()> {
+        if self.retries == 0 {
+            return Ok(());
+        }
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            match store.head(location).await {
+                Ok(_) => return Ok(()),
+                Err(object_store::Error::NotFound { .. }) if retries < self.retries => {
+                    retries += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use object_store::{path::Path, ObjectStore};
+
+    use super::ConsistencyWait;
+    use crate::storage::faulty_store::{Fault, FaultyStore, Operation};
+
+    /// Store not finding a written object for the first `hidden` heads
+    fn delayed_visibility(hidden: usize) -> FaultyStore {
+        let store = FaultyStore::default();
+        let not_found = Fault::error(|| object_store::Error::NotFound {
+            path: "app/date=2024-01-01/data.parquet".to_owned(),
+            source: "NoSuchKey".into(),
+        });
+        store.inject(Operation::Head, not_found, hidden);
+        store
+    }
+
+    #[actix_web::test]
+    async fn write_waits_until_object_is_visible() {
+        let path = Path::from("app/date=2024-01-01/data.parquet");
+        let wait = ConsistencyWait::new(3, Duration::from_millis(1));
+
+        let store = delayed_visibility(2);
+        store.put(&path, Bytes::from_static(b"PAR1")).await.unwrap();
+        assert!(wait.until_visible(&store, &path).await.is_ok());
+        assert_eq!(store.calls(Operation::Head), 3);
+
+        let store = delayed_visibility(10);
+        store.put(&path, Bytes::from_static(b"PAR1")).await.unwrap();
+        assert!(matches!(
+            wait.until_visible(&store, &path).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+        assert_eq!(store.calls(Operation::Head), 4);
+
+        // disabled for strongly consistent stores
+        let store = delayed_visibility(10);
+        let disabled = ConsistencyWait::default();
+        assert!(disabled.until_visible(&store, &path).await.is_ok());
+        assert_eq!(store.calls(Operation::Head), 0);
+    }
+}
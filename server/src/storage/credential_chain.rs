@@ -0,0 +1,186 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Precedence of the sources of S3 credentials. Sources are tried in the configured
+//! order and the first one set up in the environment supplies the credentials, so a
+//! host with static keys, a profile and instance metadata resolves deterministically.
+//! Instance metadata can't be probed without a request and is always taken as set up.
+//! The object store client reads the web identity variables on its own, a chain
+//! preferring instance metadata still assumes the role when they are set.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+pub const AWS_CONTAINER_CREDENTIALS_RELATIVE_URI: &str = "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI";
+
+/// Sources tried when no chain is configured
+pub const DEFAULT_CHAIN: &str = "static,env,profile,assume-role,container,imds";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CredentialSource {
+    /// Access key and secret key of the S3 configuration
+    Static,
+    /// AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN
+    Env,
+    /// Shared credentials file, profile named by AWS_PROFILE
+    Profile,
+    /// Web identity token exchanged for a role, AWS_ROLE_ARN and AWS_WEB_IDENTITY_TOKEN_FILE
+    AssumeRole,
+    /// ECS task role
+    Container,
+    /// EC2 instance metadata
+    Imds,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Keys {
+        access_key: String,
+        secret_key: String,
+        token: Option<String>,
+    },
+    /// assumed by the object store client from the web identity variables
+    AssumeRole,
+    Container {
+        relative_uri: String,
+    },
+    Imds,
+}
+
+/// Resolves the first source of `chain` set up, `static_keys` are the configured keys and
+/// `env` looks up environment variables
+pub fn resolve(
+    chain: &[CredentialSource],
+    static_keys: Option<(&str, &str)>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Option<(CredentialSource, Credentials)> {
+    chain.iter().find_map(|&source| {
+        let credentials = match source {
+            CredentialSource::Static => {
+                static_keys.map(|(access_key, secret_key)| Credentials::Keys {
+                    access_key: access_key.to_owned(),
+                    secret_key: secret_key.to_owned(),
+                    token: None,
+                })
+            }
+            CredentialSource::Env => env("AWS_ACCESS_KEY_ID")
+                .zip(env("AWS_SECRET_ACCESS_KEY"))
+                .map(|(access_key, secret_key)| Credentials::Keys {
+                    access_key,
+                    secret_key,
+                    token: env("AWS_SESSION_TOKEN"),
+                }),
+            CredentialSource::Profile => profile(&env),
+            CredentialSource::AssumeRole => (env("AWS_ROLE_ARN").is_some()
+                && env("AWS_WEB_IDENTITY_TOKEN_FILE").is_some())
+            .then_some(Credentials::AssumeRole),
+            CredentialSource::Container => env(AWS_CONTAINER_CREDENTIALS_RELATIVE_URI)
+                .map(|relative_uri| Credentials::Container { relative_uri }),
+            CredentialSource::Imds => Some(Credentials::Imds),
+        };
+        credentials.map(|credentials| (source, credentials))
+    })
+}
+
+fn profile(env: &impl Fn(&str) -> Option<String>) -> Option<Credentials> {
+    let path = env("AWS_SHARED_CREDENTIALS_FILE")
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| PathBuf::from(home).join(".aws/credentials")))?;
+    let name = env("AWS_PROFILE").unwrap_or_else(|| "default".to_owned());
+    parse_profile(&std::fs::read_to_string(path).ok()?, &name)
+}
+
+/// Keys of a profile in the ini format of the shared credentials file
+fn parse_profile(file: &str, name: &str) -> Option<Credentials> {
+    let mut in_profile = false;
+    let mut keys = HashMap::new();
+    for line in file.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            in_profile = section.trim() == name;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_profile) {
+            keys.insert(key.trim(), value.trim().to_owned());
+        }
+    }
+    Some(Credentials::Keys {
+        access_key: keys.remove("aws_access_key_id")?,
+        secret_key: keys.remove("aws_secret_access_key")?,
+        token: keys.remove("aws_session_token"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{resolve, CredentialSource, Credentials};
+
+    fn keys(access_key: &str) -> Credentials {
+        Credentials::Keys {
+            access_key: access_key.to_owned(),
+            secret_key: "secret".to_owned(),
+            token: None,
+        }
+    }
+
+    #[test]
+    fn first_source_set_up_supplies_credentials() {
+        let file =
+            std::env::temp_dir().join(format!("parseable-credentials-{}", ulid::Ulid::new()));
+        std::fs::write(
+            &file,
+            "[default]\naws_access_key_id = default\naws_secret_access_key = secret\n\n\
+             [ops]\naws_access_key_id = ops\naws_secret_access_key = secret\n",
+        )
+        .unwrap();
+        let vars = HashMap::from([
+            ("AWS_ACCESS_KEY_ID", "env".to_owned()),
+            ("AWS_SECRET_ACCESS_KEY", "secret".to_owned()),
+            ("AWS_SHARED_CREDENTIALS_FILE", file.display().to_string()),
+            ("AWS_PROFILE", "ops".to_owned()),
+        ]);
+        let env = |name: &str| vars.get(name).cloned();
+        let static_keys = Some(("static", "secret"));
+
+        use CredentialSource::*;
+        assert_eq!(
+            resolve(&[Env, Profile, Imds, AssumeRole], static_keys, env),
+            Some((Env, keys("env")))
+        );
+        assert_eq!(
+            resolve(&[Profile, Static, Env], static_keys, env),
+            Some((Profile, keys("ops")))
+        );
+        assert_eq!(
+            resolve(&[Static, Env], static_keys, env),
+            Some((Static, keys("static")))
+        );
+        // sources not set up fall back to the next one
+        assert_eq!(
+            resolve(&[AssumeRole, Container, Imds, Env], None, env),
+            Some((Imds, Credentials::Imds))
+        );
+        assert_eq!(resolve(&[Static, Container], None, env), None);
+        std::fs::remove_file(file).unwrap();
+    }
+}
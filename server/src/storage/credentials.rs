@@ -0,0 +1,345 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Temporary credentials (STS, profile, instance metadata) are cached by the client until
+//! shortly before they expire. A request can still be rejected with an expired token, e.g.
+//! when the token is revoked or the clock is skewed. Such requests are retried once with
+//! freshly fetched credentials instead of failing the operation.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    future::Future,
+    ops::Range,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use object_store::{
+    aws::{AwsCredential, AwsCredentialProvider},
+    path::Path,
+    CredentialProvider, GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore,
+    PutOptions, PutResult, Result as ObjectStoreResult,
+};
+use tokio::io::AsyncWrite;
+
+// error codes returned by S3 and STS for expired credentials
+const EXPIRED_CREDENTIAL_CODES: [&str; 2] = ["ExpiredToken", "TokenRefreshRequired"];
+
+type BuildProvider = Box<dyn Fn() -> ObjectStoreResult<AwsCredentialProvider> + Send + Sync>;
+
+/// Credential provider which can be replaced by a new one, discarding cached credentials
+pub struct RefreshableCredentials {
+    current: RwLock<AwsCredentialProvider>,
+    build: BuildProvider,
+}
+
+impl RefreshableCredentials {
+    pub fn new(
+        build: impl Fn() -> ObjectStoreResult<AwsCredentialProvider> + Send + Sync + 'static,
+    ) -> ObjectStoreResult<Arc<Self>> {
+        Ok(Arc::new(Self {
+            current: RwLock::new(build()?),
+            build: Box::new(build),
+        }))
+    }
+
+    fn current(&self) -> AwsCredentialProvider {
+        Arc::clone(
+            &self
+                .current
+                .read()
+                .expect("credentials lock is not poisoned"),
+        )
+    }
+
+    /// Replaces `stale` with a new provider. Requests failing together
+    /// replace it only once, later callers find it already replaced.
+    fn refresh(&self, stale: &AwsCredentialProvider) {
+        let mut current = self
+            .current
+            .write()
+            .expect("credentials lock is not poisoned");
+        if !Arc::ptr_eq(&current, stale) {
+            return;
+        }
+        match (self.build)() {
+            Ok(provider) => {
+                log::warn!("object store credentials expired, fetching new credentials");
+                *current = provider;
+            }
+            Err(err) => log::error!("failed to create object store credential provider: {err}"),
+        }
+    }
+
+    /// Runs `op`, retrying it once with new credentials if it failed on expired ones
+    pub async fn retry<T, E, F, Fut>(&self, op: F) -> Result<T, E>
+    where
+        E: Error + 'static,
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let provider = self.current();
+        match op().await {
+            Err(err) if is_expired_credentials(&err) => {
+                self.refresh(&provider);
+                op().await
+            }
+            res => res,
+        }
+    }
+}
+
+impl Debug for RefreshableCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshableCredentials")
+            .field("current", &self.current())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for RefreshableCredentials {
+    type Credential = AwsCredential;
+
+    async fn get_credential(&self) -> ObjectStoreResult<Arc<AwsCredential>> {
+        self.current().get_credential().await
+    }
+}
+
+/// Whether the error, or any of its sources, is a rejection of expired credentials
+pub fn is_expired_credentials(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        let message = err.to_string();
+        if EXPIRED_CREDENTIAL_CODES
+            .iter()
+            .any(|code| message.contains(code))
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Retries requests failing on expired credentials. Listings and multipart uploads are
+/// streamed and aren't retried past their first request, callers retry them as a whole.
+#[derive(Debug)]
+pub struct CredentialRetry<T> {
+    inner: T,
+    // none for unsigned requests
+    credentials: Option<Arc<RefreshableCredentials>>,
+}
+
+impl<T> CredentialRetry<T> {
+    pub fn new(inner: T, credentials: Option<Arc<RefreshableCredentials>>) -> Self {
+        Self { inner, credentials }
+    }
+
+    async fn retry<R, F, Fut>(&self, op: F) -> ObjectStoreResult<R>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ObjectStoreResult<R>>,
+    {
+        match &self.credentials {
+            Some(credentials) => credentials.retry(op).await,
+            None => op().await,
+        }
+    }
+}
+
+impl<T: ObjectStore> Display for CredentialRetry<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CredentialRetry({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for CredentialRetry<T> {
+    async fn put(&self, location: &Path, bytes: Bytes) -> ObjectStoreResult<PutResult> {
+        self.retry(|| self.inner.put(location, bytes.clone())).await
+    }
+
+    async fn put_opts(
+        &self,
+        location: &Path,
+        bytes: Bytes,
+        opts: PutOptions,
+    ) -> ObjectStoreResult<PutResult> {
+        self.retry(|| self.inner.put_opts(location, bytes.clone(), opts.clone()))
+            .await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.retry(|| self.inner.put_multipart(location)).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.abort_multipart(location, multipart_id))
+            .await
+    }
+
+    async fn get(&self, location: &Path) -> ObjectStoreResult<GetResult> {
+        self.retry(|| self.inner.get(location)).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> ObjectStoreResult<GetResult> {
+        self.retry(|| self.inner.get_opts(location, options.clone()))
+            .await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        self.retry(|| self.inner.get_range(location, range.clone()))
+            .await
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> ObjectStoreResult<Vec<Bytes>> {
+        self.retry(|| self.inner.get_ranges(location, ranges)).await
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        self.retry(|| self.inner.head(location)).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.delete(location)).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        self.retry(|| self.inner.list_with_delimiter(prefix)).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.copy(from, to)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.rename(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.copy_if_not_exists(from, to)).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.rename_if_not_exists(from, to))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use object_store::{aws::AwsCredential, CredentialProvider, StaticCredentialProvider};
+
+    use super::{is_expired_credentials, RefreshableCredentials};
+
+    fn expired() -> object_store::Error {
+        object_store::Error::Generic {
+            store: "S3",
+            source: "Client error with status 400 Bad Request: <Error><Code>ExpiredToken</Code>\
+                <Message>The provided token has expired.</Message></Error>"
+                .into(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn expired_credentials_are_refreshed_and_retried() {
+        let providers = Arc::new(AtomicUsize::new(0));
+        let credentials = RefreshableCredentials::new({
+            let providers = Arc::clone(&providers);
+            move || {
+                let generation = providers.fetch_add(1, Ordering::SeqCst);
+                Ok(Arc::new(StaticCredentialProvider::new(AwsCredential {
+                    key_id: format!("key-{generation}"),
+                    secret_key: "secret".to_string(),
+                    token: Some(format!("token-{generation}")),
+                })))
+            }
+        })
+        .unwrap();
+
+        // the request fails until it is sent with credentials of a new provider
+        let attempts = AtomicUsize::new(0);
+        let res = credentials
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                let credential = credentials.current().get_credential().await?;
+                match credential.token.as_deref() {
+                    Some("token-0") => Err(expired()),
+                    _ => Ok(credential.key_id.clone()),
+                }
+            })
+            .await;
+
+        assert_eq!(res.unwrap(), "key-1");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(providers.load(Ordering::SeqCst), 2);
+
+        // other errors are returned as is
+        let res: object_store::Result<()> = credentials
+            .retry(|| async {
+                Err(object_store::Error::NotFound {
+                    path: "stream/.stream.json".to_string(),
+                    source: "NoSuchKey".into(),
+                })
+            })
+            .await;
+        assert!(matches!(res, Err(object_store::Error::NotFound { .. })));
+        assert_eq!(providers.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn expiry_is_found_in_error_sources() {
+        let err = std::io::Error::new(std::io::ErrorKind::Other, expired());
+        assert!(is_expired_credentials(&err));
+        assert!(!is_expired_credentials(&std::io::Error::from(
+            std::io::ErrorKind::TimedOut
+        )));
+    }
+}
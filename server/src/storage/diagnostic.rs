@@ -0,0 +1,293 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Self-diagnostic of the configured object store, run with `parseable <store> check-storage`.
+//! Every operation the server relies on is run against a scratch prefix and timed, so an
+//! S3-compatible gateway lacking a feature, such as multipart uploads or server side copy,
+//! is found before the server is pointed at it. Objects written are deleted afterwards.
+
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use object_store::{path::Path, ObjectStore};
+use tokio::io::AsyncWriteExt;
+
+const SCRATCH_PREFIX: &str = ".parseable-check-storage";
+const PAYLOAD_SIZE: usize = 64 * 1024;
+
+/// Outcome of one operation, an error message when it failed
+#[derive(Debug)]
+pub struct Check {
+    pub operation: &'static str,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+
+    async fn run<F>(&mut self, operation: &'static str, check: F)
+    where
+        F: Future<Output = Result<(), String>>,
+    {
+        let start = Instant::now();
+        let result = check.await;
+        self.checks.push(Check {
+            operation,
+            elapsed: start.elapsed(),
+            error: result.err(),
+        });
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = if check.error.is_none() {
+                "ok"
+            } else {
+                "FAILED"
+            };
+            write!(
+                f,
+                "{:<20} {:<7} {:>8.1?}",
+                check.operation, status, check.elapsed
+            )?;
+            if let Some(error) = &check.error {
+                write!(f, "  {error}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every storage operation against a scratch prefix of `store` and reports
+/// which succeed and how long each took
+pub async fn check_storage(store: &dyn ObjectStore) -> Report {
+    let run_id = ulid::Ulid::new().to_string();
+    let prefix = Path::from_iter([SCRATCH_PREFIX, run_id.as_str()]);
+    let object = prefix.child("object");
+    let multipart = prefix.child("multipart");
+    let copy = prefix.child("copy");
+    let payload: Bytes = (0..PAYLOAD_SIZE).map(|i| i as u8).collect();
+    let mut report = Report::default();
+
+    // a missing object is found missing only with working credentials
+    report
+        .run("credentials", async {
+            match store.head(&prefix.child("missing")).await {
+                Err(object_store::Error::NotFound { .. }) => Ok(()),
+                Ok(_) => Err("scratch object unexpectedly exists".to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        })
+        .await;
+    report
+        .run("put", async {
+            store
+                .put(&object, payload.clone())
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        })
+        .await;
+    report
+        .run("get", async {
+            let bytes = store
+                .get(&object)
+                .await
+                .map_err(|err| err.to_string())?
+                .bytes()
+                .await
+                .map_err(|err| err.to_string())?;
+            expect_bytes(&bytes, &payload)
+        })
+        .await;
+    report
+        .run("range get", async {
+            let range = 100..1100;
+            let bytes = store
+                .get_range(&object, range.clone())
+                .await
+                .map_err(|err| err.to_string())?;
+            expect_bytes(&bytes, &payload[range])
+        })
+        .await;
+    report
+        .run("list", async {
+            let objects: Vec<_> = store
+                .list(Some(&prefix))
+                .try_collect()
+                .await
+                .map_err(|err| err.to_string())?;
+            objects
+                .iter()
+                .any(|meta| meta.location == object)
+                .then_some(())
+                .ok_or_else(|| format!("{object} is not listed"))
+        })
+        .await;
+    report
+        .run("list with delimiter", async {
+            let listed = store
+                .list_with_delimiter(Some(&prefix))
+                .await
+                .map_err(|err| err.to_string())?;
+            listed
+                .objects
+                .iter()
+                .any(|meta| meta.location == object)
+                .then_some(())
+                .ok_or_else(|| format!("{object} is not listed"))
+        })
+        .await;
+    report
+        .run("multipart upload", async {
+            let (_, mut writer) = store
+                .put_multipart(&multipart)
+                .await
+                .map_err(|err| err.to_string())?;
+            writer
+                .write_all(&payload)
+                .await
+                .map_err(|err| err.to_string())?;
+            writer.shutdown().await.map_err(|err| err.to_string())?;
+            expect_size(store, &multipart, payload.len()).await
+        })
+        .await;
+    report
+        .run("copy", async {
+            store
+                .copy(&object, &copy)
+                .await
+                .map_err(|err| err.to_string())?;
+            expect_size(store, &copy, payload.len()).await
+        })
+        .await;
+    report
+        .run("delete", async {
+            for path in [&object, &multipart, &copy] {
+                match store.delete(path).await {
+                    Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+                    Err(err) => return Err(err.to_string()),
+                }
+            }
+            match store.head(&object).await {
+                Err(object_store::Error::NotFound { .. }) => Ok(()),
+                Ok(_) => Err(format!("{object} still exists")),
+                Err(err) => Err(err.to_string()),
+            }
+        })
+        .await;
+
+    report
+}
+
+fn expect_bytes(bytes: &[u8], expected: &[u8]) -> Result<(), String> {
+    if bytes == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "read {} bytes not matching the {} bytes written",
+            bytes.len(),
+            expected.len()
+        ))
+    }
+}
+
+async fn expect_size(store: &dyn ObjectStore, path: &Path, size: usize) -> Result<(), String> {
+    let meta = store.head(path).await.map_err(|err| err.to_string())?;
+    if meta.size == size {
+        Ok(())
+    } else {
+        Err(format!("{path} is {} bytes, expected {size}", meta.size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use object_store::{memory::InMemory, ObjectStore};
+
+    use super::check_storage;
+    use crate::storage::faulty_store::{Fault, FaultyStore, Operation};
+
+    #[actix_web::test]
+    async fn every_operation_passes_on_in_memory_store() {
+        let store = InMemory::new();
+        let report = check_storage(&store).await;
+
+        let operations: Vec<_> = report.checks.iter().map(|check| check.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                "credentials",
+                "put",
+                "get",
+                "range get",
+                "list",
+                "list with delimiter",
+                "multipart upload",
+                "copy",
+                "delete"
+            ]
+        );
+        assert!(report.passed(), "{report}");
+        assert!(report.to_string().lines().all(|line| line.contains(" ok ")));
+
+        // scratch objects are cleaned up
+        let left: Vec<_> = store.list(None).try_collect().await.unwrap();
+        assert!(left.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn missing_feature_fails_only_its_operation() {
+        let store = FaultyStore::default();
+        store.inject_always(
+            Operation::Copy,
+            Fault::error(|| object_store::Error::NotImplemented),
+        );
+        let report = check_storage(&store).await;
+
+        assert!(!report.passed());
+        let failed: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|check| check.error.is_some())
+            .map(|check| check.operation)
+            .collect();
+        assert_eq!(failed, ["copy"]);
+        assert!(report
+            .to_string()
+            .lines()
+            .any(|line| line.starts_with("copy") && line.contains("FAILED")));
+    }
+}
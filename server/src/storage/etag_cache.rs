@@ -0,0 +1,140 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+use bytes::Bytes;
+use object_store::{path::Path, GetOptions, ObjectStore};
+use once_cell::sync::Lazy;
+
+use super::{MANIFEST_FILE, SCHEMA_FILE_NAME};
+
+// manifests can be large, so the number of cached objects is bounded
+const MAX_CACHED_OBJECTS: usize = 1024;
+
+// object key -> (etag, body) of the last successful read
+static ETAG_CACHE: Lazy<Mutex<HashMap<Path, (String, Bytes)>>> = Lazy::new(Mutex::default);
+
+tokio::task_local! {
+    // set while serving a cold query, reads go to the object store and nothing is cached
+    static COLD_READ: bool;
+}
+
+/// Runs `fut` with every cache bypassed: cached objects are not served and
+/// objects read are not cached, so latency and content are those of the object store
+pub async fn cold_read<F: Future>(fut: F) -> F::Output {
+    COLD_READ.scope(true, fut).await
+}
+
+/// Whether the current task is serving a cold read
+pub fn is_cold_read() -> bool {
+    COLD_READ.try_with(|cold| *cold).unwrap_or(false)
+}
+
+/// Manifests and schemas are read on every query but change rarely
+pub fn is_cacheable(key: &str) -> bool {
+    key.ends_with(MANIFEST_FILE) || key.ends_with(SCHEMA_FILE_NAME)
+}
+
+/// Reads an object sending the ETag of the last read as `If-None-Match`.
+/// A not modified response is served from the cached body.
+pub async fn get(store: &dyn ObjectStore, location: &Path) -> object_store::Result<Bytes> {
+    if is_cold_read() {
+        return store.get(location).await?.bytes().await;
+    }
+
+    let cached = ETAG_CACHE.lock().unwrap().get(location).cloned();
+    let options = GetOptions {
+        if_none_match: cached.as_ref().map(|(etag, _)| etag.clone()),
+        ..GetOptions::default()
+    };
+
+    match store.get_opts(location, options).await {
+        Ok(resp) => {
+            let etag = resp.meta.e_tag.clone();
+            let body = resp.bytes().await?;
+            if let Some(etag) = etag {
+                let mut cache = ETAG_CACHE.lock().unwrap();
+                if cache.len() >= MAX_CACHED_OBJECTS && !cache.contains_key(location) {
+                    cache.clear();
+                }
+                cache.insert(location.clone(), (etag, body.clone()));
+            }
+            Ok(body)
+        }
+        Err(object_store::Error::NotModified { .. }) if cached.is_some() => {
+            Ok(cached.map(|(_, body)| body).expect("checked above"))
+        }
+        Err(err) => {
+            ETAG_CACHE.lock().unwrap().remove(location);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+
+    use super::{cold_read, get, ETAG_CACHE};
+
+    #[actix_web::test]
+    async fn serves_cached_body_until_object_changes() {
+        let store = InMemory::new();
+        let location = Path::from("stream/date=2024-01-01/manifest.json");
+        store
+            .put(&location, Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+
+        assert_eq!(get(&store, &location).await.unwrap(), "v1");
+        assert_eq!(get(&store, &location).await.unwrap(), "v1");
+
+        store
+            .put(&location, Bytes::from_static(b"v2"))
+            .await
+            .unwrap();
+        assert_eq!(get(&store, &location).await.unwrap(), "v2");
+
+        store.delete(&location).await.unwrap();
+        assert!(get(&store, &location).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn cold_read_neither_reads_nor_writes_cache() {
+        let store = InMemory::new();
+        let cached = Path::from("cold/date=2024-01-01/manifest.json");
+        let uncached = Path::from("cold/date=2024-01-02/manifest.json");
+        store.put(&cached, Bytes::from_static(b"v1")).await.unwrap();
+        store
+            .put(&uncached, Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+        assert_eq!(get(&store, &cached).await.unwrap(), "v1");
+
+        // plant a stale body under the current etag, only a cold read sees past it
+        ETAG_CACHE.lock().unwrap().get_mut(&cached).unwrap().1 = Bytes::from_static(b"stale");
+        assert_eq!(get(&store, &cached).await.unwrap(), "stale");
+        assert_eq!(cold_read(get(&store, &cached)).await.unwrap(), "v1");
+
+        assert_eq!(cold_read(get(&store, &uncached)).await.unwrap(), "v1");
+        assert!(!ETAG_CACHE.lock().unwrap().contains_key(&uncached));
+        assert_eq!(ETAG_CACHE.lock().unwrap().get(&cached).unwrap().1, "stale");
+    }
+}
@@ -0,0 +1,364 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Parquet files written to a stream prefix by other pipelines are not in the catalog,
+//! queries don't read them. When enabled such files are added to the manifests of their
+//! stream from their parquet statistics. New files are found through the S3 event
+//! notifications of an SQS queue if one is configured, else by listing each stream and
+//! diffing against its manifests, at most a bounded number of files per round.
+//!
+//! Files are only added by a standalone server, which is the only writer of its
+//! manifests. Manifests and files are read without holding the sync lock, it is only
+//! held to wait for running uploads and to add each file to the snapshot. A stream
+//! failing is logged and the others are still served. A file must have millisecond timestamps with statistics in the time
+//! partition column and a schema compatible with the stream, others are skipped.
+
+use std::collections::{HashMap, HashSet};
+use std::future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arrow_schema::{DataType, Schema, TimeUnit};
+use futures::{Stream, StreamExt, TryStreamExt};
+use parquet::arrow::parquet_to_arrow_schema;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use relative_path::{RelativePath, RelativePathBuf};
+
+use super::object_storage::{commit_schema_to_storage, SYNC_LOCK};
+use super::sqs::{self, SqsQueue};
+use super::{ObjectStorage, ObjectStorageError};
+use crate::catalog::{self, manifest};
+use crate::event::{self, DEFAULT_TIMESTAMP_KEY};
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+
+/// Starts adding external files in the background if enabled
+pub fn init() {
+    if !CONFIG.parseable.external_files {
+        return;
+    }
+    let queue = CONFIG.storage().get_notification_queue();
+    tokio::spawn(async move {
+        let storage = CONFIG.storage().get_object_store();
+        // files which can't be added, not retried until restart
+        let mut rejected = HashSet::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            CONFIG.parseable.external_files_interval,
+        ));
+        loop {
+            let res = match &queue {
+                Some(queue) => receive(&storage, queue, &mut rejected).await,
+                None => {
+                    interval.tick().await;
+                    poll(&storage, &mut rejected).await
+                }
+            };
+            match res {
+                Ok(0) => {}
+                Ok(added) => log::info!("added {added} external parquet files to the catalog"),
+                Err(err) => {
+                    log::warn!("failed to add external parquet files: {err}");
+                    if queue.is_some() {
+                        interval.tick().await;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Adds the files listed under streams which no manifest lists
+async fn poll(
+    storage: &Arc<dyn ObjectStorage + Send>,
+    rejected: &mut HashSet<RelativePathBuf>,
+) -> Result<usize, ObjectStorageError> {
+    let mut remaining = CONFIG.parseable.external_files_max;
+    let mut added = 0;
+    for stream in STREAM_INFO.list_streams() {
+        if remaining == 0 {
+            break;
+        }
+        match poll_stream(storage, &stream, rejected, remaining).await {
+            Ok((examined, stream_added)) => {
+                remaining -= examined;
+                added += stream_added;
+            }
+            Err(err) => log::warn!("failed to add external parquet files of {stream}: {err}"),
+        }
+    }
+    Ok(added)
+}
+
+/// Adds at most `limit` files listed under a stream, returning the number of files
+/// examined and the number added
+async fn poll_stream(
+    storage: &Arc<dyn ObjectStorage + Send>,
+    stream: &str,
+    rejected: &mut HashSet<RelativePathBuf>,
+    limit: usize,
+) -> Result<(usize, usize), ObjectStorageError> {
+    let listed = listed_files(&**storage, stream).await?;
+    let files = unlisted(
+        storage.list_parquet_files(stream),
+        |key| rejected.contains(key) || listed.contains(&storage.absolute_url(key).to_string()),
+        limit,
+    )
+    .await?;
+    let examined = files.len();
+    let files = not_uploaded_here(storage, stream, files).await?;
+    let added = add_files(storage, stream, files, rejected).await?;
+    Ok((examined, added))
+}
+
+/// Adds the files created in the bucket by the next messages of the queue
+async fn receive(
+    storage: &Arc<dyn ObjectStorage + Send>,
+    queue: &SqsQueue,
+    rejected: &mut HashSet<RelativePathBuf>,
+) -> Result<usize, ObjectStorageError> {
+    let messages = queue.receive().await?;
+    let bucket = storage.get_bucket_name();
+    let mut created: HashMap<String, Vec<RelativePathBuf>> = HashMap::new();
+    for key in messages
+        .iter()
+        .flat_map(|message| sqs::created_objects(&message.body, &bucket))
+    {
+        let stream = key.iter().next().unwrap_or_default().to_owned();
+        if STREAM_INFO.stream_exists(&stream) {
+            created.entry(stream).or_default().push(key);
+        }
+    }
+
+    let mut added = 0;
+    let mut failed = HashSet::new();
+    for (stream, keys) in created {
+        // this server's own uploads are notified too
+        let res = match not_uploaded_here(storage, &stream, keys).await {
+            Ok(files) => add_files(storage, &stream, files, rejected).await,
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(stream_added) => added += stream_added,
+            Err(err) => {
+                log::warn!("failed to add external parquet files of {stream}: {err}");
+                failed.insert(stream);
+            }
+        }
+    }
+    // messages of failed streams are received again
+    let handled: Vec<_> = messages
+        .into_iter()
+        .filter(|message| {
+            sqs::created_objects(&message.body, &bucket)
+                .iter()
+                .all(|key| !failed.contains(key.iter().next().unwrap_or_default()))
+        })
+        .collect();
+    queue.delete(&handled).await?;
+    Ok(added)
+}
+
+/// Files of `files` which are not in the manifests of a stream once the running uploads
+/// of this server are done
+async fn not_uploaded_here(
+    storage: &Arc<dyn ObjectStorage + Send>,
+    stream: &str,
+    files: Vec<RelativePathBuf>,
+) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+    if files.is_empty() {
+        return Ok(files);
+    }
+    // uploads hold the lock until their file is in the manifests, once it is acquired
+    // every file this server uploaded before is listed
+    drop(SYNC_LOCK.lock().await);
+    let listed = listed_files(&**storage, stream).await?;
+    Ok(files
+        .into_iter()
+        .filter(|key| !listed.contains(&storage.absolute_url(key).to_string()))
+        .collect())
+}
+
+/// Paths of the files in the manifests of a stream
+async fn listed_files(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream: &str,
+) -> Result<HashSet<String>, ObjectStorageError> {
+    let meta = storage.get_object_store_format(stream).await?;
+    let mut files = HashSet::new();
+    for item in meta.snapshot.manifest_list {
+        let path = catalog::partition_path(stream, item.time_lower_bound, item.time_upper_bound);
+        if let Some(manifest) = storage.get_manifest(&path).await? {
+            files.extend(manifest.files.into_iter().map(|file| file.file_path));
+        }
+    }
+    Ok(files)
+}
+
+/// First `limit` parquet files of `keys` which are not `listed`
+async fn unlisted(
+    keys: impl Stream<Item = Result<RelativePathBuf, ObjectStorageError>>,
+    listed: impl Fn(&RelativePath) -> bool,
+    limit: usize,
+) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+    keys.try_filter(|key| {
+        future::ready(key.extension() == Some("parquet") && !listed(key.as_relative_path()))
+    })
+    .take(limit)
+    .try_collect()
+    .await
+}
+
+/// Adds files to the manifests of a stream, returning the number added
+async fn add_files(
+    storage: &Arc<dyn ObjectStorage + Send>,
+    stream: &str,
+    files: Vec<RelativePathBuf>,
+    rejected: &mut HashSet<RelativePathBuf>,
+) -> Result<usize, ObjectStorageError> {
+    let time_partition = STREAM_INFO
+        .get_time_partition(stream)?
+        .unwrap_or_else(|| DEFAULT_TIMESTAMP_KEY.to_string());
+    let mut added = 0;
+    for key in files {
+        let bytes = storage.get_object(&key).await?;
+        let file_size = bytes.len() as u64;
+        let reader = SerializedFileReader::new(bytes)
+            .map_err(|err| err.to_string())
+            .and_then(|reader| {
+                let metadata = reader.metadata().file_metadata();
+                let schema =
+                    parquet_to_arrow_schema(metadata.schema_descr(), metadata.key_value_metadata())
+                        .map_err(|err| err.to_string())?;
+                Ok((reader, schema))
+            });
+        let (reader, schema) = match reader {
+            Ok(reader) => reader,
+            Err(err) => {
+                log::warn!("skipping unreadable external parquet file {key}: {err}");
+                rejected.insert(key);
+                continue;
+            }
+        };
+        let file = manifest::create_from_parquet_metadata(
+            storage.absolute_url(&key).to_string(),
+            file_size,
+            reader.metadata(),
+        );
+        if let Err(err) = check_time_partition(&file, &schema, &time_partition) {
+            log::warn!("skipping external parquet file {key}: {err}");
+            rejected.insert(key);
+            continue;
+        }
+        if let Err(err) = event::commit_schema(stream, Arc::new(schema.clone())) {
+            log::warn!("skipping external parquet file {key} with an incompatible schema: {err}");
+            rejected.insert(key);
+            continue;
+        }
+
+        // the sync of this server writes the same snapshot
+        let _guard = SYNC_LOCK.lock().await;
+        commit_schema_to_storage(stream, schema).await?;
+        catalog::update_snapshot(Arc::clone(storage), stream, file).await?;
+        added += 1;
+    }
+    Ok(added)
+}
+
+// files are placed in manifests by the bounds of their time partition column
+fn check_time_partition(
+    file: &manifest::File,
+    schema: &Schema,
+    time_partition: &str,
+) -> Result<(), String> {
+    match schema
+        .field_with_name(time_partition)
+        .map(|field| field.data_type())
+    {
+        Ok(DataType::Timestamp(TimeUnit::Millisecond, _)) => {}
+        Ok(data_type) => {
+            return Err(format!(
+                "time partition column {time_partition} is {data_type}, not a millisecond timestamp"
+            ))
+        }
+        Err(_) => return Err(format!("no time partition column {time_partition}")),
+    }
+    let has_stats = file.columns.iter().any(|column| {
+        column.name == time_partition
+            && column
+                .stats
+                .as_ref()
+                .and_then(|stats| stats.timestamp_bounds(&TimeUnit::Millisecond))
+                .is_some()
+    });
+    if !has_stats {
+        return Err(format!(
+            "no statistics for time partition column {time_partition}"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use relative_path::{RelativePath, RelativePathBuf};
+
+    use super::unlisted;
+    use crate::storage::ObjectStorageError;
+
+    #[actix_web::test]
+    async fn poll_diff_returns_unlisted_parquet_files() {
+        let store = InMemory::new();
+        for key in [
+            "app/date=2024-01-01/hour=00/minute=00/a.parquet",
+            "app/date=2024-01-01/hour=00/minute=00/b.parquet",
+            "app/date=2024-01-01/hour=00/minute=01/c.parquet",
+            "app/date=2024-01-01/manifest.json",
+        ] {
+            store
+                .put(&Path::from(key), Bytes::from_static(b"data"))
+                .await
+                .unwrap();
+        }
+        let listed: HashSet<_> = ["app/date=2024-01-01/hour=00/minute=00/a.parquet"]
+            .into_iter()
+            .map(RelativePathBuf::from)
+            .collect();
+        let diff = |limit| {
+            let keys = store
+                .list(Some(&Path::from("app")))
+                .map_ok(|meta| RelativePathBuf::from(meta.location.as_ref()))
+                .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)));
+            unlisted(keys, |key: &RelativePath| listed.contains(key), limit)
+        };
+
+        assert_eq!(
+            diff(10).await.unwrap(),
+            [
+                "app/date=2024-01-01/hour=00/minute=00/b.parquet",
+                "app/date=2024-01-01/hour=00/minute=01/c.parquet",
+            ]
+            .map(RelativePathBuf::from)
+        );
+        assert_eq!(diff(1).await.unwrap().len(), 1);
+    }
+}
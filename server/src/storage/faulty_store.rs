@@ -0,0 +1,335 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! In memory object store failing, delaying or hanging operations as scripted by a test,
+//! used to exercise the retry, timeout and failover layers wrapping the object store.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, StreamExt};
+use object_store::{
+    memory::InMemory, path::Path, GetOptions, GetResult, ListResult, MultipartId, ObjectMeta,
+    ObjectStore, PutOptions, PutResult, Result as ObjectStoreResult,
+};
+use tokio::io::AsyncWrite;
+
+/// Kind of object store call a fault is injected into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// put and multipart uploads
+    Put,
+    /// get and ranged gets
+    Get,
+    Head,
+    Delete,
+    /// recursive and delimited listings
+    List,
+    Copy,
+}
+
+/// Behaviour of a call a fault is injected into
+#[derive(Clone)]
+pub enum Fault {
+    /// fails with the error built by the function
+    Error(Arc<dyn Fn() -> object_store::Error + Send + Sync>),
+    /// answers from the inner store after the delay
+    Delay(Duration),
+    /// never answers
+    Hang,
+}
+
+impl Fault {
+    pub fn error(build: impl Fn() -> object_store::Error + Send + Sync + 'static) -> Self {
+        Self::Error(Arc::new(build))
+    }
+
+    /// Generic error such as the ones S3 answers with for server errors
+    pub fn generic(message: &'static str) -> Self {
+        Self::error(move || object_store::Error::Generic {
+            store: "S3",
+            source: message.into(),
+        })
+    }
+}
+
+impl std::fmt::Debug for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Error(build) => write!(f, "Error({})", build()),
+            Fault::Delay(delay) => write!(f, "Delay({delay:?})"),
+            Fault::Hang => write!(f, "Hang"),
+        }
+    }
+}
+
+/// Faults of the upcoming calls of an operation
+#[derive(Debug, Default)]
+struct Script {
+    next: VecDeque<Fault>,
+    /// fault of every call once `next` is exhausted
+    always: Option<Fault>,
+    calls: usize,
+}
+
+/// Object store answering from memory unless a fault is scripted for the call
+#[derive(Debug, Default)]
+pub struct FaultyStore {
+    inner: InMemory,
+    scripts: Mutex<HashMap<Operation, Script>>,
+}
+
+impl std::fmt::Display for FaultyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FaultyStore")
+    }
+}
+
+impl FaultyStore {
+    /// Injects `fault` into the next `times` calls of `operation`,
+    /// after the faults already scripted for it
+    pub fn inject(&self, operation: Operation, fault: Fault, times: usize) -> &Self {
+        let mut scripts = self.scripts.lock().unwrap();
+        let script = scripts.entry(operation).or_default();
+        script.next.extend(std::iter::repeat(fault).take(times));
+        self
+    }
+
+    /// Injects `fault` into every call of `operation` once scripted faults are used up
+    pub fn inject_always(&self, operation: Operation, fault: Fault) -> &Self {
+        let mut scripts = self.scripts.lock().unwrap();
+        scripts.entry(operation).or_default().always = Some(fault);
+        self
+    }
+
+    /// Removes the faults of all operations
+    pub fn heal(&self) {
+        for script in self.scripts.lock().unwrap().values_mut() {
+            script.next.clear();
+            script.always = None;
+        }
+    }
+
+    /// Number of calls of `operation` so far, including failed ones
+    pub fn calls(&self, operation: Operation) -> usize {
+        let scripts = self.scripts.lock().unwrap();
+        scripts.get(&operation).map_or(0, |script| script.calls)
+    }
+
+    /// Scripted faults of `operation` not yet injected
+    pub fn pending(&self, operation: Operation) -> usize {
+        let scripts = self.scripts.lock().unwrap();
+        scripts
+            .get(&operation)
+            .map_or(0, |script| script.next.len())
+    }
+
+    fn next_fault(&self, operation: Operation) -> Option<Fault> {
+        let mut scripts = self.scripts.lock().unwrap();
+        let script = scripts.entry(operation).or_default();
+        script.calls += 1;
+        script.next.pop_front().or_else(|| script.always.clone())
+    }
+
+    /// Applies the fault of the call, errors are returned and delays waited out
+    async fn fault(&self, operation: Operation) -> ObjectStoreResult<()> {
+        match self.next_fault(operation) {
+            None => Ok(()),
+            Some(Fault::Error(build)) => Err(build()),
+            Some(Fault::Delay(delay)) => {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+            Some(Fault::Hang) => futures_util::future::pending().await,
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FaultyStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: Bytes,
+        opts: PutOptions,
+    ) -> ObjectStoreResult<PutResult> {
+        self.fault(Operation::Put).await?;
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.fault(Operation::Put).await?;
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> ObjectStoreResult<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> ObjectStoreResult<GetResult> {
+        self.fault(Operation::Get).await?;
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        self.fault(Operation::Get).await?;
+        self.inner.get_range(location, range).await
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        self.fault(Operation::Head).await?;
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        self.fault(Operation::Delete).await?;
+        self.inner.delete(location).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        let prefix = prefix.cloned();
+        futures_util::stream::once(async move {
+            match self.fault(Operation::List).await {
+                Ok(()) => self.inner.list(prefix.as_ref()).collect::<Vec<_>>().await,
+                Err(err) => vec![Err(err)],
+            }
+        })
+        .flat_map(futures_util::stream::iter)
+        .boxed()
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        self.fault(Operation::List).await?;
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.fault(Operation::Copy).await?;
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.fault(Operation::Copy).await?;
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use object_store::{path::Path, ObjectStore};
+    use tokio::time::timeout;
+
+    use super::{Fault, FaultyStore, Operation};
+
+    #[actix_web::test]
+    async fn scripted_failures_run_out() {
+        let store = FaultyStore::default();
+        let path = Path::from("app/.stream/.stream.json");
+        store.put(&path, Bytes::from_static(b"{}")).await.unwrap();
+
+        store
+            .inject(Operation::Head, Fault::generic("503 SlowDown"), 2)
+            .inject(
+                Operation::Head,
+                Fault::error(|| object_store::Error::NotFound {
+                    path: "app".to_string(),
+                    source: "missing".into(),
+                }),
+                1,
+            );
+        assert!(matches!(
+            store.head(&path).await,
+            Err(object_store::Error::Generic { .. })
+        ));
+        assert!(store.head(&path).await.is_err());
+        assert!(matches!(
+            store.head(&path).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+        assert!(store.head(&path).await.is_ok());
+        assert_eq!(store.calls(Operation::Head), 4);
+        assert_eq!(store.pending(Operation::Head), 0);
+
+        // other operations are not affected
+        assert!(store.get(&path).await.is_ok());
+        assert_eq!(store.calls(Operation::Get), 1);
+    }
+
+    #[actix_web::test]
+    async fn persistent_faults_last_until_healed() {
+        let store = FaultyStore::default();
+        let path = Path::from("app/.stream/.stream.json");
+        store.put(&path, Bytes::from_static(b"{}")).await.unwrap();
+        store
+            .inject(Operation::List, Fault::Delay(Duration::from_millis(50)), 1)
+            .inject_always(Operation::List, Fault::generic("connection reset"));
+
+        let start = Instant::now();
+        assert_eq!(
+            store.list_with_delimiter(None).await.unwrap().objects.len(),
+            0
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        for _ in 0..3 {
+            let listed: Vec<_> = store.list(None).collect().await;
+            assert_eq!(listed.len(), 1);
+            assert!(listed[0].is_err());
+        }
+
+        store.heal();
+        let listed: Vec<_> = store.list(None).collect().await;
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].is_ok());
+    }
+
+    #[actix_web::test]
+    async fn hanging_calls_never_answer() {
+        let store = FaultyStore::default();
+        let path = Path::from("app/.stream/.stream.json");
+        store.inject(Operation::Put, Fault::Hang, 1);
+
+        let put = timeout(
+            Duration::from_millis(50),
+            store.put(&path, Bytes::from_static(b"{}")),
+        )
+        .await;
+        assert!(put.is_err());
+        assert!(
+            store.head(&path).await.is_err(),
+            "the hung put wrote nothing"
+        );
+        assert!(store.put(&path, Bytes::from_static(b"{}")).await.is_ok());
+    }
+}
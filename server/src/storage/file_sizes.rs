@@ -0,0 +1,156 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Distribution of the sizes of the parquet files of a stream, listed from the store.
+//! Many files in the smallest buckets mean compaction is behind or its thresholds are
+//! too low. Listing a stream is a full listing, so a histogram is cached for a while.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::TryStreamExt;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use super::{ObjectStorage, ObjectStorageError};
+
+const MB: u64 = 1024 * 1024;
+
+// upper bounds of the buckets, the last bucket holds every larger file
+const BOUNDS: [u64; 5] = [MB, 10 * MB, 64 * MB, 128 * MB, 512 * MB];
+const LABELS: [&str; 6] = [
+    "<1MB",
+    "1-10MB",
+    "10-64MB",
+    "64-128MB",
+    "128-512MB",
+    ">=512MB",
+];
+
+// how long a listed histogram is served before the stream is listed again
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+// stream -> (listed at, histogram)
+static CACHE: Lazy<Mutex<HashMap<String, (Instant, FileSizeHistogram)>>> =
+    Lazy::new(Mutex::default);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub label: &'static str,
+    /// files in the bucket
+    pub files: u64,
+    /// total size of the files in the bucket
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileSizeHistogram {
+    pub files: u64,
+    pub bytes: u64,
+    pub buckets: Vec<Bucket>,
+}
+
+impl FileSizeHistogram {
+    pub fn from_sizes(sizes: impl IntoIterator<Item = u64>) -> Self {
+        let mut buckets = LABELS
+            .iter()
+            .map(|&label| Bucket {
+                label,
+                files: 0,
+                bytes: 0,
+            })
+            .collect::<Vec<_>>();
+        for size in sizes {
+            let bucket = &mut buckets[BOUNDS.partition_point(|&bound| bound <= size)];
+            bucket.files += 1;
+            bucket.bytes += size;
+        }
+        Self {
+            files: buckets.iter().map(|bucket| bucket.files).sum(),
+            bytes: buckets.iter().map(|bucket| bucket.bytes).sum(),
+            buckets,
+        }
+    }
+}
+
+/// Histogram of the parquet files of `stream_name`, listed again once the cached one expires
+pub async fn histogram(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+) -> Result<FileSizeHistogram, ObjectStorageError> {
+    if let Some((listed_at, histogram)) = CACHE.lock().unwrap().get(stream_name) {
+        if listed_at.elapsed() < CACHE_TTL {
+            return Ok(histogram.clone());
+        }
+    }
+
+    let sizes: Vec<u64> = storage
+        .list_objects_meta(stream_name)
+        .map_ok(|meta| meta.size)
+        .try_collect()
+        .await?;
+    let histogram = FileSizeHistogram::from_sizes(sizes);
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(stream_name.to_owned(), (Instant::now(), histogram.clone()));
+    Ok(histogram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSizeHistogram, MB};
+
+    #[test]
+    fn files_are_counted_in_their_size_bucket() {
+        let sizes = [
+            10 * 1024,
+            MB - 1,
+            MB,
+            5 * MB,
+            64 * MB,
+            100 * MB,
+            512 * MB,
+            2048 * MB,
+        ];
+        let histogram = FileSizeHistogram::from_sizes(sizes);
+
+        let buckets: Vec<_> = histogram
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.label, bucket.files))
+            .collect();
+        assert_eq!(
+            buckets,
+            [
+                ("<1MB", 2),
+                ("1-10MB", 2),
+                ("10-64MB", 0),
+                ("64-128MB", 2),
+                ("128-512MB", 0),
+                (">=512MB", 2),
+            ]
+        );
+        assert_eq!(histogram.buckets[0].bytes, 10 * 1024 + MB - 1);
+        assert_eq!(histogram.files, 8);
+        assert_eq!(histogram.bytes, sizes.iter().sum::<u64>());
+    }
+}
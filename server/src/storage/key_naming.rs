@@ -0,0 +1,399 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use chrono::{Days, DurationRound, NaiveDate, NaiveDateTime, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
+
+use crate::option::CONFIG;
+
+use super::partition_granularity::PartitionGranularity;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Layout of object keys for the data files of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyNaming {
+    /// `date=2024-01-01/hour=10/minute=05/<file>`
+    #[default]
+    Hive,
+    /// `2024-01-01/10/05/<file>`
+    Flat,
+}
+
+impl KeyNaming {
+    pub fn strategy(self) -> &'static dyn KeyNamingStrategy {
+        match self {
+            KeyNaming::Hive => &Hive,
+            KeyNaming::Flat => &Flat,
+        }
+    }
+}
+
+/// Strategy selected for this server
+pub fn strategy() -> &'static dyn KeyNamingStrategy {
+    CONFIG.parseable.key_naming.strategy()
+}
+
+/// A single level of the partition hierarchy of a data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionComponent {
+    Date(NaiveDate),
+    Hour(u32),
+    /// minute slot, e.g. `05` or `00-09`
+    Minute(String),
+    Custom(String, String),
+}
+
+impl PartitionComponent {
+    /// Parses a component in the `key=value` form used by staging file names
+    /// and time period prefixes
+    pub fn from_hive(component: &str) -> Option<Self> {
+        let (key, value) = component.split_once('=')?;
+        let component = match key {
+            "date" => Self::Date(NaiveDate::parse_from_str(value, DATE_FORMAT).ok()?),
+            "hour" => Self::Hour(value.parse().ok()?),
+            "minute" => Self::Minute(value.to_owned()),
+            _ => Self::Custom(key.to_owned(), value.to_owned()),
+        };
+        Some(component)
+    }
+}
+
+/// Computes the object keys of data files and the date partitions they are grouped in.
+/// Listing, retention and catalog code parse keys through the same strategy so that
+/// a stream is laid out consistently.
+pub trait KeyNamingStrategy: Send + Sync {
+    /// Renders a partition component as a single segment of an object key
+    fn segment(&self, component: &PartitionComponent) -> String;
+
+    /// Parses the date of a top level partition of a stream.
+    /// Partitions spanning a date range yield their lower bound.
+    fn parse_date(&self, segment: &str) -> Option<NaiveDate>;
+
+    /// Translates a prefix such as `date=2024-01-01/hour=10/` into this layout
+    fn prefix(&self, hive_prefix: &str) -> String {
+        hive_prefix
+            .split_terminator('/')
+            .map(|component| match PartitionComponent::from_hive(component) {
+                Some(component) => self.segment(&component) + "/",
+                None => component.to_owned() + "/",
+            })
+            .collect()
+    }
+
+    /// Object key of a staged parquet file whose name starts with `partitions` dot
+    /// separated components, time partitions finer than `granularity` are left out
+    fn object_key(
+        &self,
+        stream: &str,
+        file_name: &str,
+        partitions: usize,
+        granularity: PartitionGranularity,
+    ) -> String {
+        let mut parts = file_name.splitn(partitions + 1, '.').collect::<Vec<_>>();
+        let file = parts.pop().unwrap_or_default();
+        let mut key = format!("{stream}/");
+        for part in parts {
+            if PartitionComponent::from_hive(part)
+                .is_some_and(|component| !granularity.keeps(&component))
+            {
+                continue;
+            }
+            key.push_str(&self.prefix(part));
+        }
+        key + file
+    }
+
+    /// Top level partition holding the manifest of the given date range
+    fn date_partition(&self, lower: NaiveDate, upper: NaiveDate) -> String {
+        let segment = self.segment(&PartitionComponent::Date(lower));
+        if lower == upper {
+            segment
+        } else {
+            format!("{segment}:{}", upper.format(DATE_FORMAT))
+        }
+    }
+}
+
+/// Hive style `key=value` segments, understood by most data lake engines
+pub struct Hive;
+
+impl KeyNamingStrategy for Hive {
+    fn segment(&self, component: &PartitionComponent) -> String {
+        match component {
+            PartitionComponent::Date(date) => format!("date={}", date.format(DATE_FORMAT)),
+            PartitionComponent::Hour(hour) => format!("hour={hour:02}"),
+            PartitionComponent::Minute(minute) => format!("minute={minute}"),
+            PartitionComponent::Custom(key, value) => format!("{key}={value}"),
+        }
+    }
+
+    fn parse_date(&self, segment: &str) -> Option<NaiveDate> {
+        parse_date_prefix(segment.strip_prefix("date=")?)
+    }
+}
+
+/// Plain value segments without keys
+pub struct Flat;
+
+impl KeyNamingStrategy for Flat {
+    fn segment(&self, component: &PartitionComponent) -> String {
+        match component {
+            PartitionComponent::Date(date) => date.format(DATE_FORMAT).to_string(),
+            PartitionComponent::Hour(hour) => format!("{hour:02}"),
+            PartitionComponent::Minute(minute) => minute.clone(),
+            PartitionComponent::Custom(_, value) => value.clone(),
+        }
+    }
+
+    fn parse_date(&self, segment: &str) -> Option<NaiveDate> {
+        parse_date_prefix(segment)
+    }
+}
+
+/// Prefixes of every date in `start..=end`. For hourly partitions
+/// a range within a single day is narrowed down to the prefixes of its hours.
+pub fn range_prefixes(
+    strategy: &dyn KeyNamingStrategy,
+    granularity: PartitionGranularity,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Vec<String> {
+    let (start_date, end_date) = (start.date(), end.date());
+    if start_date > end_date {
+        return Vec::new();
+    }
+
+    let date = strategy.segment(&PartitionComponent::Date(start_date));
+    if start_date == end_date && granularity == PartitionGranularity::Hour {
+        return (start.hour()..=end.hour())
+            .map(|hour| {
+                format!(
+                    "{date}/{}/",
+                    strategy.segment(&PartitionComponent::Hour(hour))
+                )
+            })
+            .collect();
+    }
+
+    let mut prefixes = Vec::new();
+    let mut current = Some(start_date);
+    while let Some(date) = current.filter(|date| *date <= end_date) {
+        prefixes.push(strategy.segment(&PartitionComponent::Date(date)) + "/");
+        current = date.checked_add_days(Days::new(1));
+    }
+    prefixes
+}
+
+/// Wall clock time in `zone` of a UTC time, used to pick the date and hour partitions of data.
+/// Without a zone partitions are in UTC.
+pub fn partition_time(time: NaiveDateTime, zone: Option<Tz>) -> NaiveDateTime {
+    match zone {
+        Some(zone) => time.and_utc().with_timezone(&zone).naive_local(),
+        None => time,
+    }
+}
+
+/// Current date in the partition zone
+pub fn partition_today(zone: Option<Tz>) -> NaiveDate {
+    partition_time(Utc::now().naive_utc(), zone).date()
+}
+
+/// Wall clock range of the partitions holding data of the UTC range `start..end`.
+///
+/// When clocks are set back for DST, the wall clock hour before the change repeats and
+/// the data of both hours shares one partition. A UTC range inside that window can end
+/// before it starts in wall clock time, so for zones other than UTC the range is widened
+/// to whole hours, at the cost of listing up to two hours more than needed.
+pub fn partition_range(
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    zone: Option<Tz>,
+) -> (NaiveDateTime, NaiveDateTime) {
+    if zone.is_none() {
+        return (start, end);
+    }
+    let hour = TimeDelta::try_hours(1).expect("valid duration");
+    let start = partition_time(start, zone);
+    let end = partition_time(end, zone).max(start);
+    let start = start.duration_trunc(hour).unwrap_or(start);
+    let end = end.duration_trunc(hour).unwrap_or(end) + hour;
+    (start, end)
+}
+
+fn parse_date_prefix(segment: &str) -> Option<NaiveDate> {
+    let date = segment.get(..10)?;
+    NaiveDate::parse_from_str(date, DATE_FORMAT).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use chrono_tz::{America::New_York, Asia::Kolkata};
+
+    use super::{
+        partition_range, partition_time, range_prefixes, Flat, Hive, KeyNamingStrategy,
+        PartitionGranularity::{Day, Hour},
+    };
+
+    const STAGED: &str = "date=2024-01-01.hour=10.minute=05.region=eu.host.data.parquet";
+
+    #[test]
+    fn hive_keeps_staging_layout() {
+        assert_eq!(
+            Hive.object_key("app", STAGED, 4, Hour),
+            "app/date=2024-01-01/hour=10/region=eu/host.data.parquet"
+        );
+        assert_eq!(
+            Hive.object_key("app", STAGED, 4, Day),
+            "app/date=2024-01-01/region=eu/host.data.parquet"
+        );
+        assert_eq!(
+            Hive.prefix("date=2024-01-01/hour=10/"),
+            "date=2024-01-01/hour=10/"
+        );
+    }
+
+    #[test]
+    fn flat_drops_keys() {
+        assert_eq!(
+            Flat.object_key("app", STAGED, 4, Hour),
+            "app/2024-01-01/10/eu/host.data.parquet"
+        );
+        assert_eq!(
+            Flat.prefix("date=2024-01-01/hour=10/minute=00-09/"),
+            "2024-01-01/10/00-09/"
+        );
+    }
+
+    #[test]
+    fn date_partitions_round_trip() {
+        let lower = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+        let upper = NaiveDate::from_ymd_opt(2000, 1, 2).unwrap();
+        for strategy in [&Hive as &dyn KeyNamingStrategy, &Flat] {
+            let partition = strategy.date_partition(lower, upper);
+            assert_eq!(strategy.parse_date(&partition), Some(lower));
+            let partition = strategy.date_partition(upper, upper);
+            assert_eq!(strategy.parse_date(&partition), Some(upper));
+        }
+        assert_eq!(Hive.parse_date("date=2000-01-01"), Some(lower));
+        assert_eq!(Hive.parse_date(".stream"), None);
+        assert_eq!(Flat.parse_date(".stream"), None);
+    }
+
+    fn datetime(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn range_prefixes_across_month_and_year() {
+        assert_eq!(
+            range_prefixes(
+                &Hive,
+                Hour,
+                datetime("2023-12-30 10:00"),
+                datetime("2024-01-02 01:00")
+            ),
+            [
+                "date=2023-12-30/",
+                "date=2023-12-31/",
+                "date=2024-01-01/",
+                "date=2024-01-02/"
+            ]
+        );
+        assert_eq!(
+            range_prefixes(
+                &Flat,
+                Day,
+                datetime("2024-02-28 00:00"),
+                datetime("2024-03-01 00:00")
+            ),
+            ["2024-02-28/", "2024-02-29/", "2024-03-01/"]
+        );
+    }
+
+    #[test]
+    fn range_prefixes_within_a_day() {
+        assert_eq!(
+            range_prefixes(
+                &Hive,
+                Hour,
+                datetime("2024-01-01 22:15"),
+                datetime("2024-01-01 23:45")
+            ),
+            ["date=2024-01-01/hour=22/", "date=2024-01-01/hour=23/"]
+        );
+        // daily partitions are listed whole
+        assert_eq!(
+            range_prefixes(
+                &Hive,
+                Day,
+                datetime("2024-01-01 22:15"),
+                datetime("2024-01-01 23:45")
+            ),
+            ["date=2024-01-01/"]
+        );
+        assert!(range_prefixes(
+            &Hive,
+            Hour,
+            datetime("2024-01-02 00:00"),
+            datetime("2024-01-01 00:00")
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn partitions_follow_local_midnight() {
+        // 18:29 UTC is 23:59 in IST, a minute later the local date changes
+        let before = partition_time(datetime("2024-01-01 18:29"), Some(Kolkata));
+        let after = partition_time(datetime("2024-01-01 18:30"), Some(Kolkata));
+        assert_eq!(before, datetime("2024-01-01 23:59"));
+        assert_eq!(after, datetime("2024-01-02 00:00"));
+        assert_eq!(
+            partition_time(datetime("2024-01-01 18:30"), None),
+            datetime("2024-01-01 18:30")
+        );
+
+        // a UTC query around local midnight lists both local dates
+        let (start, end) = partition_range(
+            datetime("2024-01-01 18:00"),
+            datetime("2024-01-01 19:00"),
+            Some(Kolkata),
+        );
+        assert_eq!(
+            range_prefixes(&Hive, Hour, start, end),
+            ["date=2024-01-01/", "date=2024-01-02/"]
+        );
+    }
+
+    #[test]
+    fn partition_range_covers_repeated_dst_hour() {
+        // 05:30 UTC is 01:30 EDT and 06:10 UTC is 01:10 EST on 2024-11-03
+        let (start, end) = partition_range(
+            datetime("2024-11-03 05:30"),
+            datetime("2024-11-03 06:10"),
+            Some(New_York),
+        );
+        assert_eq!(start, datetime("2024-11-03 01:00"));
+        assert_eq!(end, datetime("2024-11-03 02:00"));
+        assert_eq!(
+            range_prefixes(&Hive, Hour, start, end),
+            ["date=2024-11-03/hour=01/", "date=2024-11-03/hour=02/"]
+        );
+    }
+}
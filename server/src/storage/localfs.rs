@@ -26,17 +26,27 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use datafusion::{datasource::listing::ListingTableUrl, execution::runtime_env::RuntimeConfig};
 use fs_extra::file::CopyOptions;
-use futures::{stream::FuturesUnordered, TryStreamExt};
+use futures::{
+    stream::{self, BoxStream, FuturesUnordered},
+    StreamExt, TryStreamExt,
+};
+use object_store::{local::LocalFileSystem, ObjectStore};
 use relative_path::{RelativePath, RelativePathBuf};
-use tokio::fs::{self, DirEntry};
+use tokio::{
+    fs::{self, DirEntry, ReadDir},
+    io::{AsyncReadExt, AsyncSeekExt},
+};
 use tokio_stream::wrappers::ReadDirStream;
 
 use crate::metrics::storage::{localfs::REQUEST_RESPONSE_TIME, StorageMetrics};
-use crate::option::validation;
+use crate::option::{validation, CONFIG};
+use crate::shutdown::UPLOADS;
 
 use super::{
-    LogStream, ObjectStorage, ObjectStorageError, ObjectStorageProvider, PARSEABLE_ROOT_DIRECTORY,
-    SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    metadata_store, object_key::ObjectKey, object_storage::check_object_size,
+    overwrite::OverwritePolicy, routing, LogStream, ObjectMetaInfo, ObjectStorage,
+    ObjectStorageError, ObjectStorageProvider, PARSEABLE_ROOT_DIRECTORY, QUARANTINE_ROOT_DIRECTORY,
+    SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY, TRASH_ROOT_DIRECTORY,
 };
 
 #[derive(Debug, Clone, clap::Args)]
@@ -64,7 +74,26 @@ impl ObjectStorageProvider for FSConfig {
     }
 
     fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send> {
-        Arc::new(LocalFS::new(self.root.clone()))
+        let store = Arc::new(
+            LocalFS::new(self.root.clone())
+                .with_max_object_read_size(CONFIG.parseable.max_object_read_size)
+                .with_overwrite_policy(CONFIG.parseable.object_overwrite_policy),
+        );
+        routing::route_streams(metadata_store::route_metadata(store, self), self)
+    }
+
+    fn get_object_store_at(&self, location: &str) -> Arc<dyn ObjectStorage + Send> {
+        Arc::new(
+            LocalFS::new(PathBuf::from(location))
+                .with_max_object_read_size(CONFIG.parseable.max_object_read_size)
+                .with_overwrite_policy(CONFIG.parseable.object_overwrite_policy),
+        )
+    }
+
+    fn get_raw_store(&self) -> Result<Arc<dyn ObjectStore>, ObjectStorageError> {
+        let store = LocalFileSystem::new_with_prefix(&self.root)
+            .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+        Ok(Arc::new(store))
     }
 
     fn get_endpoint(&self) -> String {
@@ -79,15 +108,65 @@ impl ObjectStorageProvider for FSConfig {
 pub struct LocalFS {
     // absolute path of the data directory
     root: PathBuf,
+    // objects larger than this are not read into memory
+    max_object_read_size: Option<usize>,
+    overwrite_policy: OverwritePolicy,
 }
 
 impl LocalFS {
     pub fn new(root: PathBuf) -> Self {
-        Self { root }
+        Self {
+            root,
+            max_object_read_size: None,
+            overwrite_policy: OverwritePolicy::default(),
+        }
+    }
+
+    pub fn with_max_object_read_size(mut self, limit: Option<usize>) -> Self {
+        self.max_object_read_size = limit;
+        self
+    }
+
+    pub fn with_overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite_policy = policy;
+        self
+    }
+
+    /// Walks directories depth first until the next parquet file. `entries` is the directory
+    /// being read, `dirs` are the directories found and not read yet.
+    async fn next_parquet_file(
+        &self,
+        dirs: &mut Vec<PathBuf>,
+        entries: &mut Option<ReadDir>,
+    ) -> Result<Option<ObjectMetaInfo>, ObjectStorageError> {
+        loop {
+            let Some(current) = entries.as_mut() else {
+                match dirs.pop() {
+                    Some(dir) => *entries = Some(fs::read_dir(dir).await?),
+                    None => return Ok(None),
+                }
+                continue;
+            };
+            let Some(entry) = current.next_entry().await? else {
+                *entries = None;
+                continue;
+            };
+
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "parquet") {
+                let size = entry.metadata().await?.len();
+                let path = path.strip_prefix(&self.root).expect("entry is under root");
+                return RelativePathBuf::from_path(path)
+                    .map(|path| Some(ObjectMetaInfo { path, size }))
+                    .map_err(ObjectStorageError::PathError);
+            }
+        }
     }
 
-    pub fn path_in_root(&self, path: &RelativePath) -> PathBuf {
-        path.to_path(&self.root)
+    pub fn path_in_root(&self, path: &RelativePath) -> Result<PathBuf, ObjectStorageError> {
+        Ok(ObjectKey::new(path)?.to_path(&self.root))
     }
 }
 
@@ -95,15 +174,21 @@ impl LocalFS {
 impl ObjectStorage for LocalFS {
     async fn get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
         let time = Instant::now();
-        let file_path = self.path_in_root(path);
-        let res: Result<Bytes, ObjectStorageError> = match fs::read(file_path).await {
-            Ok(x) => Ok(x.into()),
-            Err(e) => match e.kind() {
-                std::io::ErrorKind::NotFound => {
-                    Err(ObjectStorageError::NoSuchKey(path.to_string()))
-                }
-                _ => Err(ObjectStorageError::UnhandledError(Box::new(e))),
+        let file_path = self.path_in_root(path)?;
+        let map_err = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::NotFound => ObjectStorageError::NoSuchKey(path.to_string()),
+            _ => ObjectStorageError::UnhandledError(Box::new(e)),
+        };
+        let res: Result<Bytes, ObjectStorageError> = match fs::metadata(&file_path).await {
+            Ok(meta) => match check_object_size(
+                path.as_str(),
+                meta.len() as usize,
+                self.max_object_read_size,
+            ) {
+                Ok(()) => fs::read(file_path).await.map(Into::into).map_err(map_err),
+                Err(err) => Err(err),
             },
+            Err(e) => Err(map_err(e)),
         };
 
         let status = if res.is_ok() { "200" } else { "400" };
@@ -114,6 +199,44 @@ impl ObjectStorage for LocalFS {
         res
     }
 
+    async fn get_object_suffix(
+        &self,
+        path: &RelativePath,
+        len: usize,
+    ) -> Result<Bytes, ObjectStorageError> {
+        let time = Instant::now();
+        let file_path = self.path_in_root(path)?;
+        let res = read_suffix(&file_path, len)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => ObjectStorageError::NoSuchKey(path.to_string()),
+                _ => ObjectStorageError::UnhandledError(Box::new(e)),
+            });
+
+        let status = if res.is_ok() { "200" } else { "400" };
+        let time = time.elapsed().as_secs_f64();
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["GET", status])
+            .observe(time);
+        res
+    }
+
+    async fn object_checksum(&self, path: &RelativePath) -> Result<String, ObjectStorageError> {
+        let metadata =
+            fs::metadata(self.path_in_root(path)?)
+                .await
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => ObjectStorageError::NoSuchKey(path.to_string()),
+                    _ => ObjectStorageError::UnhandledError(Box::new(e)),
+                })?;
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Ok(format!("{modified:x}-{:x}", metadata.len()))
+    }
+
     async fn get_ingestor_meta_file_paths(
         &self,
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
@@ -155,7 +278,7 @@ impl ObjectStorage for LocalFS {
         let mut path_arr = vec![];
 
         // = data/stream_name
-        let stream_dir_path = self.path_in_root(&RelativePathBuf::from(stream_name));
+        let stream_dir_path = self.path_in_root(&RelativePathBuf::from(stream_name))?;
         let mut entries = fs::read_dir(&stream_dir_path).await?;
 
         while let Some(entry) = entries.next_entry().await? {
@@ -200,7 +323,7 @@ impl ObjectStorage for LocalFS {
         let time = Instant::now();
 
         let prefix = if let Some(path) = base_path {
-            path.to_path(&self.root)
+            self.path_in_root(path)?
         } else {
             self.root.clone()
         };
@@ -237,6 +360,10 @@ impl ObjectStorage for LocalFS {
         Ok(res)
     }
 
+    async fn exists(&self, path: &RelativePath) -> Result<bool, ObjectStorageError> {
+        Ok(fs::try_exists(self.path_in_root(path)?).await?)
+    }
+
     async fn put_object(
         &self,
         path: &RelativePath,
@@ -244,7 +371,16 @@ impl ObjectStorage for LocalFS {
     ) -> Result<(), ObjectStorageError> {
         let time = Instant::now();
 
-        let path = self.path_in_root(path);
+        let key = path.as_str();
+        let path = self.path_in_root(path)?;
+        let upload = self
+            .overwrite_policy
+            .for_key(key)
+            .allows_upload(key, || async { Ok(fs::try_exists(&path).await?) })
+            .await?;
+        if !upload {
+            return Ok(());
+        }
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).await?;
         }
@@ -259,14 +395,60 @@ impl ObjectStorage for LocalFS {
         res.map_err(Into::into)
     }
 
+    async fn put_object_if_not_exists(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError> {
+        let time = Instant::now();
+
+        let file_path = self.path_in_root(path)?;
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // the object is written aside and linked into place, linking fails if the
+        // target exists so readers never see a partially written object
+        let tmp_path = file_path.with_extension(format!("{}.tmp", ulid::Ulid::new()));
+        fs::write(&tmp_path, resource).await?;
+        let res = fs::hard_link(&tmp_path, &file_path).await;
+        let _ = fs::remove_file(&tmp_path).await;
+
+        let status = if res.is_ok() { "200" } else { "400" };
+        let time = time.elapsed().as_secs_f64();
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["PUT_IF_NOT_EXISTS", status])
+            .observe(time);
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                Err(ObjectStorageError::AlreadyExists(path.to_string()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
     async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
-        let path = self.path_in_root(path);
+        let path = self.path_in_root(path)?;
         tokio::fs::remove_dir_all(path).await?;
         Ok(())
     }
 
+    async fn move_prefix(
+        &self,
+        from: &RelativePath,
+        to: &RelativePath,
+    ) -> Result<(), ObjectStorageError> {
+        let to = self.path_in_root(to)?;
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(self.path_in_root(from)?, to).await?;
+        Ok(())
+    }
+
     async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
-        let path = self.path_in_root(path);
+        let path = self.path_in_root(path)?;
         tokio::fs::remove_file(path).await?;
         Ok(())
     }
@@ -291,7 +473,12 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
-        let ignore_dir = &["lost+found", PARSEABLE_ROOT_DIRECTORY];
+        let ignore_dir = &[
+            "lost+found",
+            PARSEABLE_ROOT_DIRECTORY,
+            QUARANTINE_ROOT_DIRECTORY,
+            TRASH_ROOT_DIRECTORY,
+        ];
         let directories = ReadDirStream::new(fs::read_dir(&self.root).await?);
         let entries: Vec<DirEntry> = directories.try_collect().await?;
         let entries = entries
@@ -311,7 +498,12 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
-        let ignore_dir = &["lost+found", PARSEABLE_ROOT_DIRECTORY];
+        let ignore_dir = &[
+            "lost+found",
+            PARSEABLE_ROOT_DIRECTORY,
+            QUARANTINE_ROOT_DIRECTORY,
+            TRASH_ROOT_DIRECTORY,
+        ];
         let directories = ReadDirStream::new(fs::read_dir(&self.root).await?);
         let entries: Vec<DirEntry> = directories.try_collect().await?;
         let entries = entries
@@ -357,13 +549,33 @@ impl ObjectStorage for LocalFS {
         Ok(dates.into_iter().flatten().collect())
     }
 
+    fn list_objects_meta(
+        &self,
+        stream_name: &str,
+    ) -> BoxStream<'_, Result<ObjectMetaInfo, ObjectStorageError>> {
+        let dirs = vec![self.root.join(stream_name)];
+        stream::try_unfold((dirs, None), move |(mut dirs, mut entries)| async move {
+            self.next_parquet_file(&mut dirs, &mut entries)
+                .await
+                .map(|path| path.map(|path| (path, (dirs, entries))))
+        })
+        .boxed()
+    }
+
     async fn upload_file(&self, key: &str, path: &Path) -> Result<(), ObjectStorageError> {
+        let _upload = UPLOADS.start();
+        let to_path = self.path_in_root(RelativePath::new(key))?;
+        let upload = self
+            .overwrite_policy
+            .allows_upload(key, || async { Ok(fs::try_exists(&to_path).await?) })
+            .await?;
+        if !upload {
+            return Ok(());
+        }
         let op = CopyOptions {
             overwrite: true,
-            skip_exist: true,
             ..CopyOptions::default()
         };
-        let to_path = self.root.join(key);
         if let Some(path) = to_path.parent() {
             fs::create_dir_all(path).await?;
         }
@@ -486,8 +698,198 @@ async fn dir_name(entry: DirEntry) -> Result<Option<String>, ObjectStorageError>
     }
 }
 
+async fn read_suffix(path: &Path, len: usize) -> std::io::Result<Bytes> {
+    let mut file = fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let start = size.saturating_sub(len as u64);
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    let mut buf = Vec::with_capacity((size - start) as usize);
+    file.read_to_end(&mut buf).await?;
+    Ok(buf.into())
+}
+
 impl From<fs_extra::error::Error> for ObjectStorageError {
     fn from(e: fs_extra::error::Error) -> Self {
         ObjectStorageError::UnhandledError(Box::new(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+    use parquet::arrow::ArrowWriter;
+    use relative_path::RelativePath;
+    use rstest::rstest;
+
+    use super::LocalFS;
+    use crate::storage::{overwrite::OverwritePolicy, ObjectStorage, ObjectStorageError};
+
+    #[actix_web::test]
+    async fn parquet_metadata_is_read_from_footer() {
+        let root = std::env::temp_dir().join(format!("parseable-localfs-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(root.join("stream")).unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..1000))],
+        )
+        .unwrap();
+        let file = std::fs::File::create(root.join("stream/data.parquet")).unwrap();
+        let mut writer = ArrowWriter::try_new(file, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let storage = LocalFS::new(root.clone());
+        let metadata = storage
+            .get_parquet_metadata(RelativePath::new("stream/data.parquet"))
+            .await;
+        let not_parquet = storage
+            .get_parquet_metadata(RelativePath::new("stream/missing.parquet"))
+            .await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let metadata = metadata.unwrap();
+        assert_eq!(metadata.file_metadata().num_rows(), 1000);
+        assert_eq!(
+            metadata.file_metadata().schema_descr().column(0).name(),
+            "a"
+        );
+        assert!(not_parquet.is_err());
+    }
+
+    #[actix_web::test]
+    async fn parquet_files_are_listed_under_nested_prefixes() {
+        let root = std::env::temp_dir().join(format!("parseable-localfs-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        let keys = [
+            "app/date=2024-01-01/hour=10/minute=05/a.parquet",
+            "app/date=2024-01-01/hour=10/minute=06/b.parquet",
+            "app/date=2024-01-01/hour=11/minute=00/c.parquet",
+            "app/date=2024-01-02/hour=00/minute=00/d.parquet",
+        ];
+        let others = [
+            "app/.stream/.stream.json",
+            "app/date=2024-01-01/manifest.json",
+            "other/date=2024-01-01/hour=10/minute=05/e.parquet",
+        ];
+        for key in keys.iter().chain(&others) {
+            storage
+                .put_object(RelativePath::new(key), Bytes::from_static(b"data"))
+                .await
+                .unwrap();
+        }
+
+        let listed: Result<Vec<_>, _> = storage.list_parquet_files("app").try_collect().await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let mut listed: Vec<String> = listed
+            .unwrap()
+            .into_iter()
+            .map(|path| path.to_string())
+            .collect();
+        listed.sort();
+        assert_eq!(listed, keys);
+    }
+
+    #[rstest]
+    #[case(OverwritePolicy::Overwrite, Some("new"))]
+    #[case(OverwritePolicy::SkipIfExists, Some("old"))]
+    #[case(OverwritePolicy::ErrorIfExists, None)]
+    #[actix_web::test]
+    async fn upload_to_existing_key_follows_policy(
+        #[case] policy: OverwritePolicy,
+        #[case] expected: Option<&str>,
+    ) {
+        let root = std::env::temp_dir().join(format!("parseable-localfs-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone()).with_overwrite_policy(policy);
+        let key = "stream/date=2024-01-01/hour=10/minute=05/data.parquet";
+        storage
+            .put_object(RelativePath::new(key), Bytes::from_static(b"old"))
+            .await
+            .unwrap();
+        let staged = root.join("staged.parquet");
+        std::fs::write(&staged, "new").unwrap();
+
+        let res = storage.upload_file(key, &staged).await;
+        let content = std::fs::read_to_string(root.join(key)).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        match expected {
+            Some(expected) => {
+                res.unwrap();
+                assert_eq!(content, expected);
+            }
+            None => {
+                assert!(matches!(res, Err(ObjectStorageError::AlreadyExists(_))));
+                assert_eq!(content, "old");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(OverwritePolicy::Overwrite, Some("new"))]
+    #[case(OverwritePolicy::SkipIfExists, Some("old"))]
+    #[case(OverwritePolicy::ErrorIfExists, None)]
+    #[actix_web::test]
+    async fn put_to_existing_key_follows_policy(
+        #[case] policy: OverwritePolicy,
+        #[case] expected: Option<&str>,
+    ) {
+        let root = std::env::temp_dir().join(format!("parseable-localfs-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone()).with_overwrite_policy(policy);
+        let key = RelativePath::new("stream/date=2024-01-01/hour=10/minute=05/data.parquet");
+        let metadata = RelativePath::new("stream/.stream/stream.json");
+        for path in [key, metadata] {
+            storage
+                .put_object(path, Bytes::from_static(b"old"))
+                .await
+                .unwrap();
+        }
+
+        let res = storage.put_object(key, Bytes::from_static(b"new")).await;
+        let content = std::fs::read_to_string(root.join(key.as_str())).unwrap();
+        storage
+            .put_object(metadata, Bytes::from_static(b"new"))
+            .await
+            .unwrap();
+        let metadata = std::fs::read_to_string(root.join(metadata.as_str())).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        match expected {
+            Some(expected) => {
+                res.unwrap();
+                assert_eq!(content, expected);
+            }
+            None => {
+                assert!(matches!(res, Err(ObjectStorageError::AlreadyExists(_))));
+                assert_eq!(content, "old");
+            }
+        }
+        assert_eq!(metadata, "new");
+    }
+
+    #[actix_web::test]
+    async fn schema_history_keeps_each_distinct_schema_once() {
+        let root = std::env::temp_dir().join(format!("parseable-localfs-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        let v1 = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        let v2 = Schema::new(vec![
+            Field::new("a", DataType::Int64, true),
+            Field::new("b", DataType::Utf8, true),
+        ]);
+
+        for schema in [&v1, &v2, &v1] {
+            storage.put_schema_history("stream", schema).await.unwrap();
+        }
+        let mut history = storage.get_schema_history("stream").await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        history.sort_by_key(|schema| schema.fields().len());
+        assert_eq!(history, [v1, v2]);
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Leases stored in the object store, so that only one of the nodes sharing a bucket
+//! runs a task at a time.
+//!
+//! A lease is created with a conditional put and kept alive by a heartbeat which pushes
+//! its expiry forward. A lease whose holder stopped heartbeating can be taken over once
+//! it expires. Takeover deletes the stale lease before creating a new one, two nodes
+//! taking over at the very same time may both succeed, so tasks guarded by a lease
+//! must tolerate a rare overlap.
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use relative_path::RelativePathBuf;
+use tokio::task::JoinHandle;
+use ulid::Ulid;
+
+use crate::option::CONFIG;
+
+use super::{ObjectStorage, ObjectStorageError, PARSEABLE_ROOT_DIRECTORY};
+
+const LOCK_DIRECTORY: &str = "locks";
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct LeaseInfo {
+    owner: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl LeaseInfo {
+    fn new(owner: &str, ttl: Duration) -> Self {
+        Self {
+            owner: owner.to_owned(),
+            expires_at: Utc::now() + ttl,
+        }
+    }
+
+    fn to_bytes(&self) -> Bytes {
+        serde_json::to_vec(self)
+            .expect("lease is serializable")
+            .into()
+    }
+}
+
+/// Lease held by this node, the heartbeat stops when it is dropped
+pub struct Lease {
+    name: String,
+    owner: String,
+    storage: Arc<dyn ObjectStorage + Send>,
+    heartbeat: JoinHandle<()>,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+    }
+}
+
+fn lock_path(name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([
+        PARSEABLE_ROOT_DIRECTORY,
+        LOCK_DIRECTORY,
+        &format!("{name}.json"),
+    ])
+}
+
+/// Acquires the lease `name` in the configured storage.
+/// Returns `None` if another node holds an unexpired lease.
+pub async fn acquire_lock(name: &str, ttl: Duration) -> Result<Option<Lease>, ObjectStorageError> {
+    acquire(CONFIG.storage().get_object_store(), name, ttl).await
+}
+
+/// Stops the heartbeat and removes the lease, unless it was taken over meanwhile
+pub async fn release_lock(lease: Lease) -> Result<(), ObjectStorageError> {
+    lease.heartbeat.abort();
+    let path = lock_path(&lease.name);
+    if current_owner(&*lease.storage, &path).await?.as_deref() == Some(lease.owner.as_str()) {
+        lease.storage.delete_object(&path).await?;
+    }
+    Ok(())
+}
+
+async fn acquire(
+    storage: Arc<dyn ObjectStorage + Send>,
+    name: &str,
+    ttl: Duration,
+) -> Result<Option<Lease>, ObjectStorageError> {
+    let path = lock_path(name);
+    let owner = Ulid::new().to_string();
+
+    if !try_create(&*storage, &path, &owner, ttl).await? {
+        let expired = match storage.get_object(&path).await {
+            // an unreadable lease can only be left behind by a failed writer
+            Ok(bytes) => serde_json::from_slice::<LeaseInfo>(&bytes)
+                .map(|lease| lease.expires_at < Utc::now())
+                .unwrap_or(true),
+            Err(ObjectStorageError::NoSuchKey(_)) => true,
+            Err(err) => return Err(err),
+        };
+        if !expired {
+            return Ok(None);
+        }
+
+        log::warn!("taking over expired lease {name}");
+        match storage.delete_object(&path).await {
+            Ok(()) | Err(ObjectStorageError::NoSuchKey(_)) => {}
+            Err(err) => return Err(err),
+        }
+        if !try_create(&*storage, &path, &owner, ttl).await? {
+            return Ok(None);
+        }
+    }
+
+    let heartbeat = tokio::spawn(heartbeat(Arc::clone(&storage), path, owner.clone(), ttl));
+
+    Ok(Some(Lease {
+        name: name.to_owned(),
+        owner,
+        storage,
+        heartbeat,
+    }))
+}
+
+async fn try_create(
+    storage: &dyn ObjectStorage,
+    path: &RelativePathBuf,
+    owner: &str,
+    ttl: Duration,
+) -> Result<bool, ObjectStorageError> {
+    let lease = LeaseInfo::new(owner, ttl);
+    match storage
+        .put_object_if_not_exists(path, lease.to_bytes())
+        .await
+    {
+        Ok(()) => Ok(true),
+        Err(ObjectStorageError::AlreadyExists(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+async fn current_owner(
+    storage: &dyn ObjectStorage,
+    path: &RelativePathBuf,
+) -> Result<Option<String>, ObjectStorageError> {
+    match storage.get_object(path).await {
+        Ok(bytes) => Ok(serde_json::from_slice::<LeaseInfo>(&bytes)
+            .ok()
+            .map(|lease| lease.owner)),
+        Err(ObjectStorageError::NoSuchKey(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Extends the lease a few times per ttl for as long as this node still owns it
+async fn heartbeat(
+    storage: Arc<dyn ObjectStorage + Send>,
+    path: RelativePathBuf,
+    owner: String,
+    ttl: Duration,
+) {
+    loop {
+        tokio::time::sleep(ttl / 3).await;
+        match current_owner(&*storage, &path).await {
+            Ok(Some(current)) if current == owner => {}
+            Ok(_) => {
+                log::warn!("lease {path} was taken over, stopping heartbeat");
+                return;
+            }
+            Err(err) => {
+                log::warn!("failed to read lease {path}: {err}");
+                continue;
+            }
+        }
+        let lease = LeaseInfo::new(&owner, ttl);
+        if let Err(err) = storage.put_object(&path, lease.to_bytes()).await {
+            log::warn!("failed to extend lease {path}: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::Utc;
+
+    use super::{acquire, lock_path, release_lock, LeaseInfo};
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn storage() -> (std::path::PathBuf, Arc<dyn ObjectStorage + Send>) {
+        let root = std::env::temp_dir().join(format!("parseable-lock-{}", ulid::Ulid::new()));
+        (root.clone(), Arc::new(LocalFS::new(root)))
+    }
+
+    #[actix_web::test]
+    async fn only_one_contender_acquires() {
+        let (root, storage) = storage();
+
+        let (first, second) = tokio::join!(
+            acquire(Arc::clone(&storage), "retention", TTL),
+            acquire(Arc::clone(&storage), "retention", TTL)
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert!(first.is_some() != second.is_some());
+
+        let lease = first.or(second).unwrap();
+        assert!(acquire(Arc::clone(&storage), "retention", TTL)
+            .await
+            .unwrap()
+            .is_none());
+        // leases are independent of each other
+        let other = acquire(Arc::clone(&storage), "compaction", TTL)
+            .await
+            .unwrap();
+        assert!(other.is_some());
+
+        release_lock(lease).await.unwrap();
+        let lease = acquire(Arc::clone(&storage), "retention", TTL)
+            .await
+            .unwrap();
+        assert!(lease.is_some());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn expired_lease_is_taken_over() {
+        let (root, storage) = storage();
+        let stale = LeaseInfo {
+            owner: "crashed-node".to_string(),
+            expires_at: Utc::now() - TTL,
+        };
+        storage
+            .put_object(&lock_path("retention"), stale.to_bytes())
+            .await
+            .unwrap();
+
+        let lease = acquire(Arc::clone(&storage), "retention", TTL)
+            .await
+            .unwrap()
+            .expect("expired lease is taken over");
+        assert!(acquire(Arc::clone(&storage), "retention", TTL)
+            .await
+            .unwrap()
+            .is_none());
+
+        release_lock(lease).await.unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
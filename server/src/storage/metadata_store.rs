@@ -0,0 +1,419 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Stores the metadata of the server, `.parseable.json`, `.stream.json`, schemas and
+//! manifests, in a location of its own, a bucket for S3 or a directory for drive, while
+//! parquet files stay in the default store. Metadata is small and read often, so it
+//! can live on a store that is cheaper per request than the one holding the data.
+
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use datafusion::datasource::listing::ListingTableUrl;
+use futures::stream::BoxStream;
+use relative_path::{RelativePath, RelativePathBuf};
+
+use crate::option::CONFIG;
+
+use super::{
+    LogStream, ObjectMetaInfo, ObjectStorage, ObjectStorageError, ObjectStorageProvider,
+    MANIFEST_FILE, PARSEABLE_METADATA_FILE_NAME, SCHEMA_FILE_NAME, SCHEMA_HISTORY_DIRECTORY,
+    STREAM_METADATA_FILE_NAME,
+};
+
+type Store = Arc<dyn ObjectStorage + Send>;
+
+/// Wraps `data` so that metadata is stored in the location of `--metadata-storage`
+/// of the same provider, both are the same store when it isn't set
+pub fn route_metadata(data: Store, provider: &dyn ObjectStorageProvider) -> Store {
+    match &CONFIG.parseable.metadata_storage {
+        Some(location) => Arc::new(MetadataRouter::new(
+            data,
+            provider.get_object_store_at(location),
+        )),
+        None => data,
+    }
+}
+
+/// Whether the object at `key` is metadata: the server and stream metadata files, including
+/// the ones of ingestors, schemas and manifests. Everything else stays in the data store.
+fn is_metadata(key: &str) -> bool {
+    let file_name = key.rsplit('/').next().unwrap_or(key);
+    let in_schema_history = key
+        .rsplit('/')
+        .nth(1)
+        .is_some_and(|dir| dir == SCHEMA_HISTORY_DIRECTORY);
+    file_name == PARSEABLE_METADATA_FILE_NAME
+        || file_name.ends_with(STREAM_METADATA_FILE_NAME)
+        || file_name.ends_with(SCHEMA_FILE_NAME)
+        || file_name == MANIFEST_FILE
+        || in_schema_history
+}
+
+/// Not found answered by a store that holds no objects under a prefix
+fn is_not_found(err: &ObjectStorageError) -> bool {
+    match err {
+        ObjectStorageError::NoSuchKey(_) => true,
+        ObjectStorageError::IoError(err) => err.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+pub struct MetadataRouter {
+    data: Store,
+    metadata: Store,
+}
+
+impl MetadataRouter {
+    pub fn new(data: Store, metadata: Store) -> Self {
+        Self { data, metadata }
+    }
+
+    fn for_key(&self, key: &str) -> &Store {
+        if is_metadata(key) {
+            &self.metadata
+        } else {
+            &self.data
+        }
+    }
+
+    /// Runs `op` on both stores, as objects under a prefix can be in either. A store
+    /// holding none of them may answer not found, which is ignored. Any other failure
+    /// fails the call, data is attempted first so metadata isn't removed on its failure.
+    async fn on_both<F, Fut>(&self, op: F) -> Result<(), ObjectStorageError>
+    where
+        F: Fn(Store) -> Fut,
+        Fut: std::future::Future<Output = Result<(), ObjectStorageError>>,
+    {
+        match op(Arc::clone(&self.data)).await {
+            Err(err) if !is_not_found(&err) => return Err(err),
+            _ => {}
+        }
+        match op(Arc::clone(&self.metadata)).await {
+            Err(err) if !is_not_found(&err) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Entries listed in either store, sorted and without duplicates.
+    /// Only fails when listing fails on both.
+    async fn list_union<F, Fut>(&self, list: F) -> Result<Vec<String>, ObjectStorageError>
+    where
+        F: Fn(Store) -> Fut,
+        Fut: std::future::Future<Output = Result<Vec<String>, ObjectStorageError>>,
+    {
+        let metadata = list(Arc::clone(&self.metadata)).await;
+        let data = list(Arc::clone(&self.data)).await;
+        match (metadata, data) {
+            (Err(err), Err(_)) => Err(err),
+            (metadata, data) => {
+                let entries: BTreeSet<String> =
+                    [metadata, data].into_iter().flatten().flatten().collect();
+                Ok(entries.into_iter().collect())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for MetadataRouter {
+    async fn get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
+        self.for_key(path.as_str()).get_object(path).await
+    }
+
+    async fn get_objects(
+        &self,
+        base_path: Option<&RelativePath>,
+        filter_fun: Box<dyn Fn(String) -> bool + Send>,
+    ) -> Result<Vec<Bytes>, ObjectStorageError> {
+        // the filter is shared by the listings of both stores
+        let filter_fun = Arc::new(Mutex::new(filter_fun));
+        let shared = || -> Box<dyn Fn(String) -> bool + Send> {
+            let filter_fun = Arc::clone(&filter_fun);
+            Box::new(move |name| (*filter_fun.lock().expect("filter is not poisoned"))(name))
+        };
+        let metadata = self.metadata.get_objects(base_path, shared()).await;
+        let data = self.data.get_objects(base_path, shared()).await;
+        match (metadata, data) {
+            (Err(err), Err(_)) => Err(err),
+            (metadata, data) => Ok([metadata, data].into_iter().flatten().flatten().collect()),
+        }
+    }
+
+    async fn get_object_suffix(
+        &self,
+        path: &RelativePath,
+        len: usize,
+    ) -> Result<Bytes, ObjectStorageError> {
+        self.for_key(path.as_str())
+            .get_object_suffix(path, len)
+            .await
+    }
+
+    async fn object_checksum(&self, path: &RelativePath) -> Result<String, ObjectStorageError> {
+        self.for_key(path.as_str()).object_checksum(path).await
+    }
+
+    async fn exists(&self, path: &RelativePath) -> Result<bool, ObjectStorageError> {
+        self.for_key(path.as_str()).exists(path).await
+    }
+
+    async fn put_object(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str()).put_object(path, resource).await
+    }
+
+    async fn put_object_if_not_exists(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str())
+            .put_object_if_not_exists(path, resource)
+            .await
+    }
+
+    async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        self.on_both(|store| async move { store.delete_prefix(path).await })
+            .await
+    }
+
+    async fn move_prefix(
+        &self,
+        from: &RelativePath,
+        to: &RelativePath,
+    ) -> Result<(), ObjectStorageError> {
+        self.on_both(|store| async move { store.move_prefix(from, to).await })
+            .await
+    }
+
+    async fn check(&self) -> Result<(), ObjectStorageError> {
+        self.data.check().await?;
+        self.metadata.check().await
+    }
+
+    async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
+        self.on_both(|store| async move { store.delete_stream(stream_name).await })
+            .await
+    }
+
+    async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        self.metadata.list_streams().await
+    }
+
+    async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        self.metadata.list_old_streams().await
+    }
+
+    async fn list_dirs(&self) -> Result<Vec<String>, ObjectStorageError> {
+        self.list_union(|store| async move { store.list_dirs().await })
+            .await
+    }
+
+    async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError> {
+        self.list_union(|store| async move { store.list_dates(stream_name).await })
+            .await
+    }
+
+    fn list_objects_meta(
+        &self,
+        stream_name: &str,
+    ) -> BoxStream<'_, Result<ObjectMetaInfo, ObjectStorageError>> {
+        self.data.list_objects_meta(stream_name)
+    }
+
+    async fn upload_file(&self, key: &str, path: &Path) -> Result<(), ObjectStorageError> {
+        self.for_key(key).upload_file(key, path).await
+    }
+
+    async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str()).delete_object(path).await
+    }
+
+    async fn get_ingestor_meta_file_paths(
+        &self,
+    ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        self.data.get_ingestor_meta_file_paths().await
+    }
+
+    async fn get_stream_file_paths(
+        &self,
+        stream_name: &str,
+    ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        self.metadata.get_stream_file_paths(stream_name).await
+    }
+
+    async fn try_delete_ingestor_meta(
+        &self,
+        ingestor_filename: String,
+    ) -> Result<(), ObjectStorageError> {
+        self.metadata
+            .try_delete_ingestor_meta(ingestor_filename)
+            .await
+    }
+
+    fn query_prefixes(&self, prefixes: Vec<String>) -> Vec<ListingTableUrl> {
+        self.data.query_prefixes(prefixes)
+    }
+
+    fn absolute_url(&self, prefix: &RelativePath) -> object_store::path::Path {
+        self.for_key(prefix.as_str()).absolute_url(prefix)
+    }
+
+    fn store_url(&self) -> url::Url {
+        self.data.store_url()
+    }
+
+    fn stream_store_url(&self, stream_name: &str) -> url::Url {
+        self.data.stream_store_url(stream_name)
+    }
+
+    fn get_bucket_name(&self) -> String {
+        self.data.get_bucket_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use bytes::Bytes;
+    use relative_path::RelativePathBuf;
+
+    use super::{is_metadata, MetadataRouter, Store};
+    use crate::storage::{
+        localfs::LocalFS, ObjectStorage, MANIFEST_FILE, PARSEABLE_METADATA_FILE_NAME,
+        PARSEABLE_ROOT_DIRECTORY, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    };
+
+    fn temp_store(name: &str) -> (PathBuf, Store) {
+        let root = std::env::temp_dir().join(format!("parseable-{name}-{}", ulid::Ulid::new()));
+        (root.clone(), Arc::new(LocalFS::new(root)))
+    }
+
+    #[actix_web::test]
+    async fn metadata_and_data_are_stored_apart() {
+        let (data_root, data) = temp_store("data");
+        let (metadata_root, metadata) = temp_store("metadata");
+        let router = MetadataRouter::new(data, metadata);
+
+        let date = "date=2024-05-01";
+        let parquet = "date=2024-05-01/hour=10/minute=00/host.data.parquet";
+        let staged = std::env::temp_dir().join(format!("staged-{}.parquet", ulid::Ulid::new()));
+        std::fs::write(&staged, b"PAR1").unwrap();
+        let objects = [
+            RelativePathBuf::from_iter([PARSEABLE_ROOT_DIRECTORY, PARSEABLE_METADATA_FILE_NAME]),
+            RelativePathBuf::from_iter(["app", STREAM_ROOT_DIRECTORY, STREAM_METADATA_FILE_NAME]),
+            RelativePathBuf::from_iter(["app", date, MANIFEST_FILE]),
+        ];
+        for path in &objects {
+            router
+                .put_object(path, Bytes::from_static(b"{}"))
+                .await
+                .unwrap();
+        }
+        router
+            .upload_file(&format!("app/{parquet}"), &staged)
+            .await
+            .unwrap();
+
+        for path in &objects {
+            assert!(metadata_root.join(path.as_str()).exists());
+            assert!(!data_root.join(path.as_str()).exists());
+        }
+        assert!(data_root.join("app").join(parquet).exists());
+        assert!(!metadata_root.join("app").join(parquet).exists());
+
+        let streams: Vec<String> = router
+            .list_streams()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|stream| stream.name)
+            .collect();
+        assert_eq!(streams, ["app"]);
+        // dates of manifests and of parquet files are listed once
+        assert_eq!(
+            router.list_dates("app").await.unwrap(),
+            [STREAM_ROOT_DIRECTORY, date]
+        );
+        assert!(router.get_object(&objects[2]).await.is_ok());
+
+        router.delete_stream("app").await.unwrap();
+        assert!(!data_root.join("app").exists());
+        assert!(!metadata_root.join("app").exists());
+
+        std::fs::remove_file(staged).unwrap();
+        for root in [data_root, metadata_root] {
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_listed_metadata_is_routed_to_metadata_store() {
+        for key in [
+            ".parseable/.parseable.json",
+            "app/.stream/.stream.json",
+            "app/.stream/.ingestor.01H.stream.json",
+            "app/.stream/.schema",
+            "app/.stream/schema_history/9f2c.json",
+            "app/date=2024-05-01/manifest.json",
+        ] {
+            assert!(is_metadata(key), "{key}");
+        }
+        for key in [
+            "app/date=2024-05-01/hour=10/minute=00/host.data.parquet",
+            ".trash/app/date=2024-05-01/hour=10/minute=00/host.data.parquet",
+            "app/.stream/.alert.json",
+            ".users/admin/dashboards/1.json",
+            "ingestor.01H.json",
+        ] {
+            assert!(!is_metadata(key), "{key}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn stream_without_data_is_deleted() {
+        let (data_root, data) = temp_store("data");
+        let (metadata_root, metadata) = temp_store("metadata");
+        std::fs::create_dir_all(&data_root).unwrap();
+        let router = MetadataRouter::new(data, metadata);
+        let path =
+            RelativePathBuf::from_iter(["app", STREAM_ROOT_DIRECTORY, STREAM_METADATA_FILE_NAME]);
+        router
+            .put_object(&path, Bytes::from_static(b"{}"))
+            .await
+            .unwrap();
+
+        // the data store holds nothing of the stream and answers not found
+        router.delete_stream("app").await.unwrap();
+        assert!(!metadata_root.join("app").exists());
+
+        for root in [data_root, metadata_root] {
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+}
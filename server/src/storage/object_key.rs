@@ -0,0 +1,112 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::path::{Path, PathBuf};
+
+use object_store::path::Path as StorePath;
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// Relative path which can't be used as the key of an object
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidKey {
+    #[error("object key is empty")]
+    Empty,
+    #[error("object key {0} is absolute")]
+    Absolute(String),
+    #[error("object key {0} leaves the root of the store")]
+    Traversal(String),
+}
+
+/// Key of an object, validated to stay under the root of the store it is used with.
+/// Keys are built from stream names and other request input, so `..` parts are rejected
+/// rather than resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectKey(RelativePathBuf);
+
+impl ObjectKey {
+    /// Validates `path`, empty and `.` parts are dropped and `\` is read as a separator
+    pub fn new(path: &RelativePath) -> Result<Self, InvalidKey> {
+        let key = path.as_str();
+        if key.starts_with(['/', '\\']) {
+            return Err(InvalidKey::Absolute(key.to_owned()));
+        }
+        let mut normalized = RelativePathBuf::new();
+        for part in key.split(['/', '\\']) {
+            match part {
+                "" | "." => {}
+                ".." => return Err(InvalidKey::Traversal(key.to_owned())),
+                part => normalized.push(part),
+            }
+        }
+        if normalized.as_str().is_empty() {
+            return Err(InvalidKey::Empty);
+        }
+        Ok(Self(normalized))
+    }
+
+    pub fn to_store_path(&self) -> StorePath {
+        StorePath::from_iter(self.0.iter())
+    }
+
+    /// Path of the object in a local directory store rooted at `root`
+    pub fn to_path(&self, root: &Path) -> PathBuf {
+        self.0.to_path(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use relative_path::RelativePath;
+
+    use super::{InvalidKey, ObjectKey};
+
+    #[test]
+    fn unsafe_keys_are_rejected() {
+        let key = |path: &str| ObjectKey::new(RelativePath::new(path));
+
+        assert_eq!(
+            key("app/../../etc/passwd"),
+            Err(InvalidKey::Traversal("app/../../etc/passwd".to_string()))
+        );
+        assert_eq!(key(".."), Err(InvalidKey::Traversal("..".to_string())));
+        assert_eq!(
+            key("/app/.stream"),
+            Err(InvalidKey::Absolute("/app/.stream".to_string()))
+        );
+        assert_eq!(key("\\app"), Err(InvalidKey::Absolute("\\app".to_string())));
+        assert_eq!(key(""), Err(InvalidKey::Empty));
+        assert_eq!(key("./"), Err(InvalidKey::Empty));
+    }
+
+    #[test]
+    fn separators_are_normalized() {
+        let key =
+            ObjectKey::new(RelativePath::new("app//date=2024-01-01\\./manifest.json")).unwrap();
+
+        assert_eq!(
+            key.to_store_path().as_ref(),
+            "app/date=2024-01-01/manifest.json"
+        );
+        assert_eq!(
+            key.to_path(Path::new("/data")),
+            Path::new("/data/app/date=2024-01-01/manifest.json")
+        );
+    }
+}
@@ -17,14 +17,16 @@
  */
 
 use super::{
-    retention::Retention, staging::convert_disk_files_to_parquet, LogStream, ObjectStorageError,
-    ObjectStoreFormat, Permisssion, StorageDir, StorageMetadata,
+    key_naming, overwrite, provenance, retention::Retention, sqs::SqsQueue,
+    staging::convert_disk_files_to_parquet, upload_backlog::UPLOAD_BACKLOG, LogStream,
+    ObjectStorageError, ObjectStoreFormat, Permisssion, StorageDir, StorageMetadata,
 };
 use super::{
     ALERT_FILE_NAME, MANIFEST_FILE, PARSEABLE_METADATA_FILE_NAME, PARSEABLE_ROOT_DIRECTORY,
-    SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    SCHEMA_FILE_NAME, SCHEMA_HISTORY_DIRECTORY, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
 };
 
+use crate::event::STREAM_WRITERS;
 use crate::handlers::http::modal::ingest_server::INGESTOR_META;
 use crate::handlers::http::users::{DASHBOARDS_DIR, FILTER_DIR, USERS_ROOT_DIR};
 use crate::metrics::{LIFETIME_EVENTS_STORAGE_SIZE, STORAGE_SIZE_TODAY};
@@ -43,25 +45,58 @@ use actix_web_prometheus::PrometheusMetrics;
 use arrow_schema::Schema;
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use datafusion::{datasource::listing::ListingTableUrl, execution::runtime_env::RuntimeConfig};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use itertools::Itertools;
+use object_store::ObjectStore;
+use once_cell::sync::Lazy;
+use parquet::file::{
+    footer::{decode_footer, decode_metadata},
+    metadata::ParquetMetaData,
+    FOOTER_SIZE,
+};
 use relative_path::RelativePath;
 use relative_path::RelativePathBuf;
 use serde_json::Value;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
     path::Path,
     sync::Arc,
     time::{Duration, Instant},
 };
 
+// held while staged data is converted and uploaded, so the flush loop and forced
+// flushes never convert or upload the same files
+pub static SYNC_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// Listed object with its size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMetaInfo {
+    pub path: RelativePathBuf,
+    pub size: u64,
+}
+
 pub trait ObjectStorageProvider: StorageMetrics + std::fmt::Debug {
     fn get_datafusion_runtime(&self) -> RuntimeConfig;
     fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send>;
+    /// Store of the same backend at another location, a bucket for S3 or a directory for drive
+    fn get_object_store_at(&self, location: &str) -> Arc<dyn ObjectStorage + Send>;
+    /// Client of the underlying object store, for operations `ObjectStorage` has no call for
+    fn get_raw_store(&self) -> Result<Arc<dyn ObjectStore>, ObjectStorageError>;
     fn get_endpoint(&self) -> String;
     fn register_store_metrics(&self, handler: &PrometheusMetrics);
+    /// Queue of the notifications for objects created in the store, if configured
+    fn get_notification_queue(&self) -> Option<SqsQueue> {
+        None
+    }
+    /// Checks clients of the store can be created, before any is needed
+    fn validate_config(&self) -> Result<(), ObjectStorageError> {
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -73,18 +108,143 @@ pub trait ObjectStorage: Sync + 'static {
         base_path: Option<&RelativePath>,
         filter_fun: Box<dyn Fn(String) -> bool + Send>,
     ) -> Result<Vec<Bytes>, ObjectStorageError>;
+    /// Fetches many objects at once, results are in the same order as `paths`.
+    /// Backends that can fetch concurrently should override this.
+    async fn get_objects_by_path(
+        &self,
+        paths: &[&RelativePath],
+    ) -> Vec<Result<Bytes, ObjectStorageError>> {
+        let mut res = Vec::with_capacity(paths.len());
+        for path in paths {
+            res.push(self.get_object(path).await);
+        }
+        res
+    }
+    /// Fetches the last `len` bytes of an object with a single ranged request.
+    /// The whole object is returned if it is shorter than `len`.
+    async fn get_object_suffix(
+        &self,
+        path: &RelativePath,
+        len: usize,
+    ) -> Result<Bytes, ObjectStorageError>;
+    /// Parses metadata of a parquet object from its footer. The footer length is read
+    /// first and then the footer itself, so the data pages are never downloaded.
+    async fn get_parquet_metadata(
+        &self,
+        path: &RelativePath,
+    ) -> Result<ParquetMetaData, ObjectStorageError> {
+        let tail = self.get_object_suffix(path, FOOTER_SIZE).await?;
+        let footer: [u8; FOOTER_SIZE] = tail.as_ref().try_into().map_err(|_| {
+            ObjectStorageError::Custom(format!("{path} is too small to be a parquet file"))
+        })?;
+        let metadata_len = decode_footer(&footer)
+            .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+
+        let tail = self
+            .get_object_suffix(path, metadata_len + FOOTER_SIZE)
+            .await?;
+        if tail.len() < metadata_len + FOOTER_SIZE {
+            return Err(ObjectStorageError::Custom(format!(
+                "{path} is smaller than the metadata length in its footer"
+            )));
+        }
+        decode_metadata(&tail[..metadata_len])
+            .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))
+    }
+    /// Custom key value metadata of a parquet object, such as its provenance
+    async fn get_parquet_key_value_metadata(
+        &self,
+        path: &RelativePath,
+    ) -> Result<BTreeMap<String, String>, ObjectStorageError> {
+        let metadata = self.get_parquet_metadata(path).await?;
+        Ok(provenance::custom_metadata(metadata.file_metadata()))
+    }
+    /// Checksum of an object kept by the store, read with a head request so the
+    /// object isn't downloaded. Changes whenever the object is written.
+    ///
+    /// For S3 this is the ETag without quotes. It is the hex MD5 of the content for
+    /// single part uploads and `<hex MD5 of the part MD5s>-<number of parts>` for
+    /// multipart uploads, which depends on the part size and can't be compared with
+    /// the MD5 of the content. For drive it is derived from the modification time and size.
+    async fn object_checksum(&self, path: &RelativePath) -> Result<String, ObjectStorageError>;
+    /// Whether an object exists at `path`, read with a head request
+    async fn exists(&self, path: &RelativePath) -> Result<bool, ObjectStorageError>;
     async fn put_object(
         &self,
         path: &RelativePath,
         resource: Bytes,
     ) -> Result<(), ObjectStorageError>;
+    /// Puts the object only if no object exists at `path`, failing with
+    /// `ObjectStorageError::AlreadyExists` otherwise. Concurrent callers see at most one success.
+    async fn put_object_if_not_exists(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError>;
     async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError>;
+    /// Moves the objects under `from` to the same keys under `to`
+    async fn move_prefix(
+        &self,
+        from: &RelativePath,
+        to: &RelativePath,
+    ) -> Result<(), ObjectStorageError>;
     async fn check(&self) -> Result<(), ObjectStorageError>;
     async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError>;
     async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError>;
     async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError>;
     async fn list_dirs(&self) -> Result<Vec<String>, ObjectStorageError>;
     async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError>;
+    /// Prefixes of the partitions of a stream covering `start..=end`,
+    /// filtered to the dates that exist in storage with a single listing.
+    /// `start` and `end` are wall clock times in the partition zone of the stream.
+    async fn prefixes_for_range(
+        &self,
+        stream_name: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<String>, ObjectStorageError> {
+        let existing: HashSet<String> = self
+            .list_dates(stream_name)
+            .await?
+            .into_iter()
+            .map(|date| date.trim_end_matches('/').to_owned())
+            .collect();
+
+        let granularity = STREAM_INFO
+            .get_partition_granularity(stream_name)
+            .unwrap_or_default();
+        let prefixes = key_naming::range_prefixes(
+            key_naming::strategy(),
+            granularity,
+            start.naive_utc(),
+            end.naive_utc(),
+        );
+        Ok(prefixes
+            .into_iter()
+            .filter(|prefix| {
+                prefix
+                    .split('/')
+                    .next()
+                    .is_some_and(|date| existing.contains(date))
+            })
+            .collect())
+    }
+    /// Lists all parquet files under a stream with their size, across all partitions.
+    /// Files are streamed as they are listed, a stream can hold any number of files.
+    fn list_objects_meta(
+        &self,
+        stream_name: &str,
+    ) -> BoxStream<'_, Result<ObjectMetaInfo, ObjectStorageError>>;
+    /// Lists paths of all parquet files under a stream, see `list_objects_meta`
+    fn list_parquet_files(
+        &self,
+        stream_name: &str,
+    ) -> BoxStream<'_, Result<RelativePathBuf, ObjectStorageError>> {
+        self.list_objects_meta(stream_name)
+            .map_ok(|meta| meta.path)
+            .boxed()
+    }
+    /// Uploads a staged data file, an existing object at `key` is handled by the overwrite policy
     async fn upload_file(&self, key: &str, path: &Path) -> Result<(), ObjectStorageError>;
     async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError>;
     async fn get_ingestor_meta_file_paths(
@@ -112,6 +272,10 @@ pub trait ObjectStorage: Sync + 'static {
     fn query_prefixes(&self, prefixes: Vec<String>) -> Vec<ListingTableUrl>;
     fn absolute_url(&self, prefix: &RelativePath) -> object_store::path::Path;
     fn store_url(&self) -> url::Url;
+    /// Url of the store holding the data of `stream_name`
+    fn stream_store_url(&self, _stream_name: &str) -> url::Url {
+        self.store_url()
+    }
 
     async fn put_schema(
         &self,
@@ -131,6 +295,7 @@ pub trait ObjectStorage: Sync + 'static {
         time_partition_limit: &str,
         custom_partition: &str,
         static_schema_flag: &str,
+        partition_time_zone: Option<Tz>,
         schema: Arc<Schema>,
     ) -> Result<(), ObjectStorageError> {
         let mut format = ObjectStoreFormat::default();
@@ -157,6 +322,7 @@ pub trait ObjectStorage: Sync + 'static {
         } else {
             format.static_schema_flag = Some(static_schema_flag.to_string());
         }
+        format.partition_time_zone = partition_time_zone.map(|zone| zone.name().to_string());
         let format_json = to_bytes(&format);
         self.put_object(&schema_path(stream_name), to_bytes(&schema))
             .await?;
@@ -208,6 +374,41 @@ pub trait ObjectStorage: Sync + 'static {
         self.put_object(&path, to_bytes(&stream_metadata)).await
     }
 
+    /// Keeps `schema` in the schema history of the stream. Objects are named by the hash
+    /// of the schema, so storing a schema again, or from several nodes at once, is a no-op.
+    async fn put_schema_history(
+        &self,
+        stream_name: &str,
+        schema: &Schema,
+    ) -> Result<(), ObjectStorageError> {
+        let schema = to_bytes(schema);
+        let path = schema_history_path(stream_name, &schema);
+        match self.put_object_if_not_exists(&path, schema).await {
+            Ok(()) | Err(ObjectStorageError::AlreadyExists(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Every schema the stream had, in no particular order
+    async fn get_schema_history(
+        &self,
+        stream_name: &str,
+    ) -> Result<Vec<Schema>, ObjectStorageError> {
+        let path = RelativePathBuf::from_iter([
+            stream_name,
+            STREAM_ROOT_DIRECTORY,
+            SCHEMA_HISTORY_DIRECTORY,
+        ]);
+        self.get_objects(
+            Some(&path),
+            Box::new(|file_name| file_name.ends_with(".json")),
+        )
+        .await?
+        .iter()
+        .map(|schema| serde_json::from_slice(schema).map_err(ObjectStorageError::from))
+        .collect()
+    }
+
     async fn put_metadata(
         &self,
         parseable_metadata: &StorageMetadata,
@@ -432,16 +633,31 @@ pub trait ObjectStorage: Sync + 'static {
     }
 
     async fn sync(&self) -> Result<(), ObjectStorageError> {
+        self.sync_streams(&STREAM_INFO.list_streams(), false)
+            .await
+            .map(|_| ())
+    }
+
+    /// Converts and uploads the staged data of `streams`, returning the number of files
+    /// uploaded. With `force` the files of the current minute are flushed too, writers
+    /// of a stream are closed and held off while its files are converted.
+    async fn sync_streams(
+        &self,
+        streams: &[String],
+        force: bool,
+    ) -> Result<usize, ObjectStorageError> {
+        let _guard = SYNC_LOCK.lock().await;
         if !Path::new(&CONFIG.staging_dir()).exists() {
-            return Ok(());
+            return Ok(0);
         }
 
-        let streams = STREAM_INFO.list_streams();
-
+        let mut uploaded = 0;
         let cache_manager = LocalCacheManager::global();
         let mut cache_updates: HashMap<&String, Vec<_>> = HashMap::new();
 
-        for stream in &streams {
+        for stream in streams {
+            // number of files uploaded per date partition, used for flush markers
+            let mut partition_files: HashMap<String, usize> = HashMap::new();
             let cache_enabled = STREAM_INFO
                 .cache_enabled(stream)
                 .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
@@ -451,13 +667,32 @@ pub trait ObjectStorage: Sync + 'static {
             let custom_partition = STREAM_INFO
                 .get_custom_partition(stream)
                 .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+            let sort_order = STREAM_INFO
+                .get_sort_order(stream)
+                .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+            let statistics_level = STREAM_INFO
+                .get_statistics_level(stream)
+                .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+            let partition_granularity = STREAM_INFO
+                .get_partition_granularity(stream)
+                .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
             let dir = StorageDir::new(stream);
-            let schema = convert_disk_files_to_parquet(
-                stream,
-                &dir,
-                time_partition,
-                custom_partition.clone(),
-            )
+            let convert = || {
+                convert_disk_files_to_parquet(
+                    stream,
+                    &dir,
+                    time_partition,
+                    custom_partition.clone(),
+                    &sort_order,
+                    statistics_level,
+                    force,
+                )
+            };
+            let schema = if force {
+                STREAM_WRITERS.close_stream_while(stream, convert)
+            } else {
+                convert()
+            }
             .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
 
             if let Some(schema) = schema {
@@ -473,6 +708,7 @@ pub trait ObjectStorage: Sync + 'static {
             parquet_files.iter().for_each(|file| {
                 compressed_size += file.metadata().map_or(0, |meta| meta.len());
             });
+            UPLOAD_BACKLOG.set_pending(stream, parquet_files.len() as u64, compressed_size);
             STORAGE_SIZE
                 .with_label_values(&["data", stream, "parquet"])
                 .add(compressed_size as i64);
@@ -489,25 +725,36 @@ pub trait ObjectStorage: Sync + 'static {
                     .expect("only parquet files are returned by iterator")
                     .to_str()
                     .expect("filename is valid string");
-                let mut file_suffix = str::replacen(filename, ".", "/", 3);
-
-                let custom_partition_clone = custom_partition.clone();
-                if custom_partition_clone.is_some() {
-                    let custom_partition_fields = custom_partition_clone.unwrap();
-                    let custom_partition_list =
-                        custom_partition_fields.split(',').collect::<Vec<&str>>();
-                    file_suffix =
-                        str::replacen(filename, ".", "/", 3 + custom_partition_list.len());
+                // date, hour and minute followed by custom partitions
+                let partitions = 3 + custom_partition
+                    .as_ref()
+                    .map_or(0, |fields| fields.split(',').count());
+                let stream_relative_path = key_naming::strategy().object_key(
+                    stream,
+                    filename,
+                    partitions,
+                    partition_granularity,
+                );
+                let file_size = file.metadata().map_or(0, |meta| meta.len());
+                let stream_relative_path = overwrite::upload_data_file(
+                    self,
+                    CONFIG.parseable.object_overwrite_policy,
+                    &stream_relative_path,
+                    &file,
+                )
+                .await?;
+                UPLOAD_BACKLOG.uploaded(stream, file_size);
+                uploaded += 1;
+                if let Some(partition) = stream_relative_path.split('/').nth(1) {
+                    *partition_files.entry(partition.to_owned()).or_default() += 1;
                 }
-                let stream_relative_path = format!("{stream}/{file_suffix}");
-                self.upload_file(&stream_relative_path, &file).await?;
                 let absolute_path = self
                     .absolute_url(RelativePath::from_path(&stream_relative_path).unwrap())
                     .to_string();
                 let store = CONFIG.storage().get_object_store();
                 let manifest =
                     catalog::create_from_parquet_file(absolute_path.clone(), &file).unwrap();
-                catalog::update_snapshot(store, stream, manifest).await?;
+                catalog::batch::update(store, stream, manifest).await?;
                 let stats = stats::get_current_stats(stream, "json");
                 if let Some(stats) = stats {
                     if let Err(e) = self.put_stats(stream, &stats).await {
@@ -523,6 +770,14 @@ pub trait ObjectStorage: Sync + 'static {
                     let _ = fs::remove_file(file);
                 }
             }
+
+            if force {
+                catalog::batch::flush(CONFIG.storage().get_object_store(), stream).await?;
+            }
+
+            if let Some(marker) = &CONFIG.parseable.flush_marker {
+                put_flush_markers(self, stream, marker, partition_files).await?;
+            }
         }
 
         if let Some(manager) = cache_manager {
@@ -543,7 +798,7 @@ pub trait ObjectStorage: Sync + 'static {
             });
         }
 
-        Ok(())
+        Ok(uploaded)
     }
 
     // pick a better name
@@ -556,10 +811,36 @@ pub async fn commit_schema_to_storage(
 ) -> Result<(), ObjectStorageError> {
     let storage = CONFIG.storage().get_object_store();
     let stream_schema = storage.get_schema(stream_name).await?;
-    let new_schema = Schema::try_merge(vec![schema, stream_schema]).unwrap();
+    let new_schema = Schema::try_merge(vec![schema, stream_schema.clone()])
+        .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+    if new_schema == stream_schema {
+        return Ok(());
+    }
+    // the history is written first, a schema in use is never missing from it. The
+    // schema replaced is kept as well for streams created before the history existed
+    storage
+        .put_schema_history(stream_name, &stream_schema)
+        .await?;
+    storage.put_schema_history(stream_name, &new_schema).await?;
     storage.put_schema(stream_name, &new_schema).await
 }
 
+/// Rejects reading an object larger than `limit` before its body is buffered in memory
+pub(crate) fn check_object_size(
+    path: &str,
+    size: usize,
+    limit: Option<usize>,
+) -> Result<(), ObjectStorageError> {
+    match limit {
+        Some(limit) if size > limit => Err(ObjectStorageError::ObjectTooLarge {
+            path: path.to_owned(),
+            size,
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
 #[inline(always)]
 pub fn to_bytes(any: &(impl ?Sized + serde::Serialize)) -> Bytes {
     serde_json::to_vec(any)
@@ -568,7 +849,7 @@ pub fn to_bytes(any: &(impl ?Sized + serde::Serialize)) -> Bytes {
 }
 
 #[inline(always)]
-fn schema_path(stream_name: &str) -> RelativePathBuf {
+pub fn schema_path(stream_name: &str) -> RelativePathBuf {
     match CONFIG.parseable.mode {
         Mode::Ingest => {
             let file_name = format!(
@@ -585,6 +866,17 @@ fn schema_path(stream_name: &str) -> RelativePathBuf {
     }
 }
 
+/// Path of `schema` in the schema history of the stream, named by the hash of its bytes
+pub fn schema_history_path(stream_name: &str, schema: &[u8]) -> RelativePathBuf {
+    let hash = xxhash_rust::xxh3::xxh3_64(schema);
+    RelativePathBuf::from_iter([
+        stream_name,
+        STREAM_ROOT_DIRECTORY,
+        SCHEMA_HISTORY_DIRECTORY,
+        &format!("{hash:x}.json"),
+    ])
+}
+
 #[inline(always)]
 pub fn stream_json_path(stream_name: &str) -> RelativePathBuf {
     match &CONFIG.parseable.mode {
@@ -604,6 +896,25 @@ pub fn stream_json_path(stream_name: &str) -> RelativePathBuf {
     }
 }
 
+/// Writes the marker object `marker` under each date partition of `stream` files were
+/// uploaded to, with the number of files uploaded by the flush
+async fn put_flush_markers(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream: &str,
+    marker: &str,
+    partition_files: HashMap<String, usize>,
+) -> Result<(), ObjectStorageError> {
+    for (partition, file_count) in partition_files {
+        let path = RelativePathBuf::from_iter([stream, &partition, marker]);
+        let marker = serde_json::json!({
+            "flushed_at": Utc::now().to_rfc3339(),
+            "file_count": file_count,
+        });
+        storage.put_object(&path, to_bytes(&marker)).await?;
+    }
+    Ok(())
+}
+
 /// if dashboard_id is an empty str it should not append it to the rel path
 #[inline(always)]
 pub fn dashboard_path(user_id: &str, dashboard_file_name: &str) -> RelativePathBuf {
@@ -662,3 +973,80 @@ pub fn ingestor_metadata_path(id: Option<&str>) -> RelativePathBuf {
         &format!("ingestor.{}.json", INGESTOR_META.get_ingestor_id()),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+    use relative_path::RelativePath;
+
+    use super::{check_object_size, put_flush_markers};
+    use crate::storage::{localfs::LocalFS, ObjectStorage, ObjectStorageError};
+
+    #[actix_web::test]
+    async fn batched_get_reports_missing_objects_in_place() {
+        let root = std::env::temp_dir().join(format!("parseable-batched-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        for name in ["a", "c"] {
+            let path = format!("app/{name}.json");
+            storage
+                .put_object(RelativePath::new(&path), Bytes::from(name))
+                .await
+                .unwrap();
+        }
+
+        let paths = ["app/c.json", "app/b.json", "app/a.json"].map(RelativePath::new);
+        let objects = storage.get_objects_by_path(&paths).await;
+        let no_objects = storage.get_objects_by_path(&[]).await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // a missing object fails on its own, the others are still returned in order
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects[0].as_ref().unwrap(), &Bytes::from("c"));
+        assert!(matches!(objects[1], Err(ObjectStorageError::NoSuchKey(_))));
+        assert_eq!(objects[2].as_ref().unwrap(), &Bytes::from("a"));
+        assert!(no_objects.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn flush_markers_are_written_only_to_partitions_with_uploads() {
+        let root = std::env::temp_dir().join(format!("parseable-markers-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+
+        let partition_files = HashMap::from([
+            ("date=2024-01-01".to_owned(), 2),
+            ("date=2024-01-02".to_owned(), 1),
+        ]);
+        put_flush_markers(&storage, "app", "_SUCCESS", partition_files)
+            .await
+            .unwrap();
+        // a flush that uploaded nothing leaves no marker
+        put_flush_markers(&storage, "idle", "_SUCCESS", HashMap::new())
+            .await
+            .unwrap();
+
+        let mut counts = Vec::new();
+        for date in ["2024-01-01", "2024-01-02"] {
+            let path = format!("app/date={date}/_SUCCESS");
+            let marker = storage.get_object(RelativePath::new(&path)).await;
+            let marker: serde_json::Value = serde_json::from_slice(&marker.unwrap()).unwrap();
+            counts.push(marker["file_count"].clone());
+        }
+        let idle_exists = root.join("idle").exists();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(counts, [2, 1]);
+        assert!(!idle_exists);
+    }
+
+    #[test]
+    fn object_size_limit() {
+        assert!(check_object_size("stream/big", 2048, None).is_ok());
+        assert!(check_object_size("stream/big", 1024, Some(1024)).is_ok());
+        assert!(matches!(
+            check_object_size("stream/big", 1025, Some(1024)),
+            Err(ObjectStorageError::ObjectTooLarge { size: 1025, .. })
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::future::Future;
+use std::path::Path;
+
+use object_store::PutMode;
+
+use super::{ObjectStorage, ObjectStorageError};
+
+// uploads of a data file retried under a new name before giving up
+const MAX_RENAMES: usize = 3;
+
+/// What an upload of a data file does when an object already exists at its key,
+/// e.g. when a staged file name is reused after a restart. Metadata objects are
+/// read, modified and written back, they are always overwritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    #[default]
+    Overwrite,
+    /// Keeps the existing object, the upload succeeds without writing
+    SkipIfExists,
+    /// Fails the upload with `ObjectStorageError::AlreadyExists`
+    ErrorIfExists,
+    /// Fails the upload like `ErrorIfExists`, flushes then upload the file under a new
+    /// name. Keeps both files when several writers stage files of the same name.
+    RenameIfExists,
+}
+
+impl OverwritePolicy {
+    /// Policy for an object written at `key`, only data files are kept from being overwritten
+    pub fn for_key(self, key: &str) -> OverwritePolicy {
+        if key.ends_with(".parquet") {
+            self
+        } else {
+            OverwritePolicy::Overwrite
+        }
+    }
+
+    /// Whether the upload to `key` goes ahead. `exists` is only called when
+    /// the policy doesn't overwrite.
+    pub async fn allows_upload<F, Fut>(
+        self,
+        key: &str,
+        exists: F,
+    ) -> Result<bool, ObjectStorageError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<bool, ObjectStorageError>>,
+    {
+        if self == OverwritePolicy::Overwrite || !exists().await? {
+            return Ok(true);
+        }
+        self.on_existing(key)
+    }
+
+    /// Whether the upload to `key` goes ahead once an object is found at `key`
+    pub fn on_existing(self, key: &str) -> Result<bool, ObjectStorageError> {
+        match self {
+            OverwritePolicy::Overwrite => Ok(true),
+            OverwritePolicy::SkipIfExists => {
+                log::warn!("object {key} already exists, skipping upload");
+                Ok(false)
+            }
+            _ => Err(ObjectStorageError::AlreadyExists(key.to_string())),
+        }
+    }
+
+    /// Mode of single request uploads, writers racing for a key are only told
+    /// apart by a conditional put
+    pub fn put_mode(self) -> PutMode {
+        match self {
+            OverwritePolicy::Overwrite => PutMode::Overwrite,
+            _ => PutMode::Create,
+        }
+    }
+}
+
+/// `key` with a unique name, the name is kept and a ulid is added before the extension
+pub fn renamed_key(key: &str) -> String {
+    let (name, extension) = key.rsplit_once('.').unwrap_or((key, "parquet"));
+    format!("{name}.{}.{extension}", ulid::Ulid::new())
+}
+
+/// Uploads a staged data file and returns the key it was uploaded to. With
+/// `OverwritePolicy::RenameIfExists` a key already holding an object is retried
+/// under a new name, see `renamed_key`.
+pub async fn upload_data_file(
+    storage: &(impl ObjectStorage + ?Sized),
+    policy: OverwritePolicy,
+    key: &str,
+    path: &Path,
+) -> Result<String, ObjectStorageError> {
+    let mut key = key.to_owned();
+    for _ in 0..MAX_RENAMES {
+        match storage.upload_file(&key, path).await {
+            Err(ObjectStorageError::AlreadyExists(_))
+                if policy == OverwritePolicy::RenameIfExists =>
+            {
+                let renamed = renamed_key(&key);
+                log::warn!("object {key} already exists, uploading as {renamed}");
+                key = renamed;
+            }
+            res => return res.map(|_| key),
+        }
+    }
+    storage.upload_file(&key, path).await.map(|_| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+    use relative_path::RelativePath;
+
+    use super::{upload_data_file, OverwritePolicy};
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    #[actix_web::test]
+    async fn overwrites_make_no_existence_check() {
+        let key = "app/date=2024-01-01/hour=10/minute=05/host.data.parquet";
+        let upload = OverwritePolicy::Overwrite
+            .allows_upload(key, || async { panic!("existence checked") })
+            .await
+            .unwrap();
+        assert!(upload);
+
+        // metadata is always written back
+        let policy = OverwritePolicy::ErrorIfExists;
+        assert_eq!(policy.for_key(key), policy);
+        assert_eq!(
+            policy.for_key("app/.stream/stream.json"),
+            OverwritePolicy::Overwrite
+        );
+    }
+
+    #[actix_web::test]
+    async fn colliding_uploads_keep_both_files() {
+        let root = std::env::temp_dir().join(format!("parseable-overwrite-{}", ulid::Ulid::new()));
+        let policy = OverwritePolicy::RenameIfExists;
+        let storage = LocalFS::new(root.clone()).with_overwrite_policy(policy);
+        // two writers staging a file of the same name in the same flush window
+        let key = "app/date=2024-01-01/hour=10/minute=05/host.data.parquet";
+        std::fs::create_dir_all(root.join("staging")).unwrap();
+        let first = root.join("staging/first.parquet");
+        let second = root.join("staging/second.parquet");
+        std::fs::write(&first, "first").unwrap();
+        std::fs::write(&second, "second").unwrap();
+
+        let first_key = upload_data_file(&storage, policy, key, &first)
+            .await
+            .unwrap();
+        let second_key = upload_data_file(&storage, policy, key, &second)
+            .await
+            .unwrap();
+        let listed: Vec<_> = storage
+            .list_parquet_files("app")
+            .try_collect()
+            .await
+            .unwrap();
+        let contents = [
+            storage
+                .get_object(RelativePath::new(&first_key))
+                .await
+                .unwrap(),
+            storage
+                .get_object(RelativePath::new(&second_key))
+                .await
+                .unwrap(),
+        ];
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(first_key, key);
+        assert_ne!(second_key, key);
+        assert!(second_key.starts_with("app/date=2024-01-01/hour=10/minute=05/host.data."));
+        assert!(second_key.ends_with(".parquet"));
+        assert_eq!(contents, [Bytes::from("first"), Bytes::from("second")]);
+        // both files are listed for queries
+        assert_eq!(listed.len(), 2);
+    }
+}
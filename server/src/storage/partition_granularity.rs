@@ -0,0 +1,85 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Time partitions in the object keys of data files. Daily streams keep their files
+//! directly under the date partition, hourly streams under an hour partition of the date.
+//! Dates stay the top level partitions either way, so listing dates, retention and
+//! manifests don't depend on the granularity. Files written with minute partitions or
+//! before the granularity of a stream changed are still found under their date.
+
+use super::key_naming::PartitionComponent;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionGranularity {
+    /// `date=2024-01-01/hour=10/<file>`, for high volume streams
+    Hour,
+    /// `date=2024-01-01/<file>`
+    #[default]
+    Day,
+}
+
+impl PartitionGranularity {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether a component of the staged file name is part of the object key
+    pub fn keeps(self, component: &PartitionComponent) -> bool {
+        match component {
+            PartitionComponent::Date(_) | PartitionComponent::Custom(..) => true,
+            PartitionComponent::Hour(_) => self == Self::Hour,
+            PartitionComponent::Minute(_) => false,
+        }
+    }
+
+    /// Narrows a prefix such as `date=2024-01-01/hour=10/minute=05/` down to the
+    /// partitions of this granularity
+    pub fn truncate(self, hive_prefix: &str) -> String {
+        hive_prefix
+            .split_terminator('/')
+            .take_while(|component| {
+                PartitionComponent::from_hive(component)
+                    .is_some_and(|component| self.keeps(&component))
+            })
+            .map(|component| component.to_owned() + "/")
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartitionGranularity;
+
+    #[test]
+    fn prefixes_are_truncated_to_granularity() {
+        let prefix = "date=2024-01-01/hour=10/minute=05/";
+        assert_eq!(
+            PartitionGranularity::Hour.truncate(prefix),
+            "date=2024-01-01/hour=10/"
+        );
+        assert_eq!(
+            PartitionGranularity::Day.truncate(prefix),
+            "date=2024-01-01/"
+        );
+        assert_eq!(
+            PartitionGranularity::Hour.truncate("date=2024-01-01/"),
+            "date=2024-01-01/"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Provenance of parquet files, stamped into the key value metadata of the file footer
+//! so the node and server version which produced a file can be told from the file alone.
+
+use std::collections::BTreeMap;
+
+use parquet::file::metadata::{FileMetaData, KeyValue};
+
+use crate::{
+    about,
+    handlers::http::modal::ingest_server::INGESTOR_META,
+    option::{Mode, CONFIG},
+    utils::hostname_unchecked,
+};
+
+pub const NODE_KEY: &str = "parseable.node";
+pub const VERSION_KEY: &str = "parseable.version";
+pub const SOURCE_KEY: &str = "parseable.source";
+
+/// Key under which the arrow writer stores the arrow schema, not part of the provenance
+const ARROW_SCHEMA_KEY: &str = "ARROW:schema";
+
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    /// ingestor id in distributed mode, host name otherwise
+    pub node: String,
+    /// server version and commit
+    pub version: String,
+    /// how the data reached the file, e.g. `staging` for files converted from staging
+    pub source: &'static str,
+}
+
+impl Provenance {
+    /// Provenance of files converted from the staging files of this node
+    pub fn staging() -> Self {
+        let node = if CONFIG.parseable.mode == Mode::Ingest {
+            INGESTOR_META.get_ingestor_id()
+        } else {
+            hostname_unchecked()
+        };
+        let version = about::current();
+        Self {
+            node,
+            version: format!("{}+{}", version.released_version, version.commit_hash),
+            source: "staging",
+        }
+    }
+
+    pub fn key_value_metadata(&self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new(NODE_KEY.to_owned(), self.node.clone()),
+            KeyValue::new(VERSION_KEY.to_owned(), self.version.clone()),
+            KeyValue::new(SOURCE_KEY.to_owned(), self.source.to_owned()),
+        ]
+    }
+}
+
+/// Custom key value metadata of a parquet file, without the arrow schema
+pub fn custom_metadata(metadata: &FileMetaData) -> BTreeMap<String, String> {
+    metadata
+        .key_value_metadata()
+        .into_iter()
+        .flatten()
+        .filter(|kv| kv.key != ARROW_SCHEMA_KEY)
+        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+    use relative_path::RelativePath;
+
+    use super::{Provenance, NODE_KEY, SOURCE_KEY, VERSION_KEY};
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    #[actix_web::test]
+    async fn provenance_is_read_back_from_footer() {
+        let root = std::env::temp_dir().join(format!("parseable-provenance-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..10))],
+        )
+        .unwrap();
+        let provenance = Provenance {
+            node: "ingestor-1".to_owned(),
+            version: "1.2.0+abc123".to_owned(),
+            source: "staging",
+        };
+        let props = WriterProperties::builder()
+            .set_key_value_metadata(Some(provenance.key_value_metadata()))
+            .build();
+        let file = std::fs::File::create(root.join("app/data.parquet")).unwrap();
+        let mut writer = ArrowWriter::try_new(file, schema, Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let storage = LocalFS::new(root.clone());
+        let metadata = storage
+            .get_parquet_key_value_metadata(RelativePath::new("app/data.parquet"))
+            .await;
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let metadata = metadata.unwrap();
+        assert_eq!(metadata.len(), 3, "the arrow schema is left out");
+        assert_eq!(metadata[NODE_KEY], "ingestor-1");
+        assert_eq!(metadata[VERSION_KEY], "1.2.0+abc123");
+        assert_eq!(metadata[SOURCE_KEY], "staging");
+    }
+}
@@ -0,0 +1,425 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{
+    io,
+    ops::Range,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, FutureExt, Stream, StreamExt};
+use object_store::{
+    path::Path, GetOptions, GetResult, GetResultPayload, ListResult, MultipartId, ObjectMeta,
+    ObjectStore, PutOptions, PutResult, Result as ObjectStoreResult,
+};
+use prometheus::IntGauge;
+use tokio::{
+    io::AsyncWrite,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+use crate::metrics::storage::s3::{
+    REQUESTS_WAITING_FOR_PERMIT, REQUEST_PERMITS_IN_USE, REQUEST_PERMIT_WAITS,
+    REQUEST_PERMIT_WAIT_TIME,
+};
+
+/// Bounded number of concurrent object store requests of one kind
+#[derive(Debug)]
+pub struct RequestBudget {
+    name: &'static str,
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl RequestBudget {
+    pub fn new(name: &'static str, limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+        })
+    }
+
+    async fn acquire(self: &Arc<Self>) -> Permit {
+        let permit = match Arc::clone(&self.semaphore).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => self.wait_for_permit().await,
+        };
+        self.report();
+        Permit {
+            permit: Some(permit),
+            budget: Arc::clone(self),
+        }
+    }
+
+    /// Waits for a permit of a saturated budget, recording the wait so operators can tell
+    /// when the limit is the bottleneck
+    async fn wait_for_permit(&self) -> OwnedSemaphorePermit {
+        REQUEST_PERMIT_WAITS.with_label_values(&[self.name]).inc();
+        let _waiting = Waiting::new(REQUESTS_WAITING_FOR_PERMIT.with_label_values(&[self.name]));
+
+        let start = Instant::now();
+        let permit = Arc::clone(&self.semaphore)
+            .acquire_owned()
+            .await
+            .expect("request budget semaphore is never closed");
+        REQUEST_PERMIT_WAIT_TIME
+            .with_label_values(&[self.name])
+            .observe(start.elapsed().as_secs_f64());
+        permit
+    }
+
+    fn in_use(&self) -> usize {
+        self.limit - self.semaphore.available_permits()
+    }
+
+    fn report(&self) {
+        REQUEST_PERMITS_IN_USE
+            .with_label_values(&[self.name])
+            .set(self.in_use() as i64);
+    }
+}
+
+/// Counts a request as queued for a permit until dropped, also when the request is cancelled
+struct Waiting(IntGauge);
+
+impl Waiting {
+    fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Permit of a budget, released when the request or its response stream is dropped
+struct Permit {
+    permit: Option<OwnedSemaphorePermit>,
+    budget: Arc<RequestBudget>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.permit.take();
+        self.budget.report();
+    }
+}
+
+/// Limits concurrent requests to the inner store with separate budgets for reads and writes,
+/// so that a burst of queries can't hold back uploads of ingested data and vice versa
+#[derive(Debug)]
+pub struct ReadWriteLimitStore<T: ObjectStore> {
+    inner: T,
+    read: Arc<RequestBudget>,
+    write: Arc<RequestBudget>,
+}
+
+impl<T: ObjectStore> ReadWriteLimitStore<T> {
+    pub fn new(inner: T, read: Arc<RequestBudget>, write: Arc<RequestBudget>) -> Self {
+        Self { inner, read, write }
+    }
+}
+
+impl<T: ObjectStore> std::fmt::Display for ReadWriteLimitStore<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ReadWriteLimitStore({}, {}, {})",
+            self.read.limit, self.write.limit, self.inner
+        )
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for ReadWriteLimitStore<T> {
+    async fn put(&self, location: &Path, bytes: Bytes) -> ObjectStoreResult<PutResult> {
+        let _permit = self.write.acquire().await;
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: Bytes,
+        opts: PutOptions,
+    ) -> ObjectStoreResult<PutResult> {
+        let _permit = self.write.acquire().await;
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        let permit = self.write.acquire().await;
+        let (id, write) = self.inner.put_multipart(location).await?;
+        Ok((id, Box::new(PermitWrapper::new(write, permit))))
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> ObjectStoreResult<()> {
+        let _permit = self.write.acquire().await;
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get(&self, location: &Path) -> ObjectStoreResult<GetResult> {
+        let permit = self.read.acquire().await;
+        let res = self.inner.get(location).await?;
+        Ok(with_permit(res, permit))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> ObjectStoreResult<GetResult> {
+        let permit = self.read.acquire().await;
+        let res = self.inner.get_opts(location, options).await?;
+        Ok(with_permit(res, permit))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        let _permit = self.read.acquire().await;
+        self.inner.get_range(location, range).await
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> ObjectStoreResult<Vec<Bytes>> {
+        let _permit = self.read.acquire().await;
+        self.inner.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        let _permit = self.read.acquire().await;
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        let _permit = self.write.acquire().await;
+        self.inner.delete(location).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, ObjectStoreResult<Path>>,
+    ) -> BoxStream<'a, ObjectStoreResult<Path>> {
+        self.write
+            .acquire()
+            .map(move |permit| PermitWrapper::new(self.inner.delete_stream(locations), permit))
+            .into_stream()
+            .flatten()
+            .boxed()
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        let prefix = prefix.cloned();
+        self.read
+            .acquire()
+            .map(move |permit| PermitWrapper::new(self.inner.list(prefix.as_ref()), permit))
+            .into_stream()
+            .flatten()
+            .boxed()
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        let prefix = prefix.cloned();
+        let offset = offset.clone();
+        self.read
+            .acquire()
+            .map(move |permit| {
+                let list = self.inner.list_with_offset(prefix.as_ref(), &offset);
+                PermitWrapper::new(list, permit)
+            })
+            .into_stream()
+            .flatten()
+            .boxed()
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        let _permit = self.read.acquire().await;
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        let _permit = self.write.acquire().await;
+        self.inner.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        let _permit = self.write.acquire().await;
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        let _permit = self.write.acquire().await;
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        let _permit = self.write.acquire().await;
+        self.inner.rename_if_not_exists(from, to).await
+    }
+}
+
+/// Keeps the permit of a get until its body is consumed
+fn with_permit(res: GetResult, permit: Permit) -> GetResult {
+    let payload = match res.payload {
+        payload @ GetResultPayload::File(_, _) => payload,
+        GetResultPayload::Stream(stream) => {
+            GetResultPayload::Stream(PermitWrapper::new(stream, permit).boxed())
+        }
+    };
+    GetResult { payload, ..res }
+}
+
+struct PermitWrapper<T> {
+    inner: T,
+    _permit: Permit,
+}
+
+impl<T> PermitWrapper<T> {
+    fn new(inner: T, permit: Permit) -> Self {
+        Self {
+            inner,
+            _permit: permit,
+        }
+    }
+}
+
+impl<T: Stream + Unpin> Stream for PermitWrapper<T> {
+    type Item = T::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for PermitWrapper<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use tokio::time::timeout;
+
+    use crate::metrics::storage::s3::{REQUESTS_WAITING_FOR_PERMIT, REQUEST_PERMIT_WAITS};
+
+    use super::{ReadWriteLimitStore, RequestBudget};
+
+    #[actix_web::test]
+    async fn writes_proceed_while_reads_are_saturated() {
+        let store = ReadWriteLimitStore::new(
+            InMemory::new(),
+            RequestBudget::new("read", 1),
+            RequestBudget::new("write", 1),
+        );
+        let path = Path::from("app/date=2024-01-01/file.parquet");
+        store.put(&path, Bytes::from_static(b"data")).await.unwrap();
+
+        // the body of an unconsumed get holds the only read permit
+        let pending = store.get(&path).await.unwrap();
+        assert_eq!(store.read.in_use(), 1);
+
+        let read = timeout(Duration::from_millis(100), store.head(&path)).await;
+        assert!(read.is_err(), "reads should wait for a free permit");
+
+        let write = timeout(
+            Duration::from_secs(1),
+            store.put(&path, Bytes::from_static(b"more data")),
+        )
+        .await;
+        assert!(write.expect("writes are not limited by reads").is_ok());
+        assert_eq!(store.write.in_use(), 0);
+
+        drop(pending);
+        assert_eq!(store.read.in_use(), 0);
+        assert!(store.head(&path).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn saturated_budget_records_waiting_requests() {
+        // a budget name of its own, metrics are shared by the whole process
+        let name = "saturated_test";
+        let store = ReadWriteLimitStore::new(
+            InMemory::new(),
+            RequestBudget::new(name, 1),
+            RequestBudget::new("write", 1),
+        );
+        let path = Path::from("app/date=2024-01-01/file.parquet");
+        store.put(&path, Bytes::from_static(b"data")).await.unwrap();
+        let waits = REQUEST_PERMIT_WAITS.with_label_values(&[name]);
+        let waiting = REQUESTS_WAITING_FOR_PERMIT.with_label_values(&[name]);
+
+        // a free permit is taken without waiting
+        let pending = store.get(&path).await.unwrap();
+        assert_eq!(waits.get(), 0);
+
+        let queued = tokio::spawn(async move {
+            let res = store.head(&path).await;
+            (store, res)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(waits.get(), 1);
+        assert_eq!(waiting.get(), 1);
+
+        drop(pending);
+        let (store, res) = timeout(Duration::from_secs(1), queued)
+            .await
+            .expect("queued request gets the released permit")
+            .unwrap();
+        assert!(res.is_ok());
+        assert_eq!(waiting.get(), 0);
+        assert_eq!(store.read.in_use(), 0);
+    }
+}
@@ -30,6 +30,11 @@ use once_cell::sync::Lazy;
 
 use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
+use crate::storage::lock::{acquire_lock, release_lock};
+use crate::storage::{tiering, trash};
+
+const RETENTION_LOCK: &str = "retention";
+const RETENTION_LOCK_TTL: Duration = Duration::from_secs(5 * 60);
 
 type SchedulerHandle = thread::JoinHandle<()>;
 
@@ -53,6 +58,19 @@ pub fn init_scheduler() {
     let mut scheduler = AsyncScheduler::new();
     let func = move || async {
         //get retention every day at 12 am
+        // only one of the nodes sharing the storage runs retention
+        let lease = match acquire_lock(RETENTION_LOCK, RETENTION_LOCK_TTL).await {
+            Ok(Some(lease)) => lease,
+            Ok(None) => {
+                log::info!("retention is running on another node, skipping");
+                return;
+            }
+            Err(err) => {
+                log::warn!("failed to acquire retention lock due to {err:?}");
+                return;
+            }
+        };
+
         for stream in STREAM_INFO.list_streams() {
             let res = CONFIG
                 .storage()
@@ -65,14 +83,7 @@ pub fn init_scheduler() {
                     for Task { action, days, .. } in config.tasks.into_iter() {
                         match action {
                             Action::Delete => {
-                                let stream = stream.to_string();
-                                thread::spawn(move || {
-                                    let rt = tokio::runtime::Runtime::new().unwrap();
-                                    rt.block_on(async {
-                                        // Run the asynchronous delete action
-                                        action::delete(stream.clone(), u32::from(days)).await;
-                                    });
-                                });
+                                action::delete(stream.clone(), u32::from(days)).await;
                             }
                         };
                     }
@@ -82,6 +93,12 @@ pub fn init_scheduler() {
                 }
             };
         }
+        trash::purge_expired().await;
+        tiering::migrate_aged().await;
+
+        if let Err(err) = release_lock(lease).await {
+            log::warn!("failed to release retention lock due to {err:?}");
+        }
     };
 
     // Execute once on startup
@@ -190,8 +207,9 @@ impl From<Retention> for Vec<TaskView> {
 
 mod action {
     use crate::catalog::remove_manifest_from_snapshot;
+    use crate::storage::{key_naming, tiering, trash, ObjectStorage};
     use crate::{metadata, option::CONFIG};
-    use chrono::{Days, NaiveDate, Utc};
+    use chrono::{Days, NaiveDate};
     use futures::{stream::FuturesUnordered, StreamExt};
     use itertools::Itertools;
     use relative_path::RelativePathBuf;
@@ -200,31 +218,45 @@ mod action {
         log::info!("running retention task - delete for stream={stream_name}");
         let store = CONFIG.storage().get_object_store();
 
-        let retain_until = get_retain_until(Utc::now().date_naive(), days as u64);
+        // dates of partitions are in the partition zone of the stream
+        let zone = metadata::STREAM_INFO
+            .get_partition_time_zone(&stream_name)
+            .unwrap_or_default();
+        let retain_until = get_retain_until(key_naming::partition_today(zone), days as u64);
 
-        let Ok(mut dates) = store.list_dates(&stream_name).await else {
+        let Some(dates_to_delete) = aged_dates(&*store, &stream_name, retain_until).await else {
             return;
         };
-        dates.retain(|date| date.starts_with("date"));
-        let dates_to_delete = dates
-            .into_iter()
-            .filter(|date| string_to_date(date) < retain_until)
+        // partitions moved to the cold tier are deleted from the cold storage
+        let cold_store = tiering::cold_store();
+        let cold_dates_to_delete = match &cold_store {
+            Some(cold_store) => aged_dates(&**cold_store, &stream_name, retain_until)
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let dates = dates_to_delete
+            .iter()
+            .chain(&cold_dates_to_delete)
+            .unique()
+            .cloned()
             .collect_vec();
-        let dates = dates_to_delete.clone();
         if !dates.is_empty() {
             let delete_tasks = FuturesUnordered::new();
             let res_remove_manifest =
                 remove_manifest_from_snapshot(store.clone(), &stream_name, dates.clone()).await;
 
-            for date in dates_to_delete {
+            let partitions = dates_to_delete
+                .into_iter()
+                .map(|date| (store.clone(), date))
+                .chain(cold_store.into_iter().flat_map(|cold_store| {
+                    cold_dates_to_delete
+                        .iter()
+                        .map(move |date| (cold_store.clone(), date.clone()))
+                }));
+            for (store, date) in partitions {
                 let path = RelativePathBuf::from_iter([&stream_name, &date]);
-                delete_tasks.push(async move {
-                    CONFIG
-                        .storage()
-                        .get_object_store()
-                        .delete_prefix(&path)
-                        .await
-                });
+                delete_tasks.push(async move { trash::delete_prefix(&*store, &path).await });
             }
 
             let res: Vec<_> = delete_tasks.collect().await;
@@ -248,16 +280,28 @@ mod action {
         }
     }
 
-    fn get_retain_until(current_date: NaiveDate, days: u64) -> NaiveDate {
-        current_date - Days::new(days)
+    /// Dates of the partitions of a stream in `store` before `retain_until`
+    async fn aged_dates(
+        store: &dyn ObjectStorage,
+        stream_name: &str,
+        retain_until: NaiveDate,
+    ) -> Option<Vec<String>> {
+        let dates = store.list_dates(stream_name).await.ok()?;
+        let strategy = key_naming::strategy();
+        Some(
+            dates
+                .into_iter()
+                .filter(|date| {
+                    strategy
+                        .parse_date(date)
+                        .is_some_and(|date| date < retain_until)
+                })
+                .collect_vec(),
+        )
     }
 
-    fn string_to_date(date: &str) -> NaiveDate {
-        let year = date[5..9].parse().unwrap();
-        let month = date[10..12].parse().unwrap();
-        let day = date[13..15].parse().unwrap();
-
-        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    fn get_retain_until(current_date: NaiveDate, days: u64) -> NaiveDate {
+        current_date - Days::new(days)
     }
 
     #[cfg(test)]
@@ -265,13 +309,13 @@ mod action {
         use chrono::{Datelike, NaiveDate};
 
         use super::get_retain_until;
-        use super::string_to_date;
+        use crate::storage::key_naming::{Hive, KeyNamingStrategy};
 
         #[test]
         fn test_time_from_string() {
             let value = "date=2000-01-01";
-            let time = string_to_date(value);
-            assert_eq!(time, NaiveDate::from_ymd_opt(2000, 1, 1).unwrap());
+            let time = Hive.parse_date(value);
+            assert_eq!(time, NaiveDate::from_ymd_opt(2000, 1, 1));
         }
         #[test]
         fn test_retain_day() {
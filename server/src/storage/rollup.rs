@@ -0,0 +1,368 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Rollup of a stream: per minute count, min, max and sum of measure columns grouped by
+//! dimension columns, materialized into the `<stream>_rollup` stream once a minute has
+//! settled. Aggregate queries over dimensions and time buckets of a minute or coarser
+//! read the rollup for the range it covers and raw data for the rest, see `query::rollup`.
+//!
+//! Rollups are materialized by a standalone server, the only writer of the manifests of
+//! the rollup stream. A rollup covers data from the time it was set on, events arriving
+//! later than the settle delay after their minute are not in the rollup.
+
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arrow_array::RecordBatch;
+use arrow_schema::{ArrowError, Schema};
+use arrow_select::concat::concat_batches;
+use chrono::{DateTime, DurationRound, TimeDelta, Timelike, Utc};
+use datafusion::error::DataFusionError;
+use parquet::{arrow::ArrowWriter, errors::ParquetError, file::properties::WriterProperties};
+use relative_path::RelativePath;
+
+use super::object_storage::{commit_schema_to_storage, SYNC_LOCK};
+use super::provenance::Provenance;
+use super::sort_order::{self, SortColumn, SortDirection};
+use super::{key_naming, ObjectStorageError, OBJECT_STORE_DATA_GRANULARITY};
+use crate::catalog;
+use crate::event::{self, error::EventError, DEFAULT_TIMESTAMP_KEY};
+use crate::handlers::http::ingest::{create_stream_if_not_exists, PostError};
+use crate::metadata::{error::stream_info::MetadataError, STREAM_INFO};
+use crate::option::CONFIG;
+use crate::query::{rollup::partial_sql, QUERY_SESSION};
+use crate::utils;
+
+/// Column of the rollup with the number of events of a row
+pub const COUNT_COLUMN: &str = "p_count";
+
+/// Aggregate functions materialized per measure, with the suffix of their column
+pub const PARTIALS: [(&str, &str); 4] = [
+    ("MIN", "min"),
+    ("MAX", "max"),
+    ("SUM", "sum"),
+    ("COUNT", "count"),
+];
+
+/// Minutes newer than this are not rolled up yet, their events may still be arriving
+const SETTLE_DELAY: TimeDelta = TimeDelta::minutes(5);
+
+const INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rollup {
+    /// Columns the events of a minute are grouped by
+    pub dimensions: Vec<String>,
+    /// Numeric columns aggregated per group
+    pub measures: Vec<String>,
+    /// Range of event time materialized, maintained by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<Coverage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Coverage {
+    pub from: DateTime<Utc>,
+    /// exclusive
+    pub until: DateTime<Utc>,
+}
+
+impl Rollup {
+    /// Name of the stream holding the rollup of `stream`
+    pub fn stream_name(stream: &str) -> String {
+        format!("{stream}_rollup")
+    }
+
+    pub fn partial_column(measure: &str, suffix: &str) -> String {
+        format!("{measure}_{suffix}")
+    }
+
+    /// Columns of the rollup stream, in the order they are selected
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns = vec![DEFAULT_TIMESTAMP_KEY.to_owned()];
+        columns.extend(self.dimensions.iter().cloned());
+        columns.push(COUNT_COLUMN.to_owned());
+        for measure in &self.measures {
+            columns.extend(
+                PARTIALS
+                    .iter()
+                    .map(|(_, suffix)| Self::partial_column(measure, suffix)),
+            );
+        }
+        columns
+    }
+
+    /// Rollups are bucketed on the event timestamp, not on a time partition column
+    pub fn validate(&self, schema: &Schema, time_partition: Option<&str>) -> Result<(), String> {
+        if time_partition.is_some() {
+            return Err("streams with a time partition can't be rolled up".to_owned());
+        }
+        for column in self.dimensions.iter().chain(&self.measures) {
+            if column == DEFAULT_TIMESTAMP_KEY {
+                return Err(format!("{column} is the time bucket of the rollup"));
+            }
+            if schema.field_with_name(column).is_err() {
+                return Err(format!("unknown column {column}"));
+            }
+        }
+        for measure in &self.measures {
+            let data_type = schema.field_with_name(measure).unwrap().data_type();
+            if !data_type.is_numeric() {
+                return Err(format!(
+                    "measure {measure} of type {data_type} is not numeric"
+                ));
+            }
+        }
+        let columns = self.columns();
+        if columns.iter().collect::<HashSet<_>>().len() != columns.len() {
+            return Err("columns of the rollup must have distinct names".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Next range to materialize, whole minutes settled by `now` within a single hour.
+    /// A new rollup starts at the last settled minute, earlier data is not rolled up.
+    pub fn next_window(&self, now: DateTime<Utc>) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let settled = now.duration_trunc(TimeDelta::minutes(1)).ok()? - SETTLE_DELAY;
+        let start = self.coverage.map_or(settled, |coverage| coverage.until);
+        let next_hour = start.duration_trunc(TimeDelta::hours(1)).ok()? + TimeDelta::hours(1);
+        let end = settled.min(next_hour);
+        (start < end || self.coverage.is_none()).then_some((start, end))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RollupError {
+    #[error("{0}")]
+    DataFusion(#[from] DataFusionError),
+    #[error("{0}")]
+    Arrow(#[from] ArrowError),
+    #[error("{0}")]
+    Parquet(#[from] ParquetError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    ObjectStorage(#[from] ObjectStorageError),
+    #[error("{0}")]
+    Metadata(#[from] MetadataError),
+    #[error("{0}")]
+    Event(#[from] EventError),
+    #[error("{0}")]
+    CreateStream(#[from] PostError),
+}
+
+/// Starts materializing the rollups of streams in the background
+pub fn init() {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INTERVAL);
+        loop {
+            interval.tick().await;
+            for stream in STREAM_INFO.list_streams() {
+                let Ok(Some(rollup)) = STREAM_INFO.get_rollup(&stream) else {
+                    continue;
+                };
+                if let Err(err) = materialize(&stream, rollup).await {
+                    log::warn!("failed to materialize rollup of {stream}: {err}");
+                }
+            }
+        }
+    });
+}
+
+/// Materializes every settled window not yet covered by the rollup of `stream`
+async fn materialize(stream: &str, mut rollup: Rollup) -> Result<(), RollupError> {
+    let rollup_stream = Rollup::stream_name(stream);
+    create_stream_if_not_exists(&rollup_stream).await?;
+    while let Some((start, end)) = rollup.next_window(Utc::now()) {
+        if start < end {
+            let sql = partial_sql(
+                stream,
+                &rollup,
+                Bound::Included(start.naive_utc()),
+                Bound::Excluded(end.naive_utc()),
+            );
+            let df = QUERY_SESSION.sql(&sql).await?;
+            let schema = Arc::new(Schema::from(df.schema()));
+            let batch = concat_batches(&schema, &df.collect().await?)?;
+
+            // the rollup stream is queried with the time column as any other stream
+            event::commit_schema(&rollup_stream, Arc::clone(&schema))?;
+            commit_schema_to_storage(&rollup_stream, schema.as_ref().clone()).await?;
+            if batch.num_rows() > 0 {
+                upload(&rollup_stream, start, &batch).await?;
+            }
+        }
+
+        rollup.coverage = Some(Coverage {
+            from: rollup.coverage.map_or(start, |coverage| coverage.from),
+            until: end,
+        });
+        // the spec may have been replaced while the window was materialized
+        if STREAM_INFO.get_rollup(stream)?.as_ref().map(strip_coverage)
+            != Some(strip_coverage(&rollup))
+        {
+            return Ok(());
+        }
+        let storage = CONFIG.storage().get_object_store();
+        let mut stream_metadata = storage.get_stream_metadata(stream).await?;
+        stream_metadata.rollup = Some(rollup.clone());
+        storage
+            .put_stream_manifest(stream, &stream_metadata)
+            .await?;
+        STREAM_INFO.set_rollup(stream, Some(rollup.clone()))?;
+        log::debug!("rolled up {stream} until {end}");
+    }
+    Ok(())
+}
+
+fn strip_coverage(rollup: &Rollup) -> (&[String], &[String]) {
+    (&rollup.dimensions, &rollup.measures)
+}
+
+/// Writes the rows of a window starting at `start` to a parquet file of the rollup stream
+/// and adds it to its manifests
+async fn upload(
+    rollup_stream: &str,
+    start: DateTime<Utc>,
+    batch: &RecordBatch,
+) -> Result<(), RollupError> {
+    let batch = sort_order::sort_batch(
+        batch,
+        &[SortColumn {
+            column: DEFAULT_TIMESTAMP_KEY.to_owned(),
+            order: SortDirection::Desc,
+        }],
+    )?;
+    let props = WriterProperties::builder()
+        .set_key_value_metadata(Some(
+            Provenance {
+                source: "rollup",
+                ..Provenance::staging()
+            }
+            .key_value_metadata(),
+        ))
+        .build();
+
+    // not named as a parquet file so that staging sync leaves it alone
+    let dir = CONFIG.parseable.local_stream_data_path(rollup_stream);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.rollup.part", ulid::Ulid::new()));
+    let mut writer = ArrowWriter::try_new(fs::File::create(&path)?, batch.schema(), Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    let file_name = format!(
+        "{}{}{}{}.data.parquet",
+        utils::date_to_prefix(start.date_naive()),
+        utils::hour_to_prefix(start.hour()),
+        utils::minute_to_prefix(start.minute(), OBJECT_STORE_DATA_GRANULARITY).unwrap(),
+        ulid::Ulid::new()
+    )
+    .replace('/', ".");
+    let key = key_naming::strategy().object_key(
+        rollup_stream,
+        &file_name,
+        3,
+        STREAM_INFO.get_partition_granularity(rollup_stream)?,
+    );
+
+    let storage = CONFIG.storage().get_object_store();
+    let res = async {
+        let _guard = SYNC_LOCK.lock().await;
+        storage.upload_file(&key, &path).await?;
+        let absolute_path = storage.absolute_url(RelativePath::new(&key)).to_string();
+        let file = catalog::create_from_parquet_file(absolute_path, &path)
+            .map_err(|err| ObjectStorageError::UnhandledError(err.into()))?;
+        catalog::update_snapshot(storage, rollup_stream, file).await
+    }
+    .await;
+    fs::remove_file(&path)?;
+    Ok(res?)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{Coverage, Rollup};
+
+    fn rollup() -> Rollup {
+        serde_json::from_str(r#"{"dimensions": ["host"], "measures": ["latency"]}"#).unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn spec_must_name_columns_of_the_stream() {
+        let schema = Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("latency", DataType::Float64, true),
+        ]);
+        assert!(rollup().validate(&schema, None).is_ok());
+        assert!(rollup().validate(&schema, Some("event_time")).is_err());
+
+        let mut non_numeric = rollup();
+        non_numeric.measures = vec!["host".to_owned()];
+        assert!(non_numeric.validate(&schema, None).is_err());
+
+        let mut unknown = rollup();
+        unknown.dimensions.push("region".to_owned());
+        assert!(unknown.validate(&schema, None).is_err());
+
+        let mut time_bucket = rollup();
+        time_bucket.dimensions = vec!["p_timestamp".to_owned()];
+        assert!(time_bucket.validate(&schema, None).is_err());
+    }
+
+    #[test]
+    fn windows_advance_by_settled_minutes_within_an_hour() {
+        let mut rollup = rollup();
+        // a new rollup only starts covering
+        assert_eq!(
+            rollup.next_window(time(10, 20) + chrono::TimeDelta::seconds(30)),
+            Some((time(10, 15), time(10, 15)))
+        );
+
+        rollup.coverage = Some(Coverage {
+            from: time(9, 30),
+            until: time(9, 40),
+        });
+        assert_eq!(
+            rollup.next_window(time(10, 20)),
+            Some((time(9, 40), time(10, 0)))
+        );
+
+        rollup.coverage = Some(Coverage {
+            from: time(9, 30),
+            until: time(10, 15),
+        });
+        assert_eq!(rollup.next_window(time(10, 20)), None);
+    }
+}
@@ -0,0 +1,344 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Stores the objects of some streams in a location of their own, a bucket for S3 or a
+//! directory for drive. Every object key starts with the stream name, so operations are
+//! dispatched on the first segment of the key. Server metadata, users and streams without
+//! a location stay in the default store.
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use datafusion::datasource::listing::ListingTableUrl;
+use futures::stream::BoxStream;
+use relative_path::{RelativePath, RelativePathBuf};
+
+use crate::option::CONFIG;
+
+use super::{
+    LogStream, ObjectMetaInfo, ObjectStorage, ObjectStorageError, ObjectStorageProvider,
+    TRASH_ROOT_DIRECTORY,
+};
+
+type Store = Arc<dyn ObjectStorage + Send>;
+
+/// Wraps `default` so that streams configured with `--stream-storage` are stored
+/// in their own location of the same provider
+pub fn route_streams(default: Store, provider: &dyn ObjectStorageProvider) -> Store {
+    let locations = &CONFIG.parseable.stream_storage;
+    if locations.is_empty() {
+        return default;
+    }
+
+    let routes = locations
+        .iter()
+        .map(|(stream, location)| (stream.clone(), provider.get_object_store_at(location)))
+        .collect();
+    Arc::new(StreamRouter::new(default, routes))
+}
+
+pub struct StreamRouter {
+    default: Store,
+    // stream name -> store holding all objects of the stream
+    routes: HashMap<String, Store>,
+}
+
+impl StreamRouter {
+    pub fn new(default: Store, routes: HashMap<String, Store>) -> Self {
+        Self { default, routes }
+    }
+
+    fn for_stream(&self, stream_name: &str) -> &Store {
+        self.routes.get(stream_name).unwrap_or(&self.default)
+    }
+
+    fn for_key(&self, key: &str) -> &Store {
+        let stream_name = key.trim_start_matches('/').split('/').next().unwrap_or("");
+        self.for_stream(stream_name)
+    }
+
+    /// Entries of the default store that aren't routed elsewhere,
+    /// followed by the entry of each routed stream found in its own store
+    async fn list_all<T, F, Fut>(
+        &self,
+        list: F,
+        name: fn(&T) -> &str,
+    ) -> Result<Vec<T>, ObjectStorageError>
+    where
+        F: Fn(Store) -> Fut,
+        Fut: std::future::Future<Output = Result<Vec<T>, ObjectStorageError>>,
+    {
+        let mut entries: Vec<T> = list(Arc::clone(&self.default))
+            .await?
+            .into_iter()
+            .filter(|entry| !self.routes.contains_key(name(entry)))
+            .collect();
+        for (stream_name, store) in &self.routes {
+            entries.extend(
+                list(Arc::clone(store))
+                    .await?
+                    .into_iter()
+                    .filter(|entry| name(entry) == stream_name.as_str()),
+            );
+        }
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for StreamRouter {
+    async fn get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
+        self.for_key(path.as_str()).get_object(path).await
+    }
+
+    async fn get_objects(
+        &self,
+        base_path: Option<&RelativePath>,
+        filter_fun: Box<dyn Fn(String) -> bool + Send>,
+    ) -> Result<Vec<Bytes>, ObjectStorageError> {
+        let store = match base_path {
+            Some(path) => self.for_key(path.as_str()),
+            None => &self.default,
+        };
+        store.get_objects(base_path, filter_fun).await
+    }
+
+    async fn get_object_suffix(
+        &self,
+        path: &RelativePath,
+        len: usize,
+    ) -> Result<Bytes, ObjectStorageError> {
+        self.for_key(path.as_str())
+            .get_object_suffix(path, len)
+            .await
+    }
+
+    async fn object_checksum(&self, path: &RelativePath) -> Result<String, ObjectStorageError> {
+        self.for_key(path.as_str()).object_checksum(path).await
+    }
+
+    async fn exists(&self, path: &RelativePath) -> Result<bool, ObjectStorageError> {
+        self.for_key(path.as_str()).exists(path).await
+    }
+
+    async fn put_object(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str()).put_object(path, resource).await
+    }
+
+    async fn put_object_if_not_exists(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str())
+            .put_object_if_not_exists(path, resource)
+            .await
+    }
+
+    async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str()).delete_prefix(path).await
+    }
+
+    async fn move_prefix(
+        &self,
+        from: &RelativePath,
+        to: &RelativePath,
+    ) -> Result<(), ObjectStorageError> {
+        // trash is kept in the store of the trashed stream, so moves into and out of it
+        // are routed by the key outside of the trash
+        let key = if from.starts_with(TRASH_ROOT_DIRECTORY) {
+            to
+        } else {
+            from
+        };
+        self.for_key(key.as_str()).move_prefix(from, to).await
+    }
+
+    async fn check(&self) -> Result<(), ObjectStorageError> {
+        self.default.check().await?;
+        for store in self.routes.values() {
+            store.check().await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
+        self.for_stream(stream_name)
+            .delete_stream(stream_name)
+            .await
+    }
+
+    async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        self.list_all(
+            |store| async move { store.list_streams().await },
+            |stream| stream.name.as_str(),
+        )
+        .await
+    }
+
+    async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        self.list_all(
+            |store| async move { store.list_old_streams().await },
+            |stream| stream.name.as_str(),
+        )
+        .await
+    }
+
+    async fn list_dirs(&self) -> Result<Vec<String>, ObjectStorageError> {
+        self.list_all(
+            |store| async move { store.list_dirs().await },
+            String::as_str,
+        )
+        .await
+    }
+
+    async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError> {
+        self.for_stream(stream_name).list_dates(stream_name).await
+    }
+
+    fn list_objects_meta(
+        &self,
+        stream_name: &str,
+    ) -> BoxStream<'_, Result<ObjectMetaInfo, ObjectStorageError>> {
+        self.for_stream(stream_name).list_objects_meta(stream_name)
+    }
+
+    async fn upload_file(&self, key: &str, path: &Path) -> Result<(), ObjectStorageError> {
+        self.for_key(key).upload_file(key, path).await
+    }
+
+    async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        self.for_key(path.as_str()).delete_object(path).await
+    }
+
+    async fn get_ingestor_meta_file_paths(
+        &self,
+    ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        self.default.get_ingestor_meta_file_paths().await
+    }
+
+    async fn get_stream_file_paths(
+        &self,
+        stream_name: &str,
+    ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        self.for_stream(stream_name)
+            .get_stream_file_paths(stream_name)
+            .await
+    }
+
+    async fn try_delete_ingestor_meta(
+        &self,
+        ingestor_filename: String,
+    ) -> Result<(), ObjectStorageError> {
+        self.default
+            .try_delete_ingestor_meta(ingestor_filename)
+            .await
+    }
+
+    fn query_prefixes(&self, prefixes: Vec<String>) -> Vec<ListingTableUrl> {
+        prefixes
+            .into_iter()
+            .flat_map(|prefix| self.for_key(&prefix).query_prefixes(vec![prefix]))
+            .collect()
+    }
+
+    fn absolute_url(&self, prefix: &RelativePath) -> object_store::path::Path {
+        self.for_key(prefix.as_str()).absolute_url(prefix)
+    }
+
+    fn store_url(&self) -> url::Url {
+        self.default.store_url()
+    }
+
+    fn stream_store_url(&self, stream_name: &str) -> url::Url {
+        self.for_stream(stream_name).store_url()
+    }
+
+    fn get_bucket_name(&self) -> String {
+        self.default.get_bucket_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+    use bytes::Bytes;
+    use relative_path::RelativePathBuf;
+
+    use super::{Store, StreamRouter};
+    use crate::storage::{
+        localfs::LocalFS, ObjectStorage, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    };
+
+    fn temp_store(name: &str) -> (PathBuf, Store) {
+        let root = std::env::temp_dir().join(format!("parseable-{name}-{}", ulid::Ulid::new()));
+        (root.clone(), Arc::new(LocalFS::new(root)))
+    }
+
+    #[actix_web::test]
+    async fn streams_are_stored_in_their_own_location() {
+        let (default_root, default) = temp_store("default");
+        let (a_root, a) = temp_store("tenant-a");
+        let (b_root, b) = temp_store("tenant-b");
+        let router = StreamRouter::new(
+            default,
+            HashMap::from([("tenant_a".to_string(), a), ("tenant_b".to_string(), b)]),
+        );
+
+        let metadata_path = |stream| {
+            RelativePathBuf::from_iter([stream, STREAM_ROOT_DIRECTORY, STREAM_METADATA_FILE_NAME])
+        };
+        for stream in ["tenant_a", "tenant_b", "shared"] {
+            router
+                .put_object(&metadata_path(stream), Bytes::from_static(b"{}"))
+                .await
+                .unwrap();
+        }
+
+        assert!(a_root.join("tenant_a/.stream").exists());
+        assert!(!a_root.join("tenant_b").exists());
+        assert!(b_root.join("tenant_b/.stream").exists());
+        assert!(default_root.join("shared/.stream").exists());
+        assert!(!default_root.join("tenant_a").exists());
+
+        let mut streams: Vec<String> = router
+            .list_streams()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|stream| stream.name)
+            .collect();
+        streams.sort();
+        assert_eq!(streams, ["shared", "tenant_a", "tenant_b"]);
+        assert!(router.get_object(&metadata_path("tenant_b")).await.is_ok());
+
+        router.delete_stream("tenant_a").await.unwrap();
+        assert!(!a_root.join("tenant_a").exists());
+        assert!(b_root.join("tenant_b").exists());
+
+        for root in [default_root, a_root, b_root] {
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+}
@@ -24,34 +24,79 @@ use datafusion::datasource::object_store::{
 };
 use datafusion::execution::runtime_env::RuntimeConfig;
 use futures::stream::FuturesUnordered;
-use futures::{StreamExt, TryStreamExt};
-use object_store::aws::{AmazonS3, AmazonS3Builder, AmazonS3ConfigKey, Checksum};
-use object_store::limit::LimitStore;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use http::{HeaderMap, HeaderValue};
+use itertools::Itertools;
+use object_store::aws::{
+    AmazonS3, AmazonS3Builder, AmazonS3ConfigKey, AwsCredentialProvider, Checksum, S3ConditionalPut,
+};
 use object_store::path::Path as StorePath;
-use object_store::{ClientOptions, ObjectStore};
+use object_store::{ClientOptions, GetOptions, GetRange, ObjectStore, PutMode, PutOptions};
+use once_cell::sync::OnceCell;
 use relative_path::{RelativePath, RelativePathBuf};
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use std::collections::{HashMap, HashSet};
 use std::iter::Iterator;
+use std::net::IpAddr;
 use std::path::Path as StdPath;
-use std::sync::Arc;
+use std::sync::{Arc, Once};
 use std::time::{Duration, Instant};
 
+use crate::handlers::http::middleware::current_request_id;
 use crate::handlers::http::users::USERS_ROOT_DIR;
-use crate::metrics::storage::{s3::REQUEST_RESPONSE_TIME, StorageMetrics};
-use crate::storage::{LogStream, ObjectStorage, ObjectStorageError, PARSEABLE_ROOT_DIRECTORY};
+use crate::metrics::storage::{
+    s3::{REQUEST_RESPONSE_TIME, STREAM_REQUEST_RESPONSE_TIME},
+    StorageMetrics,
+};
+use crate::option::{validation, CONFIG};
+use crate::shutdown::UPLOADS;
+use crate::storage::{
+    LogStream, ObjectMetaInfo, ObjectStorage, ObjectStorageError, PARSEABLE_ROOT_DIRECTORY,
+    QUARANTINE_ROOT_DIRECTORY, TRASH_ROOT_DIRECTORY,
+};
 
+use super::consistency_wait::ConsistencyWait;
+use super::credential_chain::{self, CredentialSource, Credentials};
+use super::credentials::{CredentialRetry, RefreshableCredentials};
+use super::etag_cache;
 use super::metrics_layer::MetricLayer;
-use super::object_storage::parseable_json_path;
+use super::object_key::ObjectKey;
+use super::object_storage::{check_object_size, parseable_json_path};
+use super::overwrite::OverwritePolicy;
+use super::request_limit::{ReadWriteLimitStore, RequestBudget};
+use super::sqs::SqsQueue;
+use super::throttle_retry::ThrottleRetry;
+use super::timeout_layer::{OperationTimeout, TimeoutStore};
 use super::{
-    ObjectStorageProvider, SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    metadata_store, routing, ObjectStorageProvider, SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME,
+    STREAM_ROOT_DIRECTORY,
 };
 
 // in bytes
 const MULTIPART_UPLOAD_SIZE: usize = 1024 * 1024 * 100;
+// max number of objects fetched in parallel by a batched get
+const MAX_CONCURRENT_GETS: usize = 64;
 const CONNECT_TIMEOUT_SECS: u64 = 5;
-const AWS_CONTAINER_CREDENTIALS_RELATIVE_URI: &str = "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI";
+// object_store release the client is built with, named in errors for options it lacks
+const OBJECT_STORE_VERSION: &str = "0.9.1";
+// proxy carrying a custom CA certificate, never connected to as no host uses it
+const CA_CERT_PROXY_URL: &str = "http://127.0.0.1:9";
+// the * wildcard only matches domain names, IP addresses need their own entries
+const CA_CERT_PROXY_EXCLUDES: &str = "*,0.0.0.0/0,::/0";
+const STORAGE_CLASS_HEADER: &str = "x-amz-storage-class";
+// region used for S3 compatible stores when none is configured, most of them ignore it
+const DEFAULT_REGION: &str = "us-east-1";
+
+// read and write budgets shared by every client of the bucket,
+// so that query and ingestion requests are limited together
+static REQUEST_BUDGETS: OnceCell<(Arc<RequestBudget>, Arc<RequestBudget>)> = OnceCell::new();
+
+// credentials are resolved for every client, the source is logged once
+static CREDENTIAL_SOURCE_LOGGED: Once = Once::new();
+
+type S3Client = ReadWriteLimitStore<TimeoutStore<ThrottleRetry<CredentialRetry<AmazonS3>>>>;
 
 #[derive(Debug, Clone, clap::Args)]
 #[command(
@@ -64,8 +109,13 @@ const AWS_CONTAINER_CREDENTIALS_RELATIVE_URI: &str = "AWS_CONTAINER_CREDENTIALS_
 )]
 pub struct S3Config {
     /// The endpoint to AWS S3 or compatible object storage platform
-    #[arg(long, env = "P_S3_URL", value_name = "url", required = true)]
-    pub endpoint_url: String,
+    #[arg(
+        long,
+        env = "P_S3_URL",
+        value_name = "url",
+        required_unless_present = "transfer_acceleration"
+    )]
+    pub endpoint_url: Option<String>,
 
     /// The access key for AWS S3 or compatible object storage platform
     #[arg(long, env = "P_S3_ACCESS_KEY", value_name = "access-key")]
@@ -75,9 +125,21 @@ pub struct S3Config {
     #[arg(long, env = "P_S3_SECRET_KEY", value_name = "secret-key")]
     pub secret_key: Option<String>,
 
-    /// The region for AWS S3 or compatible object storage platform
-    #[arg(long, env = "P_S3_REGION", value_name = "region", required = true)]
-    pub region: String,
+    /// The region for AWS S3 or compatible object storage platform,
+    /// defaults to us-east-1 for stores other than AWS S3
+    #[arg(
+        long,
+        env = "P_S3_REGION",
+        value_name = "region",
+        required_unless_present = "endpoint_url"
+    )]
+    pub region: Option<String>,
+
+    /// The region used to sign requests, defaults to the configured region.
+    /// Needed when the endpoint sits behind a gateway or proxy that validates
+    /// signatures against a region different from the one hosting the bucket
+    #[arg(long, env = "P_S3_SIGNING_REGION", value_name = "region")]
+    pub signing_region: Option<String>,
 
     /// The AWS S3 or compatible object storage bucket to be used for storage
     #[arg(long, env = "P_S3_BUCKET", value_name = "bucket-name", required = true)]
@@ -106,10 +168,23 @@ pub struct S3Config {
         long,
         env = "P_S3_TLS_SKIP_VERIFY",
         value_name = "bool",
-        default_value = "false"
+        default_value = "false",
+        conflicts_with = "ca_cert"
     )]
     pub skip_tls: bool,
 
+    /// Path to a PEM encoded CA certificate the object storage TLS certificate is verified
+    /// against, in addition to the system roots. Proxy environment variables are not used
+    /// when set.
+    #[arg(
+        long = "ca-cert-path",
+        env = "P_S3_CA_CERT",
+        value_name = "path",
+        value_parser = validation::ca_certificate,
+        required = false
+    )]
+    pub ca_cert: Option<String>,
+
     /// Set client to fallback to imdsv1
     #[arg(
         long,
@@ -127,10 +202,310 @@ pub struct S3Config {
         required = false
     )]
     pub metadata_endpoint: Option<String>,
+
+    /// Set client to send unsigned requests, for public buckets that allow anonymous read
+    #[arg(
+        long,
+        env = "P_S3_ANONYMOUS",
+        value_name = "bool",
+        default_value = "false",
+        conflicts_with_all = ["access_key_id", "secret_key", "imdsv1_fallback", "metadata_endpoint"]
+    )]
+    pub anonymous: bool,
+
+    /// Order in which credential sources are tried, the first one set up supplies the
+    /// credentials. Sources are static, env, profile, assume-role, container and imds
+    #[arg(
+        long,
+        env = "P_S3_CREDENTIAL_CHAIN",
+        value_name = "sources",
+        value_enum,
+        value_delimiter = ',',
+        default_value = credential_chain::DEFAULT_CHAIN
+    )]
+    pub credential_chain: Vec<CredentialSource>,
+
+    /// Log a warning for object store calls taking longer than this many milliseconds
+    #[arg(
+        long,
+        env = "P_S3_SLOW_LOG_MS",
+        value_name = "milliseconds",
+        required = false
+    )]
+    pub slow_log_ms: Option<u64>,
+
+    /// Overall time in seconds an object store call may take before it fails, in addition
+    /// to the client connect timeout. Streamed listings and bodies are bounded per item.
+    #[arg(
+        long,
+        env = "P_S3_OPERATION_TIMEOUT_SECS",
+        value_name = "seconds",
+        default_value_t = 300,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub operation_timeout_secs: u64,
+
+    /// Longest delay in seconds waited for the Retry-After of a throttled request
+    #[arg(
+        long,
+        env = "P_S3_MAX_RETRY_AFTER_SECS",
+        value_name = "seconds",
+        default_value_t = 30
+    )]
+    pub max_retry_after_secs: u64,
+
+    /// Heads retried after a write until the written object is found, for eventually
+    /// consistent stores. Never waits for AWS S3, which is strongly consistent
+    #[arg(
+        long,
+        env = "P_S3_CONSISTENCY_WAIT_RETRIES",
+        value_name = "count",
+        default_value_t = 0
+    )]
+    pub consistency_wait_retries: u32,
+
+    /// Delay in milliseconds before the first head retried after a write, doubled for
+    /// every following one
+    #[arg(
+        long,
+        env = "P_S3_CONSISTENCY_WAIT_BACKOFF_MS",
+        value_name = "milliseconds",
+        default_value_t = 100
+    )]
+    pub consistency_wait_backoff_ms: u64,
+
+    /// Record request latency per stream, adds a label value for every stream
+    #[arg(
+        long,
+        env = "P_S3_STREAM_METRICS",
+        value_name = "bool",
+        default_value = "false"
+    )]
+    pub stream_metrics: bool,
+
+    /// Use the S3 Transfer Acceleration endpoint of the bucket instead of a custom endpoint.
+    /// Only works with AWS S3 buckets that have transfer acceleration enabled
+    #[arg(
+        long,
+        env = "P_S3_TRANSFER_ACCEL",
+        value_name = "bool",
+        default_value = "false",
+        conflicts_with_all = ["endpoint_url", "use_path_style"]
+    )]
+    pub transfer_acceleration: bool,
+
+    /// Maximum concurrent read requests (get, head, list) to the object store
+    #[arg(
+        long,
+        env = "P_S3_MAX_READ_REQUESTS",
+        value_name = "count",
+        default_value_t = super::MAX_OBJECT_STORE_REQUESTS,
+        value_parser = clap::value_parser!(usize).range(1..)
+    )]
+    pub max_read_requests: usize,
+
+    /// Maximum concurrent write requests (put, upload, delete) to the object store
+    #[arg(
+        long,
+        env = "P_S3_MAX_WRITE_REQUESTS",
+        value_name = "count",
+        default_value_t = super::MAX_OBJECT_STORE_REQUESTS,
+        value_parser = clap::value_parser!(usize).range(1..)
+    )]
+    pub max_write_requests: usize,
+
+    /// Address family used to connect to the object store. Only `any` is supported by
+    /// object_store 0.9.1, other values stop the server at startup
+    #[arg(
+        long,
+        env = "P_S3_IP_VERSION",
+        value_name = "version",
+        value_enum,
+        default_value_t = IpVersion::Any
+    )]
+    pub ip_version: IpVersion,
+
+    /// Resolve a host to a fixed address instead of using DNS, as host=address.
+    /// Can be repeated, or comma separated in the environment variable. Not supported by
+    /// object_store 0.9.1, setting it stops the server at startup
+    #[arg(
+        long = "resolve",
+        env = "P_S3_RESOLVE",
+        value_name = "host=address",
+        value_delimiter = ',',
+        value_parser = validation::host_override
+    )]
+    pub resolve_overrides: Vec<(String, IpAddr)>,
+
+    /// Storage class of uploaded parquet files, the bucket default (usually STANDARD) when
+    /// not set. Manifests and metadata always use the bucket default
+    #[arg(long, env = "P_S3_STORAGE_CLASS", value_name = "class", value_enum)]
+    pub storage_class: Option<StorageClass>,
+
+    /// Storage class of the parquet files of a stream, as stream=class. Can be repeated,
+    /// or comma separated in the environment variable
+    #[arg(
+        long = "stream-storage-class",
+        env = "P_S3_STREAM_STORAGE_CLASS",
+        value_name = "stream=class",
+        value_delimiter = ',',
+        value_parser = stream_storage_class
+    )]
+    pub stream_storage_classes: Vec<(String, StorageClass)>,
+
+    /// SQS queue receiving the event notifications of the bucket, used to find parquet
+    /// files written to the bucket by other pipelines
+    #[arg(long, env = "P_S3_SQS_QUEUE_URL", value_name = "url", required = false)]
+    pub sqs_queue_url: Option<String>,
+}
+
+/// Storage classes objects can be uploaded with. Archive classes are left out
+/// as their objects can't be read by queries without being restored first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum StorageClass {
+    #[value(alias = "STANDARD")]
+    Standard,
+    #[value(alias = "STANDARD_IA")]
+    StandardIa,
+    #[value(alias = "ONEZONE_IA")]
+    OnezoneIa,
+    #[value(alias = "INTELLIGENT_TIERING")]
+    IntelligentTiering,
+    #[value(alias = "GLACIER_IR")]
+    GlacierIr,
+}
+
+impl StorageClass {
+    /// Value of the x-amz-storage-class header
+    fn as_header_value(self) -> &'static str {
+        match self {
+            StorageClass::Standard => "STANDARD",
+            StorageClass::StandardIa => "STANDARD_IA",
+            StorageClass::OnezoneIa => "ONEZONE_IA",
+            StorageClass::IntelligentTiering => "INTELLIGENT_TIERING",
+            StorageClass::GlacierIr => "GLACIER_IR",
+        }
+    }
+}
+
+fn stream_storage_class(s: &str) -> Result<(String, StorageClass), String> {
+    let (stream, class) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected stream=class, got {s}"))?;
+    let class = <StorageClass as clap::ValueEnum>::from_str(class, true)
+        .map_err(|_| format!("unknown storage class {class}"))?;
+    Ok((stream.to_owned(), class))
+}
+
+/// Storage class of uploaded data files, per stream with a fallback for other streams
+#[derive(Debug, Clone, Default)]
+struct StorageClasses {
+    default: Option<StorageClass>,
+    streams: HashMap<String, StorageClass>,
+}
+
+impl StorageClasses {
+    /// Storage class for an object key, the stream is the first segment of the key.
+    /// Only parquet files get one, manifests and stream metadata are read and rewritten
+    /// often and stay in the bucket default.
+    fn for_key(&self, key: &str) -> Option<StorageClass> {
+        if !key.ends_with(".parquet") {
+            return None;
+        }
+        key.split('/')
+            .next()
+            .and_then(|stream| self.streams.get(stream))
+            .copied()
+            .or(self.default)
+    }
+
+    fn all(&self) -> HashSet<StorageClass> {
+        self.default
+            .into_iter()
+            .chain(self.streams.values().copied())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IpVersion {
+    /// Use whatever addresses DNS returns
+    Any,
+    V4,
+    V6,
 }
 
 impl S3Config {
+    fn endpoint(&self) -> String {
+        if self.transfer_acceleration {
+            format!("https://{}.s3-accelerate.amazonaws.com", self.bucket_name)
+        } else {
+            self.endpoint_url
+                .clone()
+                .expect("endpoint is required without transfer acceleration")
+        }
+    }
+
+    /// Whether requests are sent to AWS S3 itself rather than a compatible store
+    pub fn is_aws_endpoint(&self) -> bool {
+        self.transfer_acceleration
+            || url::Url::parse(&self.endpoint())
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.ends_with(".amazonaws.com")))
+                .unwrap_or(false)
+    }
+
+    fn region(&self) -> &str {
+        self.region.as_deref().unwrap_or(DEFAULT_REGION)
+    }
+
+    fn signing_region(&self) -> &str {
+        self.signing_region.as_deref().unwrap_or(self.region())
+    }
+
+    fn limit_requests<T: ObjectStore>(&self, s3: T) -> ReadWriteLimitStore<T> {
+        let (read, write) = REQUEST_BUDGETS.get_or_init(|| {
+            (
+                RequestBudget::new("read", self.max_read_requests),
+                RequestBudget::new("write", self.max_write_requests),
+            )
+        });
+        ReadWriteLimitStore::new(s3, Arc::clone(read), Arc::clone(write))
+    }
+
+    /// Address family and DNS overrides need a hook into the HTTP client, object_store 0.9
+    /// builds its reqwest client internally and exposes neither `local_address` nor
+    /// `resolve`. Fail at startup rather than silently connecting over DNS.
+    pub fn check_network_options(&self) -> Result<(), String> {
+        if self.ip_version != IpVersion::Any {
+            return Err(format!(
+                "--ip-version {:?} is not supported by object_store {OBJECT_STORE_VERSION}, \
+                 only any is",
+                self.ip_version
+            ));
+        }
+        if let Some((host, _)) = self.resolve_overrides.first() {
+            return Err(format!(
+                "--resolve for {host} is not supported by object_store {OBJECT_STORE_VERSION}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn storage_classes(&self) -> StorageClasses {
+        StorageClasses {
+            default: self.storage_class,
+            streams: self.stream_storage_classes.iter().cloned().collect(),
+        }
+    }
+
     fn get_default_builder(&self) -> AmazonS3Builder {
+        self.get_builder(None)
+    }
+
+    /// Builder of a client sending the storage class header with every request,
+    /// only used for uploads
+    fn get_builder(&self, storage_class: Option<StorageClass>) -> AmazonS3Builder {
         let mut client_options = ClientOptions::default()
             .with_allow_http(true)
             .with_connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS));
@@ -139,30 +514,71 @@ impl S3Config {
             client_options = client_options.with_allow_invalid_certificates(true)
         }
 
+        // ip version and resolve overrides are rejected by check_network_options
+        // until ClientOptions can carry them
+
+        if let Some(storage_class) = storage_class {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                STORAGE_CLASS_HEADER,
+                HeaderValue::from_static(storage_class.as_header_value()),
+            );
+            client_options = client_options.with_default_headers(headers);
+        }
+
+        if let Some(ca_cert) = &self.ca_cert {
+            // object_store 0.9 only takes an extra trusted CA for a proxy. Every host
+            // bypasses this proxy, so requests still go straight to the endpoint but the
+            // client trusts the CA for them as well.
+            client_options = client_options
+                .with_proxy_url(CA_CERT_PROXY_URL)
+                .with_proxy_ca_certificate(ca_cert)
+                .with_proxy_excludes(CA_CERT_PROXY_EXCLUDES);
+        }
+
+        // endpoint is always set explicitly, so the builder region is only used for signing
         let mut builder = AmazonS3Builder::new()
-            .with_region(&self.region)
-            .with_endpoint(&self.endpoint_url)
+            .with_region(self.signing_region())
+            .with_endpoint(self.endpoint())
             .with_bucket_name(&self.bucket_name)
-            .with_virtual_hosted_style_request(!self.use_path_style)
+            // accelerate endpoint is bucket specific, requests can't be path style
+            .with_virtual_hosted_style_request(self.transfer_acceleration || !self.use_path_style)
+            // conditional puts are sent with If-None-Match, only used when asked for
+            .with_conditional_put(S3ConditionalPut::ETagMatch)
             .with_allow_http(true);
 
         if self.set_checksum {
             builder = builder.with_checksum_algorithm(Checksum::SHA256)
         }
 
-        if let Some((access_key, secret_key)) =
-            self.access_key_id.as_ref().zip(self.secret_key.as_ref())
-        {
-            builder = builder
-                .with_access_key_id(access_key)
-                .with_secret_access_key(secret_key);
+        if self.anonymous {
+            // skip the credential chain entirely, requests are sent unsigned
+            return builder
+                .with_skip_signature(true)
+                .with_client_options(client_options);
         }
 
-        if let Ok(relative_uri) = std::env::var(AWS_CONTAINER_CREDENTIALS_RELATIVE_URI) {
-            builder = builder.with_config(
-                AmazonS3ConfigKey::ContainerCredentialsRelativeUri,
-                relative_uri,
-            );
+        match self.credentials() {
+            Some(Credentials::Keys {
+                access_key,
+                secret_key,
+                token,
+            }) => {
+                builder = builder
+                    .with_access_key_id(access_key)
+                    .with_secret_access_key(secret_key);
+                if let Some(token) = token {
+                    builder = builder.with_token(token);
+                }
+            }
+            Some(Credentials::Container { relative_uri }) => {
+                builder = builder.with_config(
+                    AmazonS3ConfigKey::ContainerCredentialsRelativeUri,
+                    relative_uri,
+                );
+            }
+            // the client assumes the role or falls back to instance metadata by itself
+            Some(Credentials::AssumeRole | Credentials::Imds) | None => {}
         }
 
         if self.imdsv1_fallback {
@@ -175,76 +591,317 @@ impl S3Config {
 
         builder.with_client_options(client_options)
     }
-}
 
-impl ObjectStorageProvider for S3Config {
-    fn get_datafusion_runtime(&self) -> RuntimeConfig {
-        let s3 = self.get_default_builder().build().unwrap();
+    /// Credentials of the first source of the credential chain set up
+    fn credentials(&self) -> Option<Credentials> {
+        let resolved = credential_chain::resolve(
+            &self.credential_chain,
+            self.access_key_id
+                .as_deref()
+                .zip(self.secret_key.as_deref()),
+            |name| std::env::var(name).ok(),
+        );
+        CREDENTIAL_SOURCE_LOGGED.call_once(|| match &resolved {
+            Some((source, _)) => log::info!("S3 credentials are supplied by {source:?}"),
+            None => log::warn!(
+                "no source of the S3 credential chain {:?} is set up",
+                self.credential_chain
+            ),
+        });
+        resolved.map(|(_, credentials)| credentials)
+    }
+
+    /// Credentials shared by the clients of a bucket, replaced when a request is
+    /// rejected with expired ones. Unsigned requests have none.
+    fn refreshable_credentials(&self) -> Option<Arc<RefreshableCredentials>> {
+        if self.anonymous {
+            return None;
+        }
+        let config = self.clone();
+        let credentials = RefreshableCredentials::new(move || {
+            // a new provider starts from the credential chain, without cached credentials
+            let s3 = config.get_default_builder().build()?;
+            Ok(Arc::clone(s3.credentials()))
+        });
+        // checked by validate_config on startup, a failure here leaves the client's own provider
+        match credentials {
+            Ok(credentials) => Some(credentials),
+            Err(err) => {
+                log::error!("failed to create S3 credential provider: {err}");
+                None
+            }
+        }
+    }
+
+    fn build_client(
+        &self,
+        storage_class: Option<StorageClass>,
+        credentials: &Option<Arc<RefreshableCredentials>>,
+    ) -> S3Client {
+        let mut builder = self.get_builder(storage_class);
+        if let Some(credentials) = credentials {
+            builder = builder.with_credentials(Arc::clone(credentials) as AwsCredentialProvider);
+        }
+        let s3 = CredentialRetry::new(builder.build().unwrap(), credentials.clone());
+        let s3 = ThrottleRetry::new(s3, Duration::from_secs(self.max_retry_after_secs));
+        let s3 = TimeoutStore::new(s3, Duration::from_secs(self.operation_timeout_secs));
 
         // limit objectstore to a concurrent request limit
-        let s3 = LimitStore::new(s3, super::MAX_OBJECT_STORE_REQUESTS);
-        let s3 = MetricLayer::new(s3);
+        self.limit_requests(s3)
+    }
 
-        let object_store_registry: DefaultObjectStoreRegistry = DefaultObjectStoreRegistry::new();
-        let url = ObjectStoreUrl::parse(format!("s3://{}", &self.bucket_name)).unwrap();
-        object_store_registry.register_store(url.as_ref(), Arc::new(s3));
+    fn consistency_wait(&self) -> ConsistencyWait {
+        if self.is_aws_endpoint() {
+            return ConsistencyWait::default();
+        }
+        ConsistencyWait::new(
+            self.consistency_wait_retries,
+            Duration::from_millis(self.consistency_wait_backoff_ms),
+        )
+    }
 
-        RuntimeConfig::new().with_object_store_registry(Arc::new(object_store_registry))
+    fn at_bucket(&self, bucket_name: &str) -> S3Config {
+        S3Config {
+            bucket_name: bucket_name.to_owned(),
+            ..self.clone()
+        }
     }
 
-    fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send> {
-        let s3 = self.get_default_builder().build().unwrap();
+    fn build_store(&self, overwrite_policy: OverwritePolicy) -> S3 {
+        let credentials = self.refreshable_credentials();
+        let s3 = self.build_client(None, &credentials);
 
-        // limit objectstore to a concurrent request limit
-        let s3 = LimitStore::new(s3, super::MAX_OBJECT_STORE_REQUESTS);
+        let storage_classes = self.storage_classes();
+        let upload_clients = storage_classes
+            .all()
+            .into_iter()
+            .map(|class| (class, self.build_client(Some(class), &credentials)))
+            .collect();
 
-        Arc::new(S3 {
+        S3 {
             client: s3,
+            upload_clients,
+            storage_classes,
+            credentials,
             bucket: self.bucket_name.clone(),
             root: StorePath::from(""),
-        })
+            slow_log_threshold: self.slow_log_ms.map(Duration::from_millis),
+            stream_metrics: self.stream_metrics,
+            overwrite_policy,
+            consistency_wait: self.consistency_wait(),
+        }
+    }
+}
+
+impl ObjectStorageProvider for S3Config {
+    fn get_datafusion_runtime(&self) -> RuntimeConfig {
+        let object_store_registry: DefaultObjectStoreRegistry = DefaultObjectStoreRegistry::new();
+
+        // streams stored in a bucket of their own are queried from that bucket,
+        // partitions in the cold tier from the cold bucket
+        let buckets = std::iter::once(self.bucket_name.as_str())
+            .chain(CONFIG.parseable.stream_storage.values().map(String::as_str))
+            .chain(CONFIG.parseable.cold_storage.as_deref());
+        for bucket in buckets {
+            let config = self.at_bucket(bucket);
+            let s3 = config.build_client(None, &config.refreshable_credentials());
+            let s3 = MetricLayer::new(s3);
+
+            let url = ObjectStoreUrl::parse(format!("s3://{bucket}")).unwrap();
+            object_store_registry.register_store(url.as_ref(), Arc::new(s3));
+        }
+
+        RuntimeConfig::new().with_object_store_registry(Arc::new(object_store_registry))
+    }
+
+    fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send> {
+        let store = metadata_store::route_metadata(
+            Arc::new(self.build_store(CONFIG.parseable.object_overwrite_policy)),
+            self,
+        );
+        routing::route_streams(store, self)
+    }
+
+    fn get_object_store_at(&self, location: &str) -> Arc<dyn ObjectStorage + Send> {
+        Arc::new(
+            self.at_bucket(location)
+                .build_store(CONFIG.parseable.object_overwrite_policy),
+        )
+    }
+
+    fn get_raw_store(&self) -> Result<Arc<dyn ObjectStore>, ObjectStorageError> {
+        Ok(Arc::new(
+            self.build_client(None, &self.refreshable_credentials()),
+        ))
     }
 
     fn get_endpoint(&self) -> String {
-        format!("{}/{}", self.endpoint_url, self.bucket_name)
+        bucket_url(
+            &self.endpoint(),
+            &self.bucket_name,
+            self.transfer_acceleration || !self.use_path_style,
+        )
     }
 
     fn register_store_metrics(&self, handler: &actix_web_prometheus::PrometheusMetrics) {
         self.register_metrics(handler)
     }
+
+    fn validate_config(&self) -> Result<(), ObjectStorageError> {
+        self.get_default_builder()
+            .build()
+            .map(|_| ())
+            .map_err(|err| ObjectStorageError::Custom(format!("invalid S3 configuration: {err}")))
+    }
+
+    fn get_notification_queue(&self) -> Option<SqsQueue> {
+        let queue_url = self.sqs_queue_url.as_deref()?;
+        let Some(credentials) = self.refreshable_credentials() else {
+            log::warn!("SQS queue {queue_url} can't be read with anonymous credentials");
+            return None;
+        };
+        match SqsQueue::new(queue_url, self.region(), credentials) {
+            Ok(queue) => Some(queue),
+            Err(err) => {
+                log::warn!("invalid SQS queue url {queue_url}: {err}");
+                None
+            }
+        }
+    }
+}
+
+/// Stream whose directory `key` is in, none for server metadata, users and root objects
+fn stream_of_key(key: &str) -> Option<&str> {
+    let (stream, _) = key.split_once('/')?;
+    if stream.is_empty() || stream == PARSEABLE_ROOT_DIRECTORY || stream == USERS_ROOT_DIR {
+        return None;
+    }
+    Some(stream)
+}
+
+/// Operations are slow only when a threshold is set and they take longer than it
+fn is_slow(threshold: Option<Duration>, elapsed: Duration) -> bool {
+    threshold.is_some_and(|threshold| elapsed > threshold)
+}
+
+/// URL of the bucket at `endpoint`. Path style endpoints are joined with the bucket
+/// after any path prefix, virtual hosted endpoints already name the bucket in the host.
+fn bucket_url(endpoint: &str, bucket: &str, virtual_hosted: bool) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if virtual_hosted {
+        endpoint.to_owned()
+    } else {
+        format!("{endpoint}/{bucket}")
+    }
+}
+
+/// ETags are quoted and may be marked weak, only the value identifies the content
+fn normalize_etag(etag: &str) -> String {
+    etag.trim_start_matches("W/").trim_matches('"').to_owned()
 }
 
-fn to_object_store_path(path: &RelativePath) -> StorePath {
-    StorePath::from(path.as_str())
+fn to_object_store_path(path: &RelativePath) -> Result<StorePath, ObjectStorageError> {
+    Ok(ObjectKey::new(path)?.to_store_path())
 }
 
 pub struct S3 {
-    client: LimitStore<AmazonS3>,
+    client: S3Client,
+    // clients sending a storage class header, keyed by the class
+    upload_clients: HashMap<StorageClass, S3Client>,
+    storage_classes: StorageClasses,
+    credentials: Option<Arc<RefreshableCredentials>>,
     bucket: String,
     root: StorePath,
+    slow_log_threshold: Option<Duration>,
+    stream_metrics: bool,
+    overwrite_policy: OverwritePolicy,
+    consistency_wait: ConsistencyWait,
 }
 
 impl S3 {
+    /// Client to upload `key` with, sets the storage class configured for its stream
+    fn upload_client(&self, key: &str) -> &S3Client {
+        self.storage_classes
+            .for_key(key)
+            .and_then(|class| self.upload_clients.get(&class))
+            .unwrap_or(&self.client)
+    }
+
+    /// Records request latency against the stream owning `key`, if enabled.
+    /// Keys outside of a stream (server metadata, users) are not recorded.
+    fn observe_stream_request(&self, key: &str, method: &str, status: &str, elapsed: Duration) {
+        if !self.stream_metrics {
+            return;
+        }
+        let Some(stream) = stream_of_key(key) else {
+            return;
+        };
+        STREAM_REQUEST_RESPONSE_TIME
+            .with_label_values(&[stream, method, status])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    fn log_if_slow(&self, method: &str, key: &str, elapsed: Duration) {
+        if is_slow(self.slow_log_threshold, elapsed) {
+            log::warn!(
+                "slow object store operation method={} key={} elapsed_ms={} request_id={}",
+                method,
+                key,
+                elapsed.as_millis(),
+                current_request_id().unwrap_or_default()
+            );
+        }
+    }
+
     async fn _get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
         let instant = Instant::now();
 
-        let resp = self.client.get(&to_object_store_path(path)).await;
+        let key = to_object_store_path(path)?;
+        let resp: Result<Bytes, ObjectStorageError> = if etag_cache::is_cacheable(path.as_str()) {
+            etag_cache::get(&self.client, &key)
+                .await
+                .map_err(Into::into)
+        } else {
+            match self.client.get(&key).await {
+                // dropping the response without reading aborts the transfer
+                Ok(resp) => match check_object_size(
+                    path.as_str(),
+                    resp.meta.size,
+                    CONFIG.parseable.max_object_read_size,
+                ) {
+                    Ok(()) => resp.bytes().await.map_err(Into::into),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err.into()),
+            }
+        };
+        self.log_if_slow("GET", path.as_str(), instant.elapsed());
 
         match resp {
-            Ok(resp) => {
+            Ok(body) => {
+                self.observe_stream_request(path.as_str(), "GET", "200", instant.elapsed());
                 let time = instant.elapsed().as_secs_f64();
                 REQUEST_RESPONSE_TIME
                     .with_label_values(&["GET", "200"])
                     .observe(time);
-                let body = resp.bytes().await.unwrap();
                 Ok(body)
             }
             Err(err) => {
+                // missing keys are expected, e.g. optional stream metadata
+                if !matches!(err, ObjectStorageError::NoSuchKey(_)) {
+                    log::error!(
+                        "object store operation failed method=GET key={} request_id={} error={}",
+                        path,
+                        current_request_id().unwrap_or_default(),
+                        err
+                    );
+                }
+                self.observe_stream_request(path.as_str(), "GET", "400", instant.elapsed());
                 let time = instant.elapsed().as_secs_f64();
                 REQUEST_RESPONSE_TIME
                     .with_label_values(&["GET", "400"])
                     .observe(time);
-                Err(err.into())
+                Err(err)
             }
         }
     }
@@ -254,28 +911,66 @@ impl S3 {
         path: &RelativePath,
         resource: Bytes,
     ) -> Result<(), ObjectStorageError> {
+        let policy = self.overwrite_policy.for_key(path.as_str());
+        let opts = PutOptions {
+            mode: policy.put_mode(),
+            ..PutOptions::default()
+        };
         let time = Instant::now();
-        let resp = self.client.put(&to_object_store_path(path), resource).await;
+        // an existing object is only found by the conditional put, overwrites make no extra request
+        let resp = match self
+            .upload_client(path.as_str())
+            .put_opts(&to_object_store_path(path)?, resource, opts)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(
+                object_store::Error::AlreadyExists { .. }
+                | object_store::Error::Precondition { .. },
+            ) => policy.on_existing(path.as_str()).map(|_| false),
+            Err(err) => Err(bucket_error(err, &self.bucket)),
+        };
         let status = if resp.is_ok() { "200" } else { "400" };
+        self.log_if_slow("PUT", path.as_str(), time.elapsed());
+        self.observe_stream_request(path.as_str(), "PUT", status, time.elapsed());
         let time = time.elapsed().as_secs_f64();
         REQUEST_RESPONSE_TIME
             .with_label_values(&["PUT", status])
             .observe(time);
 
-        if let Err(object_store::Error::NotFound { source, .. }) = &resp {
-            let source_str = source.to_string();
-            if source_str.contains("<Code>NoSuchBucket</Code>") {
-                return Err(ObjectStorageError::Custom(
-                    format!("Bucket '{}' does not exist in S3.", self.bucket).to_string(),
-                ));
-            }
+        if let Err(err) = &resp {
+            log::error!(
+                "object store operation failed method=PUT key={} request_id={} error={}",
+                path,
+                current_request_id().unwrap_or_default(),
+                err
+            );
+        }
+
+        // an object kept by the policy is not waited for
+        if resp? {
+            self.wait_until_visible(path.as_str()).await?;
         }
+        Ok(())
+    }
 
-        resp.map(|_| ()).map_err(|err| err.into())
+    /// Waits for an object just written to be found, see `ConsistencyWait`
+    async fn wait_until_visible(&self, key: &str) -> Result<(), ObjectStorageError> {
+        let location = to_object_store_path(RelativePath::new(key))?;
+        self.consistency_wait
+            .until_visible(&self.client, &location)
+            .await
+            .map_err(|err| match err {
+                object_store::Error::NotFound { .. } => {
+                    ObjectStorageError::Custom(format!("{key} is not visible after it was written"))
+                }
+                err => bucket_error(err, &self.bucket),
+            })
     }
 
     async fn _delete_prefix(&self, key: &str) -> Result<(), ObjectStorageError> {
-        let object_stream = self.client.list(Some(&(key.into())));
+        let prefix = to_object_store_path(RelativePath::new(key))?;
+        let object_stream = self.client.list(Some(&prefix));
 
         object_stream
             .for_each_concurrent(None, |x| async {
@@ -296,7 +991,9 @@ impl S3 {
     }
 
     async fn _list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        let instant = Instant::now();
         let resp = self.client.list_with_delimiter(None).await?;
+        self.log_if_slow("LIST", "/", instant.elapsed());
 
         let common_prefixes = resp.common_prefixes; // get all dirs
 
@@ -306,6 +1003,8 @@ impl S3 {
             .filter_map(|path| path.parts().next())
             .map(|name| name.as_ref().to_string())
             .filter(|x| x != PARSEABLE_ROOT_DIRECTORY)
+            .filter(|x| x != QUARANTINE_ROOT_DIRECTORY)
+            .filter(|x| x != TRASH_ROOT_DIRECTORY)
             .filter(|x| x != USERS_ROOT_DIR)
             .collect();
 
@@ -326,10 +1025,12 @@ impl S3 {
     }
 
     async fn _list_dates(&self, stream: &str) -> Result<Vec<String>, ObjectStorageError> {
+        let instant = Instant::now();
         let resp = self
             .client
-            .list_with_delimiter(Some(&(stream.into())))
+            .list_with_delimiter(Some(&to_object_store_path(RelativePath::new(stream))?))
             .await?;
+        self.log_if_slow("LIST", stream, instant.elapsed());
 
         let common_prefixes = resp.common_prefixes;
 
@@ -344,64 +1045,103 @@ impl S3 {
     }
 
     async fn _upload_file(&self, key: &str, path: &StdPath) -> Result<(), ObjectStorageError> {
+        let location = to_object_store_path(RelativePath::new(key))?;
+        // multipart uploads can't be conditional, the other policies look for the object first
+        if self.overwrite_policy != OverwritePolicy::Overwrite {
+            let upload = self
+                .overwrite_policy
+                .allows_upload(key, || self.exists(RelativePath::new(key)))
+                .await?;
+            if !upload {
+                return Ok(());
+            }
+        }
         let instant = Instant::now();
 
         let should_multipart = std::fs::metadata(path)?.len() > MULTIPART_UPLOAD_SIZE as u64;
 
         let res = if should_multipart {
-            self._upload_multipart(key, path).await
+            // parts are sent by the writer without retries, an upload
+            // failing on expired credentials is started over
+            match &self.credentials {
+                Some(credentials) => {
+                    credentials
+                        .retry(|| self._upload_multipart(key, path))
+                        .await
+                }
+                None => self._upload_multipart(key, path).await,
+            }
         } else {
             let bytes = tokio::fs::read(path).await?;
-            let result = self.client.put(&key.into(), bytes.into()).await?;
-            log::info!("Uploaded file to S3: {:?}", result);
-            Ok(())
+            let opts = PutOptions {
+                mode: self.overwrite_policy.put_mode(),
+                ..PutOptions::default()
+            };
+            // an object written since the existence check is detected by the conditional put
+            match self
+                .upload_client(key)
+                .put_opts(&location, bytes.into(), opts)
+                .await
+            {
+                Ok(result) => {
+                    log::info!("Uploaded file to S3: {:?}", result);
+                    Ok(())
+                }
+                Err(
+                    object_store::Error::AlreadyExists { .. }
+                    | object_store::Error::Precondition { .. },
+                ) => self.overwrite_policy.on_existing(key).map(|_| ()),
+                Err(err) => Err(err.into()),
+            }
         };
 
         let status = if res.is_ok() { "200" } else { "400" };
+        self.log_if_slow("UPLOAD_PARQUET", key, instant.elapsed());
+        self.observe_stream_request(key, "UPLOAD_PARQUET", status, instant.elapsed());
         let time = instant.elapsed().as_secs_f64();
         REQUEST_RESPONSE_TIME
             .with_label_values(&["UPLOAD_PARQUET", status])
             .observe(time);
 
-        res
+        res?;
+        self.wait_until_visible(key).await
     }
 
     async fn _upload_multipart(&self, key: &str, path: &StdPath) -> Result<(), ObjectStorageError> {
         let mut buf = vec![0u8; MULTIPART_UPLOAD_SIZE / 2];
         let mut file = OpenOptions::new().read(true).open(path).await?;
 
-        let (multipart_id, mut async_writer) = self.client.put_multipart(&key.into()).await?;
-
-        let close_multipart = |err| async move {
-            log::error!("multipart upload failed. {:?}", err);
-            self.client
-                .abort_multipart(&key.into(), &multipart_id)
-                .await
-        };
+        let location = to_object_store_path(RelativePath::new(key))?;
+        let client = self.upload_client(key);
+        let (multipart_id, mut async_writer) = client.put_multipart(&location).await?;
 
-        loop {
-            match file.read(&mut buf).await {
-                Ok(len) => {
-                    if len == 0 {
-                        break;
-                    }
-                    if let Err(err) = async_writer.write_all(&buf[0..len]).await {
-                        close_multipart(err).await?;
-                        break;
-                    }
-                    if let Err(err) = async_writer.flush().await {
-                        close_multipart(err).await?;
-                        break;
-                    }
-                }
-                Err(err) => {
-                    close_multipart(err).await?;
+        let upload = async {
+            loop {
+                let len = file.read(&mut buf).await?;
+                if len == 0 {
                     break;
                 }
+                async_writer.write_all(&buf[0..len]).await?;
+                async_writer.flush().await?;
             }
-        }
+            async_writer.shutdown().await
+        };
+        // uploads still running when the shutdown timeout passes are aborted
+        let res = tokio::select! {
+            res = upload => res,
+            _ = UPLOADS.aborted() => Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "server is shutting down",
+            )),
+        };
 
-        async_writer.shutdown().await?;
+        if let Err(err) = res {
+            log::error!("multipart upload failed. {:?}", err);
+            if let Err(abort_err) = client.abort_multipart(&location, &multipart_id).await {
+                log::error!("failed to abort multipart upload. {:?}", abort_err);
+            }
+            return Err(ObjectStorageError::IoError(err));
+        }
 
         Ok(())
     }
@@ -421,14 +1161,14 @@ impl ObjectStorage for S3 {
         let instant = Instant::now();
 
         let prefix = if let Some(base_path) = base_path {
-            to_object_store_path(base_path)
+            to_object_store_path(base_path)?
         } else {
             self.root.clone()
         };
 
         let mut list_stream = self.client.list(Some(&prefix));
 
-        let mut res = vec![];
+        let mut paths = vec![];
 
         while let Some(meta) = list_stream.next().await.transpose()? {
             let ingestor_file = filter_func(meta.location.filename().unwrap().to_string());
@@ -437,16 +1177,24 @@ impl ObjectStorage for S3 {
                 continue;
             }
 
-            let byts = self
-                .get_object(
-                    RelativePath::from_path(meta.location.as_ref())
-                        .map_err(ObjectStorageError::PathError)?,
-                )
-                .await?;
-
-            res.push(byts);
+            paths.push(
+                RelativePath::from_path(meta.location.as_ref())
+                    .map_err(ObjectStorageError::PathError)?
+                    .to_relative_path_buf(),
+            );
         }
 
+        let paths = paths
+            .iter()
+            .map(|path| path.as_relative_path())
+            .collect_vec();
+        let res = self
+            .get_objects_by_path(&paths)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.log_if_slow("LIST", prefix.as_ref(), instant.elapsed());
         let instant = instant.elapsed().as_secs_f64();
         REQUEST_RESPONSE_TIME
             .with_label_values(&["GET", "200"])
@@ -455,6 +1203,81 @@ impl ObjectStorage for S3 {
         Ok(res)
     }
 
+    async fn get_objects_by_path(
+        &self,
+        paths: &[&RelativePath],
+    ) -> Vec<Result<Bytes, ObjectStorageError>> {
+        futures::stream::iter(paths)
+            .map(|path| self._get_object(path))
+            .buffered(MAX_CONCURRENT_GETS)
+            .collect()
+            .await
+    }
+
+    async fn get_object_suffix(
+        &self,
+        path: &RelativePath,
+        len: usize,
+    ) -> Result<Bytes, ObjectStorageError> {
+        let instant = Instant::now();
+        let options = GetOptions {
+            range: Some(GetRange::Suffix(len)),
+            ..GetOptions::default()
+        };
+
+        let resp = self
+            .client
+            .get_opts(&to_object_store_path(path)?, options)
+            .await;
+        self.log_if_slow("GET", path.as_str(), instant.elapsed());
+
+        let status = if resp.is_ok() { "200" } else { "400" };
+        self.observe_stream_request(path.as_str(), "GET", status, instant.elapsed());
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["GET", status])
+            .observe(instant.elapsed().as_secs_f64());
+        Ok(resp?.bytes().await?)
+    }
+
+    async fn exists(&self, path: &RelativePath) -> Result<bool, ObjectStorageError> {
+        let instant = Instant::now();
+        let resp = self.client.head(&to_object_store_path(path)?).await;
+        self.log_if_slow("HEAD", path.as_str(), instant.elapsed());
+
+        let status = match &resp {
+            Ok(_) => "200",
+            Err(object_store::Error::NotFound { .. }) => "404",
+            Err(_) => "400",
+        };
+        self.observe_stream_request(path.as_str(), "HEAD", status, instant.elapsed());
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["HEAD", status])
+            .observe(instant.elapsed().as_secs_f64());
+
+        match resp {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(bucket_error(err, &self.bucket)),
+        }
+    }
+
+    async fn object_checksum(&self, path: &RelativePath) -> Result<String, ObjectStorageError> {
+        let instant = Instant::now();
+        let resp = self.client.head(&to_object_store_path(path)?).await;
+        self.log_if_slow("HEAD", path.as_str(), instant.elapsed());
+
+        let status = if resp.is_ok() { "200" } else { "400" };
+        self.observe_stream_request(path.as_str(), "HEAD", status, instant.elapsed());
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["HEAD", status])
+            .observe(instant.elapsed().as_secs_f64());
+
+        resp?
+            .e_tag
+            .map(|etag| normalize_etag(&etag))
+            .ok_or_else(|| ObjectStorageError::Custom(format!("no etag returned for {path}")))
+    }
+
     async fn get_ingestor_meta_file_paths(
         &self,
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
@@ -484,7 +1307,7 @@ impl ObjectStorage for S3 {
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
         let time = Instant::now();
         let mut path_arr = vec![];
-        let path = to_object_store_path(&RelativePathBuf::from(stream_name));
+        let path = to_object_store_path(&RelativePathBuf::from(stream_name))?;
         let mut object_stream = self.client.list(Some(&path));
 
         while let Some(meta) = object_stream.next().await.transpose()? {
@@ -521,20 +1344,81 @@ impl ObjectStorage for S3 {
         Ok(())
     }
 
+    async fn put_object_if_not_exists(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+    ) -> Result<(), ObjectStorageError> {
+        let time = Instant::now();
+        let opts = PutOptions {
+            mode: PutMode::Create,
+            ..PutOptions::default()
+        };
+        let resp = self
+            .client
+            .put_opts(&to_object_store_path(path)?, resource, opts)
+            .await;
+        let status = if resp.is_ok() { "200" } else { "400" };
+        self.log_if_slow("PUT_IF_NOT_EXISTS", path.as_str(), time.elapsed());
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["PUT_IF_NOT_EXISTS", status])
+            .observe(time.elapsed().as_secs_f64());
+
+        match resp {
+            Ok(_) => Ok(()),
+            Err(
+                object_store::Error::AlreadyExists { .. }
+                | object_store::Error::Precondition { .. },
+            ) => Err(ObjectStorageError::AlreadyExists(path.to_string())),
+            Err(err) => Err(bucket_error(err, &self.bucket)),
+        }
+    }
+
     async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
         self._delete_prefix(path.as_ref()).await?;
 
         Ok(())
     }
 
+    async fn move_prefix(
+        &self,
+        from: &RelativePath,
+        to: &RelativePath,
+    ) -> Result<(), ObjectStorageError> {
+        let from = to_object_store_path(from)?;
+        let to = to_object_store_path(to)?;
+        // S3 has no rename, each object is copied and then deleted
+        self.client
+            .list(Some(&from))
+            .map_err(ObjectStorageError::from)
+            .try_for_each_concurrent(None, |meta| {
+                let (from, to) = (&from, &to);
+                async move {
+                    let suffix = meta
+                        .location
+                        .prefix_match(from)
+                        .expect("listed under the prefix");
+                    let dest = StorePath::from_iter(to.parts().chain(suffix));
+                    Ok(self.client.rename(&meta.location, &dest).await?)
+                }
+            })
+            .await
+    }
+
     async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
-        Ok(self.client.delete(&to_object_store_path(path)).await?)
+        Ok(self.client.delete(&to_object_store_path(path)?).await?)
     }
 
     async fn check(&self) -> Result<(), ObjectStorageError> {
+        // head responses have no body, so a missing bucket is only told apart by a listing
+        self.client
+            .list_with_delimiter(None)
+            .await
+            .map_err(|err| bucket_error(err, &self.bucket))?;
+
         Ok(self
             .client
-            .head(&to_object_store_path(&parseable_json_path()))
+            .head(&to_object_store_path(&parseable_json_path())?)
             .await
             .map(|_| ())?)
     }
@@ -550,7 +1434,7 @@ impl ObjectStorage for S3 {
         ingestor_filename: String,
     ) -> Result<(), ObjectStorageError> {
         let file = RelativePathBuf::from(&ingestor_filename);
-        match self.client.delete(&to_object_store_path(&file)).await {
+        match self.client.delete(&to_object_store_path(&file)?).await {
             Ok(_) => Ok(()),
             Err(err) => {
                 // if the object is not found, it is not an error
@@ -583,6 +1467,8 @@ impl ObjectStorage for S3 {
             .filter_map(|path| path.parts().next())
             .map(|name| name.as_ref().to_string())
             .filter(|x| x != PARSEABLE_ROOT_DIRECTORY)
+            .filter(|x| x != QUARANTINE_ROOT_DIRECTORY)
+            .filter(|x| x != TRASH_ROOT_DIRECTORY)
             .collect();
 
         let stream_json_check = FuturesUnordered::new();
@@ -604,7 +1490,27 @@ impl ObjectStorage for S3 {
         Ok(streams)
     }
 
+    fn list_objects_meta(
+        &self,
+        stream_name: &str,
+    ) -> BoxStream<'_, Result<ObjectMetaInfo, ObjectStorageError>> {
+        let prefix = StorePath::from(stream_name);
+        self.client
+            .list(Some(&prefix))
+            .map_err(|err| bucket_error(err, &self.bucket))
+            .try_filter_map(|meta| async move {
+                Ok(
+                    (meta.location.extension() == Some("parquet")).then(|| ObjectMetaInfo {
+                        path: RelativePathBuf::from(meta.location.as_ref()),
+                        size: meta.size as u64,
+                    }),
+                )
+            })
+            .boxed()
+    }
+
     async fn upload_file(&self, key: &str, path: &StdPath) -> Result<(), ObjectStorageError> {
+        let _upload = UPLOADS.start();
         self._upload_file(key, path).await?;
 
         Ok(())
@@ -645,9 +1551,25 @@ impl ObjectStorage for S3 {
     }
 }
 
+// S3 reports a missing bucket through the error code in the response body
+fn bucket_error(err: object_store::Error, bucket: &str) -> ObjectStorageError {
+    match &err {
+        object_store::Error::Generic { source, .. }
+        | object_store::Error::NotFound { source, .. }
+            if source.to_string().contains("NoSuchBucket") =>
+        {
+            ObjectStorageError::BucketNotFound(bucket.to_owned())
+        }
+        _ => err.into(),
+    }
+}
+
 impl From<object_store::Error> for ObjectStorageError {
     fn from(error: object_store::Error) -> Self {
         match error {
+            object_store::Error::Generic { source, .. } if source.is::<OperationTimeout>() => {
+                ObjectStorageError::Timeout(source.to_string())
+            }
             object_store::Error::Generic { source, .. } => {
                 ObjectStorageError::UnhandledError(source)
             }
@@ -662,3 +1584,655 @@ impl From<serde_json::Error> for ObjectStorageError {
         ObjectStorageError::UnhandledError(Box::new(error))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use object_store::{aws::AmazonS3ConfigKey, ClientConfigKey};
+    use rstest::rstest;
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use relative_path::RelativePath;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::{
+        bucket_error, bucket_url, is_slow, normalize_etag, stream_of_key, CredentialSource,
+        IpVersion, S3Config, StorageClass,
+    };
+    use crate::storage::{overwrite::OverwritePolicy, ObjectStorage, ObjectStorageError};
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        s3: S3Config,
+    }
+
+    // self signed, only parsed and never used to verify a connection
+    const TEST_CA_CERT: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBjzCCATWgAwIBAgIUHFsnVEhgtnqFwppf+8QSlVQAaC0wCgYIKoZIzj0EAwIw\n\
+HDEaMBgGA1UEAwwRcGFyc2VhYmxlLXRlc3QtY2EwIBcNMjYxMDE2MDY0MzEzWhgP\n\
+MjEyNjA5MjIwNjQzMTNaMBwxGjAYBgNVBAMMEXBhcnNlYWJsZS10ZXN0LWNhMFkw\n\
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEOULf1HDTEmp1ffDUbqudFwnyC+cJt0ro\n\
+KenpElXtOqEZaP+b/TetSDBglTJ4y8i9kuPsl59k4QJ0MAEkJNms66NTMFEwHQYD\n\
+VR0OBBYEFCYLaLK7h257E/9GOlRpEZru2uq/MB8GA1UdIwQYMBaAFCYLaLK7h257\n\
+E/9GOlRpEZru2uq/MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIh\n\
+AMZPq36vRNEHb87Q3BLjZ4Ub3X7TtXR1xYxLZnmZ3RHgAiBCAGjOd8dSc48WUf3k\n\
+hFPbMG6kO/l1KimHiplx0J4lAw==\n\
+-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn anonymous_mode_skips_signing_and_rejects_credentials() {
+        let args = [
+            "parseable",
+            "--endpoint-url",
+            "http://localhost:9000",
+            "--region",
+            "us-east-1",
+            "--bucket-name",
+            "logs",
+        ];
+        let skip_signature = |cli: TestCli| {
+            cli.s3
+                .get_default_builder()
+                .get_config_value(&AmazonS3ConfigKey::SkipSignature)
+        };
+
+        let cli = TestCli::try_parse_from(args.into_iter().chain(["--anonymous", "true"]));
+        assert_eq!(skip_signature(cli.unwrap()), Some("true".to_string()));
+        let cli = TestCli::try_parse_from(args);
+        assert_eq!(skip_signature(cli.unwrap()), Some("false".to_string()));
+
+        // credentials would never be used, asking for them is a configuration error
+        for credentials in [
+            ["--access-key-id", "key"],
+            ["--secret-key", "secret"],
+            ["--imdsv1-fallback", "true"],
+            ["--metadata-endpoint", "http://169.254.169.254"],
+        ] {
+            let res = TestCli::try_parse_from(
+                args.into_iter()
+                    .chain(["--anonymous", "true"])
+                    .chain(credentials),
+            );
+            assert!(res.is_err(), "{credentials:?} accepted in anonymous mode");
+        }
+    }
+
+    #[test]
+    fn slow_operations_are_over_the_threshold() {
+        let ms = Duration::from_millis;
+        assert!(is_slow(Some(ms(100)), ms(101)));
+        assert!(!is_slow(Some(ms(100)), ms(100)));
+        // without a threshold nothing is logged
+        assert!(!is_slow(None, Duration::from_secs(3600)));
+
+        let args = [
+            "parseable",
+            "--endpoint-url",
+            "http://localhost:9000",
+            "--region",
+            "us-east-1",
+            "--bucket-name",
+            "logs",
+            "--slow-log-ms",
+        ];
+        let cli = TestCli::try_parse_from(args.into_iter().chain(["250"])).unwrap();
+        assert_eq!(cli.s3.slow_log_ms, Some(250));
+        for threshold in ["-1", "0.5", "1s"] {
+            let res = TestCli::try_parse_from(args.into_iter().chain([threshold]));
+            assert!(res.is_err(), "{threshold} accepted as a threshold");
+        }
+    }
+
+    #[test]
+    fn ca_certificate_is_trusted_through_a_bypassed_proxy() {
+        let dir = std::env::temp_dir().join(format!("parseable-ca-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let valid = write("ca.pem", TEST_CA_CERT);
+        let not_pem = write("ca.txt", "not a certificate");
+        let not_der = write(
+            "broken.pem",
+            "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n",
+        );
+        let missing = dir.join("missing.pem").to_string_lossy().into_owned();
+
+        let args = [
+            "parseable",
+            "--endpoint-url",
+            "https://minio.internal:9000",
+            "--region",
+            "us-east-1",
+            "--bucket-name",
+            "logs",
+            "--ca-cert-path",
+        ];
+        let parse = |path: &str, extra: &[&str]| {
+            TestCli::try_parse_from(args.iter().chain([&path]).chain(extra))
+        };
+        let cli = parse(&valid, &[]);
+        let rejected = [&not_pem, &not_der, &missing].map(|path| parse(path, &[]).is_err());
+        let conflicting = parse(&valid, &["--skip-tls", "true"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let builder = cli.unwrap().s3.get_default_builder();
+        let client_config = |key| builder.get_config_value(&AmazonS3ConfigKey::Client(key));
+        assert_eq!(
+            client_config(ClientConfigKey::ProxyCaCertificate).as_deref(),
+            Some(TEST_CA_CERT)
+        );
+        assert_eq!(
+            client_config(ClientConfigKey::ProxyExcludes).as_deref(),
+            Some("*,0.0.0.0/0,::/0")
+        );
+        assert_eq!(rejected, [true; 3]);
+        // skipping verification would make the certificate pointless
+        assert!(conflicting.is_err());
+    }
+
+    #[test]
+    fn only_keys_in_a_stream_are_labelled_with_it() {
+        assert_eq!(
+            stream_of_key("app/date=2024-01-01/data.parquet"),
+            Some("app")
+        );
+        assert_eq!(stream_of_key("app/.stream/.stream.json"), Some("app"));
+
+        // one label value per key outside of streams would grow without bound
+        for key in [
+            ".parseable/.parseable.json",
+            ".users/admin/filters/errors.json",
+            "/app/data.parquet",
+            "orphan.json",
+            "",
+        ] {
+            assert_eq!(stream_of_key(key), None, "{key:?} labelled as a stream");
+        }
+    }
+
+    #[test]
+    fn transfer_acceleration_uses_accelerate_endpoint() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--region",
+            "us-east-1",
+            "--bucket-name",
+            "logs",
+            "--transfer-acceleration",
+        ])
+        .unwrap();
+
+        let builder = cli.s3.get_default_builder();
+
+        assert_eq!(
+            builder.get_config_value(&AmazonS3ConfigKey::Endpoint),
+            Some("https://logs.s3-accelerate.amazonaws.com".to_string())
+        );
+        assert_eq!(
+            builder.get_config_value(&AmazonS3ConfigKey::VirtualHostedStyleRequest),
+            Some("true".to_string())
+        );
+    }
+
+    #[test]
+    fn transfer_acceleration_conflicts_with_endpoint() {
+        let res = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "http://localhost:9000",
+            "--region",
+            "us-east-1",
+            "--bucket-name",
+            "logs",
+            "--transfer-acceleration",
+        ]);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn region_defaults_for_custom_endpoint() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "http://localhost:9000",
+            "--bucket-name",
+            "logs",
+        ])
+        .unwrap();
+
+        assert!(!cli.s3.is_aws_endpoint());
+        assert_eq!(
+            cli.s3
+                .get_default_builder()
+                .get_config_value(&AmazonS3ConfigKey::Region),
+            Some("us-east-1".to_string())
+        );
+    }
+
+    #[test]
+    fn region_required_with_transfer_acceleration() {
+        let res = TestCli::try_parse_from([
+            "parseable",
+            "--bucket-name",
+            "logs",
+            "--transfer-acceleration",
+        ]);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn credential_chain_decides_over_static_keys() {
+        let args = [
+            "parseable",
+            "--endpoint-url",
+            "http://localhost:9000",
+            "--bucket-name",
+            "logs",
+            "--access-key-id",
+            "static",
+            "--secret-key",
+            "secret",
+        ];
+        let cli = TestCli::try_parse_from(args).unwrap();
+        assert_eq!(
+            cli.s3
+                .get_default_builder()
+                .get_config_value(&AmazonS3ConfigKey::AccessKeyId),
+            Some("static".to_string())
+        );
+
+        let cli = TestCli::try_parse_from(
+            args.into_iter()
+                .chain(["--credential-chain", "imds,static"]),
+        )
+        .unwrap();
+        assert_eq!(
+            cli.s3.credential_chain,
+            [CredentialSource::Imds, CredentialSource::Static]
+        );
+        assert_eq!(
+            cli.s3
+                .get_default_builder()
+                .get_config_value(&AmazonS3ConfigKey::AccessKeyId),
+            None
+        );
+    }
+
+    #[test]
+    fn aws_endpoint_is_detected() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://s3.eu-west-1.amazonaws.com",
+            "--bucket-name",
+            "logs",
+        ])
+        .unwrap();
+
+        assert!(cli.s3.is_aws_endpoint());
+    }
+
+    #[test]
+    fn signing_region_overrides_region_for_signing() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://s3-gateway.example.com",
+            "--region",
+            "eu-west-1",
+            "--signing-region",
+            "us-east-1",
+            "--bucket-name",
+            "logs",
+        ])
+        .unwrap();
+
+        let builder = cli.s3.get_default_builder();
+
+        assert_eq!(cli.s3.region(), "eu-west-1");
+        assert_eq!(
+            builder.get_config_value(&AmazonS3ConfigKey::Region),
+            Some("us-east-1".to_string())
+        );
+        assert_eq!(
+            builder.get_config_value(&AmazonS3ConfigKey::Endpoint),
+            Some("https://s3-gateway.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn signing_region_defaults_to_region() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://s3.eu-west-1.amazonaws.com",
+            "--region",
+            "eu-west-1",
+            "--bucket-name",
+            "logs",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            cli.s3
+                .get_default_builder()
+                .get_config_value(&AmazonS3ConfigKey::Region),
+            Some("eu-west-1".to_string())
+        );
+    }
+
+    #[test]
+    fn storage_class_is_resolved_per_stream() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://s3.eu-west-1.amazonaws.com",
+            "--region",
+            "eu-west-1",
+            "--bucket-name",
+            "logs",
+            "--storage-class",
+            "STANDARD_IA",
+            "--stream-storage-class",
+            "audit=glacier-ir",
+        ])
+        .unwrap();
+        let classes = cli.s3.storage_classes();
+
+        assert_eq!(
+            classes.for_key("audit/date=2024-01-01/hour=00/minute=00/data.parquet"),
+            Some(StorageClass::GlacierIr)
+        );
+        assert_eq!(
+            classes.for_key("app/date=2024-01-01/hour=00/minute=00/data.parquet"),
+            Some(StorageClass::StandardIa)
+        );
+        assert_eq!(classes.for_key("audit/.stream/.stream.json"), None);
+        assert_eq!(classes.for_key("audit/date=2024-01-01/manifest.json"), None);
+        assert_eq!(StorageClass::GlacierIr.as_header_value(), "GLACIER_IR");
+        // every class in use gets an upload client
+        assert_eq!(
+            cli.s3
+                .build_store(OverwritePolicy::default())
+                .upload_clients
+                .len(),
+            2
+        );
+
+        assert!(TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://s3.eu-west-1.amazonaws.com",
+            "--bucket-name",
+            "logs",
+            "--storage-class",
+            "DEEP_ARCHIVE",
+        ])
+        .is_err());
+    }
+
+    /// Serves every request with an empty 200 response, recording the path and storage
+    /// class header of each PUT
+    async fn recording_server() -> (String, Arc<Mutex<Vec<(String, Option<String>)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let puts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&puts);
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let puts = Arc::clone(&recorded);
+                tokio::spawn(async move {
+                    let mut socket = BufReader::new(socket);
+                    let mut request_line = String::new();
+                    while socket.read_line(&mut request_line).await.unwrap_or(0) > 0 {
+                        let mut content_length = 0;
+                        let mut storage_class = None;
+                        loop {
+                            let mut line = String::new();
+                            socket.read_line(&mut line).await.unwrap();
+                            let line = line.trim_end();
+                            if line.is_empty() {
+                                break;
+                            }
+                            let (name, value) = line.split_once(':').unwrap();
+                            match name.to_ascii_lowercase().as_str() {
+                                "content-length" => content_length = value.trim().parse().unwrap(),
+                                "x-amz-storage-class" => storage_class = Some(value.trim().into()),
+                                _ => {}
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        socket.read_exact(&mut body).await.unwrap();
+
+                        let mut parts = request_line.split_whitespace();
+                        if parts.next() == Some("PUT") {
+                            let path = parts.next().unwrap().to_owned();
+                            puts.lock().unwrap().push((path, storage_class));
+                        }
+                        socket
+                            .write_all(
+                                b"HTTP/1.1 200 OK\r\nETag: \"0\"\r\nLast-Modified: Mon, 01 Jan 2024 00:00:00 GMT\r\nContent-Length: 0\r\n\r\n",
+                            )
+                            .await
+                            .unwrap();
+                        request_line.clear();
+                    }
+                });
+            }
+        });
+        (endpoint, puts)
+    }
+
+    #[actix_web::test]
+    async fn storage_class_header_is_only_sent_with_data_files() {
+        let (endpoint, puts) = recording_server().await;
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            &endpoint,
+            "--access-key-id",
+            "key",
+            "--secret-key",
+            "secret",
+            "--bucket-name",
+            "logs",
+            "--storage-class",
+            "STANDARD_IA",
+        ])
+        .unwrap();
+        let store = cli.s3.build_store(OverwritePolicy::default());
+
+        for path in [
+            "app/date=2024-01-01/hour=00/minute=00/data.parquet",
+            "app/date=2024-01-01/manifest.json",
+            "app/.stream/.stream.json",
+        ] {
+            store
+                .put_object(RelativePath::new(path), bytes::Bytes::from_static(b"{}"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            *puts.lock().unwrap(),
+            [
+                (
+                    "/logs/app/date=2024-01-01/hour=00/minute=00/data.parquet".to_owned(),
+                    Some("STANDARD_IA".to_owned())
+                ),
+                ("/logs/app/date=2024-01-01/manifest.json".to_owned(), None),
+                ("/logs/app/.stream/.stream.json".to_owned(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn etags_are_unquoted() {
+        assert_eq!(
+            normalize_etag("\"9bb58f26192e4ba00f01e2e7b136bbd8\""),
+            "9bb58f26192e4ba00f01e2e7b136bbd8"
+        );
+        assert_eq!(
+            normalize_etag("W/\"d41d8cd98f00b204e9800998ecf8427e-3\""),
+            "d41d8cd98f00b204e9800998ecf8427e-3"
+        );
+    }
+
+    // needs a MinIO server with a `parseable` bucket, e.g.
+    // `docker run -p 9000:9000 minio/minio server /data` and `mc mb local/parseable`
+    #[actix_web::test]
+    #[ignore = "needs a running MinIO server"]
+    async fn checksum_is_md5_of_single_part_object() {
+        let endpoint =
+            std::env::var("P_TEST_S3_URL").unwrap_or_else(|_| "http://localhost:9000".to_string());
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            &endpoint,
+            "--access-key-id",
+            "minioadmin",
+            "--secret-key",
+            "minioadmin",
+            "--bucket-name",
+            "parseable",
+        ])
+        .unwrap();
+        let store = cli.s3.build_store(OverwritePolicy::default());
+        let path = relative_path::RelativePath::new("checksum-test/object.json");
+
+        store
+            .put_object(path, bytes::Bytes::from_static(b"{}"))
+            .await
+            .unwrap();
+        // md5 of "{}"
+        assert_eq!(
+            store.object_checksum(path).await.unwrap(),
+            "99914b932bd37a50b983c5e7c90ae93b"
+        );
+        store.delete_object(path).await.unwrap();
+        assert!(matches!(
+            store.object_checksum(path).await,
+            Err(ObjectStorageError::NoSuchKey(_))
+        ));
+    }
+
+    #[test]
+    fn network_options_are_parsed_and_rejected() {
+        let cli = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://minio.internal:9000",
+            "--bucket-name",
+            "logs",
+            "--ip-version",
+            "v6",
+            "--resolve",
+            "minio.internal=fd00::10",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.s3.ip_version, IpVersion::V6);
+        assert_eq!(
+            cli.s3.resolve_overrides,
+            vec![("minio.internal".to_string(), "fd00::10".parse().unwrap())]
+        );
+        let err = cli.s3.check_network_options().unwrap_err();
+        assert!(err.contains("--ip-version V6") && err.contains("object_store 0.9.1"));
+
+        let resolve_only = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://minio.internal:9000",
+            "--bucket-name",
+            "logs",
+            "--ip-version",
+            "any",
+            "--resolve",
+            "minio.internal=10.0.0.5",
+        ])
+        .unwrap();
+        let err = resolve_only.s3.check_network_options().unwrap_err();
+        assert!(err.contains("minio.internal") && err.contains("object_store 0.9.1"));
+
+        let defaults = TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://minio.internal:9000",
+            "--bucket-name",
+            "logs",
+        ])
+        .unwrap();
+        assert_eq!(defaults.s3.ip_version, IpVersion::Any);
+        assert!(defaults.s3.check_network_options().is_ok());
+
+        assert!(TestCli::try_parse_from([
+            "parseable",
+            "--endpoint-url",
+            "https://minio.internal:9000",
+            "--bucket-name",
+            "logs",
+            "--resolve",
+            "minio.internal",
+        ])
+        .is_err());
+    }
+
+    #[rstest]
+    #[case("http://localhost:9000", false, "http://localhost:9000/logs")]
+    #[case("http://localhost:9000/", false, "http://localhost:9000/logs")]
+    #[case(
+        "https://gateway.internal/s3/",
+        false,
+        "https://gateway.internal/s3/logs"
+    )]
+    #[case(
+        "https://gateway.internal:8443/s3",
+        false,
+        "https://gateway.internal:8443/s3/logs"
+    )]
+    #[case(
+        "https://logs.s3.us-east-1.amazonaws.com/",
+        true,
+        "https://logs.s3.us-east-1.amazonaws.com"
+    )]
+    fn bucket_url_is_joined_once(
+        #[case] endpoint: &str,
+        #[case] virtual_hosted: bool,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(bucket_url(endpoint, "logs", virtual_hosted), expected);
+    }
+
+    #[test]
+    fn no_such_bucket_is_reported_with_bucket_name() {
+        let body = "<Error><Code>NoSuchBucket</Code><BucketName>logs</BucketName></Error>";
+        let err = object_store::Error::NotFound {
+            path: String::new(),
+            source: body.into(),
+        };
+        assert!(matches!(
+            bucket_error(err, "logs"),
+            ObjectStorageError::BucketNotFound(bucket) if bucket == "logs"
+        ));
+
+        let err = object_store::Error::NotFound {
+            path: "stream/.stream.json".to_string(),
+            source: "NoSuchKey".into(),
+        };
+        assert!(matches!(
+            bucket_error(err, "logs"),
+            ObjectStorageError::NoSuchKey(_)
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Schemas of streams kept on the local disk, so that a restart doesn't wait for the
+//! schema of every stream to be read from the store. Each schema is cached with the
+//! checksum of the schema object it was read from. A cached schema is served on
+//! startup and checked against the store afterwards, it is replaced if the checksum
+//! of the schema object changed.
+
+use std::path::PathBuf;
+
+use arrow_schema::Schema;
+use relative_path::RelativePath;
+
+use crate::option::CONFIG;
+
+use super::{ObjectStorage, ObjectStorageError};
+
+// directory of the cache under the staging directory
+const SCHEMA_CACHE_DIR: &str = ".schema-cache";
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct CachedSchema {
+    // checksum of the schema object the schema was read from
+    checksum: String,
+    schema: Schema,
+}
+
+pub struct SchemaCache {
+    dir: PathBuf,
+}
+
+impl SchemaCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Cache in the staging directory of this server
+    pub fn global() -> Self {
+        Self::new(CONFIG.staging_dir().join(SCHEMA_CACHE_DIR))
+    }
+
+    fn path(&self, stream_name: &str) -> PathBuf {
+        self.dir.join(format!("{stream_name}.json"))
+    }
+
+    fn read(&self, stream_name: &str) -> Option<CachedSchema> {
+        let bytes = std::fs::read(self.path(stream_name)).ok()?;
+        match serde_json::from_slice(&bytes) {
+            Ok(cached) => Some(cached),
+            Err(err) => {
+                log::warn!("ignoring unreadable cached schema of stream {stream_name}: {err}");
+                None
+            }
+        }
+    }
+
+    /// Cached schema of a stream, it may be outdated until checked with `refresh`
+    pub fn get(&self, stream_name: &str) -> Option<Schema> {
+        self.read(stream_name).map(|cached| cached.schema)
+    }
+
+    /// Caches `schema`, read from an object with `checksum`. Failing to write
+    /// the cache only costs a read of the store on the next start.
+    pub fn put(&self, stream_name: &str, checksum: String, schema: Schema) {
+        let cached = CachedSchema { checksum, schema };
+        let res = std::fs::create_dir_all(&self.dir).and_then(|_| {
+            let bytes = serde_json::to_vec(&cached)?;
+            std::fs::write(self.path(stream_name), bytes)
+        });
+        if let Err(err) = res {
+            log::warn!("failed to cache schema of stream {stream_name}: {err}");
+        }
+    }
+
+    /// Reads the schema at `path` in the store and caches it
+    pub async fn load(
+        &self,
+        storage: &(impl ObjectStorage + ?Sized),
+        stream_name: &str,
+        path: &RelativePath,
+    ) -> Result<Schema, ObjectStorageError> {
+        // the checksum is read first, a write racing the read is found by the next refresh
+        let checksum = storage.object_checksum(path).await?;
+        let schema: Schema = serde_json::from_slice(&storage.get_object(path).await?)?;
+        self.put(stream_name, checksum, schema.clone());
+        Ok(schema)
+    }
+
+    /// Checks the cached schema of a stream against the schema object at `path`. Returns
+    /// the schema of the store when it changed since it was cached, none otherwise.
+    pub async fn refresh(
+        &self,
+        storage: &(impl ObjectStorage + ?Sized),
+        stream_name: &str,
+        path: &RelativePath,
+    ) -> Result<Option<Schema>, ObjectStorageError> {
+        let checksum = storage.object_checksum(path).await?;
+        if self
+            .read(stream_name)
+            .is_some_and(|cached| cached.checksum == checksum)
+        {
+            return Ok(None);
+        }
+        self.load(storage, stream_name, path).await.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use relative_path::RelativePath;
+
+    use super::SchemaCache;
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    #[actix_web::test]
+    async fn cached_schema_is_replaced_when_checksum_changes() {
+        let root = std::env::temp_dir().join(format!("parseable-schema-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.join("store"));
+        let cache = SchemaCache::new(root.join("cache"));
+        let path = RelativePath::new("app/.stream/.schema");
+        let v1 = Schema::new(vec![Field::new("a", DataType::Utf8, true)]);
+        let v2 = Schema::new(vec![
+            Field::new("a", DataType::Utf8, true),
+            Field::new("b", DataType::Int64, true),
+        ]);
+        let put = |schema: &Schema| {
+            storage.put_object(path, Bytes::from(serde_json::to_vec(schema).unwrap()))
+        };
+
+        put(&v1).await.unwrap();
+        assert!(cache.get("app").is_none());
+        assert_eq!(cache.load(&storage, "app", path).await.unwrap(), v1);
+
+        // served from the cache, unchanged objects aren't read again
+        assert_eq!(cache.get("app").unwrap(), v1);
+        assert_eq!(cache.refresh(&storage, "app", path).await.unwrap(), None);
+
+        put(&v2).await.unwrap();
+        assert_eq!(cache.get("app").unwrap(), v1);
+        let refreshed = cache.refresh(&storage, "app", path).await;
+        let cached = cache.get("app");
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(refreshed.unwrap(), Some(v2.clone()));
+        assert_eq!(cached.unwrap(), v2);
+    }
+}
@@ -0,0 +1,186 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Schema enforcement on ingestion. Lenient streams add the new fields of events to their
+//! schema. Strict streams reject events with a field not in the schema, or with values of
+//! a type conflicting with the type of the field. The schema of a strict stream is taken
+//! from its first event unless the stream was created with a static schema.
+
+use std::{collections::HashMap, sync::Arc};
+
+use arrow_schema::{DataType, Field};
+use serde_json::Value;
+
+use crate::event::format::json::{derive_arrow_schema, mismatched_field};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaMode {
+    /// New fields are added to the schema
+    #[default]
+    Lenient,
+    /// Events must match the schema
+    Strict,
+}
+
+/// Field of an event rejected by a strict stream
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaViolation {
+    #[error("field {0} is not in the schema of the stream")]
+    UnknownField(String),
+    #[error(
+        "field {field} is of type {existing} in the schema of the stream, the event has a value of another type"
+    )]
+    TypeConflict { field: String, existing: DataType },
+}
+
+impl SchemaMode {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks events, as flattened for ingestion, against the schema of the stream. Values
+    /// are checked with the rules ingestion decodes them by, so a strict stream takes every
+    /// event a lenient stream with the same schema takes without adding a field.
+    pub fn check(
+        self,
+        schema: &HashMap<String, Arc<Field>>,
+        events: &[Value],
+    ) -> Result<(), SchemaViolation> {
+        if self == SchemaMode::Lenient || schema.is_empty() {
+            return Ok(());
+        }
+        for event in events {
+            // malformed events are rejected when decoded
+            let Some(fields) = event.as_object() else {
+                continue;
+            };
+            let Ok(event_schema) =
+                derive_arrow_schema(schema, fields.keys().map(String::as_str).collect())
+            else {
+                let field = fields
+                    .keys()
+                    .find(|field| !schema.contains_key(*field))
+                    .expect("schema is derived unless a field is missing from it");
+                return Err(SchemaViolation::UnknownField(field.to_owned()));
+            };
+            if let Some(field) = mismatched_field(&event_schema, event) {
+                return Err(SchemaViolation::TypeConflict {
+                    field: field.to_owned(),
+                    existing: schema[field].data_type().clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_schema::{DataType, Field, TimeUnit};
+    use serde_json::{json, Value};
+
+    use super::{SchemaMode, SchemaViolation};
+
+    fn schema() -> HashMap<String, Arc<Field>> {
+        [
+            Field::new("status", DataType::Int64, true),
+            Field::new("message", DataType::Utf8, true),
+            Field::new("latency", DataType::Float64, true),
+            Field::new(
+                "received_at",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+        ]
+        .into_iter()
+        .map(|field| (field.name().to_owned(), Arc::new(field)))
+        .collect()
+    }
+
+    fn check(mode: SchemaMode, events: Value) -> Result<(), SchemaViolation> {
+        let Value::Array(events) = events else {
+            return mode.check(&schema(), &[events]);
+        };
+        mode.check(&schema(), &events)
+    }
+
+    #[test]
+    fn strict_mode_rejects_conflicting_types() {
+        let event = json!({"status": "ok", "message": "done"});
+
+        assert!(check(SchemaMode::Lenient, event.clone()).is_ok());
+        match check(SchemaMode::Strict, event) {
+            Err(SchemaViolation::TypeConflict { field, existing }) => {
+                assert_eq!(field, "status");
+                assert_eq!(existing, DataType::Int64);
+            }
+            res => panic!("expected a type conflict, got {res:?}"),
+        }
+        assert!(matches!(
+            check(SchemaMode::Strict, json!({"latency": "12ms"})),
+            Err(SchemaViolation::TypeConflict { field, .. }) if field == "latency"
+        ));
+    }
+
+    #[test]
+    fn strict_mode_rejects_new_fields() {
+        let event = json!([{"status": 200}, {"status": 500, "trace_id": "a1"}]);
+        let res = check(SchemaMode::Strict, event.clone());
+        assert!(matches!(res, Err(SchemaViolation::UnknownField(field)) if field == "trace_id"));
+
+        // a new field is rejected even when all its values are null
+        let res = check(SchemaMode::Strict, json!({"status": 200, "span_id": null}));
+        assert!(matches!(res, Err(SchemaViolation::UnknownField(field)) if field == "span_id"));
+
+        let matching = json!({"status": 200, "message": null});
+        assert!(check(SchemaMode::Strict, matching).is_ok());
+        assert!(check(SchemaMode::Lenient, event.clone()).is_ok());
+        // the first event of a stream sets its schema
+        assert!(SchemaMode::Strict.check(&HashMap::new(), &[event]).is_ok());
+    }
+
+    #[test]
+    fn strict_mode_takes_timestamp_strings_and_numbers() {
+        // not the time partition, the field is a timestamp in the schema
+        let events = json!([
+            {"received_at": "2024-05-01T10:00:00.000Z"},
+            {"received_at": 1714557600000_i64},
+        ]);
+        assert!(check(SchemaMode::Strict, events).is_ok());
+
+        let res = check(SchemaMode::Strict, json!({"received_at": true}));
+        assert!(
+            matches!(res, Err(SchemaViolation::TypeConflict { field, .. }) if field == "received_at")
+        );
+    }
+
+    #[test]
+    fn strict_mode_takes_integers_in_float_fields() {
+        let events = json!([{"latency": 12}, {"latency": 12.5}, {"latency": -3}]);
+        assert!(check(SchemaMode::Strict, events).is_ok());
+
+        // floats don't fit integer fields
+        let res = check(SchemaMode::Strict, json!({"status": 200.5}));
+        assert!(
+            matches!(res, Err(SchemaViolation::TypeConflict { field, .. }) if field == "status")
+        );
+    }
+}
@@ -0,0 +1,230 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Declared sort order of the rows in the parquet files of a stream. Files are sorted
+//! by the time partition column, descending, unless a stream declares its own order,
+//! e.g. tenant and then time. Queries ordering by a prefix of the declared columns
+//! don't have to sort the files again.
+
+use std::collections::HashSet;
+
+use arrow_array::RecordBatch;
+use arrow_schema::{ArrowError, Schema, SortOptions};
+use arrow_select::take::take;
+use datafusion::{
+    arrow::compute::{self, lexsort_to_indices},
+    prelude::{col, Expr},
+};
+use parquet::format::SortingColumn;
+
+use crate::event::DEFAULT_TIMESTAMP_KEY;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SortColumn {
+    pub column: String,
+    #[serde(default)]
+    pub order: SortDirection,
+}
+
+impl SortColumn {
+    pub fn descending(&self) -> bool {
+        self.order == SortDirection::Desc
+    }
+
+    /// Nulls are placed as in SQL by default, last when ascending and first when descending
+    pub fn options(&self) -> SortOptions {
+        SortOptions {
+            descending: self.descending(),
+            nulls_first: self.descending(),
+        }
+    }
+
+    pub fn to_expr(&self) -> Expr {
+        let options = self.options();
+        col(&self.column).sort(!options.descending, options.nulls_first)
+    }
+}
+
+/// Checks that every column of the sort order exists in the schema, can be sorted
+/// and is listed only once. An empty sort order restores the default.
+pub fn validate(sort_order: &[SortColumn], schema: &Schema) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for sort_column in sort_order {
+        let name = sort_column.column.as_str();
+        if !seen.insert(name) {
+            return Err(format!("column {name} is listed more than once"));
+        }
+        // the timestamp is added to every event, it is missing until the first event
+        if name == DEFAULT_TIMESTAMP_KEY {
+            continue;
+        }
+        let field = schema
+            .field_with_name(name)
+            .map_err(|_| format!("column {name} does not exist in the schema of this stream"))?;
+        if field.data_type().is_nested() {
+            return Err(format!(
+                "column {name} of type {} can't be sorted",
+                field.data_type()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Sorts the rows of `batch` by the sort order, columns missing from the batch are skipped
+pub fn sort_batch(
+    batch: &RecordBatch,
+    sort_order: &[SortColumn],
+) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<_> = sort_order
+        .iter()
+        .filter_map(|sort_column| {
+            let values = batch.column_by_name(&sort_column.column)?;
+            Some(compute::SortColumn {
+                values: values.clone(),
+                options: Some(sort_column.options()),
+            })
+        })
+        .collect();
+    if columns.is_empty() {
+        return Ok(batch.clone());
+    }
+
+    let indices = lexsort_to_indices(&columns, None)?;
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column, &indices, None))
+        .collect::<Result<Vec<_>, _>>()?;
+    RecordBatch::try_new(batch.schema(), columns)
+}
+
+/// Sorting columns recorded in the parquet metadata of a file with this schema.
+/// A column missing from the file is null in every row and doesn't change the order.
+pub fn sorting_columns(sort_order: &[SortColumn], schema: &Schema) -> Vec<SortingColumn> {
+    sort_order
+        .iter()
+        .filter_map(|sort_column| {
+            let index = schema.index_of(&sort_column.column).ok()?;
+            let options = sort_column.options();
+            Some(SortingColumn {
+                column_idx: index as i32,
+                descending: options.descending,
+                nulls_first: options.nulls_first,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::{sort_batch, sorting_columns, validate, SortColumn, SortDirection};
+
+    fn sort_order() -> Vec<SortColumn> {
+        serde_json::from_str(
+            r#"[{"column": "tenant"}, {"column": "p_timestamp", "order": "desc"}]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rows_are_sorted_by_two_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("p_timestamp", DataType::Int64, false),
+            Field::new("tenant", DataType::Utf8, true),
+            Field::new("message", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5])) as ArrayRef,
+                Arc::new(StringArray::from(vec![
+                    Some("b"),
+                    Some("a"),
+                    None,
+                    Some("b"),
+                    Some("a"),
+                ])),
+                Arc::new(StringArray::from(vec!["1", "2", "3", "4", "5"])),
+            ],
+        )
+        .unwrap();
+
+        let sort_order = sort_order();
+        assert_eq!(sort_order[0].order, SortDirection::Asc);
+        validate(&sort_order, &schema).unwrap();
+
+        let sorted = sort_batch(&batch, &sort_order).unwrap();
+        let messages = sorted
+            .column_by_name("message")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .iter()
+            .map(Option::unwrap)
+            .collect::<Vec<_>>();
+        // tenant ascending with nulls last, then the latest event first
+        assert_eq!(messages, ["5", "2", "4", "1", "3"]);
+
+        let sorting_columns = sorting_columns(&sort_order, &schema);
+        assert_eq!(
+            sorting_columns
+                .iter()
+                .map(|column| (column.column_idx, column.descending, column.nulls_first))
+                .collect::<Vec<_>>(),
+            [(1, false, false), (0, true, true)]
+        );
+    }
+
+    #[test]
+    fn columns_must_exist_once() {
+        let schema = Schema::new(vec![
+            Field::new("tenant", DataType::Utf8, true),
+            Field::new(
+                "tags",
+                DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+                true,
+            ),
+        ]);
+        let column = |name: &str| SortColumn {
+            column: name.to_string(),
+            order: SortDirection::Asc,
+        };
+
+        assert!(validate(&[], &schema).is_ok());
+        // the timestamp doesn't have to be in the schema yet
+        assert!(validate(&[column("tenant"), column("p_timestamp")], &schema).is_ok());
+        assert!(validate(&[column("host")], &schema).is_err());
+        assert!(validate(&[column("tags")], &schema).is_err());
+        assert!(validate(&[column("tenant"), column("tenant")], &schema).is_err());
+    }
+}
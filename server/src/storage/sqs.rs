@@ -0,0 +1,341 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Minimal SQS client receiving the S3 event notifications of a bucket. Requests use
+//! the JSON protocol of SQS, signed with SigV4 and the credentials of the S3 client.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use object_store::{aws::AwsCredential, CredentialProvider};
+use relative_path::RelativePathBuf;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::credentials::RefreshableCredentials;
+use super::ObjectStorageError;
+
+// messages received per request, the most SQS returns
+const MAX_MESSAGES: usize = 10;
+// seconds a receive waits for messages before returning none
+const WAIT_TIME_SECS: u64 = 20;
+
+pub struct SqsQueue {
+    client: reqwest::Client,
+    queue_url: String,
+    endpoint: Url,
+    region: String,
+    credentials: Arc<RefreshableCredentials>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Message {
+    pub receipt_handle: String,
+    pub body: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReceiveMessageResult {
+    #[serde(default)]
+    messages: Vec<Message>,
+}
+
+impl SqsQueue {
+    pub fn new(
+        queue_url: &str,
+        region: &str,
+        credentials: Arc<RefreshableCredentials>,
+    ) -> Result<Self, url::ParseError> {
+        let url = Url::parse(queue_url)?;
+        let endpoint = url.join("/")?;
+        // queues of AWS are at sqs.<region>.amazonaws.com
+        let region = url
+            .host_str()
+            .and_then(|host| host.strip_prefix("sqs."))
+            .and_then(|host| host.split_once('.'))
+            .map_or(region, |(region, _)| region)
+            .to_owned();
+        Ok(Self {
+            client: reqwest::Client::new(),
+            queue_url: queue_url.to_owned(),
+            endpoint,
+            region,
+            credentials,
+        })
+    }
+
+    /// Waits for the next messages of the queue, none if no message arrives in time
+    pub async fn receive(&self) -> Result<Vec<Message>, ObjectStorageError> {
+        let body = json!({
+            "QueueUrl": self.queue_url,
+            "MaxNumberOfMessages": MAX_MESSAGES,
+            "WaitTimeSeconds": WAIT_TIME_SECS,
+        });
+        let res: ReceiveMessageResult = self.request("ReceiveMessage", &body).await?;
+        Ok(res.messages)
+    }
+
+    /// Deletes handled messages, messages which are not deleted are received again
+    pub async fn delete(&self, messages: &[Message]) -> Result<(), ObjectStorageError> {
+        for batch in messages.chunks(MAX_MESSAGES) {
+            let entries: Vec<_> = batch
+                .iter()
+                .enumerate()
+                .map(|(id, message)| {
+                    json!({ "Id": id.to_string(), "ReceiptHandle": message.receipt_handle })
+                })
+                .collect();
+            let body = json!({ "QueueUrl": self.queue_url, "Entries": entries });
+            let res: serde_json::Value = self.request("DeleteMessageBatch", &body).await?;
+            let failed = res.get("Failed").and_then(|failed| failed.as_array());
+            if let Some(failed) = failed.filter(|failed| !failed.is_empty()) {
+                log::warn!("failed to delete queue messages: {failed:?}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        action: &str,
+        body: &serde_json::Value,
+    ) -> Result<T, ObjectStorageError> {
+        let credential = self
+            .credentials
+            .get_credential()
+            .await
+            .map_err(|err| ObjectStorageError::ConnectionError(Box::new(err)))?;
+        let body = serde_json::to_vec(body)?;
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{port}", self.endpoint.host_str().unwrap_or_default()),
+            None => self.endpoint.host_str().unwrap_or_default().to_owned(),
+        };
+        let headers = sign(
+            &credential,
+            &self.region,
+            &host,
+            &format!("AmazonSQS.{action}"),
+            &body,
+            Utc::now(),
+        );
+
+        let mut req = self.client.post(self.endpoint.clone()).body(body);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let res = req
+            .send()
+            .await
+            .map_err(|err| ObjectStorageError::ConnectionError(Box::new(err)))?;
+        let status = res.status();
+        let bytes = res
+            .bytes()
+            .await
+            .map_err(|err| ObjectStorageError::ConnectionError(Box::new(err)))?;
+        if !status.is_success() {
+            return Err(ObjectStorageError::Custom(format!(
+                "SQS {action} failed with {status}: {}",
+                String::from_utf8_lossy(&bytes)
+            )));
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// Headers of a signed SQS request, the host header is sent by the client
+fn sign(
+    credential: &AwsCredential,
+    region: &str,
+    host: &str,
+    target: &str,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    // sorted by name, as the canonical request lists them
+    let mut headers = vec![
+        ("content-type", "application/x-amz-json-1.0".to_owned()),
+        ("host", host.to_owned()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &credential.token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    headers.push(("x-amz-target", target.to_owned()));
+
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    let signed_headers = signed_headers.join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{value}\n"))
+        .collect();
+    let canonical_request = format!(
+        "POST\n/\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+
+    let scope = format!("{date}/{region}/sqs/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request))
+    );
+    let key = signing_key(&credential.secret_key, &date, region, "sqs");
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+    headers.retain(|(name, _)| *name != "host");
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credential.key_id
+        ),
+    ));
+    headers
+}
+
+/// Key signing the requests of a day to a service of a region
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    [region, service, "aws4_request"].iter().fold(
+        hmac(format!("AWS4{secret_key}").as_bytes(), date.as_bytes()),
+        |key, part| hmac(&key, part.as_bytes()),
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Event {
+    #[serde(default, rename = "Records")]
+    records: Vec<S3EventRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct S3EventRecord {
+    event_name: String,
+    s3: S3Entity,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Entity {
+    bucket: S3Bucket,
+    object: S3Object,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Bucket {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct S3Object {
+    key: String,
+}
+
+/// Keys of the objects created in `bucket` by the S3 event in a message body. Events
+/// delivered through an SNS topic are wrapped in an SNS notification.
+pub fn created_objects(body: &str, bucket: &str) -> Vec<RelativePathBuf> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return vec![];
+    };
+    let value = match value.get("Message").and_then(|message| message.as_str()) {
+        Some(message) => serde_json::from_str(message).unwrap_or_default(),
+        None => value,
+    };
+    let Ok(event) = serde_json::from_value::<S3Event>(value) else {
+        return vec![];
+    };
+
+    event
+        .records
+        .into_iter()
+        .filter(|record| {
+            record.event_name.starts_with("ObjectCreated:") && record.s3.bucket.name == bucket
+        })
+        .filter_map(|record| {
+            // keys are form encoded, spaces are sent as '+'
+            url::form_urlencoded::parse(format!("key={}", record.s3.object.key).as_bytes())
+                .next()
+                .map(|(_, key)| RelativePathBuf::from(key.into_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use relative_path::RelativePathBuf;
+
+    use super::{created_objects, hmac, signing_key};
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hex::encode(hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        // from the SigV4 documentation of AWS
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn created_objects_of_the_bucket_are_decoded() {
+        let record = |event: &str, bucket: &str, key: &str| {
+            serde_json::json!({
+                "eventName": event,
+                "s3": { "bucket": { "name": bucket }, "object": { "key": key } }
+            })
+        };
+        let event = serde_json::json!({
+            "Records": [
+                record("ObjectCreated:Put", "logs", "app/date%3D2024-01-01/a+b.parquet"),
+                record("ObjectRemoved:Delete", "logs", "app/c.parquet"),
+                record("ObjectCreated:Put", "other", "app/d.parquet"),
+            ]
+        });
+        let expected = vec![RelativePathBuf::from("app/date=2024-01-01/a b.parquet")];
+
+        assert_eq!(created_objects(&event.to_string(), "logs"), expected);
+        let sns = serde_json::json!({ "Type": "Notification", "Message": event.to_string() });
+        assert_eq!(created_objects(&sns.to_string(), "logs"), expected);
+        assert!(created_objects(r#"{"Event":"s3:TestEvent"}"#, "logs").is_empty());
+    }
+}
@@ -21,8 +21,13 @@ use crate::{
     event::DEFAULT_TIMESTAMP_KEY,
     handlers::http::modal::{ingest_server::INGESTOR_META, IngestorMetadata, DEFAULT_VERSION},
     metrics,
-    option::{Mode, CONFIG},
-    storage::OBJECT_STORE_DATA_GRANULARITY,
+    option::{BloomFilterColumn, Mode, CONFIG},
+    storage::{
+        provenance::Provenance,
+        sort_order::{self, SortColumn},
+        statistics_level::StatisticsLevel,
+        OBJECT_STORE_DATA_GRANULARITY,
+    },
     utils::{
         self, arrow::merged_reader::MergedReverseRecordReader, get_ingestor_id, get_url,
         hostname_unchecked,
@@ -30,6 +35,7 @@ use crate::{
 };
 use anyhow::anyhow;
 use arrow_schema::{ArrowError, Schema};
+use arrow_select::concat::concat_batches;
 use base64::Engine;
 use chrono::{NaiveDateTime, Timelike, Utc};
 use itertools::Itertools;
@@ -41,17 +47,22 @@ use parquet::{
     format::SortingColumn,
     schema::types::ColumnPath,
 };
-use rand::distributions::DistString;
 use serde_json::Value as JsonValue;
 use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
     process,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
+use sysinfo::Disks;
+use ulid::Ulid;
 
 const ARROW_FILE_EXTENSION: &str = "data.arrows";
+const QUARANTINE_DIR: &str = "quarantine";
 // const PARQUET_FILE_EXTENSION: &str = "data.parquet";
 
 #[derive(Debug)]
@@ -135,6 +146,15 @@ impl StorageDir {
         paths
     }
 
+    /// Size in bytes of the arrow files staged for this stream
+    pub fn arrow_files_size(&self) -> u64 {
+        self.arrow_files()
+            .iter()
+            .filter_map(|file| file.metadata().ok())
+            .map(|meta| meta.len())
+            .sum()
+    }
+
     #[allow(dead_code)]
     pub fn arrow_files_grouped_by_time(&self) -> HashMap<PathBuf, Vec<PathBuf>> {
         // hashmap <time, vec[paths]>
@@ -153,22 +173,24 @@ impl StorageDir {
 
     pub fn arrow_files_grouped_exclude_time(
         &self,
-        exclude: NaiveDateTime,
+        exclude: Option<NaiveDateTime>,
     ) -> HashMap<PathBuf, Vec<PathBuf>> {
         let mut grouped_arrow_file: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
         let mut arrow_files = self.arrow_files();
-        arrow_files.retain(|path| {
-            !path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .starts_with(&exclude.format("%Y%m%dT%H%M").to_string())
-        });
-        let random_string =
-            rand::distributions::Alphanumeric.sample_string(&mut rand::thread_rng(), 15);
+        if let Some(exclude) = exclude {
+            arrow_files.retain(|path| {
+                !path
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .starts_with(&exclude.format("%Y%m%dT%H%M").to_string())
+            });
+        }
+        // unique across writers flushing the same partitions at the same time
+        let flush_id = Ulid::new().to_string();
         for arrow_file_path in arrow_files {
-            let key = Self::arrow_path_to_parquet(&arrow_file_path, random_string.clone());
+            let key = Self::arrow_path_to_parquet(&arrow_file_path, &flush_id);
             grouped_arrow_file
                 .entry(key)
                 .or_default()
@@ -177,6 +199,11 @@ impl StorageDir {
         grouped_arrow_file
     }
 
+    /// Directory unreadable staged files are moved to
+    pub fn quarantine_path(&self) -> PathBuf {
+        self.data_path.join(QUARANTINE_DIR)
+    }
+
     pub fn parquet_files(&self) -> Vec<PathBuf> {
         let Ok(dir) = self.data_path.read_dir() else {
             return vec![];
@@ -188,14 +215,14 @@ impl StorageDir {
             .collect()
     }
 
-    fn arrow_path_to_parquet(path: &Path, random_string: String) -> PathBuf {
+    fn arrow_path_to_parquet(path: &Path, flush_id: &str) -> PathBuf {
         let filename = path.file_stem().unwrap().to_str().unwrap();
         let (_, filename) = filename.split_once('.').unwrap();
         let filename = filename.rsplit_once('.').expect("contains the delim `.`");
         let filename = format!("{}.{}", filename.0, filename.1);
-        let filename_with_random_number = format!("{}.{}.{}", filename, random_string, "arrows");
+        let filename_with_flush_id = format!("{}.{}.{}", filename, flush_id, "arrows");
         let mut parquet_path = path.to_owned();
-        parquet_path.set_file_name(filename_with_random_number);
+        parquet_path.set_file_name(filename_with_flush_id);
         parquet_path.set_extension("parquet");
         parquet_path
     }
@@ -208,16 +235,63 @@ impl StorageDir {
 //     data_path.join(dir)
 // }
 
+// free space in bytes on the staging disk when last sampled, u64::MAX until sampled or if unknown
+static SAMPLED_FREE_DISK: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Interval in seconds at which free space on the staging disk is sampled
+pub const FREE_DISK_SAMPLE_INTERVAL: u32 = 5;
+
+/// Returns the free space in bytes on the disk holding the staging directory
+pub fn staging_free_disk_space() -> Option<u64> {
+    let staging = CONFIG.staging_dir().canonicalize().ok()?;
+    Disks::new_with_refreshed_list()
+        .iter()
+        .filter(|disk| staging.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Samples free space on the staging disk for `has_free_disk_space` and the metric,
+/// run on a timer as listing disks is too slow to do for every event.
+pub fn sample_free_disk_space() {
+    let free = staging_free_disk_space();
+    if let Some(free) = free {
+        metrics::STAGING_FREE_DISK.set(free as i64);
+    }
+    SAMPLED_FREE_DISK.store(free.unwrap_or(u64::MAX), Ordering::Relaxed);
+}
+
+/// Checks free space on the staging disk, as last sampled, against the configured minimum.
+/// Always true if no minimum is configured or free space can't be determined.
+pub fn has_free_disk_space() -> bool {
+    is_above_min_free_disk(
+        SAMPLED_FREE_DISK.load(Ordering::Relaxed),
+        CONFIG.parseable.min_free_disk,
+    )
+}
+
+fn is_above_min_free_disk(free: u64, min_free_disk: Option<u64>) -> bool {
+    min_free_disk.map_or(true, |min_free_disk| free >= min_free_disk)
+}
+
+/// Converts the staged arrow files of a stream to parquet. Files of the current
+/// minute are still being written to and are left out unless `include_current` is
+/// set, in which case the caller must have closed the writers of the stream.
+/// Files that can't be read are moved to the quarantine directory of the stream,
+/// only the files written to parquet are deleted.
 pub fn convert_disk_files_to_parquet(
     stream: &str,
     dir: &StorageDir,
     time_partition: Option<String>,
     custom_partition: Option<String>,
+    sort_order: &[SortColumn],
+    statistics_level: StatisticsLevel,
+    include_current: bool,
 ) -> Result<Option<Schema>, MoveDataError> {
     let mut schemas = Vec::new();
 
     let time = chrono::Utc::now().naive_utc();
-    let staging_files = dir.arrow_files_grouped_exclude_time(time);
+    let staging_files = dir.arrow_files_grouped_exclude_time((!include_current).then_some(time));
     if staging_files.is_empty() {
         metrics::STAGING_FILES.with_label_values(&[stream]).set(0);
         metrics::STORAGE_SIZE
@@ -243,6 +317,14 @@ pub fn convert_disk_files_to_parquet(
         }
 
         let record_reader = MergedReverseRecordReader::try_new(&files).unwrap();
+        // only files that were read are removed once converted, the others are kept aside
+        let (files, unreadable): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|file| !record_reader.unreadable.contains(file));
+        quarantine_staging_files(stream, dir, unreadable);
+        if record_reader.readers.is_empty() {
+            continue;
+        }
         let merged_schema = record_reader.merged_schema();
         let mut index_time_partition: usize = 0;
         if let Some(time_partition) = time_partition.as_ref() {
@@ -256,34 +338,42 @@ pub fn convert_disk_files_to_parquet(
             }
         }
         let parquet_file = fs::File::create(&parquet_path).map_err(|_| MoveDataError::Create)?;
-        let props = parquet_writer_props(
+        let mut props = parquet_writer_props(
             time_partition.clone(),
             index_time_partition,
             custom_partition_fields,
-        )
-        .build();
+        );
+        if !sort_order.is_empty() {
+            props = props.set_sorting_columns(Some(sort_order::sorting_columns(
+                sort_order,
+                &merged_schema,
+            )));
+        }
+        let props = statistics_level
+            .apply(
+                props,
+                time_partition.as_deref().unwrap_or(DEFAULT_TIMESTAMP_KEY),
+            )
+            .set_key_value_metadata(Some(Provenance::staging().key_value_metadata()))
+            .build();
 
         schemas.push(merged_schema.clone());
         let schema = Arc::new(merged_schema);
         let mut writer = ArrowWriter::try_new(parquet_file, schema.clone(), Some(props))?;
-        for ref record in record_reader.merged_iter(schema, time_partition.clone()) {
-            writer.write(record)?;
+        let records = record_reader.merged_iter(schema.clone(), time_partition.clone());
+        if sort_order.is_empty() {
+            for ref record in records {
+                writer.write(record)?;
+            }
+        } else {
+            // the declared order holds across the whole file, not only within a batch
+            let records = concat_batches(&schema, &records.collect_vec())?;
+            writer.write(&sort_order::sort_batch(&records, sort_order)?)?;
         }
 
         writer.close()?;
 
-        for file in files {
-            let file_size = file.metadata().unwrap().len();
-            let file_type = file.extension().unwrap().to_str().unwrap();
-
-            if fs::remove_file(file.clone()).is_err() {
-                log::error!("Failed to delete file. Unstable state");
-                process::abort()
-            }
-            metrics::STORAGE_SIZE
-                .with_label_values(&["staging", stream, file_type])
-                .sub(file_size as i64);
-        }
+        remove_staging_files(stream, files);
     }
 
     if !schemas.is_empty() {
@@ -293,6 +383,59 @@ pub fn convert_disk_files_to_parquet(
     }
 }
 
+/// Moves staged files that can't be read out of the way of later conversions, into the
+/// quarantine directory of the stream where they can be inspected or recovered by hand.
+/// Files that can't be moved are left in place and retried on the next conversion.
+fn quarantine_staging_files(stream: &str, dir: &StorageDir, files: Vec<PathBuf>) {
+    if files.is_empty() {
+        return;
+    }
+    let quarantine = dir.quarantine_path();
+    if let Err(err) = fs::create_dir_all(&quarantine) {
+        log::error!(
+            "Failed to create staging quarantine {:?}, leaving {} unreadable file(s) in place: {}",
+            quarantine,
+            files.len(),
+            err
+        );
+        return;
+    }
+    for file in files {
+        let file_size = file.metadata().map_or(0, |meta| meta.len());
+        let target = quarantine.join(file.file_name().expect("staged file has a name"));
+        match fs::rename(&file, &target) {
+            Ok(()) => {
+                log::error!("Moved unreadable staging file {:?} to {:?}", file, target);
+                metrics::STORAGE_SIZE
+                    .with_label_values(&["staging", stream, "arrows"])
+                    .sub(file_size as i64);
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to quarantine unreadable staging file {:?}: {}",
+                    file,
+                    err
+                )
+            }
+        }
+    }
+}
+
+fn remove_staging_files(stream: &str, files: Vec<PathBuf>) {
+    for file in files {
+        let file_size = file.metadata().unwrap().len();
+        let file_type = file.extension().unwrap().to_str().unwrap();
+
+        if fs::remove_file(file.clone()).is_err() {
+            log::error!("Failed to delete file. Unstable state");
+            process::abort()
+        }
+        metrics::STORAGE_SIZE
+            .with_label_values(&["staging", stream, file_type])
+            .sub(file_size as i64);
+    }
+}
+
 pub fn parquet_writer_props(
     time_partition: Option<String>,
     index_time_partition: usize,
@@ -317,6 +460,17 @@ pub fn parquet_writer_props(
             Encoding::DELTA_BINARY_PACKED,
         );
 
+    // dictionary pages only pay off for low cardinality columns
+    if !CONFIG.parseable.parquet_dictionary_columns.is_empty() {
+        props = props.set_dictionary_enabled(false);
+        for column in &CONFIG.parseable.parquet_dictionary_columns {
+            props =
+                props.set_column_dictionary_enabled(ColumnPath::new(vec![column.clone()]), true);
+        }
+    }
+
+    props = with_bloom_filters(props, &CONFIG.parseable.parquet_bloom_filter_columns);
+
     for (field, index) in custom_partition_fields {
         let field = ColumnPath::new(vec![field]);
         let encoding = Encoding::DELTA_BYTE_ARRAY;
@@ -333,6 +487,27 @@ pub fn parquet_writer_props(
     props
 }
 
+/// Bloom filters let queries skip row groups on equality and IN filters that min/max
+/// statistics can't rule out, they are only read when bloom filter columns are configured.
+/// A lower fpp or higher ndv makes the filter larger, ndv beyond the rows of a row group
+/// only wastes space.
+fn with_bloom_filters(
+    mut props: WriterPropertiesBuilder,
+    columns: &[BloomFilterColumn],
+) -> WriterPropertiesBuilder {
+    for bloom_filter in columns {
+        let column = ColumnPath::new(vec![bloom_filter.column.clone()]);
+        props = props.set_column_bloom_filter_enabled(column.clone(), true);
+        if let Some(fpp) = bloom_filter.fpp {
+            props = props.set_column_bloom_filter_fpp(column.clone(), fpp);
+        }
+        if let Some(ndv) = bloom_filter.ndv {
+            props = props.set_column_bloom_filter_ndv(column, ndv);
+        }
+    }
+    props
+}
+
 pub fn get_ingestor_info() -> anyhow::Result<IngestorMetadata> {
     let path = PathBuf::from(&CONFIG.parseable.local_staging_path);
 
@@ -451,3 +626,128 @@ pub enum MoveDataError {
     #[error("Could not generate parquet file")]
     Create,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use arrow_array::{Int64Array, RecordBatch, TimestampMillisecondArray};
+    use arrow_ipc::writer::StreamWriter;
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use parquet::{file::properties::WriterProperties, schema::types::ColumnPath};
+
+    use super::{
+        convert_disk_files_to_parquet, is_above_min_free_disk, with_bloom_filters, StorageDir,
+    };
+    use crate::{
+        event::DEFAULT_TIMESTAMP_KEY, option::validation::bloom_filter_column,
+        storage::statistics_level::StatisticsLevel,
+    };
+
+    #[test]
+    fn free_disk_is_checked_against_configured_minimum() {
+        assert!(is_above_min_free_disk(0, None));
+        assert!(is_above_min_free_disk(1024, Some(1024)));
+        assert!(!is_above_min_free_disk(1023, Some(1024)));
+        // free space not sampled yet or unknown
+        assert!(is_above_min_free_disk(u64::MAX, Some(1024)));
+    }
+
+    #[test]
+    fn bloom_filter_config_is_written_to_properties() {
+        let columns = ["host", "trace_id:0.01:50000"]
+            .into_iter()
+            .map(|column| bloom_filter_column(column).unwrap())
+            .collect::<Vec<_>>();
+        let props = with_bloom_filters(WriterProperties::builder(), &columns).build();
+
+        let host = props
+            .bloom_filter_properties(&ColumnPath::from("host"))
+            .unwrap();
+        let default = parquet::file::properties::BloomFilterProperties::default();
+        assert_eq!((host.fpp, host.ndv), (default.fpp, default.ndv));
+
+        let trace_id = props
+            .bloom_filter_properties(&ColumnPath::from("trace_id"))
+            .unwrap();
+        assert_eq!((trace_id.fpp, trace_id.ndv), (0.01, 50000));
+
+        assert!(props
+            .bloom_filter_properties(&ColumnPath::from("message"))
+            .is_none());
+    }
+
+    #[test]
+    fn bloom_filter_fpp_is_validated() {
+        for invalid in [
+            "host:0",
+            "host:1",
+            "host:1.5",
+            "host:0.1:0",
+            ":0.1",
+            "host:0.1:10:1",
+        ] {
+            assert!(bloom_filter_column(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn unreadable_staging_file_is_quarantined_not_deleted() {
+        let data_path =
+            std::env::temp_dir().join(format!("parseable-staging-{}", ulid::Ulid::new()));
+        fs::create_dir_all(&data_path).unwrap();
+        let dir = StorageDir {
+            data_path: data_path.clone(),
+        };
+        // both files belong to the same minute of a past day, so they make one parquet file
+        let name = "date=2020-01-01.hour=00.minute=00.host.data.arrows";
+        let readable = data_path.join(format!("20200101T0000a.{name}"));
+        let truncated = data_path.join(format!("20200101T0000b.{name}"));
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                DEFAULT_TIMESTAMP_KEY,
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![1577836800000])),
+                Arc::new(Int64Array::from(vec![1])),
+            ],
+        )
+        .unwrap();
+        let mut writer =
+            StreamWriter::try_new(fs::File::create(&readable).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        // a writer killed before its first batch leaves a file without a complete message
+        fs::write(&truncated, [0xff, 0xff]).unwrap();
+
+        let schema = convert_disk_files_to_parquet(
+            "staging_test",
+            &dir,
+            None,
+            None,
+            &[],
+            StatisticsLevel::default(),
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(schema.fields().len(), 2);
+
+        assert_eq!(dir.parquet_files().len(), 1);
+        assert!(dir.arrow_files().is_empty());
+        assert!(!readable.exists());
+        assert!(dir
+            .quarantine_path()
+            .join(truncated.file_name().unwrap())
+            .exists());
+
+        fs::remove_dir_all(&data_path).unwrap();
+    }
+}
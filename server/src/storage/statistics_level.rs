@@ -0,0 +1,142 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Column statistics written to the parquet files of a stream. Statistics are computed
+//! over every value written, high throughput streams can trade them for write CPU. The
+//! cost is paid by queries: without page statistics pages can't be skipped with the page
+//! index, and without chunk statistics the catalog has no min/max for a column, so files
+//! and row groups are only pruned by time.
+
+use parquet::file::properties::{EnabledStatistics, WriterPropertiesBuilder};
+use parquet::schema::types::ColumnPath;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatisticsLevel {
+    /// Only the time partition column has statistics
+    None,
+    /// Statistics per row group
+    Chunk,
+    /// Statistics per row group and per page
+    #[default]
+    Page,
+}
+
+impl StatisticsLevel {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Sets the level on all columns. The time partition column keeps at least chunk
+    /// statistics, its bounds place the file in the manifests of the stream.
+    pub fn apply(
+        self,
+        props: WriterPropertiesBuilder,
+        time_partition: &str,
+    ) -> WriterPropertiesBuilder {
+        let props = props.set_statistics_enabled(self.into());
+        match self {
+            StatisticsLevel::None => props.set_column_statistics_enabled(
+                ColumnPath::new(vec![time_partition.to_owned()]),
+                EnabledStatistics::Chunk,
+            ),
+            _ => props,
+        }
+    }
+}
+
+impl From<StatisticsLevel> for EnabledStatistics {
+    fn from(level: StatisticsLevel) -> Self {
+        match level {
+            StatisticsLevel::None => EnabledStatistics::None,
+            StatisticsLevel::Chunk => EnabledStatistics::Chunk,
+            StatisticsLevel::Page => EnabledStatistics::Page,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{RecordBatch, StringArray, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use bytes::Bytes;
+    use parquet::{
+        arrow::ArrowWriter,
+        file::{
+            properties::WriterProperties,
+            reader::{FileReader, SerializedFileReader},
+        },
+    };
+    use rstest::rstest;
+
+    use super::StatisticsLevel;
+    use crate::catalog::manifest;
+
+    #[rstest]
+    #[case(StatisticsLevel::None, false, false)]
+    #[case(StatisticsLevel::Chunk, true, false)]
+    #[case(StatisticsLevel::Page, true, true)]
+    fn level_sets_written_statistics(
+        #[case] level: StatisticsLevel,
+        #[case] chunk_stats: bool,
+        #[case] page_index: bool,
+    ) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("message", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec!["a", "b"])),
+            ],
+        )
+        .unwrap();
+        let props = level
+            .apply(WriterProperties::builder(), "p_timestamp")
+            .build();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, schema, Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let reader = SerializedFileReader::new(Bytes::from(buf)).unwrap();
+        let metadata = reader.metadata();
+        let message = metadata.row_group(0).column(1);
+        assert_eq!(message.column_index_offset().is_some(), page_index);
+
+        let file = manifest::create_from_parquet_metadata("a.parquet".to_string(), 0, metadata);
+        let has_stats = |name: &str| {
+            file.columns
+                .iter()
+                .find(|column| column.name == name)
+                .unwrap()
+                .stats
+                .is_some()
+        };
+        assert!(has_stats("p_timestamp"));
+        assert_eq!(has_stats("message"), chunk_stats);
+    }
+}
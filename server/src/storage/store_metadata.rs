@@ -61,6 +61,8 @@ pub struct StorageMetadata {
     pub roles: HashMap<String, Vec<DefaultPrivilege>>,
     #[serde(default)]
     pub default_role: Option<String>,
+    #[serde(default)]
+    pub stream_aliases: HashMap<String, String>,
 }
 
 impl StorageMetadata {
@@ -76,6 +78,7 @@ impl StorageMetadata {
             streams: Vec::new(),
             roles: HashMap::default(),
             default_role: None,
+            stream_aliases: HashMap::default(),
         }
     }
 
@@ -0,0 +1,309 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Requests throttled by the object store (503 SlowDown, 429) are retried after the delay
+//! the store asks for in `Retry-After`, capped at a configured maximum, and with an
+//! exponential backoff when it doesn't ask for one. The delay is only known when the
+//! error reports the header, the client of object_store 0.9 doesn't expose response headers.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    future::Future,
+    ops::Range,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use object_store::{
+    path::Path, GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore,
+    PutOptions, PutResult, Result as ObjectStoreResult,
+};
+use tokio::io::AsyncWrite;
+
+// error codes and statuses returned for throttled requests
+const THROTTLED_CODES: [&str; 4] = [
+    "SlowDown",
+    "503 Service Unavailable",
+    "429 Too Many Requests",
+    "TooManyRequests",
+];
+// retries of a throttled request before its error is returned
+const THROTTLE_RETRIES: u32 = 3;
+// first delay of the backoff used when the store doesn't ask for one
+const BASE_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Whether the error, or any of its sources, is a throttling response
+pub fn is_throttled(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        let message = err.to_string();
+        if THROTTLED_CODES.iter().any(|code| message.contains(code)) {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Delay asked for by a `Retry-After` reported in the error or any of its sources,
+/// in seconds or as an HTTP date
+pub fn retry_after(err: &(dyn Error + 'static), now: DateTime<Utc>) -> Option<Duration> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(delay) = parse_retry_after(&err.to_string(), now) {
+            return Some(delay);
+        }
+        source = err.source();
+    }
+    None
+}
+
+fn parse_retry_after(message: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let start = message.to_ascii_lowercase().find("retry-after")? + "retry-after".len();
+    let value = message[start..].trim_start_matches([':', '=', ' ', '"']);
+
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    if digits > 0 {
+        return value[..digits].parse().ok().map(Duration::from_secs);
+    }
+    // HTTP dates always end in GMT
+    let end = value.find("GMT")? + "GMT".len();
+    let date = DateTime::parse_from_rfc2822(&value[..end]).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Retries requests rejected because of throttling. Listings are streamed and aren't
+/// retried past their first request, callers retry them as a whole.
+#[derive(Debug)]
+pub struct ThrottleRetry<T> {
+    inner: T,
+    max_retry_after: Duration,
+}
+
+impl<T> ThrottleRetry<T> {
+    pub fn new(inner: T, max_retry_after: Duration) -> Self {
+        Self {
+            inner,
+            max_retry_after,
+        }
+    }
+
+    async fn retry<R, F, Fut>(&self, op: F) -> ObjectStoreResult<R>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ObjectStoreResult<R>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(err) if attempt < THROTTLE_RETRIES && is_throttled(&err) => {
+                    let delay = retry_after(&err, Utc::now())
+                        .unwrap_or(BASE_BACKOFF * 2u32.pow(attempt))
+                        .min(self.max_retry_after);
+                    log::warn!("object store request throttled, retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T: ObjectStore> Display for ThrottleRetry<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ThrottleRetry({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for ThrottleRetry<T> {
+    async fn put(&self, location: &Path, bytes: Bytes) -> ObjectStoreResult<PutResult> {
+        self.retry(|| self.inner.put(location, bytes.clone())).await
+    }
+
+    async fn put_opts(
+        &self,
+        location: &Path,
+        bytes: Bytes,
+        opts: PutOptions,
+    ) -> ObjectStoreResult<PutResult> {
+        self.retry(|| self.inner.put_opts(location, bytes.clone(), opts.clone()))
+            .await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.retry(|| self.inner.put_multipart(location)).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.abort_multipart(location, multipart_id))
+            .await
+    }
+
+    async fn get(&self, location: &Path) -> ObjectStoreResult<GetResult> {
+        self.retry(|| self.inner.get(location)).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> ObjectStoreResult<GetResult> {
+        self.retry(|| self.inner.get_opts(location, options.clone()))
+            .await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        self.retry(|| self.inner.get_range(location, range.clone()))
+            .await
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> ObjectStoreResult<Vec<Bytes>> {
+        self.retry(|| self.inner.get_ranges(location, ranges)).await
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        self.retry(|| self.inner.head(location)).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.delete(location)).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        self.retry(|| self.inner.list_with_delimiter(prefix)).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.copy(from, to)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.rename(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.copy_if_not_exists(from, to)).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.retry(|| self.inner.rename_if_not_exists(from, to))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use chrono::{TimeZone, Utc};
+    use object_store::{path::Path, ObjectStore};
+
+    use super::{parse_retry_after, ThrottleRetry};
+    use crate::storage::faulty_store::{Fault, FaultyStore, Operation};
+
+    /// Store rejecting the first `throttled` heads with a 503 asking to retry after `retry_after`
+    fn throttling_store(throttled: usize, retry_after: &'static str) -> FaultyStore {
+        let store = FaultyStore::default();
+        let throttle = Fault::error(move || object_store::Error::Generic {
+            store: "S3",
+            source: format!(
+                "Server error with status 503 Service Unavailable, Retry-After: {retry_after}: \
+                <Error><Code>SlowDown</Code></Error>"
+            )
+            .into(),
+        });
+        store.inject(Operation::Head, throttle, throttled);
+        store
+    }
+
+    #[actix_web::test]
+    async fn throttled_requests_wait_for_retry_after() {
+        let path = Path::from("app/.stream/.stream.json");
+        let store = |throttled, retry_after, max_retry_after| {
+            ThrottleRetry::new(throttling_store(throttled, retry_after), max_retry_after)
+        };
+
+        let honored = store(1, "1", Duration::from_secs(5));
+        honored.put(&path, Bytes::from_static(b"{}")).await.unwrap();
+        let start = Instant::now();
+        assert!(honored.head(&path).await.is_ok());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // a longer delay than the maximum is cut short
+        let capped = store(2, "3600", Duration::from_millis(50));
+        capped.put(&path, Bytes::from_static(b"{}")).await.unwrap();
+        let start = Instant::now();
+        assert!(capped.head(&path).await.is_ok());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the error is returned once retries are exhausted
+        let exhausted = store(10, "0", Duration::from_secs(5));
+        assert!(exhausted.head(&path).await.is_err());
+        assert_eq!(exhausted.inner.pending(Operation::Head), 4);
+    }
+
+    #[test]
+    fn retry_after_is_parsed_as_seconds_or_date() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 30).unwrap();
+        assert_eq!(
+            parse_retry_after("503 SlowDown, retry-after: 7", now),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            parse_retry_after("Retry-After: Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Retry-After: Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("503 SlowDown", now), None);
+    }
+}
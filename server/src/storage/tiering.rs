@@ -0,0 +1,445 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! With a cold storage configured, partitions older than the hot tier days are moved from
+//! the storage they are written to into the cold storage, a bucket or directory of the same
+//! provider. The snapshot of a stream records the tier of every partition, queries read the
+//! manifests and files of cold partitions from the cold storage.
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Days, Utc};
+use futures::TryStreamExt;
+use itertools::Itertools;
+use relative_path::{RelativePath, RelativePathBuf};
+
+use super::{
+    object_storage::{manifest_path, SYNC_LOCK},
+    ObjectStorage, ObjectStorageError,
+};
+use crate::{
+    catalog::{
+        self,
+        manifest::Manifest,
+        snapshot::{ManifestItem, Snapshot},
+    },
+    metadata::STREAM_INFO,
+    option::{Mode, CONFIG},
+};
+
+/// Storage a partition of a stream is kept in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    /// Storage the stream is written to
+    #[default]
+    Hot,
+    /// Cold storage partitions are moved to once aged
+    Cold,
+}
+
+impl Tier {
+    pub fn is_hot(&self) -> bool {
+        *self == Tier::Hot
+    }
+}
+
+/// Cold storage, if configured
+pub fn cold_store() -> Option<Arc<dyn ObjectStorage + Send>> {
+    let location = CONFIG.parseable.cold_storage.as_deref()?;
+    Some(CONFIG.storage().get_object_store_at(location))
+}
+
+/// Storage the partitions of `tier` are kept in, `hot` being the storage of the stream
+pub fn store_of(
+    tier: Tier,
+    hot: Arc<dyn ObjectStorage + Send>,
+) -> Result<Arc<dyn ObjectStorage + Send>, ObjectStorageError> {
+    match tier {
+        Tier::Hot => Ok(hot),
+        Tier::Cold => cold_store().ok_or_else(|| {
+            ObjectStorageError::Custom(
+                "partition is in the cold tier but no cold storage is set".to_string(),
+            )
+        }),
+    }
+}
+
+/// Splits the partitions of a snapshot into the hot and the cold ones
+pub fn split(snapshot: Snapshot) -> (Snapshot, Snapshot) {
+    let (hot, cold) = snapshot
+        .manifest_list
+        .into_iter()
+        .partition(|item| item.tier.is_hot());
+    (
+        Snapshot {
+            manifest_list: hot,
+            version: snapshot.version.clone(),
+        },
+        Snapshot {
+            manifest_list: cold,
+            version: snapshot.version,
+        },
+    )
+}
+
+/// Moves partitions older than the hot tier days of every stream into the cold storage.
+/// Only a standalone server moves partitions, in distributed mode the snapshot is updated
+/// by every ingestor and can't be swapped safely from the query server.
+pub async fn migrate_aged() {
+    let Some(cold) = cold_store() else {
+        return;
+    };
+    if CONFIG.parseable.mode != Mode::All {
+        log::warn!("partitions are not moved to the cold tier in distributed mode");
+        return;
+    }
+    let hot = CONFIG.storage().get_object_store();
+    let Some(hot_until) =
+        Utc::now().checked_sub_days(Days::new(CONFIG.parseable.hot_tier_days.into()))
+    else {
+        return;
+    };
+
+    for stream in STREAM_INFO.list_streams() {
+        match migrate_stream(&*hot, &*cold, &stream, hot_until).await {
+            Ok(0) => {}
+            Ok(moved) => log::info!("moved {moved} partitions of {stream} to the cold tier"),
+            Err(err) => {
+                log::warn!("failed to move partitions of {stream} to the cold tier due to {err:?}")
+            }
+        }
+    }
+}
+
+/// Moves the hot partitions of `stream` ending before `hot_until` into `cold`,
+/// returning the number of partitions moved
+pub async fn migrate_stream(
+    hot: &dyn ObjectStorage,
+    cold: &dyn ObjectStorage,
+    stream: &str,
+    hot_until: DateTime<Utc>,
+) -> Result<usize, ObjectStorageError> {
+    let meta = hot.get_object_store_format(stream).await?;
+    let aged = meta
+        .snapshot
+        .manifest_list
+        .iter()
+        .filter(|item| item.tier.is_hot() && item.time_upper_bound < hot_until)
+        .map(|item| catalog::partition_path(stream, item.time_lower_bound, item.time_upper_bound))
+        .collect_vec();
+    if aged.is_empty() {
+        return Ok(0);
+    }
+
+    // partitions are copied while uploads carry on, the lock is only taken to swap the snapshot
+    let files = stream_files(hot, stream).await?;
+    let mut copied = Vec::with_capacity(aged.len());
+    for partition in aged {
+        let manifest_key = manifest_path(partition.as_str());
+        let manifest = copy_partition(hot, cold, &files, &manifest_key).await?;
+        copied.push((partition, manifest_key, manifest));
+    }
+
+    let mut moved = Vec::with_capacity(copied.len());
+    {
+        let _guard = SYNC_LOCK.lock().await;
+        let mut meta = hot.get_object_store_format(stream).await?;
+        for (partition, manifest_key, manifest) in copied {
+            // files uploaded to the partition while it was copied are moved on the next run
+            let current: Manifest = serde_json::from_slice(&hot.get_object(&manifest_key).await?)?;
+            if !same_files(&current, &manifest) {
+                continue;
+            }
+            let Some(item) = meta.snapshot.manifest_list.iter_mut().find(|item| {
+                item.tier.is_hot()
+                    && catalog::partition_path(stream, item.time_lower_bound, item.time_upper_bound)
+                        == partition
+            }) else {
+                continue;
+            };
+            item.manifest_path = cold.absolute_url(&manifest_key).to_string();
+            item.tier = Tier::Cold;
+            moved.push((manifest_key, manifest));
+        }
+        if moved.is_empty() {
+            return Ok(0);
+        }
+        meta.snapshot.manifest_list = merge_items(meta.snapshot.manifest_list);
+        hot.put_snapshot(stream, meta.snapshot).await?;
+        // an upload after the swap starts a new hot manifest, these must be gone by then
+        for (manifest_key, _) in &moved {
+            hot.delete_object(manifest_key).await?;
+        }
+    }
+
+    // the snapshot points at the cold copies, the hot ones are no longer read
+    for (_, manifest) in &moved {
+        for file in &manifest.files {
+            if let Some(key) = files.get(&file.file_path) {
+                hot.delete_object(key).await?;
+            }
+        }
+    }
+    Ok(moved.len())
+}
+
+/// Whether two manifests of a partition list the same files
+fn same_files(a: &Manifest, b: &Manifest) -> bool {
+    let paths = |manifest: &Manifest| {
+        manifest
+            .files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .sorted()
+            .collect_vec()
+    };
+    paths(a) == paths(b)
+}
+
+/// Parquet files of a stream by the path manifests refer to them with
+async fn stream_files(
+    storage: &dyn ObjectStorage,
+    stream: &str,
+) -> Result<HashMap<String, RelativePathBuf>, ObjectStorageError> {
+    storage
+        .list_parquet_files(stream)
+        .map_ok(|key| (storage.absolute_url(&key).to_string(), key))
+        .try_collect()
+        .await
+}
+
+/// Copies the files of the manifest at `manifest_key` and the manifest itself from `hot`
+/// to the same keys in `cold`, returning the copied manifest. Files of a partition already
+/// in `cold`, which happens when late events were uploaded after it was moved, are merged
+/// into its manifest.
+async fn copy_partition(
+    hot: &dyn ObjectStorage,
+    cold: &dyn ObjectStorage,
+    files: &HashMap<String, RelativePathBuf>,
+    manifest_key: &RelativePath,
+) -> Result<Manifest, ObjectStorageError> {
+    let manifest: Manifest = serde_json::from_slice(&hot.get_object(manifest_key).await?)?;
+    let mut cold_manifest: Manifest = if cold.exists(manifest_key).await? {
+        serde_json::from_slice(&cold.get_object(manifest_key).await?)?
+    } else {
+        Manifest::default()
+    };
+
+    for mut file in manifest.files.iter().cloned() {
+        let Some(key) = files.get(&file.file_path) else {
+            return Err(ObjectStorageError::Custom(format!(
+                "{} is in the manifest at {manifest_key} but not in storage",
+                file.file_path
+            )));
+        };
+        cold.put_object(key, hot.get_object(key).await?).await?;
+        file.file_path = cold.absolute_url(key).to_string();
+        cold_manifest.apply_change(file);
+    }
+    cold.put_object(manifest_key, serde_json::to_vec(&cold_manifest)?.into())
+        .await?;
+    Ok(manifest)
+}
+
+/// Merges items of a partition moved into the cold tier more than once
+fn merge_items(items: Vec<ManifestItem>) -> Vec<ManifestItem> {
+    let mut merged: Vec<ManifestItem> = Vec::with_capacity(items.len());
+    for item in items {
+        match merged
+            .iter_mut()
+            .find(|kept| kept.manifest_path == item.manifest_path)
+        {
+            Some(kept) => {
+                kept.events_ingested += item.events_ingested;
+                kept.ingestion_size += item.ingestion_size;
+                kept.storage_size += item.storage_size;
+            }
+            None => merged.push(item),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use chrono::NaiveDate;
+    use datafusion::{
+        config::TableParquetOptions,
+        datasource::{
+            listing::PartitionedFile,
+            physical_plan::{FileScanConfig, ParquetExec},
+        },
+        execution::object_store::ObjectStoreUrl,
+        physical_plan::{collect, Statistics},
+        prelude::SessionContext,
+    };
+    use object_store::{local::LocalFileSystem, path::Path, ObjectStore};
+    use parquet::arrow::ArrowWriter;
+    use relative_path::{RelativePath, RelativePathBuf};
+
+    use super::{copy_partition, same_files, split, stream_files, Tier};
+    use crate::catalog::{
+        manifest::{File, Manifest},
+        snapshot::{ManifestItem, Snapshot},
+    };
+    use crate::query::partition_format::PartitionFormat;
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    fn parquet(rows: i64) -> Bytes {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(0..rows))],
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        buf.into()
+    }
+
+    #[actix_web::test]
+    async fn query_spans_hot_and_cold_partitions() {
+        let root = std::env::temp_dir().join(format!("parseable-tiering-{}", ulid::Ulid::new()));
+        let hot = LocalFS::new(root.join("hot"));
+        let cold = LocalFS::new(root.join("cold"));
+
+        // one partition of 3 rows aged into the cold tier, one of 2 rows still hot
+        let mut manifest_list = Vec::new();
+        for (day, rows) in [(1, 3), (2, 2)] {
+            let date = NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+            let partition = RelativePathBuf::from(format!("app/date={date}"));
+            let key = partition.join("hour=00/minute=00/data.parquet");
+            let data = parquet(rows);
+            let manifest = Manifest {
+                files: vec![File {
+                    file_path: hot.absolute_url(&key).to_string(),
+                    num_rows: rows as u64,
+                    file_size: data.len() as u64,
+                    ..File::default()
+                }],
+                ..Manifest::default()
+            };
+            let manifest_key = partition.join("manifest.json");
+            hot.put_object(&key, data).await.unwrap();
+            hot.put_object(&manifest_key, serde_json::to_vec(&manifest).unwrap().into())
+                .await
+                .unwrap();
+            manifest_list.push(ManifestItem {
+                manifest_path: hot.absolute_url(&manifest_key).to_string(),
+                time_lower_bound: date.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                time_upper_bound: date
+                    .and_hms_nano_opt(23, 59, 59, 999_999_999)
+                    .unwrap()
+                    .and_utc(),
+                events_ingested: rows as u64,
+                ingestion_size: 0,
+                storage_size: 0,
+                tier: Tier::Hot,
+                format: PartitionFormat::Parquet,
+            });
+        }
+
+        let files = stream_files(&hot, "app").await.unwrap();
+        let manifest_key = RelativePath::new("app/date=2024-01-01/manifest.json");
+        copy_partition(&hot, &cold, &files, manifest_key)
+            .await
+            .unwrap();
+        manifest_list[0].manifest_path = cold.absolute_url(manifest_key).to_string();
+        manifest_list[0].tier = Tier::Cold;
+        hot.delete_prefix(RelativePath::new("app/date=2024-01-01"))
+            .await
+            .unwrap();
+
+        // manifests of each tier are read from the storage of the tier
+        let (hot_snapshot, cold_snapshot) = split(Snapshot {
+            manifest_list,
+            ..Snapshot::default()
+        });
+        let store = LocalFileSystem::new();
+        let mut data_files = Vec::new();
+        for item in hot_snapshot
+            .manifest_list
+            .iter()
+            .chain(&cold_snapshot.manifest_list)
+        {
+            let bytes = store
+                .get(&Path::parse(&item.manifest_path).unwrap())
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            let manifest: Manifest = serde_json::from_slice(&bytes).unwrap();
+            data_files.extend(manifest.files);
+        }
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let exec = ParquetExec::new(
+            FileScanConfig {
+                object_store_url: ObjectStoreUrl::local_filesystem(),
+                file_schema: schema.clone(),
+                file_groups: vec![data_files
+                    .iter()
+                    .map(|file| PartitionedFile::new(file.file_path.clone(), file.file_size))
+                    .collect()],
+                statistics: Statistics::new_unknown(&schema),
+                projection: None,
+                limit: None,
+                output_ordering: vec![],
+                table_partition_cols: vec![],
+            },
+            None,
+            None,
+            TableParquetOptions::default(),
+        );
+        let batches = collect(Arc::new(exec), SessionContext::new().task_ctx()).await;
+        let cold_prefix = cold.absolute_url(RelativePath::new("app")).to_string();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let rows: usize = batches.unwrap().iter().map(|batch| batch.num_rows()).sum();
+        assert_eq!(rows, 5);
+        assert_eq!(hot_snapshot.manifest_list.len(), 1);
+        assert_eq!(cold_snapshot.manifest_list.len(), 1);
+        assert!(data_files[1].file_path.starts_with(&cold_prefix));
+        assert!(!data_files[0].file_path.starts_with(&cold_prefix));
+    }
+
+    #[test]
+    fn partitions_with_files_uploaded_while_copied_are_not_swapped() {
+        let manifest = |paths: &[&str]| Manifest {
+            files: paths
+                .iter()
+                .map(|path| File {
+                    file_path: path.to_string(),
+                    ..File::default()
+                })
+                .collect(),
+            ..Manifest::default()
+        };
+        assert!(same_files(&manifest(&["a", "b"]), &manifest(&["b", "a"])));
+        assert!(!same_files(&manifest(&["a"]), &manifest(&["a", "b"])));
+    }
+}
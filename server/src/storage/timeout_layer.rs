@@ -0,0 +1,279 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{future::Future, ops::Range, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, StreamExt};
+use object_store::{
+    path::Path, GetOptions, GetResult, GetResultPayload, ListResult, MultipartId, ObjectMeta,
+    ObjectStore, PutOptions, PutResult, Result as ObjectStoreResult,
+};
+use tokio::io::AsyncWrite;
+
+/// Object store operation which didn't complete within the operation timeout
+#[derive(Debug, thiserror::Error)]
+#[error("{operation} did not complete within {timeout:?}")]
+pub struct OperationTimeout {
+    pub operation: &'static str,
+    pub timeout: Duration,
+}
+
+/// Bounds every call to the inner store by an overall timeout, independent of the
+/// connect and read timeouts of the HTTP client which don't fire on a gateway that
+/// keeps the connection open without answering. Streamed responses are bounded per item.
+#[derive(Debug)]
+pub struct TimeoutStore<T: ObjectStore> {
+    inner: T,
+    timeout: Duration,
+}
+
+impl<T: ObjectStore> TimeoutStore<T> {
+    pub fn new(inner: T, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+
+    async fn run<R>(
+        &self,
+        operation: &'static str,
+        fut: impl Future<Output = ObjectStoreResult<R>>,
+    ) -> ObjectStoreResult<R> {
+        tokio::time::timeout(self.timeout, fut)
+            .await
+            .unwrap_or_else(|_| Err(timeout_error(operation, self.timeout)))
+    }
+}
+
+impl<T: ObjectStore> std::fmt::Display for TimeoutStore<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TimeoutStore({:?}, {})", self.timeout, self.inner)
+    }
+}
+
+fn timeout_error(operation: &'static str, timeout: Duration) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "TimeoutStore",
+        source: Box::new(OperationTimeout { operation, timeout }),
+    }
+}
+
+/// Ends the stream with a timeout error if the next item doesn't arrive in time
+fn timeout_stream<'a, I: Send + 'a>(
+    operation: &'static str,
+    timeout: Duration,
+    stream: BoxStream<'a, ObjectStoreResult<I>>,
+) -> BoxStream<'a, ObjectStoreResult<I>> {
+    futures_util::stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some(stream))),
+            Ok(None) => None,
+            Err(_) => Some((Err(timeout_error(operation, timeout)), None)),
+        }
+    })
+    .boxed()
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for TimeoutStore<T> {
+    async fn put(&self, location: &Path, bytes: Bytes) -> ObjectStoreResult<PutResult> {
+        self.run("put", self.inner.put(location, bytes)).await
+    }
+
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: Bytes,
+        opts: PutOptions,
+    ) -> ObjectStoreResult<PutResult> {
+        self.run("put", self.inner.put_opts(location, payload, opts))
+            .await
+    }
+
+    // parts are written by the returned writer, only creating the upload is bounded
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.run("put_multipart", self.inner.put_multipart(location))
+            .await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> ObjectStoreResult<()> {
+        self.run(
+            "abort_multipart",
+            self.inner.abort_multipart(location, multipart_id),
+        )
+        .await
+    }
+
+    async fn get(&self, location: &Path) -> ObjectStoreResult<GetResult> {
+        let res = self.run("get", self.inner.get(location)).await?;
+        Ok(self.bound_body(res))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> ObjectStoreResult<GetResult> {
+        let res = self
+            .run("get", self.inner.get_opts(location, options))
+            .await?;
+        Ok(self.bound_body(res))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        self.run("get_range", self.inner.get_range(location, range))
+            .await
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> ObjectStoreResult<Vec<Bytes>> {
+        self.run("get_ranges", self.inner.get_ranges(location, ranges))
+            .await
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        self.run("head", self.inner.head(location)).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        self.run("delete", self.inner.delete(location)).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, ObjectStoreResult<Path>>,
+    ) -> BoxStream<'a, ObjectStoreResult<Path>> {
+        timeout_stream(
+            "delete_stream",
+            self.timeout,
+            self.inner.delete_stream(locations),
+        )
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        timeout_stream("list", self.timeout, self.inner.list(prefix))
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, ObjectStoreResult<ObjectMeta>> {
+        timeout_stream(
+            "list_with_offset",
+            self.timeout,
+            self.inner.list_with_offset(prefix, offset),
+        )
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        self.run(
+            "list_with_delimiter",
+            self.inner.list_with_delimiter(prefix),
+        )
+        .await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.run("copy", self.inner.copy(from, to)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.run("rename", self.inner.rename(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.run(
+            "copy_if_not_exists",
+            self.inner.copy_if_not_exists(from, to),
+        )
+        .await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.run(
+            "rename_if_not_exists",
+            self.inner.rename_if_not_exists(from, to),
+        )
+        .await
+    }
+}
+
+impl<T: ObjectStore> TimeoutStore<T> {
+    /// Bounds the wait for each chunk of a streamed body
+    fn bound_body(&self, res: GetResult) -> GetResult {
+        let payload = match res.payload {
+            payload @ GetResultPayload::File(_, _) => payload,
+            GetResultPayload::Stream(stream) => {
+                GetResultPayload::Stream(timeout_stream("get", self.timeout, stream))
+            }
+        };
+        GetResult { payload, ..res }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use object_store::{path::Path, ObjectStore};
+
+    use super::TimeoutStore;
+    use crate::storage::{
+        faulty_store::{Fault, FaultyStore, Operation},
+        ObjectStorageError,
+    };
+
+    #[actix_web::test]
+    async fn hanging_operations_time_out() {
+        // head and listings never answer
+        let hanging = FaultyStore::default();
+        hanging
+            .inject_always(Operation::Head, Fault::Hang)
+            .inject_always(Operation::List, Fault::Hang);
+        let store = TimeoutStore::new(hanging, Duration::from_millis(50));
+        let path = Path::from("app/.stream/.stream.json");
+        store.put(&path, Bytes::from_static(b"{}")).await.unwrap();
+
+        let head = store.head(&path).await.unwrap_err();
+        let list = store.list_with_delimiter(None).await.unwrap_err();
+        let listed: Vec<_> = store.list(None).collect().await;
+        let get = store.get(&path).await;
+
+        assert!(matches!(
+            ObjectStorageError::from(head),
+            ObjectStorageError::Timeout(_)
+        ));
+        assert!(matches!(
+            ObjectStorageError::from(list),
+            ObjectStorageError::Timeout(_)
+        ));
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].is_err());
+        assert!(get.is_ok(), "answered operations are not affected");
+    }
+}
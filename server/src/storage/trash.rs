@@ -0,0 +1,282 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! In soft delete mode deleted streams and partitions are moved to `.trash/<timestamp>/`
+//! and only purged once the grace period has passed, a deleted stream can be restored
+//! until then. Trash is never listed as a stream, so trashed data isn't queried.
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use itertools::Itertools;
+use relative_path::{RelativePath, RelativePathBuf};
+
+use super::{
+    ObjectStorage, ObjectStorageError, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    TRASH_ROOT_DIRECTORY,
+};
+use crate::option::CONFIG;
+
+// timestamp of the trash directory objects are moved to, sorts by time
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Handling of objects deleted with a stream or by retention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeleteMode {
+    /// Objects are removed right away
+    #[default]
+    Hard,
+    /// Objects are moved to the trash and removed after the grace period
+    Soft,
+}
+
+/// Deletes the objects under `path`, into the trash in soft delete mode
+pub async fn delete_prefix(
+    storage: &(impl ObjectStorage + ?Sized),
+    path: &RelativePath,
+) -> Result<(), ObjectStorageError> {
+    match CONFIG.parseable.delete_mode {
+        DeleteMode::Hard => storage.delete_prefix(path).await,
+        DeleteMode::Soft => move_to_trash(storage, path, Utc::now()).await.map(|_| ()),
+    }
+}
+
+/// Deletes the objects of a stream, into the trash in soft delete mode
+pub async fn delete_stream(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+) -> Result<(), ObjectStorageError> {
+    match CONFIG.parseable.delete_mode {
+        DeleteMode::Hard => storage.delete_stream(stream_name).await,
+        DeleteMode::Soft => move_to_trash(storage, RelativePath::new(stream_name), Utc::now())
+            .await
+            .map(|_| ()),
+    }
+}
+
+/// Moves the objects under `path` to the trash, returning the prefix they are moved to
+pub async fn move_to_trash(
+    storage: &(impl ObjectStorage + ?Sized),
+    path: &RelativePath,
+    now: DateTime<Utc>,
+) -> Result<RelativePathBuf, ObjectStorageError> {
+    let timestamp = now.format(TIMESTAMP_FORMAT).to_string();
+    let trashed = RelativePathBuf::from_iter([TRASH_ROOT_DIRECTORY, &timestamp, path.as_str()]);
+    storage.move_prefix(path, &trashed).await?;
+    log::info!("moved {path} to {trashed}");
+    Ok(trashed)
+}
+
+/// Moves trashed objects back to the prefix they were deleted from and returns it
+pub async fn restore(
+    storage: &(impl ObjectStorage + ?Sized),
+    trashed: &RelativePath,
+) -> Result<RelativePathBuf, ObjectStorageError> {
+    let mut parts = trashed.iter();
+    let path = match (parts.next(), parts.next()) {
+        (Some(TRASH_ROOT_DIRECTORY), Some(_)) => RelativePathBuf::from_iter(parts),
+        _ => RelativePathBuf::new(),
+    };
+    if path.as_str().is_empty() {
+        return Err(ObjectStorageError::Custom(format!(
+            "{trashed} is not a trashed prefix"
+        )));
+    }
+    storage.move_prefix(trashed, &path).await?;
+    log::info!("restored {trashed} to {path}");
+    Ok(path)
+}
+
+/// Prefix of the latest trashed copy of a stream, None when the trash has none
+pub async fn find_trashed_stream(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+) -> Result<Option<RelativePathBuf>, ObjectStorageError> {
+    // directories sort by the time they were trashed at
+    for dir in trash_dirs(storage).await?.into_iter().sorted().rev() {
+        let trashed = RelativePathBuf::from_iter([TRASH_ROOT_DIRECTORY, &dir, stream_name]);
+        let stream_json = trashed
+            .join(STREAM_ROOT_DIRECTORY)
+            .join(STREAM_METADATA_FILE_NAME);
+        if storage.exists(&stream_json).await? {
+            return Ok(Some(trashed));
+        }
+    }
+    Ok(None)
+}
+
+// directories of the trash are listed like the dates of a stream
+async fn trash_dirs(
+    storage: &(impl ObjectStorage + ?Sized),
+) -> Result<Vec<String>, ObjectStorageError> {
+    match storage.list_dates(TRASH_ROOT_DIRECTORY).await {
+        Ok(dirs) => Ok(dirs
+            .into_iter()
+            .map(|dir| dir.trim_end_matches('/').to_owned())
+            .collect()),
+        Err(ObjectStorageError::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            Ok(vec![])
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Deletes the objects trashed more than `grace` before `now`,
+/// returning the number of trash directories removed
+pub async fn purge(
+    storage: &(impl ObjectStorage + ?Sized),
+    grace: Duration,
+    now: DateTime<Utc>,
+) -> Result<usize, ObjectStorageError> {
+    let mut purged = 0;
+    for dir in trash_dirs(storage).await? {
+        let Ok(trashed_at) = NaiveDateTime::parse_from_str(&dir, TIMESTAMP_FORMAT) else {
+            continue;
+        };
+        if now - trashed_at.and_utc() < grace {
+            continue;
+        }
+        storage
+            .delete_prefix(&RelativePathBuf::from_iter([TRASH_ROOT_DIRECTORY, &dir]))
+            .await?;
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+/// Purges the trash of every location streams are stored in
+pub async fn purge_expired() {
+    let grace = Duration::days(CONFIG.parseable.trash_retention_days.into());
+    let stores = std::iter::once(CONFIG.storage().get_object_store()).chain(
+        CONFIG
+            .parseable
+            .stream_storage
+            .values()
+            .unique()
+            .map(|location| CONFIG.storage().get_object_store_at(location)),
+    );
+    for store in stores {
+        match purge(&*store, grace, Utc::now()).await {
+            Ok(0) => {}
+            Ok(purged) => log::info!("purged {purged} expired trash directories"),
+            Err(err) => log::warn!("failed to purge trash due to {err:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use chrono::{Duration, Utc};
+    use relative_path::RelativePath;
+
+    use super::{find_trashed_stream, move_to_trash, purge, restore};
+    use crate::storage::{localfs::LocalFS, ObjectStorage};
+
+    #[actix_web::test]
+    async fn soft_deleted_stream_is_restored() {
+        let root = std::env::temp_dir().join(format!("parseable-trash-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        let stream_json = RelativePath::new("app/.stream/.stream.json");
+        let data = RelativePath::new("app/date=2024-01-01/hour=00/minute=00/data.parquet");
+        for path in [stream_json, data] {
+            storage
+                .put_object(path, Bytes::from_static(b"{}"))
+                .await
+                .unwrap();
+        }
+        let now = Utc::now();
+        let grace = Duration::days(7);
+
+        let trashed = move_to_trash(&storage, RelativePath::new("app"), now)
+            .await
+            .unwrap();
+        let trashed_streams = storage.list_streams().await.unwrap();
+        let trashed_data = storage.exists(data).await.unwrap();
+        let purged_early = purge(&storage, grace, now).await.unwrap();
+
+        let found = find_trashed_stream(&storage, "app").await.unwrap();
+        let restored = restore(&storage, &trashed).await.unwrap();
+        let restored_streams = storage.list_streams().await.unwrap();
+        let restored_data = storage.exists(data).await.unwrap();
+
+        move_to_trash(&storage, RelativePath::new("app"), now)
+            .await
+            .unwrap();
+        let purged = purge(&storage, grace, now + Duration::days(8))
+            .await
+            .unwrap();
+        let trash_left = storage.list_dates(".trash").await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(trashed_streams.is_empty());
+        assert!(!trashed_data);
+        assert_eq!(purged_early, 0);
+        assert_eq!(found, Some(trashed));
+        assert_eq!(restored.as_str(), "app");
+        assert_eq!(
+            restored_streams
+                .into_iter()
+                .map(|stream| stream.name)
+                .collect::<Vec<_>>(),
+            ["app"]
+        );
+        assert!(restored_data);
+        assert_eq!(purged, 1);
+        assert!(trash_left.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn latest_trashed_copy_of_a_stream_is_found() {
+        let root = std::env::temp_dir().join(format!("parseable-trash-{}", ulid::Ulid::new()));
+        let storage = LocalFS::new(root.clone());
+        let stream_json = RelativePath::new("app/.stream/.stream.json");
+        let now = Utc::now();
+
+        let missing = find_trashed_stream(&storage, "app").await.unwrap();
+        let mut trashed = vec![];
+        for minutes in [0, 5] {
+            storage
+                .put_object(stream_json, Bytes::from_static(b"{}"))
+                .await
+                .unwrap();
+            let at = now + Duration::minutes(minutes);
+            trashed.push(
+                move_to_trash(&storage, RelativePath::new("app"), at)
+                    .await
+                    .unwrap(),
+            );
+        }
+        // a partition deleted by retention is not a stream
+        storage
+            .put_object(
+                RelativePath::new("other/date=2024-01-01/data.parquet"),
+                Bytes::from_static(b"data"),
+            )
+            .await
+            .unwrap();
+        move_to_trash(&storage, RelativePath::new("other/date=2024-01-01"), now)
+            .await
+            .unwrap();
+        let found = find_trashed_stream(&storage, "app").await.unwrap();
+        let partition = find_trashed_stream(&storage, "other").await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(missing, None);
+        assert_eq!(found.as_ref(), trashed.last());
+        assert_eq!(partition, None);
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Staged parquet files waiting to be uploaded. When uploads fall behind ingestion,
+//! ingestion is rejected until the backlog drains so that staging doesn't grow unbounded.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    metrics::{PENDING_UPLOAD_FILES, PENDING_UPLOAD_SIZE, UPLOAD_BACKPRESSURE},
+    option::CONFIG,
+};
+
+/// Once engaged, backpressure is released when the backlog is below this fraction of the limits
+const RESUME_FRACTION: f64 = 0.8;
+
+pub static UPLOAD_BACKLOG: Lazy<UploadBacklog> = Lazy::new(|| {
+    UploadBacklog::new(
+        CONFIG.parseable.max_pending_upload_files,
+        CONFIG.parseable.max_pending_upload_size,
+    )
+});
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Pending {
+    files: u64,
+    bytes: u64,
+}
+
+#[derive(Debug)]
+pub struct UploadBacklog {
+    pending: Mutex<HashMap<String, Pending>>,
+    max_files: Option<u64>,
+    max_bytes: Option<u64>,
+    engaged: AtomicBool,
+}
+
+impl UploadBacklog {
+    pub fn new(max_files: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Self {
+            pending: Mutex::default(),
+            max_files,
+            max_bytes,
+            engaged: AtomicBool::new(false),
+        }
+    }
+
+    /// Replaces the backlog of a stream with the files found in its staging directory
+    pub fn set_pending(&self, stream: &str, files: u64, bytes: u64) {
+        let mut pending = self.pending.lock().expect("backlog lock is not poisoned");
+        pending.insert(stream.to_owned(), Pending { files, bytes });
+        report(stream, Pending { files, bytes });
+    }
+
+    /// Removes an uploaded file
+    pub fn uploaded(&self, stream: &str, bytes: u64) {
+        self.update(stream, |entry| {
+            entry.files = entry.files.saturating_sub(1);
+            entry.bytes = entry.bytes.saturating_sub(bytes);
+        })
+    }
+
+    pub fn remove_stream(&self, stream: &str) {
+        let mut pending = self.pending.lock().expect("backlog lock is not poisoned");
+        pending.remove(stream);
+        report(stream, Pending::default());
+    }
+
+    fn update(&self, stream: &str, f: impl FnOnce(&mut Pending)) {
+        let mut pending = self.pending.lock().expect("backlog lock is not poisoned");
+        let entry = pending.entry(stream.to_owned()).or_default();
+        f(entry);
+        report(stream, *entry);
+    }
+
+    fn total(&self) -> Pending {
+        let pending = self.pending.lock().expect("backlog lock is not poisoned");
+        pending
+            .values()
+            .fold(Pending::default(), |total, entry| Pending {
+                files: total.files + entry.files,
+                bytes: total.bytes + entry.bytes,
+            })
+    }
+
+    /// Whether ingestion should be rejected. Engages once a limit is reached and
+    /// stays engaged until the backlog drains below `RESUME_FRACTION` of the limits.
+    pub fn is_full(&self) -> bool {
+        if self.max_files.is_none() && self.max_bytes.is_none() {
+            return false;
+        }
+
+        let total = self.total();
+        let engaged = self.engaged.load(Ordering::Relaxed);
+        let fraction = if engaged { RESUME_FRACTION } else { 1.0 };
+        let reached = |limit: Option<u64>, value: u64| {
+            limit.is_some_and(|limit| value as f64 >= limit as f64 * fraction)
+        };
+        let full = reached(self.max_files, total.files) || reached(self.max_bytes, total.bytes);
+
+        if full != engaged {
+            self.engaged.store(full, Ordering::Relaxed);
+            UPLOAD_BACKPRESSURE.set(full as i64);
+            if full {
+                log::warn!(
+                    "{} staged files ({} bytes) are waiting to be uploaded, rejecting ingestion",
+                    total.files,
+                    total.bytes
+                );
+            } else {
+                log::info!("upload backlog drained, accepting ingestion");
+            }
+        }
+        full
+    }
+}
+
+fn report(stream: &str, pending: Pending) {
+    PENDING_UPLOAD_FILES
+        .with_label_values(&[stream])
+        .set(pending.files as i64);
+    PENDING_UPLOAD_SIZE
+        .with_label_values(&[stream])
+        .set(pending.bytes as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use object_store::{
+        memory::InMemory,
+        path::Path,
+        throttle::{ThrottleConfig, ThrottledStore},
+        ObjectStore,
+    };
+
+    use super::UploadBacklog;
+
+    #[actix_web::test]
+    async fn backpressure_engages_when_uploads_fall_behind() {
+        let backlog = Arc::new(UploadBacklog::new(Some(4), None));
+        let store = ThrottledStore::new(
+            InMemory::new(),
+            ThrottleConfig {
+                wait_put_per_call: Duration::from_millis(50),
+                ..ThrottleConfig::default()
+            },
+        );
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Path>();
+
+        let uploader = {
+            let backlog = Arc::clone(&backlog);
+            tokio::spawn(async move {
+                while let Some(path) = rx.recv().await {
+                    store.put(&path, Bytes::from_static(b"data")).await.unwrap();
+                    backlog.uploaded("app", 4);
+                }
+            })
+        };
+
+        // files are staged faster than the store accepts them
+        let mut engaged = false;
+        for i in 0..8 {
+            backlog.update("app", |entry| {
+                entry.files += 1;
+                entry.bytes += 4;
+            });
+            tx.send(Path::from(format!("app/{i}.parquet"))).unwrap();
+            engaged |= backlog.is_full();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(engaged, "backpressure should engage while uploads lag");
+
+        drop(tx);
+        uploader.await.unwrap();
+        assert!(!backlog.is_full(), "backpressure is released once drained");
+    }
+
+    #[test]
+    fn releases_below_resume_fraction() {
+        let backlog = UploadBacklog::new(None, Some(100));
+        backlog.set_pending("app", 1, 100);
+        assert!(backlog.is_full());
+        // still above 80% of the limit
+        backlog.set_pending("app", 1, 90);
+        assert!(backlog.is_full());
+        backlog.set_pending("app", 1, 70);
+        assert!(!backlog.is_full());
+        backlog.set_pending("app", 1, 90);
+        assert!(!backlog.is_full());
+    }
+}
@@ -16,17 +16,18 @@
  *
  */
 
-use clokwerk::{AsyncScheduler, Job, Scheduler, TimeUnits};
+use clokwerk::{Job, Scheduler, TimeUnits};
 use thread_priority::{ThreadBuilder, ThreadPriority};
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::error::TryRecvError;
 
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
-use crate::{storage, STORAGE_UPLOAD_INTERVAL};
+use crate::storage::{self, staging, StorageDir};
 
 pub fn object_store_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<()>) {
     let (outbox_tx, outbox_rx) = oneshot::channel::<()>();
@@ -36,20 +37,33 @@ pub fn object_store_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::S
         let res = catch_unwind(move || {
             let rt = actix_web::rt::System::new();
             rt.block_on(async {
-                let mut scheduler = AsyncScheduler::new();
-                scheduler
-                    .every(STORAGE_UPLOAD_INTERVAL.seconds())
-                    // Extra time interval is added so that this schedular does not race with local sync.
-                    .plus(5u32.seconds())
-                    .run(|| async {
-                        if let Err(e) = CONFIG.storage().get_object_store().sync().await {
-                            log::warn!("failed to sync local data with object store. {:?}", e);
-                        }
-                    });
+                // Extra time interval is added so that this sync does not race with local sync.
+                let mut policy = FlushPolicy::new(
+                    CONFIG.parseable.staging_max_size,
+                    Duration::from_secs(CONFIG.parseable.staging_flush_interval + 5),
+                    Instant::now(),
+                );
 
                 loop {
                     tokio::time::sleep(Duration::from_secs(1)).await;
-                    scheduler.run_pending().await;
+                    let staged_bytes = policy.max_bytes.map_or(0, |_| staged_bytes());
+                    match policy.check(staged_bytes, Instant::now()) {
+                        Some(FlushTrigger::Size) => {
+                            log::info!(
+                                "flushing {staged_bytes} staged bytes before the flush interval"
+                            );
+                            // the files being written to hold most of the data, they are
+                            // converted too rather than left for the next interval
+                            flush_staged_data().await;
+                            policy.flushed(Instant::now(), staged_bytes());
+                        }
+                        Some(FlushTrigger::Time) => {
+                            sync_with_object_store().await;
+                            let remaining = policy.max_bytes.map_or(0, |_| staged_bytes());
+                            policy.flushed(Instant::now(), remaining);
+                        }
+                        None => {}
+                    }
                     match AssertUnwindSafe(|| inbox_rx.try_recv())() {
                         Ok(_) => break,
                         Err(TryRecvError::Empty) => continue,
@@ -70,6 +84,78 @@ pub fn object_store_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::S
     (handle, outbox_rx, inbox_tx)
 }
 
+async fn sync_with_object_store() {
+    if let Err(e) = CONFIG.storage().get_object_store().sync().await {
+        log::warn!("failed to sync local data with object store. {:?}", e);
+    }
+}
+
+/// Converts and uploads all staged data, the files of the current minute included
+async fn flush_staged_data() {
+    let streams = STREAM_INFO.list_streams();
+    if let Err(e) = CONFIG
+        .storage()
+        .get_object_store()
+        .sync_streams(&streams, true)
+        .await
+    {
+        log::warn!("failed to flush staged data to object store. {:?}", e);
+    }
+}
+
+fn staged_bytes() -> u64 {
+    STREAM_INFO
+        .list_streams()
+        .iter()
+        .map(|stream| StorageDir::new(stream).arrow_files_size())
+        .sum()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FlushTrigger {
+    Size,
+    Time,
+}
+
+/// Decides when staged data is flushed and uploaded,
+/// busy streams flush on size and quiet streams on time.
+struct FlushPolicy {
+    max_bytes: Option<u64>,
+    max_age: Duration,
+    last_flush: Instant,
+    // bytes left in staging by the last flush
+    unflushed_bytes: u64,
+}
+
+impl FlushPolicy {
+    fn new(max_bytes: Option<u64>, max_age: Duration, now: Instant) -> Self {
+        Self {
+            max_bytes,
+            max_age,
+            last_flush: now,
+            unflushed_bytes: 0,
+        }
+    }
+
+    fn check(&self, staged_bytes: u64, now: Instant) -> Option<FlushTrigger> {
+        if now.duration_since(self.last_flush) >= self.max_age {
+            Some(FlushTrigger::Time)
+        } else if self
+            .max_bytes
+            .is_some_and(|max| staged_bytes.saturating_sub(self.unflushed_bytes) >= max)
+        {
+            Some(FlushTrigger::Size)
+        } else {
+            None
+        }
+    }
+
+    fn flushed(&mut self, now: Instant, unflushed_bytes: u64) {
+        self.last_flush = now;
+        self.unflushed_bytes = unflushed_bytes;
+    }
+}
+
 pub fn run_local_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<()>) {
     let (outbox_tx, outbox_rx) = oneshot::channel::<()>();
     let (inbox_tx, inbox_rx) = oneshot::channel::<()>();
@@ -87,6 +173,10 @@ pub fn run_local_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Send
                 scheduler
                     .every((storage::LOCAL_SYNC_INTERVAL as u32).seconds())
                     .run(move || crate::event::STREAM_WRITERS.unset_all());
+                staging::sample_free_disk_space();
+                scheduler
+                    .every(staging::FREE_DISK_SAMPLE_INTERVAL.seconds())
+                    .run(staging::sample_free_disk_space);
 
                 loop {
                     thread::sleep(Duration::from_millis(50));
@@ -110,3 +200,48 @@ pub fn run_local_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Send
 
     (handle, outbox_rx, inbox_tx)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FlushPolicy, FlushTrigger};
+
+    #[test]
+    fn flushes_on_size_before_interval() {
+        let start = Instant::now();
+        let mut policy = FlushPolicy::new(Some(1024), Duration::from_secs(60), start);
+
+        assert_eq!(policy.check(512, start + Duration::from_secs(10)), None);
+        assert_eq!(
+            policy.check(2048, start + Duration::from_secs(10)),
+            Some(FlushTrigger::Size)
+        );
+
+        // data left behind by the flush doesn't trigger another one
+        policy.flushed(start + Duration::from_secs(10), 2048);
+        assert_eq!(policy.check(2048, start + Duration::from_secs(11)), None);
+        assert_eq!(
+            policy.check(3072, start + Duration::from_secs(11)),
+            Some(FlushTrigger::Size)
+        );
+    }
+
+    #[test]
+    fn flushes_on_time_when_quiet() {
+        let start = Instant::now();
+        let mut policy = FlushPolicy::new(None, Duration::from_secs(60), start);
+
+        assert_eq!(
+            policy.check(u64::MAX, start + Duration::from_secs(59)),
+            None
+        );
+        assert_eq!(
+            policy.check(0, start + Duration::from_secs(60)),
+            Some(FlushTrigger::Time)
+        );
+
+        policy.flushed(start + Duration::from_secs(60), 0);
+        assert_eq!(policy.check(0, start + Duration::from_secs(61)), None);
+    }
+}
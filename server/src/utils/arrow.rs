@@ -27,11 +27,13 @@ pub mod batch_adapter;
 pub mod flight;
 pub mod merged_reader;
 pub mod reverse_reader;
+pub mod unified_schema;
 
 use anyhow::Result;
 pub use batch_adapter::adapt_batch;
 pub use merged_reader::MergedRecordReader;
 use serde_json::{Map, Value};
+pub use unified_schema::merge_schemas;
 
 /// example function for concat recordbatch(may not work)
 /// use arrow::record_batch::RecordBatch;
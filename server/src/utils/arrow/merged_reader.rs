@@ -39,8 +39,13 @@ impl MergedRecordReader {
         let mut readers = Vec::with_capacity(files.len());
 
         for file in files {
-            let reader = StreamReader::try_new(File::open(file).unwrap(), None).map_err(|_| ())?;
-            readers.push(reader);
+            let reader = File::open(file)
+                .map_err(|err| err.to_string())
+                .and_then(|file| StreamReader::try_new(file, None).map_err(|err| err.to_string()));
+            match reader {
+                Ok(reader) => readers.push(reader),
+                Err(err) => log::warn!("skipping unreadable staging file {:?}: {}", file, err),
+            }
         }
 
         Ok(Self { readers })
@@ -59,19 +64,29 @@ impl MergedRecordReader {
 #[derive(Debug)]
 pub struct MergedReverseRecordReader {
     pub readers: Vec<StreamReader<BufReader<OffsetReader<File>>>>,
+    // files skipped as no record batch could be read from them
+    pub unreadable: Vec<PathBuf>,
 }
 
 impl MergedReverseRecordReader {
     pub fn try_new(files: &[PathBuf]) -> Result<Self, ()> {
         let mut readers = Vec::with_capacity(files.len());
+        let mut unreadable = Vec::new();
         for file in files {
-            let reader =
-                utils::arrow::reverse_reader::get_reverse_reader(File::open(file).unwrap())
-                    .map_err(|_| ())?;
-            readers.push(reader);
+            // files left behind by a crash may have no complete record batch
+            match File::open(file).and_then(utils::arrow::reverse_reader::get_reverse_reader) {
+                Ok(reader) => readers.push(reader),
+                Err(err) => {
+                    log::warn!("skipping unreadable staging file {:?}: {}", file, err);
+                    unreadable.push(file.clone());
+                }
+            }
         }
 
-        Ok(Self { readers })
+        Ok(Self {
+            readers,
+            unreadable,
+        })
     }
 
     pub fn merged_iter(
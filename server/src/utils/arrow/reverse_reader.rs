@@ -117,6 +117,8 @@ pub fn get_reverse_reader<T: Read + Seek>(
 ) -> Result<StreamReader<BufReader<OffsetReader<T>>>, io::Error> {
     let mut offset = 0;
     let mut messages = Vec::new();
+    let len = reader.seek(SeekFrom::End(0))? as usize;
+    reader.rewind()?;
 
     while let Some(res) = find_limit_and_type(&mut reader).transpose() {
         match res {
@@ -129,6 +131,15 @@ pub fn get_reverse_reader<T: Read + Seek>(
         }
     }
 
+    // a crash while writing can leave the last message partially written
+    messages.retain(|(_, offset, size)| offset + size <= len);
+    if messages.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream does not contain a schema message",
+        ));
+    }
+
     // reverse everything leaving the first because it has schema message.
     messages[1..].reverse();
     let messages = messages
@@ -139,7 +150,8 @@ pub fn get_reverse_reader<T: Read + Seek>(
     // reset reader
     reader.rewind()?;
 
-    Ok(StreamReader::try_new(OffsetReader::new(reader, messages), None).unwrap())
+    StreamReader::try_new(OffsetReader::new(reader, messages), None)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
 }
 
 pub fn reverse(rb: &RecordBatch) -> RecordBatch {
@@ -271,6 +283,27 @@ mod tests {
         assert_eq!(rb.num_rows(), 1);
     }
 
+    #[test]
+    fn test_truncated_last_message() {
+        let mut buf = write_mem(&[rb(1), rb(2), rb(3)]);
+        // drop end of stream marker and part of the last batch
+        buf.truncate(buf.len() - 16);
+        let reader = Cursor::new(buf);
+        let mut reader = get_reverse_reader(reader).unwrap();
+
+        let rb = reader.next().unwrap().unwrap();
+        assert_eq!(rb.num_rows(), 2);
+        let rb = reader.next().unwrap().unwrap();
+        assert_eq!(rb.num_rows(), 1);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let reader = Cursor::new(Vec::new());
+        assert!(get_reverse_reader(reader).is_err());
+    }
+
     #[test]
     fn manual_write() {
         let error_on_replacement = true;
@@ -0,0 +1,260 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use itertools::Itertools;
+
+/// Merges schemas of all versions of a stream into a superset schema, read at query time.
+///
+/// Unlike `Schema::try_merge`, a field present with different types across versions
+/// does not fail the merge. The type is widened so that datafusion can coerce older
+/// parquet files into the unified schema while reading. Fields missing in some versions
+/// are marked nullable so they are read as nulls from older files. Fields keep the order
+/// they are first seen in, so the first schema decides the column order.
+pub fn merge_schemas(schemas: impl IntoIterator<Item = Schema>) -> Schema {
+    let schemas = schemas.into_iter().collect_vec();
+    let mut fields: Vec<Field> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    let mut metadata = HashMap::new();
+
+    for schema in &schemas {
+        metadata.extend(schema.metadata().clone());
+        for field in schema.fields() {
+            *occurrences.entry(field.name().clone()).or_default() += 1;
+            match positions.get(field.name()) {
+                Some(&position) => {
+                    let existing = &mut fields[position];
+                    let data_type = widen(existing.data_type(), field.data_type());
+                    let nullable = existing.is_nullable() || field.is_nullable();
+                    *existing = existing
+                        .clone()
+                        .with_data_type(data_type)
+                        .with_nullable(nullable);
+                }
+                None => {
+                    positions.insert(field.name().clone(), fields.len());
+                    fields.push(field.as_ref().clone());
+                }
+            }
+        }
+    }
+
+    let fields = fields
+        .into_iter()
+        .map(|field| {
+            if occurrences[field.name()] < schemas.len() {
+                field.with_nullable(true)
+            } else {
+                field
+            }
+        })
+        .collect_vec();
+
+    Schema::new_with_metadata(fields, metadata)
+}
+
+/// Returns the narrowest type both `this` and `other` can be losslessly read as.
+/// Falls back to Utf8 when types are unrelated.
+pub fn widen(this: &DataType, other: &DataType) -> DataType {
+    if this == other {
+        return this.clone();
+    }
+
+    match (this, other) {
+        (DataType::Null, other) | (other, DataType::Null) => other.clone(),
+        (lhs, rhs) if lhs.is_integer() && rhs.is_integer() => {
+            if lhs.is_signed_integer() || rhs.is_signed_integer() {
+                DataType::Int64
+            } else {
+                DataType::UInt64
+            }
+        }
+        (lhs, rhs) if lhs.is_numeric() && rhs.is_numeric() => DataType::Float64,
+        (DataType::Timestamp(lhs_unit, lhs_tz), DataType::Timestamp(rhs_unit, rhs_tz)) => {
+            let tz = if lhs_tz == rhs_tz {
+                lhs_tz.clone()
+            } else {
+                None
+            };
+            DataType::Timestamp(finer_unit(lhs_unit, rhs_unit), tz)
+        }
+        (DataType::List(lhs), DataType::List(rhs)) => DataType::List(
+            Field::new(
+                lhs.name(),
+                widen(lhs.data_type(), rhs.data_type()),
+                lhs.is_nullable() || rhs.is_nullable(),
+            )
+            .into(),
+        ),
+        _ => DataType::Utf8,
+    }
+}
+
+fn finer_unit(lhs: &TimeUnit, rhs: &TimeUnit) -> TimeUnit {
+    fn rank(unit: &TimeUnit) -> u8 {
+        match unit {
+            TimeUnit::Second => 0,
+            TimeUnit::Millisecond => 1,
+            TimeUnit::Microsecond => 2,
+            TimeUnit::Nanosecond => 3,
+        }
+    }
+
+    if rank(lhs) >= rank(rhs) {
+        lhs.clone()
+    } else {
+        rhs.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, sync::Arc};
+
+    use arrow_array::{Array, Float64Array, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::prelude::{ParquetReadOptions, SessionContext};
+    use parquet::arrow::ArrowWriter;
+
+    use super::{merge_schemas, widen};
+
+    #[test]
+    fn widen_numeric_types() {
+        assert_eq!(widen(&DataType::Int32, &DataType::Int64), DataType::Int64);
+        assert_eq!(widen(&DataType::UInt8, &DataType::UInt32), DataType::UInt64);
+        assert_eq!(
+            widen(&DataType::Int64, &DataType::Float32),
+            DataType::Float64
+        );
+        assert_eq!(widen(&DataType::Boolean, &DataType::Int64), DataType::Utf8);
+    }
+
+    #[test]
+    fn widen_timestamp_units() {
+        assert_eq!(
+            widen(
+                &DataType::Timestamp(TimeUnit::Millisecond, None),
+                &DataType::Timestamp(TimeUnit::Microsecond, None)
+            ),
+            DataType::Timestamp(TimeUnit::Microsecond, None)
+        );
+    }
+
+    #[test]
+    fn merge_adds_missing_fields_as_nullable() {
+        let v1 = Schema::new(vec![Field::new("a", DataType::Int64, false)]);
+        let v2 = Schema::new(vec![
+            Field::new("a", DataType::Float64, false),
+            Field::new("b", DataType::Utf8, false),
+        ]);
+
+        let merged = merge_schemas([v1, v2]);
+
+        assert_eq!(
+            merged,
+            Schema::new(vec![
+                Field::new("a", DataType::Float64, false),
+                Field::new("b", DataType::Utf8, true),
+            ])
+        );
+    }
+
+    #[test]
+    fn merge_keeps_first_seen_field_order() {
+        let v1 = Schema::new(vec![
+            Field::new("zone", DataType::Utf8, true),
+            Field::new("app", DataType::Utf8, true),
+        ]);
+        let v2 = Schema::new(vec![
+            Field::new("level", DataType::Utf8, true),
+            Field::new("app", DataType::Utf8, true),
+        ]);
+
+        let merged = merge_schemas([v1, v2]);
+        let names = merged
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["zone", "app", "level"]);
+    }
+
+    #[actix_web::test]
+    async fn query_files_written_with_two_schema_versions() {
+        let dir =
+            std::env::temp_dir().join(format!("parseable-unified-schema-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let v1 = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, true)]));
+        let v2 = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Float64, true),
+            Field::new("b", DataType::Utf8, true),
+        ]));
+        let batches = [
+            RecordBatch::try_new(v1.clone(), vec![Arc::new(Int64Array::from(vec![1, 2]))]).unwrap(),
+            RecordBatch::try_new(
+                v2.clone(),
+                vec![
+                    Arc::new(Float64Array::from(vec![3.5])),
+                    Arc::new(StringArray::from(vec!["x"])),
+                ],
+            )
+            .unwrap(),
+        ];
+        for (index, batch) in batches.iter().enumerate() {
+            let file = File::create(dir.join(format!("{index}.parquet"))).unwrap();
+            let mut writer = ArrowWriter::try_new(file, batch.schema(), None).unwrap();
+            writer.write(batch).unwrap();
+            writer.close().unwrap();
+        }
+
+        let schema = merge_schemas([v1.as_ref().clone(), v2.as_ref().clone()]);
+        let ctx = SessionContext::new();
+        ctx.register_parquet(
+            "stream",
+            dir.to_str().unwrap(),
+            ParquetReadOptions::default().schema(&schema),
+        )
+        .await
+        .unwrap();
+
+        let records = ctx
+            .sql("SELECT a, b FROM stream ORDER BY a")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rows: usize = records.iter().map(|batch| batch.num_rows()).sum();
+        assert_eq!(rows, 3);
+        let batch = arrow_select::concat::concat_batches(&records[0].schema(), &records).unwrap();
+        let a = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(a.values(), &[1.0, 2.0, 3.5]);
+        assert_eq!(batch.column(1).null_count(), 2);
+    }
+}